@@ -17,12 +17,17 @@ use revm::{Database, DatabaseCommit};
 use zeth_primitives::{
     block::Header,
     transactions::{ethereum::EthereumTxEssence, optimism::OptimismTxEssence, TxEssence},
+    B256,
 };
 
+#[cfg(feature = "trace")]
+use crate::trace::ExecutionTracer;
 use crate::{
     consts::ChainSpec,
     execution::{ethereum::EthTxExecStrategy, optimism::OpTxExecStrategy, TxExecStrategy},
-    finalization::{BlockBuildStrategy, BuildFromMemDbStrategy},
+    finalization::{
+        tx_range_state_roots, tx_state_roots, BlockBuildStrategy, BuildFromMemDbStrategy,
+    },
     initialization::{DbInitStrategy, MemDbInitStrategy},
     input::Input,
     mem_db::MemDb,
@@ -35,6 +40,8 @@ pub struct BlockBuilder<'a, D, E: TxEssence> {
     pub(crate) input: Input<E>,
     pub(crate) db: Option<D>,
     pub(crate) header: Option<Header>,
+    #[cfg(feature = "trace")]
+    pub(crate) tracer: Option<ExecutionTracer>,
 }
 
 impl<D, E> BlockBuilder<'_, D, E>
@@ -50,6 +57,8 @@ where
             db: None,
             header: None,
             input,
+            #[cfg(feature = "trace")]
+            tracer: None,
         }
     }
 
@@ -172,6 +181,32 @@ where
     pub fn mut_db(&mut self) -> Option<&mut N::Database> {
         self.0.db.as_mut()
     }
+
+    /// Traces the execution of the transactions with `tracer`.
+    #[cfg(feature = "trace")]
+    pub fn with_tracer(mut self, tracer: ExecutionTracer) -> Self {
+        self.0.tracer = Some(tracer);
+        self
+    }
+
+    /// Takes the tracer, with the trace of the transactions once executed.
+    #[cfg(feature = "trace")]
+    pub fn take_tracer(&mut self) -> Option<ExecutionTracer> {
+        self.0.tracer.take()
+    }
+}
+
+impl<N: NetworkStrategyBundle<Database = MemDb>> ConfiguredBlockBuilder<'_, N> {
+    /// The state root after each transaction executed, see [tx_state_roots].
+    pub fn tx_state_roots(&self) -> Result<Vec<B256>> {
+        tx_state_roots(&self.0)
+    }
+
+    /// The state root after the first `pre_commits` commits and the one after each of
+    /// the following ones, see [tx_range_state_roots].
+    pub fn tx_range_state_roots(&self, pre_commits: usize) -> Result<(B256, Vec<B256>)> {
+        tx_range_state_roots(&self.0, pre_commits)
+    }
 }
 
 pub struct EthereumStrategyBundle {}
@@ -21,7 +21,7 @@ use anyhow::bail;
 use once_cell::sync::Lazy;
 use revm::primitives::SpecId;
 use serde::{Deserialize, Serialize};
-use zeth_primitives::{uint, Address, BlockNumber, ChainId, U256};
+use zeth_primitives::{uint, Address, BlockNumber, Bytes, ChainId, B256, U256};
 
 /// U256 representation of 0.
 pub const ZERO: U256 = U256::ZERO;
@@ -66,6 +66,12 @@ pub static ETH_MAINNET_CHAIN_SPEC: Lazy<ChainSpec> = Lazy::new(|| {
         l1_signal_service: None,
         l2_contract: None,
         l2_signal_service: None,
+        consensus_overrides: ConsensusOverrides::default(),
+        system_overrides: SystemOverrides::default(),
+        state_overrides: StateOverrides::default(),
+        mismatch_policy: ChainSpecMismatchPolicy::default(),
+        verifier_call: VerifierCallLayout::default(),
+        report_data: ReportDataLayout::default(),
     }
 });
 
@@ -89,6 +95,12 @@ macro_rules! taiko_chain_spec {
                 l1_signal_service: Some(*L1_SIGNAL_SERVICE),
                 l2_contract: Some(*L2_CONTRACT),
                 l2_signal_service: Some(*L2_SIGNAL_SERVICE),
+                consensus_overrides: ConsensusOverrides::default(),
+                system_overrides: SystemOverrides::default(),
+                state_overrides: StateOverrides::default(),
+                mismatch_policy: ChainSpecMismatchPolicy::default(),
+                verifier_call: VerifierCallLayout::default(),
+                report_data: ReportDataLayout::default(),
             }
         });
     };
@@ -98,6 +110,28 @@ taiko_chain_spec!(TAIKO_TESTNET_CHAIN_SPEC, testnet);
 taiko_chain_spec!(TAIKO_INTERNAL_DEVNET_A_CHAIN_SPEC, internal_devnet_a);
 taiko_chain_spec!(TAIKO_INTERNAL_DEVNET_B_CHAIN_SPEC, internal_devnet_b);
 
+/// The names of the taiko chains of [get_taiko_chain_spec].
+pub const TAIKO_CHAINS: [&str; 3] = ["testnet", "internal_devnet_a", "internal_devnet_b"];
+
+/// Checks the testing-only chain id override of the spec of `chain` against the chain
+/// specs: the override must not be the chain id of a known chain, whose verifier would
+/// then accept the proofs, `chain` itself included.
+pub fn check_chain_id_override(chain: &str, chain_id: ChainId) -> anyhow::Result<()> {
+    let known = TAIKO_CHAINS
+        .iter()
+        .find(|known| get_taiko_chain_spec(known).chain_id() == chain_id);
+    if let Some(known) = known {
+        bail!(
+            "Chain id override {} of {} is the chain id of {}",
+            chain_id,
+            chain,
+            known
+        );
+    }
+    get_taiko_chain_spec(chain).with_chain_id_override(chain_id)?;
+    Ok(())
+}
+
 pub fn get_taiko_chain_spec(chain: &str) -> ChainSpec {
     match chain {
         "testnet" => TAIKO_TESTNET_CHAIN_SPEC.clone(),
@@ -121,6 +155,12 @@ pub static OP_MAINNET_CHAIN_SPEC: Lazy<ChainSpec> = Lazy::new(|| ChainSpec {
     l1_signal_service: None,
     l2_contract: None,
     l2_signal_service: None,
+    consensus_overrides: ConsensusOverrides::default(),
+    system_overrides: SystemOverrides::default(),
+    state_overrides: StateOverrides::default(),
+    mismatch_policy: ChainSpecMismatchPolicy::default(),
+    verifier_call: VerifierCallLayout::default(),
+    report_data: ReportDataLayout::default(),
 });
 
 /// The condition at which a fork is activated.
@@ -163,6 +203,202 @@ impl Default for Eip1559Constants {
     }
 }
 
+/// Block parameters replacing the ones of the block data, to prove blocks as if other
+/// consensus rules applied. Such proofs do not verify on the real chain.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ConsensusOverrides {
+    pub base_fee_per_gas: Option<U256>,
+    pub gas_limit: Option<U256>,
+}
+
+impl ConsensusOverrides {
+    pub fn is_empty(&self) -> bool {
+        self.base_fee_per_gas.is_none() && self.gas_limit.is_none()
+    }
+}
+
+/// Code replacing the one of system contracts or precompile addresses in the pre-state
+/// of the blocks, to prove blocks as if other contracts had been deployed. Such proofs do
+/// not verify on the real chain.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SystemOverrides {
+    pub code: BTreeMap<Address, Bytes>,
+}
+
+impl SystemOverrides {
+    pub fn is_empty(&self) -> bool {
+        self.code.is_empty()
+    }
+}
+
+/// Values replacing the ones of an account in the pre-state of the blocks, like the state
+/// override set of `eth_call`.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountOverride {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub balance: Option<U256>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<u64>,
+    /// slots replaced, the other ones left as they are
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub state_diff: BTreeMap<U256, U256>,
+}
+
+/// Accounts overridden in the pre-state of the blocks, to prove blocks against a
+/// hypothetical starting state, as `{"<address>": {"balance": "0x..", "nonce": 1,
+/// "stateDiff": {"<slot>": "<value>"}}}`. Such proofs do not verify on the real chain.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct StateOverrides {
+    pub accounts: BTreeMap<Address, AccountOverride>,
+}
+
+impl StateOverrides {
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+}
+
+/// How the block data disagreeing with the chain spec, on its chain id or on its fork, is
+/// handled.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChainSpecMismatchPolicy {
+    /// Rejects the block data before building the block.
+    #[default]
+    Error,
+    /// Builds the block with the chain id and fork implied by the data.
+    Warn,
+    /// Builds the block with the chain id and fork of the chain spec.
+    Override,
+}
+
+impl FromStr for ChainSpecMismatchPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(ChainSpecMismatchPolicy::Error),
+            "warn" => Ok(ChainSpecMismatchPolicy::Warn),
+            "override" => Ok(ChainSpecMismatchPolicy::Override),
+            _ => Err(format!(
+                "invalid chain spec mismatch policy {:?}, expected error, warn or override",
+                s
+            )),
+        }
+    }
+}
+
+impl core::fmt::Display for ChainSpecMismatchPolicy {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ChainSpecMismatchPolicy::Error => write!(f, "error"),
+            ChainSpecMismatchPolicy::Warn => write!(f, "warn"),
+            ChainSpecMismatchPolicy::Override => write!(f, "override"),
+        }
+    }
+}
+
+/// Tier of the sgx proofs on the L1 contract.
+pub const SGX_TIER: u16 = 200;
+
+/// The on-chain call the proofs of a chain are submitted with, as the calldata emitted
+/// next to the proofs is laid out.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "function", rename_all = "camelCase")]
+pub enum VerifierCallLayout {
+    /// `proveBlock(uint64 blockId, bytes input)` of the L1 contract, the input being
+    /// `abi.encode(meta, tran, TierProof(tier, proof))`.
+    ProveBlock { tier: u16 },
+    /// `verifyProof(bytes32 publicInputsHash, bytes proof)` of a standalone verifier
+    /// contract.
+    VerifyProof { verifier: Address },
+}
+
+impl Default for VerifierCallLayout {
+    fn default() -> Self {
+        VerifierCallLayout::ProveBlock { tier: SGX_TIER }
+    }
+}
+
+/// Size of the report data of an SGX quote.
+pub const REPORT_DATA_SIZE: usize = 64;
+
+/// A part of the report data of the attestation quotes.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ReportDataField {
+    /// the 20 bytes of the address of the instance key
+    InstanceAddress,
+    /// the 32 bytes of the keccak hash of the compressed instance public key, its
+    /// fingerprint
+    PublicKeyHash,
+    /// the 8 big-endian bytes of the chain ID
+    ChainId,
+}
+
+impl ReportDataField {
+    /// Returns the size of the field in the report data.
+    pub fn size(&self) -> usize {
+        match self {
+            ReportDataField::InstanceAddress => 20,
+            ReportDataField::PublicKeyHash => 32,
+            ReportDataField::ChainId => 8,
+        }
+    }
+}
+
+/// The fields the report data of the attestation quotes is composed of, concatenated in
+/// order and zero-padded to [REPORT_DATA_SIZE] bytes.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ReportDataLayout(pub Vec<ReportDataField>);
+
+impl Default for ReportDataLayout {
+    fn default() -> Self {
+        ReportDataLayout(vec![ReportDataField::InstanceAddress])
+    }
+}
+
+impl ReportDataLayout {
+    /// Checks that the fields fit the report data.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let size: usize = self.0.iter().map(ReportDataField::size).sum();
+        if size > REPORT_DATA_SIZE {
+            bail!(
+                "Report data layout {:?} takes {} bytes, more than the {} of the quote",
+                self.0,
+                size,
+                REPORT_DATA_SIZE
+            );
+        }
+        Ok(())
+    }
+    /// Returns the report data of the instance key of `public_key_hash` and `instance`,
+    /// attesting it for `chain_id`.
+    pub fn compose(
+        &self,
+        instance: Address,
+        public_key_hash: B256,
+        chain_id: ChainId,
+    ) -> anyhow::Result<[u8; REPORT_DATA_SIZE]> {
+        self.validate()?;
+        let mut report_data = [0; REPORT_DATA_SIZE];
+        let mut offset = 0;
+        for field in &self.0 {
+            let bytes = match field {
+                ReportDataField::InstanceAddress => instance.to_vec(),
+                ReportDataField::PublicKeyHash => public_key_hash.to_vec(),
+                ReportDataField::ChainId => chain_id.to_be_bytes().to_vec(),
+            };
+            report_data[offset..offset + bytes.len()].copy_from_slice(&bytes);
+            offset += bytes.len();
+        }
+        Ok(report_data)
+    }
+}
+
 /// Specification of a specific chain.
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ChainSpec {
@@ -173,6 +409,18 @@ pub struct ChainSpec {
     pub l1_signal_service: Option<Address>,
     pub l2_contract: Option<Address>,
     pub l2_signal_service: Option<Address>,
+    #[serde(default)]
+    consensus_overrides: ConsensusOverrides,
+    #[serde(default)]
+    system_overrides: SystemOverrides,
+    #[serde(default)]
+    state_overrides: StateOverrides,
+    #[serde(default)]
+    mismatch_policy: ChainSpecMismatchPolicy,
+    #[serde(default)]
+    verifier_call: VerifierCallLayout,
+    #[serde(default)]
+    report_data: ReportDataLayout,
 }
 
 impl ChainSpec {
@@ -190,12 +438,102 @@ impl ChainSpec {
             l1_signal_service: None,
             l2_contract: None,
             l2_signal_service: None,
+            consensus_overrides: ConsensusOverrides::default(),
+            system_overrides: SystemOverrides::default(),
+            state_overrides: StateOverrides::default(),
+            mismatch_policy: ChainSpecMismatchPolicy::default(),
+            verifier_call: VerifierCallLayout::default(),
+            report_data: ReportDataLayout::default(),
         }
     }
     /// Returns the network chain ID.
     pub fn chain_id(&self) -> ChainId {
         self.chain_id
     }
+    /// Returns the specification with the chain ID replaced. Only meant for testing forks
+    /// which reuse the recorded block data of another chain, so the chain ID of the data
+    /// is overridden rather than checked.
+    pub fn with_chain_id_override(mut self, chain_id: ChainId) -> anyhow::Result<Self> {
+        if chain_id == 0 {
+            bail!("Chain id override must not be 0");
+        }
+        self.chain_id = chain_id;
+        self.mismatch_policy = ChainSpecMismatchPolicy::Override;
+        Ok(self)
+    }
+    /// Returns the specification with the handling of the block data disagreeing with it.
+    pub fn with_mismatch_policy(mut self, policy: ChainSpecMismatchPolicy) -> Self {
+        self.mismatch_policy = policy;
+        self
+    }
+    /// Returns the handling of the block data disagreeing with the specification.
+    pub fn mismatch_policy(&self) -> ChainSpecMismatchPolicy {
+        self.mismatch_policy
+    }
+    /// Returns the specification with the proofs submitted with another on-chain call.
+    pub fn with_verifier_call(mut self, layout: VerifierCallLayout) -> Self {
+        self.verifier_call = layout;
+        self
+    }
+    /// Returns the on-chain call the proofs are submitted with.
+    pub fn verifier_call(&self) -> VerifierCallLayout {
+        self.verifier_call
+    }
+    /// Returns the specification with the report data of the attestation quotes composed
+    /// of other fields.
+    pub fn with_report_data(mut self, layout: ReportDataLayout) -> anyhow::Result<Self> {
+        layout.validate()?;
+        self.report_data = layout;
+        Ok(self)
+    }
+    /// Returns the fields the report data of the attestation quotes is composed of.
+    pub fn report_data(&self) -> &ReportDataLayout {
+        &self.report_data
+    }
+    /// Returns the contract the proofs are submitted to.
+    pub fn verifier_contract(&self) -> Option<Address> {
+        match self.verifier_call {
+            VerifierCallLayout::ProveBlock { .. } => self.l1_contract,
+            VerifierCallLayout::VerifyProof { verifier } => Some(verifier),
+        }
+    }
+    /// Returns the specification with `spec_id` the fork of every block, the later forks
+    /// removed.
+    pub fn with_fork(mut self, spec_id: SpecId) -> Self {
+        self.hard_forks.retain(|fork, _| *fork < spec_id);
+        self.hard_forks.insert(spec_id, ForkCondition::Block(0));
+        self
+    }
+    /// Returns the specification with the block parameters overridden. Only meant for
+    /// testing, the blocks built with it differ from the ones of the chain.
+    pub fn with_consensus_overrides(mut self, overrides: ConsensusOverrides) -> Self {
+        self.consensus_overrides = overrides;
+        self
+    }
+    /// Returns the overrides of the block parameters.
+    pub fn consensus_overrides(&self) -> &ConsensusOverrides {
+        &self.consensus_overrides
+    }
+    /// Returns the specification with the code of system contracts overridden. Only meant
+    /// for testing, the blocks built with it differ from the ones of the chain.
+    pub fn with_system_overrides(mut self, overrides: SystemOverrides) -> Self {
+        self.system_overrides = overrides;
+        self
+    }
+    /// Returns the overrides of the code of system contracts.
+    pub fn system_overrides(&self) -> &SystemOverrides {
+        &self.system_overrides
+    }
+    /// Returns the specification with accounts of the pre-state overridden. Only meant
+    /// for testing, the blocks built with it differ from the ones of the chain.
+    pub fn with_state_overrides(mut self, overrides: StateOverrides) -> Self {
+        self.state_overrides = overrides;
+        self
+    }
+    /// Returns the overrides of the accounts of the pre-state.
+    pub fn state_overrides(&self) -> &StateOverrides {
+        &self.state_overrides
+    }
     /// Returns the revm specification ID for `block_number`.
     pub fn spec_id(&self, block_number: BlockNumber) -> SpecId {
         for (spec_id, fork) in self.hard_forks.iter().rev() {
@@ -252,4 +590,108 @@ mod tests {
         assert_eq!(ETH_MAINNET_CHAIN_SPEC.spec_id(17034869), SpecId::MERGE);
         assert_eq!(ETH_MAINNET_CHAIN_SPEC.spec_id(17034870), SpecId::SHANGHAI);
     }
+
+    /// Historical blocks are built with the rules of their own fork, whatever the fork of
+    /// the chain head: the builder selects them by the number of the block.
+    #[cfg(feature = "taiko")]
+    #[test]
+    fn blocks_around_a_fork_are_built_with_their_own_rules() {
+        use zeth_primitives::{block::Header, transactions::ethereum::EthereumTxEssence};
+
+        use crate::{
+            block_builder::BlockBuilder, execution::TxExecStrategy, input::Input, mem_db::MemDb,
+            taiko::execute::TaikoTxExecStrategy,
+        };
+
+        let spec = ChainSpec {
+            hard_forks: BTreeMap::from([
+                (SpecId::MERGE, ForkCondition::Block(0)),
+                (SpecId::SHANGHAI, ForkCondition::Block(100)),
+            ]),
+            ..TAIKO_TESTNET_CHAIN_SPEC.clone()
+        };
+        let build = |number| {
+            let input = Input::<EthereumTxEssence> {
+                parent_header: Default::default(),
+                beneficiary: Default::default(),
+                gas_limit: Default::default(),
+                timestamp: Default::default(),
+                extra_data: Default::default(),
+                mix_hash: Default::default(),
+                transactions: vec![],
+                withdrawals: vec![],
+                parent_state_trie: Default::default(),
+                parent_storage: Default::default(),
+                contracts: vec![],
+                ancestor_headers: vec![],
+                base_fee_per_gas: Default::default(),
+            };
+            let mut block_builder = BlockBuilder::new(&spec, input).with_db(MemDb::default());
+            block_builder.header = Some(Header {
+                number,
+                ..Default::default()
+            });
+            let block_builder = TaikoTxExecStrategy::execute_transactions(block_builder).unwrap();
+            block_builder.header.unwrap()
+        };
+
+        // the withdrawals came with Shanghai
+        assert_eq!(spec.spec_id(99), SpecId::MERGE);
+        assert_eq!(build(99).withdrawals_root, None);
+        assert_eq!(spec.spec_id(100), SpecId::SHANGHAI);
+        assert!(build(100).withdrawals_root.is_some());
+    }
+
+    #[test]
+    fn chain_id_override() {
+        let spec = TAIKO_TESTNET_CHAIN_SPEC
+            .clone()
+            .with_chain_id_override(31337)
+            .unwrap();
+        assert_eq!(spec.chain_id(), 31337);
+        assert_eq!(spec.l2_contract, TAIKO_TESTNET_CHAIN_SPEC.l2_contract);
+        assert!(TAIKO_TESTNET_CHAIN_SPEC
+            .clone()
+            .with_chain_id_override(0)
+            .is_err());
+
+        assert!(check_chain_id_override("testnet", 31337).is_ok());
+        assert!(check_chain_id_override("testnet", 0).is_err());
+        let err = check_chain_id_override("testnet", 167001).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Chain id override 167001 of testnet is the chain id of internal_devnet_a"
+        );
+        assert!(check_chain_id_override("testnet", TAIKO_TESTNET_CHAIN_SPEC.chain_id()).is_err());
+    }
+
+    #[test]
+    fn report_data_is_composed_of_the_configured_fields() {
+        let instance = Address::repeat_byte(0xaa);
+        let key_hash = B256::repeat_byte(0xbb);
+        let report_data = ReportDataLayout::default()
+            .compose(instance, key_hash, 167)
+            .unwrap();
+        assert_eq!(&report_data[..20], instance.as_slice());
+        assert_eq!(report_data[20..], [0; 44]);
+
+        let layout: ReportDataLayout =
+            serde_json::from_str(r#"["publicKeyHash", "chainId"]"#).unwrap();
+        let report_data = layout.compose(instance, key_hash, 167).unwrap();
+        assert_eq!(&report_data[..32], key_hash.as_slice());
+        assert_eq!(report_data[32..40], 167u64.to_be_bytes());
+        assert_eq!(report_data[40..], [0; 24]);
+
+        // 72 bytes do not fit the quote
+        let oversized = ReportDataLayout(vec![
+            ReportDataField::PublicKeyHash,
+            ReportDataField::InstanceAddress,
+            ReportDataField::InstanceAddress,
+        ]);
+        assert!(oversized.compose(instance, key_hash, 167).is_err());
+        assert!(TAIKO_TESTNET_CHAIN_SPEC
+            .clone()
+            .with_report_data(oversized)
+            .is_err());
+    }
 }
@@ -12,7 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use anyhow::Result;
+use anyhow::{ensure, Context, Result};
 use hashbrown::HashMap;
 use revm::primitives::Address;
 use zeth_primitives::{
@@ -20,13 +20,14 @@ use zeth_primitives::{
     keccak::keccak,
     transactions::TxEssence,
     trie::{MptNode, StateAccount},
-    U256,
+    B256, U256,
 };
 
 use crate::{
     block_builder::BlockBuilder,
     guest_mem_forget,
-    mem_db::{AccountState, MemDb},
+    input::StorageEntry,
+    mem_db::{AccountState, DbAccount, MemDb},
 };
 
 pub trait BlockBuildStrategy<E: TxEssence> {
@@ -47,68 +48,15 @@ impl BuildFromMemDbStrategy {
 
         // apply state updates
         let state_trie = &mut block_builder.input.parent_state_trie;
+        let parent_storage = &mut block_builder.input.parent_storage;
         for (address, account) in &db.accounts {
-            // if the account has not been touched, it can be ignored
-            if account.state == AccountState::None {
-                if let Some(map) = debug_storage_tries {
-                    let storage_root = block_builder
-                        .input
-                        .parent_storage
-                        .get(address)
-                        .unwrap()
-                        .0
-                        .clone();
-                    map.insert(*address, storage_root);
-                }
-                continue;
-            }
-
-            // compute the index of the current account in the state trie
-            let state_trie_index = keccak(address);
-
-            // remove deleted accounts from the state trie
-            if account.state == AccountState::Deleted {
-                state_trie.delete(&state_trie_index)?;
-                continue;
-            }
-
-            // otherwise, compute the updated storage root for that account
-            let state_storage = &account.storage;
-            let storage_root = {
-                // getting a mutable reference is more efficient than calling remove
-                // every account must have an entry, even newly created accounts
-                let (storage_trie, _) =
-                    block_builder.input.parent_storage.get_mut(address).unwrap();
-                // for cleared accounts always start from the empty trie
-                if account.state == AccountState::StorageCleared {
-                    storage_trie.clear();
-                }
-
-                // apply all new storage entries for the current account (address)
-                for (key, value) in state_storage {
-                    let storage_trie_index = keccak(key.to_be_bytes::<32>());
-                    if value == &U256::ZERO {
-                        storage_trie.delete(&storage_trie_index)?;
-                    } else {
-                        storage_trie.insert_rlp(&storage_trie_index, *value)?;
-                    }
-                }
-
-                // insert the storage trie for host debugging
-                if let Some(map) = debug_storage_tries {
-                    map.insert(*address, storage_trie.clone());
-                }
-
-                storage_trie.hash()
-            };
-
-            let state_account = StateAccount {
-                nonce: account.info.nonce,
-                balance: account.info.balance,
-                storage_root,
-                code_hash: account.info.code_hash,
-            };
-            state_trie.insert_rlp(&state_trie_index, state_account)?;
+            apply_account_update(
+                state_trie,
+                parent_storage,
+                address,
+                account,
+                debug_storage_tries,
+            )?;
         }
 
         // update result header with the new state root
@@ -135,6 +83,122 @@ impl<E: TxEssence> BlockBuildStrategy<E> for BuildFromMemDbStrategy {
     }
 }
 
+/// Applies the state of an account of the [MemDb] to the state trie and to the storage
+/// trie of the account.
+fn apply_account_update(
+    state_trie: &mut MptNode,
+    parent_storage: &mut HashMap<Address, StorageEntry>,
+    address: &Address,
+    account: &DbAccount,
+    debug_storage_tries: &mut Option<HashMap<Address, MptNode>>,
+) -> Result<()> {
+    // if the account has not been touched, it can be ignored
+    if account.state == AccountState::None {
+        if let Some(map) = debug_storage_tries {
+            let storage_root = parent_storage.get(address).unwrap().0.clone();
+            map.insert(*address, storage_root);
+        }
+        return Ok(());
+    }
+
+    // compute the index of the current account in the state trie
+    let state_trie_index = keccak(address);
+
+    // remove deleted accounts from the state trie
+    if account.state == AccountState::Deleted {
+        state_trie.delete(&state_trie_index)?;
+        return Ok(());
+    }
+
+    // otherwise, compute the updated storage root for that account
+    let state_storage = &account.storage;
+    let storage_root = {
+        // getting a mutable reference is more efficient than calling remove
+        // every account must have an entry, even newly created accounts
+        let (storage_trie, _) = parent_storage.get_mut(address).unwrap();
+        // for cleared accounts always start from the empty trie
+        if account.state == AccountState::StorageCleared {
+            storage_trie.clear();
+        }
+
+        // apply all new storage entries for the current account (address)
+        for (key, value) in state_storage {
+            let storage_trie_index = keccak(key.to_be_bytes::<32>());
+            if value == &U256::ZERO {
+                storage_trie.delete(&storage_trie_index)?;
+            } else {
+                storage_trie.insert_rlp(&storage_trie_index, *value)?;
+            }
+        }
+
+        // insert the storage trie for host debugging
+        if let Some(map) = debug_storage_tries {
+            map.insert(*address, storage_trie.clone());
+        }
+
+        storage_trie.hash()
+    };
+
+    let state_account = StateAccount {
+        nonce: account.info.nonce,
+        balance: account.info.balance,
+        storage_root,
+        code_hash: account.info.code_hash,
+    };
+    state_trie.insert_rlp(&state_trie_index, state_account)?;
+    Ok(())
+}
+
+/// The state root after each commit to the [MemDb] of an executed block builder, that
+/// is after each transaction executed and each withdrawal, as recorded by
+/// [MemDb::record_commits]. For debugging: the tries of the parent state are copied and
+/// hashed once per commit.
+pub fn tx_state_roots<E: TxEssence>(block_builder: &BlockBuilder<MemDb, E>) -> Result<Vec<B256>> {
+    let commits = block_builder
+        .db
+        .as_ref()
+        .and_then(|db| db.commits.as_ref())
+        .context("the commits to the database were not recorded")?;
+    let mut state_trie = block_builder.input.parent_state_trie.clone();
+    let mut parent_storage = block_builder.input.parent_storage.clone();
+    let mut roots = Vec::with_capacity(commits.len());
+    for accounts in commits {
+        for (address, account) in accounts {
+            apply_account_update(
+                &mut state_trie,
+                &mut parent_storage,
+                address,
+                account,
+                &mut None,
+            )?;
+        }
+        roots.push(state_trie.hash());
+    }
+    Ok(roots)
+}
+
+/// The state root after the first `pre_commits` commits to the [MemDb] of an executed
+/// block builder, the one of the parent when there are none, and the state root after
+/// each of the following commits, see [tx_state_roots].
+pub fn tx_range_state_roots<E: TxEssence>(
+    block_builder: &BlockBuilder<MemDb, E>,
+    pre_commits: usize,
+) -> Result<(B256, Vec<B256>)> {
+    let mut roots = tx_state_roots(block_builder)?;
+    ensure!(
+        pre_commits <= roots.len(),
+        "{} commits were recorded, fewer than the {} before the range",
+        roots.len(),
+        pre_commits
+    );
+    let range_roots = roots.split_off(pre_commits);
+    let pre_state_root = match roots.last() {
+        Some(root) => *root,
+        None => block_builder.input.parent_state_trie.hash(),
+    };
+    Ok((pre_state_root, range_roots))
+}
+
 pub struct DebugBuildFromMemDbStrategy {}
 
 impl<E: TxEssence> BlockBuildStrategy<E> for DebugBuildFromMemDbStrategy {
@@ -147,3 +211,113 @@ impl<E: TxEssence> BlockBuildStrategy<E> for DebugBuildFromMemDbStrategy {
         Ok((header, storage_trace.unwrap()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use revm::{
+        primitives::{Account, AccountInfo},
+        DatabaseCommit,
+    };
+    use zeth_primitives::transactions::ethereum::EthereumTxEssence;
+
+    use super::*;
+    use crate::{consts::ETH_MAINNET_CHAIN_SPEC, input::Input};
+
+    fn touched(balance: u64) -> Account {
+        let mut account: Account = AccountInfo {
+            balance: U256::from(balance),
+            ..Default::default()
+        }
+        .into();
+        account.mark_touch();
+        account
+    }
+
+    #[test]
+    fn tx_state_roots_end_at_the_block_state_root() {
+        let (alice, bob) = (Address::repeat_byte(0x0a), Address::repeat_byte(0x0b));
+        let input = Input::<EthereumTxEssence> {
+            parent_header: Default::default(),
+            beneficiary: Default::default(),
+            gas_limit: Default::default(),
+            timestamp: Default::default(),
+            extra_data: Default::default(),
+            mix_hash: Default::default(),
+            transactions: vec![],
+            withdrawals: vec![],
+            parent_state_trie: Default::default(),
+            parent_storage: [alice, bob]
+                .map(|address| (address, Default::default()))
+                .into(),
+            contracts: vec![],
+            ancestor_headers: vec![],
+            base_fee_per_gas: Default::default(),
+        };
+        let mut db = MemDb::default();
+        db.record_commits();
+        let mut block_builder = BlockBuilder::new(&ETH_MAINNET_CHAIN_SPEC, input);
+        block_builder.header = Some(Header::default());
+
+        let txs: [HashMap<Address, Account>; 3] = [
+            [(alice, touched(1))].into(),
+            [(alice, touched(2)), (bob, touched(3))].into(),
+            // emptied, deleting it
+            [(alice, touched(0))].into(),
+        ];
+        // the state after each transaction, built on its own
+        let mut state_roots = vec![];
+        for changes in txs {
+            db.commit(changes);
+            let header = BuildFromMemDbStrategy::build_header(
+                &mut None,
+                block_builder.clone().with_db(db.clone()),
+            )
+            .unwrap();
+            state_roots.push(header.state_root);
+        }
+
+        let block_builder = block_builder.with_db(db);
+        let roots = tx_state_roots(&block_builder).unwrap();
+        assert_eq!(roots, state_roots);
+        assert!(roots.windows(2).all(|pair| pair[0] != pair[1]));
+        let header = BuildFromMemDbStrategy::build_header(&mut None, block_builder).unwrap();
+        assert_eq!(roots.last(), Some(&header.state_root));
+    }
+
+    #[test]
+    fn tx_range_state_roots_start_from_the_pre_state() {
+        let alice = Address::repeat_byte(0x0a);
+        let input = Input::<EthereumTxEssence> {
+            parent_header: Default::default(),
+            beneficiary: Default::default(),
+            gas_limit: Default::default(),
+            timestamp: Default::default(),
+            extra_data: Default::default(),
+            mix_hash: Default::default(),
+            transactions: vec![],
+            withdrawals: vec![],
+            parent_state_trie: Default::default(),
+            parent_storage: [(alice, Default::default())].into(),
+            contracts: vec![],
+            ancestor_headers: vec![],
+            base_fee_per_gas: Default::default(),
+        };
+        let mut db = MemDb::default();
+        db.record_commits();
+        for balance in [1, 2, 3] {
+            db.commit([(alice, touched(balance))].into());
+        }
+        let block_builder = BlockBuilder::new(&ETH_MAINNET_CHAIN_SPEC, input).with_db(db);
+        let roots = tx_state_roots(&block_builder).unwrap();
+
+        // the range of the last two transactions
+        let (pre_state_root, range_roots) = tx_range_state_roots(&block_builder, 1).unwrap();
+        assert_eq!(pre_state_root, roots[0]);
+        assert_eq!(range_roots, roots[1..]);
+        // a range from the first transaction starts from the parent state
+        let (pre_state_root, range_roots) = tx_range_state_roots(&block_builder, 0).unwrap();
+        assert_eq!(pre_state_root, MptNode::default().hash());
+        assert_eq!(range_roots, roots);
+        assert!(tx_range_state_roots(&block_builder, 4).is_err());
+    }
+}
@@ -0,0 +1,94 @@
+//! Files replaced atomically: each write goes to a temporary file of its own next to the
+//! file, which is synced and renamed over it, the directory being synced last. Concurrent
+//! writers of a file never share a temporary file, and a crash leaves either the old or
+//! the new file behind, never a torn one.
+use std::{fs::File, io, path::Path};
+
+use anyhow::{Context, Result};
+
+/// Replaces `path` with what `write` writes, through a temporary file named
+/// `{file name}.{random}.{extension}`, removed if the write fails.
+pub fn write_atomically<F>(path: &Path, extension: &str, write: F) -> Result<()>
+where
+    F: FnOnce(&mut File) -> Result<()>,
+{
+    let dir = parent_dir(path);
+    let file_name = path
+        .file_name()
+        .with_context(|| format!("{} is not a file", path.display()))?;
+    let mut tmp = tempfile::Builder::new()
+        .prefix(&format!("{}.", file_name.to_string_lossy()))
+        .suffix(&format!(".{}", extension))
+        .tempfile_in(dir)
+        .with_context(|| format!("Failed to create a temporary file in {}", dir.display()))?;
+    write(tmp.as_file_mut())?;
+    tmp.as_file().sync_all()?;
+    tmp.persist(path)
+        .with_context(|| format!("Failed to replace {}", path.display()))?;
+    sync_dir(dir).with_context(|| format!("Failed to sync {}", dir.display()))?;
+    Ok(())
+}
+
+/// Makes the renames and removals of files in `dir` durable.
+pub fn sync_dir(dir: &Path) -> io::Result<()> {
+    // directories can't be opened for syncing elsewhere
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+/// The name of the file `file_name` is a temporary file of, written by
+/// [write_atomically] with `extension`.
+pub fn temp_file_target<'a>(file_name: &'a str, extension: &str) -> Option<&'a str> {
+    let (target, random) = file_name
+        .strip_suffix(extension)?
+        .strip_suffix('.')?
+        .rsplit_once('.')?;
+    (!target.is_empty() && !random.is_empty()).then_some(target)
+}
+
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Write};
+
+    use anyhow::bail;
+
+    use super::*;
+
+    #[test]
+    fn failed_writes_leave_the_file_as_it_was() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("1.l1.json.gz");
+        write_atomically(&path, "tmp", |file| Ok(file.write_all(b"old")?)).unwrap();
+
+        // interrupted halfway
+        let err = write_atomically(&path, "tmp", |file| {
+            file.write_all(b"ne")?;
+            bail!("interrupted")
+        })
+        .unwrap_err();
+        assert_eq!(err.to_string(), "interrupted");
+        assert_eq!(fs::read(&path).unwrap(), b"old");
+        let names: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(names, ["1.l1.json.gz"]);
+
+        write_atomically(&path, "tmp", |file| Ok(file.write_all(b"new")?)).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert_eq!(
+            temp_file_target("1.l1.json.gz.a1B2c3.tmp", "tmp"),
+            Some("1.l1.json.gz")
+        );
+        assert_eq!(temp_file_target("1.l1.json.tmp", "part"), None);
+        assert_eq!(temp_file_target("notes.tmp", "tmp"), None);
+    }
+}
@@ -38,15 +38,17 @@ use crate::{
     consts::ChainSpec,
     host::{
         mpt::{orphaned_digests, resolve_digests, shorten_key},
-        provider::{new_provider, BlockQuery},
+        provider::{new_provider, BlockQuery, RpcOptions},
     },
     input::{Input, StorageEntry},
     mem_db::MemDb,
 };
 
+pub mod atomic_file;
 pub mod mpt;
 pub mod provider;
 pub mod provider_db;
+pub mod validate;
 
 #[derive(Clone)]
 pub struct Init<E: TxEssence> {
@@ -70,7 +72,7 @@ where
     N::TxEssence: TryFrom<EthersTransaction>,
     <N::TxEssence as TryFrom<EthersTransaction>>::Error: Debug,
 {
-    let mut provider = new_provider(cache_path, rpc_url)?;
+    let mut provider = new_provider(cache_path, rpc_url, &RpcOptions::default())?;
 
     // Fetch the initial block
     let init_block = provider.get_partial_block(&BlockQuery {
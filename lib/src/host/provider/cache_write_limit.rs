@@ -0,0 +1,104 @@
+//! Caps the cache files written at once, shared by the providers, so that a warm or a
+//! burst of first proofs does not saturate the disk. The reads are not capped.
+use std::sync::{Condvar, Mutex};
+
+#[derive(Debug, Default)]
+struct Writes {
+    active: usize,
+    waiting: usize,
+}
+
+/// The cache writes in progress, at most `max` at once. The providers save their cache
+/// files from blocking threads, so the slots are waited for by blocking.
+#[derive(Debug)]
+pub struct CacheWriteLimit {
+    max: usize,
+    writes: Mutex<Writes>,
+    released: Condvar,
+}
+
+/// A slot of the cache writes, freed when dropped.
+pub struct CacheWritePermit<'a> {
+    limit: &'a CacheWriteLimit,
+}
+
+impl CacheWriteLimit {
+    pub fn new(max: usize) -> Self {
+        CacheWriteLimit {
+            max,
+            writes: Default::default(),
+            released: Condvar::new(),
+        }
+    }
+
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    /// The writes waiting for a slot.
+    pub fn queue_depth(&self) -> usize {
+        self.writes.lock().unwrap().waiting
+    }
+
+    /// Blocks until a write slot is free, kept until the permit is dropped.
+    pub fn acquire(&self) -> CacheWritePermit<'_> {
+        let mut writes = self.writes.lock().unwrap();
+        writes.waiting += 1;
+        while writes.active >= self.max {
+            writes = self.released.wait(writes).unwrap();
+        }
+        writes.waiting -= 1;
+        writes.active += 1;
+        CacheWritePermit { limit: self }
+    }
+}
+
+impl Drop for CacheWritePermit<'_> {
+    fn drop(&mut self) {
+        self.limit.writes.lock().unwrap().active -= 1;
+        self.limit.released.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+        time::Duration,
+    };
+
+    use super::*;
+
+    #[test]
+    fn simultaneous_writes_are_capped() {
+        let limit = Arc::new(CacheWriteLimit::new(2));
+        let (active, peak) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        // the writers queue up behind two writes in progress
+        let held = (limit.acquire(), limit.acquire());
+        let writers: Vec<_> = (0..16)
+            .map(|_| {
+                let (limit, active, peak) = (limit.clone(), active.clone(), peak.clone());
+                thread::spawn(move || {
+                    let _permit = limit.acquire();
+                    let writing = active.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(writing, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(10));
+                    active.fetch_sub(1, Ordering::SeqCst);
+                })
+            })
+            .collect();
+        while limit.queue_depth() < 16 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        drop(held);
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        assert!(peak.load(Ordering::SeqCst) <= 2);
+        assert_eq!(limit.queue_depth(), 0);
+    }
+}
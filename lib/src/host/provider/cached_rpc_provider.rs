@@ -12,35 +12,52 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::sync::Arc;
+
 use anyhow::Result;
 use ethers_core::types::{Block, Bytes, EIP1186ProofResponse, Transaction, H256, U256};
 #[cfg(feature = "taiko")]
 use zeth_primitives::taiko::BlockProposed;
 
 use super::{
-    file_provider::FileProvider, rpc_provider::RpcProvider, AccountQuery, BlockQuery, MutProvider,
-    ProofQuery, Provider, StorageQuery,
+    cache_write_limit::CacheWriteLimit, file_provider::FileProvider, new_rpc_provider,
+    AccountQuery, BlockQuery, MutProvider, ProofQuery, Provider, RpcOptions, StorageQuery,
 };
+#[cfg(feature = "taiko")]
+use crate::taiko::blob::BlobSidecar;
 
 pub struct CachedRpcProvider {
     cache: FileProvider,
-    rpc: RpcProvider,
+    rpc: Box<dyn Provider>,
+    write_limit: Option<Arc<CacheWriteLimit>>,
 }
 
 impl CachedRpcProvider {
-    pub fn new(cache_path: String, rpc_url: String) -> Result<Self> {
-        let cache = match FileProvider::read_from_file(cache_path.clone()) {
-            Ok(provider) => provider,
-            Err(_) => FileProvider::empty(cache_path),
+    pub fn new(cache_path: String, rpc_url: String, options: &RpcOptions) -> Result<Self> {
+        // the cache file is replaced by the fresh entries
+        let cache = match options.skip_cache_reads {
+            true => FileProvider::empty(cache_path),
+            false => match FileProvider::read_from_file(cache_path.clone()) {
+                Ok(provider) => provider,
+                Err(_) => FileProvider::empty(cache_path),
+            },
         };
-        let rpc = RpcProvider::new(rpc_url)?;
+        let rpc = new_rpc_provider(rpc_url, options)?;
 
-        Ok(CachedRpcProvider { cache, rpc })
+        Ok(CachedRpcProvider {
+            cache,
+            rpc,
+            write_limit: options.cache_write_limit.clone(),
+        })
     }
 }
 
 impl Provider for CachedRpcProvider {
     fn save(&self) -> Result<()> {
+        if !self.cache.is_dirty() {
+            return Ok(());
+        }
+        let _permit = self.write_limit.as_deref().map(CacheWriteLimit::acquire);
         self.cache.save()
     }
 
@@ -159,4 +176,104 @@ impl Provider for CachedRpcProvider {
         }
         Ok(out)
     }
+
+    #[cfg(feature = "taiko")]
+    fn get_blob(&mut self, query: &super::BlobQuery) -> Result<BlobSidecar> {
+        let cache_out = self.cache.get_blob(query);
+        if cache_out.is_ok() {
+            return cache_out;
+        }
+
+        let out = self.rpc.get_blob(query)?;
+        self.cache.insert_blob(query.clone(), out.clone());
+
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use ethers_core::types::H160;
+
+    use super::*;
+
+    #[test]
+    fn concurrent_saves_never_tear_the_cache_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_path = dir.path().join("1.l2.json.gz");
+        let cache_path = cache_path.to_str().unwrap().to_owned();
+        let query = AccountQuery {
+            block_no: 1,
+            address: H160::zero(),
+        };
+        let save = |byte: u8| {
+            let mut cache = FileProvider::empty(cache_path.clone());
+            // large enough for the saves to overlap
+            cache.insert_code(query.clone(), Bytes::from(vec![byte; 1 << 20]));
+            cache.save().unwrap();
+        };
+        save(0);
+
+        // the provers of the same block saving it, and the ones reading it, at once
+        std::thread::scope(|scope| {
+            for byte in 1..=4 {
+                scope.spawn(move || (0..5).for_each(|_| save(byte)));
+            }
+            for _ in 0..2 {
+                scope.spawn(|| {
+                    for _ in 0..50 {
+                        let mut cache = FileProvider::read_from_file(cache_path.clone()).unwrap();
+                        let code = cache.get_code(&query).unwrap();
+                        assert!(code.iter().all(|&byte| byte == code[0]));
+                    }
+                });
+            }
+        });
+        let names: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(names, ["1.l2.json.gz"]);
+    }
+
+    #[tokio::test]
+    async fn skipping_the_cache_fetches_the_cached_entries_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_path = dir.path().join("1.l2.json.gz");
+        let cache_path = cache_path.to_str().unwrap().to_owned();
+        let query = AccountQuery {
+            block_no: 1,
+            address: H160::zero(),
+        };
+        let (stale, fresh) = (Bytes::from_static(&[0x01]), Bytes::from_static(&[0x02]));
+
+        let mut cache = FileProvider::empty(cache_path.clone());
+        cache.insert_code(query.clone(), stale.clone());
+        cache.save().unwrap();
+        let provider = |skip_cache_reads| {
+            let options = RpcOptions {
+                skip_cache_reads,
+                ..Default::default()
+            };
+            let mut provider = CachedRpcProvider::new(
+                cache_path.clone(),
+                "http://localhost:8545".into(),
+                &options,
+            )
+            .unwrap();
+            let mut rpc = FileProvider::empty(String::new());
+            rpc.insert_code(query.clone(), fresh.clone());
+            provider.rpc = Box::new(rpc);
+            provider
+        };
+
+        assert_eq!(provider(false).get_code(&query).unwrap(), stale);
+        let mut skipping = provider(true);
+        assert_eq!(skipping.get_code(&query).unwrap(), fresh);
+        // the fresh entry replaces the stale one
+        skipping.save().unwrap();
+        assert_eq!(provider(false).get_code(&query).unwrap(), fresh);
+    }
 }
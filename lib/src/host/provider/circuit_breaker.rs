@@ -0,0 +1,179 @@
+//! Fails the RPC calls to a host fast while it is down, rather than having every block
+//! fetch spend its budget on a provider in an outage.
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use thiserror::Error as ThisError;
+use tracing::{info, warn};
+
+/// Error of the RPC calls short-circuited by an open breaker.
+#[derive(Debug, ThisError)]
+#[error("RPC host {host} is unavailable, its circuit breaker is open for another {retry_in:?}")]
+pub struct RpcUnavailable {
+    pub host: String,
+    /// when the breaker lets a probe through, zero while a probe is in flight
+    pub retry_in: Duration,
+}
+
+/// The state of the breaker of a host.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BreakerState {
+    /// the calls go through
+    Closed,
+    /// the cooldown is over, a single call probes the host
+    HalfOpen,
+    /// the calls fail fast until the end of the cooldown
+    Open,
+}
+
+impl fmt::Display for BreakerState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BreakerState::Closed => write!(f, "closed"),
+            BreakerState::HalfOpen => write!(f, "half-open"),
+            BreakerState::Open => write!(f, "open"),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct HostBreaker {
+    failures: u32,
+    open_until: Option<Instant>,
+    probing: bool,
+}
+
+impl HostBreaker {
+    fn state(&self, now: Instant) -> BreakerState {
+        match self.open_until {
+            None => BreakerState::Closed,
+            Some(open_until) if now < open_until => BreakerState::Open,
+            Some(_) => BreakerState::HalfOpen,
+        }
+    }
+}
+
+/// Opens the breaker of an RPC host after `threshold` consecutive failures of its calls,
+/// for `cooldown`. A single call then probes the host, closing the breaker on success
+/// and opening it again on failure. Shared by the providers, per host.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    hosts: Mutex<HashMap<String, HostBreaker>>,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        CircuitBreaker {
+            threshold,
+            cooldown,
+            hosts: Default::default(),
+        }
+    }
+
+    /// Lets a call to `host` through, unless its breaker is open or already probing.
+    pub fn admit(&self, host: &str) -> Result<(), RpcUnavailable> {
+        let now = Instant::now();
+        let mut hosts = self.hosts.lock().unwrap();
+        let Some(breaker) = hosts.get_mut(host) else {
+            return Ok(());
+        };
+        let unavailable = |retry_in| RpcUnavailable {
+            host: host.to_owned(),
+            retry_in,
+        };
+        match breaker.state(now) {
+            BreakerState::Closed => Ok(()),
+            BreakerState::Open => Err(unavailable(
+                breaker.open_until.unwrap_or(now).duration_since(now),
+            )),
+            BreakerState::HalfOpen if breaker.probing => Err(unavailable(Duration::ZERO)),
+            BreakerState::HalfOpen => {
+                info!("Probing RPC host {} after its cooldown", host);
+                breaker.probing = true;
+                Ok(())
+            }
+        }
+    }
+
+    /// Records the outcome of a call let through by [CircuitBreaker::admit].
+    pub fn record(&self, host: &str, success: bool) {
+        let mut hosts = self.hosts.lock().unwrap();
+        if success {
+            if hosts.remove(host).is_some_and(|breaker| breaker.probing) {
+                info!("RPC host {} recovered, closing its circuit breaker", host);
+            }
+            return;
+        }
+        let breaker = hosts.entry(host.to_owned()).or_default();
+        breaker.failures += 1;
+        if breaker.probing || breaker.failures >= self.threshold {
+            warn!(
+                "RPC host {} failed {} times in a row, opening its circuit breaker for {:?}",
+                host, breaker.failures, self.cooldown
+            );
+            breaker.open_until = Some(Instant::now() + self.cooldown);
+            breaker.probing = false;
+        }
+    }
+
+    pub fn state(&self, host: &str) -> BreakerState {
+        let hosts = self.hosts.lock().unwrap();
+        hosts.get(host).map_or(BreakerState::Closed, |breaker| {
+            breaker.state(Instant::now())
+        })
+    }
+
+    /// The state of the breaker of every host which failed since its last success.
+    pub fn states(&self) -> Vec<(String, BreakerState)> {
+        let now = Instant::now();
+        let hosts = self.hosts.lock().unwrap();
+        hosts
+            .iter()
+            .map(|(host, breaker)| (host.clone(), breaker.state(now)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn breaker_opens_fails_fast_then_recovers() {
+        let breaker = CircuitBreaker::new(2, Duration::from_millis(100));
+        let host = "rpc.example";
+        assert!(breaker.admit(host).is_ok());
+        breaker.record(host, false);
+        assert_eq!(breaker.state(host), BreakerState::Closed);
+        assert!(breaker.admit(host).is_ok());
+        breaker.record(host, false);
+        assert_eq!(breaker.state(host), BreakerState::Open);
+
+        // short-circuited during the cooldown, other hosts unaffected
+        let err = breaker.admit(host).unwrap_err();
+        assert!(err.retry_in <= Duration::from_millis(100));
+        assert!(breaker.admit("other.example").is_ok());
+
+        // a single probe after the cooldown, which fails
+        std::thread::sleep(Duration::from_millis(120));
+        assert_eq!(breaker.state(host), BreakerState::HalfOpen);
+        assert!(breaker.admit(host).is_ok());
+        assert!(breaker.admit(host).is_err());
+        breaker.record(host, false);
+        assert_eq!(breaker.state(host), BreakerState::Open);
+
+        // the next probe succeeds and closes the breaker
+        std::thread::sleep(Duration::from_millis(120));
+        assert!(breaker.admit(host).is_ok());
+        breaker.record(host, true);
+        assert_eq!(breaker.state(host), BreakerState::Closed);
+        assert!(breaker.admit(host).is_ok());
+        assert!(breaker.states().is_empty());
+    }
+}
@@ -0,0 +1,65 @@
+//! Caps the connections to each RPC host, for the providers rate-limiting connections
+//! rather than requests.
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// The RPC calls in flight to a host, each holding a connection, shared by all the
+/// clients. The clients keep as many idle connections per host for reuse, short
+/// `pool_idle_timeout`s close them sooner.
+#[derive(Debug)]
+pub struct ConnectionLimit {
+    max_per_host: usize,
+    hosts: Mutex<HashMap<String, Arc<Semaphore>>>,
+}
+
+impl ConnectionLimit {
+    pub fn new(max_per_host: usize) -> Self {
+        ConnectionLimit {
+            max_per_host,
+            hosts: Default::default(),
+        }
+    }
+
+    pub fn max_per_host(&self) -> usize {
+        self.max_per_host
+    }
+
+    /// Waits for a free connection to `host`, kept until the permit is dropped.
+    pub async fn acquire(&self, host: &str) -> OwnedSemaphorePermit {
+        let semaphore = self
+            .hosts
+            .lock()
+            .unwrap()
+            .entry(host.to_owned())
+            .or_insert_with(|| Arc::new(Semaphore::new(self.max_per_host)))
+            .clone();
+        // the semaphores are never closed
+        semaphore.acquire_owned().await.unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn calls_beyond_the_limit_wait_for_a_connection() {
+        let limit = ConnectionLimit::new(2);
+        let first = limit.acquire("rpc.example").await;
+        let _second = limit.acquire("rpc.example").await;
+        // other hosts have their own connections
+        let _other = limit.acquire("l1.example").await;
+
+        let third = tokio::time::timeout(Duration::from_millis(50), limit.acquire("rpc.example"));
+        assert!(third.await.is_err());
+        drop(first);
+        let third = tokio::time::timeout(Duration::from_millis(50), limit.acquire("rpc.example"));
+        assert!(third.await.is_ok());
+    }
+}
@@ -0,0 +1,377 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Where the block data missing from the cache comes from.
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{anyhow, Context, Result};
+use ethers_core::types::{Block, Bytes, EIP1186ProofResponse, Transaction, H160, H256, U256};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_with::{DeserializeFromStr, SerializeDisplay};
+#[cfg(feature = "taiko")]
+use zeth_primitives::taiko::BlockProposed;
+
+use super::{
+    rpc_provider::RpcProvider, AccountQuery, BlockQuery, ProofQuery, Provider, StorageQuery,
+};
+#[cfg(feature = "taiko")]
+use super::{BlobQuery, ProposeQuery};
+#[cfg(feature = "taiko")]
+use crate::taiko::blob::BlobSidecar;
+
+/// The data the witness builder needs from a source of chain data. Everything else it
+/// looks up is derived from these.
+pub trait BlockDataSource: Send {
+    fn fetch_block(&mut self, query: &BlockQuery) -> Result<Block<Transaction>>;
+    /// The account with the storage slots of `query.indices`.
+    fn fetch_proofs(&mut self, query: &ProofQuery) -> Result<EIP1186ProofResponse>;
+    fn fetch_code(&mut self, query: &AccountQuery) -> Result<Bytes>;
+    /// The L1 transaction proposing `query.l2_block_no` with its event.
+    #[cfg(feature = "taiko")]
+    fn fetch_l1_anchor(&mut self, query: &ProposeQuery) -> Result<(Transaction, BlockProposed)>;
+}
+
+/// Kind of the configured data source. With `dir` the RPC URLs name directories of
+/// exported data instead, laid out as described on [DirectorySource].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, DeserializeFromStr, SerializeDisplay)]
+pub enum DataSourceKind {
+    #[default]
+    Rpc,
+    Dir,
+}
+
+impl FromStr for DataSourceKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rpc" => Ok(DataSourceKind::Rpc),
+            "dir" => Ok(DataSourceKind::Dir),
+            _ => Err(format!("invalid data source {:?}, expected rpc or dir", s)),
+        }
+    }
+}
+
+impl fmt::Display for DataSourceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DataSourceKind::Rpc => write!(f, "rpc"),
+            DataSourceKind::Dir => write!(f, "dir"),
+        }
+    }
+}
+
+impl BlockDataSource for RpcProvider {
+    fn fetch_block(&mut self, query: &BlockQuery) -> Result<Block<Transaction>> {
+        self.get_full_block(query)
+    }
+
+    fn fetch_proofs(&mut self, query: &ProofQuery) -> Result<EIP1186ProofResponse> {
+        self.get_proof(query)
+    }
+
+    fn fetch_code(&mut self, query: &AccountQuery) -> Result<Bytes> {
+        self.get_code(query)
+    }
+
+    #[cfg(feature = "taiko")]
+    fn fetch_l1_anchor(&mut self, query: &ProposeQuery) -> Result<(Transaction, BlockProposed)> {
+        self.get_propose(query)
+    }
+}
+
+/// An account as exported at a block, with all the storage slots the export covers.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ExportedAccount {
+    pub proof: EIP1186ProofResponse,
+    pub code: Bytes,
+}
+
+/// Reads the block data from a directory of pre-exported JSON files:
+///
+/// - `blocks/{block_no}.json`: the block with its transactions,
+/// - `accounts/{block_no}/{address}.json`: an [ExportedAccount] at the end of the block,
+/// - `anchors/{l2_block_no}.json`: the proposing L1 transaction and its `BlockProposed`
+///   event, as a pair.
+///
+/// Addresses are lowercase hex with the `0x` prefix.
+pub struct DirectorySource {
+    dir: PathBuf,
+}
+
+impl DirectorySource {
+    pub fn new(dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        if !dir.is_dir() {
+            return Err(anyhow!("Data source directory {:?} does not exist", dir));
+        }
+        Ok(DirectorySource { dir })
+    }
+
+    fn read<T: DeserializeOwned>(&self, path: &Path) -> Result<T> {
+        let path = self.dir.join(path);
+        let data = fs::read(&path).with_context(|| format!("No data in {:?}", path))?;
+        serde_json::from_slice(&data).with_context(|| format!("Malformed data in {:?}", path))
+    }
+
+    fn read_account(&self, block_no: u64, address: &H160) -> Result<ExportedAccount> {
+        self.read(
+            &Path::new("accounts")
+                .join(block_no.to_string())
+                .join(format!("{:#x}.json", address)),
+        )
+    }
+}
+
+impl BlockDataSource for DirectorySource {
+    fn fetch_block(&mut self, query: &BlockQuery) -> Result<Block<Transaction>> {
+        self.read(&Path::new("blocks").join(format!("{}.json", query.block_no)))
+    }
+
+    fn fetch_proofs(&mut self, query: &ProofQuery) -> Result<EIP1186ProofResponse> {
+        let mut proof = self.read_account(query.block_no, &query.address)?.proof;
+        proof
+            .storage_proof
+            .retain(|storage| query.indices.contains(&storage.key));
+        if proof.storage_proof.len() != query.indices.len() {
+            return Err(anyhow!(
+                "Storage slots missing from the export: {:?}",
+                query
+            ));
+        }
+        Ok(proof)
+    }
+
+    fn fetch_code(&mut self, query: &AccountQuery) -> Result<Bytes> {
+        Ok(self.read_account(query.block_no, &query.address)?.code)
+    }
+
+    #[cfg(feature = "taiko")]
+    fn fetch_l1_anchor(&mut self, query: &ProposeQuery) -> Result<(Transaction, BlockProposed)> {
+        self.read(&Path::new("anchors").join(format!("{}.json", query.l2_block_no)))
+    }
+}
+
+/// Serves the [Provider] lookups from a [BlockDataSource].
+pub struct SourceProvider<S> {
+    source: S,
+}
+
+impl<S: BlockDataSource> SourceProvider<S> {
+    pub fn new(source: S) -> Self {
+        SourceProvider { source }
+    }
+
+    fn account(&mut self, query: &AccountQuery) -> Result<EIP1186ProofResponse> {
+        self.source.fetch_proofs(&ProofQuery {
+            block_no: query.block_no,
+            address: query.address,
+            indices: Default::default(),
+        })
+    }
+}
+
+impl<S: BlockDataSource> Provider for SourceProvider<S> {
+    fn save(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn get_full_block(&mut self, query: &BlockQuery) -> Result<Block<Transaction>> {
+        self.source.fetch_block(query)
+    }
+
+    fn get_partial_block(&mut self, query: &BlockQuery) -> Result<Block<H256>> {
+        // a block with the hashes of its transactions serializes like the full block with
+        // them replaced
+        let mut block = serde_json::to_value(self.source.fetch_block(query)?)?;
+        if let Some(transactions) = block.get_mut("transactions") {
+            let hashes: Vec<H256> =
+                serde_json::from_value::<Vec<Transaction>>(transactions.take())?
+                    .into_iter()
+                    .map(|tx| tx.hash)
+                    .collect();
+            *transactions = serde_json::to_value(hashes)?;
+        }
+        Ok(serde_json::from_value(block)?)
+    }
+
+    fn get_proof(&mut self, query: &ProofQuery) -> Result<EIP1186ProofResponse> {
+        self.source.fetch_proofs(query)
+    }
+
+    fn get_transaction_count(&mut self, query: &AccountQuery) -> Result<U256> {
+        Ok(self.account(query)?.nonce.as_u64().into())
+    }
+
+    fn get_balance(&mut self, query: &AccountQuery) -> Result<U256> {
+        Ok(self.account(query)?.balance)
+    }
+
+    fn get_code(&mut self, query: &AccountQuery) -> Result<Bytes> {
+        self.source.fetch_code(query)
+    }
+
+    fn get_storage(&mut self, query: &StorageQuery) -> Result<H256> {
+        let proof = self.source.fetch_proofs(&ProofQuery {
+            block_no: query.block_no,
+            address: query.address,
+            indices: [query.index].into(),
+        })?;
+        let value = proof.storage_proof[0].value;
+        let mut out = H256::zero();
+        value.to_big_endian(out.as_bytes_mut());
+        Ok(out)
+    }
+
+    #[cfg(feature = "taiko")]
+    fn get_propose(&mut self, query: &ProposeQuery) -> Result<(Transaction, BlockProposed)> {
+        self.source.fetch_l1_anchor(query)
+    }
+
+    #[cfg(feature = "taiko")]
+    fn batch_get_partial_blocks(&mut self, query: &BlockQuery) -> Result<Vec<Block<H256>>> {
+        // the ancestors are fetched one by one when they are looked up
+        Ok(vec![self.get_partial_block(&BlockQuery {
+            block_no: query.block_no - 1,
+        })?])
+    }
+
+    #[cfg(feature = "taiko")]
+    fn get_blob(&mut self, query: &BlobQuery) -> Result<BlobSidecar> {
+        Err(anyhow!(
+            "Blobs are not part of the data source: {:?}",
+            query
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use ethers_core::types::{StorageProof, U64};
+
+    use super::*;
+
+    fn export(dir: &Path, block_no: u64, account: &ExportedAccount) {
+        let accounts = dir.join("accounts").join(block_no.to_string());
+        fs::create_dir_all(&accounts).unwrap();
+        fs::write(
+            accounts.join(format!("{:#x}.json", account.proof.address)),
+            serde_json::to_vec(account).unwrap(),
+        )
+        .unwrap();
+
+        let blocks = dir.join("blocks");
+        fs::create_dir_all(&blocks).unwrap();
+        let block = Block::<Transaction> {
+            number: Some(block_no.into()),
+            transactions: vec![Transaction {
+                hash: H256::repeat_byte(0xaa),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        fs::write(
+            blocks.join(format!("{}.json", block_no)),
+            serde_json::to_vec(&block).unwrap(),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn provider_lookups_are_served_from_an_export_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let address = H160::repeat_byte(0x11);
+        let slot = H256::from_low_u64_be(1);
+        let account = ExportedAccount {
+            proof: EIP1186ProofResponse {
+                address,
+                balance: 1000.into(),
+                nonce: U64::from(7),
+                storage_proof: vec![
+                    StorageProof {
+                        key: slot,
+                        value: 42.into(),
+                        ..Default::default()
+                    },
+                    StorageProof {
+                        key: H256::from_low_u64_be(2),
+                        value: 43.into(),
+                        ..Default::default()
+                    },
+                ],
+                ..Default::default()
+            },
+            code: Bytes::from_static(&[0x60, 0x00]),
+        };
+        export(dir.path(), 5, &account);
+
+        let mut provider = SourceProvider::new(DirectorySource::new(dir.path()).unwrap());
+        let account_query = AccountQuery {
+            block_no: 5,
+            address,
+        };
+        assert_eq!(
+            provider.get_transaction_count(&account_query).unwrap(),
+            7.into()
+        );
+        assert_eq!(provider.get_balance(&account_query).unwrap(), 1000.into());
+        assert_eq!(provider.get_code(&account_query).unwrap(), account.code);
+        let storage = provider
+            .get_storage(&StorageQuery {
+                block_no: 5,
+                address,
+                index: slot,
+            })
+            .unwrap();
+        assert_eq!(storage, H256::from_low_u64_be(42));
+
+        let proof = provider
+            .get_proof(&ProofQuery {
+                block_no: 5,
+                address,
+                indices: BTreeSet::from([slot]),
+            })
+            .unwrap();
+        assert_eq!(proof.storage_proof.len(), 1);
+        assert!(provider
+            .get_proof(&ProofQuery {
+                block_no: 5,
+                address,
+                indices: BTreeSet::from([H256::from_low_u64_be(3)]),
+            })
+            .is_err());
+
+        let block = provider
+            .get_partial_block(&BlockQuery { block_no: 5 })
+            .unwrap();
+        assert_eq!(block.transactions, vec![H256::repeat_byte(0xaa)]);
+        assert!(provider
+            .get_full_block(&BlockQuery { block_no: 6 })
+            .is_err());
+    }
+
+    #[test]
+    fn data_source_kind_round_trips() {
+        for kind in [DataSourceKind::Rpc, DataSourceKind::Dir] {
+            assert_eq!(kind.to_string().parse::<DataSourceKind>(), Ok(kind));
+        }
+        assert!("lake".parse::<DataSourceKind>().is_err());
+    }
+}
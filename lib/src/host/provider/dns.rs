@@ -0,0 +1,154 @@
+//! DNS resolution of the RPC hosts, for load balancers rotating their IPs and for test
+//! environments without DNS.
+use std::{
+    collections::HashMap,
+    fmt,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use serde_with::{DeserializeFromStr, SerializeDisplay};
+use tracing::debug;
+
+/// Resolves the RPC hosts, reusing the addresses for `ttl`. A zero `ttl` resolves the
+/// host again for every new connection.
+#[derive(Debug)]
+pub struct CachingResolver {
+    ttl: Duration,
+    cache: Arc<Mutex<HashMap<String, (Instant, Vec<SocketAddr>)>>>,
+}
+
+impl CachingResolver {
+    pub fn new(ttl: Duration) -> Self {
+        CachingResolver {
+            ttl,
+            cache: Default::default(),
+        }
+    }
+}
+
+impl Resolve for CachingResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_owned();
+        let ttl = self.ttl;
+        let cache = self.cache.clone();
+        Box::pin(async move {
+            let cached = cache
+                .lock()
+                .unwrap()
+                .get(&host)
+                .filter(|(resolved_at, _)| resolved_at.elapsed() < ttl)
+                .map(|(_, addrs)| addrs.clone());
+            let addrs = match cached {
+                Some(addrs) => {
+                    debug!("Resolved {} to {:?} from the cache", host, addrs);
+                    addrs
+                }
+                None => {
+                    let addrs: Vec<SocketAddr> =
+                        tokio::net::lookup_host((host.as_str(), 0)).await?.collect();
+                    debug!("Resolved {} to {:?}", host, addrs);
+                    if !ttl.is_zero() {
+                        cache
+                            .lock()
+                            .unwrap()
+                            .insert(host, (Instant::now(), addrs.clone()));
+                    }
+                    addrs
+                }
+            };
+            Ok::<_, Box<dyn std::error::Error + Send + Sync>>(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Pins a host to an IP, given as `host:ip`.
+#[derive(Debug, Clone, PartialEq, Eq, DeserializeFromStr, SerializeDisplay)]
+pub struct ResolveOverride {
+    pub host: String,
+    pub ip: IpAddr,
+}
+
+impl FromStr for ResolveOverride {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // hosts have no colons, IPv6 addresses do
+        let (host, ip) = s
+            .split_once(':')
+            .filter(|(host, _)| !host.is_empty())
+            .ok_or_else(|| format!("invalid resolve override {:?}, expected host:ip", s))?;
+        let ip = ip
+            .parse()
+            .map_err(|e| format!("invalid ip {:?} in {:?}: {}", ip, s, e))?;
+        Ok(ResolveOverride {
+            host: host.to_owned(),
+            ip,
+        })
+    }
+}
+
+impl fmt::Display for ResolveOverride {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.host, self.ip)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Body, Response};
+
+    use super::*;
+    use crate::host::provider::{mock_server::serve, RpcOptions};
+
+    #[test]
+    fn parse_resolve_override() {
+        let pin: ResolveOverride = "rpc.example:10.0.0.1".parse().unwrap();
+        assert_eq!(pin.host, "rpc.example");
+        assert_eq!(pin.ip, IpAddr::from([10, 0, 0, 1]));
+        assert_eq!(pin.to_string(), "rpc.example:10.0.0.1");
+        let pin: ResolveOverride = "rpc.example:::1".parse().unwrap();
+        assert_eq!(pin.ip, "::1".parse::<IpAddr>().unwrap());
+        assert!("rpc.example".parse::<ResolveOverride>().is_err());
+        assert!(":10.0.0.1".parse::<ResolveOverride>().is_err());
+        assert!("rpc.example:10.0.0".parse::<ResolveOverride>().is_err());
+    }
+
+    #[tokio::test]
+    async fn static_override_reaches_the_pinned_ip() {
+        let url = serve(|_| async { Response::new(Body::from("pinned")) });
+
+        let options = RpcOptions {
+            resolve_overrides: vec!["rpc.invalid:127.0.0.1".parse().unwrap()],
+            dns_resolver: Some(Arc::new(CachingResolver::new(Duration::from_secs(60)))),
+            ..Default::default()
+        };
+        let client = options.client_builder().build().unwrap();
+        let body = client
+            .get(url.replace("127.0.0.1", "rpc.invalid"))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert_eq!(body, "pinned");
+    }
+
+    #[tokio::test]
+    async fn resolved_addresses_are_cached_for_the_ttl() {
+        let resolve =
+            |resolver: &CachingResolver| resolver.resolve(Name::from_str("localhost").unwrap());
+        let resolver = CachingResolver::new(Duration::from_secs(60));
+        let addrs: Vec<_> = resolve(&resolver).await.unwrap().collect();
+        assert!(!addrs.is_empty());
+        assert_eq!(resolver.cache.lock().unwrap()["localhost"].1, addrs);
+
+        let resolver = CachingResolver::new(Duration::ZERO);
+        assert!(resolve(&resolver).await.is_ok());
+        assert!(resolver.cache.lock().unwrap().is_empty());
+    }
+}
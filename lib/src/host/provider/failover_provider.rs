@@ -0,0 +1,248 @@
+//! Fails over between the RPC nodes of a comma-separated `rpc_url`, in their order, for
+//! availability during the incidents of a provider. The data fetched from any of them
+//! goes through the same checks.
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use ethers_core::types::{Block, Bytes, EIP1186ProofResponse, Transaction, H256, U256};
+use ethers_providers::{ProviderError, RpcError};
+use tracing::warn;
+#[cfg(feature = "taiko")]
+use zeth_primitives::taiko::BlockProposed;
+
+use super::{
+    circuit_breaker::RpcUnavailable,
+    rpc_provider::{RpcProvider, RpcTimeout},
+    AccountQuery, BlockQuery, ProofQuery, Provider, RpcOptions, StorageQuery,
+};
+#[cfg(feature = "taiko")]
+use super::{BlobQuery, ProposeQuery};
+#[cfg(feature = "taiko")]
+use crate::taiko::blob::BlobSidecar;
+
+/// How many consecutive failures of an endpoint get it skipped.
+const FAILURES_BEFORE_SKIPPING: u32 = 3;
+/// How long a failing endpoint is skipped, before it is tried again.
+const SKIP_DURATION: Duration = Duration::from_secs(30);
+
+/// The consecutive failures of the RPC endpoints, shared by the providers so that an
+/// endpoint failing persistently is skipped by all of them.
+#[derive(Debug, Default)]
+pub struct EndpointHealth {
+    endpoints: Mutex<HashMap<String, (u32, Option<Instant>)>>,
+}
+
+impl EndpointHealth {
+    fn is_skipped(&self, url: &str) -> bool {
+        self.endpoints
+            .lock()
+            .unwrap()
+            .get(url)
+            .and_then(|(_, skipped_until)| *skipped_until)
+            .map_or(false, |skipped_until| Instant::now() < skipped_until)
+    }
+
+    fn record_success(&self, url: &str) {
+        self.endpoints.lock().unwrap().remove(url);
+    }
+
+    fn record_failure(&self, url: &str) {
+        let mut endpoints = self.endpoints.lock().unwrap();
+        let (failures, skipped_until) = endpoints.entry(url.to_owned()).or_default();
+        *failures += 1;
+        if *failures >= FAILURES_BEFORE_SKIPPING {
+            warn!(
+                "RPC endpoint {} failed {} times in a row, skipping it for {:?}",
+                url, failures, SKIP_DURATION
+            );
+            *skipped_until = Some(Instant::now() + SKIP_DURATION);
+        }
+    }
+}
+
+/// Whether `err` is a fault of the endpoint rather than of the call, worth retrying on
+/// another endpoint: a transport error, a timeout, an open circuit breaker, or an answer
+/// that is no JSON-RPC response, such as the error pages of the 5xx statuses.
+pub fn is_endpoint_failure(err: &anyhow::Error) -> bool {
+    if err.is::<RpcTimeout>() || err.is::<RpcUnavailable>() {
+        return true;
+    }
+    match err.downcast_ref::<ProviderError>() {
+        Some(ProviderError::HTTPError(_)) => true,
+        Some(ProviderError::JsonRpcClientError(err)) => !err.is_error_response(),
+        _ => false,
+    }
+}
+
+pub struct FailoverProvider {
+    endpoints: Vec<(String, Box<dyn Provider>)>,
+    health: Arc<EndpointHealth>,
+}
+
+impl FailoverProvider {
+    pub fn new(rpc_urls: Vec<String>, options: &RpcOptions) -> Result<Self> {
+        if rpc_urls.is_empty() {
+            bail!("No RPC endpoint given");
+        }
+        let endpoints = rpc_urls
+            .into_iter()
+            .map(|url| {
+                let rpc: Box<dyn Provider> = Box::new(RpcProvider::new(url.clone(), options)?);
+                Ok((url, rpc))
+            })
+            .collect::<Result<_>>()?;
+        Ok(FailoverProvider {
+            endpoints,
+            health: options.endpoint_health.clone(),
+        })
+    }
+
+    /// Runs `call` on the first healthy endpoint, failing over to the next ones on the
+    /// faults of the endpoints. The skipped endpoints are tried last rather than never.
+    fn call<T>(&mut self, mut call: impl FnMut(&mut dyn Provider) -> Result<T>) -> Result<T> {
+        let health = self.health.clone();
+        let (healthy, skipped): (Vec<_>, Vec<_>) = self
+            .endpoints
+            .iter_mut()
+            .partition(|(url, _)| !health.is_skipped(url));
+        let mut last_err = None;
+        for (url, rpc) in healthy.into_iter().chain(skipped) {
+            match call(rpc.as_mut()) {
+                Ok(out) => {
+                    health.record_success(url);
+                    return Ok(out);
+                }
+                Err(err) if is_endpoint_failure(&err) => {
+                    warn!("RPC endpoint {} failed, failing over: {:#}", url, err);
+                    health.record_failure(url);
+                    last_err = Some(err);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Err(last_err.expect("the endpoints are never empty"))
+    }
+}
+
+impl Provider for FailoverProvider {
+    fn save(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn get_full_block(&mut self, query: &BlockQuery) -> Result<Block<Transaction>> {
+        self.call(|rpc| rpc.get_full_block(query))
+    }
+
+    fn get_partial_block(&mut self, query: &BlockQuery) -> Result<Block<H256>> {
+        self.call(|rpc| rpc.get_partial_block(query))
+    }
+
+    fn get_proof(&mut self, query: &ProofQuery) -> Result<EIP1186ProofResponse> {
+        self.call(|rpc| rpc.get_proof(query))
+    }
+
+    fn get_transaction_count(&mut self, query: &AccountQuery) -> Result<U256> {
+        self.call(|rpc| rpc.get_transaction_count(query))
+    }
+
+    fn get_balance(&mut self, query: &AccountQuery) -> Result<U256> {
+        self.call(|rpc| rpc.get_balance(query))
+    }
+
+    fn get_code(&mut self, query: &AccountQuery) -> Result<Bytes> {
+        self.call(|rpc| rpc.get_code(query))
+    }
+
+    fn get_storage(&mut self, query: &StorageQuery) -> Result<H256> {
+        self.call(|rpc| rpc.get_storage(query))
+    }
+
+    #[cfg(feature = "taiko")]
+    fn get_propose(&mut self, query: &ProposeQuery) -> Result<(Transaction, BlockProposed)> {
+        self.call(|rpc| rpc.get_propose(query))
+    }
+
+    #[cfg(feature = "taiko")]
+    fn batch_get_partial_blocks(&mut self, query: &BlockQuery) -> Result<Vec<Block<H256>>> {
+        self.call(|rpc| rpc.batch_get_partial_blocks(query))
+    }
+
+    #[cfg(feature = "taiko")]
+    fn get_blob(&mut self, query: &BlobQuery) -> Result<BlobSidecar> {
+        self.call(|rpc| rpc.get_blob(query))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use anyhow::anyhow;
+    use ethers_core::types::H160;
+    use hyper::{Body, Response, StatusCode};
+    use tokio::runtime::Runtime;
+
+    use super::*;
+    use crate::host::provider::mock_server::{serve, serve_json_rpc};
+
+    /// An RPC node answering every call with `503`, counting the calls.
+    fn unavailable_rpc(runtime: &Runtime) -> (String, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        let _guard = runtime.enter();
+        let url = serve(move |_| {
+            counted.fetch_add(1, Ordering::SeqCst);
+            async {
+                let mut response = Response::new(Body::from("upstream unavailable"));
+                *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+                response
+            }
+        });
+        (url, calls)
+    }
+
+    /// An RPC node answering every call with `result`.
+    fn healthy_rpc(runtime: &Runtime, result: &'static str) -> String {
+        let _guard = runtime.enter();
+        serve_json_rpc(move |_| result.into())
+    }
+
+    #[test]
+    fn fails_over_to_the_next_endpoint() {
+        let runtime = Runtime::new().unwrap();
+        let (unavailable, calls) = unavailable_rpc(&runtime);
+        let healthy = healthy_rpc(&runtime, "0x7");
+        let query = AccountQuery {
+            block_no: 1,
+            address: H160::zero(),
+        };
+
+        let options = RpcOptions::default();
+        let provider = |urls: Vec<String>| {
+            let _guard = runtime.enter();
+            FailoverProvider::new(urls, &options).unwrap()
+        };
+        let mut first = provider(vec![unavailable.clone(), healthy.clone()]);
+        for _ in 0..FAILURES_BEFORE_SKIPPING {
+            assert_eq!(first.get_transaction_count(&query).unwrap(), 7.into());
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert!(options.endpoint_health.is_skipped(&unavailable));
+
+        // the failing endpoint is skipped, by the other providers too
+        let mut second = provider(vec![unavailable, healthy]);
+        assert_eq!(second.get_transaction_count(&query).unwrap(), 7.into());
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn errors_of_the_calls_are_not_failed_over() {
+        let err = anyhow::Error::from(ProviderError::CustomError("reverted".to_owned()));
+        assert!(!is_endpoint_failure(&err));
+        assert!(!is_endpoint_failure(&anyhow!("No data for block 1")));
+    }
+}
@@ -0,0 +1,40 @@
+//! Caps the RPC calls in flight per layer, shared by the proofs, so that a slow or
+//! rate-limited L1 provider holds back the L1 calls only, and not the L2 state fetches.
+use std::{sync::Arc, time::Duration};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// The cap of the RPC calls in flight and the request timeout of the fetches of a layer.
+#[derive(Debug, Clone, Default)]
+pub struct LayerLimit {
+    /// `None` leaves the calls unlimited
+    concurrency: Option<Arc<Semaphore>>,
+    /// replaces the request timeout of the options, `None` keeps it
+    pub request_timeout: Option<Duration>,
+}
+
+impl LayerLimit {
+    pub fn new(concurrency: Option<usize>, request_timeout: Option<Duration>) -> Self {
+        LayerLimit {
+            concurrency: concurrency.map(|limit| Arc::new(Semaphore::new(limit))),
+            request_timeout,
+        }
+    }
+
+    /// Waits for a free slot of the layer, kept until the permit is dropped. `None` when
+    /// the calls are unlimited.
+    pub async fn acquire(&self) -> Option<OwnedSemaphorePermit> {
+        match &self.concurrency {
+            // the semaphores are never closed
+            Some(semaphore) => Some(semaphore.clone().acquire_owned().await.unwrap()),
+            None => None,
+        }
+    }
+}
+
+/// The limits of the L1 and of the L2 fetches.
+#[derive(Debug, Clone, Default)]
+pub struct LayerLimits {
+    pub l1: LayerLimit,
+    pub l2: LayerLimit,
+}
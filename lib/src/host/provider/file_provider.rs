@@ -16,6 +16,7 @@ use std::{
     collections::{BTreeMap, HashMap},
     fs::File,
     io::{Read, Write},
+    path::Path,
 };
 
 use anyhow::{anyhow, Result};
@@ -25,7 +26,19 @@ use serde_with::serde_as;
 #[cfg(feature = "taiko")]
 use zeth_primitives::taiko::BlockProposed;
 
-use super::{AccountQuery, BlockQuery, MutProvider, ProofQuery, Provider, StorageQuery};
+#[cfg(feature = "taiko")]
+use super::BlobQuery;
+use super::{
+    witness_version::{deserialize_witness, WITNESS_VERSION},
+    AccountQuery, BlockQuery, MutProvider, ProofQuery, Provider, StorageQuery,
+};
+use crate::host::atomic_file::write_atomically;
+#[cfg(feature = "taiko")]
+use crate::taiko::blob::BlobSidecar;
+
+/// Extension of the temporary files of the saves, e.g. `1.l1.json.gz.a1B2c3.tmp` while
+/// saving `1.l1.json.gz`.
+pub const TMP_EXTENSION: &str = "tmp";
 
 #[serde_as]
 #[derive(Deserialize, Serialize)]
@@ -34,6 +47,9 @@ pub struct FileProvider {
     file_path: String,
     #[serde(skip)]
     dirty: bool,
+    /// the version of the witness format, [WITNESS_VERSION] once read
+    #[serde(default)]
+    version: u32,
     #[serde_as(as = "Vec<(_, _)>")]
     full_blocks: HashMap<BlockQuery, Block<Transaction>>,
     #[serde_as(as = "Vec<(_, _)>")]
@@ -50,6 +66,10 @@ pub struct FileProvider {
     storage: HashMap<StorageQuery, H256>,
     #[cfg(feature = "taiko")]
     propose: Option<(Transaction, BlockProposed)>,
+    #[cfg(feature = "taiko")]
+    #[serde(default)]
+    #[serde_as(as = "Vec<(_, _)>")]
+    blobs: HashMap<BlobQuery, BlobSidecar>,
 }
 
 impl FileProvider {
@@ -57,6 +77,7 @@ impl FileProvider {
         FileProvider {
             file_path,
             dirty: false,
+            version: WITNESS_VERSION,
             full_blocks: HashMap::new(),
             partial_blocks: BTreeMap::new(),
             proofs: HashMap::new(),
@@ -66,6 +87,8 @@ impl FileProvider {
             storage: HashMap::new(),
             #[cfg(feature = "taiko")]
             propose: Default::default(),
+            #[cfg(feature = "taiko")]
+            blobs: HashMap::new(),
         }
     }
 
@@ -74,21 +97,49 @@ impl FileProvider {
         let mut decoder = flate2::read::GzDecoder::new(File::open(&file_path)?);
         decoder.read_to_end(&mut buf)?;
 
-        let mut out: Self = serde_json::from_slice(&buf[..])?;
+        let mut out = deserialize_witness(&buf[..])?;
 
         out.file_path = file_path;
         out.dirty = false;
         Ok(out)
     }
 
+    /// The provider read from a file of an older version, converted to `version`.
+    pub(crate) fn with_version(self, version: u32) -> Self {
+        FileProvider { version, ..self }
+    }
+
+    /// The full blocks of the file, by block number.
+    pub fn full_blocks(&self) -> Vec<(&BlockQuery, &Block<Transaction>)> {
+        let mut blocks: Vec<_> = self.full_blocks.iter().collect();
+        blocks.sort_by_key(|(query, _)| query.block_no);
+        blocks
+    }
+
+    pub fn partial_block(&self, query: &BlockQuery) -> Option<&Block<H256>> {
+        self.partial_blocks.get(query)
+    }
+
+    /// The account proofs of the file, the pre-state of its blocks among them.
+    pub fn proofs(&self) -> impl Iterator<Item = (&ProofQuery, &EIP1186ProofResponse)> {
+        self.proofs.iter()
+    }
+
+    /// Whether entries were inserted since the data was read.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Saves the data atomically, see [crate::host::atomic_file]: an interrupted save or
+    /// concurrent saves of the same block never leave a torn file behind.
     pub fn save_to_file(&self, file_path: &String) -> Result<()> {
         if self.dirty {
-            let mut encoder = flate2::write::GzEncoder::new(
-                File::create(file_path)?,
-                flate2::Compression::best(),
-            );
-            encoder.write_all(&serde_json::to_vec(self)?)?;
-            encoder.finish()?;
+            write_atomically(Path::new(file_path), TMP_EXTENSION, |file| {
+                let mut encoder = flate2::write::GzEncoder::new(file, flate2::Compression::best());
+                encoder.write_all(&serde_json::to_vec(self)?)?;
+                encoder.finish()?;
+                Ok(())
+            })?;
         }
 
         Ok(())
@@ -165,6 +216,14 @@ impl Provider for FileProvider {
             Ok(self.partial_blocks.values().cloned().collect())
         }
     }
+
+    #[cfg(feature = "taiko")]
+    fn get_blob(&mut self, query: &BlobQuery) -> Result<BlobSidecar> {
+        match self.blobs.get(query) {
+            Some(val) => Ok(val.clone()),
+            None => Err(anyhow!("No data for {:?}", query)),
+        }
+    }
 }
 
 impl MutProvider for FileProvider {
@@ -208,4 +267,10 @@ impl MutProvider for FileProvider {
         self.propose = Some(val);
         self.dirty = true;
     }
+
+    #[cfg(feature = "taiko")]
+    fn insert_blob(&mut self, query: BlobQuery, val: BlobSidecar) {
+        self.blobs.insert(query, val);
+        self.dirty = true;
+    }
 }
@@ -0,0 +1,57 @@
+//! Local HTTP servers standing in for the RPC nodes and services of the tests.
+use std::{convert::Infallible, future::Future, net::SocketAddr};
+
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server,
+};
+use serde_json::Value;
+
+/// Serves `handle` on a free local port, returning the url of the server. Must be called
+/// within the tokio runtime serving it.
+pub fn serve<F, Fut>(handle: F) -> String
+where
+    F: Fn(Request<Body>) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = Response<Body>> + Send + 'static,
+{
+    let make_service = make_service_fn(move |_| {
+        let handle = handle.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let response = handle(req);
+                async move { Ok::<_, Infallible>(response.await) }
+            }))
+        }
+    });
+    let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_service);
+    let url = format!("http://{}", server.local_addr());
+    tokio::spawn(server);
+    url
+}
+
+/// Serves a JSON-RPC node answering each request with the result `answer` gives it.
+pub fn serve_json_rpc<F>(answer: F) -> String
+where
+    F: Fn(&Value) -> Value + Clone + Send + 'static,
+{
+    serve(move |req| {
+        let answer = answer.clone();
+        async move {
+            let request = read_json(req).await;
+            json_rpc_response(&request, serde_json::json!({ "result": answer(&request) }))
+        }
+    })
+}
+
+/// The JSON body of `req`.
+pub async fn read_json(req: Request<Body>) -> Value {
+    let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
+
+/// Answers the JSON-RPC `request` with `response`, its `result` or its `error`.
+pub fn json_rpc_response(request: &Value, mut response: Value) -> Response<Body> {
+    response["jsonrpc"] = "2.0".into();
+    response["id"] = request["id"].clone();
+    Response::new(Body::from(response.to_string()))
+}
@@ -12,7 +12,13 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::collections::BTreeSet;
+use std::{
+    collections::BTreeSet,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use anyhow::{anyhow, Result};
 use ethers_core::types::{Block, Bytes, EIP1186ProofResponse, Transaction, H160, H256, U256};
@@ -20,9 +26,42 @@ use serde::{Deserialize, Serialize};
 #[cfg(feature = "taiko")]
 use zeth_primitives::taiko::BlockProposed;
 
+use self::{
+    cache_write_limit::CacheWriteLimit,
+    circuit_breaker::CircuitBreaker,
+    connection_limit::ConnectionLimit,
+    data_source::{DataSourceKind, DirectorySource, SourceProvider},
+    dns::{CachingResolver, ResolveOverride},
+    failover_provider::{EndpointHealth, FailoverProvider},
+    fetch_limit::{LayerLimit, LayerLimits},
+    retry_policy::RetryPolicies,
+    rpc_call_budget::RpcCallBudget,
+    rpc_consistency::RpcInconsistencyPolicy,
+    rpc_header::{header_map, RpcHeader},
+    shared_cache_provider::SharedCacheProvider,
+};
+#[cfg(feature = "taiko")]
+use crate::taiko::blob::BlobSidecar;
+
+pub mod cache_write_limit;
 pub mod cached_rpc_provider;
+pub mod circuit_breaker;
+pub mod connection_limit;
+pub mod data_source;
+pub mod dns;
+pub mod failover_provider;
+pub mod fetch_limit;
 pub mod file_provider;
+#[cfg(test)]
+mod mock_server;
+pub mod retry_policy;
+pub mod rpc_allowlist;
+pub mod rpc_call_budget;
+pub mod rpc_consistency;
+pub mod rpc_header;
 pub mod rpc_provider;
+pub mod shared_cache_provider;
+pub mod witness_version;
 
 #[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub struct AccountQuery {
@@ -57,6 +96,147 @@ pub struct ProposeQuery {
     pub l2_block_no: u64,
 }
 
+#[cfg(feature = "taiko")]
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct BlobQuery {
+    /// Timestamp of the L1 block carrying the blob.
+    pub timestamp: u64,
+    pub versioned_hash: H256,
+}
+
+/// Tunables of the outbound RPC client.
+#[derive(Clone, Debug, Default)]
+pub struct RpcOptions {
+    /// How long an idle pooled connection is kept before it's closed. `None` keeps the
+    /// client's default.
+    pub pool_idle_timeout: Option<Duration>,
+    /// What the `rpc_url`s name.
+    pub data_source: DataSourceKind,
+    /// Beacon node serving the blob sidecars of the L1 blocks.
+    pub beacon_rpc_url: Option<String>,
+    /// KZG trusted setup verifying the blobs.
+    pub kzg_trusted_setup: Option<PathBuf>,
+    /// How long connecting to the RPC node may take.
+    pub connect_timeout: Option<Duration>,
+    /// How long a single RPC call may take, connecting included.
+    pub request_timeout: Option<Duration>,
+    /// How long all the RPC calls fetching a block may take together.
+    pub block_fetch_budget: Option<Duration>,
+    /// When the budget of the block being fetched runs out, see
+    /// [RpcOptions::start_block_fetch].
+    pub block_fetch_deadline: Option<Instant>,
+    /// HTTP cache shared by a fleet of provers, looked up before the RPC node. Its
+    /// entries must be kept apart per chain and layer, see
+    /// [RpcOptions::with_shared_cache_scope].
+    pub shared_cache_url: Option<String>,
+    /// Resolver of the RPC hosts, shared by the clients. `None` keeps the client's
+    /// default.
+    pub dns_resolver: Option<Arc<CachingResolver>>,
+    /// Hosts pinned to an IP, bypassing the resolver.
+    pub resolve_overrides: Vec<ResolveOverride>,
+    /// Cap of the connections to each RPC host, shared by the clients. `None` leaves
+    /// them unlimited.
+    pub connection_limit: Option<Arc<ConnectionLimit>>,
+    /// The caps of the calls in flight and the request timeouts of the L1 and L2
+    /// fetches, shared by the clients, see [RpcOptions::with_layer_limit].
+    pub layer_limits: LayerLimits,
+    /// The cap and request timeout of the layer fetched with these options.
+    pub fetch_limit: LayerLimit,
+    /// Fails the calls to the RPC hosts in an outage fast, shared by the clients. `None`
+    /// lets every call through.
+    pub circuit_breaker: Option<Arc<CircuitBreaker>>,
+    /// Failures of the RPC endpoints failed over between, shared by the clients.
+    pub endpoint_health: Arc<EndpointHealth>,
+    /// Cap of the transactions of the L2 block, checked once its data is fetched. `None`
+    /// leaves it unlimited.
+    pub max_txs_per_block: Option<usize>,
+    /// JSON-RPC methods the providers may call on top of the
+    /// [rpc_allowlist::ALLOWED_RPC_METHODS], for experimental sources.
+    pub extra_rpc_methods: Arc<BTreeSet<String>>,
+    /// Headers sent with every call to the RPC nodes, like their credentials.
+    pub rpc_headers: Vec<RpcHeader>,
+    /// Fetches every entry from the RPC nodes, ignoring the ones of the cache file and
+    /// of the shared cache, which are still written.
+    pub skip_cache_reads: bool,
+    /// Cap of the cache files written at once, shared by the providers. `None` leaves
+    /// them unlimited.
+    pub cache_write_limit: Option<Arc<CacheWriteLimit>>,
+    /// Cap of the RPC calls issued to build the witness of a block, the L1 and the L2
+    /// calls together. `None` leaves them unlimited.
+    pub max_rpc_calls_per_block: Option<u64>,
+    /// The RPC calls of the block being fetched, shared by its providers, see
+    /// [RpcOptions::start_block_fetch].
+    pub rpc_call_budget: Option<Arc<RpcCallBudget>>,
+    /// How the calls failing on a fault of the node are retried, per JSON-RPC method.
+    pub retry_policies: RetryPolicies,
+    /// How the data of a block served inconsistently by the RPC nodes is handled.
+    pub on_rpc_inconsistency: RpcInconsistencyPolicy,
+}
+
+impl RpcOptions {
+    /// The builder of the outbound HTTP clients.
+    pub fn client_builder(&self) -> reqwest::ClientBuilder {
+        let mut client_builder = reqwest::Client::builder();
+        if let Some(pool_idle_timeout) = self.pool_idle_timeout {
+            client_builder = client_builder.pool_idle_timeout(pool_idle_timeout);
+        }
+        if let Some(connect_timeout) = self.connect_timeout {
+            client_builder = client_builder.connect_timeout(connect_timeout);
+        }
+        if let Some(limit) = &self.connection_limit {
+            client_builder = client_builder.pool_max_idle_per_host(limit.max_per_host());
+        }
+        if let Some(resolver) = &self.dns_resolver {
+            client_builder = client_builder.dns_resolver(resolver.clone());
+        }
+        for pin in &self.resolve_overrides {
+            tracing::debug!("Resolving {} to {}", pin.host, pin.ip);
+            // the port of the URL is used
+            client_builder = client_builder.resolve(&pin.host, SocketAddr::new(pin.ip, 0));
+        }
+        client_builder
+    }
+
+    /// The builder of the clients calling the RPC nodes, sending the `rpc_headers`.
+    pub fn rpc_client_builder(&self) -> reqwest::ClientBuilder {
+        self.client_builder()
+            .default_headers(header_map(&self.rpc_headers))
+    }
+
+    /// Starts the budgets of the block about to be fetched with these options, keeping
+    /// the RPC call budget given by the caller to read the calls of the block from.
+    pub fn start_block_fetch(mut self) -> Self {
+        self.block_fetch_deadline = self
+            .block_fetch_budget
+            .map(|budget| Instant::now() + budget);
+        if self.rpc_call_budget.is_none() {
+            self.rpc_call_budget = Some(Arc::new(RpcCallBudget::new(self.max_rpc_calls_per_block)));
+        }
+        self
+    }
+
+    /// Fetches with the cap of the calls in flight and the request timeout of `limit`,
+    /// one of the [RpcOptions::layer_limits].
+    pub fn with_layer_limit(&self, limit: &LayerLimit) -> Self {
+        RpcOptions {
+            request_timeout: limit.request_timeout.or(self.request_timeout),
+            fetch_limit: limit.clone(),
+            ..self.clone()
+        }
+    }
+
+    /// Keeps the entries fetched with these options in `scope` of the shared cache.
+    pub fn with_shared_cache_scope(&self, scope: &str) -> Self {
+        RpcOptions {
+            shared_cache_url: self
+                .shared_cache_url
+                .as_ref()
+                .map(|url| format!("{}/{}", url.trim_end_matches('/'), scope)),
+            ..self.clone()
+        }
+    }
+}
+
 pub trait Provider: Send {
     fn save(&self) -> Result<()>;
 
@@ -73,6 +253,8 @@ pub trait Provider: Send {
     #[cfg(feature = "taiko")]
     /// get 256 blocks one time to reduce the fetch time cost
     fn batch_get_partial_blocks(&mut self, query: &BlockQuery) -> Result<Vec<Block<H256>>>;
+    #[cfg(feature = "taiko")]
+    fn get_blob(&mut self, query: &BlobQuery) -> Result<BlobSidecar>;
 }
 
 pub trait MutProvider: Provider {
@@ -86,6 +268,8 @@ pub trait MutProvider: Provider {
 
     #[cfg(feature = "taiko")]
     fn insert_propose(&mut self, query: ProposeQuery, val: (Transaction, BlockProposed));
+    #[cfg(feature = "taiko")]
+    fn insert_blob(&mut self, query: BlobQuery, val: BlobSidecar);
 }
 
 pub fn new_file_provider(file_path: String) -> Result<Box<dyn Provider>> {
@@ -94,14 +278,41 @@ pub fn new_file_provider(file_path: String) -> Result<Box<dyn Provider>> {
     Ok(Box::new(provider))
 }
 
-pub fn new_rpc_provider(rpc_url: String) -> Result<Box<dyn Provider>> {
-    let provider = rpc_provider::RpcProvider::new(rpc_url)?;
-
-    Ok(Box::new(provider))
+pub fn new_rpc_provider(rpc_url: String, options: &RpcOptions) -> Result<Box<dyn Provider>> {
+    match options.data_source {
+        DataSourceKind::Rpc => {
+            // a comma-separated list of RPC nodes, failed over between in their order
+            let rpc_urls: Vec<_> = rpc_url
+                .split(',')
+                .map(str::trim)
+                .filter(|url| !url.is_empty())
+                .map(str::to_owned)
+                .collect();
+            let rpc: Box<dyn Provider> = match <[String; 1]>::try_from(rpc_urls) {
+                Ok([rpc_url]) => Box::new(rpc_provider::RpcProvider::new(rpc_url, options)?),
+                Err(rpc_urls) => Box::new(FailoverProvider::new(rpc_urls, options)?),
+            };
+            match &options.shared_cache_url {
+                Some(url) => Ok(Box::new(SharedCacheProvider::new(
+                    url.clone(),
+                    rpc,
+                    options,
+                )?)),
+                None => Ok(rpc),
+            }
+        }
+        DataSourceKind::Dir => Ok(Box::new(SourceProvider::new(DirectorySource::new(
+            rpc_url,
+        )?))),
+    }
 }
 
-pub fn new_cached_rpc_provider(cache_path: String, rpc_url: String) -> Result<Box<dyn Provider>> {
-    let provider = cached_rpc_provider::CachedRpcProvider::new(cache_path, rpc_url)?;
+pub fn new_cached_rpc_provider(
+    cache_path: String,
+    rpc_url: String,
+    options: &RpcOptions,
+) -> Result<Box<dyn Provider>> {
+    let provider = cached_rpc_provider::CachedRpcProvider::new(cache_path, rpc_url, options)?;
 
     Ok(Box::new(provider))
 }
@@ -109,11 +320,12 @@ pub fn new_cached_rpc_provider(cache_path: String, rpc_url: String) -> Result<Bo
 pub fn new_provider(
     cache_path: Option<String>,
     rpc_url: Option<String>,
+    options: &RpcOptions,
 ) -> Result<Box<dyn Provider>> {
     match (cache_path, rpc_url) {
-        (Some(cache_path), Some(rpc_url)) => new_cached_rpc_provider(cache_path, rpc_url),
+        (Some(cache_path), Some(rpc_url)) => new_cached_rpc_provider(cache_path, rpc_url, options),
         (Some(cache_path), None) => new_file_provider(cache_path),
-        (None, Some(rpc_url)) => new_rpc_provider(rpc_url),
+        (None, Some(rpc_url)) => new_rpc_provider(rpc_url, options),
         (None, None) => Err(anyhow!("No cache_path or rpc_url given")),
     }
 }
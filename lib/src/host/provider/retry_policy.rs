@@ -0,0 +1,172 @@
+//! Retries of the RPC calls failing on a fault of the node, tuned per JSON-RPC method:
+//! the heavy `eth_getProof` fails transiently far more often than the light calls, and
+//! is worth retrying harder without slowing down the others. All the calls are
+//! read-only, so any of them is safe to retry.
+use std::{collections::HashMap, fmt, str::FromStr, time::Duration};
+
+use serde_with::{DeserializeFromStr, SerializeDisplay};
+
+/// How many times a failed call is retried, waiting `base_delay` before the first retry
+/// and twice as long before each next one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// The delay before the `retry`th retry, counted from 1.
+    pub fn delay(&self, retry: u32) -> Duration {
+        self.base_delay
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+    }
+}
+
+/// The retry policy of a JSON-RPC method, given as `method=max_retries:base_delay_ms`.
+#[derive(Debug, Clone, PartialEq, Eq, DeserializeFromStr, SerializeDisplay)]
+pub struct MethodRetryPolicy {
+    pub method: String,
+    pub policy: RetryPolicy,
+}
+
+impl FromStr for MethodRetryPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid RPC retry policy {:?}, expected method=max_retries:base_delay_ms",
+                s
+            )
+        };
+        let (method, policy) = s.split_once('=').ok_or_else(invalid)?;
+        let (max_retries, base_delay_ms) = policy.split_once(':').ok_or_else(invalid)?;
+        if method.is_empty() {
+            return Err(invalid());
+        }
+        Ok(MethodRetryPolicy {
+            method: method.to_owned(),
+            policy: RetryPolicy {
+                max_retries: max_retries.parse().map_err(|_| invalid())?,
+                base_delay: Duration::from_millis(base_delay_ms.parse().map_err(|_| invalid())?),
+            },
+        })
+    }
+}
+
+impl fmt::Display for MethodRetryPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}={}:{}",
+            self.method,
+            self.policy.max_retries,
+            self.policy.base_delay.as_millis()
+        )
+    }
+}
+
+/// The retry policies of the RPC calls: the one of their method, or the default one.
+#[derive(Debug, Clone, Default)]
+pub struct RetryPolicies {
+    default: RetryPolicy,
+    methods: HashMap<String, RetryPolicy>,
+}
+
+impl RetryPolicies {
+    pub fn new(default: RetryPolicy, methods: &[MethodRetryPolicy]) -> Self {
+        RetryPolicies {
+            default,
+            methods: methods
+                .iter()
+                .map(|method| (method.method.clone(), method.policy))
+                .collect(),
+        }
+    }
+
+    pub fn of(&self, method: &str) -> RetryPolicy {
+        self.methods.get(method).copied().unwrap_or(self.default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeMap,
+        sync::{Arc, Mutex},
+    };
+
+    use ethers_core::types::H160;
+    use hyper::{Body, Response, StatusCode};
+    use tokio::runtime::Runtime;
+
+    use super::*;
+    use crate::host::provider::{
+        mock_server::{read_json, serve},
+        rpc_provider::RpcProvider,
+        AccountQuery, ProofQuery, Provider, RpcOptions,
+    };
+
+    #[test]
+    fn methods_are_retried_as_configured() {
+        let runtime = Runtime::new().unwrap();
+        // a node failing every call with a 503, counting them by method
+        let calls = Arc::new(Mutex::new(BTreeMap::<String, u32>::new()));
+        let counted = calls.clone();
+        let url = {
+            let _guard = runtime.enter();
+            serve(move |req| {
+                let calls = counted.clone();
+                async move {
+                    let req = read_json(req).await;
+                    let method = req["method"].as_str().unwrap().to_owned();
+                    *calls.lock().unwrap().entry(method).or_default() += 1;
+                    let mut resp = Response::new(Body::from("unavailable"));
+                    *resp.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+                    resp
+                }
+            })
+        };
+
+        let get_proof: MethodRetryPolicy = "eth_getProof=3:1".parse().unwrap();
+        assert_eq!(get_proof.to_string(), "eth_getProof=3:1");
+        let options = RpcOptions {
+            retry_policies: RetryPolicies::new(
+                RetryPolicy {
+                    max_retries: 1,
+                    base_delay: Duration::from_millis(1),
+                },
+                &[get_proof],
+            ),
+            ..Default::default()
+        };
+        let mut provider = {
+            let _guard = runtime.enter();
+            RpcProvider::new(url, &options).unwrap()
+        };
+        let proof = ProofQuery {
+            block_no: 1,
+            address: H160::zero(),
+            indices: Default::default(),
+        };
+        assert!(provider.get_proof(&proof).is_err());
+        let account = AccountQuery {
+            block_no: 1,
+            address: H160::zero(),
+        };
+        assert!(provider.get_code(&account).is_err());
+
+        let calls = calls.lock().unwrap();
+        assert_eq!(calls["eth_getProof"], 4);
+        assert_eq!(calls["eth_getCode"], 2);
+
+        assert!("eth_getProof=3".parse::<MethodRetryPolicy>().is_err());
+        assert!("=3:100".parse::<MethodRetryPolicy>().is_err());
+        let policy = RetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_millis(100),
+        };
+        assert_eq!(policy.delay(1), Duration::from_millis(100));
+        assert_eq!(policy.delay(3), Duration::from_millis(400));
+    }
+}
@@ -0,0 +1,127 @@
+//! The JSON-RPC methods the providers may call, enforced by their transport so that a
+//! state-changing call can't be introduced by accident.
+use std::{collections::BTreeSet, fmt::Debug, sync::Arc};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use ethers_providers::{Http, HttpClientError, JsonRpcClient, JsonRpcError};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use tracing::error;
+
+use super::RpcOptions;
+
+/// The read-only methods of the RPC nodes the providers call, the only ones the
+/// transport lets through unless extended with `extra_rpc_methods`.
+pub const ALLOWED_RPC_METHODS: &[&str] = &[
+    // the head the RPC benchmark samples at
+    "eth_blockNumber",
+    "eth_getBalance",
+    "eth_getBlockByNumber",
+    "eth_getCode",
+    "eth_getLogs",
+    "eth_getProof",
+    "eth_getStorageAt",
+    "eth_getTransactionByHash",
+    "eth_getTransactionCount",
+    "taiko_getL2ParentHeaders",
+];
+
+/// JSON-RPC error code of the methods outside of the allowlist.
+pub const METHOD_NOT_ALLOWED_CODE: i64 = -32601;
+
+/// The HTTP transport of the RPC providers, rejecting the methods outside of
+/// [ALLOWED_RPC_METHODS] and of the `extra_methods` before anything is sent.
+#[derive(Debug, Clone)]
+pub struct AllowlistedHttp {
+    inner: Http,
+    extra_methods: Arc<BTreeSet<String>>,
+}
+
+impl AllowlistedHttp {
+    pub fn new(inner: Http, extra_methods: Arc<BTreeSet<String>>) -> Self {
+        AllowlistedHttp {
+            inner,
+            extra_methods,
+        }
+    }
+
+    /// The transport of the providers of `rpc_url`, with the RPC headers of `options`.
+    pub fn for_url(rpc_url: reqwest::Url, options: &RpcOptions) -> Result<Self> {
+        let http = Http::new_with_client(rpc_url, options.rpc_client_builder().build()?);
+        Ok(AllowlistedHttp::new(
+            http,
+            options.extra_rpc_methods.clone(),
+        ))
+    }
+
+    pub fn is_allowed(&self, method: &str) -> bool {
+        ALLOWED_RPC_METHODS.contains(&method) || self.extra_methods.contains(method)
+    }
+
+    /// Calls `method` outside of a provider, e.g. to time the node.
+    pub async fn call(&self, method: &str, params: Value) -> Result<Value, HttpClientError> {
+        self.request(method, params).await
+    }
+}
+
+#[async_trait]
+impl JsonRpcClient for AllowlistedHttp {
+    type Error = HttpClientError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        if !self.is_allowed(method) {
+            error!(
+                "Refused to call the RPC method {}, which is not allowed, see \
+                 ALLOWED_RPC_METHODS and extra_rpc_methods",
+                method
+            );
+            return Err(HttpClientError::JsonRpcError(JsonRpcError {
+                code: METHOD_NOT_ALLOWED_CODE,
+                message: format!("the RPC method {} is not allowed", method),
+                data: None,
+            }));
+        }
+        self.inner.request(method, params).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn methods_outside_of_the_allowlist_are_rejected() {
+        // nothing listens there, the allowed calls fail to connect
+        let http = Http::new(reqwest::Url::parse("http://127.0.0.1:1").unwrap());
+        let extra_methods = Arc::new(BTreeSet::from(["debug_traceBlock".to_owned()]));
+        let transport = AllowlistedHttp::new(http, extra_methods);
+
+        let err = transport
+            .request::<_, String>("eth_sendRawTransaction", ["0x00"])
+            .await
+            .unwrap_err();
+        let HttpClientError::JsonRpcError(err) = err else {
+            panic!("the call was sent: {:?}", err);
+        };
+        assert_eq!(err.code, METHOD_NOT_ALLOWED_CODE);
+        assert!(err.message.contains("eth_sendRawTransaction"));
+
+        for method in ["eth_getBlockByNumber", "debug_traceBlock"] {
+            let err = transport
+                .request::<_, String>(method, ["0x1"])
+                .await
+                .unwrap_err();
+            assert!(
+                matches!(err, HttpClientError::ReqwestError(_)),
+                "{}: {:?}",
+                method,
+                err
+            );
+        }
+    }
+}
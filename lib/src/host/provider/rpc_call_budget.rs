@@ -0,0 +1,101 @@
+//! Counts the RPC calls issued to build the witness of a block, shared by its L1 and L2
+//! providers, and aborts the fetch past a maximum: the blocks touching thousands of
+//! accounts are expensive to fetch, and sometimes a sign of bad data. The entries served
+//! by the caches issue no call and are not counted.
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use thiserror::Error as ThisError;
+
+/// Error returned when a block needs more RPC calls than its budget.
+#[derive(Debug, ThisError, PartialEq, Eq)]
+#[error(
+    "RPC call {calls} fetching the block exceeds the maximum of {max} (max_rpc_calls_per_block)"
+)]
+pub struct RpcBudgetExceeded {
+    pub calls: u64,
+    pub max: u64,
+}
+
+/// The RPC calls of a block, at most `max` of them.
+#[derive(Debug, Default)]
+pub struct RpcCallBudget {
+    /// `None` leaves the calls unlimited, only counted
+    max: Option<u64>,
+    calls: AtomicU64,
+}
+
+impl RpcCallBudget {
+    pub fn new(max: Option<u64>) -> Self {
+        RpcCallBudget {
+            max,
+            calls: AtomicU64::new(0),
+        }
+    }
+
+    /// The calls issued so far, the one refused included.
+    pub fn calls(&self) -> u64 {
+        self.calls.load(Ordering::Relaxed)
+    }
+
+    /// Counts a call about to be issued, refused once the budget is spent.
+    pub fn spend(&self) -> Result<(), RpcBudgetExceeded> {
+        let calls = self.calls.fetch_add(1, Ordering::Relaxed) + 1;
+        match self.max {
+            Some(max) if calls > max => Err(RpcBudgetExceeded { calls, max }),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ethers_core::types::{Bytes, H160};
+    use tokio::runtime::Runtime;
+
+    use super::*;
+    use crate::host::provider::{
+        cached_rpc_provider::CachedRpcProvider, file_provider::FileProvider, AccountQuery,
+        MutProvider, Provider, RpcOptions,
+    };
+
+    #[test]
+    fn block_over_the_rpc_call_budget_is_aborted() {
+        let runtime = Runtime::new().unwrap();
+        let _guard = runtime.enter();
+        let dir = tempfile::tempdir().unwrap();
+        let cache_path = dir.path().join("1.l2.json.gz");
+        let cache_path = cache_path.to_str().unwrap().to_owned();
+        let account = |i: u8| AccountQuery {
+            block_no: 1,
+            address: H160::repeat_byte(i),
+        };
+        let mut cache = FileProvider::empty(cache_path.clone());
+        cache.insert_code(account(0), Bytes::from_static(&[0x60, 0x00]));
+        cache.save().unwrap();
+
+        let budget = Arc::new(RpcCallBudget::new(Some(2)));
+        let options = RpcOptions {
+            rpc_call_budget: Some(budget.clone()),
+            ..Default::default()
+        };
+        // nothing listens there, the calls fail but are issued
+        let mut provider =
+            CachedRpcProvider::new(cache_path, "http://127.0.0.1:1".into(), &options).unwrap();
+        for _ in 0..5 {
+            provider.get_code(&account(0)).unwrap();
+        }
+        assert_eq!(budget.calls(), 0);
+        for i in 1..=2 {
+            let err = provider.get_code(&account(i)).unwrap_err();
+            assert!(!err.is::<RpcBudgetExceeded>(), "{:?}", err);
+        }
+        let err = provider.get_code(&account(3)).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<RpcBudgetExceeded>(),
+            Some(&RpcBudgetExceeded { calls: 3, max: 2 })
+        );
+        assert_eq!(budget.calls(), 3);
+    }
+}
@@ -0,0 +1,251 @@
+//! Checks that the data of a block was served by RPC nodes agreeing on the chain. Behind
+//! a load balancer, the calls building one witness may reach nodes at different heights,
+//! the lagging one still on a fork the others reorged away from, and mix their blocks
+//! and states into an inconsistent witness. The state calls of an [RpcProvider] are
+//! pinned to the hashes of the blocks it fetched, the blocks fetched by number are
+//! checked to be linked and the proofs to be of the state of their block.
+//!
+//! [RpcProvider]: super::rpc_provider::RpcProvider
+use std::{fmt, str::FromStr};
+
+use anyhow::Result;
+use ethers_core::types::{Block, EIP1186ProofResponse, H256};
+use serde::{Deserialize, Serialize};
+use thiserror::Error as ThisError;
+use tracing::warn;
+use zeth_primitives::keccak::keccak;
+
+use super::RpcOptions;
+
+/// How the inconsistent data of a block is handled.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RpcInconsistencyPolicy {
+    /// Fetches the data of the block again, once, skipping the cached entries.
+    #[default]
+    Retry,
+    /// Fails with [RpcInconsistent].
+    Error,
+}
+
+impl FromStr for RpcInconsistencyPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "retry" => Ok(RpcInconsistencyPolicy::Retry),
+            "error" => Ok(RpcInconsistencyPolicy::Error),
+            _ => Err(format!(
+                "invalid RPC inconsistency policy {:?}, expected retry or error",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for RpcInconsistencyPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpcInconsistencyPolicy::Retry => write!(f, "retry"),
+            RpcInconsistencyPolicy::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Error returned when the RPC nodes served the data of a block from different chains.
+#[derive(Debug, ThisError, PartialEq, Eq)]
+#[error("RpcInconsistent: the {layer} data of block {block_no} is inconsistent, {reason}")]
+pub struct RpcInconsistent {
+    pub layer: String,
+    pub block_no: u64,
+    pub reason: String,
+}
+
+/// Checks that `block` is the child of `parent`, as fetched by number.
+pub fn check_linked_blocks<T>(
+    layer: &str,
+    parent: &Block<H256>,
+    block: &Block<T>,
+) -> Result<(), RpcInconsistent> {
+    let block_no = block.number.unwrap_or_default().as_u64();
+    let inconsistent = |reason| RpcInconsistent {
+        layer: layer.to_owned(),
+        block_no,
+        reason,
+    };
+    let parent_no = parent.number.unwrap_or_default().as_u64();
+    if parent_no + 1 != block_no {
+        return Err(inconsistent(format!(
+            "the block after {} was served as block {}",
+            parent_no, block_no
+        )));
+    }
+    if parent.hash != Some(block.parent_hash) {
+        return Err(inconsistent(format!(
+            "its parent is {:?}, the block {} served is {:?}",
+            block.parent_hash,
+            parent_no,
+            parent.hash.unwrap_or_default()
+        )));
+    }
+    Ok(())
+}
+
+/// Checks that `proof` is of the state whose root is `state_root`, the one of the block
+/// `block_no`.
+pub fn check_state_root(
+    layer: &str,
+    block_no: u64,
+    proof: &EIP1186ProofResponse,
+    state_root: H256,
+) -> Result<(), RpcInconsistent> {
+    // the root node opens the account proof
+    let Some(root) = proof.account_proof.first() else {
+        return Ok(());
+    };
+    let root = H256::from(keccak(root));
+    if root != state_root {
+        return Err(RpcInconsistent {
+            layer: layer.to_owned(),
+            block_no,
+            reason: format!(
+                "the proof of {:?} is of the state {:?}, the block has the state {:?}",
+                proof.address, root, state_root
+            ),
+        });
+    }
+    Ok(())
+}
+
+/// Runs `fetch` with `options`, running it again skipping the cached entries when the
+/// data it fetched is [RpcInconsistent] and the policy says to retry.
+pub fn retry_inconsistent<T>(
+    options: &RpcOptions,
+    mut fetch: impl FnMut(&RpcOptions) -> Result<T>,
+) -> Result<T> {
+    match fetch(options) {
+        Err(err)
+            if options.on_rpc_inconsistency == RpcInconsistencyPolicy::Retry
+                && err.chain().any(|cause| cause.is::<RpcInconsistent>()) =>
+        {
+            warn!("{:#}, fetching the data of the block again", err);
+            fetch(&RpcOptions {
+                skip_cache_reads: true,
+                ..options.clone()
+            })
+        }
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+    };
+
+    use ethers_core::types::{Transaction, H160};
+    use serde_json::{json, Value};
+    use tokio::runtime::Runtime;
+
+    use super::*;
+    use crate::host::provider::{
+        mock_server::serve_json_rpc, new_provider, AccountQuery, BlockQuery, Provider,
+    };
+
+    type Node = HashMap<u64, Value>;
+
+    fn block(number: u64, hash: u64, parent_hash: u64) -> Value {
+        serde_json::to_value(Block::<Transaction> {
+            number: Some(number.into()),
+            hash: Some(H256::from_low_u64_be(hash)),
+            parent_hash: H256::from_low_u64_be(parent_hash),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    /// A load balancer routing its first call to `lagging`, the others to `synced`,
+    /// recording the block parameters of the state calls.
+    fn load_balanced_rpc(
+        runtime: &Runtime,
+        lagging: Node,
+        synced: Node,
+    ) -> (String, Arc<Mutex<Vec<Value>>>) {
+        let nodes = Arc::new([lagging, synced]);
+        let calls = Arc::new(AtomicUsize::new(0));
+        let state_blocks = Arc::new(Mutex::new(vec![]));
+        let recorded = state_blocks.clone();
+        let _guard = runtime.enter();
+        let url = serve_json_rpc(move |request| {
+            let node = &nodes[(calls.fetch_add(1, Ordering::SeqCst) > 0) as usize];
+            match request["method"].as_str().unwrap() {
+                "eth_getBlockByNumber" => {
+                    let number = request["params"][0].as_str().unwrap();
+                    let number = u64::from_str_radix(&number[2..], 16).unwrap();
+                    node.get(&number).cloned().unwrap_or(Value::Null)
+                }
+                _ => {
+                    recorded.lock().unwrap().push(request["params"][1].clone());
+                    json!("0x7")
+                }
+            }
+        });
+        (url, state_blocks)
+    }
+
+    #[test]
+    fn nodes_at_different_heights_trigger_the_policy() {
+        let runtime = Runtime::new().unwrap();
+        // the lagging node is behind, on a fork the synced one reorged away from
+        let lagging = Node::from([(9, block(9, 0xb9, 0x8))]);
+        let synced = Node::from([(9, block(9, 0xa9, 0x8)), (10, block(10, 0xa10, 0xa9))]);
+        let fetch = |url: &str, options: &RpcOptions| -> Result<Box<dyn Provider>> {
+            let _guard = runtime.enter();
+            let mut provider = new_provider(None, Some(url.to_owned()), options)?;
+            let parent = provider.get_partial_block(&BlockQuery { block_no: 9 })?;
+            let block = provider.get_full_block(&BlockQuery { block_no: 10 })?;
+            check_linked_blocks("L2", &parent, &block)?;
+            Ok(provider)
+        };
+
+        let (url, _) = load_balanced_rpc(&runtime, lagging.clone(), synced.clone());
+        let options = RpcOptions {
+            on_rpc_inconsistency: RpcInconsistencyPolicy::Error,
+            ..Default::default()
+        };
+        let err = retry_inconsistent(&options, |options| fetch(&url, options))
+            .err()
+            .unwrap();
+        let inconsistent = err.downcast_ref::<RpcInconsistent>().unwrap();
+        assert_eq!(inconsistent.block_no, 10);
+        assert!(err.to_string().starts_with("RpcInconsistent"), "{}", err);
+
+        let (url, state_blocks) = load_balanced_rpc(&runtime, lagging, synced);
+        let options = RpcOptions::default();
+        let mut provider = retry_inconsistent(&options, |options| fetch(&url, options)).unwrap();
+        // the state calls are pinned to the block fetched
+        let query = AccountQuery {
+            block_no: 10,
+            address: H160::zero(),
+        };
+        assert_eq!(provider.get_transaction_count(&query).unwrap(), 7.into());
+        assert_eq!(
+            state_blocks.lock().unwrap()[0],
+            json!({ "blockHash": format!("{:?}", H256::from_low_u64_be(0xa10)) })
+        );
+
+        // the proofs of another state are told apart
+        let proof = EIP1186ProofResponse {
+            account_proof: vec![vec![0xc0].into()],
+            ..Default::default()
+        };
+        let root = H256::from(keccak([0xc0]));
+        assert_eq!(check_state_root("L1", 3, &proof, root), Ok(()));
+        assert!(check_state_root("L1", 3, &proof, H256::zero()).is_err());
+    }
+}
@@ -0,0 +1,120 @@
+//! Custom HTTP headers sent with every RPC call, for the providers authenticating by
+//! header rather than by a key in the URL.
+use std::{fmt, str::FromStr};
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde_with::{DeserializeFromStr, SerializeDisplay};
+
+/// A header of the RPC calls, given as `Name: Value`. Its value is redacted when printed,
+/// as it usually holds a credential.
+#[derive(Clone, PartialEq, Eq, DeserializeFromStr, SerializeDisplay)]
+pub struct RpcHeader {
+    pub name: HeaderName,
+    pub value: HeaderValue,
+}
+
+impl FromStr for RpcHeader {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = s
+            .split_once(':')
+            .ok_or_else(|| format!("invalid RPC header {:?}, expected \"Name: Value\"", s))?;
+        let name = HeaderName::from_str(name.trim())
+            .map_err(|e| format!("invalid RPC header name {:?}: {}", name.trim(), e))?;
+        let mut value = HeaderValue::from_str(value.trim())
+            .map_err(|e| format!("invalid value of the RPC header {}: {}", name, e))?;
+        value.set_sensitive(true);
+        Ok(RpcHeader { name, value })
+    }
+}
+
+impl fmt::Display for RpcHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: ***", self.name)
+    }
+}
+
+impl fmt::Debug for RpcHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "RpcHeader({})", self)
+    }
+}
+
+/// The headers as the default ones of a client, the later of the same name winning.
+pub fn header_map(headers: &[RpcHeader]) -> HeaderMap {
+    let mut map = HeaderMap::new();
+    for header in headers {
+        map.insert(header.name.clone(), header.value.clone());
+    }
+    map
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use ethers_core::types::H160;
+    use serde_json::json;
+    use tokio::runtime::Runtime;
+
+    use super::*;
+    use crate::host::provider::{
+        mock_server::{json_rpc_response, read_json, serve},
+        rpc_provider::RpcProvider,
+        AccountQuery, Provider, RpcOptions,
+    };
+
+    #[test]
+    fn parse_rpc_header() {
+        let header: RpcHeader = "X-Project-Id: proj 1".parse().unwrap();
+        assert_eq!(header.name, "x-project-id");
+        assert_eq!(header.value, "proj 1");
+        assert!(header.value.is_sensitive());
+        assert_eq!(header.to_string(), "x-project-id: ***");
+        assert!(!format!("{:?}", header).contains("proj"));
+        assert!("Authorization".parse::<RpcHeader>().is_err());
+        assert!("Bad Name: value".parse::<RpcHeader>().is_err());
+        assert!("X-Id: line\nbreak".parse::<RpcHeader>().is_err());
+    }
+
+    #[test]
+    fn configured_headers_reach_the_rpc_node() {
+        let runtime = Runtime::new().unwrap();
+        let seen = Arc::new(Mutex::new(vec![]));
+        let server_seen = seen.clone();
+        let url = {
+            let _guard = runtime.enter();
+            serve(move |req| {
+                server_seen.lock().unwrap().push(req.headers().clone());
+                async move {
+                    let req = read_json(req).await;
+                    json_rpc_response(&req, json!({ "result": "0x1" }))
+                }
+            })
+        };
+
+        let options = RpcOptions {
+            rpc_headers: vec![
+                "Authorization: Bearer secret".parse().unwrap(),
+                "X-Project-Id: proj".parse().unwrap(),
+            ],
+            ..Default::default()
+        };
+        let mut provider = {
+            let _guard = runtime.enter();
+            RpcProvider::new(url, &options).unwrap()
+        };
+        let count = provider
+            .get_transaction_count(&AccountQuery {
+                block_no: 1,
+                address: H160::zero(),
+            })
+            .unwrap();
+        assert_eq!(count, 1.into());
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen[0]["authorization"], "Bearer secret");
+        assert_eq!(seen[0]["x-project-id"], "proj");
+    }
+}
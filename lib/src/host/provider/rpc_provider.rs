@@ -12,35 +12,270 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
 use anyhow::{anyhow, Result};
 #[cfg(feature = "taiko")]
 use ethers_core::types::Filter;
-use ethers_core::types::{Block, Bytes, EIP1186ProofResponse, Transaction, H256, U256};
-use ethers_providers::{Http, Middleware};
+use ethers_core::types::{
+    Block, BlockId, BlockNumber, Bytes, EIP1186ProofResponse, Transaction, H256, U256,
+};
+use ethers_providers::{Middleware, ProviderError};
 #[cfg(not(feature = "taiko"))]
 use log::info;
+use thiserror::Error as ThisError;
 #[cfg(feature = "taiko")]
 use tracing::info;
 #[cfg(feature = "taiko")]
-use zeth_primitives::taiko::{filter_propose_block_event, BlockProposed};
+use zeth_primitives::{
+    ethers::from_ethers_h256,
+    taiko::{filter_propose_block_event, BlockProposed},
+};
+
+use super::{
+    circuit_breaker::{CircuitBreaker, RpcUnavailable},
+    connection_limit::ConnectionLimit,
+    failover_provider::is_endpoint_failure,
+    fetch_limit::LayerLimit,
+    retry_policy::RetryPolicies,
+    rpc_allowlist::AllowlistedHttp,
+    rpc_call_budget::RpcCallBudget,
+    AccountQuery, BlockQuery, ProofQuery, Provider, RpcOptions, StorageQuery,
+};
+#[cfg(feature = "taiko")]
+use crate::taiko::blob::{fetch_blob_sidecars, kzg_to_versioned_hash, BlobFetchError, BlobSidecar};
+
+/// Which of the RPC timeouts ran out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeoutPhase {
+    Connect,
+    Request,
+    BlockFetchBudget,
+}
+
+impl fmt::Display for TimeoutPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimeoutPhase::Connect => write!(f, "connect timeout"),
+            TimeoutPhase::Request => write!(f, "request timeout"),
+            TimeoutPhase::BlockFetchBudget => write!(f, "block fetch budget"),
+        }
+    }
+}
 
-use super::{AccountQuery, BlockQuery, ProofQuery, Provider, StorageQuery};
+/// Error returned when an RPC call runs out of time.
+#[derive(Debug, ThisError)]
+#[error("RPC {phase} exceeded after {elapsed:?} querying {query}")]
+pub struct RpcTimeout {
+    pub phase: TimeoutPhase,
+    pub elapsed: Duration,
+    pub query: String,
+}
 
 pub struct RpcProvider {
-    http_client: ethers_providers::Provider<Http>,
+    http_client: ethers_providers::Provider<AllowlistedHttp>,
+    beacon_client: reqwest::Client,
+    beacon_rpc_url: Option<String>,
+    connect_timeout: Option<Duration>,
+    request_timeout: Option<Duration>,
+    block_fetch_deadline: Option<Instant>,
+    rpc_host: String,
+    connection_limit: Option<Arc<ConnectionLimit>>,
+    fetch_limit: LayerLimit,
+    circuit_breaker: Option<Arc<CircuitBreaker>>,
+    rpc_call_budget: Option<Arc<RpcCallBudget>>,
+    retry_policies: RetryPolicies,
+    /// hashes of the blocks fetched, the state calls at their number are pinned to
+    pinned_blocks: HashMap<u64, H256>,
     tokio_handle: tokio::runtime::Handle,
 }
 
 impl RpcProvider {
-    pub fn new(rpc_url: String) -> Result<Self> {
-        let http_client = ethers_providers::Provider::<Http>::try_from(&rpc_url)?;
+    pub fn new(rpc_url: String, options: &RpcOptions) -> Result<Self> {
+        let client = options.client_builder().build()?;
+        let rpc_url = reqwest::Url::parse(&rpc_url)?;
+        let rpc_host = rpc_url.host_str().unwrap_or_default().to_owned();
+        // the headers are meant for the RPC node, not for the beacon node
+        let http_client =
+            ethers_providers::Provider::new(AllowlistedHttp::for_url(rpc_url, options)?);
         let tokio_handle = tokio::runtime::Handle::current();
 
         Ok(RpcProvider {
             http_client,
+            beacon_client: client,
+            beacon_rpc_url: options.beacon_rpc_url.clone(),
+            connect_timeout: options.connect_timeout,
+            request_timeout: options.request_timeout,
+            block_fetch_deadline: options.block_fetch_deadline,
+            rpc_host,
+            connection_limit: options.connection_limit.clone(),
+            fetch_limit: options.fetch_limit.clone(),
+            circuit_breaker: options.circuit_breaker.clone(),
+            rpc_call_budget: options.rpc_call_budget.clone(),
+            retry_policies: options.retry_policies.clone(),
+            pinned_blocks: Default::default(),
             tokio_handle,
         })
     }
+
+    /// The block the state calls at `block_no` are made at, by hash once the block was
+    /// fetched: the nodes behind a load balancer may disagree on the block of a number.
+    fn state_block(&self, block_no: u64) -> BlockId {
+        match self.pinned_blocks.get(&block_no) {
+            Some(hash) => BlockId::Hash(*hash),
+            None => block_no.into(),
+        }
+    }
+
+    fn pin_block<T>(&mut self, block_no: u64, block: &Block<T>) {
+        if let Some(hash) = block.hash {
+            self.pinned_blocks.insert(block_no, hash);
+        }
+    }
+
+    /// The latest block of the node, the head of its chain.
+    pub fn get_head_block(&mut self) -> Result<Block<H256>> {
+        info!("Querying RPC for the head block");
+
+        let response = self.call("eth_getBlockByNumber", &"head block", || {
+            self.http_client.get_block(BlockNumber::Latest)
+        })?;
+
+        response.ok_or_else(|| anyhow!("No head block"))
+    }
+
+    /// Runs the RPC call `query` of `method`, retrying it on the faults of the node as
+    /// the retry policy of the method says.
+    fn call<T, F: Future<Output = Result<T, ProviderError>>>(
+        &self,
+        method: &str,
+        query: &dyn fmt::Debug,
+        call: impl Fn() -> F,
+    ) -> Result<T> {
+        let policy = self.retry_policies.of(method);
+        let mut retry = 0;
+        loop {
+            match self.call_once(query, call()) {
+                Err(err) if retry < policy.max_retries && is_transient(&err) => {
+                    retry += 1;
+                    let delay = policy.delay(retry);
+                    info!(
+                        "Retrying {} in {:?} ({}/{}): {:#}",
+                        method, delay, retry, policy.max_retries, err
+                    );
+                    std::thread::sleep(delay);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Runs the RPC call `query` unless the circuit breaker of the host is open or the
+    /// RPC call budget of the block is spent, counting its failure against the host if
+    /// the host is at fault.
+    fn call_once<T>(
+        &self,
+        query: &dyn fmt::Debug,
+        call: impl Future<Output = Result<T, ProviderError>>,
+    ) -> Result<T> {
+        if let Some(budget) = &self.rpc_call_budget {
+            budget.spend()?;
+        }
+        let Some(breaker) = &self.circuit_breaker else {
+            return self.call_node(query, call);
+        };
+        breaker.admit(&self.rpc_host)?;
+        let result = self.call_node(query, call);
+        let host_failed = result.as_ref().err().is_some_and(|err| {
+            // running out of the budget of the block is no fault of the host
+            let out_of_budget = err
+                .downcast_ref::<RpcTimeout>()
+                .is_some_and(|timeout| timeout.phase == TimeoutPhase::BlockFetchBudget);
+            is_endpoint_failure(err) && !out_of_budget
+        });
+        breaker.record(&self.rpc_host, !host_failed);
+        result
+    }
+
+    /// Runs the RPC call `query` within the request timeout and what is left of the
+    /// block fetch budget, waiting for a free slot of the layer and connection included.
+    fn call_node<T>(
+        &self,
+        query: &dyn fmt::Debug,
+        call: impl Future<Output = Result<T, ProviderError>>,
+    ) -> Result<T> {
+        let start = Instant::now();
+        let timeout = |phase| RpcTimeout {
+            phase,
+            elapsed: start.elapsed(),
+            query: format!("{:?}", query),
+        };
+        let request_deadline = self.request_timeout.map(|timeout| start + timeout);
+        let (deadline, phase) = match (request_deadline, self.block_fetch_deadline) {
+            (Some(request), Some(budget)) if budget < request => {
+                (Some(budget), TimeoutPhase::BlockFetchBudget)
+            }
+            (Some(request), _) => (Some(request), TimeoutPhase::Request),
+            (None, budget) => (budget, TimeoutPhase::BlockFetchBudget),
+        };
+        let call = async {
+            let _fetch_permit = self.fetch_limit.acquire().await;
+            let _permit = match &self.connection_limit {
+                Some(limit) => Some(limit.acquire(&self.rpc_host).await),
+                None => None,
+            };
+            call.await
+        };
+        let result = match deadline {
+            Some(deadline) if deadline <= start => return Err(timeout(phase).into()),
+            Some(deadline) => self
+                .tokio_handle
+                .block_on(tokio::time::timeout_at(deadline.into(), call))
+                .map_err(|_| timeout(phase))?,
+            None => self.tokio_handle.block_on(call),
+        };
+        result.map_err(|err| {
+            // the client gives up connecting at the connect timeout
+            let timed_out_connecting = self
+                .connect_timeout
+                .map_or(false, |connect_timeout| start.elapsed() >= connect_timeout)
+                && is_connect_error(&err);
+            if timed_out_connecting {
+                timeout(TimeoutPhase::Connect).into()
+            } else {
+                err.into()
+            }
+        })
+    }
+}
+
+/// Whether a retry of the failed call may succeed: the node was at fault, its host is not
+/// shut out by the circuit breaker and the block fetch budget is not spent.
+fn is_transient(err: &anyhow::Error) -> bool {
+    let out_of_budget = err
+        .downcast_ref::<RpcTimeout>()
+        .is_some_and(|timeout| timeout.phase == TimeoutPhase::BlockFetchBudget);
+    is_endpoint_failure(err) && !err.is::<RpcUnavailable>() && !out_of_budget
+}
+
+fn is_connect_error(err: &ProviderError) -> bool {
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(err);
+    while let Some(err) = source {
+        if err
+            .downcast_ref::<hyper::Error>()
+            .map_or(false, hyper::Error::is_connect)
+        {
+            return true;
+        }
+        source = err.source();
+    }
+    false
 }
 
 impl Provider for RpcProvider {
@@ -51,12 +286,15 @@ impl Provider for RpcProvider {
     fn get_full_block(&mut self, query: &BlockQuery) -> Result<Block<Transaction>> {
         info!("Querying RPC for full block: {:?}", query);
 
-        let response = self
-            .tokio_handle
-            .block_on(async { self.http_client.get_block_with_txs(query.block_no).await })?;
+        let response = self.call("eth_getBlockByNumber", query, || {
+            self.http_client.get_block_with_txs(query.block_no)
+        })?;
 
         match response {
-            Some(out) => Ok(out),
+            Some(out) => {
+                self.pin_block(query.block_no, &out);
+                Ok(out)
+            }
             None => Err(anyhow!("No data for {:?}", query)),
         }
     }
@@ -64,12 +302,15 @@ impl Provider for RpcProvider {
     fn get_partial_block(&mut self, query: &BlockQuery) -> Result<Block<H256>> {
         info!("Querying RPC for partial block: {:?}", query);
 
-        let response = self
-            .tokio_handle
-            .block_on(async { self.http_client.get_block(query.block_no).await })?;
+        let response = self.call("eth_getBlockByNumber", query, || {
+            self.http_client.get_block(query.block_no)
+        })?;
 
         match response {
-            Some(out) => Ok(out),
+            Some(out) => {
+                self.pin_block(query.block_no, &out);
+                Ok(out)
+            }
             None => Err(anyhow!("No data for {:?}", query)),
         }
     }
@@ -77,14 +318,13 @@ impl Provider for RpcProvider {
     fn get_proof(&mut self, query: &ProofQuery) -> Result<EIP1186ProofResponse> {
         info!("Querying RPC for inclusion proof: {:?}", query);
 
-        let out = self.tokio_handle.block_on(async {
-            self.http_client
-                .get_proof(
-                    query.address,
-                    query.indices.iter().cloned().collect(),
-                    Some(query.block_no.into()),
-                )
-                .await
+        let block = self.state_block(query.block_no);
+        let out = self.call("eth_getProof", query, || {
+            self.http_client.get_proof(
+                query.address,
+                query.indices.iter().cloned().collect(),
+                Some(block),
+            )
         })?;
 
         Ok(out)
@@ -93,10 +333,10 @@ impl Provider for RpcProvider {
     fn get_transaction_count(&mut self, query: &AccountQuery) -> Result<U256> {
         info!("Querying RPC for transaction count: {:?}", query);
 
-        let out = self.tokio_handle.block_on(async {
+        let block = self.state_block(query.block_no);
+        let out = self.call("eth_getTransactionCount", query, || {
             self.http_client
-                .get_transaction_count(query.address, Some(query.block_no.into()))
-                .await
+                .get_transaction_count(query.address, Some(block))
         })?;
 
         Ok(out)
@@ -105,10 +345,9 @@ impl Provider for RpcProvider {
     fn get_balance(&mut self, query: &AccountQuery) -> Result<U256> {
         info!("Querying RPC for balance: {:?}", query);
 
-        let out = self.tokio_handle.block_on(async {
-            self.http_client
-                .get_balance(query.address, Some(query.block_no.into()))
-                .await
+        let block = self.state_block(query.block_no);
+        let out = self.call("eth_getBalance", query, || {
+            self.http_client.get_balance(query.address, Some(block))
         })?;
 
         Ok(out)
@@ -117,10 +356,9 @@ impl Provider for RpcProvider {
     fn get_code(&mut self, query: &AccountQuery) -> Result<Bytes> {
         info!("Querying RPC for code: {:?}", query);
 
-        let out = self.tokio_handle.block_on(async {
-            self.http_client
-                .get_code(query.address, Some(query.block_no.into()))
-                .await
+        let block = self.state_block(query.block_no);
+        let out = self.call("eth_getCode", query, || {
+            self.http_client.get_code(query.address, Some(block))
         })?;
 
         Ok(out)
@@ -129,10 +367,10 @@ impl Provider for RpcProvider {
     fn get_storage(&mut self, query: &StorageQuery) -> Result<H256> {
         info!("Querying RPC for storage: {:?}", query);
 
-        let out = self.tokio_handle.block_on(async {
+        let block = self.state_block(query.block_no);
+        let out = self.call("eth_getStorageAt", query, || {
             self.http_client
-                .get_storage_at(query.address, query.index, Some(query.block_no.into()))
-                .await
+                .get_storage_at(query.address, query.index, Some(block))
         })?;
 
         Ok(out)
@@ -146,16 +384,14 @@ impl Provider for RpcProvider {
             .address(query.l1_contract)
             .from_block(query.l1_block_no)
             .to_block(query.l1_block_no);
-        let logs = self
-            .tokio_handle
-            .block_on(async { self.http_client.get_logs(&filter).await })?;
+        let logs = self.call("eth_getLogs", query, || self.http_client.get_logs(&filter))?;
         let result =
             filter_propose_block_event(&logs, zeth_primitives::U256::from(query.l2_block_no))?;
         let (tx_hash, block_proposed) =
             result.ok_or_else(|| anyhow!("No propose block event for {:?}", query))?;
-        let response = self
-            .tokio_handle
-            .block_on(async { self.http_client.get_transaction(tx_hash).await })?;
+        let response = self.call("eth_getTransactionByHash", query, || {
+            self.http_client.get_transaction(tx_hash)
+        })?;
         match response {
             Some(out) => Ok((out, block_proposed)),
             None => Err(anyhow!("No data for {:?}", query)),
@@ -167,13 +403,179 @@ impl Provider for RpcProvider {
     fn batch_get_partial_blocks(&mut self, query: &BlockQuery) -> Result<Vec<Block<H256>>> {
         info!("Querying RPC for partial blocks: {:?}", query);
 
-        let out = self.tokio_handle.block_on(async {
-            use ethers_core::utils;
-            let id = utils::serialize(&query.block_no);
+        let id = ethers_core::utils::serialize(&query.block_no);
+        let out = self.call("taiko_getL2ParentHeaders", query, || {
             self.http_client
-                .request("taiko_getL2ParentHeaders", [id])
-                .await
+                .request("taiko_getL2ParentHeaders", [id.clone()])
         })?;
         Ok(out)
     }
+
+    #[cfg(feature = "taiko")]
+    fn get_blob(&mut self, query: &super::BlobQuery) -> Result<BlobSidecar> {
+        info!("Querying beacon node for blob: {:?}", query);
+
+        let versioned_hash = from_ethers_h256(query.versioned_hash);
+        let beacon_rpc_url = self
+            .beacon_rpc_url
+            .as_deref()
+            .ok_or(BlobFetchError::NoBeaconRpc(versioned_hash))?;
+        let sidecars = self.tokio_handle.block_on(fetch_blob_sidecars(
+            &self.beacon_client,
+            beacon_rpc_url,
+            query.timestamp,
+        ))?;
+        let sidecar = sidecars
+            .into_iter()
+            .find(|sidecar| kzg_to_versioned_hash(&sidecar.kzg_commitment) == versioned_hash)
+            .ok_or(BlobFetchError::NotFound(versioned_hash))?;
+        Ok(sidecar)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use tokio::{io::AsyncReadExt, net::TcpListener, runtime::Runtime};
+
+    use super::*;
+    use crate::host::provider::{
+        circuit_breaker::{BreakerState, RpcUnavailable},
+        fetch_limit::LayerLimits,
+    };
+
+    /// An RPC node reading the requests without ever answering them.
+    fn slow_rpc(runtime: &Runtime) -> String {
+        slow_counted_rpc(runtime).0
+    }
+
+    /// A [slow_rpc] counting the connections it accepted.
+    fn slow_counted_rpc(runtime: &Runtime) -> (String, Arc<AtomicUsize>) {
+        let listener = runtime.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let connections = Arc::new(AtomicUsize::new(0));
+        let accepted = connections.clone();
+        runtime.spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                accepted.fetch_add(1, Ordering::Relaxed);
+                tokio::spawn(async move {
+                    let mut buf = [0u8; 1024];
+                    while socket.read(&mut buf).await.map_or(false, |n| n > 0) {}
+                });
+            }
+        });
+        (url, connections)
+    }
+
+    fn slow_provider(runtime: &Runtime, options: RpcOptions) -> RpcProvider {
+        let url = slow_rpc(runtime);
+        let _guard = runtime.enter();
+        RpcProvider::new(url, &options.start_block_fetch()).unwrap()
+    }
+
+    fn timeout(result: Result<Block<Transaction>>) -> RpcTimeout {
+        result
+            .unwrap_err()
+            .downcast::<RpcTimeout>()
+            .expect("a timeout")
+    }
+
+    #[test]
+    fn slow_call_hits_the_request_timeout() {
+        let runtime = Runtime::new().unwrap();
+        let mut provider = slow_provider(
+            &runtime,
+            RpcOptions {
+                request_timeout: Some(Duration::from_millis(100)),
+                block_fetch_budget: Some(Duration::from_secs(60)),
+                ..Default::default()
+            },
+        );
+        let err = timeout(provider.get_full_block(&BlockQuery { block_no: 1 }));
+        assert_eq!(err.phase, TimeoutPhase::Request);
+        assert!(err.to_string().contains("request timeout"));
+    }
+
+    #[test]
+    fn block_fetch_budget_caps_all_the_calls() {
+        let runtime = Runtime::new().unwrap();
+        let mut provider = slow_provider(
+            &runtime,
+            RpcOptions {
+                request_timeout: Some(Duration::from_secs(60)),
+                block_fetch_budget: Some(Duration::from_millis(100)),
+                ..Default::default()
+            },
+        );
+        let err = timeout(provider.get_full_block(&BlockQuery { block_no: 1 }));
+        assert_eq!(err.phase, TimeoutPhase::BlockFetchBudget);
+
+        // the next calls of the block fail fast
+        let err = timeout(provider.get_full_block(&BlockQuery { block_no: 2 }));
+        assert_eq!(err.phase, TimeoutPhase::BlockFetchBudget);
+        assert!(err.elapsed < Duration::from_millis(100));
+    }
+
+    #[test]
+    fn open_breaker_fails_the_calls_fast() {
+        let runtime = Runtime::new().unwrap();
+        let breaker = Arc::new(CircuitBreaker::new(2, Duration::from_secs(60)));
+        let mut provider = slow_provider(
+            &runtime,
+            RpcOptions {
+                request_timeout: Some(Duration::from_millis(100)),
+                circuit_breaker: Some(breaker.clone()),
+                ..Default::default()
+            },
+        );
+        for block_no in [1, 2] {
+            let err = timeout(provider.get_full_block(&BlockQuery { block_no }));
+            assert_eq!(err.phase, TimeoutPhase::Request);
+        }
+        assert_eq!(breaker.state("127.0.0.1"), BreakerState::Open);
+
+        let start = Instant::now();
+        let err = provider
+            .get_full_block(&BlockQuery { block_no: 3 })
+            .unwrap_err();
+        assert!(err.is::<RpcUnavailable>(), "{:#}", err);
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+
+    #[test]
+    fn saturated_l1_fetches_leave_the_l2_ones_through() {
+        let runtime = Runtime::new().unwrap();
+        let options = RpcOptions {
+            request_timeout: Some(Duration::from_millis(100)),
+            layer_limits: LayerLimits {
+                l1: LayerLimit::new(Some(1), None),
+                l2: LayerLimit::new(Some(1), Some(Duration::from_millis(200))),
+            },
+            ..Default::default()
+        };
+        let provider = |options: &RpcOptions| {
+            let (url, connections) = slow_counted_rpc(&runtime);
+            let _guard = runtime.enter();
+            (RpcProvider::new(url, options).unwrap(), connections)
+        };
+        let l1_options = options.with_layer_limit(&options.layer_limits.l1);
+        let l2_options = options.with_layer_limit(&options.layer_limits.l2);
+        assert_eq!(l2_options.request_timeout, Some(Duration::from_millis(200)));
+        let (mut l1_provider, l1_connections) = provider(&l1_options);
+        let (mut l2_provider, l2_connections) = provider(&l2_options);
+
+        // the only slot of the L1 is taken by a fetch in flight
+        let _l1_busy = runtime.block_on(options.layer_limits.l1.acquire());
+        let err = timeout(l1_provider.get_full_block(&BlockQuery { block_no: 1 }));
+        assert_eq!(err.phase, TimeoutPhase::Request);
+        assert_eq!(l1_connections.load(Ordering::Relaxed), 0);
+
+        // the L2 calls still reach their node, up to their own timeout
+        let err = timeout(l2_provider.get_full_block(&BlockQuery { block_no: 1 }));
+        assert_eq!(err.phase, TimeoutPhase::Request);
+        assert!(err.elapsed >= Duration::from_millis(200));
+        assert_eq!(l2_connections.load(Ordering::Relaxed), 1);
+    }
 }
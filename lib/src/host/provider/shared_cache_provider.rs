@@ -0,0 +1,243 @@
+//! Fronts the RPC node with an HTTP cache shared by a fleet of provers, so that the
+//! block data fetched by one of them is fetched from the RPC node only once.
+//!
+//! An entry is read with `GET {url}/{kind}/{key}`, answered with its JSON or `404`, and
+//! written with `PUT {url}/{kind}/{key}`, `key` being the SHA-256 of the JSON query.
+use anyhow::Result;
+use ethers_core::types::{Block, Bytes, EIP1186ProofResponse, Transaction, H256, U256};
+use reqwest::{header::CONTENT_TYPE, StatusCode};
+use serde::{de::DeserializeOwned, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::warn;
+#[cfg(feature = "taiko")]
+use zeth_primitives::taiko::BlockProposed;
+
+use super::{AccountQuery, BlockQuery, ProofQuery, Provider, RpcOptions, StorageQuery};
+#[cfg(feature = "taiko")]
+use super::{BlobQuery, ProposeQuery};
+#[cfg(feature = "taiko")]
+use crate::taiko::blob::BlobSidecar;
+
+pub struct SharedCacheProvider {
+    url: String,
+    client: reqwest::Client,
+    upstream: Box<dyn Provider>,
+    tokio_handle: tokio::runtime::Handle,
+    /// only shares the entries fetched from upstream, see [RpcOptions::skip_cache_reads]
+    skip_reads: bool,
+}
+
+impl SharedCacheProvider {
+    pub fn new(url: String, upstream: Box<dyn Provider>, options: &RpcOptions) -> Result<Self> {
+        let mut client_builder = options.client_builder();
+        if let Some(request_timeout) = options.request_timeout {
+            client_builder = client_builder.timeout(request_timeout);
+        }
+        Ok(SharedCacheProvider {
+            url: url.trim_end_matches('/').to_owned(),
+            client: client_builder.build()?,
+            upstream,
+            tokio_handle: tokio::runtime::Handle::current(),
+            skip_reads: options.skip_cache_reads,
+        })
+    }
+
+    /// Looks `query` up in the shared cache, or fetches it from upstream and shares it.
+    /// The shared cache failing only costs the upstream fetch.
+    fn read_through<Q: Serialize, T: Serialize + DeserializeOwned>(
+        &mut self,
+        kind: &str,
+        query: &Q,
+        fetch: impl FnOnce(&mut dyn Provider) -> Result<T>,
+    ) -> Result<T> {
+        let key = hex::encode(Sha256::digest(serde_json::to_vec(query)?));
+        let url = format!("{}/{}/{}", self.url, kind, key);
+        if !self.skip_reads {
+            match self.get(&url) {
+                Ok(Some(out)) => return Ok(out),
+                Ok(None) => {}
+                Err(err) => warn!("Shared cache lookup of {} {} failed: {}", kind, key, err),
+            }
+        }
+
+        let out = fetch(self.upstream.as_mut())?;
+        if let Err(err) = self.put(&url, &out) {
+            warn!("Sharing {} {} failed: {}", kind, key, err);
+        }
+        Ok(out)
+    }
+
+    fn get<T: DeserializeOwned>(&self, url: &str) -> Result<Option<T>> {
+        self.tokio_handle.block_on(async {
+            let response = self.client.get(url).send().await?;
+            if response.status() == StatusCode::NOT_FOUND {
+                return Ok(None);
+            }
+            let body = response.error_for_status()?.bytes().await?;
+            Ok(Some(serde_json::from_slice(&body)?))
+        })
+    }
+
+    fn put<T: Serialize>(&self, url: &str, value: &T) -> Result<()> {
+        let body = serde_json::to_vec(value)?;
+        self.tokio_handle.block_on(async {
+            self.client
+                .put(url)
+                .header(CONTENT_TYPE, "application/json")
+                .body(body)
+                .send()
+                .await?
+                .error_for_status()?;
+            Ok(())
+        })
+    }
+}
+
+impl Provider for SharedCacheProvider {
+    fn save(&self) -> Result<()> {
+        self.upstream.save()
+    }
+
+    fn get_full_block(&mut self, query: &BlockQuery) -> Result<Block<Transaction>> {
+        self.read_through("full_block", query, |upstream| {
+            upstream.get_full_block(query)
+        })
+    }
+
+    fn get_partial_block(&mut self, query: &BlockQuery) -> Result<Block<H256>> {
+        self.read_through("partial_block", query, |upstream| {
+            upstream.get_partial_block(query)
+        })
+    }
+
+    fn get_proof(&mut self, query: &ProofQuery) -> Result<EIP1186ProofResponse> {
+        self.read_through("proof", query, |upstream| upstream.get_proof(query))
+    }
+
+    fn get_transaction_count(&mut self, query: &AccountQuery) -> Result<U256> {
+        self.read_through("transaction_count", query, |upstream| {
+            upstream.get_transaction_count(query)
+        })
+    }
+
+    fn get_balance(&mut self, query: &AccountQuery) -> Result<U256> {
+        self.read_through("balance", query, |upstream| upstream.get_balance(query))
+    }
+
+    fn get_code(&mut self, query: &AccountQuery) -> Result<Bytes> {
+        self.read_through("code", query, |upstream| upstream.get_code(query))
+    }
+
+    fn get_storage(&mut self, query: &StorageQuery) -> Result<H256> {
+        self.read_through("storage", query, |upstream| upstream.get_storage(query))
+    }
+
+    #[cfg(feature = "taiko")]
+    fn get_propose(&mut self, query: &ProposeQuery) -> Result<(Transaction, BlockProposed)> {
+        self.read_through("propose", query, |upstream| upstream.get_propose(query))
+    }
+
+    #[cfg(feature = "taiko")]
+    fn batch_get_partial_blocks(&mut self, query: &BlockQuery) -> Result<Vec<Block<H256>>> {
+        self.read_through("partial_blocks_batch", query, |upstream| {
+            upstream.batch_get_partial_blocks(query)
+        })
+    }
+
+    #[cfg(feature = "taiko")]
+    fn get_blob(&mut self, query: &BlobQuery) -> Result<BlobSidecar> {
+        self.read_through("blob", query, |upstream| upstream.get_blob(query))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    use hyper::{Body, Method, Response};
+    use tokio::runtime::Runtime;
+
+    use super::*;
+    use crate::host::provider::{file_provider::FileProvider, mock_server::serve, MutProvider};
+
+    type Entries = Arc<Mutex<HashMap<String, Vec<u8>>>>;
+
+    /// A shared cache service keeping its entries in memory.
+    fn mock_shared_cache(runtime: &Runtime) -> (String, Entries) {
+        let entries = Entries::default();
+        let service_entries = entries.clone();
+        let _guard = runtime.enter();
+        let url = serve(move |req| {
+            let entries = service_entries.clone();
+            async move {
+                let path = req.uri().path().to_owned();
+                let mut response = Response::default();
+                if req.method() == Method::PUT {
+                    let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                    entries.lock().unwrap().insert(path, body.to_vec());
+                } else {
+                    let entry = entries.lock().unwrap().get(&path).cloned();
+                    match entry {
+                        Some(body) => *response.body_mut() = Body::from(body),
+                        None => *response.status_mut() = StatusCode::NOT_FOUND,
+                    }
+                }
+                response
+            }
+        });
+        (format!("{}/167001/l2", url), entries)
+    }
+
+    fn worker(runtime: &Runtime, url: &str, upstream: FileProvider) -> SharedCacheProvider {
+        let _guard = runtime.enter();
+        SharedCacheProvider::new(url.to_owned(), Box::new(upstream), &RpcOptions::default())
+            .unwrap()
+    }
+
+    fn block(block_no: u64) -> Block<H256> {
+        Block {
+            number: Some(block_no.into()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn workers_share_the_fetched_entries() {
+        let runtime = Runtime::new().unwrap();
+        let (url, entries) = mock_shared_cache(&runtime);
+        let query = BlockQuery { block_no: 1 };
+
+        let mut upstream = FileProvider::empty(String::new());
+        upstream.insert_partial_block(query.clone(), block(1));
+        let mut first = worker(&runtime, &url, upstream);
+        assert_eq!(first.get_partial_block(&query).unwrap(), block(1));
+        assert_eq!(entries.lock().unwrap().len(), 1);
+        assert!(entries
+            .lock()
+            .unwrap()
+            .keys()
+            .all(|path| path.starts_with("/167001/l2/partial_block/")));
+
+        // the upstream of the second worker has nothing
+        let mut second = worker(&runtime, &url, FileProvider::empty(String::new()));
+        assert_eq!(second.get_partial_block(&query).unwrap(), block(1));
+        assert!(second
+            .get_partial_block(&BlockQuery { block_no: 2 })
+            .is_err());
+        assert_eq!(entries.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn unreachable_shared_cache_falls_back_to_upstream() {
+        let runtime = Runtime::new().unwrap();
+        let query = BlockQuery { block_no: 1 };
+
+        let mut upstream = FileProvider::empty(String::new());
+        upstream.insert_partial_block(query.clone(), block(1));
+        let mut provider = worker(&runtime, "http://127.0.0.1:1", upstream);
+        assert_eq!(provider.get_partial_block(&query).unwrap(), block(1));
+    }
+}
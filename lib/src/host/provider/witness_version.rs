@@ -0,0 +1,132 @@
+//! The versions of the witness files, the block data of the cache and of
+//! `--blocks-data-file`. A file tells its version by its `version` field, the files
+//! written before the field being version 1. A build reads the files of its version and
+//! of the previous one, for the block data fetched by the hosts not upgraded yet to be
+//! proven by the upgraded ones during a rolling upgrade. The files are always written in
+//! the current version.
+use serde::Deserialize;
+use thiserror::Error as ThisError;
+
+use super::file_provider::FileProvider;
+
+/// The version of the witness files written.
+pub const WITNESS_VERSION: u32 = 2;
+/// The version of the files without a `version` field.
+pub const UNTAGGED_WITNESS_VERSION: u32 = 1;
+
+type WitnessDeserializer = fn(&[u8]) -> serde_json::Result<FileProvider>;
+
+/// The deserializers of the versions read, the current one and the previous one.
+const WITNESS_DESERIALIZERS: &[(u32, WitnessDeserializer)] = &[
+    (UNTAGGED_WITNESS_VERSION, deserialize_v1),
+    (WITNESS_VERSION, deserialize_v2),
+];
+
+/// Error returned when a witness file is of a version this build does not read.
+#[derive(Debug, ThisError, PartialEq, Eq)]
+#[error(
+    "UnsupportedWitnessVersion: the witness is of version {version}, this build reads the \
+     versions {supported:?}"
+)]
+pub struct UnsupportedWitnessVersion {
+    pub version: u32,
+    pub supported: Vec<u32>,
+}
+
+#[derive(Deserialize)]
+struct VersionTag {
+    #[serde(default = "untagged_witness_version")]
+    version: u32,
+}
+
+fn untagged_witness_version() -> u32 {
+    UNTAGGED_WITNESS_VERSION
+}
+
+/// The versions of the witness files this build reads.
+pub fn supported_witness_versions() -> Vec<u32> {
+    WITNESS_DESERIALIZERS
+        .iter()
+        .map(|(version, _)| *version)
+        .collect()
+}
+
+/// Deserializes a witness file, uncompressed, of any version this build reads.
+pub fn deserialize_witness(bytes: &[u8]) -> anyhow::Result<FileProvider> {
+    let VersionTag { version } = serde_json::from_slice(bytes)?;
+    let Some((_, deserialize)) = WITNESS_DESERIALIZERS
+        .iter()
+        .find(|(registered, _)| *registered == version)
+    else {
+        return Err(UnsupportedWitnessVersion {
+            version,
+            supported: supported_witness_versions(),
+        }
+        .into());
+    };
+    Ok(deserialize(bytes)?.with_version(WITNESS_VERSION))
+}
+
+/// Version 2 only added the `version` field to the layout of version 1.
+fn deserialize_v1(bytes: &[u8]) -> serde_json::Result<FileProvider> {
+    serde_json::from_slice(bytes)
+}
+
+fn deserialize_v2(bytes: &[u8]) -> serde_json::Result<FileProvider> {
+    serde_json::from_slice(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers_core::types::{Bytes, H160};
+
+    use super::*;
+    use crate::host::provider::{AccountQuery, MutProvider, Provider};
+
+    /// A witness file written before the files were versioned.
+    const V1_WITNESS: &str = r#"{
+        "full_blocks": [],
+        "partial_blocks": [],
+        "proofs": [],
+        "transaction_count": [],
+        "balance": [],
+        "code": [[{"block_no": 1, "address": "0x0000000000000000000000000000000000000001"}, "0x6000"]],
+        "storage": [],
+        "propose": null
+    }"#;
+
+    #[test]
+    fn previous_witness_versions_are_read() {
+        let query = AccountQuery {
+            block_no: 1,
+            address: H160::from_low_u64_be(1),
+        };
+        let mut witness = deserialize_witness(V1_WITNESS.as_bytes()).unwrap();
+        assert_eq!(
+            witness.get_code(&query).unwrap(),
+            Bytes::from(vec![0x60, 0x00])
+        );
+
+        // written back in the current version
+        witness.insert_code(query.clone(), Bytes::from(vec![0x60, 0x01]));
+        let written = serde_json::to_vec(&witness).unwrap();
+        let mut value: serde_json::Value = serde_json::from_slice(&written).unwrap();
+        assert_eq!(value["version"], WITNESS_VERSION);
+        let mut read = deserialize_witness(&written).unwrap();
+        assert_eq!(
+            read.get_code(&query).unwrap(),
+            Bytes::from(vec![0x60, 0x01])
+        );
+
+        value["version"] = 9.into();
+        let err = deserialize_witness(&serde_json::to_vec(&value).unwrap()).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<UnsupportedWitnessVersion>(),
+            Some(&UnsupportedWitnessVersion {
+                version: 9,
+                supported: vec![1, 2],
+            })
+        );
+        assert!(err.to_string().starts_with("UnsupportedWitnessVersion"));
+    }
+}
@@ -23,10 +23,11 @@ use revm::{
 use zeth_primitives::{
     block::Header,
     ethers::{from_ethers_bytes, from_ethers_u256},
-    Address, B256, U256,
+    Address, Bytes, B256, U256,
 };
 
 use crate::{
+    consts::AccountOverride,
     host::provider::{AccountQuery, BlockQuery, ProofQuery, Provider, StorageQuery},
     mem_db::{DbError, MemDb},
 };
@@ -60,6 +61,40 @@ impl ProviderDb {
         &self.latest_db
     }
 
+    /// Replaces the code of `address` in the initial state, so that the execution runs
+    /// the overridden code and the initial db carries it into the witness.
+    pub fn override_code(&mut self, address: Address, code: Bytes) -> Result<(), anyhow::Error> {
+        self.basic(address)?;
+        let account = self.initial_db.accounts.get_mut(&address).unwrap();
+        let bytecode = Bytecode::new_raw(code);
+        account.info.code_hash = bytecode.hash_slow();
+        account.info.code = Some(bytecode);
+        Ok(())
+    }
+
+    /// Replaces the balance, the nonce and the slots of `address` in the initial state,
+    /// the slots read first for the witness to cover them.
+    pub fn override_account(
+        &mut self,
+        address: Address,
+        overrides: &AccountOverride,
+    ) -> Result<(), anyhow::Error> {
+        for (slot, value) in &overrides.state_diff {
+            self.storage(address, *slot)?;
+            self.initial_db
+                .insert_account_storage(&address, *slot, *value);
+        }
+        self.basic(address)?;
+        let account = self.initial_db.accounts.get_mut(&address).unwrap();
+        if let Some(balance) = overrides.balance {
+            account.info.balance = balance;
+        }
+        if let Some(nonce) = overrides.nonce {
+            account.info.nonce = nonce;
+        }
+        Ok(())
+    }
+
     fn get_proofs(
         &mut self,
         block_no: u64,
@@ -0,0 +1,335 @@
+//! Cross-checks exported block data against the canonical chain of a live node, before
+//! it enters the proving pipeline. Nothing is built nor proven.
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use ethers_core::types::{Block, Transaction, H256};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use thiserror::Error as ThisError;
+
+use super::provider::{
+    file_provider::FileProvider, new_rpc_provider, rpc_provider::RpcProvider, BlockQuery, Provider,
+    RpcOptions,
+};
+
+/// The header fields compared, named as in the JSON-RPC blocks.
+const HEADER_FIELDS: [&str; 18] = [
+    "hash",
+    "parentHash",
+    "sha3Uncles",
+    "miner",
+    "stateRoot",
+    "transactionsRoot",
+    "receiptsRoot",
+    "logsBloom",
+    "difficulty",
+    "number",
+    "gasLimit",
+    "gasUsed",
+    "timestamp",
+    "extraData",
+    "mixHash",
+    "nonce",
+    "baseFeePerGas",
+    "withdrawalsRoot",
+];
+
+/// A field of a block whose value in the block data file differs from the node's.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldMismatch {
+    pub block_no: u64,
+    pub field: String,
+    pub file: Value,
+    pub rpc: Value,
+}
+
+/// Why a block data file is too stale to be proven, e.g. an export predating a reorg.
+#[derive(Debug, Clone, PartialEq, Eq, ThisError)]
+pub enum StaleData {
+    #[error("block {block_no} is {age_secs}s old, more than the {max_age_secs}s allowed")]
+    TooOld {
+        block_no: u64,
+        age_secs: u64,
+        max_age_secs: u64,
+    },
+    #[error("block {block_no} {file:?} is no longer canonical, the node has {canonical:?}")]
+    NotCanonical {
+        block_no: u64,
+        file: H256,
+        canonical: H256,
+    },
+}
+
+/// Checks that the newest full block of `file` is at most `max_age` older than
+/// `reference_timestamp`, and when `canonical` is given that its hash is still the one
+/// of the canonical chain.
+pub fn check_data_age(
+    file: &FileProvider,
+    max_age: Duration,
+    reference_timestamp: u64,
+    canonical: Option<&mut dyn Provider>,
+) -> Result<Option<StaleData>> {
+    let Some((query, block)) = file
+        .full_blocks()
+        .into_iter()
+        .max_by_key(|(query, _)| query.block_no)
+    else {
+        bail!("No full block in the block data");
+    };
+    let age_secs = reference_timestamp.saturating_sub(block.timestamp.as_u64());
+    if age_secs > max_age.as_secs() {
+        return Ok(Some(StaleData::TooOld {
+            block_no: query.block_no,
+            age_secs,
+            max_age_secs: max_age.as_secs(),
+        }));
+    }
+    if let Some(canonical) = canonical {
+        let canonical_hash = canonical.get_partial_block(query)?.hash;
+        if canonical_hash != block.hash {
+            return Ok(Some(StaleData::NotCanonical {
+                block_no: query.block_no,
+                file: block.hash.unwrap_or_default(),
+                canonical: canonical_hash.unwrap_or_default(),
+            }));
+        }
+    }
+    Ok(None)
+}
+
+/// [check_data_age] of the block data file at `file_path`, relative to the chain head
+/// of `rpc_url` and checked against its chain, or relative to `now` without a node.
+pub fn check_data_age_of_file(
+    file_path: String,
+    max_age: Duration,
+    rpc: Option<(String, &RpcOptions)>,
+    now: u64,
+) -> Result<Option<StaleData>> {
+    let file = FileProvider::read_from_file(file_path)?;
+    match rpc {
+        Some((rpc_url, options)) => {
+            let mut rpc = RpcProvider::new(rpc_url, options)?;
+            let head = rpc.get_head_block()?;
+            check_data_age(&file, max_age, head.timestamp.as_u64(), Some(&mut rpc))
+        }
+        None => check_data_age(&file, max_age, now, None),
+    }
+}
+
+/// Compares the block data file at `file_path` with the blocks served by `rpc_url`.
+pub fn validate_against_rpc(
+    file_path: String,
+    rpc_url: String,
+    options: &RpcOptions,
+) -> Result<Vec<FieldMismatch>> {
+    let file = FileProvider::read_from_file(file_path)?;
+    let mut rpc = new_rpc_provider(rpc_url, options)?;
+    diff_block_data(&file, rpc.as_mut())
+}
+
+/// Compares the full blocks of `file`, and the parent blocks whose state they build on,
+/// with the ones of `canonical`.
+pub fn diff_block_data(
+    file: &FileProvider,
+    canonical: &mut dyn Provider,
+) -> Result<Vec<FieldMismatch>> {
+    let mut mismatches = vec![];
+    for (query, block) in file.full_blocks() {
+        let canonical_block = canonical.get_full_block(query)?;
+        diff_headers(query.block_no, block, &canonical_block, &mut mismatches)?;
+        let tx_hashes = |block: &Block<Transaction>| {
+            serde_json::to_value(
+                block
+                    .transactions
+                    .iter()
+                    .map(|tx| tx.hash)
+                    .collect::<Vec<_>>(),
+            )
+        };
+        push_if_different(
+            &mut mismatches,
+            query.block_no,
+            "transactions",
+            tx_hashes(block)?,
+            tx_hashes(&canonical_block)?,
+        );
+
+        let Some(parent_no) = query.block_no.checked_sub(1) else {
+            continue;
+        };
+        let parent_query = BlockQuery {
+            block_no: parent_no,
+        };
+        if let Some(parent) = file.partial_block(&parent_query) {
+            let canonical_parent = canonical.get_partial_block(&parent_query)?;
+            diff_headers(parent_no, parent, &canonical_parent, &mut mismatches)?;
+        }
+    }
+    Ok(mismatches)
+}
+
+fn diff_headers<T: Serialize>(
+    block_no: u64,
+    file: &Block<T>,
+    canonical: &Block<T>,
+    mismatches: &mut Vec<FieldMismatch>,
+) -> Result<()> {
+    let file = serde_json::to_value(file)?;
+    let canonical = serde_json::to_value(canonical)?;
+    for field in HEADER_FIELDS {
+        push_if_different(
+            mismatches,
+            block_no,
+            field,
+            file.get(field).cloned().unwrap_or_default(),
+            canonical.get(field).cloned().unwrap_or_default(),
+        );
+    }
+    Ok(())
+}
+
+fn push_if_different(
+    mismatches: &mut Vec<FieldMismatch>,
+    block_no: u64,
+    field: &str,
+    file: Value,
+    rpc: Value,
+) {
+    if file != rpc {
+        mismatches.push(FieldMismatch {
+            block_no,
+            field: field.to_owned(),
+            file,
+            rpc,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers_core::types::U256;
+
+    use super::*;
+    use crate::host::provider::MutProvider;
+
+    fn block_data(state_root: H256) -> FileProvider {
+        let mut provider = FileProvider::empty(String::new());
+        provider.insert_full_block(
+            BlockQuery { block_no: 2 },
+            Block {
+                number: Some(2.into()),
+                state_root,
+                transactions: vec![Transaction {
+                    hash: H256::repeat_byte(0xaa),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+        );
+        provider.insert_partial_block(
+            BlockQuery { block_no: 1 },
+            Block {
+                number: Some(1.into()),
+                gas_limit: U256::from(30_000_000),
+                ..Default::default()
+            },
+        );
+        provider
+    }
+
+    #[test]
+    fn matching_block_data_has_no_mismatches() {
+        let file = block_data(H256::repeat_byte(1));
+        let mut canonical = block_data(H256::repeat_byte(1));
+        assert_eq!(diff_block_data(&file, &mut canonical).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn stale_block_data_reports_the_mismatched_fields() {
+        let file = block_data(H256::repeat_byte(1));
+        let mut canonical = block_data(H256::repeat_byte(2));
+        let mut parent = canonical
+            .get_partial_block(&BlockQuery { block_no: 1 })
+            .unwrap();
+        parent.gas_limit = U256::from(15_000_000);
+        canonical.insert_partial_block(BlockQuery { block_no: 1 }, parent);
+
+        let mismatches = diff_block_data(&file, &mut canonical).unwrap();
+        let fields: Vec<_> = mismatches
+            .iter()
+            .map(|mismatch| (mismatch.block_no, mismatch.field.as_str()))
+            .collect();
+        assert_eq!(fields, vec![(2, "stateRoot"), (1, "gasLimit")]);
+        assert_eq!(
+            mismatches[0].rpc,
+            serde_json::to_value(H256::repeat_byte(2)).unwrap()
+        );
+    }
+
+    #[test]
+    fn missing_canonical_block_is_an_error() {
+        let file = block_data(H256::repeat_byte(1));
+        let mut canonical = FileProvider::empty(String::new());
+        assert!(diff_block_data(&file, &mut canonical).is_err());
+    }
+
+    fn exported_block(hash: H256, timestamp: u64) -> FileProvider {
+        let mut provider = FileProvider::empty(String::new());
+        provider.insert_full_block(
+            BlockQuery { block_no: 2 },
+            Block {
+                number: Some(2.into()),
+                hash: Some(hash),
+                timestamp: timestamp.into(),
+                ..Default::default()
+            },
+        );
+        provider.insert_partial_block(
+            BlockQuery { block_no: 2 },
+            Block {
+                number: Some(2.into()),
+                hash: Some(hash),
+                timestamp: timestamp.into(),
+                ..Default::default()
+            },
+        );
+        provider
+    }
+
+    #[test]
+    fn stale_block_data_is_rejected() {
+        let max_age = Duration::from_secs(600);
+        let mut canonical = exported_block(H256::repeat_byte(1), 10_000);
+        let fresh = exported_block(H256::repeat_byte(1), 10_000);
+        assert_eq!(
+            check_data_age(&fresh, max_age, 10_100, Some(&mut canonical)).unwrap(),
+            None
+        );
+
+        // exported before a reorg replaced the block
+        let reorged = exported_block(H256::repeat_byte(2), 10_000);
+        assert_eq!(
+            check_data_age(&reorged, max_age, 10_100, Some(&mut canonical)).unwrap(),
+            Some(StaleData::NotCanonical {
+                block_no: 2,
+                file: H256::repeat_byte(2),
+                canonical: H256::repeat_byte(1),
+            })
+        );
+        // without a node, only its age gives it away
+        assert_eq!(
+            check_data_age(&reorged, max_age, 10_100, None).unwrap(),
+            None
+        );
+        assert_eq!(
+            check_data_age(&reorged, max_age, 11_000, None).unwrap(),
+            Some(StaleData::TooOld {
+                block_no: 2,
+                age_secs: 1000,
+                max_age_secs: 600,
+            })
+        );
+    }
+}
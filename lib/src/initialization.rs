@@ -153,6 +153,7 @@ impl<E: TxEssence> DbInitStrategy<E> for MemDbInitStrategy {
         Ok(block_builder.with_db(MemDb {
             accounts,
             block_hashes,
+            commits: None,
         }))
     }
 }
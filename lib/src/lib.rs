@@ -26,6 +26,8 @@ pub mod initialization;
 pub mod input;
 pub mod mem_db;
 pub mod preparation;
+#[cfg(feature = "trace")]
+pub mod trace;
 
 #[cfg(feature = "taiko")]
 pub mod taiko;
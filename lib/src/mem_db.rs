@@ -83,6 +83,9 @@ pub struct MemDb {
     pub accounts: HashMap<Address, DbAccount>,
     /// All cached block hashes.
     pub block_hashes: HashMap<u64, B256>,
+    /// The accounts changed by each commit, recorded once [MemDb::record_commits] is
+    /// called.
+    pub commits: Option<Vec<Vec<(Address, DbAccount)>>>,
 }
 
 impl MemDb {
@@ -117,6 +120,12 @@ impl MemDb {
         account.storage.insert(index, data);
     }
 
+    /// Records the accounts changed by each commit from now on, for
+    /// [crate::finalization::tx_state_roots].
+    pub fn record_commits(&mut self) {
+        self.commits = Some(vec![]);
+    }
+
     /// Insert the specified block hash. Panics if a different block hash exists.
     pub fn insert_block_hash(&mut self, block_no: u64, block_hash: B256) {
         match self.block_hashes.entry(block_no) {
@@ -182,6 +191,14 @@ impl Database for MemDb {
 
 impl DatabaseCommit for MemDb {
     fn commit(&mut self, changes: HashMap<Address, Account>) {
+        let touched: Vec<Address> = match self.commits {
+            Some(_) => changes
+                .iter()
+                .filter(|(_, account)| account.is_touched())
+                .map(|(address, _)| *address)
+                .collect(),
+            None => vec![],
+        };
         for (address, new_account) in changes {
             // if nothing was touched, there is nothing to do
             if !new_account.is_touched() {
@@ -269,5 +286,15 @@ impl DatabaseCommit for MemDb {
                     .map(|(key, value)| (key, value.present_value())),
             );
         }
+
+        if let Some(commits) = &mut self.commits {
+            let accounts = &self.accounts;
+            commits.push(
+                touched
+                    .into_iter()
+                    .filter_map(|address| Some((address, accounts.get(&address)?.clone())))
+                    .collect(),
+            );
+        }
     }
 }
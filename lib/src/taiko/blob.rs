@@ -0,0 +1,349 @@
+//! EIP-4844 blobs carrying the L2 transaction lists.
+use std::path::Path;
+
+use c_kzg::{Blob, Bytes48, KzgProof, KzgSettings};
+use ethers_core::types::Bytes;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+use sha2::{Digest, Sha256};
+use thiserror::Error as ThisError;
+use zeth_primitives::{taiko::BlockMetadata, B256};
+
+/// Size of a blob, 4096 field elements of 32 bytes.
+pub const BLOB_SIZE: usize = 4096 * 32;
+const VERSIONED_HASH_VERSION_KZG: u8 = 0x01;
+const ENCODING_VERSION: u8 = 0;
+const VERSION_OFFSET: usize = 1;
+const ROUNDS: usize = 1024;
+const MAX_BLOB_DATA_SIZE: usize = (4 * 31 + 3) * ROUNDS - 4;
+
+/// Error returned when a blob can't be fetched or doesn't match its versioned hash.
+#[derive(Debug, ThisError)]
+pub enum BlobFetchError {
+    /// Returned when a block uses a blob but no beacon node is configured.
+    #[error("blob {0} is needed but no beacon RPC URL is configured")]
+    NoBeaconRpc(B256),
+    /// Returned when the beacon node request fails.
+    #[error("beacon node request failed: {0}")]
+    Beacon(String),
+    /// Returned when none of the sidecars of the slot carries the blob.
+    #[error("no blob sidecar with versioned hash {0}")]
+    NotFound(B256),
+    /// Returned when the KZG commitment does not hash to the versioned hash.
+    #[error("blob commitment hashes to {actual}, expected {expected}")]
+    VersionedHashMismatch { expected: B256, actual: B256 },
+    /// Returned when the blob does not match its KZG commitment.
+    #[error("blob {0} does not match its KZG commitment")]
+    InvalidProof(B256),
+    /// Returned when the trusted setup is missing or the KZG library fails.
+    #[error("KZG: {0}")]
+    Kzg(String),
+    /// Returned when the blob data is not a valid encoding of the transaction list.
+    #[error("malformed blob: {0}")]
+    Malformed(String),
+}
+
+/// A blob with its commitment and proof, as served by the beacon node's
+/// `/eth/v1/beacon/blob_sidecars` endpoint.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BlobSidecar {
+    #[serde_as(as = "DisplayFromStr")]
+    pub index: u64,
+    pub blob: Bytes,
+    pub kzg_commitment: Bytes,
+    pub kzg_proof: Bytes,
+}
+
+#[derive(Deserialize)]
+struct BeaconResponse<T> {
+    data: T,
+}
+
+#[serde_as]
+#[derive(Deserialize)]
+struct Genesis {
+    #[serde_as(as = "DisplayFromStr")]
+    genesis_time: u64,
+}
+
+#[serde_as]
+#[derive(Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+struct Spec {
+    #[serde_as(as = "DisplayFromStr")]
+    seconds_per_slot: u64,
+}
+
+async fn beacon_get<T: for<'de> Deserialize<'de>>(
+    client: &reqwest::Client,
+    url: String,
+) -> Result<T, BlobFetchError> {
+    let response = client
+        .get(&url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| BlobFetchError::Beacon(e.to_string()))?;
+    let body = response
+        .bytes()
+        .await
+        .map_err(|e| BlobFetchError::Beacon(e.to_string()))?;
+    let response: BeaconResponse<T> = serde_json::from_slice(&body)
+        .map_err(|e| BlobFetchError::Beacon(format!("malformed response of {}: {}", url, e)))?;
+    Ok(response.data)
+}
+
+/// Fetches the blob sidecars of the slot of the L1 block with `timestamp`.
+pub async fn fetch_blob_sidecars(
+    client: &reqwest::Client,
+    beacon_rpc_url: &str,
+    timestamp: u64,
+) -> Result<Vec<BlobSidecar>, BlobFetchError> {
+    let beacon_rpc_url = beacon_rpc_url.trim_end_matches('/');
+    let genesis: Genesis =
+        beacon_get(client, format!("{}/eth/v1/beacon/genesis", beacon_rpc_url)).await?;
+    let spec: Spec = beacon_get(client, format!("{}/eth/v1/config/spec", beacon_rpc_url)).await?;
+    let slot = timestamp
+        .checked_sub(genesis.genesis_time)
+        .filter(|_| spec.seconds_per_slot > 0)
+        .map(|elapsed| elapsed / spec.seconds_per_slot)
+        .ok_or_else(|| {
+            BlobFetchError::Beacon(format!("block timestamp {} is before genesis", timestamp))
+        })?;
+    beacon_get(
+        client,
+        format!("{}/eth/v1/beacon/blob_sidecars/{}", beacon_rpc_url, slot),
+    )
+    .await
+}
+
+/// The versioned hash of a KZG commitment, as referenced by blob transactions.
+pub fn kzg_to_versioned_hash(commitment: &[u8]) -> B256 {
+    let mut hash: [u8; 32] = Sha256::digest(commitment).into();
+    hash[0] = VERSIONED_HASH_VERSION_KZG;
+    hash.into()
+}
+
+pub fn load_kzg_settings(trusted_setup: Option<&Path>) -> Result<KzgSettings, BlobFetchError> {
+    let trusted_setup = trusted_setup.ok_or_else(|| {
+        BlobFetchError::Kzg("no trusted setup file configured to verify blobs".to_string())
+    })?;
+    KzgSettings::load_trusted_setup_file(trusted_setup)
+        .map_err(|e| BlobFetchError::Kzg(format!("{:?}: {:?}", trusted_setup, e)))
+}
+
+/// Checks that the blob of `sidecar` matches its KZG commitment.
+pub fn verify_kzg_proof(
+    sidecar: &BlobSidecar,
+    settings: &KzgSettings,
+) -> Result<(), BlobFetchError> {
+    let kzg_error = |e: c_kzg::Error| BlobFetchError::Kzg(format!("{:?}", e));
+    let blob = Blob::from_bytes(&sidecar.blob).map_err(kzg_error)?;
+    let commitment = Bytes48::from_bytes(&sidecar.kzg_commitment).map_err(kzg_error)?;
+    let proof = Bytes48::from_bytes(&sidecar.kzg_proof).map_err(kzg_error)?;
+    if KzgProof::verify_blob_kzg_proof(&blob, &commitment, &proof, settings).map_err(kzg_error)? {
+        Ok(())
+    } else {
+        Err(BlobFetchError::InvalidProof(kzg_to_versioned_hash(
+            &sidecar.kzg_commitment,
+        )))
+    }
+}
+
+/// The transaction list of the block proposed with `meta`, read from the blob in
+/// `sidecar` after checking its commitment against the versioned hash of the proposal.
+pub fn blob_tx_list(
+    meta: &BlockMetadata,
+    sidecar: &BlobSidecar,
+) -> Result<Vec<u8>, BlobFetchError> {
+    let actual = kzg_to_versioned_hash(&sidecar.kzg_commitment);
+    if actual != meta.blobHash {
+        return Err(BlobFetchError::VersionedHashMismatch {
+            expected: meta.blobHash,
+            actual,
+        });
+    }
+    let data = decode_blob_data(&sidecar.blob)?;
+    let start = meta.txListByteOffset as usize;
+    let end = start + meta.txListByteSize as usize;
+    if end > data.len() {
+        return Err(BlobFetchError::Malformed(format!(
+            "transaction list {}..{} is out of the {} bytes of blob data",
+            start,
+            end,
+            data.len()
+        )));
+    }
+    Ok(data[start..end].to_vec())
+}
+
+/// Decodes the data of a blob packed 127 bytes to 4 field elements, the first round
+/// starting with the encoding version and the 3 byte length of the data.
+pub fn decode_blob_data(blob: &[u8]) -> Result<Vec<u8>, BlobFetchError> {
+    if blob.len() != BLOB_SIZE {
+        return Err(BlobFetchError::Malformed(format!(
+            "{} bytes instead of {}",
+            blob.len(),
+            BLOB_SIZE
+        )));
+    }
+    if blob[VERSION_OFFSET] != ENCODING_VERSION {
+        return Err(BlobFetchError::Malformed(format!(
+            "unknown encoding version {}",
+            blob[VERSION_OFFSET]
+        )));
+    }
+    let len = (blob[2] as usize) << 16 | (blob[3] as usize) << 8 | blob[4] as usize;
+    if len > MAX_BLOB_DATA_SIZE {
+        return Err(BlobFetchError::Malformed(format!(
+            "data length {} exceeds {}",
+            len, MAX_BLOB_DATA_SIZE
+        )));
+    }
+
+    let mut output = vec![0u8; MAX_BLOB_DATA_SIZE];
+    // the version and the length take the first 5 bytes of the first field element
+    output[..27].copy_from_slice(&blob[5..32]);
+    let mut opos = 28;
+    let mut ipos = 32;
+    let mut encoded = [blob[0], 0, 0, 0];
+    for byte in encoded.iter_mut().skip(1) {
+        *byte = decode_field_element(blob, &mut opos, &mut ipos, &mut output)?;
+    }
+    opos = reassemble_bytes(opos, &encoded, &mut output);
+    for _ in 1..ROUNDS {
+        if opos >= len {
+            break;
+        }
+        for byte in encoded.iter_mut() {
+            *byte = decode_field_element(blob, &mut opos, &mut ipos, &mut output)?;
+        }
+        opos = reassemble_bytes(opos, &encoded, &mut output);
+    }
+    output.truncate(len);
+    Ok(output)
+}
+
+/// Copies the low 31 bytes of the field element at `ipos` and returns its first byte,
+/// which carries 6 bits of the round's remaining 3 bytes.
+fn decode_field_element(
+    blob: &[u8],
+    opos: &mut usize,
+    ipos: &mut usize,
+    output: &mut [u8],
+) -> Result<u8, BlobFetchError> {
+    let first = blob[*ipos];
+    if first & 0b1100_0000 != 0 {
+        return Err(BlobFetchError::Malformed(format!(
+            "invalid field element at byte {}",
+            ipos
+        )));
+    }
+    output[*opos..*opos + 31].copy_from_slice(&blob[*ipos + 1..*ipos + 32]);
+    *opos += 32;
+    *ipos += 32;
+    Ok(first)
+}
+
+/// Puts the 3 bytes spread over the first bytes of a round's field elements into the
+/// gaps left in the output, returning the position of the next round.
+fn reassemble_bytes(opos: usize, encoded: &[u8; 4], output: &mut [u8]) -> usize {
+    let opos = opos - 1;
+    let x = (encoded[0] & 0b0011_1111) | ((encoded[1] & 0b0011_0000) << 2);
+    let y = (encoded[1] & 0b0000_1111) | ((encoded[3] & 0b0000_1111) << 4);
+    let z = (encoded[2] & 0b0011_1111) | ((encoded[3] & 0b0011_0000) << 2);
+    output[opos - 32] = z;
+    output[opos - 32 * 2] = y;
+    output[opos - 32 * 3] = x;
+    opos
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The inverse of [decode_blob_data].
+    fn encode_blob_data(data: &[u8]) -> Vec<u8> {
+        let len = data.len();
+        let mut stream = vec![
+            ENCODING_VERSION,
+            (len >> 16) as u8,
+            (len >> 8) as u8,
+            len as u8,
+        ];
+        stream.extend_from_slice(data);
+        stream.resize(ROUNDS * 127, 0);
+        let mut blob = vec![0u8; BLOB_SIZE];
+        for (round, chunk) in stream.chunks(127).enumerate() {
+            let (x, y, z) = (chunk[31], chunk[63], chunk[95]);
+            let encoded = [
+                x & 0b0011_1111,
+                ((x & 0b1100_0000) >> 2) | (y & 0b0000_1111),
+                z & 0b0011_1111,
+                ((y & 0b1111_0000) >> 4) | ((z & 0b1100_0000) >> 2),
+            ];
+            for (j, byte) in encoded.into_iter().enumerate() {
+                let element = &mut blob[(4 * round + j) * 32..][..32];
+                element[0] = byte;
+                element[1..].copy_from_slice(&chunk[32 * j..32 * j + 31]);
+            }
+        }
+        blob
+    }
+
+    fn sidecar(blob: Vec<u8>) -> BlobSidecar {
+        BlobSidecar {
+            index: 0,
+            blob: blob.into(),
+            kzg_commitment: vec![0xc0; 48].into(),
+            kzg_proof: vec![0xc0; 48].into(),
+        }
+    }
+
+    #[test]
+    fn blob_data_round_trips() {
+        for len in [0, 1, 27, 28, 123, 124, 1000, MAX_BLOB_DATA_SIZE] {
+            let data: Vec<u8> = (0..len).map(|i| (i * 7 + 3) as u8).collect();
+            assert_eq!(decode_blob_data(&encode_blob_data(&data)).unwrap(), data);
+        }
+        let mut blob = encode_blob_data(&[1, 2, 3]);
+        blob[VERSION_OFFSET] = 1;
+        assert!(decode_blob_data(&blob).is_err());
+        assert!(decode_blob_data(&blob[1..]).is_err());
+    }
+
+    #[test]
+    fn tx_list_is_read_from_the_referenced_blob() {
+        let tx_list = vec![0xf8, 0x03, 0x01, 0x02, 0x03];
+        let mut data = vec![0xee; 10];
+        data.extend_from_slice(&tx_list);
+        data.extend_from_slice(&[0xee; 10]);
+        let sidecar = sidecar(encode_blob_data(&data));
+        let json = serde_json::to_string(&sidecar).unwrap();
+        assert!(json.contains(r#""index":"0""#));
+        assert_eq!(serde_json::from_str::<BlobSidecar>(&json).unwrap(), sidecar);
+
+        let mut meta = BlockMetadata {
+            blobHash: kzg_to_versioned_hash(&sidecar.kzg_commitment),
+            txListByteOffset: 10,
+            txListByteSize: tx_list.len() as u32,
+            blobUsed: true,
+            ..Default::default()
+        };
+        assert_eq!(meta.blobHash[0], VERSIONED_HASH_VERSION_KZG);
+        assert_eq!(blob_tx_list(&meta, &sidecar).unwrap(), tx_list);
+
+        meta.txListByteSize = data.len() as u32;
+        assert!(matches!(
+            blob_tx_list(&meta, &sidecar),
+            Err(BlobFetchError::Malformed(_))
+        ));
+
+        meta.blobHash = B256::repeat_byte(0x01);
+        assert!(matches!(
+            blob_tx_list(&meta, &sidecar),
+            Err(BlobFetchError::VersionedHashMismatch { .. })
+        ));
+    }
+}
@@ -101,6 +101,8 @@ impl TxExecStrategy<EthereumTxEssence> for TaikoTxExecStrategy {
         evm.env.block.gas_limit = block_builder.input.gas_limit;
 
         evm.database(block_builder.db.take().unwrap());
+        #[cfg(feature = "trace")]
+        let mut tracer = block_builder.tracer.take();
 
         // bloom filter over all transaction logs
         let mut logs_bloom = Bloom::default();
@@ -160,7 +162,18 @@ impl TxExecStrategy<EthereumTxEssence> for TaikoTxExecStrategy {
                 tx_from,
                 is_anchor,
             );
-            let ResultAndState { result, state } = match evm.transact() {
+            // inspected only when tracing, not to slow down the proofs
+            #[cfg(feature = "trace")]
+            let transacted = match tracer.as_mut() {
+                Some(tracer) => {
+                    tracer.begin_tx(tx_no);
+                    evm.inspect(tracer)
+                }
+                None => evm.transact(),
+            };
+            #[cfg(not(feature = "trace"))]
+            let transacted = evm.transact();
+            let ResultAndState { result, state } = match transacted {
                 Ok(result) => result,
                 Err(err) => {
                     if is_anchor {
@@ -257,6 +270,10 @@ impl TxExecStrategy<EthereumTxEssence> for TaikoTxExecStrategy {
         }
 
         let mut db = evm.take_db();
+        #[cfg(feature = "trace")]
+        {
+            block_builder.tracer = tracer;
+        }
 
         // process withdrawals unconditionally after any transactions
         let mut withdrawals_trie = MptNode::default();
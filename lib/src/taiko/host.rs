@@ -1,23 +1,36 @@
-use anyhow::Result;
+use std::path::Path;
+
+use anyhow::{bail, Result};
 use ethers_core::types::{Block, Transaction as EthersTransaction, H160, H256, U256};
 use tracing::info;
 use zeth_primitives::{
     ethers::{from_ethers_h160, from_ethers_h256, from_ethers_u256},
+    keccak::keccak,
     taiko::*,
     transactions::ethereum::EthereumTxEssence,
+    trie::StateAccount,
     withdrawal::Withdrawal,
     Address, B256,
 };
 
 use crate::{
     block_builder::{BlockBuilder, NetworkStrategyBundle},
-    consts::ChainSpec,
+    consts::{ChainSpec, ConsensusOverrides, StateOverrides, SystemOverrides},
     host::{
-        provider::{new_provider, BlockQuery, ProofQuery, ProposeQuery, Provider},
+        provider::{
+            new_provider,
+            rpc_consistency::{check_linked_blocks, check_state_root, retry_inconsistent},
+            BlobQuery, BlockQuery, ProofQuery, ProposeQuery, Provider, RpcOptions,
+        },
         Init,
     },
     input::Input,
-    taiko::{precheck::rebuild_and_precheck_block, Layer},
+    taiko::{
+        blob::{blob_tx_list, load_kzg_settings, verify_kzg_proof},
+        precheck::rebuild_and_precheck_block,
+        spec_mismatch::{check_chain_spec, ChainSpecMismatch},
+        Layer,
+    },
 };
 
 #[derive(Debug)]
@@ -25,6 +38,8 @@ pub struct TaikoExtra {
     pub l1_hash: B256,
     pub l1_height: u64,
     pub l2_tx_list: Vec<u8>,
+    /// Versioned hash of the blob the transaction list was read from.
+    pub l2_blob_hash: Option<B256>,
     pub prover: Address,
     pub graffiti: B256,
     pub l1_signal_root: B256,
@@ -32,6 +47,18 @@ pub struct TaikoExtra {
     pub l2_withdrawals: Vec<Withdrawal>,
     pub block_proposed: BlockProposed,
     pub l1_next_block: Block<EthersTransaction>,
+    /// How the L2 block data disagrees with the chain spec, if it does.
+    pub chain_spec_mismatch: Option<ChainSpecMismatch>,
+}
+
+impl TaikoExtra {
+    /// The chain spec the L2 block is built with, as resolved by the mismatch policy.
+    pub fn resolved_chain_spec(&self, l2_chain_spec: &ChainSpec) -> Result<ChainSpec> {
+        match &self.chain_spec_mismatch {
+            Some(mismatch) => mismatch.resolve(l2_chain_spec.clone()),
+            None => Ok(l2_chain_spec.clone()),
+        }
+    }
 }
 
 #[allow(clippy::type_complexity)]
@@ -39,6 +66,7 @@ fn fetch_data(
     annotation: &str,
     cache_path: Option<String>,
     rpc_url: Option<String>,
+    rpc_options: &RpcOptions,
     block_no: u64,
     signal_service: Address,
     layer: Layer,
@@ -49,7 +77,14 @@ fn fetch_data(
     B256,
     Input<EthereumTxEssence>,
 )> {
-    let mut provider = new_provider(cache_path, rpc_url)?;
+    let layer_limit = match layer {
+        Layer::L1 => &rpc_options.layer_limits.l1,
+        Layer::L2 => &rpc_options.layer_limits.l2,
+    };
+    let rpc_options = rpc_options
+        .with_layer_limit(layer_limit)
+        .with_shared_cache_scope(&annotation.to_lowercase());
+    let mut provider = new_provider(cache_path, rpc_url, &rpc_options)?;
 
     let fini_query = BlockQuery { block_no };
     match layer {
@@ -80,6 +115,7 @@ fn fetch_data(
         fini_block.hash.unwrap()
     );
     info!("Transaction count: {:?}", fini_block.transactions.len());
+    check_linked_blocks(annotation, &init_block, &fini_block)?;
 
     // Get l2 signal root by signal service
     let proof = provider.get_proof(&ProofQuery {
@@ -87,6 +123,7 @@ fn fetch_data(
         address: H160::from_slice(signal_service.as_slice()),
         indices: Default::default(),
     })?;
+    check_state_root(annotation, block_no, &proof, fini_block.state_root)?;
     let signal_root = from_ethers_h256(proof.storage_hash);
 
     info!(
@@ -127,6 +164,114 @@ fn fetch_data(
     Ok((provider, init_block, fini_block, signal_root, input))
 }
 
+/// Rejects the blocks of more than `max_txs` transactions, the anchor included, before
+/// their witness is built.
+fn check_tx_count(block_no: u64, block: &Block<EthersTransaction>, max_txs: usize) -> Result<()> {
+    let tx_count = block.transactions.len();
+    if tx_count > max_txs {
+        bail!(
+            "Block {} has {} transactions, more than the maximum of {} (max_txs_per_block)",
+            block_no,
+            tx_count,
+            max_txs
+        );
+    }
+    Ok(())
+}
+
+/// Builds the block with the overridden parameters instead of the ones of its data.
+fn apply_consensus_overrides(
+    overrides: &ConsensusOverrides,
+    input: &mut Input<EthereumTxEssence>,
+    fini_block: &mut Block<EthersTransaction>,
+) {
+    if let Some(base_fee_per_gas) = overrides.base_fee_per_gas {
+        input.base_fee_per_gas = base_fee_per_gas;
+        fini_block.base_fee_per_gas = Some(U256(base_fee_per_gas.into_limbs()));
+    }
+    if let Some(gas_limit) = overrides.gas_limit {
+        input.gas_limit = gas_limit;
+        fini_block.gas_limit = U256(gas_limit.into_limbs());
+    }
+}
+
+/// Reads the system overrides from a JSON file, as `{"code": {"<address>":
+/// "<bytecode>"}}`.
+pub fn read_system_overrides(path: &Path) -> Result<SystemOverrides> {
+    let json = std::fs::read(path)?;
+    Ok(serde_json::from_slice(&json)?)
+}
+
+/// Replaces the code of the overridden accounts in the pre-state of the witness, built
+/// with the overrides, and the pre-state root with the one committing to the overridden
+/// code. The parent block hash changes accordingly, testing only.
+pub fn apply_system_overrides(
+    overrides: &SystemOverrides,
+    input: &mut Input<EthereumTxEssence>,
+) -> Result<()> {
+    if overrides.is_empty() {
+        return Ok(());
+    }
+    for (address, code) in &overrides.code {
+        if !input.parent_storage.contains_key(address) {
+            bail!("Overridden account {} is not part of the witness", address);
+        }
+        let key = keccak(address);
+        let mut account: StateAccount = input.parent_state_trie.get_rlp(&key)?.unwrap_or_default();
+        account.code_hash = keccak(code).into();
+        input.parent_state_trie.insert_rlp(&key, account)?;
+        if !input.contracts.contains(code) {
+            input.contracts.push(code.clone());
+        }
+    }
+    input.parent_header.state_root = input.parent_state_trie.hash();
+    Ok(())
+}
+
+/// Replaces the overridden balances, nonces and slots in the pre-state of the witness,
+/// built with the overrides, and the pre-state root with the one committing to them. The
+/// parent block hash changes accordingly, testing only.
+pub fn apply_state_overrides(
+    overrides: &StateOverrides,
+    input: &mut Input<EthereumTxEssence>,
+) -> Result<()> {
+    if overrides.is_empty() {
+        return Ok(());
+    }
+    for (address, overrides) in &overrides.accounts {
+        let Some((storage_trie, slots)) = input.parent_storage.get_mut(address) else {
+            bail!("Overridden account {} is not part of the witness", address);
+        };
+        let key = keccak(address);
+        let mut account: StateAccount = input.parent_state_trie.get_rlp(&key)?.unwrap_or_default();
+        if let Some(balance) = overrides.balance {
+            account.balance = balance;
+        }
+        if let Some(nonce) = overrides.nonce {
+            account.nonce = nonce;
+        }
+        for (slot, value) in &overrides.state_diff {
+            if !slots.contains(slot) {
+                bail!(
+                    "Overridden slot {} of {} is not part of the witness",
+                    slot,
+                    address
+                );
+            }
+            let slot_key = keccak(slot.to_be_bytes::<32>());
+            if value.is_zero() {
+                storage_trie.delete(&slot_key)?;
+            } else {
+                storage_trie.insert_rlp(&slot_key, *value)?;
+            }
+        }
+        account.storage_root = storage_trie.hash();
+        input.parent_state_trie.insert_rlp(&key, account)?;
+    }
+    input.parent_header.state_root = input.parent_state_trie.hash();
+    Ok(())
+}
+
 fn execute_data<N: NetworkStrategyBundle<TxEssence = EthereumTxEssence>>(
     provider: Box<dyn Provider>,
     chain_spec: ChainSpec,
@@ -135,8 +280,15 @@ fn execute_data<N: NetworkStrategyBundle<TxEssence = EthereumTxEssence>>(
     fini_block: Block<EthersTransaction>,
 ) -> Result<Init<EthereumTxEssence>> {
     // Create the provider DB
-    let provider_db =
+    let mut provider_db =
         crate::host::provider_db::ProviderDb::new(provider, init_block.number.unwrap().as_u64());
+    // run the overridden code and state, for the witness to cover the state read by it
+    for (address, code) in &chain_spec.system_overrides().code {
+        provider_db.override_code(*address, code.clone())?;
+    }
+    for (address, overrides) in &chain_spec.state_overrides().accounts {
+        provider_db.override_account(*address, overrides)?;
+    }
     // Create the block builder, run the transactions and extract the DB
     let mut builder = BlockBuilder::new(&chain_spec, input)
         .with_db(provider_db)
@@ -149,6 +301,13 @@ fn execute_data<N: NetworkStrategyBundle<TxEssence = EthereumTxEssence>>(
     // Gather inclusion proofs for the initial and final state
     let init_proofs = provider_db.get_initial_proofs()?;
     let fini_proofs = provider_db.get_latest_proofs()?;
+    let init_block_no = init_block.number.unwrap().as_u64();
+    for proof in init_proofs.values() {
+        check_state_root("L2", init_block_no, proof, init_block.state_root)?;
+    }
+    for proof in fini_proofs.values() {
+        check_state_root("L2", init_block_no + 1, proof, fini_block.state_root)?;
+    }
 
     // Gather proofs for block history
     let history_headers = provider_db.provider.batch_get_partial_blocks(&BlockQuery {
@@ -206,7 +365,7 @@ fn execute_data<N: NetworkStrategyBundle<TxEssence = EthereumTxEssence>>(
 #[allow(clippy::too_many_arguments)]
 pub fn get_taiko_initial_data<N: NetworkStrategyBundle<TxEssence = EthereumTxEssence>>(
     l1_cache_path: Option<String>,
-    _l1_chain_spec: ChainSpec,
+    l1_chain_spec: ChainSpec,
     l1_rpc_url: Option<String>,
     prover: Address,
     l2_cache_path: Option<String>,
@@ -214,15 +373,107 @@ pub fn get_taiko_initial_data<N: NetworkStrategyBundle<TxEssence = EthereumTxEss
     l2_rpc_url: Option<String>,
     l2_block_no: u64,
     graffiti: B256,
+    rpc_options: RpcOptions,
 ) -> Result<(Init<EthereumTxEssence>, TaikoExtra)> {
-    let (l2_provider, l2_init_block, mut l2_fini_block, l2_signal_root, l2_input) = fetch_data(
+    // the budget covers the L1 and the L2 data of the block, fetched again or not
+    let rpc_options = rpc_options.start_block_fetch();
+    retry_inconsistent(&rpc_options, |rpc_options| {
+        let (l2_provider, l2_init_block, l2_input, l2_fini_block, extra) = prepare_data(
+            l1_cache_path.clone(),
+            l1_chain_spec.clone(),
+            l1_rpc_url.clone(),
+            prover,
+            l2_cache_path.clone(),
+            &l2_chain_spec,
+            l2_rpc_url.clone(),
+            l2_block_no,
+            graffiti,
+            rpc_options.clone(),
+        )?;
+
+        // execute transactions and get states
+        let init = execute_data::<N>(
+            l2_provider,
+            extra.resolved_chain_spec(&l2_chain_spec)?,
+            l2_init_block,
+            l2_input,
+            l2_fini_block,
+        )?;
+        Ok((init, extra))
+    })
+}
+
+/// Loads the L1 and L2 data of `l2_block_no` and runs the structural and anchor checks,
+/// without executing the block: no state is read beyond the signal service proofs.
+#[allow(clippy::too_many_arguments)]
+pub fn check_taiko_data(
+    l1_cache_path: Option<String>,
+    l1_chain_spec: ChainSpec,
+    l1_rpc_url: Option<String>,
+    prover: Address,
+    l2_cache_path: Option<String>,
+    l2_chain_spec: ChainSpec,
+    l2_rpc_url: Option<String>,
+    l2_block_no: u64,
+    graffiti: B256,
+    rpc_options: RpcOptions,
+) -> Result<TaikoExtra> {
+    let rpc_options = rpc_options.start_block_fetch();
+    retry_inconsistent(&rpc_options, |rpc_options| {
+        let (_, _, _, _, extra) = prepare_data(
+            l1_cache_path.clone(),
+            l1_chain_spec.clone(),
+            l1_rpc_url.clone(),
+            prover,
+            l2_cache_path.clone(),
+            &l2_chain_spec,
+            l2_rpc_url.clone(),
+            l2_block_no,
+            graffiti,
+            rpc_options.clone(),
+        )?;
+        Ok(extra)
+    })
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn prepare_data(
+    l1_cache_path: Option<String>,
+    _l1_chain_spec: ChainSpec,
+    l1_rpc_url: Option<String>,
+    prover: Address,
+    l2_cache_path: Option<String>,
+    l2_chain_spec: &ChainSpec,
+    l2_rpc_url: Option<String>,
+    l2_block_no: u64,
+    graffiti: B256,
+    rpc_options: RpcOptions,
+) -> Result<(
+    Box<dyn Provider>,
+    Block<H256>,
+    Input<EthereumTxEssence>,
+    Block<EthersTransaction>,
+    TaikoExtra,
+)> {
+    let (l2_provider, l2_init_block, mut l2_fini_block, l2_signal_root, mut l2_input) = fetch_data(
         "L2",
         l2_cache_path,
         l2_rpc_url,
+        &rpc_options,
         l2_block_no,
         l2_chain_spec.l2_signal_service.unwrap(),
         Layer::L2,
     )?;
+    // fails before the L1 data is fetched
+    if let Some(max_txs) = rpc_options.max_txs_per_block {
+        check_tx_count(l2_block_no, &l2_fini_block, max_txs)?;
+    }
+    let chain_spec_mismatch = check_chain_spec(l2_chain_spec, l2_block_no, &l2_fini_block)?;
+    apply_consensus_overrides(
+        l2_chain_spec.consensus_overrides(),
+        &mut l2_input,
+        &mut l2_fini_block,
+    );
     // Get anchor call parameters
     let anchorCall {
         l1Hash: anchor_l1_hash,
@@ -235,6 +486,7 @@ pub fn get_taiko_initial_data<N: NetworkStrategyBundle<TxEssence = EthereumTxEss
         "L1",
         l1_cache_path,
         l1_rpc_url,
+        &rpc_options,
         l1_block_no,
         l2_chain_spec.l1_signal_service.unwrap(),
         Layer::L1,
@@ -250,6 +502,15 @@ pub fn get_taiko_initial_data<N: NetworkStrategyBundle<TxEssence = EthereumTxEss
         block_no: l1_block_no + 1,
     })?;
 
+    let blob_sidecar = if block_metadata.meta.blobUsed {
+        Some(l1_provider.get_blob(&BlobQuery {
+            timestamp: l1_next_block.timestamp.as_u64(),
+            versioned_hash: H256::from(block_metadata.meta.blobHash.0),
+        })?)
+    } else {
+        None
+    };
+
     // save l1 data
     l1_provider.save()?;
 
@@ -257,6 +518,17 @@ pub fn get_taiko_initial_data<N: NetworkStrategyBundle<TxEssence = EthereumTxEss
         params: _,
         txList: l2_tx_list,
     } = decode_propose_block_call_args(&propose_tx.input)?;
+    let (l2_tx_list, l2_blob_hash) = match blob_sidecar {
+        Some(sidecar) => {
+            let kzg_settings = load_kzg_settings(rpc_options.kzg_trusted_setup.as_deref())?;
+            verify_kzg_proof(&sidecar, &kzg_settings)?;
+            (
+                blob_tx_list(&block_metadata.meta, &sidecar)?,
+                Some(block_metadata.meta.blobHash),
+            )
+        }
+        None => (l2_tx_list, None),
+    };
 
     // 1. check l2 parent gas used
     if l2_init_block.gas_used != U256::from(l2_parent_gas_used) {
@@ -287,6 +559,7 @@ pub fn get_taiko_initial_data<N: NetworkStrategyBundle<TxEssence = EthereumTxEss
         l1_hash: anchor_l1_hash,
         l1_height: l1_block_no,
         l2_tx_list,
+        l2_blob_hash,
         prover,
         graffiti,
         l1_signal_root,
@@ -294,18 +567,282 @@ pub fn get_taiko_initial_data<N: NetworkStrategyBundle<TxEssence = EthereumTxEss
         l2_withdrawals: l2_input.withdrawals.clone(),
         block_proposed: block_metadata,
         l1_next_block,
+        chain_spec_mismatch,
     };
 
     // rebuild transaction list by tx_list from l1 contract
+    let l2_chain_spec = extra.resolved_chain_spec(l2_chain_spec)?;
     rebuild_and_precheck_block(&l2_chain_spec, &mut l2_fini_block, &extra)?;
 
-    // execute transactions and get states
-    let init = execute_data::<N>(
-        l2_provider,
+    Ok((l2_provider, l2_init_block, l2_input, l2_fini_block, extra))
+}
+
+/// Builds the witness of `l2_block_no` from the L1 and L2 cache files alone, without RPC
+/// access, the way the guest does.
+pub fn get_taiko_initial_data_from_cache<
+    N: NetworkStrategyBundle<TxEssence = EthereumTxEssence>,
+>(
+    l1_cache_path: String,
+    l2_cache_path: String,
+    l2_chain_spec: ChainSpec,
+    l2_block_no: u64,
+    prover: Address,
+    graffiti: B256,
+) -> Result<(Init<EthereumTxEssence>, TaikoExtra)> {
+    get_taiko_initial_data::<N>(
+        Some(l1_cache_path),
+        crate::consts::ETH_MAINNET_CHAIN_SPEC.clone(),
+        None,
+        prover,
+        Some(l2_cache_path),
         l2_chain_spec,
-        l2_init_block,
-        l2_input,
-        l2_fini_block,
-    )?;
-    Ok((init, extra))
+        None,
+        l2_block_no,
+        graffiti,
+        RpcOptions::default(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use revm::{
+        primitives::{SpecId, TransactTo},
+        EVM,
+    };
+    use zeth_primitives::{block::Header, trie::MptNode, uint, Bytes, U256};
+
+    use super::*;
+    use crate::{
+        consts::{get_taiko_chain_spec, AccountOverride},
+        initialization::MemDbInitStrategy,
+        mem_db::MemDb,
+        taiko::prepare::TaikoHeaderPrepStrategy,
+    };
+
+    #[test]
+    fn consensus_overrides_flow_into_the_witness() {
+        let mut input = Input::<EthereumTxEssence> {
+            parent_header: Default::default(),
+            beneficiary: Default::default(),
+            gas_limit: uint!(15_250_000_U256),
+            timestamp: Default::default(),
+            extra_data: Default::default(),
+            mix_hash: Default::default(),
+            transactions: vec![],
+            withdrawals: vec![],
+            parent_state_trie: Default::default(),
+            parent_storage: Default::default(),
+            contracts: vec![],
+            ancestor_headers: vec![],
+            base_fee_per_gas: uint!(7_U256),
+        };
+        let mut fini_block = Block::<EthersTransaction> {
+            number: Some(1.into()),
+            author: Some(H160::zero()),
+            logs_bloom: Some(Default::default()),
+            mix_hash: Some(H256::zero()),
+            nonce: Some(Default::default()),
+            base_fee_per_gas: Some(7.into()),
+            gas_limit: 15_250_000.into(),
+            ..Default::default()
+        };
+
+        // the values of the block data by default
+        apply_consensus_overrides(&ConsensusOverrides::default(), &mut input, &mut fini_block);
+        assert_eq!(input.base_fee_per_gas, uint!(7_U256));
+        assert_eq!(fini_block.gas_limit, 15_250_000.into());
+
+        let overrides = ConsensusOverrides {
+            base_fee_per_gas: Some(uint!(1_000_000_000_U256)),
+            gas_limit: Some(uint!(30_000_000_U256)),
+        };
+        apply_consensus_overrides(&overrides, &mut input, &mut fini_block);
+        assert_eq!(input.base_fee_per_gas, uint!(1_000_000_000_U256));
+        assert_eq!(input.gas_limit, uint!(30_000_000_U256));
+        let header = Header::try_from(fini_block).unwrap();
+        assert_eq!(header.base_fee_per_gas, uint!(1_000_000_000_U256));
+        assert_eq!(header.gas_limit, uint!(30_000_000_U256));
+
+        // the overridden gas limit only passes the checks of the overriding chain spec
+        let spec = get_taiko_chain_spec("testnet");
+        assert!(BlockBuilder::<MemDb, _>::new(&spec, input.clone())
+            .prepare_header::<TaikoHeaderPrepStrategy>()
+            .is_err());
+        let spec = spec.with_consensus_overrides(overrides);
+        let header = BlockBuilder::<MemDb, _>::new(&spec, input)
+            .prepare_header::<TaikoHeaderPrepStrategy>()
+            .unwrap()
+            .header
+            .unwrap();
+        assert_eq!(header.base_fee_per_gas, uint!(1_000_000_000_U256));
+        assert_eq!(header.gas_limit, uint!(30_000_000_U256));
+    }
+
+    #[test]
+    fn block_over_the_tx_cap_is_rejected() {
+        let block = Block::<EthersTransaction> {
+            transactions: vec![Default::default(); 3],
+            ..Default::default()
+        };
+        check_tx_count(7, &block, 3).unwrap();
+        let err = check_tx_count(7, &block, 2).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Block 7 has 3 transactions, more than the maximum of 2 (max_txs_per_block)"
+        );
+    }
+
+    #[test]
+    fn overridden_code_appears_in_the_witness() {
+        let address = Address::repeat_byte(0x01);
+        let original = Bytes::from(vec![0x60, 0x00]);
+        let overridden = Bytes::from(vec![0x60, 0x01, 0x60, 0x00, 0x55]);
+        let mut state_trie = MptNode::default();
+        state_trie
+            .insert_rlp(
+                &keccak(address),
+                StateAccount {
+                    code_hash: keccak(&original).into(),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        let mut input = Input::<EthereumTxEssence> {
+            parent_header: Header {
+                state_root: state_trie.hash(),
+                ..Default::default()
+            },
+            beneficiary: Default::default(),
+            gas_limit: Default::default(),
+            timestamp: Default::default(),
+            extra_data: Default::default(),
+            mix_hash: Default::default(),
+            transactions: vec![],
+            withdrawals: vec![],
+            parent_state_trie: state_trie,
+            parent_storage: [(address, (MptNode::default(), vec![]))]
+                .into_iter()
+                .collect(),
+            contracts: vec![original],
+            ancestor_headers: vec![],
+            base_fee_per_gas: Default::default(),
+        };
+        let original_root = input.parent_header.state_root;
+
+        let overrides = SystemOverrides {
+            code: [(address, overridden.clone())].into(),
+        };
+        apply_system_overrides(&overrides, &mut input).unwrap();
+        assert!(input.contracts.contains(&overridden));
+        assert_ne!(input.parent_header.state_root, original_root);
+
+        // the witness verifies against the overridden pre-state root
+        let spec = get_taiko_chain_spec("testnet").with_system_overrides(overrides);
+        let builder = BlockBuilder::<MemDb, _>::new(&spec, input)
+            .initialize_database::<MemDbInitStrategy>()
+            .unwrap();
+        let account = &builder.db().unwrap().accounts[&address];
+        assert_eq!(account.info.code.as_ref().unwrap().bytecode, overridden);
+
+        // accounts the block did not touch cannot be overridden
+        let mut input = builder.input;
+        let overrides = SystemOverrides {
+            code: [(Address::repeat_byte(0x02), overridden)].into(),
+        };
+        assert!(apply_system_overrides(&overrides, &mut input).is_err());
+    }
+
+    /// Sends `value` from `sender` to itself, the sender earning the fees.
+    fn self_transfer(db: MemDb, sender: Address, value: u64) -> bool {
+        let mut evm = EVM::new();
+        evm.env.cfg.spec_id = SpecId::SHANGHAI;
+        evm.env.block.coinbase = sender;
+        evm.env.tx.caller = sender;
+        evm.env.tx.transact_to = TransactTo::Call(sender);
+        evm.env.tx.value = U256::from(value);
+        evm.env.tx.gas_limit = 21_000;
+        evm.database(db);
+        evm.transact().is_ok()
+    }
+
+    #[test]
+    fn overridden_balance_is_executed_from_the_witness() {
+        let address = Address::repeat_byte(0x01);
+        let slot = U256::from(3);
+        let mut state_trie = MptNode::default();
+        state_trie
+            .insert_rlp(&keccak(address), StateAccount::default())
+            .unwrap();
+        let mut input = Input::<EthereumTxEssence> {
+            parent_header: Header {
+                state_root: state_trie.hash(),
+                ..Default::default()
+            },
+            beneficiary: Default::default(),
+            gas_limit: Default::default(),
+            timestamp: Default::default(),
+            extra_data: Default::default(),
+            mix_hash: Default::default(),
+            transactions: vec![],
+            withdrawals: vec![],
+            parent_state_trie: state_trie,
+            parent_storage: [(address, (MptNode::default(), vec![slot]))]
+                .into_iter()
+                .collect(),
+            contracts: vec![],
+            ancestor_headers: vec![],
+            base_fee_per_gas: Default::default(),
+        };
+        let original_root = input.parent_header.state_root;
+        let spec = get_taiko_chain_spec("testnet");
+        let builder = BlockBuilder::<MemDb, _>::new(&spec, input.clone())
+            .initialize_database::<MemDbInitStrategy>()
+            .unwrap();
+        // the account cannot pay for the transfer
+        assert!(!self_transfer(builder.db.unwrap(), address, 1_000));
+
+        let overrides = StateOverrides {
+            accounts: [(
+                address,
+                AccountOverride {
+                    balance: Some(U256::from(1_000_000)),
+                    nonce: Some(4),
+                    state_diff: [(slot, U256::from(7))].into(),
+                },
+            )]
+            .into(),
+        };
+        apply_state_overrides(&overrides, &mut input).unwrap();
+        assert_ne!(input.parent_header.state_root, original_root);
+
+        // the witness verifies against the overridden pre-state root
+        let spec = spec.with_state_overrides(overrides);
+        let builder = BlockBuilder::<MemDb, _>::new(&spec, input)
+            .initialize_database::<MemDbInitStrategy>()
+            .unwrap();
+        let account = &builder.db().unwrap().accounts[&address];
+        assert_eq!(account.info.balance, U256::from(1_000_000));
+        assert_eq!(account.info.nonce, 4);
+        assert_eq!(account.storage[&slot], U256::from(7));
+        assert!(self_transfer(builder.db.unwrap(), address, 1_000));
+
+        // accounts and slots the block did not touch cannot be overridden
+        let mut input = builder.input;
+        let balance = AccountOverride {
+            balance: Some(U256::from(1)),
+            ..Default::default()
+        };
+        let overrides = StateOverrides {
+            accounts: [(Address::repeat_byte(0x02), balance)].into(),
+        };
+        assert!(apply_state_overrides(&overrides, &mut input).is_err());
+        let untouched = AccountOverride {
+            state_diff: [(U256::from(4), U256::from(1))].into(),
+            ..Default::default()
+        };
+        let overrides = StateOverrides {
+            accounts: [(address, untouched)].into(),
+        };
+        assert!(apply_state_overrides(&overrides, &mut input).is_err());
+    }
 }
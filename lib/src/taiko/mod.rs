@@ -1,3 +1,5 @@
+#[cfg(not(target_os = "zkvm"))]
+pub mod blob;
 pub mod block_builder;
 #[cfg(not(target_os = "zkvm"))]
 pub mod execute;
@@ -6,6 +8,8 @@ pub mod host;
 pub mod precheck;
 pub mod prepare;
 pub mod protocol_instance;
+#[cfg(not(target_os = "zkvm"))]
+pub mod spec_mismatch;
 pub mod utils;
 
 pub enum Layer {
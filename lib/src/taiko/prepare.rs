@@ -18,10 +18,15 @@ impl HeaderPrepStrategy for TaikoHeaderPrepStrategy {
         E: TxEssence,
     {
         // Validate gas limit
-        if block_builder.input.gas_limit != *BLOCK_GAS_LIMIT {
+        let gas_limit = block_builder
+            .chain_spec
+            .consensus_overrides()
+            .gas_limit
+            .unwrap_or(*BLOCK_GAS_LIMIT);
+        if block_builder.input.gas_limit != gas_limit {
             bail!(
                 "Invalid gas limit: expected == {}, got {}",
-                *BLOCK_GAS_LIMIT,
+                gas_limit,
                 block_builder.input.gas_limit,
             );
         }
@@ -7,10 +7,10 @@ use zeth_primitives::{
         deposits_hash, string_to_bytes32, BlockMetadata, EthDeposit, ProtocolInstance, Transition,
         ANCHOR_GAS_LIMIT,
     },
-    TxHash, U256,
+    Address, TxHash, U256,
 };
 
-use crate::taiko::host::TaikoExtra;
+use crate::{consts::VerifierCallLayout, taiko::host::TaikoExtra};
 
 pub fn assemble_protocol_instance(extra: &TaikoExtra, header: &Header) -> Result<ProtocolInstance> {
     let tx_list_hash = TxHash::from(keccak::keccak(extra.l2_tx_list.as_slice()));
@@ -27,7 +27,12 @@ pub fn assemble_protocol_instance(extra: &TaikoExtra, header: &Header) -> Result
     let extra_data = string_to_bytes32(&header.extra_data);
     //   meta.difficulty = meta.blobHash ^ bytes32(block.prevrandao * b.numBlocks *
     // block.number);
-    let block_hash = tx_list_hash;
+    let (blob_hash, blob_used, tx_list_byte_offset) = match extra.l2_blob_hash {
+        // the transaction list is a slice of the blob
+        Some(blob_hash) => (blob_hash, true, extra.block_proposed.meta.txListByteOffset),
+        None => (tx_list_hash, extra.l2_tx_list.is_empty(), 0u32),
+    };
+    let block_hash = blob_hash;
     let block_hash_h256: U256 = block_hash.into();
     let prevrando = if cfg!(feature = "pos") {
         from_ethers_h256(extra.l1_next_block.mix_hash.unwrap_or_default()).into()
@@ -49,7 +54,7 @@ pub fn assemble_protocol_instance(extra: &TaikoExtra, header: &Header) -> Result
         block_metadata: BlockMetadata {
             l1Hash: extra.l1_hash,
             difficulty: difficulty.into(),
-            blobHash: tx_list_hash,
+            blobHash: blob_hash,
             extraData: extra_data.into(),
             depositsHash: deposits_hash,
             coinbase: header.beneficiary,
@@ -57,10 +62,10 @@ pub fn assemble_protocol_instance(extra: &TaikoExtra, header: &Header) -> Result
             gasLimit: (gas_limit - ANCHOR_GAS_LIMIT) as u32,
             timestamp: header.timestamp.try_into().unwrap(),
             l1Height: extra.l1_height,
-            txListByteOffset: 0u32,
+            txListByteOffset: tx_list_byte_offset,
             txListByteSize: extra.l2_tx_list.len() as u32,
             minTier: extra.block_proposed.meta.minTier,
-            blobUsed: extra.l2_tx_list.is_empty(),
+            blobUsed: blob_used,
             parentMetaHash: extra.block_proposed.meta.parentMetaHash,
         },
         prover: extra.prover,
@@ -69,8 +74,22 @@ pub fn assemble_protocol_instance(extra: &TaikoExtra, header: &Header) -> Result
     {
         use zeth_primitives::taiko::assert_pi_and_bp;
         assert_pi_and_bp(&pi, &extra.block_proposed)?;
-        println!("Protocol instance Transition: {:?}", pi.transition);
-        println!("Protocol instance Metahash: {}", pi.meta_hash());
+        tracing::info!("Protocol instance Transition: {:?}", pi.transition);
+        tracing::info!("Protocol instance Metahash: {}", pi.meta_hash());
     }
     Ok(pi)
 }
+
+/// The calldata of the on-chain call submitting `proof`, the sgx proof of `pi` signed by
+/// `new_instance`, laid out as `layout`.
+pub fn verifier_calldata(
+    layout: VerifierCallLayout,
+    pi: &ProtocolInstance,
+    new_instance: Address,
+    proof: Vec<u8>,
+) -> Vec<u8> {
+    match layout {
+        VerifierCallLayout::ProveBlock { tier } => pi.prove_block_calldata(tier, proof),
+        VerifierCallLayout::VerifyProof { .. } => pi.verify_proof_calldata(new_instance, proof),
+    }
+}
@@ -0,0 +1,178 @@
+//! Checks that the block data is of the chain and of the fork of the chain spec, so that
+//! a disagreement fails early rather than as a late mismatch of the built block, and
+//! handles it as the `ChainSpecMismatchPolicy` of the spec says.
+use anyhow::{bail, Result};
+use ethers_core::types::{Block, Transaction};
+use revm::primitives::SpecId;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use zeth_primitives::ChainId;
+
+use crate::consts::{ChainSpec, ChainSpecMismatchPolicy};
+
+/// How the block data disagrees with the chain spec, and how it was handled.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChainSpecMismatch {
+    pub policy: ChainSpecMismatchPolicy,
+    pub config_chain_id: ChainId,
+    /// chain id of the transactions, when not the one of the spec
+    pub data_chain_id: Option<ChainId>,
+    pub config_fork: SpecId,
+    /// fork implied by the header, when not the one of the spec
+    pub data_fork: Option<SpecId>,
+}
+
+impl ChainSpecMismatch {
+    /// The spec the block is built with: the one implied by the data with the `warn`
+    /// policy, the configured one otherwise.
+    pub fn resolve(&self, spec: ChainSpec) -> Result<ChainSpec> {
+        if self.policy != ChainSpecMismatchPolicy::Warn {
+            return Ok(spec);
+        }
+        let mut spec = spec;
+        if let Some(chain_id) = self.data_chain_id {
+            spec = spec.with_chain_id_override(chain_id)?;
+        }
+        if let Some(fork) = self.data_fork {
+            spec = spec.with_fork(fork);
+        }
+        // the mismatch is resolved, the policy of the data spec is not checked again
+        Ok(spec.with_mismatch_policy(ChainSpecMismatchPolicy::Warn))
+    }
+
+    fn describe(&self) -> String {
+        let mut mismatches = vec![];
+        if let Some(chain_id) = self.data_chain_id {
+            mismatches.push(format!(
+                "chain id {} of the data, {} in the chain spec",
+                chain_id, self.config_chain_id
+            ));
+        }
+        if let Some(fork) = self.data_fork {
+            mismatches.push(format!(
+                "fork {:?} of the data, {:?} in the chain spec",
+                fork, self.config_fork
+            ));
+        }
+        mismatches.join(", ")
+    }
+}
+
+/// The fork the header fields of `block` imply, at the granularity the data tells apart:
+/// the withdrawals came with Shanghai.
+fn data_fork(block: &Block<Transaction>) -> SpecId {
+    match block.withdrawals_root {
+        Some(_) => SpecId::SHANGHAI,
+        None => SpecId::MERGE,
+    }
+}
+
+/// Compares the chain id of the transactions and the fork of the header of `block` with
+/// `spec`. Fails with the `error` policy, logs the decision with the others.
+pub fn check_chain_spec(
+    spec: &ChainSpec,
+    block_no: u64,
+    block: &Block<Transaction>,
+) -> Result<Option<ChainSpecMismatch>> {
+    let data_chain_id = block
+        .transactions
+        .iter()
+        .find_map(|tx| tx.chain_id)
+        .map(|chain_id| chain_id.as_u64())
+        .filter(|chain_id| *chain_id != spec.chain_id());
+    let config_fork = spec.spec_id(block_no);
+    let data_fork = Some(data_fork(block)).filter(|fork| {
+        let config_has_withdrawals = SpecId::enabled(config_fork, SpecId::SHANGHAI);
+        config_has_withdrawals != SpecId::enabled(*fork, SpecId::SHANGHAI)
+    });
+    if data_chain_id.is_none() && data_fork.is_none() {
+        return Ok(None);
+    }
+    let mismatch = ChainSpecMismatch {
+        policy: spec.mismatch_policy(),
+        config_chain_id: spec.chain_id(),
+        data_chain_id,
+        config_fork,
+        data_fork,
+    };
+    match mismatch.policy {
+        ChainSpecMismatchPolicy::Error => bail!(
+            "The data of block {} disagrees with the chain spec: {}",
+            block_no,
+            mismatch.describe()
+        ),
+        ChainSpecMismatchPolicy::Warn => warn!(
+            "The data of block {} disagrees with the chain spec, building it with the values \
+             of the data: {}",
+            block_no,
+            mismatch.describe()
+        ),
+        ChainSpecMismatchPolicy::Override => warn!(
+            "The data of block {} disagrees with the chain spec, building it with the values \
+             of the chain spec: {}",
+            block_no,
+            mismatch.describe()
+        ),
+    }
+    Ok(Some(mismatch))
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers_core::types::{H256, U256};
+
+    use super::*;
+    use crate::consts::get_taiko_chain_spec;
+
+    /// A block of chain `chain_id`, with withdrawals from Shanghai on.
+    fn block(chain_id: u64, shanghai: bool) -> Block<Transaction> {
+        Block {
+            transactions: vec![Transaction {
+                chain_id: Some(U256::from(chain_id)),
+                ..Default::default()
+            }],
+            withdrawals_root: shanghai.then(H256::zero),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn mismatched_data_is_handled_as_configured() {
+        let spec = get_taiko_chain_spec("testnet");
+        let chain_id = spec.chain_id();
+        assert_eq!(
+            check_chain_spec(&spec, 1, &block(chain_id, true)).unwrap(),
+            None
+        );
+
+        // error rejects both kinds of mismatch
+        let err = check_chain_spec(&spec, 1, &block(chain_id + 1, true)).unwrap_err();
+        assert!(err.to_string().contains("chain id"));
+        let err = check_chain_spec(&spec, 1, &block(chain_id, false)).unwrap_err();
+        assert!(err.to_string().contains("fork MERGE"));
+
+        // warn builds with the values of the data
+        let warn = spec
+            .clone()
+            .with_mismatch_policy(ChainSpecMismatchPolicy::Warn);
+        let mismatch = check_chain_spec(&warn, 1, &block(chain_id + 1, false))
+            .unwrap()
+            .unwrap();
+        assert_eq!(mismatch.data_chain_id, Some(chain_id + 1));
+        assert_eq!(mismatch.data_fork, Some(SpecId::MERGE));
+        let resolved = mismatch.resolve(warn).unwrap();
+        assert_eq!(resolved.chain_id(), chain_id + 1);
+        assert_eq!(resolved.spec_id(1), SpecId::MERGE);
+
+        // override builds with the values of the chain spec
+        let overridden = spec.with_mismatch_policy(ChainSpecMismatchPolicy::Override);
+        let mismatch = check_chain_spec(&overridden, 1, &block(chain_id + 1, false))
+            .unwrap()
+            .unwrap();
+        assert_eq!(mismatch.policy, ChainSpecMismatchPolicy::Override);
+        let resolved = mismatch.resolve(overridden).unwrap();
+        assert_eq!(resolved.chain_id(), chain_id);
+        assert_eq!(resolved.spec_id(1), SpecId::SHANGHAI);
+    }
+}
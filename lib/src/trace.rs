@@ -0,0 +1,276 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The execution trace of the transactions of a block, step by step, to compare with
+//! the trace of a reference client when debugging a consensus mismatch. The EVM of the
+//! block builder is only inspected when given an [ExecutionTracer], and not at all
+//! without the `trace` feature.
+
+use revm::{
+    interpreter::{opcode::OPCODE_JUMPMAP, Interpreter},
+    Database, EVMData, Inspector,
+};
+use serde::Serialize;
+use zeth_primitives::{Bytes, U256};
+
+/// A step of the interpreter, the stack and the memory it changed with their detail
+/// recorded.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TraceStep {
+    pub pc: usize,
+    pub op: u8,
+    pub op_name: &'static str,
+    /// the call depth
+    pub depth: u64,
+    /// the gas left before the step
+    pub gas: u64,
+    pub gas_cost: u64,
+    /// the stack items popped
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stack_pop: Option<usize>,
+    /// the stack items pushed, the top last
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stack_push: Option<Vec<U256>>,
+    /// the memory size after the step
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_size: Option<usize>,
+    /// the memory the step wrote
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_write: Option<MemoryWrite>,
+}
+
+/// The bytes of memory from `offset` on a step wrote.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MemoryWrite {
+    pub offset: usize,
+    pub data: Bytes,
+}
+
+/// The steps of a transaction.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct TxTrace {
+    pub tx: usize,
+    pub steps: Vec<TraceStep>,
+}
+
+/// A step being executed, with the state it started from.
+#[derive(Debug, Clone)]
+struct StartedStep {
+    /// the index of the step in its transaction
+    index: usize,
+    stack: Vec<U256>,
+    memory: Vec<u8>,
+}
+
+/// Records the steps of the transactions executed, `max_steps` of them at most, the
+/// changes of the stack with `stack` and the ones of the memory with `memory`.
+#[derive(Debug, Clone, Default)]
+pub struct ExecutionTracer {
+    max_steps: usize,
+    stack: bool,
+    memory: bool,
+    steps: usize,
+    truncated: bool,
+    transactions: Vec<TxTrace>,
+    /// the steps of the calls being executed, the innermost last; `None` for the ones
+    /// past `max_steps`
+    started: Vec<Option<StartedStep>>,
+}
+
+impl ExecutionTracer {
+    pub fn new(max_steps: usize, stack: bool, memory: bool) -> Self {
+        ExecutionTracer {
+            max_steps,
+            stack,
+            memory,
+            ..Default::default()
+        }
+    }
+
+    /// Records the steps from now on as the ones of transaction `tx`.
+    pub fn begin_tx(&mut self, tx: usize) {
+        self.started.clear();
+        self.transactions.push(TxTrace { tx, steps: vec![] });
+    }
+
+    /// The transactions traced, in their order of execution.
+    pub fn transactions(&self) -> &[TxTrace] {
+        &self.transactions
+    }
+
+    /// Whether steps were dropped past `max_steps`.
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+}
+
+impl<DB: Database> Inspector<DB> for ExecutionTracer {
+    fn step(&mut self, interp: &mut Interpreter, data: &mut EVMData<'_, DB>) {
+        let Some(tx) = self.transactions.last_mut() else {
+            return;
+        };
+        if self.steps == self.max_steps {
+            self.truncated = true;
+            self.started.push(None);
+            return;
+        }
+        self.steps += 1;
+        let op = interp.current_opcode();
+        tx.steps.push(TraceStep {
+            pc: interp.program_counter(),
+            op,
+            op_name: OPCODE_JUMPMAP[op as usize].unwrap_or("UNKNOWN"),
+            depth: data.journaled_state.depth(),
+            gas: interp.gas.remaining(),
+            gas_cost: 0,
+            stack_pop: None,
+            stack_push: None,
+            memory_size: None,
+            memory_write: None,
+        });
+        self.started.push(Some(StartedStep {
+            index: tx.steps.len() - 1,
+            stack: if self.stack {
+                interp.stack.data().clone()
+            } else {
+                vec![]
+            },
+            memory: if self.memory {
+                interp.memory.data().clone()
+            } else {
+                vec![]
+            },
+        }));
+    }
+
+    fn step_end(&mut self, interp: &mut Interpreter, _data: &mut EVMData<'_, DB>) {
+        let Some(Some(started)) = self.started.pop() else {
+            return;
+        };
+        let Some(step) = self
+            .transactions
+            .last_mut()
+            .and_then(|tx| tx.steps.get_mut(started.index))
+        else {
+            return;
+        };
+        step.gas_cost = step.gas.saturating_sub(interp.gas.remaining());
+        if self.stack {
+            let stack = interp.stack.data();
+            let kept = common_prefix(&started.stack, stack);
+            step.stack_pop = Some(started.stack.len() - kept);
+            step.stack_push = Some(stack[kept..].to_vec());
+        }
+        if self.memory {
+            let memory = interp.memory.data();
+            step.memory_size = Some(memory.len());
+            step.memory_write = memory_write(&started.memory, memory);
+        }
+    }
+}
+
+fn common_prefix<T: PartialEq>(before: &[T], after: &[T]) -> usize {
+    before
+        .iter()
+        .zip(after)
+        .take_while(|(before, after)| before == after)
+        .count()
+}
+
+/// The range of `after` which differs from `before`, the memory only growing.
+fn memory_write(before: &[u8], after: &[u8]) -> Option<MemoryWrite> {
+    let byte = |memory: &[u8], i: usize| memory.get(i).copied().unwrap_or_default();
+    let first = (0..after.len()).find(|&i| byte(before, i) != after[i])?;
+    let last = (first..after.len())
+        .rev()
+        .find(|&i| byte(before, i) != after[i])?;
+    Some(MemoryWrite {
+        offset: first,
+        data: Bytes::copy_from_slice(&after[first..=last]),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use revm::{
+        primitives::{AccountInfo, Bytecode, SpecId, TransactTo},
+        EVM,
+    };
+    use zeth_primitives::Address;
+
+    use super::*;
+    use crate::mem_db::MemDb;
+
+    /// Calls a contract storing 2 + 3 in memory, the way the block builder executes the
+    /// first transaction of a block.
+    fn trace_tx(tracer: &mut ExecutionTracer) {
+        let sender = Address::repeat_byte(0x01);
+        let contract = Address::repeat_byte(0x02);
+        // PUSH1 2 PUSH1 3 ADD PUSH1 0 MSTORE STOP
+        let code = Bytes::from_static(&[0x60, 0x02, 0x60, 0x03, 0x01, 0x60, 0x00, 0x52, 0x00]);
+        let mut db = MemDb::default();
+        db.insert_account_info(sender, AccountInfo::default());
+        let bytecode = Bytecode::new_raw(code);
+        db.insert_account_info(
+            contract,
+            AccountInfo::new(U256::ZERO, 0, bytecode.hash_slow(), bytecode),
+        );
+        let mut evm = EVM::new();
+        evm.env.cfg.spec_id = SpecId::SHANGHAI;
+        evm.env.block.coinbase = sender;
+        evm.env.tx.caller = sender;
+        evm.env.tx.transact_to = TransactTo::Call(contract);
+        evm.env.tx.gas_limit = 100_000;
+        evm.database(db);
+        tracer.begin_tx(0);
+        assert!(evm.inspect(&mut *tracer).unwrap().result.is_success());
+    }
+
+    #[test]
+    fn the_trace_of_a_transaction_follows_its_opcodes() {
+        let mut tracer = ExecutionTracer::new(100, true, true);
+        trace_tx(&mut tracer);
+        let [tx] = tracer.transactions() else {
+            panic!("{:?}", tracer.transactions());
+        };
+        let op_names: Vec<_> = tx.steps.iter().map(|step| step.op_name).collect();
+        assert_eq!(
+            op_names,
+            ["PUSH1", "PUSH1", "ADD", "PUSH1", "MSTORE", "STOP"]
+        );
+        assert!(!tracer.truncated());
+
+        let add = &tx.steps[2];
+        assert_eq!((add.pc, add.gas_cost), (4, 3));
+        assert_eq!(add.stack_pop, Some(2));
+        assert_eq!(add.stack_push, Some(vec![U256::from(5)]));
+        let mstore = &tx.steps[4];
+        assert_eq!(mstore.memory_size, Some(32));
+        let write = mstore.memory_write.as_ref().unwrap();
+        assert_eq!((write.offset, &write.data[..]), (31, &[5u8][..]));
+
+        // bounded by the steps, without the stack and the memory
+        let mut tracer = ExecutionTracer::new(3, false, false);
+        trace_tx(&mut tracer);
+        let steps = &tracer.transactions()[0].steps;
+        assert_eq!(steps.len(), 3);
+        assert_eq!(steps[2].op_name, "ADD");
+        assert_eq!(
+            (steps[2].stack_push.as_ref(), steps[2].memory_size),
+            (None, None)
+        );
+        assert!(tracer.truncated());
+    }
+}
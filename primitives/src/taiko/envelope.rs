@@ -0,0 +1,500 @@
+use alloy_primitives::{Address, Bytes, B256, U256};
+use anyhow::{bail, Context, Result};
+use k256::{
+    ecdsa::{RecoveryId, Signature as K256Signature, VerifyingKey as K256VerifyingKey},
+    elliptic_curve::sec1::ToEncodedPoint,
+    PublicKey as K256PublicKey,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{keccak::keccak, signature::TxSignature};
+
+/// Separates the envelope signatures from the other signatures of the instance key.
+const ENVELOPE_DOMAIN: &[u8] = b"raiko proof envelope";
+/// Length of the sgx proofs: 4 bytes of instance id, 20 of instance address and 65 of
+/// signature.
+pub const SGX_PROOF_LEN: usize = 89;
+/// Offset of the mrEnclave measurement in the SGX quotes.
+pub const QUOTE_MR_ENCLAVE_OFFSET: usize = 112;
+/// Offset of the report data in the SGX quotes, laid out by the chain spec, the instance
+/// address the quote attests followed by zeros by default.
+pub const QUOTE_REPORT_DATA_OFFSET: usize = 368;
+pub const QUOTE_REPORT_DATA_SIZE: usize = 64;
+/// The caveat of the quote checks which pass: they compare fields of the quote, whose
+/// signature and certificate chain are not verified.
+const QUOTE_NOT_AUTHENTICATED: &str = "the quote is not authenticated";
+
+/// The checks of [ProofEnvelope::verification_report].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum EnvelopeCheck {
+    /// the envelope is signed by its signer
+    Signature,
+    /// the sgx proof is the signature of the public inputs by the signer
+    PublicInputs,
+    /// the report data of the quote is the one of the signer, the quote itself not being
+    /// authenticated
+    QuoteConsistency,
+    /// the mrEnclave of the quote is the expected one, the quote not being authenticated
+    Measurement,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CheckStatus {
+    Passed,
+    Failed,
+    /// nothing to check against
+    Skipped,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckResult {
+    pub check: EnvelopeCheck,
+    pub status: CheckStatus,
+    /// why the check failed or was skipped, or what it left unverified when it passed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// The outcome of each check of an envelope, with what was verified.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerificationReport {
+    pub checks: Vec<CheckResult>,
+    /// keccak256 of the compressed public key which signed the envelope
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<B256>,
+    /// the mrEnclave read from the quote, unauthenticated
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mr_enclave: Option<B256>,
+}
+
+impl VerificationReport {
+    /// The first failed check, `None` if the envelope is valid.
+    pub fn failure(&self) -> Option<&CheckResult> {
+        self.checks
+            .iter()
+            .find(|check| check.status == CheckStatus::Failed)
+    }
+
+    fn push(&mut self, check: EnvelopeCheck, result: Result<(), String>) {
+        self.checks.push(CheckResult {
+            check,
+            status: match result {
+                Ok(()) => CheckStatus::Passed,
+                Err(_) => CheckStatus::Failed,
+            },
+            reason: result.err(),
+        });
+    }
+
+    /// Pushes a check of the quote, with the caveat of an unauthenticated quote once
+    /// passed.
+    fn push_quote_check(&mut self, check: EnvelopeCheck, result: Result<(), String>) {
+        let passed = result.is_ok();
+        self.push(check, result);
+        if passed {
+            if let Some(last) = self.checks.last_mut() {
+                last.reason = Some(QUOTE_NOT_AUTHENTICATED.to_owned());
+            }
+        }
+    }
+
+    fn skip(&mut self, check: EnvelopeCheck, reason: &str) {
+        self.checks.push(CheckResult {
+            check,
+            status: CheckStatus::Skipped,
+            reason: Some(reason.to_owned()),
+        });
+    }
+}
+
+/// A proof as delivered by the prover, signed with the key of the SGX instance which
+/// produced it. Authenticates the delivery, independently of the transport and of the
+/// attestation within the proof.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProofEnvelope {
+    pub proof: Bytes,
+    /// hash of the protocol instance the proof is for
+    pub public_inputs: B256,
+    /// when the proof was signed, in seconds since the unix epoch
+    pub timestamp: u64,
+    /// address of the instance key
+    pub signer: Address,
+    pub signature: TxSignature,
+}
+
+impl ProofEnvelope {
+    /// The hash signed by the instance key.
+    pub fn signing_hash(proof: &[u8], public_inputs: B256, timestamp: u64) -> B256 {
+        let mut data = ENVELOPE_DOMAIN.to_vec();
+        data.extend(proof);
+        data.extend(public_inputs.as_slice());
+        data.extend(timestamp.to_be_bytes());
+        keccak(data).into()
+    }
+
+    /// Recovers the address of the key which signed the envelope.
+    pub fn recover_signer(&self) -> Result<Address> {
+        let hash = Self::signing_hash(&self.proof, self.public_inputs, self.timestamp);
+        recover_address(hash, &self.signature)
+    }
+
+    /// Runs every check of the envelope rather than stopping at the first failure. The
+    /// `quote` of the instance, if given, must hold the report data of the signer,
+    /// composed by `report_data` from its address and fingerprint, or else the signer
+    /// address followed by zeros. Its mrEnclave must be `expected_mr_enclave`, if
+    /// given. Only the consistency of the quote with the envelope is checked: its
+    /// signature and certificate chain are not verified, a valid attestation must be
+    /// checked with DCAP.
+    pub fn verification_report(
+        &self,
+        quote: Option<&[u8]>,
+        report_data: Option<&dyn Fn(Address, B256) -> Result<[u8; QUOTE_REPORT_DATA_SIZE]>>,
+        expected_mr_enclave: Option<B256>,
+    ) -> VerificationReport {
+        let mut report = VerificationReport {
+            checks: vec![],
+            fingerprint: None,
+            mr_enclave: None,
+        };
+        let hash = Self::signing_hash(&self.proof, self.public_inputs, self.timestamp);
+        let signature = self.verify();
+        if signature.is_ok() {
+            report.fingerprint = recover_public_key(hash, &self.signature)
+                .ok()
+                .map(|key| keccak(key.to_encoded_point(true).as_bytes()).into());
+        }
+        report.push(
+            EnvelopeCheck::Signature,
+            signature.map_err(|err| err.to_string()),
+        );
+
+        match self.proof.len() {
+            SGX_PROOF_LEN => report.push(
+                EnvelopeCheck::PublicInputs,
+                self.check_sgx_proof().map_err(|err| err.to_string()),
+            ),
+            _ => report.skip(EnvelopeCheck::PublicInputs, "not an sgx proof"),
+        }
+
+        let Some(quote) = quote else {
+            report.skip(EnvelopeCheck::QuoteConsistency, "no quote given");
+            match expected_mr_enclave {
+                Some(_) => report.push(
+                    EnvelopeCheck::Measurement,
+                    Err("no quote to read the mrEnclave of".to_owned()),
+                ),
+                None => report.skip(EnvelopeCheck::Measurement, "no mrEnclave expected"),
+            }
+            return report;
+        };
+        let field = |offset: usize, len: usize| quote.get(offset..offset + len);
+        report.mr_enclave = field(QUOTE_MR_ENCLAVE_OFFSET, 32).map(B256::from_slice);
+        let expected = match report_data {
+            Some(report_data) => report_data(self.signer, report.fingerprint.unwrap_or_default())
+                .map_err(|err| format!("the report data of the signer is unknown: {}", err)),
+            None => {
+                let mut expected = [0; QUOTE_REPORT_DATA_SIZE];
+                expected[..20].copy_from_slice(self.signer.as_slice());
+                Ok(expected)
+            }
+        };
+        report.push_quote_check(
+            EnvelopeCheck::QuoteConsistency,
+            expected.and_then(|expected| {
+                match field(QUOTE_REPORT_DATA_OFFSET, QUOTE_REPORT_DATA_SIZE) {
+                    Some(attested) if attested == expected.as_slice() => Ok(()),
+                    Some(attested) => Err(format!(
+                        "the report data of the quote is {}, not the one of the signer {}",
+                        Bytes::copy_from_slice(attested),
+                        self.signer
+                    )),
+                    None => Err(format!("the quote is too short, {} bytes", quote.len())),
+                }
+            }),
+        );
+        match (expected_mr_enclave, report.mr_enclave) {
+            (None, _) => report.skip(EnvelopeCheck::Measurement, "no mrEnclave expected"),
+            (Some(expected), Some(measured)) if expected == measured => {
+                report.push_quote_check(EnvelopeCheck::Measurement, Ok(()))
+            }
+            (Some(expected), Some(measured)) => report.push(
+                EnvelopeCheck::Measurement,
+                Err(format!(
+                    "the mrEnclave of the quote is {}, expected {}",
+                    measured, expected
+                )),
+            ),
+            (Some(_), None) => report.push(
+                EnvelopeCheck::Measurement,
+                Err("the quote is too short to hold an mrEnclave".to_owned()),
+            ),
+        }
+        report
+    }
+
+    /// Checks that the sgx proof in the envelope is the signature of its public inputs by
+    /// the instance it names, the one which signed the envelope.
+    pub fn verify_sgx_proof(&self) -> Result<()> {
+        self.check_sgx_proof()?;
+        self.verify()
+    }
+
+    fn check_sgx_proof(&self) -> Result<()> {
+        if self.proof.len() != SGX_PROOF_LEN {
+            bail!(
+                "sgx proofs have {} bytes, got {}",
+                SGX_PROOF_LEN,
+                self.proof.len()
+            );
+        }
+        let instance = Address::from_slice(&self.proof[4..24]);
+        let signature = TxSignature {
+            r: U256::from_be_slice(&self.proof[24..56]),
+            s: U256::from_be_slice(&self.proof[56..88]),
+            v: self.proof[88] as u64,
+        };
+        let recovered = recover_address(self.public_inputs, &signature)?;
+        if recovered != instance {
+            bail!(
+                "proof signed by {}, not by instance {}",
+                recovered,
+                instance
+            );
+        }
+        if instance != self.signer {
+            bail!("proof of instance {}, signed by {}", instance, self.signer);
+        }
+        Ok(())
+    }
+
+    /// Checks that the envelope is signed by `signer`, i.e. that neither the proof, its
+    /// public inputs nor its timestamp were altered.
+    pub fn verify(&self) -> Result<()> {
+        let recovered = self.recover_signer()?;
+        if recovered != self.signer {
+            bail!("envelope signed by {}, not by {}", recovered, self.signer);
+        }
+        Ok(())
+    }
+}
+
+/// Recovers the address of the key which signed `hash`.
+fn recover_address(hash: B256, signature: &TxSignature) -> Result<Address> {
+    let public_key = recover_public_key(hash, signature)?.to_encoded_point(false);
+    let hash = keccak(&public_key.as_bytes()[1..]);
+    Ok(Address::from_slice(&hash[12..]))
+}
+
+/// Recovers the key which signed `hash`.
+fn recover_public_key(hash: B256, signature: &TxSignature) -> Result<K256PublicKey> {
+    let is_y_odd = match signature.v {
+        0 | 27 => false,
+        1 | 28 => true,
+        v => bail!("v invalid: {}", v),
+    };
+    let k256_signature =
+        K256Signature::from_scalars(signature.r.to_be_bytes(), signature.s.to_be_bytes())
+            .context("r, s invalid")?;
+    let verify_key = K256VerifyingKey::recover_from_prehash(
+        hash.as_slice(),
+        &k256_signature,
+        RecoveryId::new(is_y_odd, false),
+    )
+    .context("invalid signature")?;
+    Ok(K256PublicKey::from(&verify_key))
+}
+
+#[cfg(test)]
+mod tests {
+    use k256::ecdsa::SigningKey;
+
+    use super::*;
+
+    fn key() -> (SigningKey, Address) {
+        let key = SigningKey::from_slice(&[7u8; 32]).unwrap();
+        let public_key = K256PublicKey::from(key.verifying_key()).to_encoded_point(false);
+        let signer = Address::from_slice(&keccak(&public_key.as_bytes()[1..])[12..]);
+        (key, signer)
+    }
+
+    fn sign(key: &SigningKey, hash: B256) -> TxSignature {
+        let (signature, recovery_id) = key.sign_prehash_recoverable(hash.as_slice()).unwrap();
+        let (r, s) = signature.split_bytes();
+        TxSignature {
+            v: recovery_id.is_y_odd() as u64,
+            r: U256::from_be_slice(&r),
+            s: U256::from_be_slice(&s),
+        }
+    }
+
+    fn envelope_of(proof: Bytes) -> ProofEnvelope {
+        let (key, signer) = key();
+        let public_inputs = B256::repeat_byte(0x11);
+        let timestamp = 1_700_000_000;
+        let hash = ProofEnvelope::signing_hash(&proof, public_inputs, timestamp);
+        ProofEnvelope {
+            proof,
+            public_inputs,
+            timestamp,
+            signer,
+            signature: sign(&key, hash),
+        }
+    }
+
+    fn signed_envelope() -> ProofEnvelope {
+        envelope_of(Bytes::from(vec![1, 2, 3]))
+    }
+
+    /// An sgx proof of `pi_hash` by the test key, as the guest encodes them.
+    fn sgx_proof(pi_hash: B256) -> Bytes {
+        let (key, signer) = key();
+        let signature = sign(&key, pi_hash);
+        let mut proof = 1u32.to_be_bytes().to_vec();
+        proof.extend(signer.as_slice());
+        proof.extend(signature.r.to_be_bytes::<32>());
+        proof.extend(signature.s.to_be_bytes::<32>());
+        proof.push(signature.v as u8 + 27);
+        proof.into()
+    }
+
+    #[test]
+    fn valid_envelope_verifies() {
+        let envelope = signed_envelope();
+        assert_eq!(envelope.recover_signer().unwrap(), envelope.signer);
+        assert!(envelope.verify().is_ok());
+    }
+
+    #[test]
+    fn tampered_envelope_fails_to_verify() {
+        let mut envelope = signed_envelope();
+        envelope.proof = Bytes::from(vec![1, 2, 4]);
+        assert!(envelope.verify().is_err());
+
+        let mut envelope = signed_envelope();
+        envelope.timestamp += 1;
+        assert!(envelope.verify().is_err());
+
+        let mut envelope = signed_envelope();
+        envelope.signer = Address::repeat_byte(0x22);
+        assert!(envelope.verify().is_err());
+    }
+
+    #[test]
+    fn sgx_proof_of_other_public_inputs_fails_to_verify() {
+        let envelope = envelope_of(sgx_proof(B256::repeat_byte(0x11)));
+        assert!(envelope.verify_sgx_proof().is_ok());
+
+        // signed by the instance, but not over the public inputs of the envelope
+        let envelope = envelope_of(sgx_proof(B256::repeat_byte(0x12)));
+        assert!(envelope.verify().is_ok());
+        assert!(envelope.verify_sgx_proof().is_err());
+
+        assert!(signed_envelope().verify_sgx_proof().is_err());
+    }
+
+    /// A quote of the test key with the mrEnclave `0x33..`.
+    fn quote() -> Vec<u8> {
+        let mut quote = vec![0; 432];
+        quote[QUOTE_MR_ENCLAVE_OFFSET..QUOTE_MR_ENCLAVE_OFFSET + 32].fill(0x33);
+        quote[QUOTE_REPORT_DATA_OFFSET..QUOTE_REPORT_DATA_OFFSET + 20]
+            .copy_from_slice(key().1.as_slice());
+        quote
+    }
+
+    fn statuses(report: &VerificationReport) -> Vec<CheckStatus> {
+        report.checks.iter().map(|check| check.status).collect()
+    }
+
+    #[test]
+    fn valid_envelope_passes_every_check() {
+        let envelope = envelope_of(sgx_proof(B256::repeat_byte(0x11)));
+        let pin = B256::repeat_byte(0x33);
+        let report = envelope.verification_report(Some(&quote()), None, Some(pin));
+        assert_eq!(statuses(&report), vec![CheckStatus::Passed; 4]);
+        assert!(report.failure().is_none());
+        // the quote itself is not authenticated
+        for check in &report.checks[2..] {
+            assert_eq!(check.reason.as_deref(), Some(QUOTE_NOT_AUTHENTICATED));
+        }
+        assert_eq!(report.mr_enclave, Some(pin));
+        let (key, _) = key();
+        let public_key = K256PublicKey::from(key.verifying_key()).to_encoded_point(true);
+        assert_eq!(
+            report.fingerprint,
+            Some(keccak(public_key.as_bytes()).into())
+        );
+
+        let report = signed_envelope().verification_report(None, None, None);
+        assert!(report.failure().is_none());
+        assert_eq!(
+            statuses(&report),
+            vec![
+                CheckStatus::Passed,
+                CheckStatus::Skipped,
+                CheckStatus::Skipped,
+                CheckStatus::Skipped
+            ]
+        );
+    }
+
+    #[test]
+    fn report_names_the_failed_check() {
+        let mut envelope = envelope_of(sgx_proof(B256::repeat_byte(0x11)));
+        envelope.timestamp += 1;
+        let report = envelope.verification_report(Some(&quote()), None, None);
+        let failure = report.failure().unwrap();
+        assert_eq!(failure.check, EnvelopeCheck::Signature);
+        assert!(failure
+            .reason
+            .as_ref()
+            .unwrap()
+            .contains("envelope signed by"));
+
+        // the measurement differs from the pin
+        let envelope = envelope_of(sgx_proof(B256::repeat_byte(0x11)));
+        let report =
+            envelope.verification_report(Some(&quote()), None, Some(B256::repeat_byte(0x44)));
+        let failure = report.failure().unwrap();
+        assert_eq!(failure.check, EnvelopeCheck::Measurement);
+        assert!(failure.reason.as_ref().unwrap().contains("expected 0x4444"));
+        assert_eq!(report.checks[2].status, CheckStatus::Passed);
+
+        // a pin without a quote fails too
+        let report = envelope.verification_report(None, None, Some(B256::repeat_byte(0x33)));
+        assert_eq!(report.failure().unwrap().check, EnvelopeCheck::Measurement);
+    }
+
+    #[test]
+    fn quote_report_data_follows_the_layout() {
+        let envelope = envelope_of(sgx_proof(B256::repeat_byte(0x11)));
+        // the fingerprint after the address
+        let layout = |signer: Address, fingerprint: B256| -> Result<[u8; QUOTE_REPORT_DATA_SIZE]> {
+            let mut report_data = [0; QUOTE_REPORT_DATA_SIZE];
+            report_data[..20].copy_from_slice(signer.as_slice());
+            report_data[20..52].copy_from_slice(fingerprint.as_slice());
+            Ok(report_data)
+        };
+        let fingerprint = envelope.verification_report(None, None, None).fingerprint;
+        let mut quote = quote();
+        quote[QUOTE_REPORT_DATA_OFFSET + 20..QUOTE_REPORT_DATA_OFFSET + 52]
+            .copy_from_slice(fingerprint.unwrap().as_slice());
+        let report = envelope.verification_report(Some(&quote), Some(&layout), None);
+        assert!(report.failure().is_none());
+
+        // the default layout, the address alone, is not the one of the quote
+        let report = envelope.verification_report(Some(&quote), None, None);
+        assert_eq!(
+            report.failure().unwrap().check,
+            EnvelopeCheck::QuoteConsistency
+        );
+        let report = envelope.verification_report(Some(&quote()), Some(&layout), None);
+        assert_eq!(
+            report.failure().unwrap().check,
+            EnvelopeCheck::QuoteConsistency
+        );
+    }
+}
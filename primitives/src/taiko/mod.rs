@@ -1,11 +1,13 @@
 pub mod anchor;
 pub mod consts;
+pub mod envelope;
 pub mod proposal;
 pub mod protocol_instance;
 pub mod utils;
 
 pub use anchor::*;
 pub use consts::*;
+pub use envelope::*;
 pub use proposal::*;
 pub use protocol_instance::*;
 pub use utils::*;
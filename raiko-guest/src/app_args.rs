@@ -1,9 +1,13 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, str::FromStr};
 
-use clap::{ArgAction, Args, Parser, Subcommand};
+use clap::{ArgAction, ArgGroup, Args, Parser, Subcommand, ValueEnum};
+use zeth_lib::{consts::ChainSpecMismatchPolicy, host::provider::data_source::DataSourceKind};
 use zeth_primitives::{Address, B256};
 
+use crate::{encryption::KeySource, exit_code::EXIT_CODES_HELP};
+
 #[derive(Debug, Parser)]
+#[clap(after_help = EXIT_CODES_HELP)]
 pub struct App {
     #[clap(flatten)]
     pub global_opts: GlobalOpts,
@@ -18,7 +22,33 @@ pub enum Command {
     OneShot(OneShotArgs),
     /// Bootstrap the application and exit. Bootstraping process creates the first
     /// public-private key pair and saves it on disk in encrypted form.
-    Bootstrap,
+    Bootstrap(BootstrapArgs),
+    /// Migrate a plaintext private key of an older setup into the encrypted secrets
+    /// directory.
+    MigrateKeys(MigrateKeysArgs),
+    /// Make the key rotated with `--new-key-activation-delay-secs` the active key now,
+    /// without waiting for the delay to elapse.
+    ActivateKey,
+    /// Convert a block data file between the plain and the gzipped JSON encoding,
+    /// validating it against the current block data schema.
+    Convert(ConvertArgs),
+    /// Print a summary of a proof file: the block and the prover it is for and the SGX
+    /// instance which signed it.
+    Inspect(InspectArgs),
+    /// Report what differs between two proof files, in their public inputs and metadata,
+    /// or between two witnesses, in their blocks and in the accounts and storage slots
+    /// of their pre-state.
+    Diff(DiffArgs),
+    /// Prove each block of a fixtures directory with a fixed test key, writing the test
+    /// vectors of the on-chain verifier. The output is the same on every run.
+    GenVectors(GenVectorsArgs),
+    /// Print a fresh SGX quote over the active key, or over the pending key with
+    /// `--pending`, with the measurements of the enclave, as JSON. Outside of an enclave
+    /// only the key is printed.
+    Quote(QuoteArgs),
+    /// Print the public inputs a proof of a block is expected to sign, ABI encoded and as
+    /// JSON, without proving the block.
+    PublicInputs(PublicInputsArgs),
 }
 
 #[derive(Debug, Args)]
@@ -28,12 +58,35 @@ pub struct ServerArgs {
 }
 
 #[derive(Debug, Args)]
+#[clap(group(
+    ArgGroup::new("l1_data")
+        .required(true)
+        .args(["l1_blocks_data_file", "l1_anchor_hash"])
+))]
 pub struct OneShotArgs {
     #[clap(long)]
     /// Path of the *.json.gz file with the block data.
     pub blocks_data_file: PathBuf,
     #[clap(long)]
-    pub l1_blocks_data_file: PathBuf,
+    pub l1_blocks_data_file: Option<PathBuf>,
+    #[clap(long, requires = "l1_rpc")]
+    /// Hash of the L1 block anchoring the block, fetched from `--l1-rpc` instead of
+    /// reading `--l1-blocks-data-file`.
+    pub l1_anchor_hash: Option<B256>,
+    #[clap(long)]
+    /// URL of the L1 RPC node serving the anchor block.
+    pub l1_rpc: Option<String>,
+    #[clap(long, default_value_t = DataSourceKind::Rpc)]
+    /// What `--l1-rpc` names: an RPC node (`rpc`) or a directory of exported block data
+    /// (`dir`).
+    pub data_source: DataSourceKind,
+    #[clap(long, requires = "l1_rpc")]
+    /// Beacon node serving the blob sidecars, when the block is proposed with a blob and
+    /// the L1 data is fetched from `--l1-rpc`.
+    pub beacon_rpc_url: Option<String>,
+    #[clap(long)]
+    /// KZG trusted setup file verifying the blob of blocks proposed with one.
+    pub kzg_trusted_setup: Option<PathBuf>,
     #[clap(long)]
     pub prover: Address,
     #[clap(long)]
@@ -42,6 +95,338 @@ pub struct OneShotArgs {
     pub sgx_instance_id: u32,
     #[clap(long, default_value = "internal_devnet_a")]
     pub l2_chain: Option<String>,
+    #[clap(long, default_value_t = 0)]
+    /// How many times to retry the SGX attestation step on a transient fault. The block
+    /// inputs are loaded only once; invalid inputs are never retried.
+    pub retries: u32,
+    #[clap(long, default_value_t = 1000)]
+    /// Delay between two attestation attempts, in milliseconds.
+    pub retry_delay_ms: u64,
+    #[clap(long)]
+    /// Build the block with this chain id instead of the one of `l2_chain`. Testing only,
+    /// for forks reusing the recorded block data of another chain.
+    pub chain_id_override: Option<u64>,
+    #[clap(long, default_value_t = ChainSpecMismatchPolicy::Error)]
+    /// What to do when the chain id or the fork of the block data disagrees with
+    /// `l2_chain`: reject the block (`error`), build it with the values of the data
+    /// (`warn`) or with the ones of `l2_chain` (`override`).
+    pub on_chainspec_mismatch: ChainSpecMismatchPolicy,
+    #[clap(long, requires = "allow_consensus_overrides")]
+    /// Build the block with this base fee per gas, in wei, instead of the one of its
+    /// data. Testing only, the proof will not verify on the chain.
+    pub base_fee_override: Option<u64>,
+    #[clap(long, requires = "allow_consensus_overrides")]
+    /// Build the block with this gas limit instead of the one of its data. Testing only,
+    /// the proof will not verify on the chain.
+    pub gas_limit_override: Option<u64>,
+    #[clap(long, requires = "allow_consensus_overrides")]
+    /// JSON file of the code replacing the one of system contracts in the pre-state, as
+    /// `{"code": {"<address>": "<bytecode>"}}`. Testing only, the proof will not verify
+    /// on the chain.
+    pub system_overrides: Option<PathBuf>,
+    #[clap(long, requires = "allow_consensus_overrides")]
+    /// JSON of the accounts overridden in the pre-state, as `{"<address>": {"balance":
+    /// "0x..", "nonce": 1, "stateDiff": {"<slot>": "<value>"}}}` like the state overrides
+    /// of `eth_call`. Testing only, the proof will not verify on the chain.
+    pub state_overrides: Option<String>,
+    #[clap(long)]
+    /// Write the proof as JSON to this file instead of printing it, `-` for stdout.
+    pub output: Option<PathBuf>,
+    #[clap(long, value_enum, default_value_t = ExistingOutput::Overwrite)]
+    /// What to do when the `--output` file already exists, e.g. after an interrupted
+    /// batch.
+    pub on_existing_output: ExistingOutput,
+    #[clap(long, value_enum, default_value_t = OutputCompression::None)]
+    /// Compress the `--output` file with this codec, adding its extension to the file
+    /// name, e.g. for archival sinks. `inspect` reads the compressed files as they are.
+    pub output_compression: OutputCompression,
+    #[clap(long, conflicts_with_all = ["verify_against_rpc", "parse_only"])]
+    /// Encrypt the `--output` file with the AES-256 key of this source, `env:<variable>`
+    /// or `file:<path>` holding it hex encoded, for proofs stored on shared storage.
+    /// `inspect` reads the encrypted files given the same key source. Unrelated to the
+    /// signing key.
+    pub output_encryption_key_source: Option<KeySource>,
+    #[clap(long)]
+    /// Only compare the block data with the blocks of this L2 RPC node, and the L1 block
+    /// data with `--l1-rpc` when both are given, reporting the mismatched fields. Neither
+    /// builds nor proves the block.
+    pub verify_against_rpc: Option<String>,
+    #[clap(long)]
+    /// Reject the block data when its block is older than this many seconds, relative to
+    /// the chain head of `--l2-rpc` or to now without one, so that a stale export is not
+    /// proven.
+    pub max_data_age_secs: Option<u64>,
+    #[clap(long, requires = "max_data_age_secs")]
+    /// URL of the L2 RPC node whose chain head `--max-data-age-secs` is relative to, also
+    /// rejecting the block data whose block is no longer canonical on it.
+    pub l2_rpc: Option<String>,
+    #[clap(long)]
+    /// Also write the public inputs of the proof to this file, as JSON with their ABI
+    /// encoding in the layout of the on-chain verifier under `abiEncoded`.
+    pub emit_public_inputs: Option<PathBuf>,
+    #[clap(long)]
+    /// Also write the calldata of the on-chain call submitting the proof to this file, as
+    /// JSON with the contract it is sent to under `to`. The call is the one of the chain
+    /// spec, `proveBlock` of the L1 contract by default.
+    pub emit_calldata: Option<PathBuf>,
+    #[clap(long)]
+    /// Also write the state root after each transaction of the block to this file, as
+    /// JSON, to find the transaction a state root mismatch comes from. Debugging only,
+    /// the state tries are hashed after every transaction.
+    pub emit_tx_state_roots: Option<PathBuf>,
+    #[clap(long, conflicts_with_all = ["tx_range", "parse_only", "verify_against_rpc"])]
+    /// Also write the execution trace of the transactions of the block to this file, as
+    /// JSON: the opcode and the gas of each step, with what `--trace-verbosity` tells.
+    /// Debugging only, the EVM is inspected at every step, and the guest must be built
+    /// with the `trace` feature.
+    pub emit_trace: Option<PathBuf>,
+    #[clap(long, value_enum, default_value_t = TraceVerbosity::Opcodes)]
+    /// What `--emit-trace` records of each step besides its opcode and gas.
+    pub trace_verbosity: TraceVerbosity,
+    #[clap(long, default_value_t = 1_000_000)]
+    /// Steps `--emit-trace` records at most, the trace being truncated beyond.
+    pub max_trace_steps: usize,
+    #[clap(long)]
+    /// Allow the options proving something else than the block of the data, as
+    /// `--tx-range` or the base fee, gas limit, system and state overrides. Testing only,
+    /// the proof will not verify on the chain.
+    pub allow_consensus_overrides: bool,
+    #[clap(
+        long,
+        requires = "allow_consensus_overrides",
+        conflicts_with_all = ["emit_tx_state_roots", "parse_only", "verify_against_rpc"]
+    )]
+    /// Only prove the transactions `from:to` of the block, `to` excluded, against the
+    /// state after the ones before `from`, and report the state root after each of them.
+    /// Debugging only, the proof is not the one of the block.
+    pub tx_range: Option<TxRange>,
+    #[clap(long, conflicts_with = "verify_against_rpc")]
+    /// Only load the block data and run the structural and anchor checks, reporting the
+    /// L1 block the block is anchored to. Neither builds nor proves the block, and the
+    /// application needs no bootstrapping.
+    pub parse_only: bool,
+    #[clap(long)]
+    /// Account the time spent in each phase of the run, with the gas used by the block,
+    /// and report it as JSON after the proof.
+    pub profile: bool,
+    #[clap(long, requires = "profile")]
+    /// Write the profile to this file instead of printing it.
+    pub profile_out: Option<PathBuf>,
+    #[clap(long, conflicts_with_all = ["verify_against_rpc", "parse_only"])]
+    /// Append a JSON line to this file once the block is proven, skipped or failed, with
+    /// its hash, its `--output` file, the outcome, the duration and the MRENCLAVE of the
+    /// quote. The runs of a batch share the file.
+    pub manifest: Option<PathBuf>,
+    #[clap(long)]
+    /// Read this unix timestamp instead of the clock, for the envelope of the proof and
+    /// the age of the block data, so that runs over the same data produce the same
+    /// output. Testing and reproduction only, the timestamp of the block is the one
+    /// of its data.
+    pub fixed_now: Option<u64>,
+    #[clap(long)]
+    /// Append the progress of the run to this file or FIFO as JSON lines: the run
+    /// starting, each phase of the proof ending, and the run ending with its outcome. The
+    /// runs of a batch may share the file.
+    pub progress_out: Option<PathBuf>,
+    #[clap(long, requires = "progress_out")]
+    /// Index of the block in its batch, carried by its progress events.
+    pub batch_index: Option<u64>,
+}
+
+/// A range `from:to` of the transactions of a block, `to` excluded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TxRange {
+    pub from: usize,
+    pub to: usize,
+}
+
+impl FromStr for TxRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (from, to) = s
+            .split_once(':')
+            .ok_or_else(|| format!("invalid tx range {:?}, expected from:to", s))?;
+        let parse = |index: &str| {
+            index
+                .parse()
+                .map_err(|_| format!("invalid tx index {:?} in {:?}", index, s))
+        };
+        let (from, to) = (parse(from)?, parse(to)?);
+        if from >= to {
+            return Err(format!("empty tx range {:?}", s));
+        }
+        Ok(TxRange { from, to })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExistingOutput {
+    /// Prove the block again, replacing the file.
+    Overwrite,
+    /// Keep the file when it holds a verifying proof of the block, for the same prover
+    /// and graffiti, and prove the block again otherwise.
+    Skip,
+    /// Fail without proving the block.
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputCompression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum TraceVerbosity {
+    Opcodes,
+    /// the stack items popped and pushed
+    Stack,
+    /// the stack items and the memory written
+    Memory,
+}
+
+#[derive(Debug, Args)]
+pub struct BootstrapArgs {
+    #[clap(long, value_enum, default_value_t = OutputFormat::Text)]
+    /// Format of the bootstrap report printed to stdout.
+    pub format: OutputFormat,
+    #[clap(long)]
+    /// Overwrite the private key if the application was already bootstrapped.
+    pub force: bool,
+    #[clap(long, default_value_t = 0)]
+    /// Rotate the key, with --force, into a pending key signing once this delay elapsed
+    /// or `activate-key` is run. The previous key keeps signing meanwhile.
+    pub new_key_activation_delay_secs: u64,
+    #[clap(long)]
+    /// Compose the report data of the quote with the layout of this chain spec. Without
+    /// it the report data is the instance address.
+    pub l2_chain: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct QuoteArgs {
+    #[clap(long)]
+    /// Compose the report data of the quote with the layout of this chain spec. Without
+    /// it the report data is the instance address.
+    pub l2_chain: Option<String>,
+    #[clap(long)]
+    /// Quote the key rotated with `--new-key-activation-delay-secs`, to register it
+    /// before its activation.
+    pub pending: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Args)]
+pub struct MigrateKeysArgs {
+    #[clap(long)]
+    /// Path of the plaintext private key. Defaults to the key file in the secrets
+    /// directory, which is then migrated in place.
+    pub from: Option<PathBuf>,
+    #[clap(long)]
+    /// Overwrite and remove the plaintext key file after migrating it.
+    pub delete_original: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ConvertArgs {
+    #[clap(long)]
+    /// Path of the block data file to convert.
+    pub input: PathBuf,
+    #[clap(long, required_unless_present = "dry_run")]
+    /// Path of the converted block data file.
+    pub output: Option<PathBuf>,
+    #[clap(long, value_enum, default_value_t = BlockDataFormat::JsonGz)]
+    pub in_format: BlockDataFormat,
+    #[clap(long, value_enum, default_value_t = BlockDataFormat::JsonGz)]
+    pub out_format: BlockDataFormat,
+    #[clap(long)]
+    /// Only validate the input file, without writing the output.
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct InspectArgs {
+    #[clap(long)]
+    /// Path of the proof file: the `--output` of `one-shot`, a proof envelope, or a proof
+    /// delivered by the host, told apart by their fields.
+    pub input: PathBuf,
+    #[clap(long)]
+    /// Also check the signatures of the proof and of its envelope.
+    pub verify: bool,
+    #[clap(long)]
+    /// Decrypt the proof file with the key of this source, as given to `one-shot`.
+    pub output_encryption_key_source: Option<KeySource>,
+    #[clap(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+}
+
+#[derive(Debug, Args)]
+pub struct DiffArgs {
+    #[clap(long)]
+    /// Path of the first file: a proof file as read by `inspect`, a witness dumped by
+    /// the host or a block data file, told apart by their fields.
+    pub left: PathBuf,
+    #[clap(long)]
+    /// Path of the second file, of the same kind as the first.
+    pub right: PathBuf,
+    #[clap(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+}
+
+#[derive(Debug, Args)]
+pub struct GenVectorsArgs {
+    #[clap(long)]
+    /// Directory of the fixture blocks, the `{block}.l2.json.gz` (or `{block}.json.gz`)
+    /// and `{block}.l1.json.gz` block data files of each.
+    pub fixtures: PathBuf,
+    #[clap(long)]
+    /// Directory the vectors are written to, one `{block}` directory per fixture block.
+    pub out: PathBuf,
+    #[clap(long, default_value = "internal_devnet_a")]
+    pub l2_chain: String,
+    #[clap(long, default_value_t = Address::ZERO)]
+    pub prover: Address,
+    #[clap(long, default_value_t = B256::ZERO)]
+    pub graffiti: B256,
+    #[clap(long, default_value_t = 0)]
+    pub sgx_instance_id: u32,
+    #[clap(long, default_value_t = 0)]
+    /// Timestamp of the proof envelopes, fixed for the output to be reproducible.
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Args)]
+pub struct PublicInputsArgs {
+    #[clap(long)]
+    /// Path of the *.json.gz file with the block data.
+    pub blocks_data_file: PathBuf,
+    #[clap(long)]
+    pub l1_blocks_data_file: PathBuf,
+    #[clap(long)]
+    pub prover: Address,
+    #[clap(long)]
+    pub graffiti: B256,
+    #[clap(long, default_value = "internal_devnet_a")]
+    pub l2_chain: String,
+    #[clap(long)]
+    /// Address of the instance signing the proof, the one of the key of the secrets
+    /// directory by default.
+    pub new_instance: Option<Address>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum BlockDataFormat {
+    /// Gzipped JSON, the format of the cache files.
+    JsonGz,
+    /// Plain JSON, for inspecting and editing.
+    Json,
 }
 
 #[derive(Debug, Args)]
@@ -54,4 +439,8 @@ pub struct GlobalOpts {
     #[clap(long, short, global = true, action = ArgAction::Count)]
     /// Verbosity of the application. Use multiple times to increase verbosity.
     pub verbose: u8,
+
+    #[clap(long, short, global = true, conflicts_with = "verbose")]
+    /// Print only errors and the proof output, so that stdout can be piped.
+    pub quiet: bool,
 }
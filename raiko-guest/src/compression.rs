@@ -0,0 +1,83 @@
+//! Compression of the `--output` files, for the archival sinks storing the proofs. The
+//! codec is told by the extension of the file and by its magic bytes, so that readers
+//! need not be told which one was used.
+use std::{
+    fs,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+
+use crate::app_args::OutputCompression;
+
+/// Magic bytes opening a gzip stream.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+/// Magic bytes opening a zstd frame.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+impl OutputCompression {
+    /// Name of the codec, as recorded in the compressed outputs.
+    pub fn name(self) -> &'static str {
+        match self {
+            OutputCompression::None => "none",
+            OutputCompression::Gzip => "gzip",
+            OutputCompression::Zstd => "zstd",
+        }
+    }
+
+    fn extension(self) -> Option<&'static str> {
+        match self {
+            OutputCompression::None => None,
+            OutputCompression::Gzip => Some("gz"),
+            OutputCompression::Zstd => Some("zst"),
+        }
+    }
+
+    /// `path` with the extension of the codec, unless it already ends with it.
+    pub fn output_path(self, path: &Path) -> PathBuf {
+        match self.extension() {
+            Some(extension) if path.extension() != Some(extension.as_ref()) => {
+                let mut path = path.as_os_str().to_owned();
+                path.push(".");
+                path.push(extension);
+                path.into()
+            }
+            _ => path.to_path_buf(),
+        }
+    }
+}
+
+/// `bytes` compressed with `compression`.
+pub fn compress(compression: OutputCompression, bytes: &[u8]) -> Result<Vec<u8>> {
+    Ok(match compression {
+        OutputCompression::None => bytes.to_vec(),
+        OutputCompression::Gzip => {
+            let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::best());
+            encoder.write_all(bytes)?;
+            encoder.finish()?
+        }
+        OutputCompression::Zstd => {
+            zstd::stream::encode_all(bytes, zstd::DEFAULT_COMPRESSION_LEVEL)?
+        }
+    })
+}
+
+/// The content of `path`, decompressed when it starts with the magic bytes of a codec.
+pub fn read_decompressed(path: &Path) -> Result<Vec<u8>> {
+    decompress(fs::read(path)?)
+}
+
+/// `bytes` decompressed when they start with the magic bytes of a codec, as they are
+/// otherwise.
+pub fn decompress(bytes: Vec<u8>) -> Result<Vec<u8>> {
+    if bytes.starts_with(&GZIP_MAGIC) {
+        let mut decompressed = vec![];
+        flate2::read::GzDecoder::new(bytes.as_slice()).read_to_end(&mut decompressed)?;
+        Ok(decompressed)
+    } else if bytes.starts_with(&ZSTD_MAGIC) {
+        Ok(zstd::stream::decode_all(bytes.as_slice())?)
+    } else {
+        Ok(bytes)
+    }
+}
@@ -0,0 +1,156 @@
+use std::{
+    fs::File,
+    io::{Read, Write},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use zeth_lib::host::provider::file_provider::FileProvider;
+
+use crate::{
+    app_args::{BlockDataFormat, ConvertArgs},
+    exit_code::Failure,
+};
+
+/// Re-encodes a block data file, validating it against the current block data schema.
+pub fn convert(args: ConvertArgs) -> Result<()> {
+    let block_data = read_block_data(&args.input, args.in_format)
+        .with_context(|| format!("Failed to read block data from {}", args.input.display()))
+        .context(Failure::InvalidBlockData)?;
+    status!("{} holds valid block data", args.input.display());
+    if args.dry_run {
+        return Ok(());
+    }
+    let output = args
+        .output
+        .context("--output is required unless --dry-run is given")
+        .context(Failure::InvalidArgs)?;
+    write_block_data(&output, args.out_format, &block_data)
+        .with_context(|| format!("Failed to write block data to {}", output.display()))
+        .context(Failure::Secrets)?;
+    status!("Converted {} to {}", args.input.display(), output.display());
+    Ok(())
+}
+
+fn read_block_data(path: &Path, format: BlockDataFormat) -> Result<FileProvider> {
+    let file = File::open(path)?;
+    let mut reader: Box<dyn Read> = match format {
+        BlockDataFormat::JsonGz => Box::new(flate2::read::GzDecoder::new(file)),
+        BlockDataFormat::Json => Box::new(file),
+    };
+    let mut data = vec![];
+    reader.read_to_end(&mut data)?;
+    Ok(serde_json::from_slice(&data)?)
+}
+
+fn write_block_data(path: &Path, format: BlockDataFormat, block_data: &FileProvider) -> Result<()> {
+    let data = serde_json::to_vec(block_data)?;
+    let file = File::create(path)?;
+    match format {
+        BlockDataFormat::JsonGz => {
+            let mut encoder = flate2::write::GzEncoder::new(file, flate2::Compression::best());
+            encoder.write_all(&data)?;
+            encoder.finish()?.sync_all()?;
+        }
+        BlockDataFormat::Json => {
+            let mut file = file;
+            file.write_all(&data)?;
+            file.sync_all()?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::*;
+    use crate::exit_code::exit_code;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("fixtures")
+            .join(name)
+    }
+
+    fn read_json(path: &Path) -> serde_json::Value {
+        serde_json::from_slice(&fs::read(path).unwrap()).unwrap()
+    }
+
+    fn convert_args(
+        input: PathBuf,
+        in_format: BlockDataFormat,
+        output: Option<PathBuf>,
+        out_format: BlockDataFormat,
+    ) -> ConvertArgs {
+        ConvertArgs {
+            input,
+            output,
+            in_format,
+            out_format,
+            dry_run: false,
+        }
+    }
+
+    #[test]
+    fn block_data_round_trips_through_both_formats() {
+        let dir = tempfile::tempdir().unwrap();
+        let gz = dir.path().join("1.json.gz");
+        let json = dir.path().join("1.json");
+        convert(convert_args(
+            fixture("1.json"),
+            BlockDataFormat::Json,
+            Some(gz.clone()),
+            BlockDataFormat::JsonGz,
+        ))
+        .unwrap();
+        convert(convert_args(
+            gz,
+            BlockDataFormat::JsonGz,
+            Some(json.clone()),
+            BlockDataFormat::Json,
+        ))
+        .unwrap();
+        assert_eq!(read_json(&json), read_json(&fixture("1.json")));
+
+        convert(convert_args(
+            fixture("1.json.gz"),
+            BlockDataFormat::JsonGz,
+            Some(json.clone()),
+            BlockDataFormat::Json,
+        ))
+        .unwrap();
+        assert_eq!(read_json(&json), read_json(&fixture("1.json")));
+    }
+
+    #[test]
+    fn dry_run_validates_without_writing() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("1.json");
+        let args = ConvertArgs {
+            dry_run: true,
+            ..convert_args(
+                fixture("1.json.gz"),
+                BlockDataFormat::JsonGz,
+                Some(output.clone()),
+                BlockDataFormat::Json,
+            )
+        };
+        convert(args).unwrap();
+        assert!(!output.exists());
+
+        // the plain json fixture is no valid gzip file
+        let args = ConvertArgs {
+            dry_run: true,
+            ..convert_args(
+                fixture("1.json"),
+                BlockDataFormat::JsonGz,
+                None,
+                BlockDataFormat::Json,
+            )
+        };
+        let err = convert(args).unwrap_err();
+        assert_eq!(exit_code(&err), Failure::InvalidBlockData as u8);
+    }
+}
@@ -0,0 +1,397 @@
+//! Reports what differs between two proofs, or between the two witnesses of a block, to
+//! tell what changed when a proof regresses.
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+};
+
+use anyhow::{anyhow, Context, Result};
+use ethers_core::types::{H160, H256, U256};
+use serde::Serialize;
+use serde_json::{json, Value};
+use zeth_lib::host::{
+    provider::file_provider::FileProvider,
+    validate::{diff_block_data, FieldMismatch},
+};
+
+use crate::{
+    app_args::{DiffArgs, OutputFormat},
+    compression::{decompress, read_decompressed},
+    exit_code::Failure,
+    inspect::proof_summary,
+};
+
+/// A field of the summaries of two proofs which differs.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldDiff {
+    pub field: String,
+    pub left: Value,
+    pub right: Value,
+}
+
+/// A header field or the transactions of a block which differ between two witnesses.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockDiff {
+    /// `l1` or `l2` in the witness dumps, `blocksData` for the block data files
+    pub layer: &'static str,
+    pub block_no: u64,
+    pub field: String,
+    pub left: Value,
+    pub right: Value,
+}
+
+/// An account field or a storage slot of the pre-state which differs between two
+/// witnesses, `null` on the side which does not hold it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StateDiff {
+    pub layer: &'static str,
+    pub address: H160,
+    /// set for the storage slots
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slot: Option<H256>,
+    /// `balance`, `nonce`, `codeHash` or `storage`
+    pub field: &'static str,
+    pub left: Value,
+    pub right: Value,
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiffReport {
+    /// `proof` or `witness`
+    pub kind: &'static str,
+    pub fields: Vec<FieldDiff>,
+    pub blocks: Vec<BlockDiff>,
+    pub state: Vec<StateDiff>,
+}
+
+impl DiffReport {
+    fn is_empty(&self) -> bool {
+        self.fields.is_empty() && self.blocks.is_empty() && self.state.is_empty()
+    }
+}
+
+/// A proof file or a witness, told apart by their fields.
+enum DiffInput {
+    /// the summary of the proof, as printed by `inspect`
+    Proof(Value),
+    /// the block data of each layer
+    Witness(Vec<(&'static str, FileProvider)>),
+}
+
+impl DiffInput {
+    /// Reads a proof file, a witness dumped by `/debug/dump-witness/{block}` or a block
+    /// data file, compressed or not.
+    fn read(path: &Path) -> Result<Self> {
+        let file: Value = serde_json::from_slice(&read_decompressed(path)?)?;
+        if file.get("l2BlocksData").is_some() {
+            let layers = [("l1", "l1BlocksData"), ("l2", "l2BlocksData")];
+            let blocks_data = layers
+                .into_iter()
+                .map(|(layer, field)| {
+                    let hex_str = file[field].as_str().context("block data is not hex")?;
+                    let gzipped = hex::decode(hex_str.trim_start_matches("0x"))?;
+                    let block_data = serde_json::from_slice(&decompress(gzipped)?)?;
+                    Ok((layer, block_data))
+                })
+                .collect::<Result<_>>()?;
+            Ok(DiffInput::Witness(blocks_data))
+        } else if file.get("full_blocks").is_some() {
+            Ok(DiffInput::Witness(vec![(
+                "blocksData",
+                serde_json::from_value(file)?,
+            )]))
+        } else {
+            let summary = proof_summary(file).context("Neither a proof nor a witness")??;
+            Ok(DiffInput::Proof(summary))
+        }
+    }
+}
+
+/// Prints what differs between the two files of `args`.
+pub fn diff(args: DiffArgs) -> Result<()> {
+    let report = diff_files(&args.left, &args.right)?;
+    match args.format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+        OutputFormat::Text if report.is_empty() => println!("No difference"),
+        OutputFormat::Text => {
+            for diff in &report.fields {
+                println!("{}: {} -> {}", diff.field, diff.left, diff.right);
+            }
+            for diff in &report.blocks {
+                println!(
+                    "{} block {} {}: {} -> {}",
+                    diff.layer, diff.block_no, diff.field, diff.left, diff.right
+                );
+            }
+            for diff in &report.state {
+                match diff.slot {
+                    Some(slot) => println!(
+                        "{} {:?} slot {:?}: {} -> {}",
+                        diff.layer, diff.address, slot, diff.left, diff.right
+                    ),
+                    None => println!(
+                        "{} {:?} {}: {} -> {}",
+                        diff.layer, diff.address, diff.field, diff.left, diff.right
+                    ),
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn diff_files(left: &Path, right: &Path) -> Result<DiffReport> {
+    let read = |path: &Path| {
+        DiffInput::read(path)
+            .with_context(|| format!("Failed to read {}", path.display()))
+            .context(Failure::InvalidArgs)
+    };
+    match (read(left)?, read(right)?) {
+        (DiffInput::Proof(left), DiffInput::Proof(right)) => Ok(DiffReport {
+            kind: "proof",
+            fields: diff_summaries(&left, &right),
+            ..Default::default()
+        }),
+        (DiffInput::Witness(left), DiffInput::Witness(right)) => diff_witnesses(left, right),
+        _ => Err(anyhow!("Cannot diff a proof with a witness").context(Failure::InvalidArgs)),
+    }
+}
+
+fn diff_summaries(left: &Value, right: &Value) -> Vec<FieldDiff> {
+    let fields: BTreeSet<_> = [left, right]
+        .iter()
+        .filter_map(|summary| summary.as_object())
+        .flat_map(|summary| summary.keys().cloned())
+        .collect();
+    fields
+        .into_iter()
+        .filter(|field| left.get(field) != right.get(field))
+        .map(|field| FieldDiff {
+            left: left.get(&field).cloned().unwrap_or_default(),
+            right: right.get(&field).cloned().unwrap_or_default(),
+            field,
+        })
+        .collect()
+}
+
+fn diff_witnesses(
+    left: Vec<(&'static str, FileProvider)>,
+    right: Vec<(&'static str, FileProvider)>,
+) -> Result<DiffReport> {
+    let mut report = DiffReport {
+        kind: "witness",
+        ..Default::default()
+    };
+    if left.len() != right.len() {
+        return Err(anyhow!("Cannot diff a witness dump with a block data file")
+            .context(Failure::InvalidArgs));
+    }
+    for ((layer, left), (_, mut right)) in left.into_iter().zip(right) {
+        let block_numbers = |file: &FileProvider| {
+            let numbers: Vec<_> = file
+                .full_blocks()
+                .iter()
+                .map(|(query, _)| query.block_no)
+                .collect();
+            json!(numbers)
+        };
+        let (left_blocks, right_blocks) = (block_numbers(&left), block_numbers(&right));
+        if left_blocks != right_blocks {
+            // the witnesses of different blocks, only their blocks are compared
+            report.fields.push(FieldDiff {
+                field: format!("{}.blocks", layer),
+                left: left_blocks,
+                right: right_blocks,
+            });
+            continue;
+        }
+        let mismatches = diff_block_data(&left, &mut right)?;
+        report.blocks.extend(mismatches.into_iter().map(
+            |FieldMismatch {
+                 block_no,
+                 field,
+                 file,
+                 rpc,
+             }| BlockDiff {
+                layer,
+                block_no,
+                field,
+                left: file,
+                right: rpc,
+            },
+        ));
+        report.state.extend(diff_pre_states(
+            layer,
+            &pre_state(&left),
+            &pre_state(&right),
+        ));
+    }
+    Ok(report)
+}
+
+/// An account of the pre-state, as proven in the block data.
+#[derive(Debug, Default, PartialEq)]
+struct AccountState {
+    balance: U256,
+    nonce: u64,
+    code_hash: H256,
+    storage: BTreeMap<H256, U256>,
+}
+
+/// The accounts proven at the earliest block of the proofs of `file`, the parent of the
+/// block built on them.
+fn pre_state(file: &FileProvider) -> BTreeMap<H160, AccountState> {
+    let mut accounts: BTreeMap<H160, AccountState> = BTreeMap::new();
+    let Some(block_no) = file.proofs().map(|(query, _)| query.block_no).min() else {
+        return accounts;
+    };
+    for (query, proof) in file
+        .proofs()
+        .filter(|(query, _)| query.block_no == block_no)
+    {
+        let account = accounts.entry(query.address).or_default();
+        account.balance = proof.balance;
+        account.nonce = proof.nonce.as_u64();
+        account.code_hash = proof.code_hash;
+        for slot in &proof.storage_proof {
+            account.storage.insert(slot.key, slot.value);
+        }
+    }
+    accounts
+}
+
+fn diff_pre_states(
+    layer: &'static str,
+    left: &BTreeMap<H160, AccountState>,
+    right: &BTreeMap<H160, AccountState>,
+) -> Vec<StateDiff> {
+    let mut diffs = vec![];
+    let addresses: BTreeSet<_> = left.keys().chain(right.keys()).collect();
+    for address in addresses {
+        let (left, right) = (left.get(address), right.get(address));
+        if left == right {
+            continue;
+        }
+        let mut push =
+            |field: &'static str, slot: Option<H256>, left: Option<Value>, right: Option<Value>| {
+                if left != right {
+                    diffs.push(StateDiff {
+                        layer,
+                        address: *address,
+                        slot,
+                        field,
+                        left: left.unwrap_or_default(),
+                        right: right.unwrap_or_default(),
+                    });
+                }
+            };
+        push(
+            "balance",
+            None,
+            left.map(|account| json!(account.balance)),
+            right.map(|account| json!(account.balance)),
+        );
+        push(
+            "nonce",
+            None,
+            left.map(|account| json!(account.nonce)),
+            right.map(|account| json!(account.nonce)),
+        );
+        push(
+            "codeHash",
+            None,
+            left.map(|account| json!(account.code_hash)),
+            right.map(|account| json!(account.code_hash)),
+        );
+        let slots: BTreeSet<_> = left
+            .into_iter()
+            .chain(right)
+            .flat_map(|account| account.storage.keys())
+            .collect();
+        for slot in slots {
+            let value = |account: Option<&AccountState>| {
+                account.and_then(|account| account.storage.get(slot).map(|value| json!(value)))
+            };
+            push("storage", Some(*slot), value(left), value(right));
+        }
+    }
+    diffs
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers_core::types::{Block, EIP1186ProofResponse, StorageProof};
+    use zeth_lib::host::provider::{BlockQuery, MutProvider, ProofQuery, Provider};
+
+    use super::*;
+
+    /// The block data of block 2, with the pre-state of an account holding `value` in
+    /// slot 1 and 7 in slot 2.
+    fn write_witness(dir: &Path, name: &str, value: u64) -> std::path::PathBuf {
+        let path = dir.join(name);
+        let mut block_data = FileProvider::empty(path.to_string_lossy().into_owned());
+        block_data.insert_full_block(
+            BlockQuery { block_no: 2 },
+            Block {
+                number: Some(2.into()),
+                ..Default::default()
+            },
+        );
+        let address = H160::repeat_byte(0x11);
+        let slots = [
+            (H256::from_low_u64_be(1), value),
+            (H256::from_low_u64_be(2), 7),
+        ];
+        block_data.insert_proof(
+            ProofQuery {
+                block_no: 1,
+                address,
+                indices: slots.iter().map(|(slot, _)| *slot).collect(),
+            },
+            EIP1186ProofResponse {
+                address,
+                balance: 1000.into(),
+                storage_proof: slots
+                    .iter()
+                    .map(|(slot, value)| StorageProof {
+                        key: *slot,
+                        value: (*value).into(),
+                        ..Default::default()
+                    })
+                    .collect(),
+                ..Default::default()
+            },
+        );
+        block_data.save().unwrap();
+        path
+    }
+
+    #[test]
+    fn witnesses_differing_in_a_slot_report_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let left = write_witness(dir.path(), "2.json.gz", 42);
+        let right = write_witness(dir.path(), "2.rerun.json.gz", 43);
+
+        let report = diff_files(&left, &left).unwrap();
+        assert_eq!(report.kind, "witness");
+        assert!(report.is_empty());
+
+        let report = diff_files(&left, &right).unwrap();
+        assert!(report.fields.is_empty());
+        assert!(report.blocks.is_empty());
+        assert_eq!(
+            report.state,
+            vec![StateDiff {
+                layer: "blocksData",
+                address: H160::repeat_byte(0x11),
+                slot: Some(H256::from_low_u64_be(1)),
+                field: "storage",
+                left: json!(U256::from(42)),
+                right: json!(U256::from(43)),
+            }]
+        );
+    }
+}
@@ -0,0 +1,158 @@
+//! Encryption at rest of the `--output` proof files, for the proofs kept on shared
+//! storage: the file, compressed or not, is sealed with AES-256-GCM under a key of the
+//! operator, unrelated to the signing key of the enclave. The encrypted files are told by
+//! their magic bytes, so that `inspect` and `--on-existing-output skip` read them as they
+//! are once given the key.
+use std::{
+    env, fmt, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use rand::RngCore;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+
+use crate::compression::decompress;
+
+/// Magic bytes opening an encrypted output, the version of its layout included. They
+/// are authenticated with the content.
+const ENCRYPTED_MAGIC: [u8; 8] = *b"raikoae1";
+/// Name of the cipher, as recorded in the encrypted outputs.
+pub const OUTPUT_CIPHER: &str = "aes-256-gcm";
+
+/// Where the key of the outputs is read from, `env:<variable>` or `file:<path>`, as the
+/// 32 bytes of an AES-256 key, hex encoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeySource {
+    Env(String),
+    File(PathBuf),
+}
+
+impl FromStr for KeySource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("env", var)) if !var.is_empty() => Ok(KeySource::Env(var.to_owned())),
+            Some(("file", path)) if !path.is_empty() => Ok(KeySource::File(path.into())),
+            _ => Err(format!(
+                "invalid key source {:?}, expected env:<variable> or file:<path>",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for KeySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeySource::Env(var) => write!(f, "env:{}", var),
+            KeySource::File(path) => write!(f, "file:{}", path.display()),
+        }
+    }
+}
+
+impl KeySource {
+    pub fn load(&self) -> Result<OutputKey> {
+        let hex_key = match self {
+            KeySource::Env(var) => env::var(var).with_context(|| format!("{} is not set", var))?,
+            KeySource::File(path) => fs::read_to_string(path)
+                .with_context(|| format!("Failed to read {}", path.display()))?,
+        };
+        OutputKey::from_hex(hex_key.trim()).with_context(|| format!("Invalid key in {}", self))
+    }
+}
+
+/// The key the outputs are encrypted with.
+pub struct OutputKey(LessSafeKey);
+
+impl OutputKey {
+    pub fn from_hex(hex_key: &str) -> Result<Self> {
+        let bytes = hex::decode(hex_key.trim_start_matches("0x"))?;
+        let key = UnboundKey::new(&AES_256_GCM, &bytes)
+            .map_err(|_| anyhow!("the key must have 32 bytes, got {}", bytes.len()))?;
+        Ok(OutputKey(LessSafeKey::new(key)))
+    }
+
+    /// `plaintext` sealed under the key, after the magic bytes and a random nonce.
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let mut sealed = plaintext.to_vec();
+        self.0
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(ENCRYPTED_MAGIC),
+                &mut sealed,
+            )
+            .map_err(|_| anyhow!("Failed to encrypt the output"))?;
+        let mut encrypted = ENCRYPTED_MAGIC.to_vec();
+        encrypted.extend(nonce);
+        encrypted.extend(sealed);
+        Ok(encrypted)
+    }
+
+    /// The plaintext of `encrypted`, failing unless it was sealed under the key and left
+    /// unaltered.
+    pub fn decrypt(&self, encrypted: &[u8]) -> Result<Vec<u8>> {
+        let Some(sealed) = encrypted.strip_prefix(&ENCRYPTED_MAGIC[..]) else {
+            bail!("not an encrypted output");
+        };
+        if sealed.len() < NONCE_LEN {
+            bail!("truncated encrypted output");
+        }
+        let (nonce, sealed) = sealed.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).unwrap();
+        let mut sealed = sealed.to_vec();
+        let plaintext = self
+            .0
+            .open_in_place(nonce, Aad::from(ENCRYPTED_MAGIC), &mut sealed)
+            .map_err(|_| anyhow!("the output is encrypted with another key, or was altered"))?;
+        Ok(plaintext.to_vec())
+    }
+}
+
+/// The content of the output at `path`, decrypted with `key` when it starts with the
+/// magic bytes of the encrypted outputs, then decompressed.
+pub fn read_output(path: &Path, key: Option<&OutputKey>) -> Result<Vec<u8>> {
+    let bytes = fs::read(path)?;
+    if !bytes.starts_with(&ENCRYPTED_MAGIC) {
+        return decompress(bytes);
+    }
+    let key = key.context("the file is encrypted, its key is needed")?;
+    decompress(key.decrypt(&bytes)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outputs_only_open_with_their_key() {
+        let key = OutputKey::from_hex(&"11".repeat(32)).unwrap();
+        let encrypted = key.encrypt(b"{\"proof\": \"0x01\"}").unwrap();
+        assert!(encrypted.starts_with(&ENCRYPTED_MAGIC));
+        assert_eq!(key.decrypt(&encrypted).unwrap(), b"{\"proof\": \"0x01\"}");
+        // a fresh nonce for every output
+        assert_ne!(key.encrypt(b"{\"proof\": \"0x01\"}").unwrap(), encrypted);
+
+        let other = OutputKey::from_hex(&format!("0x{}", "22".repeat(32))).unwrap();
+        assert!(other.decrypt(&encrypted).is_err());
+        let mut altered = encrypted.clone();
+        *altered.last_mut().unwrap() ^= 1;
+        assert!(key.decrypt(&altered).is_err());
+        assert!(OutputKey::from_hex(&"11".repeat(16)).is_err());
+
+        assert_eq!(
+            "env:OUTPUT_KEY".parse::<KeySource>(),
+            Ok(KeySource::Env("OUTPUT_KEY".to_owned()))
+        );
+        assert_eq!(
+            "file:/run/secrets/output.key".parse::<KeySource>(),
+            Ok(KeySource::File("/run/secrets/output.key".into()))
+        );
+        assert!("kms:alias/raiko".parse::<KeySource>().is_err());
+        assert!("env:".parse::<KeySource>().is_err());
+    }
+}
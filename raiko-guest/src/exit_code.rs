@@ -0,0 +1,77 @@
+use std::fmt;
+
+/// Failure classes of the guest CLI, each reported with its own stable process exit code
+/// so that wrapping scripts can tell a retryable failure from a bad input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    InvalidArgs = 2,
+    InvalidBlockData = 3,
+    DataIntegrity = 4,
+    Attestation = 5,
+    Proving = 6,
+    Secrets = 7,
+}
+
+/// Exit code of errors without a failure class, such as panics.
+pub const UNKNOWN_FAILURE_CODE: u8 = 1;
+
+pub const EXIT_CODES_HELP: &str = "\
+Exit codes:
+  0  success
+  1  unclassified failure
+  2  invalid arguments
+  3  invalid block data
+  4  RPC or data integrity failure
+  5  SGX or attestation failure
+  6  proving failure
+  7  IO or secrets failure";
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Failure::InvalidArgs => "invalid arguments",
+            Failure::InvalidBlockData => "invalid block data",
+            Failure::DataIntegrity => "data integrity check failed",
+            Failure::Attestation => "SGX attestation failed",
+            Failure::Proving => "proving failed",
+            Failure::Secrets => "secrets IO failed",
+        })
+    }
+}
+
+/// Exit code of the error, taken from the outermost [`Failure`] it was tagged with
+/// through `anyhow::Context`.
+pub fn exit_code(err: &anyhow::Error) -> u8 {
+    err.downcast_ref::<Failure>()
+        .map(|failure| *failure as u8)
+        .unwrap_or(UNKNOWN_FAILURE_CODE)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{anyhow, Context};
+    use clap::Parser;
+
+    use super::*;
+    use crate::app_args::App;
+
+    #[test]
+    fn exit_code_follows_the_outermost_failure() {
+        let err = anyhow!("boom");
+        assert_eq!(exit_code(&err), UNKNOWN_FAILURE_CODE);
+
+        let err = err.context(Failure::DataIntegrity);
+        assert_eq!(exit_code(&err), 4);
+
+        let err = err
+            .context("while proving block 1")
+            .context(Failure::Proving);
+        assert_eq!(exit_code(&err), 6);
+    }
+
+    #[test]
+    fn invalid_args_exit_with_code_2() {
+        let err = App::try_parse_from(["raiko-guest", "one-shot", "--prover", "nope"]).unwrap_err();
+        assert_eq!(err.exit_code(), Failure::InvalidArgs as i32);
+    }
+}
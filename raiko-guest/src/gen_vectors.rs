@@ -0,0 +1,181 @@
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use secp256k1::SecretKey;
+use serde_json::json;
+use zeth_lib::{consts::get_taiko_chain_spec, host::provider::RpcOptions};
+use zeth_primitives::{keccak256, taiko::PublicInputs};
+
+use crate::{
+    app_args::GenVectorsArgs,
+    exit_code::Failure,
+    one_shot::{
+        get_data_to_sign, proof_report, sign_proof, write_proof_json, write_public_inputs, L1Source,
+    },
+    signature::{public_key, public_key_to_address},
+};
+
+/// Secret key signing the test vectors in place of the sealed key of an instance, so that
+/// the vectors come out the same on every run. Never registered on any chain.
+const TEST_SECRET_KEY: [u8; 32] = [0x42; 32];
+
+/// Proves each block of the fixtures directory with the test key, writing the
+/// `inputs.json`, `proof.json` and `public_inputs.json` of each to `{out}/{block}`.
+pub async fn gen_vectors(args: GenVectorsArgs) -> Result<()> {
+    let fixtures = fixture_blocks(&args.fixtures)
+        .with_context(|| format!("Failed to list the fixtures in {}", args.fixtures.display()))
+        .context(Failure::InvalidArgs)?;
+    let l2_chain_spec = get_taiko_chain_spec(&args.l2_chain);
+    let secret_key = SecretKey::from_slice(&TEST_SECRET_KEY)?;
+    let instance = public_key_to_address(&public_key(&secret_key));
+    for (block_no, l2_file, l1_file) in fixtures {
+        status!("Generating the vector of block {}", block_no);
+        let (pi, ..) = get_data_to_sign(
+            &l2_chain_spec,
+            l2_file.to_string_lossy().to_string(),
+            L1Source::File(l1_file.to_string_lossy().to_string()),
+            RpcOptions::default(),
+            args.prover,
+            args.graffiti,
+            block_no,
+            None,
+            None,
+            None,
+            &mut None,
+        )
+        .await
+        .with_context(|| format!("Failed to prove fixture block {}", block_no))?;
+        let inputs = json!({
+            "blockNo": block_no,
+            "l2Chain": args.l2_chain,
+            "prover": args.prover,
+            "graffiti": args.graffiti,
+            "sgxInstanceId": args.sgx_instance_id,
+            "timestamp": args.timestamp,
+            "l2BlocksDataHash": keccak256(fs::read(&l2_file)?),
+            "l1BlocksDataHash": keccak256(fs::read(&l1_file)?),
+        });
+        write_vector(
+            &args.out.join(block_no.to_string()),
+            &secret_key,
+            &inputs,
+            &pi.public_inputs(instance),
+            args.sgx_instance_id,
+            args.timestamp,
+        )
+        .context(Failure::Secrets)?;
+    }
+    Ok(())
+}
+
+/// The fixture blocks of `dir` with their L2 and L1 block data files, by block number.
+fn fixture_blocks(dir: &Path) -> Result<Vec<(u64, PathBuf, PathBuf)>> {
+    let mut blocks = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let block_no = [".l2.json.gz", ".json.gz"]
+            .iter()
+            .find_map(|suffix| file_name.strip_suffix(suffix))
+            .and_then(|block_no| block_no.parse::<u64>().ok());
+        if let Some(block_no) = block_no {
+            let l1_file = dir.join(format!("{}.l1.json.gz", block_no));
+            blocks.push((block_no, path, l1_file));
+        }
+    }
+    blocks.sort();
+    Ok(blocks)
+}
+
+/// Writes the test vector of a block to `dir`, its proof signed with `secret_key`.
+fn write_vector(
+    dir: &Path,
+    secret_key: &SecretKey,
+    inputs: &serde_json::Value,
+    public_inputs: &PublicInputs,
+    sgx_instance_id: u32,
+    timestamp: u64,
+) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    let (proof, envelope) =
+        sign_proof(secret_key, sgx_instance_id, public_inputs.hash(), timestamp)?;
+    let proof = format!("0x{}", hex::encode(proof));
+    let report = proof_report(&proof, &public_key(secret_key), &envelope, public_inputs);
+    write_proof_json(&mut File::create(dir.join("inputs.json"))?, inputs)?;
+    write_proof_json(&mut File::create(dir.join("proof.json"))?, &report)?;
+    write_public_inputs(&dir.join("public_inputs.json"), public_inputs)
+}
+
+#[cfg(test)]
+mod tests {
+    use zeth_primitives::{Address, B256};
+
+    use super::*;
+
+    #[test]
+    fn regenerated_vectors_are_byte_stable() {
+        let secret_key = SecretKey::from_slice(&TEST_SECRET_KEY).unwrap();
+        let public_inputs = PublicInputs {
+            parent_hash: B256::repeat_byte(0x01),
+            block_hash: B256::repeat_byte(0x02),
+            signal_root: B256::repeat_byte(0x03),
+            graffiti: B256::ZERO,
+            new_instance: public_key_to_address(&public_key(&secret_key)),
+            prover: Address::repeat_byte(0x04),
+            meta_hash: B256::repeat_byte(0x05),
+        };
+        let inputs = json!({ "blockNo": 1 });
+        let generate = || {
+            let dir = tempfile::tempdir().unwrap();
+            write_vector(dir.path(), &secret_key, &inputs, &public_inputs, 1, 0).unwrap();
+            ["inputs.json", "proof.json", "public_inputs.json"]
+                .map(|name| fs::read(dir.path().join(name)).unwrap())
+        };
+        let vector = generate();
+        assert_eq!(vector, generate());
+
+        let proof: serde_json::Value = serde_json::from_slice(&vector[1]).unwrap();
+        assert_eq!(
+            proof["instanceAddress"],
+            json!(public_inputs.new_instance.to_string())
+        );
+        let encoded: serde_json::Value = serde_json::from_slice(&vector[2]).unwrap();
+        assert_eq!(
+            encoded["abiEncoded"],
+            json!(format!("0x{}", hex::encode(public_inputs.abi_encode())))
+        );
+    }
+
+    #[test]
+    fn fixture_blocks_are_listed_by_block_number() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in [
+            "10.l2.json.gz",
+            "10.l1.json.gz",
+            "9.json.gz",
+            "9.l1.json.gz",
+            "chain",
+        ] {
+            fs::write(dir.path().join(name), b"").unwrap();
+        }
+        let blocks = fixture_blocks(dir.path()).unwrap();
+        assert_eq!(
+            blocks,
+            vec![
+                (
+                    9,
+                    dir.path().join("9.json.gz"),
+                    dir.path().join("9.l1.json.gz")
+                ),
+                (
+                    10,
+                    dir.path().join("10.l2.json.gz"),
+                    dir.path().join("10.l1.json.gz")
+                ),
+            ]
+        );
+    }
+}
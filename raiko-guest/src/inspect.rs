@@ -0,0 +1,445 @@
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::{json, Value};
+use zeth_primitives::{
+    taiko::{ProofEnvelope, PublicInputs},
+    Address, B256,
+};
+
+use crate::{
+    app_args::{InspectArgs, OutputFormat},
+    encryption::{read_output, OutputKey},
+    exit_code::Failure,
+    signature::recover_signer_unchecked,
+};
+
+/// The proof files `inspect` reads, told apart by their fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ProofFormat {
+    /// The `--output` of `one-shot`, with the envelope and the public inputs.
+    ProofReport,
+    /// A bare proof envelope.
+    Envelope,
+    /// A proof delivered by the file or stdout sinks of the host.
+    SinkResult,
+}
+
+impl ProofFormat {
+    fn detect(file: &Value) -> Option<Self> {
+        let has = |field| file.get(field).is_some();
+        if has("envelope") && has("proof") {
+            Some(ProofFormat::ProofReport)
+        } else if has("signature") && has("signer") && has("publicInputs") {
+            Some(ProofFormat::Envelope)
+        } else if has("chain") && has("block") && has("proof") {
+            Some(ProofFormat::SinkResult)
+        } else {
+            None
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            ProofFormat::ProofReport => "proof-report",
+            ProofFormat::Envelope => "envelope",
+            ProofFormat::SinkResult => "sink-result",
+        }
+    }
+}
+
+/// An sgx proof: the instance id, the instance address and its signature of the public
+/// inputs hash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SgxProof {
+    instance_id: u32,
+    instance: Address,
+    signature: [u8; 65],
+}
+
+impl SgxProof {
+    fn decode(proof: &[u8]) -> Result<Self> {
+        if proof.len() != 89 {
+            bail!("sgx proofs have 89 bytes, got {}", proof.len());
+        }
+        Ok(SgxProof {
+            instance_id: u32::from_be_bytes(proof[..4].try_into()?),
+            instance: Address::from_slice(&proof[4..24]),
+            signature: proof[24..].try_into()?,
+        })
+    }
+
+    /// The address which signed `pi_hash` into the proof.
+    fn recover_signer(&self, pi_hash: B256) -> Result<Address> {
+        let mut signature = self.signature;
+        signature[64] = signature[64]
+            .checked_sub(27)
+            .ok_or_else(|| anyhow!("v invalid: {}", signature[64]))?;
+        Ok(recover_signer_unchecked(&signature, &pi_hash.0)?)
+    }
+}
+
+/// What is known of a proof file.
+struct Inspected {
+    format: ProofFormat,
+    proof: SgxProof,
+    envelope: Option<ProofEnvelope>,
+    public_inputs: Option<PublicInputs>,
+    /// the block number, prover and graffiti of the sink results
+    request: Option<Value>,
+}
+
+impl Inspected {
+    fn read(path: &Path, key: Option<&OutputKey>) -> Result<Self> {
+        Self::parse(serde_json::from_slice(&read_output(path, key)?)?)
+    }
+
+    fn parse(file: Value) -> Result<Self> {
+        let format = ProofFormat::detect(&file).context("Unknown proof file format")?;
+        let (proof, envelope, public_inputs, request) = match format {
+            ProofFormat::ProofReport => (
+                hex_bytes(&file["proof"])?,
+                Some(serde_json::from_value(file["envelope"].clone())?),
+                // not in the proofs of the older versions
+                file.get("publicInputs")
+                    .map(|public_inputs| serde_json::from_value(public_inputs.clone()))
+                    .transpose()?,
+                None,
+            ),
+            ProofFormat::Envelope => {
+                let envelope: ProofEnvelope = serde_json::from_value(file)?;
+                (envelope.proof.to_vec(), Some(envelope), None, None)
+            }
+            ProofFormat::SinkResult => (
+                hex_bytes(&file["proof"])?,
+                None,
+                None,
+                Some(json!({
+                    "chain": file["chain"],
+                    "block": file["block"],
+                    "prover": file["prover"],
+                    "graffiti": file["graffiti"],
+                })),
+            ),
+        };
+        Ok(Inspected {
+            format,
+            proof: SgxProof::decode(&proof)?,
+            envelope,
+            public_inputs,
+            request,
+        })
+    }
+
+    fn summary(&self) -> Value {
+        let mut summary = json!({
+            "format": self.format.name(),
+            "backend": "sgx",
+            "instanceId": self.proof.instance_id,
+            "instanceAddress": self.proof.instance,
+        });
+        if let Some(public_inputs) = &self.public_inputs {
+            summary["blockHash"] = json!(public_inputs.block_hash);
+            summary["parentHash"] = json!(public_inputs.parent_hash);
+            summary["signalRoot"] = json!(public_inputs.signal_root);
+            summary["prover"] = json!(public_inputs.prover);
+            summary["graffiti"] = json!(public_inputs.graffiti);
+            summary["metaHash"] = json!(public_inputs.meta_hash);
+            summary["publicInputsHash"] = json!(public_inputs.hash());
+        }
+        if let Some(envelope) = &self.envelope {
+            summary["publicInputsHash"] = json!(envelope.public_inputs);
+            summary["signedAt"] = json!(envelope.timestamp);
+        }
+        if let Some(request) = &self.request {
+            summary["block"] = request["block"].clone();
+            summary["chain"] = request["chain"].clone();
+            summary["prover"] = request["prover"].clone();
+            summary["graffiti"] = request["graffiti"].clone();
+        }
+        summary
+    }
+
+    /// Checks the signatures of the proof and of its envelope, and that they are of the
+    /// public inputs, as far as the file holds them.
+    fn verify(&self) -> Result<()> {
+        let computed = self.public_inputs.as_ref().map(PublicInputs::hash);
+        if let Some(public_inputs) = &self.public_inputs {
+            if public_inputs.new_instance != self.proof.instance {
+                bail!(
+                    "proof of instance {}, the public inputs are of {}",
+                    self.proof.instance,
+                    public_inputs.new_instance
+                );
+            }
+        }
+        if let Some(envelope) = &self.envelope {
+            envelope.verify()?;
+            if envelope.proof[..] != self.proof_bytes()[..] {
+                bail!("the envelope holds another proof");
+            }
+            if computed.is_some_and(|computed| computed != envelope.public_inputs) {
+                bail!("the envelope is of other public inputs");
+            }
+        }
+        let signed_hash = self
+            .envelope
+            .as_ref()
+            .map(|envelope| envelope.public_inputs);
+        let Some(pi_hash) = computed.or(signed_hash) else {
+            bail!("the proof file holds neither the public inputs nor their hash");
+        };
+        let signer = self.proof.recover_signer(pi_hash)?;
+        if signer != self.proof.instance {
+            bail!(
+                "proof signed by {}, not by instance {}",
+                signer,
+                self.proof.instance
+            );
+        }
+        Ok(())
+    }
+
+    fn proof_bytes(&self) -> Vec<u8> {
+        let mut proof = self.proof.instance_id.to_be_bytes().to_vec();
+        proof.extend(self.proof.instance);
+        proof.extend(self.proof.signature);
+        proof
+    }
+}
+
+fn hex_bytes(value: &Value) -> Result<Vec<u8>> {
+    let hex_str = value.as_str().context("proof is not a hex string")?;
+    Ok(hex::decode(hex_str.trim_start_matches("0x"))?)
+}
+
+/// The summary of the proof file `file`, as printed by `inspect`, or `None` when it is
+/// not a proof file.
+pub(crate) fn proof_summary(file: Value) -> Option<Result<Value>> {
+    ProofFormat::detect(&file)?;
+    Some(Inspected::parse(file).map(|inspected| inspected.summary()))
+}
+
+/// Prints the summary of a proof file, checking its signatures with `--verify`.
+pub fn inspect(args: InspectArgs) -> Result<()> {
+    let key = match &args.output_encryption_key_source {
+        Some(source) => Some(
+            source
+                .load()
+                .context("Failed to load the output encryption key")
+                .context(Failure::Secrets)?,
+        ),
+        None => None,
+    };
+    let summary = inspect_proof(&args.input, key.as_ref(), args.verify)?;
+    match args.format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&summary)?),
+        OutputFormat::Text => {
+            for (field, value) in summary.as_object().unwrap() {
+                match value.as_str() {
+                    Some(value) => println!("{}: {}", field, value),
+                    None => println!("{}: {}", field, value),
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn inspect_proof(path: &Path, key: Option<&OutputKey>, verify: bool) -> Result<Value> {
+    let inspected = Inspected::read(path, key)
+        .with_context(|| format!("Failed to read the proof file {}", path.display()))
+        .context(Failure::InvalidArgs)?;
+    let mut summary = inspected.summary();
+    if verify {
+        inspected
+            .verify()
+            .context("The proof does not verify")
+            .context(Failure::DataIntegrity)?;
+        summary["verified"] = true.into();
+    }
+    Ok(summary)
+}
+
+/// Checks that `path` holds a verifying proof of `block_no` for `prover` and `graffiti`,
+/// as the `--output` of `one-shot` encrypted with `key` if any, and returns the block
+/// hash it proves.
+pub(crate) fn verify_proof_of_block(
+    path: &Path,
+    key: Option<&OutputKey>,
+    block_no: u64,
+    prover: Address,
+    graffiti: B256,
+) -> Result<B256> {
+    let file: Value = serde_json::from_slice(&read_output(path, key)?)?;
+    if file.get("block") != Some(&json!(block_no)) {
+        bail!("not a proof of block {}", block_no);
+    }
+    let inspected = Inspected::parse(file)?;
+    let Some(public_inputs) = &inspected.public_inputs else {
+        bail!("the proof file holds no public inputs");
+    };
+    if public_inputs.prover != prover || public_inputs.graffiti != graffiti {
+        bail!(
+            "proof for prover {} and graffiti {}",
+            public_inputs.prover,
+            public_inputs.graffiti
+        );
+    }
+    let block_hash = public_inputs.block_hash;
+    inspected.verify()?;
+    Ok(block_hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{
+        app_args::OutputCompression,
+        compression::read_decompressed,
+        encryption::KeySource,
+        exit_code::exit_code,
+        one_shot::{proof_report, sign_proof, write_proof_output},
+        signature::{generate_key, public_key, public_key_to_address},
+    };
+
+    fn public_inputs(new_instance: Address) -> PublicInputs {
+        PublicInputs {
+            parent_hash: B256::repeat_byte(0x01),
+            block_hash: B256::repeat_byte(0x02),
+            signal_root: B256::repeat_byte(0x03),
+            graffiti: B256::repeat_byte(0x04),
+            new_instance,
+            prover: Address::repeat_byte(0x05),
+            meta_hash: B256::repeat_byte(0x06),
+        }
+    }
+
+    #[test]
+    fn inspecting_a_fresh_proof_reports_its_block_and_prover() {
+        let dir = tempfile::tempdir().unwrap();
+        let privkey = generate_key().secret_key();
+        let new_pubkey = public_key(&privkey);
+        let public_inputs = public_inputs(public_key_to_address(&new_pubkey));
+        let (proof, envelope) =
+            sign_proof(&privkey, 7, public_inputs.hash(), 1_700_000_000).unwrap();
+        let proof = format!("0x{}", hex::encode(proof));
+        let report = proof_report(&proof, &new_pubkey, &envelope, &public_inputs);
+        let path = dir.path().join("proof.json");
+        fs::write(&path, report.to_string()).unwrap();
+
+        let summary = inspect_proof(&path, None, true).unwrap();
+        assert_eq!(summary["format"], "proof-report");
+        assert_eq!(summary["blockHash"], json!(B256::repeat_byte(0x02)));
+        assert_eq!(summary["prover"], json!(Address::repeat_byte(0x05)));
+        assert_eq!(summary["instanceId"], 7);
+        assert_eq!(summary["instanceAddress"], json!(envelope.signer));
+        assert_eq!(summary["verified"], true);
+
+        // the bare envelope is recognized too, but only carries the public inputs hash
+        let envelope_path = dir.path().join("envelope.json");
+        fs::write(&envelope_path, serde_json::to_vec(&envelope).unwrap()).unwrap();
+        let summary = inspect_proof(&envelope_path, None, true).unwrap();
+        assert_eq!(summary["format"], "envelope");
+        assert_eq!(summary["publicInputsHash"], json!(public_inputs.hash()));
+
+        // a proof of other public inputs does not verify
+        let mut tampered = report.clone();
+        tampered["publicInputs"]["blockHash"] = json!(B256::repeat_byte(0x09));
+        fs::write(&path, tampered.to_string()).unwrap();
+        assert!(inspect_proof(&path, None, false).is_ok());
+        let err = inspect_proof(&path, None, true).unwrap_err();
+        assert_eq!(exit_code(&err), Failure::DataIntegrity as u8);
+
+        fs::write(&path, b"{\"foo\": 1}").unwrap();
+        let err = inspect_proof(&path, None, false).unwrap_err();
+        assert_eq!(exit_code(&err), Failure::InvalidArgs as u8);
+    }
+
+    #[test]
+    fn compressed_proofs_are_inspected_as_they_are() {
+        let dir = tempfile::tempdir().unwrap();
+        let privkey = generate_key().secret_key();
+        let new_pubkey = public_key(&privkey);
+        let public_inputs = public_inputs(public_key_to_address(&new_pubkey));
+        let (proof, envelope) =
+            sign_proof(&privkey, 7, public_inputs.hash(), 1_700_000_000).unwrap();
+        let proof = format!("0x{}", hex::encode(proof));
+        let mut report = proof_report(&proof, &new_pubkey, &envelope, &public_inputs);
+        report["block"] = 12345.into();
+
+        for (compression, file_name) in [
+            (OutputCompression::Gzip, "proof.json.gz"),
+            (OutputCompression::Zstd, "proof.json.zst"),
+        ] {
+            let path = compression.output_path(&dir.path().join("proof.json"));
+            assert_eq!(path, dir.path().join(file_name));
+            write_proof_output(&path, compression, None, &report).unwrap();
+            assert!(fs::read(&path).unwrap().len() < report.to_string().len());
+
+            let summary = inspect_proof(&path, None, true).unwrap();
+            assert_eq!(summary["format"], "proof-report");
+            assert_eq!(summary["verified"], true);
+            verify_proof_of_block(
+                &path,
+                None,
+                12345,
+                public_inputs.prover,
+                public_inputs.graffiti,
+            )
+            .unwrap();
+            let decompressed: Value =
+                serde_json::from_slice(&read_decompressed(&path).unwrap()).unwrap();
+            assert_eq!(decompressed["compression"], compression.name());
+        }
+    }
+
+    #[test]
+    fn encrypted_proofs_are_inspected_with_their_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let privkey = generate_key().secret_key();
+        let new_pubkey = public_key(&privkey);
+        let public_inputs = public_inputs(public_key_to_address(&new_pubkey));
+        let (proof, envelope) =
+            sign_proof(&privkey, 7, public_inputs.hash(), 1_700_000_000).unwrap();
+        let proof = format!("0x{}", hex::encode(proof));
+        let mut report = proof_report(&proof, &new_pubkey, &envelope, &public_inputs);
+        report["block"] = 12345.into();
+        let key_path = dir.path().join("output.key");
+        fs::write(&key_path, format!("{}\n", "ab".repeat(32))).unwrap();
+        let key = KeySource::File(key_path).load().unwrap();
+
+        let path = OutputCompression::Gzip.output_path(&dir.path().join("proof.json"));
+        write_proof_output(&path, OutputCompression::Gzip, Some(&key), &report).unwrap();
+        let written = fs::read(&path).unwrap();
+        assert!(!written
+            .windows(proof.len())
+            .any(|window| window == proof.as_bytes()));
+        assert!(serde_json::from_slice::<Value>(&read_decompressed(&path).unwrap()).is_err());
+
+        let summary = inspect_proof(&path, Some(&key), true).unwrap();
+        assert_eq!(summary["format"], "proof-report");
+        assert_eq!(summary["verified"], true);
+        verify_proof_of_block(
+            &path,
+            Some(&key),
+            12345,
+            public_inputs.prover,
+            public_inputs.graffiti,
+        )
+        .unwrap();
+        let decrypted: Value =
+            serde_json::from_slice(&read_output(&path, Some(&key)).unwrap()).unwrap();
+        assert_eq!(decrypted["encryption"], "aes-256-gcm");
+        assert_eq!(decrypted["compression"], "gzip");
+        assert_eq!(decrypted["proof"], report["proof"]);
+
+        // neither without the key nor with another one
+        let err = inspect_proof(&path, None, true).unwrap_err();
+        assert_eq!(exit_code(&err), Failure::InvalidArgs as u8);
+        let other = OutputKey::from_hex(&"cd".repeat(32)).unwrap();
+        assert!(inspect_proof(&path, Some(&other), true).is_err());
+    }
+}
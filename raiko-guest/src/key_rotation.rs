@@ -0,0 +1,178 @@
+//! Rotation of the instance key with an activation delay: the new instance takes time to
+//! be registered on chain, and the proofs it signs meanwhile would be rejected. Rotated
+//! with `--new-key-activation-delay-secs`, the new key is stored as pending next to the
+//! active one, which keeps signing until the delay elapses or `activate-key` is run.
+//! `quote --pending` attests the pending key, for it to be registered during the delay.
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use secp256k1::SecretKey;
+
+use crate::{
+    one_shot::PRIV_KEY_FILENAME,
+    signature::{load_private_key, save_private_key},
+};
+
+pub const PENDING_KEY_FILENAME: &str = "priv.key.pending";
+/// Holds the unix timestamp the pending key signs from.
+pub const PENDING_KEY_ACTIVATION_FILENAME: &str = "priv.key.pending.activation";
+
+/// A rotated key waiting for its activation.
+#[derive(Debug)]
+pub struct PendingKey {
+    pub secret: SecretKey,
+    pub activates_at: u64,
+}
+
+/// Stores `secret` as the pending key, signing from `activates_at` on.
+pub fn save_pending_key(
+    secrets_dir: &Path,
+    secret: &SecretKey,
+    activates_at: u64,
+) -> Result<PathBuf> {
+    // the activation time first, a key without one is not pending
+    let activation_path = secrets_dir.join(PENDING_KEY_ACTIVATION_FILENAME);
+    fs::write(&activation_path, activates_at.to_string())
+        .with_context(|| format!("Failed to write {}", activation_path.display()))?;
+    let path = secrets_dir.join(PENDING_KEY_FILENAME);
+    save_private_key(&path, secret)?;
+    Ok(path)
+}
+
+/// The pending key, if a key was rotated and not activated yet.
+pub fn load_pending_key(secrets_dir: &Path) -> Result<Option<PendingKey>> {
+    let path = secrets_dir.join(PENDING_KEY_FILENAME);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let activation_path = secrets_dir.join(PENDING_KEY_ACTIVATION_FILENAME);
+    let pending = fs::read_to_string(&activation_path)
+        .with_context(|| format!("Failed to read {}", activation_path.display()))
+        .and_then(|activates_at| {
+            activates_at.trim().parse().with_context(|| {
+                format!("Invalid activation time in {}", activation_path.display())
+            })
+        })
+        .and_then(|activates_at| {
+            Ok(PendingKey {
+                secret: load_private_key(&path)?,
+                activates_at,
+            })
+        });
+    match pending {
+        Ok(pending) => Ok(Some(pending)),
+        // activated by a concurrent run meanwhile
+        Err(_) if !path.exists() => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Removes the file, already removed by a concurrent run or not.
+fn remove_key_file(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != ErrorKind::NotFound => {
+            Err(err).with_context(|| format!("Failed to remove {}", path.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Drops the pending key, e.g. when a key is rotated without a delay.
+pub fn discard_pending_key(secrets_dir: &Path) -> Result<()> {
+    for filename in [PENDING_KEY_FILENAME, PENDING_KEY_ACTIVATION_FILENAME] {
+        remove_key_file(&secrets_dir.join(filename))?;
+    }
+    Ok(())
+}
+
+/// Makes `pending` the active key. The runs proving concurrently may all activate it,
+/// the ones finding it already moved leave it be.
+fn activate(secrets_dir: &Path, pending: PendingKey) -> Result<SecretKey> {
+    let privkey_path = secrets_dir.join(PRIV_KEY_FILENAME);
+    match fs::rename(secrets_dir.join(PENDING_KEY_FILENAME), &privkey_path) {
+        Err(err) if err.kind() != ErrorKind::NotFound => {
+            return Err(err)
+                .with_context(|| format!("Failed to replace {}", privkey_path.display()))
+        }
+        _ => remove_key_file(&secrets_dir.join(PENDING_KEY_ACTIVATION_FILENAME))?,
+    }
+    Ok(pending.secret)
+}
+
+/// Makes the pending key the active one, returning it.
+pub fn activate_pending_key(secrets_dir: &Path) -> Result<SecretKey> {
+    let Some(pending) = load_pending_key(secrets_dir)? else {
+        bail!("No pending key in {}", secrets_dir.display());
+    };
+    activate(secrets_dir, pending)
+}
+
+/// The key signing at `now`: the pending key once its activation time is reached, which
+/// becomes the active one, or else the active key.
+pub fn signing_key(secrets_dir: &Path, now: u64) -> Result<SecretKey> {
+    match load_pending_key(secrets_dir)? {
+        Some(pending) if pending.activates_at <= now => activate(secrets_dir, pending),
+        _ => load_private_key(secrets_dir.join(PRIV_KEY_FILENAME)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signature::generate_key;
+
+    #[test]
+    fn the_old_key_signs_until_the_new_one_is_activated() {
+        let dir = tempfile::tempdir().unwrap();
+        let old = generate_key().secret_key();
+        save_private_key(dir.path().join(PRIV_KEY_FILENAME), &old).unwrap();
+        assert_eq!(signing_key(dir.path(), 1_000).unwrap(), old);
+
+        // rotated at 1000 with a delay of 600s
+        let new = generate_key().secret_key();
+        save_pending_key(dir.path(), &new, 1_600).unwrap();
+        assert_eq!(signing_key(dir.path(), 1_000).unwrap(), old);
+        assert_eq!(signing_key(dir.path(), 1_599).unwrap(), old);
+        assert_eq!(load_pending_key(dir.path()).unwrap().unwrap().secret, new);
+
+        // the new key signs once the delay elapsed, for good
+        assert_eq!(signing_key(dir.path(), 1_600).unwrap(), new);
+        assert!(load_pending_key(dir.path()).unwrap().is_none());
+        assert_eq!(signing_key(dir.path(), 1_000).unwrap(), new);
+
+        // or once activated explicitly
+        let newer = generate_key().secret_key();
+        save_pending_key(dir.path(), &newer, 5_000).unwrap();
+        assert_eq!(activate_pending_key(dir.path()).unwrap(), newer);
+        assert_eq!(signing_key(dir.path(), 2_000).unwrap(), newer);
+        assert!(activate_pending_key(dir.path()).is_err());
+    }
+
+    #[test]
+    fn concurrent_runs_all_sign_with_the_activated_key() {
+        let dir = tempfile::tempdir().unwrap();
+        save_private_key(
+            dir.path().join(PRIV_KEY_FILENAME),
+            &generate_key().secret_key(),
+        )
+        .unwrap();
+        let new = generate_key().secret_key();
+        save_pending_key(dir.path(), &new, 1_000).unwrap();
+
+        let runs: Vec<_> = (0..8)
+            .map(|_| {
+                let dir = dir.path().to_owned();
+                std::thread::spawn(move || signing_key(&dir, 2_000))
+            })
+            .collect();
+        for run in runs {
+            assert_eq!(run.join().unwrap().unwrap(), new);
+        }
+        assert!(load_pending_key(dir.path()).unwrap().is_none());
+        assert_eq!(signing_key(dir.path(), 2_000).unwrap(), new);
+    }
+}
@@ -1,35 +1,86 @@
 #![feature(path_file_prefix)]
 
+#[macro_use]
+mod output;
+
 mod app_args;
+mod compression;
+mod convert;
+mod diff;
+mod encryption;
+mod exit_code;
+mod gen_vectors;
+mod inspect;
+mod key_rotation;
+mod manifest;
 mod one_shot;
+mod profile;
+mod progress;
+mod public_inputs;
 mod ratls_server;
 mod signature;
 
 extern crate rand;
 extern crate secp256k1;
 
-use anyhow::Result;
-use app_args::{App, Command};
+use std::process::ExitCode;
+
+use anyhow::{Context, Result};
+use app_args::{App, Command, OutputFormat};
 use clap::Parser;
-use one_shot::{bootstrap, one_shot};
+use convert::convert;
+use diff::diff;
+use exit_code::{exit_code, Failure};
+use gen_vectors::gen_vectors;
+use inspect::inspect;
+use one_shot::{activate_key, bootstrap, migrate_keys, one_shot, quote};
+use public_inputs::public_inputs;
 use ratls_server::ratls_server;
 
 #[tokio::main]
-pub async fn main() -> Result<()> {
+pub async fn main() -> ExitCode {
+    // clap exits with code 2 on invalid arguments
     let args = App::parse();
 
+    match run(args).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {:?}", err);
+            ExitCode::from(exit_code(&err))
+        }
+    }
+}
+
+async fn run(args: App) -> Result<()> {
+    output::set_quiet(args.global_opts.quiet);
+
     match args.command {
         Command::Server(server_args) => {
-            println!("Starting RA-TLS server - listening on {}", server_args.addr);
+            status!("Starting RA-TLS server - listening on {}", server_args.addr);
             ratls_server(args.global_opts, server_args);
         }
         Command::OneShot(one_shot_args) => {
-            println!("Starting one shot mode");
+            status!("Starting one shot mode");
             one_shot(args.global_opts, one_shot_args).await?
         }
-        Command::Bootstrap => {
-            println!("Bootstrapping the app");
-            bootstrap(args.global_opts)?
+        Command::Bootstrap(bootstrap_args) => {
+            if bootstrap_args.format == OutputFormat::Text {
+                status!("Bootstrapping the app");
+            }
+            bootstrap(args.global_opts, bootstrap_args)?
+        }
+        Command::MigrateKeys(migrate_keys_args) => {
+            status!("Migrating the private key");
+            migrate_keys(args.global_opts, migrate_keys_args).context(Failure::Secrets)?
+        }
+        Command::ActivateKey => activate_key(args.global_opts).context(Failure::Secrets)?,
+        Command::Convert(convert_args) => convert(convert_args)?,
+        Command::Inspect(inspect_args) => inspect(inspect_args)?,
+        Command::Diff(diff_args) => diff(diff_args)?,
+        Command::GenVectors(gen_vectors_args) => gen_vectors(gen_vectors_args).await?,
+        Command::Quote(quote_args) => quote(args.global_opts, quote_args)?,
+        Command::PublicInputs(public_inputs_args) => {
+            public_inputs(args.global_opts, public_inputs_args).await?
         }
     }
 
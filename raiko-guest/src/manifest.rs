@@ -0,0 +1,80 @@
+//! The manifest of a batch of `one-shot` runs sharing a `--manifest` file: a JSON line
+//! per block, appended when its run ends, so that the submitter reads what was produced
+//! without scanning the output directories and a crashed batch keeps the lines of the
+//! blocks it completed.
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use zeth_primitives::B256;
+
+/// How the run of a block ended.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Proven,
+    /// the `--output` file already held a proof of the block
+    Skipped,
+    #[default]
+    Failed,
+}
+
+/// A line of the manifest, the fields unknown when the run failed before them missing.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestEntry {
+    pub block: Option<u64>,
+    pub block_hash: Option<B256>,
+    /// the `--output` file of the proof
+    pub output: Option<PathBuf>,
+    pub outcome: Outcome,
+    pub duration_ms: u64,
+    /// MRENCLAVE of the quote attesting the proof, hex encoded
+    pub mr_enclave: Option<String>,
+}
+
+/// Appends `entry` to the manifest at `path`, creating it for the first block of a batch.
+pub fn append_entry(path: &Path, entry: &ManifestEntry) -> io::Result<()> {
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    // a single write, for the runs of a batch sharing the file not to interleave
+    file.write_all(&line)?;
+    file.sync_data()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_are_appended_as_json_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("manifest.jsonl");
+        let proven = ManifestEntry {
+            block: Some(10),
+            block_hash: Some(B256::repeat_byte(0x02)),
+            output: Some(dir.path().join("10.json")),
+            outcome: Outcome::Proven,
+            duration_ms: 1200,
+            mr_enclave: Some("ab".repeat(32)),
+        };
+        append_entry(&path, &proven).unwrap();
+        append_entry(&path, &ManifestEntry::default()).unwrap();
+
+        let manifest = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<ManifestEntry> = manifest
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines, [proven, ManifestEntry::default()]);
+        assert!(manifest
+            .lines()
+            .nth(1)
+            .unwrap()
+            .contains(r#""outcome":"failed""#));
+    }
+}
@@ -1,26 +1,62 @@
 use std::{
+    fmt,
     fs::{self, File, OpenOptions},
-    io::prelude::*,
-    os::unix::fs::PermissionsExt,
-    path::Path,
+    io::{self, prelude::*},
+    path::{Path, PathBuf},
     str::FromStr,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::{anyhow, bail, Context, Error, Result};
+use secp256k1::{PublicKey, SecretKey};
+use serde::Serialize;
+use serde_json::json;
+#[cfg(feature = "trace")]
+use zeth_lib::trace::ExecutionTracer;
 use zeth_lib::{
-    consts::{get_taiko_chain_spec, ChainSpec, ETH_MAINNET_CHAIN_SPEC},
-    host::Init,
+    consts::{
+        check_chain_id_override, get_taiko_chain_spec, ChainSpec, ConsensusOverrides,
+        ReportDataLayout, StateOverrides, ETH_MAINNET_CHAIN_SPEC, REPORT_DATA_SIZE,
+    },
+    host::{
+        provider::RpcOptions,
+        validate::{check_data_age_of_file, validate_against_rpc},
+        Init,
+    },
     input::Input,
     taiko::{
         block_builder::{TaikoBlockBuilder, TaikoStrategyBundle},
-        host::TaikoExtra,
+        host::{
+            apply_state_overrides, apply_system_overrides, check_taiko_data, read_system_overrides,
+            TaikoExtra,
+        },
+        protocol_instance::verifier_calldata,
+        spec_mismatch::ChainSpecMismatch,
     },
     EthereumTxEssence,
 };
-use zeth_primitives::{taiko::EvidenceType, Address, B256};
+use zeth_primitives::{
+    block::Header,
+    keccak256,
+    taiko::{ProofEnvelope, ProtocolInstance, PublicInputs},
+    Address, B256, U256,
+};
 
 use crate::{
-    app_args::{GlobalOpts, OneShotArgs},
+    app_args::{
+        BootstrapArgs, ExistingOutput, GlobalOpts, MigrateKeysArgs, OneShotArgs, OutputCompression,
+        OutputFormat, QuoteArgs, TraceVerbosity, TxRange,
+    },
+    compression::compress,
+    encryption::{OutputKey, OUTPUT_CIPHER},
+    exit_code::Failure,
+    inspect::verify_proof_of_block,
+    key_rotation::{
+        activate_pending_key, discard_pending_key, load_pending_key, save_pending_key, signing_key,
+    },
+    manifest::{append_entry, ManifestEntry, Outcome},
+    profile::{end_phase, Profiler},
+    progress::Progress,
     signature::*,
 };
 
@@ -29,81 +65,902 @@ pub const ATTESTATION_TYPE_DEVICE_FILE: &str = "/dev/attestation/attestation_typ
 pub const ATTESTATION_USER_REPORT_DATA_DEVICE_FILE: &str = "/dev/attestation/user_report_data";
 pub const PRIV_KEY_FILENAME: &str = "priv.key";
 
-pub fn bootstrap(global_opts: GlobalOpts) -> Result<()> {
+pub fn bootstrap(global_opts: GlobalOpts, args: BootstrapArgs) -> Result<()> {
     let privkey_path = global_opts.secrets_dir.join(PRIV_KEY_FILENAME);
+    if privkey_path.exists() && !args.force {
+        return Err(anyhow!(
+            "{} already exists, pass --force to overwrite it",
+            privkey_path.display()
+        )
+        .context(Failure::Secrets));
+    }
     let key_pair = generate_key();
-    let mut file =
-        fs::File::create(&privkey_path).with_context(|| "Failed to create private key file")?;
-    let permissions = std::fs::Permissions::from_mode(0o600);
-    file.set_permissions(permissions)
-        .with_context(|| "Failed to set permissions to private key file")?;
-    file.write_all(&key_pair.secret_bytes())
-        .with_context(|| format!("Failed to write to {}", privkey_path.display()))?;
-    println!("Public key: 0x{}", key_pair.public_key());
-    let new_instance = public_key_to_address(&key_pair.public_key());
-    println!("Instance address: {}", new_instance);
+    let secrets_dir = &global_opts.secrets_dir;
+    // a rotated key signs once the delay elapsed, the active key meanwhile
+    let (key_path, activates_at) =
+        if privkey_path.exists() && args.new_key_activation_delay_secs > 0 {
+            let activates_at = unix_now(None)? + args.new_key_activation_delay_secs;
+            let path = save_pending_key(secrets_dir, &key_pair.secret_key(), activates_at)
+                .context(Failure::Secrets)?;
+            (path, Some(activates_at))
+        } else {
+            discard_pending_key(secrets_dir).context(Failure::Secrets)?;
+            save_private_key(&privkey_path, &key_pair.secret_key()).context(Failure::Secrets)?;
+            (privkey_path, None)
+        };
+    let public_key = key_pair.public_key();
+    let fingerprint = keccak256(public_key.serialize());
+    let new_instance = public_key_to_address(&public_key);
+
+    let mut report = json!({
+        "publicKey": format!("0x{}", public_key),
+        "fingerprint": format!("0x{}", hex::encode(fingerprint)),
+        "instanceAddress": new_instance.to_string(),
+        "keyFile": key_path.display().to_string(),
+    });
+    if let Some(activates_at) = activates_at {
+        report["activatesAt"] = activates_at.into();
+    }
+    // only available when running inside the enclave
+    if get_sgx_attestation_type().is_ok() {
+        let l2_chain_spec = args.l2_chain.as_deref().map(get_taiko_chain_spec);
+        let report_data = attestation_report_data(l2_chain_spec.as_ref(), &public_key)
+            .context(Failure::InvalidArgs)?;
+        save_attestation_user_report_data(&report_data).context(Failure::Attestation)?;
+        let quote = read_sgx_quote().context(Failure::Attestation)?;
+        report["mrEnclave"] = hex::encode(&quote[112..144]).into();
+        report["quote"] = hex::encode(&quote).into();
+    }
+
+    match args.format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+        OutputFormat::Text => {
+            println!("Public key: 0x{}", public_key);
+            println!("Fingerprint: {}", report["fingerprint"].as_str().unwrap());
+            println!("Instance address: {}", new_instance);
+            println!("Key file: {}", key_path.display());
+            if let Some(activates_at) = activates_at {
+                println!("Activates at: {} (unix time)", activates_at);
+            }
+            if let Some(mr_enclave) = report["mrEnclave"].as_str() {
+                println!("MRENCLAVE: {}", mr_enclave);
+                println!("Quote: {}", report["quote"].as_str().unwrap());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Prints a fresh quote over the active key, or the pending one, as a single JSON line,
+/// for the instance to be registered. The quote is generated on every read of the quote
+/// device.
+pub fn quote(global_opts: GlobalOpts, args: QuoteArgs) -> Result<()> {
+    let privkey = if args.pending {
+        match load_pending_key(&global_opts.secrets_dir).context(Failure::Secrets)? {
+            Some(pending) => pending.secret,
+            None => {
+                return Err(
+                    anyhow!("No pending key in {}", global_opts.secrets_dir.display())
+                        .context(Failure::Secrets),
+                )
+            }
+        }
+    } else {
+        let privkey_path = global_opts.secrets_dir.join(PRIV_KEY_FILENAME);
+        load_private_key(&privkey_path).context(Failure::Secrets)?
+    };
+    let pubkey = public_key(&privkey);
+    let instance = public_key_to_address(&pubkey);
+    let mut report = json!({
+        "publicKey": format!("0x{}", pubkey),
+        "fingerprint": format!("0x{}", hex::encode(keccak256(pubkey.serialize()))),
+        "instanceAddress": instance.to_string(),
+    });
+    // only available when running inside the enclave
+    if get_sgx_attestation_type().is_ok() {
+        let l2_chain_spec = args.l2_chain.as_deref().map(get_taiko_chain_spec);
+        let report_data = attestation_report_data(l2_chain_spec.as_ref(), &pubkey)
+            .context(Failure::InvalidArgs)?;
+        save_attestation_user_report_data(&report_data).context(Failure::Attestation)?;
+        let quote = read_sgx_quote().context(Failure::Attestation)?;
+        report["mrEnclave"] = hex::encode(&quote[112..144]).into();
+        report["mrSigner"] = hex::encode(&quote[176..208]).into();
+        report["quote"] = hex::encode(&quote).into();
+    }
+    write_proof_json(&mut io::stdout().lock(), &report)
+}
+
+/// Makes the pending key the active one, ahead of its activation time.
+pub fn activate_key(global_opts: GlobalOpts) -> Result<()> {
+    let privkey = activate_pending_key(&global_opts.secrets_dir)?;
+    status!(
+        "Activated the key of instance {}",
+        public_key_to_address(&public_key(&privkey))
+    );
+    Ok(())
+}
+
+pub fn migrate_keys(global_opts: GlobalOpts, args: MigrateKeysArgs) -> Result<()> {
+    let privkey_path = global_opts.secrets_dir.join(PRIV_KEY_FILENAME);
+    let legacy_path = args.from.unwrap_or_else(|| privkey_path.clone());
+    let in_place = legacy_path == privkey_path;
+    if !in_place && privkey_path.exists() {
+        bail!(
+            "{} already exists, refusing to overwrite it",
+            privkey_path.display()
+        );
+    }
+    let secret = load_legacy_private_key(&legacy_path)?;
+    save_private_key(&privkey_path, &secret)?;
+    // make sure the migrated key reads back before dropping the original
+    let migrated = load_private_key(&privkey_path)?;
+    if migrated != secret {
+        bail!("Migrated key does not match the original");
+    }
+    status!(
+        "Migrated {} to {}",
+        legacy_path.display(),
+        privkey_path.display()
+    );
+    if args.delete_original && !in_place {
+        shred_file(&legacy_path)?;
+        status!("Removed {}", legacy_path.display());
+    }
+    status!(
+        "Instance address: {}",
+        public_key_to_address(&public_key(&secret))
+    );
     Ok(())
 }
 
 pub async fn one_shot(global_opts: GlobalOpts, args: OneShotArgs) -> Result<()> {
-    if !is_bootstrapped(&global_opts.secrets_dir) {
-        bail!("Application was not bootstrapped. Bootstrap it first.")
+    let progress = match &args.progress_out {
+        Some(path) => Some(
+            Progress::open(path, args.batch_index)
+                .with_context(|| format!("Failed to open the progress file {}", path.display()))
+                .context(Failure::InvalidArgs)?,
+        ),
+        None => None,
+    };
+    let manifest = args.manifest.clone();
+    let start = Instant::now();
+    let mut entry = ManifestEntry::default();
+    let result = run_one_shot(global_opts, args, progress.clone(), &mut entry).await;
+    if result.is_err() {
+        entry.outcome = Outcome::Failed;
     }
+    entry.duration_ms = start.elapsed().as_millis() as u64;
+    if let Some(progress) = &progress {
+        progress.done(&entry);
+    }
+    let Some(manifest) = manifest else {
+        return result;
+    };
+    let appended = append_entry(&manifest, &entry)
+        .with_context(|| format!("Failed to append to the manifest {}", manifest.display()))
+        .context(Failure::Secrets);
+    result.and(appended)
+}
 
-    println!(
+/// Proves the block of `args`, recording what it produced in `entry` and reporting its
+/// phases to `progress`.
+async fn run_one_shot(
+    global_opts: GlobalOpts,
+    mut args: OneShotArgs,
+    mut progress: Option<Progress>,
+    entry: &mut ManifestEntry,
+) -> Result<()> {
+    if args.output_compression != OutputCompression::None {
+        match &args.output {
+            Some(path) if path != Path::new("-") => {
+                args.output = Some(args.output_compression.output_path(path));
+            }
+            _ => {
+                return Err(anyhow!("--output-compression requires an --output file")
+                    .context(Failure::InvalidArgs))
+            }
+        }
+    }
+    entry.output = args.output.clone();
+    let output_key = match &args.output_encryption_key_source {
+        Some(source) => {
+            if args
+                .output
+                .as_deref()
+                .map_or(true, |path| path == Path::new("-"))
+            {
+                return Err(
+                    anyhow!("--output-encryption-key-source requires an --output file")
+                        .context(Failure::InvalidArgs),
+                );
+            }
+            Some(
+                source
+                    .load()
+                    .context("Failed to load the output encryption key")
+                    .context(Failure::Secrets)?,
+            )
+        }
+        None => None,
+    };
+    if let Some(rpc_url) = args.verify_against_rpc.clone() {
+        return verify_against_rpc(args, rpc_url).await;
+    }
+    if !args.parse_only && !is_bootstrapped(&global_opts.secrets_dir) {
+        return Err(
+            anyhow!("Application was not bootstrapped. Bootstrap it first.")
+                .context(Failure::Secrets),
+        );
+    }
+
+    status!(
         "Global options: {:?}, OneShot options: {:?}",
-        global_opts, args
+        global_opts,
+        args
     );
 
     let path_str = args.blocks_data_file.to_string_lossy().to_string();
-    let block_no = u64::from_str(&String::from(
-        args.blocks_data_file
-            .file_prefix()
-            .unwrap()
-            .to_str()
-            .unwrap(),
-    ))?;
+    let block_no = block_no_of(&args.blocks_data_file)?;
+    entry.block = Some(block_no);
+    if let Some(progress) = &mut progress {
+        progress.started(block_no);
+    }
 
-    println!("Reading input file {} (block no: {})", path_str, block_no);
+    status!("Reading input file {} (block no: {})", path_str, block_no);
 
-    let privkey_path = global_opts.secrets_dir.join(PRIV_KEY_FILENAME);
-    let prev_privkey = load_private_key(&privkey_path)?;
+    let l2_chain = args.l2_chain.unwrap();
+    let mut l2_chain_spec =
+        get_taiko_chain_spec(&l2_chain).with_mismatch_policy(args.on_chainspec_mismatch);
+    if let Some(chain_id) = args.chain_id_override {
+        eprintln!(
+            "WARNING: TESTING ONLY: overriding chain id {} with {}",
+            l2_chain_spec.chain_id(),
+            chain_id
+        );
+        check_chain_id_override(&l2_chain, chain_id).context(Failure::InvalidArgs)?;
+        l2_chain_spec = l2_chain_spec
+            .with_chain_id_override(chain_id)
+            .context(Failure::InvalidArgs)?;
+    }
+    let consensus_overrides = ConsensusOverrides {
+        base_fee_per_gas: args.base_fee_override.map(U256::from),
+        gas_limit: args.gas_limit_override.map(U256::from),
+    };
+    if !consensus_overrides.is_empty() {
+        eprintln!(
+            "WARNING: TESTING ONLY: overriding base fee {:?} and gas limit {:?}",
+            args.base_fee_override, args.gas_limit_override
+        );
+        l2_chain_spec = l2_chain_spec.with_consensus_overrides(consensus_overrides);
+    }
+    if let Some(path) = &args.system_overrides {
+        let overrides = read_system_overrides(path)
+            .with_context(|| format!("Invalid system overrides {}", path.display()))
+            .context(Failure::InvalidArgs)?;
+        eprintln!(
+            "WARNING: TESTING ONLY: overriding the code of {:?}",
+            overrides.code.keys().collect::<Vec<_>>()
+        );
+        l2_chain_spec = l2_chain_spec.with_system_overrides(overrides);
+    }
+    if let Some(json) = &args.state_overrides {
+        let overrides: StateOverrides = serde_json::from_str(json)
+            .context("Invalid state overrides")
+            .context(Failure::InvalidArgs)?;
+        eprintln!(
+            "WARNING: TESTING ONLY: overriding the state of {:?}",
+            overrides.accounts.keys().collect::<Vec<_>>()
+        );
+        l2_chain_spec = l2_chain_spec.with_state_overrides(overrides);
+    }
+    if let Some(range) = args.tx_range {
+        eprintln!(
+            "WARNING: TESTING ONLY: proving the transactions {}:{} only",
+            range.from, range.to
+        );
+    }
+
+    let l1_source = match (args.l1_blocks_data_file, args.l1_anchor_hash, args.l1_rpc) {
+        (Some(path), _, _) => L1Source::File(path.to_string_lossy().to_string()),
+        (None, Some(anchor_hash), Some(rpc_url)) => L1Source::Rpc {
+            anchor_hash,
+            rpc_url,
+        },
+        _ => {
+            return Err(anyhow!("--l1-anchor-hash requires --l1-rpc").context(Failure::InvalidArgs))
+        }
+    };
+
+    // fs::write(privkey_path, new_privkey.to_bytes())?;
+    let rpc_options = RpcOptions {
+        data_source: args.data_source,
+        beacon_rpc_url: args.beacon_rpc_url,
+        kzg_trusted_setup: args.kzg_trusted_setup,
+        ..Default::default()
+    };
+    if let Some(max_age) = args.max_data_age_secs {
+        check_data_freshness(
+            path_str.clone(),
+            Duration::from_secs(max_age),
+            args.l2_rpc.clone(),
+            unix_now(args.fixed_now)?,
+        )
+        .await?;
+    }
+    if args.parse_only {
+        return parse_only(
+            &l2_chain_spec,
+            path_str,
+            l1_source,
+            rpc_options,
+            args.prover,
+            args.graffiti,
+            block_no,
+            args.output.as_deref(),
+            args.output_compression,
+        )
+        .await;
+    }
+    if let Some(path) = &args.output {
+        let existing = existing_proof(
+            args.on_existing_output,
+            path,
+            output_key.as_ref(),
+            block_no,
+            args.prover,
+            args.graffiti,
+        )?;
+        if let Some(block_hash) = existing {
+            entry.block_hash = Some(block_hash);
+            entry.outcome = Outcome::Skipped;
+            return Ok(());
+        }
+    }
+
+    // the rotated key activates on the clock, whatever the `--fixed-now` of the proof
+    let prev_privkey =
+        signing_key(&global_opts.secrets_dir, unix_now(None)?).context(Failure::Secrets)?;
     // println!("Private key: {}", prev_privkey.display_secret());
     // let (new_privkey, new_pubkey) = generate_new_keypair()?;
     let new_pubkey = public_key(&prev_privkey);
     let new_instance = public_key_to_address(&new_pubkey);
-
-    let l2_chain_spec = get_taiko_chain_spec(&args.l2_chain.unwrap());
-
-    // fs::write(privkey_path, new_privkey.to_bytes())?;
-    let pi_hash = get_data_to_sign(
+    let mut profiler =
+        (args.profile || progress.is_some()).then(|| Profiler::new().with_progress(progress));
+    let trace = args.emit_trace.clone().map(|path| TraceOutput {
+        path,
+        verbosity: args.trace_verbosity,
+        max_steps: args.max_trace_steps,
+    });
+    let (pi, chain_spec_mismatch, tx_count, gas_used, tx_range_roots) = get_data_to_sign(
         &l2_chain_spec,
         path_str,
-        args.l1_blocks_data_file.to_string_lossy().to_string(),
+        l1_source,
+        rpc_options,
         args.prover,
         args.graffiti,
         block_no,
-        new_instance,
+        args.emit_tx_state_roots.as_deref(),
+        trace.as_ref(),
+        args.tx_range,
+        &mut profiler,
     )
     .await?;
+    if let Some(roots) = &tx_range_roots {
+        status!(
+            "Transaction range state roots: {}",
+            serde_json::to_string(roots)?
+        );
+    }
+    let public_inputs = pi.public_inputs(new_instance);
+    let pi_hash = public_inputs.hash();
+    entry.block_hash = Some(public_inputs.block_hash);
+
+    status!("Data to be signed: {}", pi_hash);
+
+    let (proof, envelope, mut report) = sign_block(
+        &prev_privkey,
+        args.sgx_instance_id,
+        &public_inputs,
+        block_no,
+        args.fixed_now,
+    )
+    .context(Failure::Proving)?;
+    end_phase(&mut profiler, "sign");
+
+    if let Some(path) = &args.emit_calldata {
+        let layout = l2_chain_spec.verifier_call();
+        let calldata = verifier_calldata(layout, &pi, new_instance, proof.clone());
+        write_calldata(path, l2_chain_spec.verifier_contract(), &calldata)
+            .context(Failure::Secrets)?;
+    }
+    let proof = format!("0x{}", hex::encode(proof));
+    if let Some(path) = &args.emit_public_inputs {
+        write_public_inputs(path, &public_inputs).context(Failure::Secrets)?;
+    }
+    match &args.output {
+        Some(path) => {
+            if let Some(mismatch) = &chain_spec_mismatch {
+                report["chainSpecMismatch"] = serde_json::to_value(mismatch)?;
+            }
+            if let Some(roots) = &tx_range_roots {
+                report["txRange"] = serde_json::to_value(roots)?;
+            }
+            write_proof_output(path, args.output_compression, output_key.as_ref(), &report)
+                .context(Failure::Secrets)?
+        }
+        None => {
+            println!("Proof: {}", proof);
+            println!("Public key: 0x{}", new_pubkey);
+            println!("Instance address: {}", new_instance);
+            println!("Envelope: {}", serde_json::to_string(&envelope)?);
+            if let Some(mismatch) = &chain_spec_mismatch {
+                println!("Chain spec mismatch: {}", serde_json::to_string(mismatch)?);
+            }
+        }
+    }
+
+    let report_data =
+        attestation_report_data(Some(&l2_chain_spec), &new_pubkey).context(Failure::InvalidArgs)?;
+    let quote = attest_with_retries(
+        &mut DeviceBackend,
+        &report_data,
+        args.retries,
+        Duration::from_millis(args.retry_delay_ms),
+    )
+    .await?;
+    entry.mr_enclave = quote.get(112..144).map(hex::encode);
+
+    end_phase(&mut profiler, "attest");
+    if let Some(profiler) = profiler.filter(|_| args.profile) {
+        let report = profiler.report(block_no, tx_count, gas_used);
+        match &args.profile_out {
+            Some(path) => write_profile(path, &report).context(Failure::Secrets)?,
+            None => println!("Profile: {}", report),
+        }
+    }
+    entry.outcome = Outcome::Proven;
+    Ok(())
+}
+
+/// Checks that the block data parses and passes the structural and anchor checks,
+/// reporting the L1 block it is anchored to.
+#[allow(clippy::too_many_arguments)]
+async fn parse_only(
+    l2_chain_spec: &ChainSpec,
+    path_str: String,
+    l1_source: L1Source,
+    rpc_options: RpcOptions,
+    prover: Address,
+    graffiti: B256,
+    block_no: u64,
+    output: Option<&Path>,
+    compression: OutputCompression,
+) -> Result<()> {
+    let l2_chain_spec = l2_chain_spec.clone();
+    let (l1_blocks_path, l1_rpc_url, anchor_hash) = l1_source.into_parts();
+    let extra = tokio::task::spawn_blocking(move || {
+        check_taiko_data(
+            l1_blocks_path,
+            ETH_MAINNET_CHAIN_SPEC.clone(),
+            l1_rpc_url,
+            prover,
+            Some(path_str),
+            l2_chain_spec,
+            None,
+            block_no,
+            graffiti,
+            rpc_options,
+        )
+    })
+    .await?
+    .map_err(|err| {
+        let failure = input_failure(&err);
+        err.context("Invalid block data").context(failure)
+    })?;
+    check_anchor_hash(&extra, anchor_hash, block_no)?;
 
-    println!("Data to be signed: {}", pi_hash);
+    let mut report = json!({
+        "blockNo": block_no,
+        "l1Height": extra.l1_height,
+        "l1Hash": extra.l1_hash,
+        "txListBytes": extra.l2_tx_list.len(),
+        "blobHash": extra.l2_blob_hash,
+    });
+    if let Some(mismatch) = &extra.chain_spec_mismatch {
+        report["chainSpecMismatch"] = serde_json::to_value(mismatch)?;
+    }
+    match output {
+        Some(path) => {
+            write_proof_output(path, compression, None, &report).context(Failure::Secrets)?
+        }
+        None => println!(
+            "Block {} is valid, anchored to L1 block {} ({}), tx list of {} bytes",
+            block_no,
+            extra.l1_height,
+            extra.l1_hash,
+            extra.l2_tx_list.len()
+        ),
+    }
+    Ok(())
+}
 
-    let sig = sign_message(&prev_privkey, pi_hash)?;
+/// The number of the block of a block data file, named after it.
+pub(crate) fn block_no_of(blocks_data_file: &Path) -> Result<u64> {
+    u64::from_str(&String::from(
+        blocks_data_file.file_prefix().unwrap().to_str().unwrap(),
+    ))
+    .context("The block data file must be named after the block number")
+    .context(Failure::InvalidArgs)
+}
+
+/// The unix timestamp of now, `fixed_now` when given.
+fn unix_now(fixed_now: Option<u64>) -> Result<u64> {
+    match fixed_now {
+        Some(now) => Ok(now),
+        None => Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs()),
+    }
+}
+
+/// Rejects the block data older than `max_age`, relative to the chain head of `l2_rpc` or
+/// to `now` without one, or whose block is no longer canonical on `l2_rpc`.
+async fn check_data_freshness(
+    path_str: String,
+    max_age: Duration,
+    l2_rpc: Option<String>,
+    now: u64,
+) -> Result<()> {
+    let stale = tokio::task::spawn_blocking(move || {
+        let options = RpcOptions::default();
+        let rpc = l2_rpc.map(|rpc_url| (rpc_url, &options));
+        check_data_age_of_file(path_str, max_age, rpc, now)
+    })
+    .await?
+    .map_err(|err| {
+        let failure = input_failure(&err);
+        err.context("Could not check the age of the block data")
+            .context(failure)
+    })?;
+    match stale {
+        Some(stale) => Err(anyhow!(stale)
+            .context("Stale block data")
+            .context(Failure::DataIntegrity)),
+        None => Ok(()),
+    }
+}
+
+/// Compares the block data files with the blocks of the RPC nodes, failing on any mismatch.
+async fn verify_against_rpc(args: OneShotArgs, rpc_url: String) -> Result<()> {
+    let mut files = vec![(args.blocks_data_file, rpc_url, RpcOptions::default())];
+    if let (Some(path), Some(l1_rpc)) = (args.l1_blocks_data_file, args.l1_rpc) {
+        let options = RpcOptions {
+            data_source: args.data_source,
+            ..Default::default()
+        };
+        files.push((path, l1_rpc, options));
+    }
+
+    let mut reports = vec![];
+    let mut mismatch_count = 0;
+    for (path, rpc_url, options) in files {
+        let path_str = path.to_string_lossy().to_string();
+        status!("Verifying {} against {}", path_str, rpc_url);
+        let file_path = path_str.clone();
+        let mismatches =
+            tokio::task::spawn_blocking(move || validate_against_rpc(file_path, rpc_url, &options))
+                .await?
+                .map_err(|err| {
+                    let failure = input_failure(&err);
+                    err.context(format!("Could not verify {}", path_str))
+                        .context(failure)
+                })?;
+        for mismatch in &mismatches {
+            status!(
+                "Block {} {}: {} in the file, {} on the node",
+                mismatch.block_no,
+                mismatch.field,
+                mismatch.file,
+                mismatch.rpc
+            );
+        }
+        mismatch_count += mismatches.len();
+        reports.push(json!({
+            "blocksDataFile": path_str,
+            "mismatches": mismatches,
+        }));
+    }
+
+    let report = json!({ "files": reports });
+    match &args.output {
+        Some(path) => write_proof_output(path, args.output_compression, None, &report)
+            .context(Failure::Secrets)?,
+        None => println!("{}", serde_json::to_string_pretty(&report)?),
+    }
+    if mismatch_count > 0 {
+        return Err(anyhow!(
+            "{} fields of the block data differ from the node's",
+            mismatch_count
+        )
+        .context(Failure::DataIntegrity));
+    }
+    Ok(())
+}
 
+/// Signs the public inputs into the sgx proof of the instance, and the proof into the
+/// envelope authenticating its delivery.
+pub fn sign_proof(
+    privkey: &SecretKey,
+    sgx_instance_id: u32,
+    pi_hash: B256,
+    timestamp: u64,
+) -> Result<(Vec<u8>, ProofEnvelope)> {
     const SGX_PROOF_LEN: usize = 89;
 
+    let new_instance = public_key_to_address(&public_key(privkey));
+    let sig = sign_message(privkey, pi_hash)?;
     let mut proof = Vec::with_capacity(SGX_PROOF_LEN);
-    proof.extend(args.sgx_instance_id.to_be_bytes());
+    proof.extend(sgx_instance_id.to_be_bytes());
     proof.extend(new_instance);
     proof.extend(sig.to_bytes());
-    let proof = hex::encode(proof);
-    println!("Proof: 0x{}", proof);
-    println!("Public key: 0x{}", new_pubkey);
-    println!("Instance address: {}", new_instance);
 
-    save_attestation_user_report_data(new_instance)?;
-    print_sgx_info()
+    let envelope_hash = ProofEnvelope::signing_hash(&proof, pi_hash, timestamp);
+    let envelope = ProofEnvelope {
+        signature: sign_message(privkey, envelope_hash)?,
+        proof: proof.clone().into(),
+        public_inputs: pi_hash,
+        timestamp,
+        signer: new_instance,
+    };
+    Ok((proof, envelope))
+}
+
+/// Signs the public inputs of the block at `fixed_now`, or now on the clock, returning
+/// the proof, its envelope and the report written to `--output`.
+fn sign_block(
+    privkey: &SecretKey,
+    sgx_instance_id: u32,
+    public_inputs: &PublicInputs,
+    block_no: u64,
+    fixed_now: Option<u64>,
+) -> Result<(Vec<u8>, ProofEnvelope, serde_json::Value)> {
+    let timestamp = unix_now(fixed_now)?;
+    let (proof, envelope) = sign_proof(privkey, sgx_instance_id, public_inputs.hash(), timestamp)?;
+    let mut report = proof_report(
+        &format!("0x{}", hex::encode(&proof)),
+        &public_key(privkey),
+        &envelope,
+        public_inputs,
+    );
+    report["block"] = block_no.into();
+    Ok((proof, envelope, report))
+}
+
+/// The proof as written to `--output`.
+pub fn proof_report(
+    proof: &str,
+    new_pubkey: &PublicKey,
+    envelope: &ProofEnvelope,
+    public_inputs: &PublicInputs,
+) -> serde_json::Value {
+    json!({
+        "proof": proof,
+        "publicKey": format!("0x{}", new_pubkey),
+        "instanceAddress": envelope.signer.to_string(),
+        "envelope": envelope,
+        "publicInputs": public_inputs,
+    })
+}
+
+/// The block hash of the proof to keep in the `--output` file, which may already exist,
+/// handled as `on_existing` says; `None` to prove the block.
+fn existing_proof(
+    on_existing: ExistingOutput,
+    path: &Path,
+    key: Option<&OutputKey>,
+    block_no: u64,
+    prover: Address,
+    graffiti: B256,
+) -> Result<Option<B256>> {
+    if path == Path::new("-") || !path.exists() {
+        return Ok(None);
+    }
+    match on_existing {
+        ExistingOutput::Overwrite => Ok(None),
+        ExistingOutput::Error => Err(anyhow!("The output file {} already exists", path.display())
+            .context(Failure::InvalidArgs)),
+        ExistingOutput::Skip => {
+            match verify_proof_of_block(path, key, block_no, prover, graffiti) {
+                Ok(block_hash) => {
+                    status!(
+                        "Block {} is already proven in {}, skipping it",
+                        block_no,
+                        path.display()
+                    );
+                    Ok(Some(block_hash))
+                }
+                Err(err) => {
+                    status!(
+                        "Proving block {} again, {} holds no valid proof of it: {:#}",
+                        block_no,
+                        path.display(),
+                        err
+                    );
+                    Ok(None)
+                }
+            }
+        }
+    }
+}
+
+/// Writes the proof report to `path`, compressed with `compression` then encrypted with
+/// `key`, which it records under `compression` and `encryption`, or to stdout when it is
+/// `-`.
+pub(crate) fn write_proof_output(
+    path: &Path,
+    compression: OutputCompression,
+    key: Option<&OutputKey>,
+    report: &serde_json::Value,
+) -> Result<()> {
+    if path == Path::new("-") {
+        return write_proof_json(&mut io::stdout().lock(), report);
+    }
+    if compression == OutputCompression::None && key.is_none() {
+        write_proof_json(&mut File::create(path)?, report)?;
+    } else {
+        let mut report = report.clone();
+        if compression != OutputCompression::None {
+            report["compression"] = compression.name().into();
+        }
+        if key.is_some() {
+            report["encryption"] = OUTPUT_CIPHER.into();
+        }
+        let mut json = vec![];
+        write_proof_json(&mut json, &report)?;
+        let mut output = compress(compression, &json)?;
+        if let Some(key) = key {
+            output = key.encrypt(&output)?;
+        }
+        fs::write(path, output)?;
+    }
+    status!("Proof written to {}", path.display());
+    Ok(())
+}
+
+/// The public inputs as JSON, with their ABI encoding for the verifier.
+pub(crate) fn public_inputs_report(public_inputs: &PublicInputs) -> Result<serde_json::Value> {
+    let mut report = serde_json::to_value(public_inputs)?;
+    report["abiEncoded"] = format!("0x{}", hex::encode(public_inputs.abi_encode())).into();
+    Ok(report)
+}
+
+/// Writes the public inputs as JSON, with their ABI encoding for the verifier.
+pub(crate) fn write_public_inputs(path: &Path, public_inputs: &PublicInputs) -> Result<()> {
+    let report = public_inputs_report(public_inputs)?;
+    write_proof_json(&mut File::create(path)?, &report)?;
+    status!("Public inputs written to {}", path.display());
+    Ok(())
+}
+
+/// Writes the state root after each transaction executed and then after each withdrawal,
+/// as JSON with the transaction hashes and the state root of the block. A transaction
+/// skipped by the builder has no state root.
+fn write_tx_state_roots(
+    path: &Path,
+    block_no: u64,
+    state_root: B256,
+    tx_hashes: &[B256],
+    roots: &[B256],
+) -> Result<()> {
+    let report = json!({
+        "block": block_no,
+        "stateRoot": state_root,
+        "transactions": tx_hashes,
+        "txStateRoots": roots,
+    });
+    write_proof_json(&mut File::create(path)?, &report)?;
+    status!("State roots written to {}", path.display());
+    Ok(())
+}
+
+/// The execution trace `--emit-trace` writes.
+#[derive(Debug, Clone)]
+#[cfg_attr(not(feature = "trace"), allow(dead_code))]
+pub(crate) struct TraceOutput {
+    path: PathBuf,
+    verbosity: TraceVerbosity,
+    max_steps: usize,
+}
+
+/// Writes the steps of each transaction executed as JSON, and whether the steps past
+/// `--max-trace-steps` were dropped.
+#[cfg(feature = "trace")]
+fn write_trace(path: &Path, tracer: &ExecutionTracer) -> Result<()> {
+    let report = json!({
+        "truncated": tracer.truncated(),
+        "transactions": tracer.transactions(),
+    });
+    write_proof_json(&mut File::create(path)?, &report)?;
+    status!("Execution trace written to {}", path.display());
+    Ok(())
+}
+
+/// Writes the calldata of the on-chain call submitting the proof as JSON, with the
+/// contract it is sent to.
+fn write_calldata(path: &Path, to: Option<Address>, calldata: &[u8]) -> Result<()> {
+    let report = json!({
+        "to": to,
+        "calldata": format!("0x{}", hex::encode(calldata)),
+    });
+    write_proof_json(&mut File::create(path)?, &report)?;
+    status!("Calldata written to {}", path.display());
+    Ok(())
+}
+
+fn write_profile(path: &Path, report: &serde_json::Value) -> Result<()> {
+    write_proof_json(&mut File::create(path)?, report)?;
+    status!("Profile written to {}", path.display());
+    Ok(())
+}
+
+pub(crate) fn write_proof_json(out: &mut impl Write, report: &serde_json::Value) -> Result<()> {
+    serde_json::to_writer(&mut *out, report)?;
+    writeln!(out)?;
+    Ok(out.flush()?)
+}
+
+/// The SGX attestation part of the proving step, returning the quote.
+pub trait SgxBackend {
+    fn attest(&mut self, report_data: &[u8; REPORT_DATA_SIZE]) -> Result<Vec<u8>>;
+}
+
+/// Attests through the Gramine attestation device files.
+struct DeviceBackend;
+
+impl SgxBackend for DeviceBackend {
+    fn attest(&mut self, report_data: &[u8; REPORT_DATA_SIZE]) -> Result<Vec<u8>> {
+        save_attestation_user_report_data(report_data)?;
+        print_sgx_info()
+    }
+}
+
+/// Runs the attestation step, retrying up to `retries` times on transient faults, and
+/// returns the quote.
+async fn attest_with_retries(
+    backend: &mut impl SgxBackend,
+    report_data: &[u8; REPORT_DATA_SIZE],
+    retries: u32,
+    delay: Duration,
+) -> Result<Vec<u8>> {
+    let mut attempt = 1;
+    loop {
+        match backend.attest(report_data) {
+            Ok(quote) => return Ok(quote),
+            Err(err) if attempt <= retries && is_transient(&err) => {
+                status!(
+                    "Attestation attempt {}/{} failed: {:#}; retrying in {:?}",
+                    attempt,
+                    retries + 1,
+                    err,
+                    delay
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(err) => return Err(err.context(Failure::Attestation)),
+        }
+    }
+}
+
+/// Whether the error is an I/O fault of the attestation device that may clear up on
+/// its own, as opposed to a missing device or malformed data.
+fn is_transient(err: &Error) -> bool {
+    err.chain()
+        .filter_map(|cause| cause.downcast_ref::<io::Error>())
+        .any(|err| {
+            matches!(
+                err.kind(),
+                io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            )
+        })
 }
 
 fn is_bootstrapped(secrets_dir: &Path) -> bool {
@@ -111,96 +968,362 @@ fn is_bootstrapped(secrets_dir: &Path) -> bool {
     privkey_path.is_file() && !privkey_path.metadata().unwrap().permissions().readonly()
 }
 
-async fn get_data_to_sign(
+/// Where the L1 data of the block's anchor comes from.
+pub(crate) enum L1Source {
+    File(String),
+    /// Fetched from the RPC node, or the export directory with a `dir` data source, the
+    /// block must be anchored to `anchor_hash`.
+    Rpc {
+        anchor_hash: B256,
+        rpc_url: String,
+    },
+}
+
+impl L1Source {
+    /// The L1 cache path, the L1 RPC URL and the expected anchor hash. The L1 block is
+    /// looked up by the height in the anchor call, whose hash is checked against the
+    /// fetched block.
+    fn into_parts(self) -> (Option<String>, Option<String>, Option<B256>) {
+        match self {
+            L1Source::File(path) => (Some(path), None, None),
+            L1Source::Rpc {
+                anchor_hash,
+                rpc_url,
+            } => (None, Some(rpc_url), Some(anchor_hash)),
+        }
+    }
+}
+
+/// The block is anchored to another L1 block than the `--l1-anchor-hash`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct AnchorMismatch {
+    pub block_no: u64,
+    pub expected: B256,
+    pub actual: B256,
+    pub l1_height: u64,
+}
+
+impl fmt::Display for AnchorMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "L1 anchor mismatch: block {} is anchored to L1 block {} ({}), expected {}",
+            self.block_no, self.l1_height, self.actual, self.expected
+        )
+    }
+}
+
+impl std::error::Error for AnchorMismatch {}
+
+fn check_anchor_hash(extra: &TaikoExtra, anchor_hash: Option<B256>, block_no: u64) -> Result<()> {
+    match anchor_hash {
+        Some(expected) if extra.l1_hash != expected => Err(Error::new(AnchorMismatch {
+            block_no,
+            expected,
+            actual: extra.l1_hash,
+            l1_height: extra.l1_height,
+        })
+        .context(Failure::InvalidArgs)),
+        _ => Ok(()),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn get_data_to_sign(
     l2_chain_spec: &ChainSpec,
     path_str: String,
-    l1_blocks_path: String,
+    l1_source: L1Source,
+    rpc_options: RpcOptions,
     prover: Address,
     graffiti: B256,
     block_no: u64,
-    new_pubkey: Address,
-) -> Result<B256> {
+    emit_tx_state_roots: Option<&Path>,
+    emit_trace: Option<&TraceOutput>,
+    tx_range: Option<TxRange>,
+    profiler: &mut Option<Profiler>,
+) -> Result<(
+    ProtocolInstance,
+    Option<ChainSpecMismatch>,
+    usize,
+    u64,
+    Option<TxRangeRoots>,
+)> {
+    #[cfg(not(feature = "trace"))]
+    if emit_trace.is_some() {
+        return Err(
+            anyhow!("--emit-trace needs a raiko-guest built with the trace feature")
+                .context(Failure::InvalidArgs),
+        );
+    }
     let (init, extra) = parse_to_init(
         l2_chain_spec,
         path_str,
-        l1_blocks_path,
+        l1_source,
+        rpc_options,
         prover,
         block_no,
         graffiti,
     )
     .await?;
-    let input: Input<zeth_lib::EthereumTxEssence> = init.clone().into();
-    let output = TaikoBlockBuilder::build_from(l2_chain_spec, input)
-        .expect("Failed to build the resulting block");
-    let pi = zeth_lib::taiko::protocol_instance::assemble_protocol_instance(&extra, &output)?;
-    let pi_hash = pi.hash(EvidenceType::Sgx { new_pubkey });
-    Ok(pi_hash)
+    end_phase(profiler, "load");
+    let tx_count = init.fini_transactions.len();
+    if let Some(range) = tx_range {
+        if range.to > tx_count {
+            return Err(anyhow!(
+                "The transaction range {}:{} is out of the {} transactions of the block",
+                range.from,
+                range.to,
+                tx_count
+            )
+            .context(Failure::InvalidArgs));
+        }
+    }
+    let stated_block = emit_tx_state_roots.map(|_| {
+        let tx_hashes: Vec<B256> = init.fini_transactions.iter().map(|tx| tx.hash()).collect();
+        (init.fini_block.state_root, tx_hashes)
+    });
+    let mut input: Input<zeth_lib::EthereumTxEssence> = init.into();
+    // built with the spec the witness was built with
+    let l2_chain_spec = extra
+        .resolved_chain_spec(l2_chain_spec)
+        .context(Failure::InvalidArgs)?;
+    apply_system_overrides(l2_chain_spec.system_overrides(), &mut input)
+        .context(Failure::DataIntegrity)?;
+    apply_state_overrides(l2_chain_spec.state_overrides(), &mut input)
+        .context(Failure::DataIntegrity)?;
+    let (output, tx_state_roots, tx_range_roots) = match tx_range {
+        Some(range) => build_tx_range(&l2_chain_spec, input, range, profiler)
+            .map(|(header, roots)| (header, None, Some(roots))),
+        None => build_block(
+            &l2_chain_spec,
+            input,
+            emit_tx_state_roots.is_some(),
+            emit_trace,
+            profiler,
+        )
+        .map(|(header, roots)| (header, roots, None)),
+    }
+    .context("Failed to build the resulting block")
+    .context(Failure::Proving)?;
+    if let (Some(path), Some((state_root, tx_hashes)), Some(roots)) =
+        (emit_tx_state_roots, stated_block, tx_state_roots)
+    {
+        write_tx_state_roots(path, block_no, state_root, &tx_hashes, &roots)
+            .context(Failure::Secrets)?;
+        if output.state_root != state_root {
+            return Err(anyhow!(
+                "The state root {} of the built block is not the one of the block, {}, see the \
+                 state root after each transaction in {}",
+                output.state_root,
+                state_root,
+                path.display()
+            )
+            .context(Failure::Proving));
+        }
+    }
+    let pi = zeth_lib::taiko::protocol_instance::assemble_protocol_instance(&extra, &output)
+        .context(Failure::DataIntegrity)?;
+    end_phase(profiler, "assemble");
+    let gas_used = output.gas_used.saturating_to();
+    Ok((
+        pi,
+        extra.chain_spec_mismatch,
+        tx_count,
+        gas_used,
+        tx_range_roots,
+    ))
+}
+
+/// Builds the block the way `TaikoBlockBuilder::build_from` does, ending a phase at
+/// each step, with the state root after each transaction when `tx_state_roots`.
+fn build_block(
+    l2_chain_spec: &ChainSpec,
+    input: Input<zeth_lib::EthereumTxEssence>,
+    tx_state_roots: bool,
+    trace: Option<&TraceOutput>,
+    profiler: &mut Option<Profiler>,
+) -> Result<(Header, Option<Vec<B256>>)> {
+    let mut builder = TaikoBlockBuilder::new(l2_chain_spec, input).initialize_database()?;
+    if tx_state_roots {
+        builder.mut_db().unwrap().record_commits();
+    }
+    #[cfg(feature = "trace")]
+    if let Some(trace) = trace {
+        builder = builder.with_tracer(ExecutionTracer::new(
+            trace.max_steps,
+            trace.verbosity >= TraceVerbosity::Stack,
+            trace.verbosity >= TraceVerbosity::Memory,
+        ));
+    }
+    end_phase(profiler, "initialize");
+    let builder = builder.prepare_header()?;
+    end_phase(profiler, "prepare");
+    #[allow(unused_mut)]
+    let mut builder = builder.execute_transactions()?;
+    end_phase(profiler, "execute");
+    #[cfg(feature = "trace")]
+    if let (Some(trace), Some(tracer)) = (trace, builder.take_tracer()) {
+        write_trace(&trace.path, &tracer)?;
+    }
+    let tx_state_roots = tx_state_roots
+        .then(|| builder.tx_state_roots())
+        .transpose()?;
+    let header = builder.build()?;
+    end_phase(profiler, "finalize");
+    Ok((header, tx_state_roots))
 }
 
-async fn parse_to_init(
+/// The state roots of a range of the transactions of a block.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TxRangeRoots {
+    from: usize,
+    to: usize,
+    /// the state root after the transactions before the range
+    pre_state_root: B256,
+    /// the state root after each transaction of the range executed, a transaction
+    /// skipped by the builder has none
+    tx_state_roots: Vec<B256>,
+}
+
+/// Builds the block of the transactions of `input` before the end of `range`, with the
+/// state root after those before the range, as executed on their own, and the one after
+/// each transaction of the range.
+fn build_tx_range(
+    l2_chain_spec: &ChainSpec,
+    mut input: Input<zeth_lib::EthereumTxEssence>,
+    range: TxRange,
+    profiler: &mut Option<Profiler>,
+) -> Result<(Header, TxRangeRoots)> {
+    // the commits of the transactions before the range, some may be skipped
+    let pre_commits = if range.from == 0 {
+        0
+    } else {
+        let mut pre_input = input.clone();
+        pre_input.transactions.truncate(range.from);
+        let mut builder = TaikoBlockBuilder::new(l2_chain_spec, pre_input).initialize_database()?;
+        builder.mut_db().unwrap().record_commits();
+        let builder = builder.prepare_header()?.execute_transactions()?;
+        let commits = builder.db().and_then(|db| db.commits.as_ref());
+        commits.map_or(0, Vec::len)
+    };
+    end_phase(profiler, "pre-execute");
+    input.transactions.truncate(range.to);
+    let mut builder = TaikoBlockBuilder::new(l2_chain_spec, input).initialize_database()?;
+    builder.mut_db().unwrap().record_commits();
+    end_phase(profiler, "initialize");
+    let builder = builder.prepare_header()?;
+    end_phase(profiler, "prepare");
+    let builder = builder.execute_transactions()?;
+    end_phase(profiler, "execute");
+    let (pre_state_root, tx_state_roots) = builder.tx_range_state_roots(pre_commits)?;
+    let header = builder.build()?;
+    end_phase(profiler, "finalize");
+    let roots = TxRangeRoots {
+        from: range.from,
+        to: range.to,
+        pre_state_root,
+        tx_state_roots,
+    };
+    Ok((header, roots))
+}
+
+pub(crate) async fn parse_to_init(
     l2_chain_spec: &ChainSpec,
     blocks_path: String,
-    l1_blocks_path: String,
+    l1_source: L1Source,
+    rpc_options: RpcOptions,
     prover: Address,
     block_no: u64,
     graffiti: B256,
 ) -> Result<(Init<zeth_lib::EthereumTxEssence>, TaikoExtra), Error> {
     let l2_chain_spec = l2_chain_spec.clone();
+    let (l1_blocks_path, l1_rpc_url, anchor_hash) = l1_source.into_parts();
     let (init, extra) = tokio::task::spawn_blocking(move || {
         zeth_lib::taiko::host::get_taiko_initial_data::<TaikoStrategyBundle>(
-            Some(l1_blocks_path),
+            l1_blocks_path,
             ETH_MAINNET_CHAIN_SPEC.clone(),
-            None,
+            l1_rpc_url,
             prover,
             Some(blocks_path),
             l2_chain_spec,
             None,
             block_no,
             graffiti,
+            rpc_options,
         )
-        .expect("Could not init")
     })
-    .await?;
+    .await?
+    .map_err(|err| {
+        let failure = input_failure(&err);
+        err.context("Could not init").context(failure)
+    })?;
+    check_anchor_hash(&extra, anchor_hash, block_no)?;
 
     Ok::<(Init<EthereumTxEssence>, TaikoExtra), _>((init, extra))
 }
 
-fn save_attestation_user_report_data(pubkey: Address) -> Result<()> {
-    let mut extended_pubkey = pubkey.to_vec();
-    extended_pubkey.resize(64, 0);
+/// Unreadable or malformed block data files are invalid inputs, anything else failed the
+/// consistency checks between the L1 and L2 data.
+fn input_failure(err: &Error) -> Failure {
+    let malformed = err
+        .chain()
+        .any(|cause| cause.is::<io::Error>() || cause.is::<serde_json::Error>());
+    if malformed {
+        Failure::InvalidBlockData
+    } else {
+        Failure::DataIntegrity
+    }
+}
+
+/// The report data attesting `pubkey`, composed with the layout of `l2_chain_spec`, or
+/// the instance address without a chain spec.
+fn attestation_report_data(
+    l2_chain_spec: Option<&ChainSpec>,
+    pubkey: &PublicKey,
+) -> Result<[u8; REPORT_DATA_SIZE]> {
+    let instance = public_key_to_address(pubkey);
+    let public_key_hash = keccak256(pubkey.serialize());
+    match l2_chain_spec {
+        Some(spec) => spec
+            .report_data()
+            .compose(instance, public_key_hash, spec.chain_id()),
+        None => ReportDataLayout::default().compose(instance, public_key_hash, 0),
+    }
+}
+
+fn save_attestation_user_report_data(report_data: &[u8; REPORT_DATA_SIZE]) -> Result<()> {
     let mut user_report_data_file = OpenOptions::new()
         .write(true)
         .open(ATTESTATION_USER_REPORT_DATA_DEVICE_FILE)?;
     user_report_data_file
-        .write_all(&extended_pubkey)
-        .map_err(|err| anyhow!("Failed to save user report data: {}", err))
+        .write_all(report_data)
+        .context("Failed to save user report data")
 }
 
-fn print_sgx_info() -> Result<()> {
+fn print_sgx_info() -> Result<Vec<u8>> {
     let attestation_type = get_sgx_attestation_type()?;
-    println!("Detected attestation type: {}", attestation_type.trim());
+    status!("Detected attestation type: {}", attestation_type.trim());
 
-    let mut quote_file = File::open(ATTESTATION_QUOTE_DEVICE_FILE)?;
-    let mut quote = Vec::new();
-    quote_file.read_to_end(&mut quote)?;
-    println!(
+    let quote = read_sgx_quote()?;
+    status!(
         "Extracted SGX quote with size = {} and the following fields:",
         quote.len()
     );
     // println!("Quote: {}", hex::encode(&quote));
-    println!(
+    status!(
         "  ATTRIBUTES.FLAGS: {}  [ Debug bit: {} ]",
         hex::encode(&quote[96..104]),
         quote[96] & 2 > 0
     );
-    println!("  ATTRIBUTES.XFRM:  {}", hex::encode(&quote[104..112]));
+    status!("  ATTRIBUTES.XFRM:  {}", hex::encode(&quote[104..112]));
     // Enclave's measurement (hash of code and data). MRENCLAVE is a 256-bit value that
     // represents the hash (message digest) of the code and data within an enclave. It is a
     // critical security feature of SGX and provides integrity protection for the enclave's
     // contents. When an enclave is instantiated, its MRENCLAVE value is computed and stored
     // in the SGX quote. This value can be used to ensure that the enclave being run is the
     // intended and correct version.
-    println!("  MRENCLAVE:        {}", hex::encode(&quote[112..144]));
+    status!("  MRENCLAVE:        {}", hex::encode(&quote[112..144]));
     // MRSIGNER is a 256-bit value that identifies the entity or signer responsible for
     // signing the enclave code. It represents the microcode revision of the software entity
     // that created the enclave. Each entity or signer, such as a software vendor or
@@ -208,19 +1331,26 @@ fn print_sgx_info() -> Result<()> {
     // MRSIGNER value provides a way to differentiate between different signers or entities,
     // allowing applications to make trust decisions based on the signer's identity and
     // trustworthiness.
-    println!("  MRSIGNER:         {}", hex::encode(&quote[176..208]));
-    println!("  ISVPRODID:        {}", hex::encode(&quote[304..306]));
-    println!("  ISVSVN:           {}", hex::encode(&quote[306..308]));
+    status!("  MRSIGNER:         {}", hex::encode(&quote[176..208]));
+    status!("  ISVPRODID:        {}", hex::encode(&quote[304..306]));
+    status!("  ISVSVN:           {}", hex::encode(&quote[306..308]));
     // The REPORTDATA field in the SGX report structure is a 64-byte array used for
     // providing additional data to the reporting process. The contents of this field are
     // application-defined and can be used to convey information that the application
     // considers relevant for its security model. The REPORTDATA field allows the
     // application to include additional contextual information that might be necessary for
     // the particular security model or usage scenario.
-    println!("  REPORTDATA:       {}", hex::encode(&quote[368..400]));
-    println!("                    {}", hex::encode(&quote[400..432]));
+    status!("  REPORTDATA:       {}", hex::encode(&quote[368..400]));
+    status!("                    {}", hex::encode(&quote[400..432]));
 
-    Ok(())
+    Ok(quote)
+}
+
+fn read_sgx_quote() -> Result<Vec<u8>> {
+    let mut quote_file = File::open(ATTESTATION_QUOTE_DEVICE_FILE)?;
+    let mut quote = Vec::new();
+    quote_file.read_to_end(&mut quote)?;
+    Ok(quote)
 }
 
 fn get_sgx_attestation_type() -> Result<String> {
@@ -237,3 +1367,721 @@ fn get_sgx_attestation_type() -> Result<String> {
         ATTESTATION_TYPE_DEVICE_FILE
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use ethers_core::types::{
+        Block, EIP1186ProofResponse, Transaction as EthersTransaction, H160, H256,
+        U256 as EthersU256,
+    };
+    use zeth_lib::{
+        consts::ChainSpecMismatchPolicy,
+        host::provider::{
+            data_source::DataSourceKind, file_provider::FileProvider, BlockQuery, MutProvider,
+            ProofQuery, ProposeQuery,
+        },
+    };
+    use zeth_primitives::taiko::{BlockProposed, ANCHOR_GAS_LIMIT, GOLDEN_TOUCH_ACCOUNT};
+
+    use super::*;
+    use crate::exit_code::exit_code;
+
+    #[test]
+    fn bootstrap_refuses_to_overwrite_without_force() {
+        let secrets_dir =
+            std::env::temp_dir().join(format!("raiko-bootstrap-{}", std::process::id()));
+        fs::create_dir_all(&secrets_dir).unwrap();
+        let privkey_path = secrets_dir.join(PRIV_KEY_FILENAME);
+        fs::write(&privkey_path, b"existing").unwrap();
+
+        let global_opts = GlobalOpts {
+            secrets_dir: secrets_dir.clone(),
+            verbose: 0,
+            quiet: false,
+        };
+        let args = BootstrapArgs {
+            format: OutputFormat::Json,
+            force: false,
+            new_key_activation_delay_secs: 0,
+            l2_chain: None,
+        };
+        assert!(bootstrap(global_opts, args).is_err());
+        assert_eq!(fs::read(&privkey_path).unwrap(), b"existing");
+        fs::remove_dir_all(&secrets_dir).unwrap();
+    }
+
+    fn one_shot_args(blocks_data_file: PathBuf) -> OneShotArgs {
+        OneShotArgs {
+            l1_blocks_data_file: Some(blocks_data_file.with_file_name("1.l1.json.gz")),
+            l1_anchor_hash: None,
+            l1_rpc: None,
+            data_source: DataSourceKind::Rpc,
+            beacon_rpc_url: None,
+            kzg_trusted_setup: None,
+            blocks_data_file,
+            prover: Address::ZERO,
+            graffiti: B256::ZERO,
+            sgx_instance_id: 0,
+            l2_chain: Some("internal_devnet_a".to_string()),
+            retries: 0,
+            retry_delay_ms: 0,
+            chain_id_override: None,
+            on_chainspec_mismatch: ChainSpecMismatchPolicy::Error,
+            base_fee_override: None,
+            gas_limit_override: None,
+            system_overrides: None,
+            state_overrides: None,
+            output: None,
+            on_existing_output: ExistingOutput::Overwrite,
+            output_compression: OutputCompression::None,
+            verify_against_rpc: None,
+            max_data_age_secs: None,
+            l2_rpc: None,
+            emit_public_inputs: None,
+            emit_calldata: None,
+            emit_tx_state_roots: None,
+            emit_trace: None,
+            trace_verbosity: TraceVerbosity::Opcodes,
+            max_trace_steps: 1_000_000,
+            allow_consensus_overrides: false,
+            tx_range: None,
+            parse_only: false,
+            profile: false,
+            profile_out: None,
+            manifest: None,
+            fixed_now: None,
+            progress_out: None,
+            batch_index: None,
+            output_encryption_key_source: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn one_shot_failures_map_to_exit_codes() {
+        let dir = tempfile::tempdir().unwrap();
+        let global_opts = || GlobalOpts {
+            secrets_dir: dir.path().to_path_buf(),
+            verbose: 0,
+            quiet: false,
+        };
+        let blocks_data_file = dir.path().join("1.json.gz");
+
+        let err = one_shot(global_opts(), one_shot_args(blocks_data_file.clone()))
+            .await
+            .unwrap_err();
+        assert_eq!(exit_code(&err), Failure::Secrets as u8);
+
+        save_private_key(
+            dir.path().join(PRIV_KEY_FILENAME),
+            &generate_key().secret_key(),
+        )
+        .unwrap();
+        let err = one_shot(
+            global_opts(),
+            one_shot_args(dir.path().join("latest.json.gz")),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(exit_code(&err), Failure::InvalidArgs as u8);
+
+        let args = OneShotArgs {
+            chain_id_override: Some(0),
+            ..one_shot_args(blocks_data_file.clone())
+        };
+        let err = one_shot(global_opts(), args).await.unwrap_err();
+        assert_eq!(exit_code(&err), Failure::InvalidArgs as u8);
+
+        let err = one_shot(global_opts(), one_shot_args(blocks_data_file.clone()))
+            .await
+            .unwrap_err();
+        assert_eq!(exit_code(&err), Failure::InvalidBlockData as u8);
+
+        fs::write(&blocks_data_file, b"not gzip").unwrap();
+        let err = one_shot(global_opts(), one_shot_args(blocks_data_file))
+            .await
+            .unwrap_err();
+        assert_eq!(exit_code(&err), Failure::InvalidBlockData as u8);
+    }
+
+    #[tokio::test]
+    async fn verifying_unreadable_block_data_is_invalid_block_data() {
+        let dir = tempfile::tempdir().unwrap();
+        // nothing is signed, the application needs no bootstrapping
+        let global_opts = GlobalOpts {
+            secrets_dir: dir.path().to_path_buf(),
+            verbose: 0,
+            quiet: false,
+        };
+        let args = OneShotArgs {
+            verify_against_rpc: Some("http://127.0.0.1:1".to_string()),
+            ..one_shot_args(dir.path().join("1.json.gz"))
+        };
+        let err = one_shot(global_opts, args).await.unwrap_err();
+        assert_eq!(exit_code(&err), Failure::InvalidBlockData as u8);
+    }
+
+    fn header<T>(block_no: u64) -> Block<T> {
+        Block {
+            number: Some(block_no.into()),
+            hash: Some(H256::from_low_u64_be(block_no)),
+            author: Some(H160::zero()),
+            logs_bloom: Some(Default::default()),
+            mix_hash: Some(H256::zero()),
+            nonce: Some(Default::default()),
+            base_fee_per_gas: Some(7.into()),
+            ..Default::default()
+        }
+    }
+
+    /// Writes the L2 data of block 1 and the L1 data of its anchor block to `dir`, the
+    /// anchor transaction committing to `anchored_signal_root`. The block is of Shanghai,
+    /// the fork of the chain, unless `pre_shanghai`.
+    fn write_block_data(dir: &Path, anchored_signal_root: H256, pre_shanghai: bool) {
+        let spec = get_taiko_chain_spec("internal_devnet_a");
+        let l1_height = 10;
+        let l1_hash = H256::from_low_u64_be(l1_height);
+        let parent_gas_used = 21_000;
+        let word = |n: u64| H256::from_low_u64_be(n).as_bytes().to_vec();
+        let to_h160 = |address: Address| H160::from_slice(address.as_slice());
+
+        let mut anchor_input = keccak256("anchor(bytes32,bytes32,uint64,uint32)")[..4].to_vec();
+        anchor_input.extend(l1_hash.as_bytes());
+        anchor_input.extend(anchored_signal_root.as_bytes());
+        anchor_input.extend(word(l1_height));
+        anchor_input.extend(word(parent_gas_used));
+        let anchor = EthersTransaction {
+            transaction_type: Some(2.into()),
+            chain_id: Some(spec.chain_id().into()),
+            from: to_h160(*GOLDEN_TOUCH_ACCOUNT),
+            to: Some(to_h160(spec.l2_contract.unwrap())),
+            gas: ANCHOR_GAS_LIMIT.into(),
+            max_fee_per_gas: Some(7.into()),
+            max_priority_fee_per_gas: Some(0.into()),
+            access_list: Some(Default::default()),
+            // the r of the anchor transactions signed with k = 1
+            r: EthersU256::from_big_endian(
+                &hex::decode("79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798")
+                    .unwrap(),
+            ),
+            input: anchor_input.into(),
+            ..Default::default()
+        };
+
+        let mut l2 = FileProvider::empty(String::new());
+        l2.insert_partial_block(
+            BlockQuery { block_no: 0 },
+            Block {
+                gas_used: parent_gas_used.into(),
+                ..header(0)
+            },
+        );
+        l2.insert_full_block(
+            BlockQuery { block_no: 1 },
+            Block {
+                transactions: vec![anchor],
+                withdrawals_root: (!pre_shanghai).then(H256::zero),
+                ..header(1)
+            },
+        );
+        l2.insert_proof(
+            ProofQuery {
+                block_no: 1,
+                address: to_h160(spec.l2_signal_service.unwrap()),
+                indices: Default::default(),
+            },
+            Default::default(),
+        );
+        l2.save_to_file(&dir.join("1.json.gz").to_string_lossy().to_string())
+            .unwrap();
+
+        // an empty RLP list of transactions
+        let mut propose_input = keccak256("proposeBlock(bytes,bytes)")[..4].to_vec();
+        propose_input.extend([word(0x40), word(0x60), word(0), word(1)].concat());
+        let mut tx_list = [0; 32];
+        tx_list[0] = 0xc0;
+        propose_input.extend(tx_list);
+        let mut l1 = FileProvider::empty(String::new());
+        l1.insert_partial_block(BlockQuery { block_no: 9 }, header(9));
+        l1.insert_full_block(BlockQuery { block_no: 10 }, header(10));
+        l1.insert_full_block(BlockQuery { block_no: 11 }, header(11));
+        l1.insert_proof(
+            ProofQuery {
+                block_no: 10,
+                address: to_h160(spec.l1_signal_service.unwrap()),
+                indices: Default::default(),
+            },
+            EIP1186ProofResponse {
+                storage_hash: H256::repeat_byte(0x22),
+                ..Default::default()
+            },
+        );
+        l1.insert_propose(
+            ProposeQuery {
+                l1_contract: to_h160(spec.l1_contract.unwrap()),
+                l1_block_no: 11,
+                l2_block_no: 1,
+            },
+            (
+                EthersTransaction {
+                    input: propose_input.into(),
+                    ..Default::default()
+                },
+                BlockProposed::default(),
+            ),
+        );
+        l1.save_to_file(&dir.join("1.l1.json.gz").to_string_lossy().to_string())
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn parse_only_checks_the_block_data_without_bootstrapping() {
+        let secrets_dir = tempfile::tempdir().unwrap();
+        let parse_only = |dir: &Path| {
+            let args = OneShotArgs {
+                parse_only: true,
+                output: Some(dir.join("report.json")),
+                ..one_shot_args(dir.join("1.json.gz"))
+            };
+            let global_opts = GlobalOpts {
+                secrets_dir: secrets_dir.path().to_path_buf(),
+                verbose: 0,
+                quiet: false,
+            };
+            one_shot(global_opts, args)
+        };
+
+        let dir = tempfile::tempdir().unwrap();
+        write_block_data(dir.path(), H256::repeat_byte(0x22), false);
+        parse_only(dir.path()).await.unwrap();
+        let report: serde_json::Value =
+            serde_json::from_slice(&fs::read(dir.path().join("report.json")).unwrap()).unwrap();
+        assert_eq!(report["blockNo"], 1);
+        assert_eq!(report["l1Height"], 10);
+        assert_eq!(report["l1Hash"], json!(B256::with_last_byte(10)));
+        assert_eq!(report["txListBytes"], 1);
+
+        // the anchor transaction commits to another L1 signal root
+        let dir = tempfile::tempdir().unwrap();
+        write_block_data(dir.path(), H256::repeat_byte(0x33), false);
+        let err = parse_only(dir.path()).await.unwrap_err();
+        assert_eq!(exit_code(&err), Failure::DataIntegrity as u8);
+
+        let gzip = |data: &[u8]| {
+            let mut encoder = flate2::write::GzEncoder::new(vec![], Default::default());
+            encoder.write_all(data).unwrap();
+            encoder.finish().unwrap()
+        };
+        let malformed: [Option<Vec<u8>>; 4] = [
+            None,
+            Some(b"not gzip".to_vec()),
+            Some(gzip(b"{")),
+            Some(gzip(br#"{"full_blocks": 1}"#)),
+        ];
+        for data in malformed {
+            let dir = tempfile::tempdir().unwrap();
+            if let Some(data) = &data {
+                fs::write(dir.path().join("1.json.gz"), data).unwrap();
+            }
+            let err = parse_only(dir.path()).await.unwrap_err();
+            assert_eq!(
+                exit_code(&err),
+                Failure::InvalidBlockData as u8,
+                "{:?}",
+                data
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn block_data_of_another_fork_is_handled_as_configured() {
+        let secrets_dir = tempfile::tempdir().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        write_block_data(dir.path(), H256::repeat_byte(0x22), true);
+        let parse_only = |on_chainspec_mismatch| {
+            let args = OneShotArgs {
+                parse_only: true,
+                output: Some(dir.path().join("report.json")),
+                on_chainspec_mismatch,
+                ..one_shot_args(dir.path().join("1.json.gz"))
+            };
+            let global_opts = GlobalOpts {
+                secrets_dir: secrets_dir.path().to_path_buf(),
+                verbose: 0,
+                quiet: false,
+            };
+            one_shot(global_opts, args)
+        };
+
+        let err = parse_only(ChainSpecMismatchPolicy::Error)
+            .await
+            .unwrap_err();
+        assert_eq!(exit_code(&err), Failure::DataIntegrity as u8);
+        assert!(format!("{:#}", err).contains("fork MERGE of the data"));
+
+        for policy in [
+            ChainSpecMismatchPolicy::Warn,
+            ChainSpecMismatchPolicy::Override,
+        ] {
+            parse_only(policy).await.unwrap();
+            let report: serde_json::Value =
+                serde_json::from_slice(&fs::read(dir.path().join("report.json")).unwrap()).unwrap();
+            assert_eq!(report["chainSpecMismatch"]["policy"], json!(policy));
+            assert_eq!(report["chainSpecMismatch"]["dataFork"], "MERGE");
+        }
+    }
+
+    #[test]
+    fn inconsistent_inputs_are_data_integrity_failures() {
+        let err = anyhow!("l1 signal root mismatch");
+        assert_eq!(input_failure(&err), Failure::DataIntegrity);
+        let err = Error::from(io::Error::from(io::ErrorKind::NotFound)).context("opening");
+        assert_eq!(input_failure(&err), Failure::InvalidBlockData);
+    }
+
+    /// Writes a proof of `block_no` and `block_hash` to `path`, for the prover `0x05..` and
+    /// the graffiti `0x04..`, as the `--output` of a run.
+    fn write_proof_file(path: &Path, block_no: u64, block_hash: B256) {
+        let privkey = generate_key().secret_key();
+        let new_pubkey = public_key(&privkey);
+        let public_inputs = PublicInputs {
+            parent_hash: B256::repeat_byte(0x01),
+            block_hash,
+            signal_root: B256::repeat_byte(0x03),
+            graffiti: B256::repeat_byte(0x04),
+            new_instance: public_key_to_address(&new_pubkey),
+            prover: Address::repeat_byte(0x05),
+            meta_hash: B256::repeat_byte(0x06),
+        };
+        let (proof, envelope) = sign_proof(&privkey, 1, public_inputs.hash(), 0).unwrap();
+        let proof = format!("0x{}", hex::encode(proof));
+        let mut report = proof_report(&proof, &new_pubkey, &envelope, &public_inputs);
+        report["block"] = block_no.into();
+        fs::write(path, report.to_string()).unwrap();
+    }
+
+    #[test]
+    fn existing_outputs_are_handled_as_configured() {
+        let dir = tempfile::tempdir().unwrap();
+        let good = dir.path().join("good.json");
+        write_proof_file(&good, 10, B256::repeat_byte(0x02));
+        // as left by an interrupted write
+        let corrupt = dir.path().join("corrupt.json");
+        fs::write(&corrupt, &fs::read(&good).unwrap()[..100]).unwrap();
+        let missing = dir.path().join("missing.json");
+
+        let existing = |on_existing, path: &Path, block_no| {
+            existing_proof(
+                on_existing,
+                path,
+                None,
+                block_no,
+                Address::repeat_byte(0x05),
+                B256::repeat_byte(0x04),
+            )
+        };
+        let prove = |on_existing, path: &Path, block_no| {
+            existing(on_existing, path, block_no).map(|existing| existing.is_none())
+        };
+        for on_existing in [
+            ExistingOutput::Overwrite,
+            ExistingOutput::Skip,
+            ExistingOutput::Error,
+        ] {
+            assert!(prove(on_existing, &missing, 10).unwrap());
+        }
+        assert!(prove(ExistingOutput::Overwrite, &good, 10).unwrap());
+        assert!(prove(ExistingOutput::Overwrite, &corrupt, 10).unwrap());
+        // only a verifying proof of the same block is kept
+        assert_eq!(
+            existing(ExistingOutput::Skip, &good, 10).unwrap(),
+            Some(B256::repeat_byte(0x02))
+        );
+        assert!(prove(ExistingOutput::Skip, &good, 11).unwrap());
+        assert!(prove(ExistingOutput::Skip, &corrupt, 10).unwrap());
+        for path in [&good, &corrupt] {
+            let err = prove(ExistingOutput::Error, path, 10).unwrap_err();
+            assert_eq!(exit_code(&err), Failure::InvalidArgs as u8);
+        }
+    }
+
+    #[tokio::test]
+    async fn batch_manifest_lists_the_proof_files() {
+        let dir = tempfile::tempdir().unwrap();
+        save_private_key(
+            dir.path().join(PRIV_KEY_FILENAME),
+            &generate_key().secret_key(),
+        )
+        .unwrap();
+        let manifest = dir.path().join("manifest.jsonl");
+        // blocks 10 and 11 were proven by an earlier batch, 12 has no block data
+        write_proof_file(&dir.path().join("10.json"), 10, B256::repeat_byte(0x0a));
+        write_proof_file(&dir.path().join("11.json"), 11, B256::repeat_byte(0x0b));
+        for block in 10..=12 {
+            let args = OneShotArgs {
+                prover: Address::repeat_byte(0x05),
+                graffiti: B256::repeat_byte(0x04),
+                output: Some(dir.path().join(format!("{}.json", block))),
+                on_existing_output: ExistingOutput::Skip,
+                manifest: Some(manifest.clone()),
+                ..one_shot_args(dir.path().join(format!("{}.json.gz", block)))
+            };
+            let global_opts = GlobalOpts {
+                secrets_dir: dir.path().to_path_buf(),
+                verbose: 0,
+                quiet: false,
+            };
+            let result = one_shot(global_opts, args).await;
+            assert_eq!(result.is_ok(), block != 12);
+        }
+
+        let entries: Vec<ManifestEntry> = fs::read_to_string(&manifest)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(entries.len(), 3);
+        for entry in &entries[..2] {
+            let output = entry.output.as_ref().unwrap();
+            let proof: serde_json::Value =
+                serde_json::from_slice(&fs::read(output).unwrap()).unwrap();
+            assert_eq!(json!(entry.block), proof["block"]);
+            assert_eq!(json!(entry.block_hash), proof["publicInputs"]["blockHash"]);
+            assert_eq!(entry.outcome, Outcome::Skipped);
+        }
+        assert_eq!(entries[2].block, Some(12));
+        assert_eq!(entries[2].outcome, Outcome::Failed);
+        assert_eq!(entries[2].block_hash, None);
+        assert!(!entries[2].output.as_ref().unwrap().exists());
+    }
+
+    #[test]
+    fn proof_output_is_a_single_json_line() {
+        let report = json!({
+            "proof": "0x01",
+            "publicKey": "0x02",
+            "instanceAddress": Address::ZERO.to_string(),
+        });
+        let mut stdout = Vec::new();
+        write_proof_json(&mut stdout, &report).unwrap();
+
+        let stdout = String::from_utf8(stdout).unwrap();
+        assert_eq!(stdout.lines().count(), 1);
+        let parsed: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+        assert_eq!(parsed, report);
+    }
+
+    #[test]
+    fn proofs_signed_at_a_fixed_now_are_reproducible() {
+        use clap::Parser;
+
+        use crate::app_args::{App, Command};
+
+        let secrets_dir = tempfile::tempdir().unwrap();
+        save_private_key(
+            secrets_dir.path().join(PRIV_KEY_FILENAME),
+            &generate_key().secret_key(),
+        )
+        .unwrap();
+        let out_dir = tempfile::tempdir().unwrap();
+        // signs a block and writes its proof the way the one-shot runs do
+        let run = |run: usize, fixed_now: &str| {
+            let app = App::try_parse_from([
+                "raiko-guest",
+                "one-shot",
+                "--blocks-data-file=1.json.gz",
+                "--l1-blocks-data-file=1.l1.json.gz",
+                "--prover=0x0505050505050505050505050505050505050505",
+                "--graffiti=0x0404040404040404040404040404040404040404040404040404040404040404",
+                "--sgx-instance-id=1",
+                fixed_now,
+            ])
+            .unwrap();
+            let Command::OneShot(args) = app.command else {
+                panic!("not a one-shot run");
+            };
+            let privkey = signing_key(secrets_dir.path(), unix_now(None).unwrap()).unwrap();
+            let new_pubkey = public_key(&privkey);
+            let public_inputs = PublicInputs {
+                parent_hash: B256::repeat_byte(0x01),
+                block_hash: B256::repeat_byte(0x02),
+                signal_root: B256::repeat_byte(0x03),
+                graffiti: args.graffiti,
+                new_instance: public_key_to_address(&new_pubkey),
+                prover: args.prover,
+                meta_hash: B256::repeat_byte(0x06),
+            };
+            let (_, envelope, report) = sign_block(
+                &privkey,
+                args.sgx_instance_id,
+                &public_inputs,
+                1,
+                args.fixed_now,
+            )
+            .unwrap();
+            let path = out_dir.path().join(format!("{}.json", run));
+            write_proof_output(&path, args.output_compression, None, &report).unwrap();
+            (fs::read(path).unwrap(), envelope.timestamp)
+        };
+
+        let (first, timestamp) = run(0, "--fixed-now=1700000000");
+        let (second, _) = run(1, "--fixed-now=1700000000");
+        assert_eq!(timestamp, 1_700_000_000);
+        assert_eq!(second, first);
+        // the proofs differ with the time they are signed at
+        assert_ne!(run(2, "--fixed-now=1700000001").0, first);
+    }
+
+    #[test]
+    fn anchor_mismatches_name_both_l1_blocks() {
+        let extra = TaikoExtra {
+            l1_hash: B256::repeat_byte(0x01),
+            l1_height: 10,
+            l2_tx_list: vec![],
+            l2_blob_hash: None,
+            prover: Address::ZERO,
+            graffiti: B256::ZERO,
+            l1_signal_root: B256::ZERO,
+            l2_signal_root: B256::ZERO,
+            l2_withdrawals: vec![],
+            block_proposed: BlockProposed::default(),
+            l1_next_block: Default::default(),
+            chain_spec_mismatch: None,
+        };
+        assert!(check_anchor_hash(&extra, None, 1).is_ok());
+        assert!(check_anchor_hash(&extra, Some(B256::repeat_byte(0x01)), 1).is_ok());
+
+        let err = check_anchor_hash(&extra, Some(B256::repeat_byte(0x02)), 1).unwrap_err();
+        assert_eq!(exit_code(&err), Failure::InvalidArgs as u8);
+        assert_eq!(
+            err.downcast_ref::<AnchorMismatch>(),
+            Some(&AnchorMismatch {
+                block_no: 1,
+                expected: B256::repeat_byte(0x02),
+                actual: B256::repeat_byte(0x01),
+                l1_height: 10,
+            })
+        );
+    }
+
+    #[test]
+    fn l1_data_comes_from_exactly_one_source() {
+        use clap::Parser;
+
+        let parse = |l1_args: &[&str]| {
+            let args = [
+                "raiko-guest",
+                "one-shot",
+                "--blocks-data-file=1.json.gz",
+                "--prover=0x0000000000000000000000000000000000000000",
+                "--graffiti=0x0000000000000000000000000000000000000000000000000000000000000000",
+                "--sgx-instance-id=0",
+            ];
+            crate::app_args::App::try_parse_from(args.iter().chain(l1_args))
+        };
+        let hash =
+            "--l1-anchor-hash=0x0000000000000000000000000000000000000000000000000000000000000001";
+        assert!(parse(&["--l1-blocks-data-file=1.l1.json.gz"]).is_ok());
+        assert!(parse(&[hash, "--l1-rpc=http://localhost:8545"]).is_ok());
+        assert!(parse(&[]).is_err());
+        assert!(parse(&[hash]).is_err());
+        assert!(parse(&[
+            "--l1-blocks-data-file=1.l1.json.gz",
+            hash,
+            "--l1-rpc=http://localhost:8545"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn tx_range_is_gated_behind_the_consensus_overrides() {
+        use clap::Parser;
+
+        let parse = |extra: &[&str]| {
+            let args = [
+                "raiko-guest",
+                "one-shot",
+                "--blocks-data-file=1.json.gz",
+                "--l1-blocks-data-file=1.l1.json.gz",
+                "--prover=0x0000000000000000000000000000000000000000",
+                "--graffiti=0x0000000000000000000000000000000000000000000000000000000000000000",
+                "--sgx-instance-id=0",
+            ];
+            crate::app_args::App::try_parse_from(args.iter().chain(extra))
+        };
+        assert!(parse(&["--tx-range=1:3"]).is_err());
+        assert!(parse(&["--tx-range=1:3", "--allow-consensus-overrides"]).is_ok());
+        for range in ["3:1", "2:2", "1", "a:2"] {
+            let arg = format!("--tx-range={}", range);
+            assert!(parse(&[arg.as_str(), "--allow-consensus-overrides"]).is_err());
+        }
+        assert_eq!("1:3".parse::<TxRange>(), Ok(TxRange { from: 1, to: 3 }));
+    }
+
+    #[test]
+    fn quiet_conflicts_with_verbose() {
+        use clap::Parser;
+
+        let args = ["raiko-guest", "--quiet", "-v", "bootstrap"];
+        assert!(crate::app_args::App::try_parse_from(args).is_err());
+        let args = ["raiko-guest", "--quiet", "bootstrap"];
+        assert!(
+            crate::app_args::App::try_parse_from(args)
+                .unwrap()
+                .global_opts
+                .quiet
+        );
+    }
+
+    struct FlakyBackend {
+        failures: u32,
+        kind: io::ErrorKind,
+        calls: u32,
+    }
+
+    impl SgxBackend for FlakyBackend {
+        fn attest(&mut self, _report_data: &[u8; REPORT_DATA_SIZE]) -> Result<Vec<u8>> {
+            self.calls += 1;
+            if self.calls <= self.failures {
+                return Err(io::Error::from(self.kind)).context("Failed to read the quote");
+            }
+            Ok(vec![])
+        }
+    }
+
+    fn flaky(failures: u32, kind: io::ErrorKind) -> FlakyBackend {
+        FlakyBackend {
+            failures,
+            kind,
+            calls: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn attestation_is_retried_on_transient_faults() {
+        let mut backend = flaky(2, io::ErrorKind::TimedOut);
+        attest_with_retries(&mut backend, &[0; REPORT_DATA_SIZE], 2, Duration::ZERO)
+            .await
+            .unwrap();
+        assert_eq!(backend.calls, 3);
+
+        let mut backend = flaky(3, io::ErrorKind::TimedOut);
+        assert!(
+            attest_with_retries(&mut backend, &[0; REPORT_DATA_SIZE], 2, Duration::ZERO)
+                .await
+                .is_err()
+        );
+        assert_eq!(backend.calls, 3);
+    }
+
+    #[tokio::test]
+    async fn permanent_faults_are_not_retried() {
+        let mut backend = flaky(1, io::ErrorKind::NotFound);
+        let err = attest_with_retries(&mut backend, &[0; REPORT_DATA_SIZE], 5, Duration::ZERO)
+            .await
+            .unwrap_err();
+        assert_eq!(backend.calls, 1);
+        assert_eq!(exit_code(&err), Failure::Attestation as u8);
+    }
+}
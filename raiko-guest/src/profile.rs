@@ -0,0 +1,102 @@
+use std::time::{Duration, Instant};
+
+use serde_json::json;
+
+use crate::progress::Progress;
+
+/// Accounts the time spent in each phase of a proving run. The guest runs natively in
+/// the enclave, so wall-clock time stands in for the cycle counts of a zkVM.
+pub struct Profiler {
+    start: Instant,
+    last: Instant,
+    phases: Vec<(&'static str, Duration)>,
+    /// reports each phase as it ends
+    progress: Option<Progress>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        let now = Instant::now();
+        Profiler {
+            start: now,
+            last: now,
+            phases: vec![],
+            progress: None,
+        }
+    }
+
+    pub fn with_progress(self, progress: Option<Progress>) -> Self {
+        Profiler { progress, ..self }
+    }
+
+    /// Ends `phase`, which started when the previous one ended.
+    pub fn end_phase(&mut self, phase: &'static str) {
+        let now = Instant::now();
+        self.phases.push((phase, now - self.last));
+        self.last = now;
+        if let Some(progress) = &self.progress {
+            progress.phase_ended(phase);
+        }
+    }
+
+    /// The time since the profiler was created, the sum of the phases ended so far.
+    pub fn total(&self) -> Duration {
+        self.last - self.start
+    }
+
+    /// The profile of the block, with what makes it expensive to prove.
+    pub fn report(&self, block_no: u64, tx_count: usize, gas_used: u64) -> serde_json::Value {
+        let phases: Vec<_> = self
+            .phases
+            .iter()
+            .map(|(phase, elapsed)| json!({ "phase": phase, "nanos": elapsed.as_nanos() as u64 }))
+            .collect();
+        json!({
+            "blockNo": block_no,
+            "txCount": tx_count,
+            "gasUsed": gas_used,
+            "totalNanos": self.total().as_nanos() as u64,
+            "phases": phases,
+        })
+    }
+}
+
+/// Ends `phase` when profiling or reporting the progress, only then reading the clock.
+pub fn end_phase(profiler: &mut Option<Profiler>, phase: &'static str) {
+    if let Some(profiler) = profiler {
+        profiler.end_phase(phase);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn phases_sum_up_to_the_total() {
+        let mut profiler = Profiler::new();
+        for phase in ["load", "execute", "sign"] {
+            std::thread::sleep(Duration::from_millis(2));
+            profiler.end_phase(phase);
+        }
+        assert!(profiler.total() >= Duration::from_millis(6));
+
+        let report = profiler.report(1, 2, 21_000);
+        let phases = report["phases"].as_array().unwrap();
+        assert_eq!(phases.len(), 3);
+        assert_eq!(phases[1]["phase"], "execute");
+        let sum: u64 = phases
+            .iter()
+            .map(|phase| phase["nanos"].as_u64().unwrap())
+            .sum();
+        assert_eq!(sum, report["totalNanos"].as_u64().unwrap());
+        assert_eq!(report["gasUsed"], 21_000);
+    }
+
+    #[test]
+    fn nothing_is_accounted_when_disabled() {
+        let mut profiler = None;
+        end_phase(&mut profiler, "load");
+        assert!(profiler.is_none());
+    }
+}
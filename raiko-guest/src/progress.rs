@@ -0,0 +1,179 @@
+//! The progress of a `one-shot` run as JSON lines appended to a `--progress-out` file or
+//! FIFO, for an external scheduler to tail: the run starting, each phase of the proof
+//! ending, with the phases of the profile, and the run ending with the outcome of the
+//! manifest. The runs of a batch may share the file.
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    sync::Arc,
+};
+
+use serde::Serialize;
+
+use crate::manifest::{ManifestEntry, Outcome};
+
+/// The phases of the proof of a block, in the order they end.
+pub const PROOF_PHASES: &[&str] = &[
+    "load",
+    "initialize",
+    "prepare",
+    "execute",
+    "finalize",
+    "assemble",
+    "sign",
+    "attest",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ProgressEvent {
+    Started {
+        block: u64,
+    },
+    /// `phase` ended, `percent` of the proof done, unless it is outside of
+    /// [PROOF_PHASES]
+    Phase {
+        block: u64,
+        phase: &'static str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        percent: Option<u8>,
+    },
+    Done {
+        block: Option<u64>,
+        outcome: Outcome,
+        #[serde(rename = "durationMs")]
+        duration_ms: u64,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ProgressLine<'a> {
+    #[serde(flatten)]
+    event: &'a ProgressEvent,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    batch_index: Option<u64>,
+}
+
+/// The progress file of the run, with the block being proven once it is known.
+#[derive(Debug, Clone)]
+pub struct Progress {
+    file: Arc<File>,
+    /// the index of the block in its batch, given by the scheduler
+    batch_index: Option<u64>,
+    block: Option<u64>,
+}
+
+impl Progress {
+    /// Opens the progress file, which a FIFO only does once it has a reader.
+    pub fn open(path: &Path, batch_index: Option<u64>) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Progress {
+            file: Arc::new(file),
+            batch_index,
+            block: None,
+        })
+    }
+
+    pub fn started(&mut self, block: u64) {
+        self.block = Some(block);
+        self.emit(&ProgressEvent::Started { block });
+    }
+
+    pub fn phase_ended(&self, phase: &'static str) {
+        let Some(block) = self.block else {
+            return;
+        };
+        let percent = PROOF_PHASES
+            .iter()
+            .position(|known| *known == phase)
+            .map(|index| ((index + 1) * 100 / PROOF_PHASES.len()) as u8);
+        self.emit(&ProgressEvent::Phase {
+            block,
+            phase,
+            percent,
+        });
+    }
+
+    pub fn done(&self, entry: &ManifestEntry) {
+        self.emit(&ProgressEvent::Done {
+            block: entry.block,
+            outcome: entry.outcome,
+            duration_ms: entry.duration_ms,
+        });
+    }
+
+    /// Appends the event as a line, in a single write for the lines of the runs sharing
+    /// the file not to interleave. The proof goes on if the file can't be written.
+    fn emit(&self, event: &ProgressEvent) {
+        let line = ProgressLine {
+            event,
+            batch_index: self.batch_index,
+        };
+        let mut line = serde_json::to_vec(&line).expect("progress events serialize");
+        line.push(b'\n');
+        if let Err(err) = (&*self.file).write_all(&line) {
+            eprintln!("WARNING: failed to write the progress: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::profile::{end_phase, Profiler};
+
+    #[test]
+    fn progress_of_a_proof_is_reported_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("progress.jsonl");
+        let mut progress = Progress::open(&path, Some(3)).unwrap();
+        progress.started(10);
+        // the phases of the proof end as one-shot ends them
+        let mut profiler = Some(Profiler::new().with_progress(Some(progress.clone())));
+        for phase in PROOF_PHASES {
+            end_phase(&mut profiler, phase);
+        }
+        progress.done(&ManifestEntry {
+            block: Some(10),
+            outcome: Outcome::Proven,
+            duration_ms: 1200,
+            ..Default::default()
+        });
+
+        let lines: Vec<Value> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), PROOF_PHASES.len() + 2);
+        assert_eq!(
+            lines[0],
+            json!({"event": "started", "block": 10, "batchIndex": 3})
+        );
+        let phases: Vec<_> = lines[1..=PROOF_PHASES.len()]
+            .iter()
+            .map(|line| {
+                assert_eq!(line["event"], "phase");
+                assert_eq!(line["block"], 10);
+                line["phase"].as_str().unwrap()
+            })
+            .collect();
+        assert_eq!(phases, PROOF_PHASES);
+        assert_eq!(lines[1]["percent"], 12);
+        assert_eq!(lines[PROOF_PHASES.len()]["percent"], 100);
+        assert_eq!(
+            lines.last().unwrap(),
+            &json!({
+                "event": "done",
+                "block": 10,
+                "outcome": "proven",
+                "durationMs": 1200,
+                "batchIndex": 3,
+            })
+        );
+    }
+}
@@ -0,0 +1,114 @@
+//! The public inputs a proof of a block is expected to sign, derived without proving:
+//! the block is loaded from its block data and assembled into its protocol instance as
+//! the prover does, taking the header of the block data for the one of the executed
+//! block. The two are the same for every block the prover proves.
+use anyhow::{Context, Result};
+use zeth_lib::{
+    consts::get_taiko_chain_spec, host::provider::RpcOptions,
+    taiko::protocol_instance::assemble_protocol_instance,
+};
+use zeth_primitives::taiko::PublicInputs;
+
+use crate::{
+    app_args::{GlobalOpts, PublicInputsArgs},
+    exit_code::Failure,
+    one_shot::{block_no_of, parse_to_init, public_inputs_report, L1Source, PRIV_KEY_FILENAME},
+    signature::{load_private_key, public_key, public_key_to_address},
+};
+
+/// Prints the expected public inputs of the block as JSON, with their ABI encoding and
+/// hash, and the state roots before and after the block.
+pub async fn public_inputs(global_opts: GlobalOpts, args: PublicInputsArgs) -> Result<()> {
+    let block_no = block_no_of(&args.blocks_data_file)?;
+    let new_instance = match args.new_instance {
+        Some(instance) => instance,
+        None => {
+            let privkey_path = global_opts.secrets_dir.join(PRIV_KEY_FILENAME);
+            let privkey = load_private_key(&privkey_path).context(Failure::Secrets)?;
+            public_key_to_address(&public_key(&privkey))
+        }
+    };
+    let (init, extra) = parse_to_init(
+        &get_taiko_chain_spec(&args.l2_chain),
+        args.blocks_data_file.to_string_lossy().to_string(),
+        L1Source::File(args.l1_blocks_data_file.to_string_lossy().to_string()),
+        RpcOptions::default(),
+        args.prover,
+        block_no,
+        args.graffiti,
+    )
+    .await?;
+    let pi =
+        assemble_protocol_instance(&extra, &init.fini_block).context(Failure::DataIntegrity)?;
+    let mut report = expected_public_inputs_report(&pi.public_inputs(new_instance))?;
+    report["block"] = block_no.into();
+    report["parentStateRoot"] = serde_json::to_value(init.init_block.state_root)?;
+    report["stateRoot"] = serde_json::to_value(init.fini_block.state_root)?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
+
+/// The public inputs as the `--emit-public-inputs` of `one-shot` writes them, with the
+/// hash a proof signs.
+fn expected_public_inputs_report(public_inputs: &PublicInputs) -> Result<serde_json::Value> {
+    let mut report = public_inputs_report(public_inputs)?;
+    report["hash"] = serde_json::to_value(public_inputs.hash())?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use zeth_primitives::{
+        taiko::{BlockMetadata, ProtocolInstance, Transition},
+        Address, B256,
+    };
+
+    use super::*;
+    use crate::{
+        one_shot::{proof_report, sign_proof},
+        signature::generate_key,
+    };
+
+    #[test]
+    fn expected_public_inputs_are_the_ones_a_proof_embeds() {
+        let pi = ProtocolInstance {
+            transition: Transition {
+                parentHash: B256::repeat_byte(0x01),
+                blockHash: B256::repeat_byte(0x02),
+                signalRoot: B256::repeat_byte(0x03),
+                graffiti: B256::repeat_byte(0x04),
+            },
+            block_metadata: BlockMetadata {
+                id: 1,
+                coinbase: Address::repeat_byte(0x05),
+                ..Default::default()
+            },
+            prover: Address::repeat_byte(0x06),
+        };
+        let privkey = generate_key().secret_key();
+        let new_pubkey = public_key(&privkey);
+        let public_inputs = pi.public_inputs(public_key_to_address(&new_pubkey));
+        let expected = expected_public_inputs_report(&public_inputs).unwrap();
+
+        // signed as one-shot signs the public inputs of the block
+        let (proof, envelope) = sign_proof(&privkey, 1, public_inputs.hash(), 0).unwrap();
+        let proof = format!("0x{}", hex::encode(proof));
+        let report = proof_report(&proof, &new_pubkey, &envelope, &public_inputs);
+        for (field, value) in report["publicInputs"].as_object().unwrap() {
+            assert_eq!(&expected[field], value, "{}", field);
+        }
+        assert_eq!(expected["metaHash"], serde_json::json!(pi.meta_hash()));
+        assert_eq!(expected["hash"], serde_json::json!(envelope.public_inputs));
+        let abi_encoded = hex::decode(
+            expected["abiEncoded"]
+                .as_str()
+                .unwrap()
+                .trim_start_matches("0x"),
+        )
+        .unwrap();
+        assert_eq!(
+            serde_json::json!(zeth_primitives::keccak256(abi_encoded)),
+            expected["hash"]
+        );
+    }
+}
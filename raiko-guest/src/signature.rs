@@ -1,10 +1,17 @@
-use std::{fs, path::Path};
+use std::{
+    fs::{self, OpenOptions, Permissions},
+    io::Write,
+    os::unix::fs::PermissionsExt,
+    path::Path,
+};
 
+use anyhow::{bail, Context};
 use rand_core::OsRng;
 use secp256k1::{
     ecdsa::{RecoverableSignature, RecoveryId},
     Error, KeyPair, Message, PublicKey, Secp256k1, SecretKey, SECP256K1,
 };
+use zeth_lib::host::atomic_file::write_atomically;
 use zeth_primitives::{keccak256, signature::TxSignature, Address, B256, U256};
 
 pub fn generate_key() -> KeyPair {
@@ -18,7 +25,6 @@ pub fn generate_key() -> KeyPair {
 ///
 /// This does not ensure that the `s` value in the signature is low, and _just_ wraps the
 /// underlying secp256k1 library.
-#[allow(dead_code)]
 pub fn recover_signer_unchecked(sig: &[u8; 65], msg: &[u8; 32]) -> Result<Address, Error> {
     let sig =
         RecoverableSignature::from_compact(&sig[0..64], RecoveryId::from_i32(sig[64] as i32)?)?;
@@ -52,9 +58,75 @@ pub fn public_key_to_address(public: &PublicKey) -> Address {
     Address::from_slice(&hash[12..])
 }
 
-pub fn load_private_key<T: AsRef<Path>>(path: T) -> Result<SecretKey, Error> {
-    let data = fs::read(path).unwrap();
-    SecretKey::from_slice(data.as_ref())
+/// Prefix of the private key files, followed by the format version and the 32 bytes of the
+/// secret. The format is versioned to tell it from the legacy plaintext keys, it is not
+/// compressed: a random secret doesn't compress. The encryption is the sealing of the
+/// secrets directory.
+const KEY_FILE_MAGIC: &[u8] = b"RAIKOKEY";
+const KEY_FILE_VERSION: u8 = 1;
+/// Extension of the temporary files the key files are written to.
+const KEY_TMP_EXTENSION: &str = "tmp";
+
+/// Saves the private key in the key file format, readable by the owner only.
+/// The secrets directory is sealed by gramine, so the file is encrypted at rest. The file
+/// is replaced atomically and durably, a crash never leaving a torn key behind.
+pub fn save_private_key<T: AsRef<Path>>(path: T, secret: &SecretKey) -> anyhow::Result<()> {
+    let path = path.as_ref();
+    let mut data = KEY_FILE_MAGIC.to_vec();
+    data.push(KEY_FILE_VERSION);
+    data.extend(secret.secret_bytes());
+    write_atomically(path, KEY_TMP_EXTENSION, |file| {
+        file.set_permissions(Permissions::from_mode(0o600))?;
+        Ok(file.write_all(&data)?)
+    })
+    .with_context(|| format!("Failed to write private key file {}", path.display()))
+}
+
+pub fn load_private_key<T: AsRef<Path>>(path: T) -> anyhow::Result<SecretKey> {
+    let path = path.as_ref();
+    let data = fs::read(path)
+        .with_context(|| format!("Failed to read private key file {}", path.display()))?;
+    match data.strip_prefix(KEY_FILE_MAGIC) {
+        Some([KEY_FILE_VERSION, secret @ ..]) => Ok(SecretKey::from_slice(secret)?),
+        Some(_) => bail!("Unsupported key file version in {}", path.display()),
+        None => bail!(
+            "{} holds a legacy plaintext key, it must be migrated before use: run `migrate-keys --from {}`",
+            path.display(),
+            path.display()
+        ),
+    }
+}
+
+/// Loads a private key of the legacy plaintext format, either the raw secret bytes or the
+/// hex-encoded secret.
+pub fn load_legacy_private_key<T: AsRef<Path>>(path: T) -> anyhow::Result<SecretKey> {
+    let path = path.as_ref();
+    let data = fs::read(path)
+        .with_context(|| format!("Failed to read legacy key file {}", path.display()))?;
+    if data.starts_with(KEY_FILE_MAGIC) {
+        bail!("{} is already in the current key format", path.display());
+    }
+    if data.len() == 32 {
+        return Ok(SecretKey::from_slice(&data)?);
+    }
+    let text = std::str::from_utf8(&data)
+        .with_context(|| format!("Unknown key format in {}", path.display()))?
+        .trim();
+    let secret = hex::decode(text.strip_prefix("0x").unwrap_or(text))
+        .with_context(|| format!("Unknown key format in {}", path.display()))?;
+    Ok(SecretKey::from_slice(&secret)?)
+}
+
+/// Overwrites the file with zeroes before removing it, so the plaintext doesn't linger in
+/// the freed blocks.
+pub fn shred_file<T: AsRef<Path>>(path: T) -> anyhow::Result<()> {
+    let path = path.as_ref();
+    let len = fs::metadata(path)?.len() as usize;
+    let mut file = OpenOptions::new().write(true).open(path)?;
+    file.write_all(&vec![0u8; len])?;
+    file.sync_all()?;
+    drop(file);
+    fs::remove_file(path).with_context(|| format!("Failed to remove {}", path.display()))
 }
 
 pub fn public_key(secret: &SecretKey) -> PublicKey {
@@ -85,4 +157,42 @@ mod tests {
         println!("Public address: {}", pub_addr);
         println!("Proof public address: {}", proof_addr);
     }
+
+    #[test]
+    fn migrated_key_signs_like_the_original() {
+        let dir = tempfile::tempdir().unwrap();
+        let legacy_path = dir.path().join("legacy.key");
+        let key_path = dir.path().join("priv.key");
+
+        let original = generate_key().secret_key();
+        fs::write(&legacy_path, original.secret_bytes()).unwrap();
+        // the legacy key is refused by the regular load path
+        assert!(load_private_key(&legacy_path).is_err());
+
+        let legacy = load_legacy_private_key(&legacy_path).unwrap();
+        save_private_key(&key_path, &legacy).unwrap();
+        shred_file(&legacy_path).unwrap();
+        assert!(!legacy_path.exists());
+
+        let migrated = load_private_key(&key_path).unwrap();
+        let msg = B256::from([7u8; 32]);
+        assert_eq!(
+            sign_message(&migrated, msg).unwrap(),
+            sign_message(&original, msg).unwrap()
+        );
+        assert_eq!(
+            public_key_to_address(&public_key(&migrated)),
+            public_key_to_address(&public_key(&original))
+        );
+        assert_eq!(
+            fs::metadata(&key_path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+        // only the key file is left
+        let names: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(names, ["priv.key"]);
+    }
 }
@@ -0,0 +1,189 @@
+//! Checks at startup that the paths of the options exist with the permissions the server
+//! needs, so that a misconfigured volume mount fails the start rather than the requests.
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::prover::{consts::SGX_PARENT_DIR, utils::guest_executable_path};
+
+/// What the server does with a path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// a directory files are read from and written to
+    ReadWriteDir,
+    /// a file run as a program
+    ExecuteFile,
+}
+
+/// A path of the options, and what the server does with it.
+#[derive(Debug, Clone)]
+pub struct PathCheck {
+    pub option: &'static str,
+    pub path: PathBuf,
+    pub access: Access,
+}
+
+/// A path which will not work as configured.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FsProblem {
+    pub option: &'static str,
+    pub path: PathBuf,
+    pub problem: String,
+}
+
+impl fmt::Display for FsProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {}: {}",
+            self.option,
+            self.path.display(),
+            self.problem
+        )
+    }
+}
+
+/// The checks of the paths of the server, the guest checked through its sgx executable.
+pub fn path_checks(
+    cache: &Path,
+    guest: &Path,
+    log_path: Option<&Path>,
+    proof_cache_dir: Option<&Path>,
+) -> Vec<PathCheck> {
+    let mut checks = vec![
+        PathCheck {
+            option: "cache",
+            path: cache.to_owned(),
+            access: Access::ReadWriteDir,
+        },
+        PathCheck {
+            option: "guest",
+            path: guest_executable_path(guest, SGX_PARENT_DIR),
+            access: Access::ExecuteFile,
+        },
+    ];
+    if let Some(log_path) = log_path {
+        checks.push(PathCheck {
+            option: "log_path",
+            path: log_path.to_owned(),
+            access: Access::ReadWriteDir,
+        });
+    }
+    if let Some(proof_cache_dir) = proof_cache_dir {
+        checks.push(PathCheck {
+            option: "proof_cache_dir",
+            path: proof_cache_dir.to_owned(),
+            access: Access::ReadWriteDir,
+        });
+    }
+    checks
+}
+
+/// Runs the checks, with one problem per path failing its check.
+pub fn check_paths(checks: &[PathCheck]) -> Vec<FsProblem> {
+    checks
+        .iter()
+        .filter_map(|check| {
+            check_path(&check.path, check.access)
+                .err()
+                .map(|problem| FsProblem {
+                    option: check.option,
+                    path: check.path.clone(),
+                    problem,
+                })
+        })
+        .collect()
+}
+
+fn check_path(path: &Path, access: Access) -> Result<(), String> {
+    // follows the symlinks, failing on the dangling ones
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) if path.is_symlink() => return Err("is a symlink to nowhere".to_owned()),
+        Err(err) => return Err(format!("does not exist or is not accessible: {}", err)),
+    };
+    match access {
+        Access::ReadWriteDir => {
+            if !metadata.is_dir() {
+                return Err("is not a directory".to_owned());
+            }
+            fs::read_dir(path).map_err(|err| format!("is not readable: {}", err))?;
+            // probing, which also catches the read-only mounts
+            tempfile::tempfile_in(path).map_err(|err| format!("is not writable: {}", err))?;
+        }
+        Access::ExecuteFile => {
+            if !metadata.is_file() {
+                return Err("is not a file".to_owned());
+            }
+            fs::File::open(path).map_err(|err| format!("is not readable: {}", err))?;
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                if metadata.permissions().mode() & 0o111 == 0 {
+                    return Err("is not executable".to_owned());
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn guest_dir(root: &Path) -> PathBuf {
+        let guest = root.join("guests");
+        let executable = guest_executable_path(&guest, SGX_PARENT_DIR);
+        fs::create_dir_all(executable.parent().unwrap()).unwrap();
+        fs::write(&executable, b"").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&executable, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        guest
+    }
+
+    #[test]
+    fn missing_dirs_are_reported_one_by_one() {
+        let root = tempfile::tempdir().unwrap();
+        let guest = guest_dir(root.path());
+        let checks = path_checks(
+            root.path(),
+            &guest,
+            Some(&root.path().join("logs")),
+            Some(&root.path().join("proofs")),
+        );
+        let problems = check_paths(&checks);
+        let options: Vec<_> = problems.iter().map(|problem| problem.option).collect();
+        assert_eq!(options, ["log_path", "proof_cache_dir"]);
+        assert!(problems[0].to_string().contains("does not exist"));
+
+        let checks = path_checks(root.path(), &root.path().join("nowhere"), None, None);
+        assert_eq!(check_paths(&checks)[0].option, "guest");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn read_only_cache_is_reported() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = tempfile::tempdir().unwrap();
+        let guest = guest_dir(root.path());
+        let cache = root.path().join("cache");
+        fs::create_dir(&cache).unwrap();
+        assert!(check_paths(&path_checks(&cache, &guest, None, None)).is_empty());
+
+        fs::set_permissions(&cache, fs::Permissions::from_mode(0o555)).unwrap();
+        // privileged users write to read-only directories anyway
+        if tempfile::tempfile_in(&cache).is_ok() {
+            return;
+        }
+        let problems = check_paths(&path_checks(&cache, &guest, None, None));
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].option, "cache");
+        assert!(problems[0].problem.contains("is not writable"));
+    }
+}
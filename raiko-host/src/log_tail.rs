@@ -0,0 +1,166 @@
+//! The log lines fanned out to the clients of `GET /logs/tail` as they are written, next
+//! to the log files. The clients lagging behind lose lines rather than hold the logging
+//! back.
+use std::{io, sync::Arc};
+
+use hyper::body::Sender;
+use lazy_static::lazy_static;
+use tokio::sync::broadcast::{self, error::RecvError, Receiver};
+use tracing::{Level, Metadata};
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Lines a client may lag behind before it loses the oldest ones.
+pub const LOG_TAIL_CAPACITY: usize = 1024;
+
+lazy_static! {
+    /// The tail of the logs of the process, written by the global subscriber.
+    pub static ref LOG_TAIL: LogTail = LogTail::new(LOG_TAIL_CAPACITY);
+}
+
+/// A line of the logs, as formatted for the log files.
+#[derive(Debug, Clone)]
+pub struct LogLine {
+    pub level: Level,
+    pub line: Arc<str>,
+}
+
+/// A writer of the subscriber broadcasting the lines it is given to the clients
+/// subscribed at the time, dropping them without any.
+#[derive(Debug, Clone)]
+pub struct LogTail {
+    sender: broadcast::Sender<LogLine>,
+}
+
+impl LogTail {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        LogTail { sender }
+    }
+
+    /// The lines written from now on.
+    pub fn subscribe(&self) -> Receiver<LogLine> {
+        self.sender.subscribe()
+    }
+
+    fn writer(&self, level: Level) -> LogTailWriter {
+        LogTailWriter {
+            // the events are not even buffered without clients
+            sender: (self.sender.receiver_count() > 0).then(|| self.sender.clone()),
+            level,
+            buf: vec![],
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for LogTail {
+    type Writer = LogTailWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.writer(Level::INFO)
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        self.writer(*meta.level())
+    }
+}
+
+/// The writer of an event, broadcasting its lines once it is written.
+pub struct LogTailWriter {
+    sender: Option<broadcast::Sender<LogLine>>,
+    level: Level,
+    buf: Vec<u8>,
+}
+
+impl io::Write for LogTailWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.sender.is_some() {
+            self.buf.extend_from_slice(buf);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for LogTailWriter {
+    fn drop(&mut self) {
+        let Some(sender) = &self.sender else {
+            return;
+        };
+        for line in String::from_utf8_lossy(&self.buf).lines() {
+            // fails only once the clients are gone
+            let _ = sender.send(LogLine {
+                level: self.level,
+                line: line.into(),
+            });
+        }
+    }
+}
+
+/// Streams the lines of `receiver` as server-sent events until the client disconnects,
+/// only the ones of `level` or more severe with a level. The lines lost for lagging
+/// behind are reported in a comment.
+pub async fn stream_log_lines(
+    mut receiver: Receiver<LogLine>,
+    level: Option<Level>,
+    mut body: Sender,
+) {
+    loop {
+        let event = match receiver.recv().await {
+            Ok(line) if level.map_or(true, |level| line.level <= level) => {
+                format!("data: {}\n\n", line.line)
+            }
+            Ok(_) => continue,
+            Err(RecvError::Lagged(lost)) => format!(": dropped {} lines\n\n", lost),
+            Err(RecvError::Closed) => return,
+        };
+        if body.send_data(event.into()).await.is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{body::HttpBody, Body};
+    use tracing::{info, warn};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn lines_emitted_after_connecting_are_streamed() {
+        let tail = LogTail::new(16);
+        let subscriber = tracing_subscriber::fmt()
+            .json()
+            .with_writer(tail.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+        warn!("before connecting");
+
+        let (sender, mut body) = Body::channel();
+        tokio::spawn(stream_log_lines(
+            tail.subscribe(),
+            Some(Level::WARN),
+            sender,
+        ));
+        info!("filtered out");
+        warn!("after connecting");
+
+        let event = body.data().await.unwrap().unwrap();
+        let event = std::str::from_utf8(&event).unwrap();
+        assert!(event.starts_with("data: {"), "{}", event);
+        assert!(event.ends_with("}\n\n"), "{}", event);
+        let record: serde_json::Value =
+            serde_json::from_str(event["data: ".len()..].trim_end()).unwrap();
+        assert_eq!(record["level"], "WARN");
+        assert_eq!(record["fields"]["message"], "after connecting");
+
+        // the stream ends with the clients
+        drop(body);
+        warn!("after disconnecting");
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert_eq!(tail.sender.receiver_count(), 0);
+    }
+}
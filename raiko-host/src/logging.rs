@@ -0,0 +1,90 @@
+//! Format of the logs, on stdout and in the log files alike.
+use std::{fmt, io::IsTerminal, str::FromStr};
+
+use serde_with::{DeserializeFromStr, SerializeDisplay};
+use tracing_subscriber::{
+    fmt::{
+        format::{DefaultFields, Format},
+        MakeWriter, SubscriberBuilder,
+    },
+    EnvFilter,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, DeserializeFromStr, SerializeDisplay)]
+pub enum LogFormat {
+    Json,
+    Pretty,
+    Compact,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(LogFormat::Json),
+            "pretty" => Ok(LogFormat::Pretty),
+            "compact" => Ok(LogFormat::Compact),
+            _ => Err(format!(
+                "invalid log format {:?}, expected json, pretty or compact",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogFormat::Json => write!(f, "json"),
+            LogFormat::Pretty => write!(f, "pretty"),
+            LogFormat::Compact => write!(f, "compact"),
+        }
+    }
+}
+
+impl LogFormat {
+    /// The format when none is given: pretty for a terminal, json for the log shippers
+    /// reading the files or a redirected stdout.
+    pub fn default_for(to_file: bool) -> Self {
+        if !to_file && std::io::stdout().is_terminal() {
+            LogFormat::Pretty
+        } else {
+            LogFormat::Json
+        }
+    }
+
+    /// Installs the global subscriber of `builder`, writing in this format.
+    pub fn init<W>(self, builder: SubscriberBuilder<DefaultFields, Format, EnvFilter, W>)
+    where
+        W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+    {
+        match self {
+            LogFormat::Json => tracing::subscriber::set_global_default(builder.json().finish()),
+            LogFormat::Pretty => tracing::subscriber::set_global_default(builder.pretty().finish()),
+            LogFormat::Compact => {
+                tracing::subscriber::set_global_default(builder.compact().finish())
+            }
+        }
+        .unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_log_format() {
+        for format in [LogFormat::Json, LogFormat::Pretty, LogFormat::Compact] {
+            assert_eq!(format.to_string().parse::<LogFormat>(), Ok(format));
+        }
+        assert!("JSON".parse::<LogFormat>().is_err());
+        assert!("".parse::<LogFormat>().is_err());
+    }
+
+    #[test]
+    fn log_files_default_to_json() {
+        assert_eq!(LogFormat::default_for(true), LogFormat::Json);
+    }
+}
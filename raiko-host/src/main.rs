@@ -14,20 +14,50 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+mod config;
+mod fs_check;
+mod log_tail;
+mod logging;
 mod metrics;
 mod prover;
 #[allow(dead_code)]
 mod rolling;
+mod statsd;
 use std::{fmt::Debug, path::PathBuf};
 
-use anyhow::{Context, Result};
-use prover::server::serve;
-use serde::Deserialize;
+use anyhow::{anyhow, bail, Context, Result};
+use log_tail::LOG_TAIL;
+use logging::LogFormat;
+use prover::{
+    allowed_range::AllowedRange,
+    cpu_affinity::CpuSet,
+    empty_block::EmptyBlockPolicy,
+    estimate::{EnclaveMemoryModel, EstimateModel},
+    guest_image::{startup_check, OnInvalidGuest, GUEST_RECHECK_INTERVAL},
+    launcher::GuestLauncher,
+    record::{replay_file, ReplayRpcs},
+    server::serve,
+    sink::{OutputSharding, SinkConfig},
+    utils::{chain_cache_dir, Secret},
+    verify_cache::verify_cache_dir,
+    warm::WarmOrder,
+};
+use serde::{Deserialize, Serialize};
 use structopt::StructOpt;
 use structopt_toml::StructOptToml;
-use tracing::info;
+use tracing::{info, warn};
+use tracing_subscriber::fmt::{writer::MakeWriterExt, TestWriter};
+use zeth_lib::{
+    consts::{check_chain_id_override, get_taiko_chain_spec, ChainSpecMismatchPolicy},
+    host::provider::{
+        data_source::DataSourceKind, dns::ResolveOverride, retry_policy::MethodRetryPolicy,
+        rpc_consistency::RpcInconsistencyPolicy, rpc_header::RpcHeader,
+    },
+    taiko::host::read_system_overrides,
+};
+use zeth_primitives::{Address, B256};
 
-#[derive(StructOpt, StructOptToml, Deserialize, Debug)]
+#[derive(StructOpt, StructOptToml, Deserialize, Serialize, Debug, Clone)]
 #[serde(default)]
 struct Opt {
     #[structopt(long, require_equals = true, default_value = "0.0.0.0:8080")]
@@ -35,6 +65,11 @@ struct Opt {
     /// [default: 0.0.0.0:8080]
     bind: String,
 
+    #[structopt(long, require_equals = true)]
+    /// Serve all the routes under this path, e.g. `/raiko`, for reverse proxies mounting
+    /// the server on a path of a shared domain
+    base_path: Option<String>,
+
     #[structopt(long, require_equals = true, default_value = "/tmp")]
     /// Use a local directory as a cache for RPC calls. Accepts a custom directory.
     cache: PathBuf,
@@ -46,15 +81,92 @@ struct Opt {
     #[structopt(long, require_equals = true, default_value = "0")]
     sgx_instance_id: u32,
 
+    #[structopt(long, require_equals = true)]
+    /// Pin the guest process to these CPU cores, e.g. `2,3`. Linux only
+    guest_cpu_affinity: Option<CpuSet>,
+
+    #[structopt(long, require_equals = true, default_value = "sudo gramine-sgx")]
+    /// Command the guest executable is launched through, with its arguments separated
+    /// by whitespace, e.g. `ego run`
+    guest_launcher: GuestLauncher,
+
     #[structopt(long, require_equals = true)]
     log_path: Option<PathBuf>,
 
+    #[structopt(long, require_equals = true)]
+    /// Format of the logs on stdout or in the `log_path` files: `json`, `pretty` or
+    /// `compact` [default: pretty on a terminal, json otherwise]
+    log_format: Option<LogFormat>,
+
     #[structopt(long, require_equals = true, default_value = "1000")]
+    /// Number of proofs cached, in memory and in `proof_cache_dir`
     proof_cache: usize,
 
+    #[structopt(long, require_equals = true, default_value = "3600")]
+    /// How long a proof request's `Idempotency-Key` dedupes its resubmissions, in seconds
+    idempotency_ttl_secs: u64,
+
+    #[structopt(long, require_equals = true)]
+    /// How long the result of a successful proof request is kept for the resubmissions of
+    /// its `Idempotency-Key`, in seconds [default: idempotency_ttl_secs]
+    retain_success_secs: Option<u64>,
+
+    #[structopt(long, require_equals = true, default_value = "0")]
+    /// How long the error of a failed proof request is kept for the resubmissions of its
+    /// `Idempotency-Key`, in seconds. The failures are not kept by default, their
+    /// resubmissions are proven again
+    retain_error_secs: u64,
+
+    #[structopt(long, require_equals = true, default_value = "10000")]
+    /// Number of `Idempotency-Key`s kept, the oldest results being dropped beyond it
+    idempotency_max_keys: usize,
+
+    #[structopt(long)]
+    /// Prove every request, ignoring the `Idempotency-Key` of resubmissions. For
+    /// debugging only, e.g. ruling out coalescing when the proofs are not deterministic
+    disable_coalescing: bool,
+
+    #[structopt(long, require_equals = true)]
+    /// Persist the cached proofs in this directory, to keep them across restarts
+    proof_cache_dir: Option<PathBuf>,
+
     #[structopt(long, require_equals = true, default_value = "10")]
     concurrency_limit: usize,
 
+    #[structopt(long, require_equals = true, default_value = "4")]
+    /// How many blocks of a `POST /prove/feasibility/batch` are checked at once
+    feasibility_batch_concurrency: usize,
+
+    #[structopt(long, require_equals = true)]
+    /// Reject the proof requests with 503 when they are projected to take longer than
+    /// this many milliseconds, waiting for the `concurrency_limit` included
+    target_proof_ms: Option<u64>,
+
+    #[structopt(long, require_equals = true)]
+    /// Fail the proof requests with `JobExpired` once they ran for this many seconds
+    /// since their submission, the wait for the `concurrency_limit` included
+    max_job_lifetime_secs: Option<u64>,
+
+    #[structopt(long, require_equals = true, default_value = "raiko_")]
+    /// Prefix of the names of the metrics, on `/metrics` and pushed to the
+    /// `statsd_endpoint`, to namespace them in a shared Prometheus. Empty keeps the names
+    /// unprefixed
+    metrics_prefix: String,
+
+    #[structopt(long, require_equals = true)]
+    /// Also push the metrics of `/metrics` to this StatsD server, given as `host:port`,
+    /// in the DogStatsD format
+    statsd_endpoint: Option<String>,
+
+    #[structopt(long, require_equals = true)]
+    /// Tag the pushed metrics with this `key:value`, on top of `sgx_instance_id` and
+    /// `l2_chain`. Can be repeated.
+    statsd_tag: Vec<String>,
+
+    #[structopt(long, require_equals = true, default_value = "10")]
+    /// How often the metrics are pushed to the `statsd_endpoint`
+    statsd_interval_secs: u64,
+
     #[structopt(long, require_equals = true, default_value = "7")]
     max_log_days: usize,
 
@@ -68,25 +180,696 @@ struct Opt {
     #[structopt(long, require_equals = true)]
     config_path: Option<PathBuf>,
 
+    #[structopt(long)]
+    /// Check that the configured paths exist with the permissions the server needs, then
+    /// exit. They are also checked before every start
+    check_fs: bool,
+
     #[structopt(long, require_equals = true, env = "RUST_LOG", default_value = "info")]
     log_level: String,
+
+    #[structopt(long, require_equals = true)]
+    /// TCP keep-alive interval of accepted connections, in seconds
+    http_keepalive_secs: Option<u64>,
+
+    #[structopt(long, require_equals = true)]
+    /// Close the connections with no request in flight which neither read nor wrote for
+    /// this many seconds
+    /// WARNING: must be less than http_keepalive_secs
+    http_idle_timeout_secs: Option<u64>,
+
+    #[structopt(long)]
+    /// Serve HTTP/2 besides HTTP/1.1, to the clients opening their connections with the
+    /// HTTP/2 preface (h2c with prior knowledge). The server has no TLS, so no ALPN
+    http2: bool,
+
+    #[structopt(long, require_equals = true)]
+    /// Refuse the new connections of a client, told by its IP address, while it has this
+    /// many open
+    max_connections_per_client: Option<usize>,
+
+    #[structopt(long, require_equals = true)]
+    /// Idle timeout of the pooled connections to the RPC nodes, in seconds
+    rpc_pool_idle_timeout: Option<u64>,
+
+    #[structopt(long, require_equals = true, default_value = "4")]
+    /// Most cache files written at once, over all the proofs and the warm. Writes beyond
+    /// it wait for a slot, the reads are not limited
+    cache_write_concurrency: usize,
+
+    #[structopt(long, require_equals = true, default_value = "8")]
+    /// Most connections open at once to each RPC host, over all the proofs. Calls beyond
+    /// it wait for a connection, however many requests are in flight
+    rpc_max_connections_per_host: usize,
+
+    #[structopt(long, require_equals = true)]
+    /// Fail the calls to an RPC host fast after this many consecutive failures, for
+    /// rpc_breaker_cooldown_secs, then probe it with a single call [default: never]
+    rpc_breaker_threshold: Option<u32>,
+
+    #[structopt(long, require_equals = true, default_value = "30")]
+    /// Seconds the calls to an RPC host fail fast once its circuit breaker opens
+    rpc_breaker_cooldown_secs: u64,
+
+    #[structopt(long, require_equals = true, default_value = "0")]
+    /// Retry the RPC calls failing on a fault of the node this many times, unless
+    /// rpc_retry says otherwise for their method
+    rpc_max_retries: u32,
+
+    #[structopt(long, require_equals = true, default_value = "100")]
+    /// Milliseconds before the first retry of an RPC call, doubled before each next one
+    rpc_retry_base_delay_ms: u64,
+
+    #[structopt(long, require_equals = true)]
+    /// Retry the calls of a JSON-RPC method as given by
+    /// `method=max_retries:base_delay_ms`, e.g. `eth_getProof=5:200`. Can be repeated
+    rpc_retry: Vec<MethodRetryPolicy>,
+
+    #[structopt(long, require_equals = true, default_value = "retry")]
+    /// What to do when the RPC nodes serve the data of a block from different chains, as
+    /// the nodes of a load balancer at different heights do: fetch the whole block again
+    /// once, skipping the cache (`retry`), or fail with RpcInconsistent (`error`)
+    on_rpc_inconsistency: RpcInconsistencyPolicy,
+
+    #[structopt(long, require_equals = true)]
+    /// How long connecting to an RPC node may take, in seconds
+    rpc_connect_timeout_secs: Option<u64>,
+
+    #[structopt(long, require_equals = true)]
+    /// How long a single RPC call may take, in seconds
+    rpc_request_timeout_secs: Option<u64>,
+
+    #[structopt(long, require_equals = true)]
+    /// How long a single RPC call fetching L1 data may take, in seconds
+    /// [default: rpc_request_timeout_secs]
+    l1_rpc_request_timeout_secs: Option<u64>,
+
+    #[structopt(long, require_equals = true)]
+    /// How long a single RPC call fetching L2 data may take, in seconds
+    /// [default: rpc_request_timeout_secs]
+    l2_rpc_request_timeout_secs: Option<u64>,
+
+    #[structopt(long, require_equals = true)]
+    /// Most RPC calls fetching L1 data in flight at once, over all the proofs
+    /// [default: unlimited]
+    l1_fetch_concurrency: Option<usize>,
+
+    #[structopt(long, require_equals = true)]
+    /// Most RPC calls fetching L2 data in flight at once, over all the proofs
+    /// [default: unlimited]
+    l2_fetch_concurrency: Option<usize>,
+
+    #[structopt(long, require_equals = true)]
+    /// How long fetching the L1 and L2 data of a block may take over all its RPC calls,
+    /// in seconds
+    rpc_block_fetch_budget_secs: Option<u64>,
+
+    #[structopt(long, require_equals = true)]
+    /// HTTP cache shared with the other provers, looked up on a miss of the local cache
+    /// before the RPC node, and filled with what is fetched from it
+    shared_cache_url: Option<String>,
+
+    #[structopt(long, require_equals = true)]
+    /// Reuse the resolved addresses of the RPC hosts for this many seconds
+    /// [default: the system resolver]
+    rpc_dns_ttl_secs: Option<u64>,
+
+    #[structopt(long)]
+    /// Resolve the RPC hosts again for every new connection
+    rpc_dns_resolve_per_connection: bool,
+
+    #[structopt(long, require_equals = true)]
+    /// Resolve an RPC host to this IP, given as `host:ip`. Can be repeated.
+    rpc_resolve: Vec<ResolveOverride>,
+
+    #[structopt(long, require_equals = true)]
+    /// Send this header, given as "Name: Value", with every call to the RPC nodes, like
+    /// the credentials of a provider authenticating by header. Can be repeated.
+    rpc_header: Vec<RpcHeader>,
+
+    #[structopt(long, require_equals = true, default_value = "rpc")]
+    /// What the `l1_rpc` and `l2_rpc` of the requests name: RPC nodes (`rpc`) or
+    /// directories of exported block data on this host (`dir`)
+    data_source: DataSourceKind,
+
+    #[structopt(long, require_equals = true)]
+    /// Data sources the requests may select with their `dataSource`, besides
+    /// `data_source`, which the requests without one use. Can be repeated
+    data_sources: Vec<DataSourceKind>,
+
+    #[structopt(long, require_equals = true)]
+    /// Beacon node serving the blob sidecars of blocks proposed with EIP-4844 blobs
+    beacon_rpc_url: Option<String>,
+
+    #[structopt(long, require_equals = true)]
+    /// KZG trusted setup file verifying the blobs, passed on to the guest
+    kzg_trusted_setup: Option<PathBuf>,
+
+    #[structopt(long, require_equals = true)]
+    /// Only prove blocks in these ranges, given as `[chain:]start-end`. Can be repeated.
+    /// Chains without any range are not restricted.
+    prove_allowed_range: Vec<AllowedRange>,
+
+    #[structopt(long, require_equals = true)]
+    /// Number of tokio worker threads [default: number of cores]
+    worker_threads: Option<usize>,
+
+    #[structopt(long, require_equals = true)]
+    /// Maximum number of tokio blocking threads, which build the witnesses and wait for
+    /// the guest [default: 512]
+    blocking_threads: Option<usize>,
+
+    #[structopt(long)]
+    /// Serve the /debug endpoints and /logs/tail, which expose full state data
+    enable_debug_endpoints: bool,
+
+    #[structopt(long, require_equals = true)]
+    /// Bearer token required by the /debug endpoints, /config and the block data uploads
+    debug_auth_token: Option<Secret>,
+
+    #[structopt(long, require_equals = true)]
+    /// Append every proof request served, with its result, to this file as JSON lines,
+    /// the credentials of the RPC URLs redacted. Recording is paused and resumed with
+    /// `POST /debug/record-requests`
+    record_requests: Option<PathBuf>,
+
+    #[structopt(long, require_equals = true)]
+    /// Write the inputs of every failed proof to a directory of its own under this one,
+    /// to reproduce the failure offline with `replay`: the request and the cached
+    /// block data, redacted of the credentials of the URLs, the chain spec and the
+    /// error
+    failure_dump_dir: Option<PathBuf>,
+
+    #[structopt(long, require_equals = true, default_value = "20")]
+    /// Most failures kept in `failure_dump_dir`, the oldest removed first
+    failure_dump_max_count: usize,
+
+    #[structopt(long, require_equals = true, default_value = "268435456")]
+    /// Most bytes of block data the inputs of a failure hold, the larger block data is
+    /// left out
+    failure_dump_max_bytes: u64,
+
+    #[structopt(long, require_equals = true)]
+    /// Replay the requests of a `record_requests` file against `replay_target`, comparing
+    /// the proofs with the recorded ones, then exit
+    replay: Option<PathBuf>,
+
+    #[structopt(long, require_equals = true)]
+    /// Server the `replay` requests are sent to, e.g. `http://localhost:8080`
+    replay_target: Option<String>,
+
+    #[structopt(long, require_equals = true)]
+    /// L1 node the replayed requests are sent with, in place of the recorded one
+    replay_l1_rpc: Option<String>,
+
+    #[structopt(long, require_equals = true)]
+    /// L2 node the replayed requests are sent with, in place of the recorded one
+    replay_l2_rpc: Option<String>,
+
+    #[structopt(long)]
+    /// Check every entry of the cache directory of the chain and of `proof_cache_dir`,
+    /// printing the corrupt and orphan ones, then exit
+    verify_cache: bool,
+
+    #[structopt(long)]
+    /// Remove the corrupt entries found by `verify_cache`
+    verify_cache_prune: bool,
+
+    #[structopt(long, require_equals = true)]
+    /// Simulate the call of this `--emit-calldata` file of the guest against the
+    /// `verifier_bytecode` on a local EVM, printing whether the verifier accepts the
+    /// proof, then exit. Requires the `verifier-simulate` feature
+    verifier_simulate: Option<PathBuf>,
+
+    #[structopt(long, require_equals = true)]
+    /// Runtime bytecode of the on-chain verifier for `verifier_simulate`, as hex or as a
+    /// JSON build artifact with a `deployedBytecode`
+    verifier_bytecode: Option<PathBuf>,
+
+    #[structopt(long, require_equals = true)]
+    /// Storage of the verifier for `verifier_simulate`, as a JSON object of the values by
+    /// slot, e.g. its registered instances. Empty by default
+    verifier_storage: Option<PathBuf>,
+
+    #[structopt(long, require_equals = true, default_value = "16")]
+    /// Number of witnesses retained for /debug/dump-witness
+    witness_retention_count: usize,
+
+    #[structopt(long, require_equals = true, default_value = "3600")]
+    /// Seconds a witness is retained for /debug/dump-witness
+    witness_retention_secs: u64,
+
+    #[structopt(long, require_equals = true, default_value = "1000")]
+    /// Number of completed proof requests retained for /proofs/recent
+    proof_history_capacity: usize,
+
+    #[structopt(long, require_equals = true, default_value = "100")]
+    /// Maximum number of proofs a page of /proofs/recent may list
+    max_proof_history_limit: usize,
+
+    #[structopt(long, require_equals = true, default_value = "20000,50,400,30")]
+    /// Milliseconds of the proofs estimated by /prove/estimate, as
+    /// base_ms,tx_ms,mgas_ms,account_ms: per block, per transaction, per million of gas
+    /// used and per account touched
+    estimate_model: EstimateModel,
+
+    #[structopt(long, require_equals = true, default_value = "100")]
+    /// Number of recent proofs whose actual times calibrate the estimates
+    estimate_calibration_samples: usize,
+
+    #[structopt(long, require_equals = true)]
+    /// Maximum estimated enclave memory of the proofs running at once, in MB. A proof
+    /// waits for the running ones to free enough of it, on top of concurrency_limit
+    max_total_enclave_mb: Option<u64>,
+
+    #[structopt(long, require_equals = true, default_value = "512,10")]
+    /// Enclave memory of a proof in MB, as base_mb,witness_factor: per proof, and per
+    /// MB of the compressed block data it loads
+    enclave_memory_model: EnclaveMemoryModel,
+
+    #[structopt(long, require_equals = true, default_value = "60")]
+    /// Seconds between two runs of /admin/rpc-benchmark, sparing the RPC node
+    rpc_benchmark_interval_secs: u64,
+
+    #[structopt(long, require_equals = true, default_value = "134217728")]
+    /// Maximum decompressed size of an uploaded block data file
+    max_block_size_bytes: u64,
+
+    #[structopt(long, require_equals = true)]
+    /// Maximum number of transactions of a proven block, unlimited by default. Larger
+    /// blocks are rejected once their data is fetched, before their witness is built
+    max_txs_per_block: Option<usize>,
+
+    #[structopt(long, require_equals = true)]
+    /// Maximum number of RPC calls building the witness of a block may issue, L1 and L2
+    /// together, unlimited by default. The entries served by the caches are not counted
+    max_rpc_calls_per_block: Option<u64>,
+
+    #[structopt(long, require_equals = true)]
+    /// JSON-RPC methods the RPC clients may call on top of the read-only ones they use,
+    /// e.g. `debug_traceBlockByNumber` for an experimental data source. Can be repeated
+    extra_rpc_methods: Vec<String>,
+
+    #[structopt(long, require_equals = true)]
+    /// Cap on the combined size of the logs, the cache and the persisted proofs. Evicts
+    /// the rotated logs, then the cache files of the blocks not being proven, then the
+    /// oldest proofs when approaching it
+    max_disk_bytes: Option<u64>,
+
+    #[structopt(long, require_equals = true)]
+    /// Compact the cache directory every this many seconds, removing the orphaned
+    /// temporary files of interrupted writes [default: never]
+    cache_compact_interval_secs: Option<u64>,
+
+    #[structopt(long, require_equals = true)]
+    /// Stop the guest after this many seconds, failing the proof [default: no timeout]
+    guest_timeout_secs: Option<u64>,
+
+    #[structopt(long, require_equals = true)]
+    /// Ceiling on the `timeoutSecs` of the proof requests, the longer ones are clamped
+    /// to it [default: guest_timeout_secs]
+    max_guest_timeout_secs: Option<u64>,
+
+    #[structopt(long)]
+    /// Verify every sgx proof before returning it, proving again the ones which do not
+    /// verify
+    verify_before_return: bool,
+
+    #[structopt(long, require_equals = true, default_value = "2")]
+    /// Number of times a proof failing verify_before_return is proven again
+    self_verify_retries: u32,
+
+    #[structopt(long, require_equals = true, default_value = "0")]
+    /// Fraction of the sgx proofs verified again in the background once served, raising
+    /// proof_reverify_failures_counter when one fails, from 0 (none) to 1 (all)
+    reverify_sample_rate: f64,
+
+    #[structopt(long)]
+    /// Serve the proof requests from the block data already in the cache, without RPC
+    /// nor proof cache nor sinks, marking the responses as benchmark results.
+    /// WARNING: load testing only
+    benchmark_mode: bool,
+
+    #[structopt(long)]
+    /// In benchmark_mode, answer with a placeholder proof instead of running the guest
+    benchmark_mock_guest: bool,
+
+    #[structopt(long, require_equals = true)]
+    /// Prover address of the proof requests omitting theirs
+    default_prover: Option<Address>,
+
+    #[structopt(long, require_equals = true)]
+    /// Graffiti of the proof requests omitting theirs
+    default_graffiti: Option<B256>,
+
+    #[structopt(long, require_equals = true)]
+    /// Build the witnesses with this chain id instead of the one of `l2_chain`.
+    /// WARNING: testing only, for forks reusing recorded block data
+    chain_id_override: Option<u64>,
+
+    #[structopt(long, require_equals = true, default_value = "error")]
+    /// What to do when the chain id or the fork of the block data disagrees with
+    /// `l2_chain`: reject the block (`error`), build it with the values of the data
+    /// (`warn`) or with the ones of `l2_chain` (`override`)
+    on_chainspec_mismatch: ChainSpecMismatchPolicy,
+
+    #[structopt(long, require_equals = true, default_value = "prove")]
+    /// What to do with the blocks of no transaction but their anchor, once their data is
+    /// fetched: prove them (`prove`), answer a skipped result (`skip`) or reject them
+    /// (`error`)
+    on_empty_block: EmptyBlockPolicy,
+
+    #[structopt(long, require_equals = true)]
+    /// Build the blocks with this base fee per gas, in wei, instead of the one of their
+    /// data. Requires allow_consensus_overrides
+    base_fee_override: Option<u64>,
+
+    #[structopt(long, require_equals = true)]
+    /// Build the blocks with this gas limit instead of the one of their data. Requires
+    /// allow_consensus_overrides
+    gas_limit_override: Option<u64>,
+
+    #[structopt(long, require_equals = true)]
+    /// JSON file of the code replacing the one of system contracts in the pre-state of
+    /// the blocks, as `{"code": {"<address>": "<bytecode>"}}`. Requires
+    /// allow_consensus_overrides
+    system_overrides: Option<PathBuf>,
+
+    #[structopt(long, require_equals = true)]
+    /// Sign the proofs at this unix timestamp instead of the time of the guest runs, for
+    /// the runs over the same block data to produce the same proofs.
+    /// WARNING: testing and reproduction only, the block timestamps are left untouched
+    fixed_now: Option<u64>,
+
+    #[structopt(long)]
+    /// Allow the overrides of the consensus parameters.
+    /// WARNING: testing only, the proofs will not verify on the chain
+    allow_consensus_overrides: bool,
+
+    #[structopt(long, require_equals = true)]
+    /// Fetch the block data of these blocks into the cache at startup, given as
+    /// `start-end`
+    warm_blocks: Option<AllowedRange>,
+
+    #[structopt(long, require_equals = true, default_value = "ascending")]
+    /// Order of warming the blocks: `ascending`, `descending`, or `file:<path>` listing
+    /// one block number per line
+    warm_order: WarmOrder,
+
+    #[structopt(long, require_equals = true)]
+    /// L1 node to warm the cache from
+    warm_l1_rpc: Option<String>,
+
+    #[structopt(long, require_equals = true)]
+    /// L2 node to warm the cache from
+    warm_l2_rpc: Option<String>,
+
+    #[structopt(long)]
+    /// Start the enclave once at startup, reporting the server ready on /health only
+    /// afterwards, for the first proof not to pay for its initialization
+    warmup_on_start: bool,
+
+    #[structopt(long, require_equals = true, default_value = "error")]
+    /// What to do when the guest under `guest` is missing or invalid at startup: exit
+    /// (`error`), or report the server unready on /health and check the guest again
+    /// until it is valid (`wait`), e.g. while an init container fills the guest volume
+    on_invalid_guest: OnInvalidGuest,
+
+    #[structopt(long, require_equals = true)]
+    /// Report the server unready on /health and /health/ready until this RPC node
+    /// answers, probing it again with a backoff
+    startup_probe_rpc: Option<String>,
+
+    #[structopt(long, require_equals = true)]
+    /// Node the head of `startup_probe_rpc` is compared to, with `max_head_lag_blocks`
+    startup_probe_reference_rpc: Option<String>,
+
+    #[structopt(long, require_equals = true)]
+    /// Also wait until the head of `startup_probe_rpc` lags at most this many blocks
+    /// behind the one of `startup_probe_reference_rpc`
+    max_head_lag_blocks: Option<u64>,
+
+    #[structopt(long, require_equals = true, default_value = "300")]
+    /// How long the node of `startup_probe_rpc` may take to become ready, after which the
+    /// server stays unready
+    startup_probe_timeout_secs: u64,
+
+    #[structopt(long, require_equals = true)]
+    /// Deliver every completed proof to these sinks, given as `stdout`, `file:<dir>`,
+    /// `webhook:<url>` or, built with the `s3` feature, `s3://<bucket>/<prefix>`, or as
+    /// `[[sink]]` tables in the config file. Can be repeated.
+    sink: Vec<SinkConfig>,
+
+    #[structopt(long, require_equals = true, default_value = "flat")]
+    /// How the `file` sinks spread the proofs over subdirectories by block number:
+    /// `flat`, `by-1000` or `by-10000`, e.g. `{dir}/{chain}/12000-12999/12345.json`
+    output_sharding: OutputSharding,
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let mut opt = Opt::from_args();
+impl Opt {
+    fn validate(&self) -> Result<()> {
+        for sink in &self.sink {
+            if let Err(err) = sink.validate() {
+                bail!(err);
+            }
+        }
+        if let Some(base_path) = &self.base_path {
+            if !base_path.starts_with('/') || base_path.contains(['?', '#']) {
+                bail!("base_path must be an absolute path, got {}", base_path);
+            }
+        }
+        if let (Some(idle), Some(keepalive)) =
+            (self.http_idle_timeout_secs, self.http_keepalive_secs)
+        {
+            if idle >= keepalive {
+                bail!(
+                    "http_idle_timeout_secs ({}) must be less than http_keepalive_secs ({})",
+                    idle,
+                    keepalive
+                );
+            }
+        }
+        if self.enable_debug_endpoints && self.debug_auth_token.is_none() {
+            bail!("enable_debug_endpoints requires debug_auth_token");
+        }
+        if self.rpc_dns_resolve_per_connection && self.rpc_dns_ttl_secs.unwrap_or(0) > 0 {
+            bail!("rpc_dns_resolve_per_connection conflicts with rpc_dns_ttl_secs");
+        }
+        if self.cache_write_concurrency == 0 {
+            bail!("cache_write_concurrency must be greater than 0");
+        }
+        if self.rpc_max_connections_per_host == 0 {
+            bail!("rpc_max_connections_per_host must be greater than 0");
+        }
+        if self.l1_fetch_concurrency == Some(0) {
+            bail!("l1_fetch_concurrency must be greater than 0");
+        }
+        if self.l2_fetch_concurrency == Some(0) {
+            bail!("l2_fetch_concurrency must be greater than 0");
+        }
+        if self.rpc_breaker_threshold == Some(0) {
+            bail!("rpc_breaker_threshold must be greater than 0");
+        }
+        if self.worker_threads == Some(0) || self.blocking_threads == Some(0) {
+            bail!("worker_threads and blocking_threads must be greater than 0");
+        }
+        if self.replay.is_some() && self.replay_target.is_none() {
+            bail!("replay requires replay_target");
+        }
+        if self.max_job_lifetime_secs == Some(0) {
+            bail!("max_job_lifetime_secs must be greater than 0");
+        }
+        if self.target_proof_ms == Some(0) {
+            bail!("target_proof_ms must be greater than 0");
+        }
+        if self.failure_dump_dir.is_some() && self.failure_dump_max_count == 0 {
+            bail!("failure_dump_max_count must be greater than 0");
+        }
+        if !(0.0..=1.0).contains(&self.reverify_sample_rate) {
+            bail!("reverify_sample_rate must be between 0 and 1");
+        }
+        if self.max_total_enclave_mb == Some(0) {
+            bail!("max_total_enclave_mb must be greater than 0");
+        }
+        if self.max_connections_per_client == Some(0) {
+            bail!("max_connections_per_client must be greater than 0");
+        }
+        if self.feasibility_batch_concurrency == 0 {
+            bail!("feasibility_batch_concurrency must be greater than 0");
+        }
+        if !metrics::is_valid_prefix(&self.metrics_prefix) {
+            bail!(
+                "invalid metrics_prefix {:?}, expected [a-zA-Z_:][a-zA-Z0-9_:]*",
+                self.metrics_prefix
+            );
+        }
+        if self.statsd_interval_secs == 0 {
+            bail!("statsd_interval_secs must be greater than 0");
+        }
+        for tag in &self.statsd_tag {
+            if !tag.contains(':') || tag.contains([',', '|', '#']) {
+                bail!("invalid statsd_tag {:?}, expected key:value", tag);
+            }
+        }
+        if self.verifier_simulate.is_some() {
+            if !cfg!(feature = "verifier-simulate") {
+                bail!("verifier_simulate requires the verifier-simulate feature");
+            }
+            if self.verifier_bytecode.is_none() {
+                bail!("verifier_simulate requires verifier_bytecode");
+            }
+        }
+        if self.verifier_storage.is_some() && self.verifier_simulate.is_none() {
+            bail!("verifier_storage requires verifier_simulate");
+        }
+        if self.verify_cache_prune && !self.verify_cache {
+            bail!("verify_cache_prune requires verify_cache");
+        }
+        if self.benchmark_mock_guest && !self.benchmark_mode {
+            bail!("benchmark_mock_guest requires benchmark_mode");
+        }
+        if self.benchmark_mock_guest && self.verify_before_return {
+            bail!("benchmark_mock_guest conflicts with verify_before_return");
+        }
+        if self.max_txs_per_block == Some(0) {
+            bail!("max_txs_per_block must be greater than 0");
+        }
+        if self.max_rpc_calls_per_block == Some(0) {
+            bail!("max_rpc_calls_per_block must be greater than 0");
+        }
+        if self.cache_compact_interval_secs == Some(0) {
+            bail!("cache_compact_interval_secs must be greater than 0");
+        }
+        if self.guest_timeout_secs == Some(0) || self.max_guest_timeout_secs == Some(0) {
+            bail!("guest_timeout_secs and max_guest_timeout_secs must be greater than 0");
+        }
+        if let (Some(timeout), Some(max_timeout)) =
+            (self.guest_timeout_secs, self.max_guest_timeout_secs)
+        {
+            if timeout > max_timeout {
+                bail!(
+                    "guest_timeout_secs ({}) must not exceed max_guest_timeout_secs ({})",
+                    timeout,
+                    max_timeout
+                );
+            }
+        }
+        if let Some(cpus) = &self.guest_cpu_affinity {
+            cpus.validate()
+                .map_err(|e| anyhow!("invalid guest_cpu_affinity: {}", e))?;
+        }
+        if let Some(chain_id) = self.chain_id_override {
+            check_chain_id_override(&self.l2_chain, chain_id)?;
+        }
+        if (self.base_fee_override.is_some() || self.gas_limit_override.is_some())
+            && !self.allow_consensus_overrides
+        {
+            bail!("base_fee_override and gas_limit_override require allow_consensus_overrides");
+        }
+        if let Some(path) = &self.system_overrides {
+            if !self.allow_consensus_overrides {
+                bail!("system_overrides requires allow_consensus_overrides");
+            }
+            read_system_overrides(path)
+                .with_context(|| format!("invalid system_overrides {}", path.display()))?;
+        }
+        if let Some(warm_blocks) = &self.warm_blocks {
+            if warm_blocks.chain.is_some() {
+                bail!("warm_blocks applies to l2_chain, it takes no chain prefix");
+            }
+            if self.warm_l1_rpc.is_none() || self.warm_l2_rpc.is_none() {
+                bail!("warm_blocks requires warm_l1_rpc and warm_l2_rpc");
+            }
+        }
+        if self.max_head_lag_blocks.is_some() != self.startup_probe_reference_rpc.is_some() {
+            bail!("max_head_lag_blocks and startup_probe_reference_rpc go together");
+        }
+        if self.startup_probe_reference_rpc.is_some() && self.startup_probe_rpc.is_none() {
+            bail!("startup_probe_reference_rpc requires startup_probe_rpc");
+        }
+        if self.startup_probe_timeout_secs == 0 {
+            bail!("startup_probe_timeout_secs must be greater than 0");
+        }
+        Ok(())
+    }
+}
+
+/// Same as the defaults of `#[tokio::main]`
+const DEFAULT_BLOCKING_THREADS: usize = 512;
 
+fn main() -> Result<()> {
+    let args = Opt::clap().get_matches();
+    let mut opt = Opt::from_clap(&args);
+
+    let mut substituted_vars = vec![];
+    let mut toml_config = None;
     if let Some(config_path) = opt.config_path {
         let config_raw = std::fs::read(&config_path)
             .context(format!("read config file {:?} failed", config_path))?;
-        opt =
-            Opt::from_args_with_toml(std::str::from_utf8(&config_raw).context("str parse failed")?)
-                .context("toml parse failed")?;
+        let (config, vars) =
+            config::interpolate_env(std::str::from_utf8(&config_raw).context("str parse failed")?)
+                .context("config interpolation failed")?;
+        substituted_vars = vars;
+        opt = Opt::from_clap_with_toml(&config, &args).context("toml parse failed")?;
+        toml_config = Some(toml::from_str(&config).context("toml parse failed")?);
+    };
+    opt.validate()?;
+    // before binding, and before the logs are set up in `log_path`
+    let fs_problems = fs_check::check_paths(&fs_check::path_checks(
+        &opt.cache,
+        &opt.guest,
+        opt.log_path.as_deref(),
+        opt.proof_cache_dir.as_deref(),
+    ));
+    for problem in &fs_problems {
+        eprintln!("error: {}", problem);
+    }
+    if !fs_problems.is_empty() {
+        bail!(
+            "{} of the configured paths are not usable",
+            fs_problems.len()
+        );
+    }
+    if opt.check_fs {
+        println!("All the configured paths are usable");
+        return Ok(());
+    }
+    let effective_config = config::effective_config(&opt, |key| {
+        config::config_source(key, &args, toml_config.as_ref())
+    })?;
+
+    let worker_threads = match opt.worker_threads {
+        Some(worker_threads) => worker_threads,
+        None => std::thread::available_parallelism()?.get(),
     };
+    let blocking_threads = opt.blocking_threads.unwrap_or(DEFAULT_BLOCKING_THREADS);
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .worker_threads(worker_threads)
+        .max_blocking_threads(blocking_threads)
+        .build()
+        .context("build tokio runtime failed")?
+        .block_on(run(
+            opt,
+            effective_config,
+            worker_threads,
+            blocking_threads,
+            substituted_vars,
+        ))
+}
 
-    let subscriber_builder = tracing_subscriber::FmtSubscriber::builder()
-        .with_env_filter(&opt.log_level)
-        .with_test_writer();
+async fn run(
+    opt: Opt,
+    effective_config: serde_json::Value,
+    worker_threads: usize,
+    blocking_threads: usize,
+    substituted_vars: Vec<String>,
+) -> Result<()> {
+    let subscriber_builder =
+        tracing_subscriber::FmtSubscriber::builder().with_env_filter(&opt.log_level);
+    let log_format = opt
+        .log_format
+        .unwrap_or_else(|| LogFormat::default_for(opt.log_path.is_some()));
     let _guard = match opt.log_path {
         Some(ref log_path) => {
             let file_appender = tracing_appender::rolling::Builder::new()
@@ -96,27 +879,255 @@ async fn main() -> Result<()> {
                 .build(log_path)
                 .expect("initializing rolling file appender failed");
             let (non_blocking, _guard) = tracing_appender::non_blocking(file_appender);
-            let subscriber = subscriber_builder.json().with_writer(non_blocking).finish();
-            tracing::subscriber::set_global_default(subscriber).unwrap();
+            log_format.init(subscriber_builder.with_writer(non_blocking.and(LOG_TAIL.clone())));
             Some(_guard)
         }
         None => {
-            let subscriber = subscriber_builder.finish();
-            tracing::subscriber::set_global_default(subscriber).unwrap();
+            log_format
+                .init(subscriber_builder.with_writer(TestWriter::new().and(LOG_TAIL.clone())));
             None
         }
     };
-    info!("Start args: {:?}", opt);
-    serve(
-        &opt.bind,
-        &opt.guest,
-        &opt.cache,
-        &opt.l2_chain,
-        opt.sgx_instance_id,
-        opt.proof_cache,
-        opt.concurrency_limit,
-        opt.max_caches,
-    )
-    .await?;
+    if !substituted_vars.is_empty() {
+        info!(
+            "Substituted environment variables in the config: {}",
+            substituted_vars.join(", ")
+        );
+    }
+    info!("{}", config::start_args(&effective_config));
+    info!(
+        "Tokio worker threads: {}, blocking threads: {}",
+        worker_threads, blocking_threads
+    );
+    info!(
+        "Http keep-alive: {:?}s, http idle timeout: {:?}s",
+        opt.http_keepalive_secs, opt.http_idle_timeout_secs
+    );
+    info!(
+        "Rpc pool: {} connections per host at most, as many kept idle for {:?}s",
+        opt.rpc_max_connections_per_host, opt.rpc_pool_idle_timeout
+    );
+    info!(
+        "Cache writes: {} at once at most",
+        opt.cache_write_concurrency
+    );
+    if let Some(max) = opt.max_rpc_calls_per_block {
+        info!("RPC calls: {} per block at most", max);
+    }
+    if let Some(threshold) = opt.rpc_breaker_threshold {
+        info!(
+            "Rpc circuit breaker: opens after {} consecutive failures, for {}s",
+            threshold, opt.rpc_breaker_cooldown_secs
+        );
+    }
+    if opt.rpc_max_retries > 0 || !opt.rpc_retry.is_empty() {
+        info!(
+            "RPC retries: {} after {}ms, {:?}",
+            opt.rpc_max_retries, opt.rpc_retry_base_delay_ms, opt.rpc_retry
+        );
+    }
+    if opt.on_rpc_inconsistency != RpcInconsistencyPolicy::Retry {
+        info!("On the RPC inconsistencies: {}", opt.on_rpc_inconsistency);
+    }
+    info!(
+        "Rpc connect timeout: {:?}s, rpc request timeout: {:?}s, rpc block fetch budget: {:?}s",
+        opt.rpc_connect_timeout_secs, opt.rpc_request_timeout_secs, opt.rpc_block_fetch_budget_secs
+    );
+    info!(
+        "L1 fetch: {:?} calls at most, request timeout {:?}s, l2 fetch: {:?} calls at most, request timeout {:?}s",
+        opt.l1_fetch_concurrency,
+        opt.l1_rpc_request_timeout_secs,
+        opt.l2_fetch_concurrency,
+        opt.l2_rpc_request_timeout_secs
+    );
+    info!(
+        "Rpc dns ttl: {:?}s, resolve per connection: {}, resolve overrides: {:?}",
+        opt.rpc_dns_ttl_secs, opt.rpc_dns_resolve_per_connection, opt.rpc_resolve
+    );
+    if !opt.rpc_header.is_empty() {
+        // the values are redacted
+        info!("Rpc headers: {:?}", opt.rpc_header);
+    }
+    info!(
+        "Data source: {}, selectable: {:?}",
+        opt.data_source, opt.data_sources
+    );
+    if !opt.extra_rpc_methods.is_empty() {
+        warn!(
+            "Allowing the RPC methods {:?} on top of the read-only ones",
+            opt.extra_rpc_methods
+        );
+    }
+    if opt.shared_cache_url.is_some() {
+        info!("Sharing the fetched block data through the shared cache");
+    }
+    if opt.disable_coalescing {
+        warn!("Coalescing disabled, resubmitted proof requests are proven again");
+    } else {
+        info!(
+            "Keeping the results of up to {} proof requests {}s, their errors {}s",
+            opt.idempotency_max_keys,
+            opt.retain_success_secs.unwrap_or(opt.idempotency_ttl_secs),
+            opt.retain_error_secs
+        );
+    }
+    if let Some(cpus) = &opt.guest_cpu_affinity {
+        if cfg!(target_os = "linux") {
+            info!("Pinning the guest to cores {}", cpus);
+        } else {
+            warn!(
+                "Ignoring guest_cpu_affinity {}, only supported on Linux",
+                cpus
+            );
+        }
+    }
+    if let Some(chain_id) = opt.chain_id_override {
+        warn!(
+            "TESTING ONLY: overriding the chain id of {} ({}) with {}, proofs will not verify on {}",
+            opt.l2_chain,
+            get_taiko_chain_spec(&opt.l2_chain).chain_id(),
+            chain_id,
+            opt.l2_chain
+        );
+    }
+    if opt.base_fee_override.is_some() || opt.gas_limit_override.is_some() {
+        warn!(
+            "TESTING ONLY: overriding the base fee ({:?}) and gas limit ({:?}) of the blocks, \
+             proofs will not verify on {}",
+            opt.base_fee_override, opt.gas_limit_override, opt.l2_chain
+        );
+    }
+    if let Some(path) = &opt.system_overrides {
+        warn!(
+            "TESTING ONLY: overriding the code of system contracts with {}, proofs will not \
+             verify on {}",
+            path.display(),
+            opt.l2_chain
+        );
+    }
+    if let Some(now) = opt.fixed_now {
+        warn!(
+            "TESTING ONLY: the guest reads the clock as the fixed timestamp {}",
+            now
+        );
+    }
+    #[cfg(feature = "verifier-simulate")]
+    if let (Some(calldata), Some(bytecode)) = (&opt.verifier_simulate, &opt.verifier_bytecode) {
+        return prover::verifier_simulate::simulate_verifier_file(
+            calldata,
+            bytecode,
+            opt.verifier_storage.as_deref(),
+        );
+    }
+    if opt.verify_cache {
+        let chain_dir = chain_cache_dir(&opt.cache, get_taiko_chain_spec(&opt.l2_chain).chain_id());
+        return verify_cache_dir(
+            &chain_dir,
+            opt.proof_cache_dir.as_deref(),
+            opt.verify_cache_prune,
+        );
+    }
+    if let (Some(path), Some(target)) = (&opt.replay, &opt.replay_target) {
+        let rpcs = ReplayRpcs {
+            l1_rpc: opt.replay_l1_rpc.clone(),
+            l2_rpc: opt.replay_l2_rpc.clone(),
+        };
+        return replay_file(path, target, &rpcs).await;
+    }
+    if let Some(path) = &opt.record_requests {
+        info!("Recording the proof requests to {}", path.display());
+    }
+    if let Some(dir) = &opt.failure_dump_dir {
+        info!(
+            "Writing the inputs of the last {} failed proofs to {}",
+            opt.failure_dump_max_count,
+            dir.display()
+        );
+    }
+    if let Some(target) = opt.target_proof_ms {
+        info!(
+            "Shedding the proof requests projected to take longer than {}ms",
+            target
+        );
+    }
+    if let Some(lifetime) = opt.max_job_lifetime_secs {
+        info!(
+            "Expiring the proof requests {}s after their submission",
+            lifetime
+        );
+    }
+    info!("Metrics prefix: {:?}", opt.metrics_prefix);
+    info!(
+        "Estimating the proofs with the model {}, calibrated by the last {} proofs",
+        opt.estimate_model, opt.estimate_calibration_samples
+    );
+    if opt.http2 {
+        info!("Serving HTTP/2 with prior knowledge besides HTTP/1.1");
+    }
+    if opt.on_empty_block != EmptyBlockPolicy::Prove {
+        info!("On the empty blocks: {}", opt.on_empty_block);
+    }
+    if let Some(max) = opt.max_connections_per_client {
+        info!("Refusing the connections of a client beyond {} open", max);
+    }
+    if let Some(max) = opt.max_total_enclave_mb {
+        info!(
+            "Admitting the proofs under {}MB of enclave memory, estimated by {}",
+            max, opt.enclave_memory_model
+        );
+    }
+    if opt.reverify_sample_rate > 0.0 {
+        info!(
+            "Verifying again {} of the proofs produced",
+            opt.reverify_sample_rate
+        );
+    }
+    if let Some(endpoint) = &opt.statsd_endpoint {
+        info!(
+            "Pushing the metrics to {} every {}s, tagged {:?}",
+            endpoint, opt.statsd_interval_secs, opt.statsd_tag
+        );
+    }
+    if opt.benchmark_mode {
+        warn!(
+            "LOAD TESTING ONLY: serving the proof requests from the cached block data{}",
+            if opt.benchmark_mock_guest {
+                ", with placeholder proofs"
+            } else {
+                ""
+            }
+        );
+    }
+    // the mock guest runs no guest
+    if !opt.benchmark_mock_guest {
+        match opt.guest_launcher.find_program() {
+            Ok(program) => info!(
+                "Launching the guest with {:?} ({:?})",
+                opt.guest_launcher.to_string(),
+                program
+            ),
+            Err(err) => bail!(
+                "Invalid guest_launcher {:?}: {}",
+                opt.guest_launcher.to_string(),
+                err
+            ),
+        }
+    }
+    let guest_valid = opt.benchmark_mock_guest
+        || match startup_check(&opt.guest, opt.on_invalid_guest) {
+            Ok(Some(image)) => {
+                info!("Guest mrenclave: 0x{}", hex::encode(image.mrenclave));
+                true
+            }
+            Ok(None) => {
+                warn!(
+                    "No valid guest under {:?} yet, unready until there is, checking every {:?}",
+                    opt.guest, GUEST_RECHECK_INTERVAL
+                );
+                false
+            }
+            Err(err) => bail!("Invalid guest under {:?}: {}", opt.guest, err),
+        };
+    serve(opt, effective_config, guest_valid).await?;
     Ok(())
 }
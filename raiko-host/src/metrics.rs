@@ -1,7 +1,22 @@
+use std::{
+    future::Future,
+    net::IpAddr,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use hyper::{body::HttpBody, Body, Request, Response};
 use lazy_static::lazy_static;
 use prometheus::{
-    labels, register_int_counter_vec, register_int_gauge, register_int_gauge_vec, IntCounterVec,
-    IntGauge, IntGaugeVec,
+    exponential_buckets, labels, proto::MetricFamily, register_histogram_vec, register_int_counter,
+    register_int_counter_vec, register_int_gauge, register_int_gauge_vec, HistogramVec, IntCounter,
+    IntCounterVec, IntGauge, IntGaugeVec,
+};
+use tower::{Layer, Service};
+use zeth_lib::host::provider::{
+    cache_write_limit::CacheWriteLimit,
+    circuit_breaker::{BreakerState, CircuitBreaker},
 };
 
 lazy_static! {
@@ -23,11 +38,148 @@ lazy_static! {
         &["blockid"]
     )
     .unwrap();
+    pub static ref RPC_CALLS_GAUGE: IntGaugeVec = register_int_gauge_vec!(
+        "rpc_calls_gauge",
+        "number of rpc calls issued to build the witness of a block",
+        &["blockid"]
+    )
+    .unwrap();
     pub static ref PREPARE_INPUT_TIME: IntGauge = register_int_gauge!(
         "prepare_input_time_gauge",
         "time taken for preparing input before proof generation"
     )
     .unwrap();
+    pub static ref COALESCED_REQUESTS_COUNTER: IntCounter = register_int_counter!(
+        "coalesced_requests_counter",
+        "number of proof requests served by the proof of an earlier submission"
+    )
+    .unwrap();
+    pub static ref CACHE_BYPASS_COUNTER: IntCounter = register_int_counter!(
+        "cache_bypass_counter",
+        "number of proof requests ignoring the cached block data and proofs, with skipCache"
+    )
+    .unwrap();
+    pub static ref SHED_REQUESTS_COUNTER: IntCounter = register_int_counter!(
+        "shed_requests_counter",
+        "number of proof requests rejected as projected to take longer than target_proof_ms"
+    )
+    .unwrap();
+    pub static ref EXPIRED_JOBS_COUNTER: IntCounter = register_int_counter!(
+        "expired_jobs_counter",
+        "number of proof requests failed as they outlived max_job_lifetime_secs"
+    )
+    .unwrap();
+    pub static ref PROOF_REVERIFIED_COUNTER: IntCounter = register_int_counter!(
+        "proof_reverified_counter",
+        "number of proofs produced verified again, with reverify_sample_rate"
+    )
+    .unwrap();
+    pub static ref PROOF_REVERIFY_FAILURES_COUNTER: IntCounter = register_int_counter!(
+        "proof_reverify_failures_counter",
+        "number of proofs produced failing their verification again, a faulty enclave"
+    )
+    .unwrap();
+    pub static ref CACHE_WRITE_QUEUE_DEPTH: IntGauge = register_int_gauge!(
+        "cache_write_queue_depth",
+        "cache file writes waiting for a slot of --cache-write-concurrency"
+    )
+    .unwrap();
+    pub static ref DISK_USAGE_BYTES: IntGauge = register_int_gauge!(
+        "disk_usage_bytes",
+        "combined size of the logs, cache files and persisted proofs"
+    )
+    .unwrap();
+    pub static ref DISK_HEADROOM_BYTES: IntGauge =
+        register_int_gauge!("disk_headroom_bytes", "bytes left before max_disk_bytes").unwrap();
+    pub static ref CACHE_ENTRIES: IntGauge = register_int_gauge!(
+        "cache_entries",
+        "number of cache files, as of the last compaction"
+    )
+    .unwrap();
+    pub static ref CACHE_BYTES: IntGauge = register_int_gauge!(
+        "cache_bytes",
+        "size of the cache files, as of the last compaction"
+    )
+    .unwrap();
+    pub static ref CACHE_COMPACTION_REMOVED_FILES: IntGauge = register_int_gauge!(
+        "cache_compaction_removed_files",
+        "orphaned temporary files removed by the last compaction"
+    )
+    .unwrap();
+    pub static ref CACHE_COMPACTION_TIMESTAMP: IntGauge = register_int_gauge!(
+        "cache_compaction_timestamp_seconds",
+        "when the last compaction ended, in seconds since the unix epoch"
+    )
+    .unwrap();
+    pub static ref RPC_BREAKER_STATE: IntGaugeVec = register_int_gauge_vec!(
+        "rpc_breaker_state",
+        "1 for the state of the circuit breaker of each RPC host which failed since its \
+         last success, the other hosts are closed",
+        &["host", "state"]
+    )
+    .unwrap();
+    pub static ref ENCLAVE_MEMORY_PROJECTED_MB: IntGauge = register_int_gauge!(
+        "enclave_memory_projected_mb",
+        "estimated enclave memory of the proofs admitted, with max_total_enclave_mb"
+    )
+    .unwrap();
+    pub static ref ENCLAVE_MEMORY_LIMIT_MB: IntGauge = register_int_gauge!(
+        "enclave_memory_limit_mb",
+        "max_total_enclave_mb, the projected enclave memory the proofs are admitted under"
+    )
+    .unwrap();
+    pub static ref CLIENT_CONNECTIONS_GAUGE: IntGaugeVec = register_int_gauge_vec!(
+        "client_connections",
+        "number of connections open per client, with max_connections_per_client",
+        &["client"]
+    )
+    .unwrap();
+    pub static ref HTTP_REQUESTS_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "http_requests_counter",
+        "number of http requests",
+        &["method", "route", "status"]
+    )
+    .unwrap();
+    pub static ref HTTP_REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "http_request_duration_seconds",
+        "time taken to respond to http requests",
+        &["method", "route"],
+        exponential_buckets(0.005, 4.0, 10).unwrap()
+    )
+    .unwrap();
+    pub static ref HTTP_REQUEST_SIZE: HistogramVec = register_histogram_vec!(
+        "http_request_size_bytes",
+        "size of the http request bodies",
+        &["method", "route"],
+        exponential_buckets(64.0, 4.0, 12).unwrap()
+    )
+    .unwrap();
+    pub static ref HTTP_RESPONSE_SIZE: HistogramVec = register_histogram_vec!(
+        "http_response_size_bytes",
+        "size of the http response bodies",
+        &["method", "route"],
+        exponential_buckets(64.0, 4.0, 12).unwrap()
+    )
+    .unwrap();
+}
+
+/// The metrics of the default registry, their names prefixed with `prefix` to namespace
+/// them in a Prometheus shared with other services.
+pub fn gather(prefix: &str) -> Vec<MetricFamily> {
+    let mut families = prometheus::gather();
+    for family in &mut families {
+        let name = format!("{}{}", prefix, family.get_name());
+        family.set_name(name);
+    }
+    families
+}
+
+/// Whether `prefix` keeps the names of the metrics legal: a letter, `_` or `:` first,
+/// then letters, digits, `_` and `:`. Empty leaves the names as registered.
+pub fn is_valid_prefix(prefix: &str) -> bool {
+    prefix.chars().enumerate().all(|(i, c)| {
+        c.is_ascii_alphabetic() || c == '_' || c == ':' || (i > 0 && c.is_ascii_digit())
+    })
 }
 
 pub fn observe_sgx_gen(block: u64, time: i64) {
@@ -54,6 +206,229 @@ pub fn inc_sgx_error(block: u64) {
     SGX_PROOF_ERROR_COUNTER.with(&label).inc();
 }
 
+pub fn observe_rpc_calls(block: u64, calls: u64) {
+    let bid = &block.to_string()[..];
+    let label = labels! {
+        "blockid" => bid,
+    };
+    RPC_CALLS_GAUGE.with(&label).set(calls as i64);
+}
+
 pub fn observe_input(time: i64) {
     PREPARE_INPUT_TIME.set(time);
 }
+
+pub fn observe_disk_usage(used_bytes: u64, headroom_bytes: u64) {
+    DISK_USAGE_BYTES.set(used_bytes as i64);
+    DISK_HEADROOM_BYTES.set(headroom_bytes as i64);
+}
+
+pub fn observe_cache_compaction(entries: u64, bytes: u64, removed_files: u64, timestamp: u64) {
+    CACHE_ENTRIES.set(entries as i64);
+    CACHE_BYTES.set(bytes as i64);
+    CACHE_COMPACTION_REMOVED_FILES.set(removed_files as i64);
+    CACHE_COMPACTION_TIMESTAMP.set(timestamp as i64);
+}
+
+pub fn observe_cache_writes(limit: &CacheWriteLimit) {
+    CACHE_WRITE_QUEUE_DEPTH.set(limit.queue_depth() as i64);
+}
+
+pub fn observe_enclave_memory(projected_mb: u64, limit_mb: u64) {
+    ENCLAVE_MEMORY_PROJECTED_MB.set(projected_mb as i64);
+    ENCLAVE_MEMORY_LIMIT_MB.set(limit_mb as i64);
+}
+
+pub fn inc_coalesced() {
+    COALESCED_REQUESTS_COUNTER.inc();
+}
+
+pub fn inc_expired_jobs() {
+    EXPIRED_JOBS_COUNTER.inc();
+}
+
+pub fn inc_reverified() {
+    PROOF_REVERIFIED_COUNTER.inc();
+}
+
+pub fn inc_reverify_failures() {
+    PROOF_REVERIFY_FAILURES_COUNTER.inc();
+}
+
+pub fn inc_cache_bypass() {
+    CACHE_BYPASS_COUNTER.inc();
+}
+
+pub fn observe_client_connections(client: &IpAddr, connections: usize) {
+    let client = &client.to_string()[..];
+    match connections {
+        // the clients gone are dropped
+        0 => {
+            let _ = CLIENT_CONNECTIONS_GAUGE.remove(&labels! { "client" => client });
+        }
+        _ => CLIENT_CONNECTIONS_GAUGE
+            .with(&labels! { "client" => client })
+            .set(connections as i64),
+    }
+}
+
+pub fn observe_rpc_breakers(breaker: &CircuitBreaker) {
+    // the hosts which recovered are dropped
+    RPC_BREAKER_STATE.reset();
+    for (host, state) in breaker.states() {
+        for other in [
+            BreakerState::Closed,
+            BreakerState::HalfOpen,
+            BreakerState::Open,
+        ] {
+            let label = labels! {
+                "host" => host.as_str(),
+                "state" => &other.to_string()[..],
+            };
+            RPC_BREAKER_STATE.with(&label).set((other == state) as i64);
+        }
+    }
+}
+
+/// Records the count, body sizes and latency of the http requests, labelled by method and
+/// by the route template `route` maps the request path to. Templates instead of the raw
+/// paths keep the label cardinality bounded.
+#[derive(Clone, Copy)]
+pub struct HttpMetricsLayer {
+    route: fn(&str) -> &'static str,
+}
+
+impl HttpMetricsLayer {
+    pub fn new(route: fn(&str) -> &'static str) -> Self {
+        Self { route }
+    }
+}
+
+impl<S> Layer<S> for HttpMetricsLayer {
+    type Service = HttpMetrics<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        HttpMetrics {
+            inner,
+            route: self.route,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct HttpMetrics<S> {
+    inner: S,
+    route: fn(&str) -> &'static str,
+}
+
+impl<S> Service<Request<Body>> for HttpMetrics<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let method = req.method().to_string();
+        let route = (self.route)(req.uri().path());
+        // streamed bodies have no known size
+        let request_size = req.body().size_hint().exact();
+        let start = Instant::now();
+        let response = self.inner.call(req);
+        Box::pin(async move {
+            let response = response.await?;
+            let labels = [method.as_str(), route];
+            HTTP_REQUEST_DURATION
+                .with_label_values(&labels)
+                .observe(start.elapsed().as_secs_f64());
+            if let Some(size) = request_size {
+                HTTP_REQUEST_SIZE
+                    .with_label_values(&labels)
+                    .observe(size as f64);
+            }
+            if let Some(size) = response.body().size_hint().exact() {
+                HTTP_RESPONSE_SIZE
+                    .with_label_values(&labels)
+                    .observe(size as f64);
+            }
+            HTTP_REQUESTS_COUNTER
+                .with_label_values(&[method.as_str(), route, response.status().as_str()])
+                .inc();
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use tower::{service_fn, ServiceExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn http_metrics_are_labelled_by_route_template() {
+        let service = HttpMetricsLayer::new(|path| {
+            if path.starts_with("/items/") {
+                "/items/{id}"
+            } else {
+                "unmatched"
+            }
+        })
+        .layer(service_fn(|_req: Request<Body>| async {
+            Ok::<_, Infallible>(Response::new(Body::from("done")))
+        }));
+        for id in 0..3 {
+            let req = Request::post(format!("/items/{}", id))
+                .body(Body::from("payload"))
+                .unwrap();
+            service.clone().oneshot(req).await.unwrap();
+        }
+
+        let labels = ["POST", "/items/{id}"];
+        assert_eq!(
+            HTTP_REQUESTS_COUNTER
+                .with_label_values(&["POST", "/items/{id}", "200"])
+                .get(),
+            3
+        );
+        let sizes = HTTP_REQUEST_SIZE.with_label_values(&labels);
+        assert_eq!(sizes.get_sample_count(), 3);
+        assert_eq!(sizes.get_sample_sum(), 21.0);
+        assert_eq!(
+            HTTP_RESPONSE_SIZE
+                .with_label_values(&labels)
+                .get_sample_sum(),
+            12.0
+        );
+        assert_eq!(
+            HTTP_REQUEST_DURATION
+                .with_label_values(&labels)
+                .get_sample_count(),
+            3
+        );
+    }
+
+    #[test]
+    fn metrics_are_exported_under_the_prefix() {
+        inc_coalesced();
+        let names: Vec<_> = gather("zeth_staging_")
+            .iter()
+            .map(|family| family.get_name().to_owned())
+            .collect();
+        assert!(names.contains(&"zeth_staging_coalesced_requests_counter".to_owned()));
+        assert!(names.iter().all(|name| name.starts_with("zeth_staging_")));
+
+        assert!(is_valid_prefix("raiko_"));
+        assert!(is_valid_prefix(""));
+        assert!(!is_valid_prefix("1raiko_"));
+        assert!(!is_valid_prefix("raiko-"));
+    }
+}
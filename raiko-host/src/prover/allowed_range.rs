@@ -0,0 +1,141 @@
+//! Guardrail restricting the blocks the server is willing to prove.
+use std::{fmt, str::FromStr};
+
+use serde_with::{DeserializeFromStr, SerializeDisplay};
+
+/// An inclusive range of block numbers, optionally bound to a chain, given as
+/// `[chain:]start-end` or `[chain:]block`.
+#[derive(Debug, Clone, PartialEq, Eq, DeserializeFromStr, SerializeDisplay)]
+pub struct AllowedRange {
+    pub chain: Option<String>,
+    pub start: u64,
+    pub end: u64,
+}
+
+impl AllowedRange {
+    fn applies_to(&self, chain: &str) -> bool {
+        match &self.chain {
+            Some(c) => c == chain,
+            None => true,
+        }
+    }
+
+    fn contains(&self, block: u64) -> bool {
+        self.start <= block && block <= self.end
+    }
+}
+
+impl FromStr for AllowedRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (chain, range) = match s.rsplit_once(':') {
+            Some((chain, range)) => (Some(chain.trim().to_owned()), range),
+            None => (None, s),
+        };
+        let parse = |n: &str| {
+            n.trim()
+                .parse::<u64>()
+                .map_err(|e| format!("invalid block number {:?} in range {:?}: {}", n, s, e))
+        };
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (parse(start)?, parse(end)?),
+            None => {
+                let block = parse(range)?;
+                (block, block)
+            }
+        };
+        if start > end {
+            return Err(format!("invalid range {:?}: start is after end", s));
+        }
+        Ok(AllowedRange { chain, start, end })
+    }
+}
+
+impl fmt::Display for AllowedRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(chain) = &self.chain {
+            write!(f, "{}:", chain)?;
+        }
+        write!(f, "{}-{}", self.start, self.end)
+    }
+}
+
+/// Checks `block` of `chain` against the allowlist. A chain without any range is not
+/// restricted.
+pub fn check_block_allowed(ranges: &[AllowedRange], chain: &str, block: u64) -> Result<(), String> {
+    let mut ranges = ranges.iter().filter(|r| r.applies_to(chain)).peekable();
+    if ranges.peek().is_none() {
+        return Ok(());
+    }
+    let mut allowed = vec![];
+    for range in ranges {
+        if range.contains(block) {
+            return Ok(());
+        }
+        allowed.push(range.to_string());
+    }
+    Err(format!(
+        "block {} of chain {} is outside the allowed ranges [{}]",
+        block,
+        chain,
+        allowed.join(", ")
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges(ranges: &[&str]) -> Vec<AllowedRange> {
+        ranges.iter().map(|r| r.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(
+            "testnet:10-20".parse::<AllowedRange>().unwrap(),
+            AllowedRange {
+                chain: Some("testnet".to_owned()),
+                start: 10,
+                end: 20
+            }
+        );
+        assert_eq!(
+            "7".parse::<AllowedRange>().unwrap(),
+            AllowedRange {
+                chain: None,
+                start: 7,
+                end: 7
+            }
+        );
+        assert!("20-10".parse::<AllowedRange>().is_err());
+        assert!("a-10".parse::<AllowedRange>().is_err());
+    }
+
+    #[test]
+    fn test_empty_allowlist_allows_all() {
+        assert!(check_block_allowed(&[], "testnet", 42).is_ok());
+        // ranges of other chains don't restrict this one
+        assert!(check_block_allowed(&ranges(&["internal_devnet_a:1-2"]), "testnet", 42).is_ok());
+    }
+
+    #[test]
+    fn test_in_and_out_of_range() {
+        let ranges = ranges(&["testnet:100-200", "300-400"]);
+        assert!(check_block_allowed(&ranges, "testnet", 150).is_ok());
+        assert!(check_block_allowed(&ranges, "testnet", 350).is_ok());
+        assert!(check_block_allowed(&ranges, "testnet", 250).is_err());
+        assert!(check_block_allowed(&ranges, "testnet", 401).is_err());
+        assert!(check_block_allowed(&ranges, "internal_devnet_a", 150).is_err());
+    }
+
+    #[test]
+    fn test_range_boundaries() {
+        let ranges = ranges(&["100-200"]);
+        assert!(check_block_allowed(&ranges, "testnet", 99).is_err());
+        assert!(check_block_allowed(&ranges, "testnet", 100).is_ok());
+        assert!(check_block_allowed(&ranges, "testnet", 200).is_ok());
+        assert!(check_block_allowed(&ranges, "testnet", 201).is_err());
+    }
+}
@@ -0,0 +1,100 @@
+//! Benchmark mode, to load test the server apart from the proving: the proof requests are
+//! served from the block data already in the cache, read without RPC, and with
+//! `benchmark_mock_guest` without running the guest. The proofs are neither cached nor
+//! delivered to the sinks, their responses are marked as benchmark results.
+use zeth_lib::taiko::block_builder::TaikoStrategyBundle;
+use zeth_primitives::taiko::SGX_PROOF_LEN;
+
+use super::{
+    allowed_range::check_block_allowed,
+    context::Context,
+    error::Result,
+    prepare_input::prepare_input,
+    proof::sgx::execute_sgx,
+    request::{ProofRequest, ProofResponse, SgxRequest, SgxResponse},
+};
+
+pub async fn execute_benchmark(ctx: &Context, req: &ProofRequest) -> Result<ProofResponse> {
+    let ProofRequest::Sgx(sgx_req) = req else {
+        return Err("benchmark_mode only serves sgx proof requests"
+            .to_owned()
+            .into());
+    };
+    check_block_allowed(&ctx.prove_allowed_ranges, &ctx.l2_chain, sgx_req.block)?;
+    sgx_req.check_metadata()?;
+    let _pin = ctx.cache_pins.pin(sgx_req.block);
+    prepare_input::<TaikoStrategyBundle>(ctx, req).await?;
+    Ok(ProofResponse::Sgx(prove_benchmark(ctx, sgx_req).await?))
+}
+
+async fn prove_benchmark(ctx: &Context, req: &SgxRequest) -> Result<SgxResponse> {
+    let resp = match ctx.benchmark_mock_guest {
+        true => SgxResponse {
+            proof: format!("0x{}", "00".repeat(SGX_PROOF_LEN)),
+            envelope: None,
+            chain_spec_mismatch: None,
+            benchmark: true,
+            metadata: Default::default(),
+        },
+        false => execute_sgx(ctx, req).await?,
+    };
+    Ok(SgxResponse {
+        benchmark: true,
+        metadata: req.metadata.clone(),
+        ..resp
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use zeth_primitives::{Address, B256};
+
+    use super::*;
+    use crate::prover::error::Error;
+
+    #[tokio::test]
+    async fn benchmark_responses_are_flagged_and_read_no_rpc() {
+        let cache = tempfile::tempdir().unwrap();
+        let ctx = Context {
+            cache_path: cache.path().to_owned(),
+            l2_chain: "testnet".to_owned(),
+            benchmark_mode: true,
+            benchmark_mock_guest: true,
+            ..Default::default()
+        };
+        let req = SgxRequest {
+            block: 7,
+            // would be refused, were they called
+            l1_rpc: "http://127.0.0.1:1".to_owned(),
+            l2_rpc: "http://127.0.0.1:1".to_owned(),
+            prover: Address::ZERO,
+            graffiti: B256::ZERO,
+            timeout_secs: None,
+            data_source: None,
+            skip_cache: false,
+            state_overrides: None,
+            metadata: Default::default(),
+            deadline: None,
+        };
+
+        // the block data of the block is not in the cache, and not fetched either
+        let err = execute_benchmark(&ctx, &ProofRequest::Sgx(req.clone()))
+            .await
+            .err()
+            .unwrap();
+        let Error::Anyhow(err) = err else {
+            panic!("unexpected error {}", err);
+        };
+        assert!(err.chain().any(|cause| cause
+            .downcast_ref::<io::Error>()
+            .is_some_and(|err| err.kind() == io::ErrorKind::NotFound)));
+
+        let resp = prove_benchmark(&ctx, &req).await.unwrap();
+        assert!(resp.benchmark);
+        let json = serde_json::to_value(&resp).unwrap();
+        assert_eq!(json["benchmark"], true);
+        assert_eq!(resp.proof.len(), 2 + 2 * SGX_PROOF_LEN);
+    }
+}
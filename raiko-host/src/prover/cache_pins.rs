@@ -0,0 +1,44 @@
+//! The blocks being proven, whose cache files are neither evicted by the disk budget nor
+//! touched by the compaction.
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+#[derive(Debug, Default, Clone)]
+pub struct CachePins {
+    /// pinned blocks, by number of jobs
+    pinned: Arc<Mutex<HashMap<u64, usize>>>,
+}
+
+/// Keeps the cache files of a block from being evicted until dropped.
+pub struct PinGuard {
+    block: u64,
+    pinned: Arc<Mutex<HashMap<u64, usize>>>,
+}
+
+impl Drop for PinGuard {
+    fn drop(&mut self) {
+        let mut pinned = self.pinned.lock().unwrap();
+        if let Some(jobs) = pinned.get_mut(&self.block) {
+            *jobs -= 1;
+            if *jobs == 0 {
+                pinned.remove(&self.block);
+            }
+        }
+    }
+}
+
+impl CachePins {
+    pub fn pin(&self, block: u64) -> PinGuard {
+        *self.pinned.lock().unwrap().entry(block).or_default() += 1;
+        PinGuard {
+            block,
+            pinned: self.pinned.clone(),
+        }
+    }
+
+    pub fn is_pinned(&self, block: u64) -> bool {
+        self.pinned.lock().unwrap().contains_key(&block)
+    }
+}
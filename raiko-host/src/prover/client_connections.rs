@@ -0,0 +1,101 @@
+//! The limit of the connections a client keeps open at once, for one client not to hold
+//! every connection the server can serve. The clients are told by the IP address of
+//! their peer: the limit applies as the connection is accepted, before any request or
+//! header.
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
+
+use crate::metrics::observe_client_connections;
+
+/// The connections open per client, at most `max` each.
+#[derive(Debug)]
+pub struct ClientConnections {
+    max: usize,
+    counts: Mutex<HashMap<IpAddr, usize>>,
+}
+
+/// A connection of a client, counted until dropped with the connection.
+#[derive(Debug)]
+pub struct ClientConnection {
+    connections: Arc<ClientConnections>,
+    client: IpAddr,
+}
+
+impl ClientConnections {
+    pub fn new(max: usize) -> Arc<Self> {
+        Arc::new(ClientConnections {
+            max,
+            counts: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Counts a new connection of `client`, `None` when it already has `max` open.
+    pub fn open(self: &Arc<Self>, client: IpAddr) -> Option<ClientConnection> {
+        let mut counts = self.counts.lock().unwrap();
+        let count = counts.entry(client).or_default();
+        if *count >= self.max {
+            return None;
+        }
+        *count += 1;
+        observe_client_connections(&client, *count);
+        Some(ClientConnection {
+            connections: self.clone(),
+            client,
+        })
+    }
+
+    /// The connections `client` has open.
+    pub fn count(&self, client: &IpAddr) -> usize {
+        self.counts
+            .lock()
+            .unwrap()
+            .get(client)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+impl Drop for ClientConnection {
+    fn drop(&mut self) {
+        let mut counts = self.connections.counts.lock().unwrap();
+        let count = counts.get_mut(&self.client).expect("an open connection");
+        *count -= 1;
+        observe_client_connections(&self.client, *count);
+        // the clients gone are forgotten
+        if *count == 0 {
+            counts.remove(&self.client);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::CLIENT_CONNECTIONS_GAUGE;
+
+    #[test]
+    fn connections_are_limited_per_client() {
+        let connections = ClientConnections::new(2);
+        let (client, other): (IpAddr, IpAddr) =
+            ("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap());
+        let first = connections.open(client).unwrap();
+        let _second = connections.open(client).unwrap();
+        assert!(connections.open(client).is_none());
+        assert_eq!(
+            CLIENT_CONNECTIONS_GAUGE
+                .with_label_values(&["10.0.0.1"])
+                .get(),
+            2
+        );
+        // the other clients are unaffected
+        let _other = connections.open(other).unwrap();
+        assert_eq!(connections.count(&other), 1);
+
+        drop(first);
+        assert_eq!(connections.count(&client), 1);
+        assert!(connections.open(client).is_some());
+    }
+}
@@ -0,0 +1,135 @@
+//! Background compaction of the cache directory of the chain: removes the temporary files
+//! orphaned by interrupted writes, which slow the scans of the directory, and recounts
+//! the cache files for the metrics.
+use std::{
+    fs, io,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use super::{
+    cache_pins::CachePins,
+    utils::{is_cache_file_name, is_temp_file_name},
+};
+use crate::metrics::observe_cache_compaction;
+
+/// Age from which a temporary file is orphaned rather than being written. The writes of
+/// the cache take seconds, the uploads of block data minutes at most.
+pub const ORPHAN_AGE: Duration = Duration::from_secs(3600);
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CompactionStats {
+    pub removed_files: u64,
+    pub removed_bytes: u64,
+    /// cache files left
+    pub entries: u64,
+    pub bytes: u64,
+}
+
+/// Removes the temporary files of `chain_dir` last modified `orphan_age` ago, but those
+/// of the pinned blocks, and counts the cache files. A missing directory is empty.
+pub fn compact(
+    chain_dir: &Path,
+    pins: &CachePins,
+    orphan_age: Duration,
+) -> io::Result<CompactionStats> {
+    let mut stats = CompactionStats::default();
+    let entries = match fs::read_dir(chain_dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(stats),
+        Err(err) => return Err(err),
+    };
+    let now = SystemTime::now();
+    for entry in entries {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        let file_name = entry.file_name();
+        let Some(name) = file_name.to_str().filter(|_| metadata.is_file()) else {
+            continue;
+        };
+        if is_cache_file_name(name) {
+            stats.entries += 1;
+            stats.bytes += metadata.len();
+        } else if is_temp_file_name(name) {
+            let block = name.split('.').next().and_then(|block| block.parse().ok());
+            let age = now.duration_since(metadata.modified()?).unwrap_or_default();
+            if age < orphan_age || block.is_some_and(|block| pins.is_pinned(block)) {
+                continue;
+            }
+            match fs::remove_file(entry.path()) {
+                Ok(()) => {
+                    stats.removed_files += 1;
+                    stats.removed_bytes += metadata.len();
+                }
+                // renamed by its write in the meantime
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err),
+            }
+        }
+    }
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    observe_cache_compaction(stats.entries, stats.bytes, stats.removed_files, timestamp);
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use super::*;
+
+    /// Writes `size` bytes to `dir/name`, last modified `age` seconds ago.
+    fn write_file(dir: &Path, name: &str, size: usize, age: u64) {
+        let path = dir.join(name);
+        fs::write(&path, vec![0; size]).unwrap();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(age))
+            .unwrap();
+    }
+
+    #[test]
+    fn removes_the_orphaned_temp_files_of_unpinned_blocks() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(dir.path(), "1.l1.json.gz", 100, 7200);
+        write_file(dir.path(), "1.l2.json.gz", 200, 7200);
+        // orphaned
+        write_file(dir.path(), "2.l1.json.gz.a1B2c3.tmp", 10, 7200);
+        write_file(dir.path(), "2.l2.json.gz.d4E5f6.part", 10, 7200);
+        // being written
+        write_file(dir.path(), "3.l1.json.gz.a1B2c3.tmp", 10, 5);
+        // orphaned, but its block is being proven
+        write_file(dir.path(), "4.l2.json.gz.a1B2c3.tmp", 10, 7200);
+        // not a cache file
+        write_file(dir.path(), "notes.tmp", 10, 7200);
+
+        let pins = CachePins::default();
+        let _pin = pins.pin(4);
+        let stats = compact(dir.path(), &pins, ORPHAN_AGE).unwrap();
+        assert_eq!(
+            stats,
+            CompactionStats {
+                removed_files: 2,
+                removed_bytes: 20,
+                entries: 2,
+                bytes: 300,
+            }
+        );
+        assert!(!dir.path().join("2.l1.json.gz.a1B2c3.tmp").exists());
+        assert!(!dir.path().join("2.l2.json.gz.d4E5f6.part").exists());
+        assert!(dir.path().join("3.l1.json.gz.a1B2c3.tmp").exists());
+        assert!(dir.path().join("4.l2.json.gz.a1B2c3.tmp").exists());
+        assert!(dir.path().join("notes.tmp").exists());
+
+        let missing = dir.path().join("missing");
+        assert_eq!(
+            compact(&missing, &pins, ORPHAN_AGE).unwrap(),
+            CompactionStats::default()
+        );
+    }
+}
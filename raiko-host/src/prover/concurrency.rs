@@ -0,0 +1,181 @@
+//! The limit of the requests served at once, resized at runtime by
+//! `POST /admin/concurrency` to tune the throughput without draining the server.
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+
+use serde::Serialize;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::info;
+
+/// The permits of the limit, `current` converging to `target`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConcurrencyStatus {
+    /// the permits, free or held by the requests in flight
+    pub current: usize,
+    /// the limit asked for, below `current` until enough requests finish
+    pub target: usize,
+}
+
+#[derive(Debug)]
+struct LimitState {
+    status: ConcurrencyStatus,
+    draining: bool,
+}
+
+/// A limit of the requests in flight, which grows at once and shrinks as the requests
+/// in flight finish.
+#[derive(Debug)]
+pub struct ConcurrencyLimit {
+    semaphore: Arc<Semaphore>,
+    state: Mutex<LimitState>,
+    waiting: AtomicUsize,
+}
+
+/// Counts a request waiting for a permit until it gets one or gives up.
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl ConcurrencyLimit {
+    pub fn new(limit: usize) -> Arc<Self> {
+        Arc::new(ConcurrencyLimit {
+            semaphore: Arc::new(Semaphore::new(limit)),
+            state: Mutex::new(LimitState {
+                status: ConcurrencyStatus {
+                    current: limit,
+                    target: limit,
+                },
+                draining: false,
+            }),
+            waiting: AtomicUsize::new(0),
+        })
+    }
+
+    /// Waits for a free permit, held until the permit is dropped.
+    pub async fn acquire(&self) -> OwnedSemaphorePermit {
+        self.waiting.fetch_add(1, Ordering::Relaxed);
+        let _waiting = Waiting(&self.waiting);
+        self.acquire_permit().await
+    }
+
+    async fn acquire_permit(&self) -> OwnedSemaphorePermit {
+        self.semaphore
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("the semaphore is never closed")
+    }
+
+    /// The requests waiting for a permit.
+    pub fn waiting(&self) -> usize {
+        self.waiting.load(Ordering::Relaxed)
+    }
+
+    /// The free permits.
+    pub fn available(&self) -> usize {
+        self.semaphore.available_permits()
+    }
+
+    pub fn status(&self) -> ConcurrencyStatus {
+        self.state.lock().unwrap().status
+    }
+
+    /// Sets the limit to `target`, adding the missing permits at once and removing the
+    /// extra ones as they are freed.
+    pub fn resize(self: &Arc<Self>, target: usize) -> ConcurrencyStatus {
+        let mut state = self.state.lock().unwrap();
+        let status = &mut state.status;
+        info!(
+            "Resizing the concurrency limit from {} to {}",
+            status.current, target
+        );
+        status.target = target;
+        if target > status.current {
+            self.semaphore.add_permits(target - status.current);
+            status.current = target;
+        }
+        while status.current > target {
+            let Ok(permit) = self.semaphore.try_acquire() else {
+                break;
+            };
+            permit.forget();
+            status.current -= 1;
+        }
+        let status = *status;
+        if status.current > status.target && !state.draining {
+            state.draining = true;
+            tokio::spawn(self.clone().drain());
+        }
+        status
+    }
+
+    /// Removes the permits of the requests in flight as they finish, until the limit is
+    /// down to its target.
+    async fn drain(self: Arc<Self>) {
+        loop {
+            let permit = self.acquire_permit().await;
+            let mut state = self.state.lock().unwrap();
+            let status = &mut state.status;
+            if status.current > status.target {
+                permit.forget();
+                status.current -= 1;
+            }
+            if status.current <= status.target {
+                info!("The concurrency limit is down to {}", status.current);
+                state.draining = false;
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time::timeout;
+
+    use super::*;
+
+    fn status(current: usize, target: usize) -> ConcurrencyStatus {
+        ConcurrencyStatus { current, target }
+    }
+
+    #[tokio::test]
+    async fn the_limit_grows_at_once_and_shrinks_as_requests_finish() {
+        let limit = ConcurrencyLimit::new(2);
+        let in_flight = vec![limit.acquire().await, limit.acquire().await];
+        let wait = Duration::from_millis(50);
+        assert!(timeout(wait, limit.acquire()).await.is_err());
+
+        // growing admits another request right away
+        assert_eq!(limit.resize(3), status(3, 3));
+        let third = timeout(wait, limit.acquire()).await.unwrap();
+
+        // shrinking waits for the requests in flight
+        assert_eq!(limit.resize(1), status(3, 1));
+        drop(third);
+        drop(in_flight);
+        timeout(Duration::from_secs(1), async {
+            while limit.status() != status(1, 1) {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap();
+        let only = limit.acquire().await;
+        assert!(timeout(wait, limit.acquire()).await.is_err());
+        drop(only);
+
+        // free permits are removed at once
+        assert_eq!(limit.resize(4), status(4, 4));
+        assert_eq!(limit.resize(2), status(2, 2));
+    }
+}
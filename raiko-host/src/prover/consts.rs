@@ -1,5 +1,7 @@
 #[allow(dead_code)]
 pub const SGX_PROOF_PREFIX: &str = "Proof:";
+pub const SGX_ENVELOPE_PREFIX: &str = "Envelope:";
+pub const SGX_CHAIN_SPEC_MISMATCH_PREFIX: &str = "Chain spec mismatch:";
 pub const SGX_PARENT_DIR: &str = "sgx";
 #[allow(dead_code)]
 pub const PSE_ZK_PARENT_DIR: &str = "pse-zk";
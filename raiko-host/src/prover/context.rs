@@ -1,43 +1,345 @@
-use std::path::{absolute, PathBuf};
+use std::{
+    path::{absolute, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
 use tracing::debug;
+use zeth_lib::{
+    consts::{get_taiko_chain_spec, ChainSpecMismatchPolicy, ConsensusOverrides},
+    host::provider::{
+        cache_write_limit::CacheWriteLimit,
+        circuit_breaker::CircuitBreaker,
+        connection_limit::ConnectionLimit,
+        data_source::DataSourceKind,
+        dns::CachingResolver,
+        fetch_limit::{LayerLimit, LayerLimits},
+        retry_policy::{RetryPolicies, RetryPolicy},
+        RpcOptions,
+    },
+};
+use zeth_primitives::U256;
+
+use crate::{
+    log_tail::{LogTail, LOG_TAIL},
+    prover::{
+        allowed_range::AllowedRange,
+        cache_pins::CachePins,
+        cpu_affinity::CpuSet,
+        disk_budget::DiskBudget,
+        empty_block::EmptyBlockPolicy,
+        enclave_memory::EnclaveMemoryLimit,
+        estimate::Estimator,
+        failure_dump::FailureDumps,
+        launcher::GuestLauncher,
+        proof_history::ProofHistory,
+        record::RequestRecorder,
+        request::RequestDefaults,
+        reverify::Reverifier,
+        sink::SinkHandle,
+        utils::{chain_cache_dir, Secret},
+        witness_store::WitnessStore,
+    },
+    Opt,
+};
 
 #[derive(Debug, Default, Clone)]
 pub struct Context {
     /// guest executable path
     pub guest_path: PathBuf,
+    /// the command the guest is launched through
+    pub guest_launcher: GuestLauncher,
     /// cache for public input
     pub cache_path: PathBuf,
     pub l2_chain: String,
+    /// chain id of `l2_chain`, namespaces the cache entries
+    pub l2_chain_id: u64,
     pub sgx_context: SgxContext,
     pub max_caches: usize,
+    pub rpc_options: RpcOptions,
+    /// data sources the requests may select besides the one of `rpc_options`, the default
+    pub data_sources: Vec<DataSourceKind>,
+    pub prove_allowed_ranges: Vec<AllowedRange>,
+    /// blocks of a feasibility batch checked at once
+    pub feasibility_batch_concurrency: usize,
+    /// prefix of the names of the metrics served
+    pub metrics_prefix: String,
+    /// witnesses of recent jobs, only kept when the debug endpoints are enabled
+    pub witness_store: Option<WitnessStore>,
+    /// the log lines streamed by /logs/tail, only when the debug endpoints are enabled
+    pub log_tail: Option<LogTail>,
+    pub debug_auth_token: Option<Secret>,
+    /// where the proof requests served are recorded to
+    pub request_recorder: Option<RequestRecorder>,
+    /// where the inputs of the failed proofs are written to
+    pub failure_dumps: Option<FailureDumps>,
+    /// the most recent proof requests served, for /proofs/recent
+    pub proof_history: ProofHistory,
+    /// most proofs a page of /proofs/recent may list
+    pub max_proof_history_limit: usize,
+    /// estimates the proving times, calibrated by the proofs served
+    pub estimator: Arc<Estimator>,
+    pub max_block_size_bytes: u64,
+    /// prover and graffiti of the requests omitting theirs
+    pub request_defaults: RequestDefaults,
+    /// testing only, replaces the chain id of `l2_chain` in the witnesses
+    pub chain_id_override: Option<u64>,
+    /// how the block data disagreeing with the chain spec is handled
+    pub on_chainspec_mismatch: ChainSpecMismatchPolicy,
+    /// how the blocks of no transaction but their anchor are handled
+    pub on_empty_block: EmptyBlockPolicy,
+    /// testing only, replaces the base fee and gas limit of the blocks in the witnesses
+    pub consensus_overrides: ConsensusOverrides,
+    /// testing only, JSON file of the code replacing the one of system contracts
+    pub system_overrides: Option<PathBuf>,
+    /// whether the requests may override the pre-state of their block, testing only
+    pub allow_consensus_overrides: bool,
+    /// testing only, the unix timestamp the guest reads as now
+    pub fixed_now: Option<u64>,
+    /// where the completed proofs are delivered to
+    pub sinks: Vec<SinkHandle>,
+    /// blocks being proven, whose cache files are kept
+    pub cache_pins: CachePins,
+    /// caps the disk footprint of the logs, the cache and the persisted proofs
+    pub disk_budget: Option<Arc<DiskBudget>>,
+    /// caps the estimated enclave memory of the proofs running at once
+    pub enclave_memory: Option<Arc<EnclaveMemoryLimit>>,
+    /// how long the guest may run, when the request sets no timeout
+    pub guest_timeout: Option<Duration>,
+    /// ceiling on the timeouts of the requests
+    pub max_guest_timeout: Option<Duration>,
+    /// how many times a proof failing to verify is proven again, when they are verified
+    pub self_verify_retries: Option<u32>,
+    /// the sample of the proofs produced verified again in the background
+    pub reverifier: Option<Arc<Reverifier>>,
+    /// load testing only, serves the proofs of the cached block data, see `benchmark`
+    pub benchmark_mode: bool,
+    /// load testing only, answers with placeholder proofs in benchmark mode
+    pub benchmark_mock_guest: bool,
 }
 
 #[derive(Debug, Default, Clone)]
 pub struct SgxContext {
     pub instance_id: u32,
+    /// cores the guest process is pinned to
+    pub cpu_affinity: Option<CpuSet>,
 }
 
 impl Context {
-    pub fn new(
-        guest_path: PathBuf,
-        cache_path: PathBuf,
-        l2_chain: String,
-        sgx_instance_id: u32,
-        max_caches: usize,
-    ) -> Self {
-        let guest_path = absolute(guest_path).unwrap();
+    pub fn new(opt: &Opt) -> Self {
+        let guest_path = absolute(&opt.guest).unwrap();
         debug!("Guest path: {:?}", guest_path);
-        let cache_path = absolute(cache_path).unwrap();
+        let cache_path = absolute(&opt.cache).unwrap();
         debug!("Cache path: {:?}", cache_path);
+        let cache_pins = CachePins::default();
+        let disk_budget = opt.max_disk_bytes.map(|max_bytes| {
+            Arc::new(DiskBudget::new(
+                max_bytes,
+                opt.log_path.clone(),
+                chain_cache_dir(&cache_path, get_taiko_chain_spec(&opt.l2_chain).chain_id()),
+                opt.proof_cache_dir.clone(),
+                cache_pins.clone(),
+            ))
+        });
         Self {
             guest_path,
+            guest_launcher: opt.guest_launcher.clone(),
             cache_path,
-            l2_chain,
+            l2_chain: opt.l2_chain.clone(),
+            l2_chain_id: get_taiko_chain_spec(&opt.l2_chain).chain_id(),
             sgx_context: SgxContext {
-                instance_id: sgx_instance_id,
+                instance_id: opt.sgx_instance_id,
+                cpu_affinity: opt.guest_cpu_affinity.clone(),
+            },
+            max_caches: opt.max_caches,
+            rpc_options: RpcOptions {
+                pool_idle_timeout: opt.rpc_pool_idle_timeout.map(Duration::from_secs),
+                connect_timeout: opt.rpc_connect_timeout_secs.map(Duration::from_secs),
+                request_timeout: opt.rpc_request_timeout_secs.map(Duration::from_secs),
+                block_fetch_budget: opt.rpc_block_fetch_budget_secs.map(Duration::from_secs),
+                data_source: opt.data_source,
+                beacon_rpc_url: opt.beacon_rpc_url.clone(),
+                kzg_trusted_setup: opt
+                    .kzg_trusted_setup
+                    .as_ref()
+                    .map(|path| absolute(path).unwrap()),
+                fixed_now: opt.fixed_now,
+                // set per block
+                block_fetch_deadline: None,
+                dns_resolver: match (opt.rpc_dns_resolve_per_connection, opt.rpc_dns_ttl_secs) {
+                    (true, _) => Some(Duration::ZERO),
+                    (false, ttl) => ttl.map(Duration::from_secs),
+                }
+                .map(|ttl| Arc::new(CachingResolver::new(ttl))),
+                resolve_overrides: opt.rpc_resolve.clone(),
+                connection_limit: Some(Arc::new(ConnectionLimit::new(
+                    opt.rpc_max_connections_per_host,
+                ))),
+                layer_limits: LayerLimits {
+                    l1: LayerLimit::new(
+                        opt.l1_fetch_concurrency,
+                        opt.l1_rpc_request_timeout_secs.map(Duration::from_secs),
+                    ),
+                    l2: LayerLimit::new(
+                        opt.l2_fetch_concurrency,
+                        opt.l2_rpc_request_timeout_secs.map(Duration::from_secs),
+                    ),
+                },
+                // set per layer
+                fetch_limit: Default::default(),
+                circuit_breaker: opt.rpc_breaker_threshold.map(|threshold| {
+                    Arc::new(CircuitBreaker::new(
+                        threshold,
+                        Duration::from_secs(opt.rpc_breaker_cooldown_secs),
+                    ))
+                }),
+                endpoint_health: Default::default(),
+                max_txs_per_block: opt.max_txs_per_block,
+                extra_rpc_methods: Arc::new(opt.extra_rpc_methods.iter().cloned().collect()),
+                rpc_headers: opt.rpc_header.clone(),
+                // set per request
+                skip_cache_reads: false,
+                cache_write_limit: Some(Arc::new(CacheWriteLimit::new(
+                    opt.cache_write_concurrency,
+                ))),
+                max_rpc_calls_per_block: opt.max_rpc_calls_per_block,
+                // set per request
+                rpc_call_budget: None,
+                retry_policies: RetryPolicies::new(
+                    RetryPolicy {
+                        max_retries: opt.rpc_max_retries,
+                        base_delay: Duration::from_millis(opt.rpc_retry_base_delay_ms),
+                    },
+                    &opt.rpc_retry,
+                ),
+                on_rpc_inconsistency: opt.on_rpc_inconsistency,
+                // separate the chains, like the cache directories
+                shared_cache_url: opt.shared_cache_url.as_ref().map(|url| {
+                    format!(
+                        "{}/{}",
+                        url.trim_end_matches('/'),
+                        get_taiko_chain_spec(&opt.l2_chain).chain_id()
+                    )
+                }),
+            },
+            data_sources: opt.data_sources.clone(),
+            prove_allowed_ranges: opt.prove_allowed_range.clone(),
+            feasibility_batch_concurrency: opt.feasibility_batch_concurrency,
+            metrics_prefix: opt.metrics_prefix.clone(),
+            witness_store: opt.enable_debug_endpoints.then(|| {
+                WitnessStore::new(
+                    opt.witness_retention_count,
+                    Duration::from_secs(opt.witness_retention_secs),
+                )
+            }),
+            log_tail: opt.enable_debug_endpoints.then(|| LOG_TAIL.clone()),
+            debug_auth_token: opt.debug_auth_token.clone(),
+            request_recorder: opt.record_requests.clone().map(RequestRecorder::new),
+            failure_dumps: opt.failure_dump_dir.clone().map(|dir| {
+                FailureDumps::new(dir, opt.failure_dump_max_count, opt.failure_dump_max_bytes)
+            }),
+            proof_history: ProofHistory::new(opt.proof_history_capacity),
+            max_proof_history_limit: opt.max_proof_history_limit,
+            estimator: Arc::new(Estimator::new(
+                opt.estimate_model,
+                opt.enclave_memory_model,
+                opt.estimate_calibration_samples,
+            )),
+            max_block_size_bytes: opt.max_block_size_bytes,
+            request_defaults: RequestDefaults {
+                prover: opt.default_prover,
+                graffiti: opt.default_graffiti,
+            },
+            chain_id_override: opt.chain_id_override,
+            on_chainspec_mismatch: opt.on_chainspec_mismatch,
+            on_empty_block: opt.on_empty_block,
+            consensus_overrides: ConsensusOverrides {
+                base_fee_per_gas: opt.base_fee_override.map(U256::from),
+                gas_limit: opt.gas_limit_override.map(U256::from),
             },
-            max_caches,
+            system_overrides: opt
+                .system_overrides
+                .as_ref()
+                .map(|path| absolute(path).unwrap()),
+            allow_consensus_overrides: opt.allow_consensus_overrides,
+            sinks: opt
+                .sink
+                .iter()
+                .map(|sink| SinkHandle::new(sink, opt.output_sharding))
+                .collect(),
+            cache_pins,
+            disk_budget,
+            enclave_memory: opt.max_total_enclave_mb.map(EnclaveMemoryLimit::new),
+            guest_timeout: opt.guest_timeout_secs.map(Duration::from_secs),
+            max_guest_timeout: opt
+                .max_guest_timeout_secs
+                .or(opt.guest_timeout_secs)
+                .map(Duration::from_secs),
+            self_verify_retries: opt.verify_before_return.then_some(opt.self_verify_retries),
+            reverifier: (opt.reverify_sample_rate > 0.0)
+                .then(|| Arc::new(Reverifier::new(opt.reverify_sample_rate))),
+            benchmark_mode: opt.benchmark_mode,
+            benchmark_mock_guest: opt.benchmark_mock_guest,
         }
     }
+
+    /// How long the guest may run for a request asking for `requested_secs`, and whether
+    /// the request asked for more than the ceiling.
+    pub fn guest_timeout(&self, requested_secs: Option<u64>) -> (Option<Duration>, bool) {
+        match (
+            requested_secs.map(Duration::from_secs),
+            self.max_guest_timeout,
+        ) {
+            (Some(requested), Some(max)) if requested > max => (Some(max), true),
+            (Some(requested), _) => (Some(requested), false),
+            (None, _) => (self.guest_timeout, false),
+        }
+    }
+
+    /// The RPC options of a request whose RPC URLs name a `data_source`, the default one
+    /// when `None`. Only the configured data sources may be selected.
+    pub fn rpc_options_for(
+        &self,
+        data_source: Option<DataSourceKind>,
+    ) -> Result<RpcOptions, String> {
+        let default = self.rpc_options.data_source;
+        let data_source = data_source.unwrap_or(default);
+        if data_source != default && !self.data_sources.contains(&data_source) {
+            return Err(format!(
+                "the data source {} is not configured, see data_sources",
+                data_source
+            ));
+        }
+        Ok(RpcOptions {
+            data_source,
+            ..self.rpc_options.clone()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_select_among_the_configured_data_sources() {
+        let mut ctx = Context {
+            data_sources: vec![DataSourceKind::Dir],
+            ..Default::default()
+        };
+        let data_source = |ctx: &Context, hint| ctx.rpc_options_for(hint).map(|o| o.data_source);
+        assert_eq!(data_source(&ctx, None), Ok(DataSourceKind::Rpc));
+        assert_eq!(
+            data_source(&ctx, Some(DataSourceKind::Dir)),
+            Ok(DataSourceKind::Dir)
+        );
+        assert_eq!(
+            data_source(&ctx, Some(DataSourceKind::Rpc)),
+            Ok(DataSourceKind::Rpc)
+        );
+
+        ctx.data_sources.clear();
+        let err = data_source(&ctx, Some(DataSourceKind::Dir)).unwrap_err();
+        assert!(err.contains("not configured"), "{}", err);
+    }
 }
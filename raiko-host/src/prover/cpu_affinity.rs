@@ -0,0 +1,100 @@
+//! Pins the guest process to a set of CPU cores, away from the I/O threads.
+use std::{fmt, str::FromStr};
+
+use serde_with::{DeserializeFromStr, SerializeDisplay};
+use tokio::process::Command;
+
+/// CPU cores given as a comma separated list, e.g. `2,3`.
+#[derive(Debug, Clone, PartialEq, Eq, DeserializeFromStr, SerializeDisplay)]
+pub struct CpuSet(Vec<usize>);
+
+impl FromStr for CpuSet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut cores = s
+            .split(',')
+            .map(|core| {
+                core.trim()
+                    .parse::<usize>()
+                    .map_err(|e| format!("invalid core {:?} in {:?}: {}", core, s, e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        cores.sort_unstable();
+        cores.dedup();
+        Ok(CpuSet(cores))
+    }
+}
+
+impl fmt::Display for CpuSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cores: Vec<String> = self.0.iter().map(ToString::to_string).collect();
+        write!(f, "{}", cores.join(","))
+    }
+}
+
+impl CpuSet {
+    /// Checks that all the cores exist on this machine, counting the isolated ones.
+    pub fn validate(&self) -> Result<(), String> {
+        #[cfg(target_os = "linux")]
+        {
+            // SAFETY: sysconf has no preconditions
+            let configured = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_CONF) };
+            let max_cores = (libc::CPU_SETSIZE as usize).min(configured.max(0) as usize);
+            if let Some(core) = self.0.iter().find(|&&core| core >= max_cores) {
+                return Err(format!(
+                    "core {} does not exist, the machine has {} cores",
+                    core, max_cores
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Makes `cmd` start on these cores. The enclave threads of the guest inherit the
+    /// affinity of its process. Does nothing on other systems than Linux.
+    pub fn apply(&self, cmd: &mut Command) {
+        #[cfg(target_os = "linux")]
+        {
+            let cores = self.0.clone();
+            // SAFETY: only calls async-signal-safe functions between fork and exec
+            unsafe {
+                cmd.pre_exec(move || {
+                    let mut set: libc::cpu_set_t = std::mem::zeroed();
+                    for &core in &cores {
+                        libc::CPU_SET(core, &mut set);
+                    }
+                    let size = std::mem::size_of::<libc::cpu_set_t>();
+                    if libc::sched_setaffinity(0, size, &set) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    Ok(())
+                });
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = cmd;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_cpu_set() {
+        let cpus: CpuSet = "3, 2,3".parse().unwrap();
+        assert_eq!(cpus, CpuSet(vec![2, 3]));
+        assert_eq!(cpus.to_string(), "2,3");
+        assert!("".parse::<CpuSet>().is_err());
+        assert!("2,x".parse::<CpuSet>().is_err());
+        assert!("-1".parse::<CpuSet>().is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn validate_rejects_missing_cores() {
+        assert!(CpuSet(vec![0]).validate().is_ok());
+        assert!(CpuSet(vec![libc::CPU_SETSIZE as usize]).validate().is_err());
+    }
+}
@@ -0,0 +1,262 @@
+//! Caps the combined disk footprint of the logs, the RPC cache and the persisted proofs,
+//! evicting the rotated logs first, then the cache files of the blocks not being proven,
+//! then the oldest proofs.
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use tracing::{info, warn};
+
+use super::{cache_pins::CachePins, utils::is_cache_file_name};
+use crate::metrics::observe_disk_usage;
+
+/// Usage, in percents of the cap, from which files are evicted.
+const HIGH_WATERMARK: u64 = 95;
+/// Usage, in percents of the cap, the eviction brings the footprint down to.
+const LOW_WATERMARK: u64 = 90;
+
+/// What a file holds, in the order the files are evicted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FileClass {
+    RotatedLog,
+    CacheEntry,
+    Proof,
+}
+
+#[derive(Debug)]
+struct DiskFile {
+    class: FileClass,
+    modified: SystemTime,
+    path: PathBuf,
+    size: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiskUsage {
+    pub used_bytes: u64,
+    pub headroom_bytes: u64,
+}
+
+#[derive(Debug)]
+pub struct DiskBudget {
+    max_bytes: u64,
+    log_dir: Option<PathBuf>,
+    cache_dir: PathBuf,
+    proof_dir: Option<PathBuf>,
+    /// blocks being proven, whose cache files are never evicted
+    pins: CachePins,
+}
+
+impl DiskBudget {
+    pub fn new(
+        max_bytes: u64,
+        log_dir: Option<PathBuf>,
+        cache_dir: PathBuf,
+        proof_dir: Option<PathBuf>,
+        pins: CachePins,
+    ) -> Self {
+        DiskBudget {
+            max_bytes,
+            log_dir,
+            cache_dir,
+            proof_dir,
+            pins,
+        }
+    }
+
+    /// Evicts files while the footprint is above the high watermark, down to the low
+    /// watermark, and reports the footprint left.
+    pub fn enforce(&self) -> io::Result<DiskUsage> {
+        let (mut files, mut used_bytes) = self.list_files()?;
+        if used_bytes * 100 > self.max_bytes * HIGH_WATERMARK {
+            let target = self.max_bytes * LOW_WATERMARK / 100;
+            files.sort_by_key(|file| (file.class, file.modified));
+            let mut evicted = 0;
+            for file in files {
+                if used_bytes <= target {
+                    break;
+                }
+                match fs::remove_file(&file.path) {
+                    Ok(()) => {
+                        used_bytes -= file.size;
+                        evicted += 1;
+                    }
+                    // removed by its subsystem in the meantime
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => used_bytes -= file.size,
+                    Err(err) => warn!("Failed to evict {:?}: {}", file.path, err),
+                }
+            }
+            info!(
+                "Evicted {} files to stay within max_disk_bytes, {} bytes used",
+                evicted, used_bytes
+            );
+        }
+        let usage = DiskUsage {
+            used_bytes,
+            headroom_bytes: self.max_bytes.saturating_sub(used_bytes),
+        };
+        observe_disk_usage(usage.used_bytes, usage.headroom_bytes);
+        Ok(usage)
+    }
+
+    /// Lists the evictable files, with the footprint of all the files.
+    fn list_files(&self) -> io::Result<(Vec<DiskFile>, u64)> {
+        let mut files = vec![];
+        let mut used_bytes = 0;
+
+        if let Some(log_dir) = &self.log_dir {
+            let mut logs = list_dir(log_dir, |name| name.starts_with("raiko.log"))?;
+            used_bytes += logs.iter().map(|(_, _, size)| size).sum::<u64>();
+            // the most recent log is the one being written
+            logs.sort();
+            logs.pop();
+            files.extend(logs.into_iter().map(|(modified, path, size)| DiskFile {
+                class: FileClass::RotatedLog,
+                modified,
+                path,
+                size,
+            }));
+        }
+
+        for (modified, path, size) in list_dir(&self.cache_dir, is_cache_file_name)? {
+            used_bytes += size;
+            let block = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.split('.').next())
+                .and_then(|block| block.parse::<u64>().ok());
+            if block.is_some_and(|block| !self.pins.is_pinned(block)) {
+                files.push(DiskFile {
+                    class: FileClass::CacheEntry,
+                    modified,
+                    path,
+                    size,
+                });
+            }
+        }
+
+        if let Some(proof_dir) = &self.proof_dir {
+            let proofs = list_dir(proof_dir, |name| name.ends_with(".json"))?;
+            used_bytes += proofs.iter().map(|(_, _, size)| size).sum::<u64>();
+            files.extend(proofs.into_iter().map(|(modified, path, size)| DiskFile {
+                class: FileClass::Proof,
+                modified,
+                path,
+                size,
+            }));
+        }
+        Ok((files, used_bytes))
+    }
+}
+
+/// The files of `dir` whose name passes `filter`, with their modification time and size.
+/// A missing directory has no files.
+fn list_dir(
+    dir: &Path,
+    filter: impl Fn(&str) -> bool,
+) -> io::Result<Vec<(SystemTime, PathBuf, u64)>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(err),
+    };
+    let mut files = vec![];
+    for entry in entries {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        let is_match = entry.file_name().to_str().is_some_and(&filter);
+        if metadata.is_file() && is_match {
+            files.push((metadata.modified()?, entry.path(), metadata.len()));
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, time::Duration};
+
+    use super::*;
+
+    /// Writes `size` bytes to `dir/name`, last modified `age` seconds ago.
+    fn write_file(dir: &Path, name: &str, size: usize, age: u64) {
+        fs::create_dir_all(dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, vec![0; size]).unwrap();
+        let modified = SystemTime::now() - Duration::from_secs(age);
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+    }
+
+    fn exists(dir: &Path, name: &str) -> bool {
+        dir.join(name).exists()
+    }
+
+    #[test]
+    fn evicts_logs_then_unpinned_cache_entries_then_oldest_proofs() {
+        let root = tempfile::tempdir().unwrap();
+        let (logs, cache, proofs) = (
+            root.path().join("logs"),
+            root.path().join("cache"),
+            root.path().join("proofs"),
+        );
+        write_file(&logs, "raiko.log.2024-01-01", 100, 300);
+        write_file(&logs, "raiko.log.2024-01-02", 100, 200);
+        write_file(&logs, "raiko.log.2024-01-03", 100, 10);
+        write_file(&cache, "5.l1.json.gz", 100, 500);
+        write_file(&cache, "5.l2.json.gz", 100, 500);
+        write_file(&cache, "6.l1.json.gz", 100, 400);
+        write_file(&cache, "6.l2.json.gz", 100, 400);
+        write_file(&proofs, "sgx-1-5.json", 100, 1000);
+        write_file(&proofs, "sgx-1-6.json", 100, 100);
+
+        let pins = CachePins::default();
+        let budget = |max_bytes| {
+            DiskBudget::new(
+                max_bytes,
+                Some(logs.clone()),
+                cache.clone(),
+                Some(proofs.clone()),
+                pins.clone(),
+            )
+        };
+
+        // within the cap, nothing is evicted
+        let usage = budget(2_000).enforce().unwrap();
+        assert_eq!(usage.used_bytes, 900);
+        assert_eq!(usage.headroom_bytes, 1_100);
+
+        // the rotated logs go first, never the current one
+        assert_eq!(budget(800).enforce().unwrap().used_bytes, 700);
+        assert!(!exists(&logs, "raiko.log.2024-01-01"));
+        assert!(!exists(&logs, "raiko.log.2024-01-02"));
+        assert!(exists(&logs, "raiko.log.2024-01-03"));
+
+        // then the cache entries of the blocks not being proven
+        let pin = pins.pin(5);
+        assert_eq!(budget(600).enforce().unwrap().used_bytes, 500);
+        assert!(exists(&cache, "5.l1.json.gz") && exists(&cache, "5.l2.json.gz"));
+        assert!(!exists(&cache, "6.l1.json.gz") && !exists(&cache, "6.l2.json.gz"));
+
+        // then the oldest proofs
+        let pin_again = pins.pin(5);
+        assert_eq!(budget(500).enforce().unwrap().used_bytes, 400);
+        assert!(!exists(&proofs, "sgx-1-5.json"));
+        assert!(exists(&proofs, "sgx-1-6.json"));
+
+        // the cache files are evictable once the jobs are done
+        drop(pin);
+        drop(pin_again);
+        assert!(!pins.is_pinned(5));
+        assert_eq!(budget(300).enforce().unwrap().used_bytes, 200);
+        assert!(!exists(&cache, "5.l1.json.gz") && !exists(&cache, "5.l2.json.gz"));
+        assert!(exists(&proofs, "sgx-1-6.json"));
+        assert!(exists(&logs, "raiko.log.2024-01-03"));
+    }
+}
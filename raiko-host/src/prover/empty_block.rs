@@ -0,0 +1,86 @@
+//! What is done with the empty blocks, holding no transaction but their anchor: some
+//! pipelines spare their proofs, others need a proof of every block.
+use std::{fmt, str::FromStr};
+
+use serde_with::{DeserializeFromStr, SerializeDisplay};
+
+/// What is done with an empty block, given as `prove`, `skip` or `error`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, DeserializeFromStr, SerializeDisplay)]
+pub enum EmptyBlockPolicy {
+    /// proven as the other blocks
+    #[default]
+    Prove,
+    /// answered with a skipped result, not an error, for the client to record it
+    Skip,
+    /// rejected
+    Error,
+}
+
+impl FromStr for EmptyBlockPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "prove" => Ok(EmptyBlockPolicy::Prove),
+            "skip" => Ok(EmptyBlockPolicy::Skip),
+            "error" => Ok(EmptyBlockPolicy::Error),
+            _ => Err(format!(
+                "invalid empty block policy {:?}, expected prove, skip or error",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for EmptyBlockPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmptyBlockPolicy::Prove => write!(f, "prove"),
+            EmptyBlockPolicy::Skip => write!(f, "skip"),
+            EmptyBlockPolicy::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Why a block was not proven.
+pub const EMPTY_BLOCK_REASON: &str = "empty block";
+
+impl EmptyBlockPolicy {
+    /// Whether `block`, of `transactions` transactions with its anchor, is skipped. Fails
+    /// when it is empty and rejected.
+    pub fn skips(&self, block: u64, transactions: usize) -> Result<bool, String> {
+        // the anchor opens every block
+        if transactions > 1 {
+            return Ok(false);
+        }
+        match self {
+            EmptyBlockPolicy::Prove => Ok(false),
+            EmptyBlockPolicy::Skip => Ok(true),
+            EmptyBlockPolicy::Error => Err(format!(
+                "block {} is empty, it holds no transaction but its anchor, see on_empty_block",
+                block
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_blocks_are_handled_per_policy() {
+        let parse = |s: &str| s.parse::<EmptyBlockPolicy>().unwrap();
+        // the anchor only
+        assert_eq!(parse("prove").skips(7, 1), Ok(false));
+        assert_eq!(parse("skip").skips(7, 1), Ok(true));
+        let err = parse("error").skips(7, 1).unwrap_err();
+        assert!(err.contains("block 7 is empty"), "{}", err);
+        // the blocks with transactions are always proven
+        for policy in ["prove", "skip", "error"] {
+            assert_eq!(parse(policy).skips(8, 2), Ok(false));
+            assert_eq!(parse(policy).to_string(), policy);
+        }
+        assert!("drop".parse::<EmptyBlockPolicy>().is_err());
+    }
+}
@@ -0,0 +1,115 @@
+//! Admission of the proofs by their estimated enclave memory: the enclaves of a box share
+//! its EPC, and the proofs of the large blocks run together thrash it far below the
+//! concurrency limit the small ones run fine at. A proof waits for the proofs running to
+//! free enough memory for the projected total to stay under `max_total_enclave_mb`, on
+//! top of the concurrency limit.
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::Notify;
+
+use crate::metrics::observe_enclave_memory;
+
+/// The estimated enclave memory of the proofs admitted, kept under `max_mb`.
+#[derive(Debug)]
+pub struct EnclaveMemoryLimit {
+    max_mb: u64,
+    admitted_mb: Mutex<u64>,
+    freed: Notify,
+}
+
+/// The memory of an admitted proof, freed when dropped.
+#[derive(Debug)]
+pub struct EnclaveMemoryPermit {
+    limit: Arc<EnclaveMemoryLimit>,
+    mb: u64,
+}
+
+impl Drop for EnclaveMemoryPermit {
+    fn drop(&mut self) {
+        let mut admitted_mb = self.limit.admitted_mb.lock().unwrap();
+        *admitted_mb -= self.mb;
+        observe_enclave_memory(*admitted_mb, self.limit.max_mb);
+        drop(admitted_mb);
+        self.limit.freed.notify_waiters();
+    }
+}
+
+impl EnclaveMemoryLimit {
+    pub fn new(max_mb: u64) -> Arc<Self> {
+        observe_enclave_memory(0, max_mb);
+        Arc::new(EnclaveMemoryLimit {
+            max_mb,
+            admitted_mb: Mutex::new(0),
+            freed: Notify::new(),
+        })
+    }
+
+    /// The estimated memory of the proofs admitted.
+    pub fn admitted_mb(&self) -> u64 {
+        *self.admitted_mb.lock().unwrap()
+    }
+
+    /// Admits a proof of `mb` once the projected total stays under the limit. A proof
+    /// over the limit on its own is admitted when no other runs.
+    pub async fn admit(self: &Arc<Self>, mb: u64) -> EnclaveMemoryPermit {
+        loop {
+            // registered before checking, not to miss the memory freed in between
+            let freed = self.freed.notified();
+            tokio::pin!(freed);
+            freed.as_mut().enable();
+            {
+                let mut admitted_mb = self.admitted_mb.lock().unwrap();
+                if *admitted_mb == 0 || *admitted_mb + mb <= self.max_mb {
+                    *admitted_mb += mb;
+                    observe_enclave_memory(*admitted_mb, self.max_mb);
+                    return EnclaveMemoryPermit {
+                        limit: self.clone(),
+                        mb,
+                    };
+                }
+            }
+            freed.await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn large_proofs_wait_for_the_enclave_memory_to_be_freed() {
+        let limit = EnclaveMemoryLimit::new(1000);
+        let running = limit.admit(600).await;
+        let small = limit.admit(300).await;
+        assert_eq!(limit.admitted_mb(), 900);
+
+        // the large block would go over the limit
+        let large = tokio::spawn({
+            let limit = limit.clone();
+            async move { limit.admit(700).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!large.is_finished());
+        drop(small);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!large.is_finished());
+
+        // admitted once the running proof frees its memory
+        drop(running);
+        let large = tokio::time::timeout(Duration::from_secs(1), large)
+            .await
+            .expect("admitted")
+            .unwrap();
+        assert_eq!(limit.admitted_mb(), 700);
+        drop(large);
+        assert_eq!(limit.admitted_mb(), 0);
+
+        // a proof over the limit on its own still runs, alone
+        let oversized = limit.admit(1500).await;
+        assert_eq!(limit.admitted_mb(), 1500);
+        drop(oversized);
+    }
+}
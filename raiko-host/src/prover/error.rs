@@ -1,11 +1,17 @@
 use std::fmt;
 
+use zeth_lib::taiko::blob::BlobFetchError;
+
 #[derive(Debug)]
 pub enum Error {
     Io(std::io::Error),
     Anyhow(anyhow::Error),
+    /// The blob of a block could not be fetched or verified.
+    BlobFetch(anyhow::Error),
     Serde(serde_json::Error),
     JoinHandle(tokio::task::JoinError),
+    /// The proof still did not verify after all the retries of verify_before_return.
+    SelfVerifyFailed(String),
     String(String),
 }
 
@@ -14,8 +20,10 @@ impl fmt::Display for Error {
         match self {
             Error::Io(e) => e.fmt(f),
             Error::Anyhow(e) => e.fmt(f),
+            Error::BlobFetch(e) => write!(f, "blob fetch failed: {:#}", e),
             Error::Serde(e) => e.fmt(f),
             Error::JoinHandle(e) => e.fmt(f),
+            Error::SelfVerifyFailed(e) => write!(f, "the proof failed to self-verify: {}", e),
             Error::String(e) => e.fmt(f),
         }
     }
@@ -29,7 +37,11 @@ impl From<std::io::Error> for Error {
 
 impl From<anyhow::Error> for Error {
     fn from(e: anyhow::Error) -> Self {
-        Error::Anyhow(e)
+        if e.chain().any(|cause| cause.is::<BlobFetchError>()) {
+            Error::BlobFetch(e)
+        } else {
+            Error::Anyhow(e)
+        }
     }
 }
 
@@ -0,0 +1,370 @@
+//! Estimates of the proving time of a block before proving it, for a scheduler to pack
+//! the blocks onto the provers: a linear model of the transactions, the gas and the
+//! accounts touched by the block, calibrated by the ratio of the actual proving times of
+//! the recent proofs to the modeled ones. The enclave memory of a proof is estimated from
+//! the size of the block data the guest loads.
+use std::{collections::VecDeque, fmt, fs, str::FromStr, sync::Mutex, time::Duration};
+
+use serde::Serialize;
+use serde_with::{DeserializeFromStr, SerializeDisplay};
+use zeth_lib::{host::Init, taiko::block_builder::TaikoStrategyBundle};
+use zeth_primitives::{transactions::TxEssence, Address, B256};
+
+use super::{
+    allowed_range::check_block_allowed,
+    context::Context,
+    error::Result,
+    prepare_input::prepare_input,
+    request::{ProofRequest, SgxRequest},
+    utils::cache_file_path,
+};
+
+/// How many recent proofs calibrate the estimates by default.
+pub const ESTIMATE_CALIBRATION_SAMPLES: usize = 100;
+
+/// The milliseconds a proof takes, given as `base_ms,tx_ms,mgas_ms,account_ms`: `base_ms`
+/// for every block, plus `tx_ms` per transaction, `mgas_ms` per million of gas used and
+/// `account_ms` per account touched.
+#[derive(Debug, Clone, Copy, PartialEq, DeserializeFromStr, SerializeDisplay)]
+pub struct EstimateModel {
+    pub base_ms: f64,
+    pub tx_ms: f64,
+    pub mgas_ms: f64,
+    pub account_ms: f64,
+}
+
+impl Default for EstimateModel {
+    fn default() -> Self {
+        EstimateModel {
+            base_ms: 20_000.0,
+            tx_ms: 50.0,
+            mgas_ms: 400.0,
+            account_ms: 30.0,
+        }
+    }
+}
+
+impl FromStr for EstimateModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let coefficients: Vec<f64> = s
+            .split(',')
+            .map(|coefficient| coefficient.trim().parse::<f64>())
+            .collect::<Result<_, _>>()
+            .ok()
+            .filter(|coefficients: &Vec<f64>| {
+                coefficients.iter().all(|c| c.is_finite() && *c >= 0.0)
+            })
+            .ok_or_else(|| {
+                format!(
+                    "invalid estimate model {:?}, expected base_ms,tx_ms,mgas_ms,account_ms",
+                    s
+                )
+            })?;
+        let [base_ms, tx_ms, mgas_ms, account_ms] = coefficients[..] else {
+            return Err(format!(
+                "invalid estimate model {:?}, expected 4 coefficients, got {}",
+                s,
+                coefficients.len()
+            ));
+        };
+        Ok(EstimateModel {
+            base_ms,
+            tx_ms,
+            mgas_ms,
+            account_ms,
+        })
+    }
+}
+
+impl fmt::Display for EstimateModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{},{},{},{}",
+            self.base_ms, self.tx_ms, self.mgas_ms, self.account_ms
+        )
+    }
+}
+
+impl EstimateModel {
+    pub fn millis(&self, cost: &BlockCost) -> f64 {
+        self.base_ms
+            + self.tx_ms * cost.transactions as f64
+            + self.mgas_ms * cost.gas_used as f64 / 1e6
+            + self.account_ms * cost.touched_accounts as f64
+    }
+}
+
+/// The enclave memory of a proof, given as `base_mb,witness_factor`: `base_mb` for every
+/// proof, plus `witness_factor` times the size of the compressed block data it loads.
+#[derive(Debug, Clone, Copy, PartialEq, DeserializeFromStr, SerializeDisplay)]
+pub struct EnclaveMemoryModel {
+    pub base_mb: f64,
+    pub witness_factor: f64,
+}
+
+impl Default for EnclaveMemoryModel {
+    fn default() -> Self {
+        EnclaveMemoryModel {
+            base_mb: 512.0,
+            witness_factor: 10.0,
+        }
+    }
+}
+
+impl FromStr for EnclaveMemoryModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid enclave memory model {:?}, expected base_mb,witness_factor",
+                s
+            )
+        };
+        let (base_mb, witness_factor) = s.split_once(',').ok_or_else(invalid)?;
+        let parse = |coefficient: &str| {
+            coefficient
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|c| c.is_finite() && *c >= 0.0)
+                .ok_or_else(invalid)
+        };
+        Ok(EnclaveMemoryModel {
+            base_mb: parse(base_mb)?,
+            witness_factor: parse(witness_factor)?,
+        })
+    }
+}
+
+impl fmt::Display for EnclaveMemoryModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{}", self.base_mb, self.witness_factor)
+    }
+}
+
+impl EnclaveMemoryModel {
+    pub fn mb(&self, witness_bytes: u64) -> u64 {
+        (self.base_mb + self.witness_factor * witness_bytes as f64 / (1 << 20) as f64).ceil() as u64
+    }
+}
+
+/// What the proving time of a block depends on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockCost {
+    /// the anchor included
+    pub transactions: u64,
+    pub gas_used: u64,
+    pub touched_accounts: u64,
+}
+
+impl BlockCost {
+    pub fn of<E: TxEssence>(init: &Init<E>) -> Self {
+        BlockCost {
+            transactions: init.fini_transactions.len() as u64,
+            gas_used: u64::try_from(init.fini_block.gas_used).unwrap_or(u64::MAX),
+            touched_accounts: init.fini_proofs.len() as u64,
+        }
+    }
+}
+
+/// The estimated proving time of a block, see `GET /prove/estimate`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Estimate {
+    pub block: u64,
+    #[serde(flatten)]
+    pub cost: BlockCost,
+    /// bytes of block data the guest loads, the L1 and L2 ones
+    pub witness_bytes: u64,
+    /// the enclave memory of the proof
+    pub enclave_mb: u64,
+    /// the time the model gives, calibrated
+    pub estimated_ms: u64,
+    /// the time the model gives, uncalibrated
+    pub model_ms: u64,
+    /// mean ratio of the actual proving times of the recent proofs to the modeled ones
+    pub calibration: f64,
+    /// the proofs the calibration is drawn from, none leaving it at 1
+    pub samples: usize,
+}
+
+/// The model and the ratios of the actual to the modeled proving times of the last
+/// `capacity` proofs.
+#[derive(Debug)]
+pub struct Estimator {
+    model: EstimateModel,
+    memory_model: EnclaveMemoryModel,
+    capacity: usize,
+    ratios: Mutex<VecDeque<f64>>,
+}
+
+impl Default for Estimator {
+    fn default() -> Self {
+        Estimator::new(
+            EstimateModel::default(),
+            EnclaveMemoryModel::default(),
+            ESTIMATE_CALIBRATION_SAMPLES,
+        )
+    }
+}
+
+impl Estimator {
+    pub fn new(model: EstimateModel, memory_model: EnclaveMemoryModel, capacity: usize) -> Self {
+        Estimator {
+            model,
+            memory_model,
+            capacity,
+            ratios: Default::default(),
+        }
+    }
+
+    /// The enclave memory of a proof loading `witness_bytes` of block data.
+    pub fn enclave_mb(&self, witness_bytes: u64) -> u64 {
+        self.memory_model.mb(witness_bytes)
+    }
+
+    /// Records the actual proving time of a block of `cost`.
+    pub fn record(&self, cost: &BlockCost, actual: Duration) {
+        let modeled = self.model.millis(cost);
+        if modeled <= 0.0 || self.capacity == 0 {
+            return;
+        }
+        let mut ratios = self.ratios.lock().unwrap();
+        ratios.push_back(actual.as_millis() as f64 / modeled);
+        while ratios.len() > self.capacity {
+            ratios.pop_front();
+        }
+    }
+
+    pub fn estimate(&self, block: u64, cost: BlockCost, witness_bytes: u64) -> Estimate {
+        let (calibration, samples) = {
+            let ratios = self.ratios.lock().unwrap();
+            match ratios.len() {
+                0 => (1.0, 0),
+                samples => (ratios.iter().sum::<f64>() / samples as f64, samples),
+            }
+        };
+        let modeled = self.model.millis(&cost);
+        Estimate {
+            block,
+            cost,
+            witness_bytes,
+            enclave_mb: self.enclave_mb(witness_bytes),
+            estimated_ms: (modeled * calibration).round() as u64,
+            model_ms: modeled.round() as u64,
+            calibration,
+            samples,
+        }
+    }
+}
+
+/// Fetches the data of `block` into the cache, as for its proof, and estimates the time
+/// of its proof.
+pub async fn estimate_block(
+    ctx: &Context,
+    block: u64,
+    l1_rpc: String,
+    l2_rpc: String,
+) -> Result<Estimate> {
+    check_block_allowed(&ctx.prove_allowed_ranges, &ctx.l2_chain, block)?;
+    let req = ProofRequest::Sgx(SgxRequest {
+        block,
+        l2_rpc,
+        l1_rpc,
+        // only used in the protocol instance, not in the cached data
+        prover: Address::ZERO,
+        graffiti: B256::ZERO,
+        timeout_secs: None,
+        data_source: None,
+        skip_cache: false,
+        state_overrides: None,
+        metadata: Default::default(),
+        deadline: None,
+    });
+    let _pin = ctx.cache_pins.pin(block);
+    let (init, _) = prepare_input::<TaikoStrategyBundle>(ctx, &req).await?;
+    Ok(ctx
+        .estimator
+        .estimate(block, BlockCost::of(&init), witness_bytes(ctx, block)))
+}
+
+/// The size of the cached block data of `block`, the L1 and L2 ones, as the guest loads
+/// them.
+pub fn witness_bytes(ctx: &Context, block: u64) -> u64 {
+    [true, false]
+        .into_iter()
+        .filter_map(|is_l1| {
+            fs::metadata(cache_file_path(
+                &ctx.cache_path,
+                ctx.l2_chain_id,
+                block,
+                is_l1,
+            ))
+            .ok()
+        })
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cost(transactions: u64, gas_used: u64, touched_accounts: u64) -> BlockCost {
+        BlockCost {
+            transactions,
+            gas_used,
+            touched_accounts,
+        }
+    }
+
+    #[test]
+    fn estimates_are_calibrated_by_the_actual_times() {
+        let model: EstimateModel = "10000,100,500,20".parse().unwrap();
+        assert_eq!(model.to_string(), "10000,100,500,20");
+        assert!("10000,100,500".parse::<EstimateModel>().is_err());
+        assert!("10000,100,500,-1".parse::<EstimateModel>().is_err());
+        let memory_model: EnclaveMemoryModel = "256,8".parse().unwrap();
+        assert_eq!(memory_model.to_string(), "256,8");
+        assert!("256".parse::<EnclaveMemoryModel>().is_err());
+        let estimator = Estimator::new(model, memory_model, 8);
+        let block = cost(101, 15_000_000, 250);
+        // 10000 + 100 * 101 + 500 * 15 + 20 * 250
+        let uncalibrated = estimator.estimate(7, block, 1 << 20);
+        assert_eq!(uncalibrated.estimated_ms, 32_600);
+        assert_eq!(uncalibrated.samples, 0);
+        // 256 + 8 * 1
+        assert_eq!(uncalibrated.enclave_mb, 264);
+
+        // the calibration blocks took about 1.5 times as long as modeled
+        let fixture = [
+            (cost(1, 21_000, 3), 15_600),
+            (cost(50, 5_000_000, 120), 31_500),
+            (cost(200, 28_000_000, 600), 83_000),
+            (cost(10, 800_000, 40), 18_300),
+            (cost(120, 14_000_000, 300), 54_000),
+        ];
+        for (cost, actual_ms) in fixture {
+            estimator.record(&cost, Duration::from_millis(actual_ms));
+        }
+        let estimate = estimator.estimate(7, block, 1 << 20);
+        assert_eq!(estimate.samples, fixture.len());
+        assert_eq!(estimate.model_ms, 32_600);
+        let expected = 32_600.0 * 1.5;
+        let error = (estimate.estimated_ms as f64 - expected).abs() / expected;
+        assert!(error < 0.1, "{:?}", estimate);
+
+        // the oldest samples are dropped
+        for _ in 0..8 {
+            estimator.record(&block, Duration::from_millis(32_600));
+        }
+        let estimate = estimator.estimate(7, block, 1 << 20);
+        assert_eq!(estimate.samples, 8);
+        assert_eq!(estimate.estimated_ms, 32_600);
+    }
+}
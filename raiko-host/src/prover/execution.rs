@@ -1,35 +1,600 @@
-use std::time::Instant;
+use std::{
+    collections::BTreeSet,
+    future::Future,
+    time::{Duration, Instant},
+};
 
+use anyhow::{bail, Context as _};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
 use zeth_lib::taiko::block_builder::TaikoStrategyBundle;
 
 use super::{
+    allowed_range::check_block_allowed,
     context::Context,
-    error::Result,
+    empty_block::EMPTY_BLOCK_REASON,
+    error::{Error, Result},
+    estimate::{witness_bytes, BlockCost},
     prepare_input::prepare_input,
-    proof::{cache::Cache, sgx::execute_sgx},
-    request::{ProofRequest, ProofResponse},
+    proof::{
+        cache::{Cache, CacheKey},
+        sgx::execute_sgx,
+        ProofType,
+    },
+    request::{
+        MultiResponse, ProofRequest, ProofResponse, SgxRequest, SgxResponse, SkippedResponse,
+    },
+    reverify::SampledProof,
+    sink::{fan_out, ProofResult},
 };
-use crate::metrics::{inc_sgx_success, observe_input, observe_sgx_gen};
+use crate::metrics::{inc_cache_bypass, inc_sgx_success, observe_input, observe_sgx_gen};
 // use crate::rolling::prune_old_caches;
 
-pub async fn execute(_cache: &Cache, ctx: &Context, req: &ProofRequest) -> Result<ProofResponse> {
+pub async fn execute(cache: &Cache, ctx: &Context, req: &ProofRequest) -> Result<ProofResponse> {
+    let skip_cache = match req {
+        ProofRequest::Sgx(req) => req.skip_cache,
+        ProofRequest::Multi(req) => req.block.skip_cache,
+        ProofRequest::PseZk(_) => false,
+    };
+    if skip_cache {
+        info!("Skipping the cached block data and proofs of the request");
+        inc_cache_bypass();
+    }
+    // the proofs of an overridden pre-state are not the ones of the block
+    let state_overridden = match req {
+        ProofRequest::Sgx(req) => req.state_overrides.is_some(),
+        ProofRequest::Multi(req) => req.block.state_overrides.is_some(),
+        ProofRequest::PseZk(_) => false,
+    };
+    if state_overridden {
+        if !ctx.allow_consensus_overrides {
+            return Err("state_overrides requires allow_consensus_overrides"
+                .to_owned()
+                .into());
+        }
+        warn!("TESTING ONLY: proving against overridden state, the proof will not verify");
+    }
+    // 0. pre-flight checks, before anything is fetched
+    let cache_key = match req {
+        ProofRequest::Sgx(req) => {
+            check_block_allowed(&ctx.prove_allowed_ranges, &ctx.l2_chain, req.block)?;
+            req.check_metadata()?;
+            // the metadata is no part of the proof, nor of its cache key
+            let cache_key = sgx_cache_key(ctx, req);
+            if let Some(proof) = cache
+                .get(&cache_key)
+                .filter(|_| !skip_cache && !is_overridden(ctx, req))
+            {
+                return Ok(ProofResponse::Sgx(SgxResponse {
+                    proof,
+                    envelope: None,
+                    chain_spec_mismatch: None,
+                    benchmark: false,
+                    metadata: req.metadata.clone(),
+                }));
+            }
+            Some(cache_key)
+        }
+        ProofRequest::Multi(req) => {
+            check_block_allowed(&ctx.prove_allowed_ranges, &ctx.l2_chain, req.block.block)?;
+            req.block.check_metadata()?;
+            if req.proof_types.is_empty() {
+                return Err("expected at least one proof type".to_owned().into());
+            }
+            None
+        }
+        ProofRequest::PseZk(_) => None,
+    };
+    // the witness is only built when some proof is not cached
+    let cached_sgx = match req {
+        ProofRequest::Multi(req)
+            if !skip_cache
+                && !is_overridden(ctx, &req.block)
+                && req.proof_types.contains(&ProofType::Sgx) =>
+        {
+            cache.get(&sgx_cache_key(ctx, &req.block))
+        }
+        _ => None,
+    };
+    let needs_witness = match req {
+        ProofRequest::Multi(req) => {
+            cached_sgx.is_none() && req.proof_types.contains(&ProofType::Sgx)
+        }
+        _ => true,
+    };
+    // the cache files of the block are kept until the proofs are done
+    let block = match req {
+        ProofRequest::Sgx(req) => Some(req.block),
+        ProofRequest::Multi(req) => Some(req.block.block),
+        ProofRequest::PseZk(_) => None,
+    };
+    let _pin = block.map(|block| ctx.cache_pins.pin(block));
     // 1. load input data into cache path
-    let start = Instant::now();
-    let _ = prepare_input::<TaikoStrategyBundle>(ctx, req).await?;
-    let elapsed = Instant::now().duration_since(start).as_millis() as i64;
-    observe_input(elapsed);
+    let mut cost = None;
+    // held until the proofs are done
+    let mut _enclave_memory = None;
+    if needs_witness {
+        let start = Instant::now();
+        let (init, _) = prepare_input::<TaikoStrategyBundle>(ctx, req).await?;
+        let elapsed = Instant::now().duration_since(start).as_millis() as i64;
+        observe_input(elapsed);
+        // the last check, once the transactions are known
+        if let Some(block) = block {
+            if ctx
+                .on_empty_block
+                .skips(block, init.fini_transactions.len())?
+            {
+                info!("Skipping the proof of block {}, which is empty", block);
+                let metadata = match req {
+                    ProofRequest::Sgx(req) => req.metadata.clone(),
+                    ProofRequest::Multi(req) => req.block.metadata.clone(),
+                    ProofRequest::PseZk(_) => Default::default(),
+                };
+                return Ok(ProofResponse::Skipped(SkippedResponse {
+                    block,
+                    reason: EMPTY_BLOCK_REASON.to_owned(),
+                    metadata,
+                }));
+            }
+        }
+        cost = Some(BlockCost::of(&init));
+        if let (Some(limit), Some(block)) = (&ctx.enclave_memory, block) {
+            let mb = ctx.estimator.enclave_mb(witness_bytes(ctx, block));
+            _enclave_memory = Some(limit.admit(mb).await);
+        }
+    }
     // 2. run proof
     // prune_old_caches(&ctx.cache_path, ctx.max_caches);
     match req {
         ProofRequest::Sgx(req) => {
-            let start = Instant::now();
-            let bid = req.block.clone();
-            let resp = execute_sgx(ctx, req).await?;
-            let time_elapsed = Instant::now().duration_since(start).as_millis() as i64;
-            observe_sgx_gen(bid, time_elapsed);
-            inc_sgx_success(bid);
+            let resp = prove_sgx(cache, ctx, req, cache_key, cost).await?;
             Ok(ProofResponse::Sgx(resp))
         }
+        ProofRequest::Multi(req) => {
+            let mut resp = prove_each(&req.proof_types, |proof_type| {
+                let cached_sgx = cached_sgx.clone();
+                async move {
+                    match proof_type {
+                        ProofType::Sgx => match cached_sgx {
+                            Some(proof) => Ok(proof),
+                            None => {
+                                let cache_key = Some(sgx_cache_key(ctx, &req.block));
+                                prove_sgx(cache, ctx, &req.block, cache_key, cost)
+                                    .await
+                                    .map(|resp| resp.proof)
+                                    .map_err(|e| e.to_string())
+                            }
+                        },
+                        ProofType::PseZk => Err("the pse_zk prover is not available".to_owned()),
+                    }
+                }
+            })
+            .await;
+            resp.metadata = req.block.metadata.clone();
+            Ok(ProofResponse::Multi(resp))
+        }
         ProofRequest::PseZk(_) => todo!(),
     }
 }
+
+fn sgx_cache_key(ctx: &Context, req: &SgxRequest) -> CacheKey {
+    CacheKey {
+        proof_type: ProofType::Sgx,
+        chain_id: ctx.chain_id_override.unwrap_or(ctx.l2_chain_id),
+        block: req.block,
+        prover: req.prover,
+        graffiti: req.graffiti,
+    }
+}
+
+/// Whether the proofs of `req` are made against overridden consensus values, system
+/// contracts or pre-state, which are not the proofs of the block: they are neither cached
+/// nor delivered.
+fn is_overridden(ctx: &Context, req: &SgxRequest) -> bool {
+    req.state_overrides.is_some()
+        || !ctx.consensus_overrides.is_empty()
+        || ctx.system_overrides.is_some()
+}
+
+/// Runs the sgx guest on the prepared input, caching the proof under `cache_key` and
+/// delivering it to the sinks. The time of the proof calibrates the estimates of the
+/// blocks of `cost`.
+async fn prove_sgx(
+    cache: &Cache,
+    ctx: &Context,
+    req: &SgxRequest,
+    cache_key: Option<CacheKey>,
+    cost: Option<BlockCost>,
+) -> Result<SgxResponse> {
+    let start = Instant::now();
+    let bid = req.block.clone();
+    let resp = match ctx.self_verify_retries {
+        Some(retries) => {
+            prove_self_verified(retries, || execute_sgx(ctx, req), verify_sgx_response).await?
+        }
+        None => execute_sgx(ctx, req).await?,
+    };
+    let time_elapsed = Instant::now().duration_since(start).as_millis() as i64;
+    observe_sgx_gen(bid, time_elapsed);
+    if let Some(cost) = cost {
+        ctx.estimator
+            .record(&cost, Duration::from_millis(time_elapsed as u64));
+    }
+    inc_sgx_success(bid);
+    keep_proof(cache, ctx, req, cache_key, &resp.proof);
+    if let (Some(reverifier), Some(envelope)) = (&ctx.reverifier, &resp.envelope) {
+        reverifier.sample(SampledProof {
+            block: req.block,
+            proof: resp.proof.clone(),
+            envelope: envelope.clone(),
+        });
+    }
+    if let Some(disk_budget) = ctx.disk_budget.clone() {
+        tokio::task::spawn_blocking(move || {
+            if let Err(err) = disk_budget.enforce() {
+                warn!("Failed to check the disk usage: {}", err);
+            }
+        });
+    }
+    Ok(SgxResponse {
+        metadata: req.metadata.clone(),
+        ..resp
+    })
+}
+
+/// Caches the proof of `req` under `cache_key` and delivers it to the sinks, unless it
+/// is overridden. Returns the delivery to the sinks, if any.
+fn keep_proof(
+    cache: &Cache,
+    ctx: &Context,
+    req: &SgxRequest,
+    cache_key: Option<CacheKey>,
+    proof: &str,
+) -> Option<JoinHandle<Vec<anyhow::Error>>> {
+    if is_overridden(ctx, req) {
+        return None;
+    }
+    // an empty proof means the guest output had no proof line
+    if let Some(cache_key) = cache_key.filter(|_| !proof.is_empty()) {
+        cache.set(cache_key, proof.to_owned());
+    }
+    if ctx.sinks.is_empty() {
+        return None;
+    }
+    let result = ProofResult {
+        chain: ctx.l2_chain.clone(),
+        chain_id: ctx.chain_id_override.unwrap_or(ctx.l2_chain_id),
+        block: req.block,
+        prover: req.prover,
+        graffiti: req.graffiti,
+        proof: proof.to_owned(),
+        metadata: req.metadata.clone(),
+    };
+    Some(tokio::spawn(fan_out(ctx.sinks.clone(), result)))
+}
+
+/// Proves until the proof passes `verify`, proving again up to `retries` times, for the
+/// nondeterministic failures of the prover.
+async fn prove_self_verified<F, Fut, V>(
+    retries: u32,
+    mut prove: F,
+    verify: V,
+) -> Result<SgxResponse>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<SgxResponse>>,
+    V: Fn(&SgxResponse) -> anyhow::Result<()>,
+{
+    let mut attempt = 0;
+    loop {
+        let resp = prove().await?;
+        match verify(&resp) {
+            Ok(()) => return Ok(resp),
+            Err(err) if attempt < retries => {
+                attempt += 1;
+                warn!(
+                    "The proof failed to self-verify, proving again ({}/{}): {:#}",
+                    attempt, retries, err
+                );
+            }
+            Err(err) => return Err(Error::SelfVerifyFailed(format!("{:#}", err))),
+        }
+    }
+}
+
+/// Checks that the proof is the one of the envelope, and that both are signed by the
+/// instance over the public inputs.
+fn verify_sgx_response(resp: &SgxResponse) -> anyhow::Result<()> {
+    let envelope = resp
+        .envelope
+        .as_ref()
+        .context("the guest returned no proof envelope")?;
+    let proof = hex::decode(resp.proof.trim_start_matches("0x"))?;
+    if proof[..] != envelope.proof[..] {
+        bail!("the envelope holds another proof");
+    }
+    envelope.verify_sgx_proof()
+}
+
+/// Proves with each of the proof types, once per type. A failing proof type doesn't fail
+/// the others, its error is returned next to their proofs.
+async fn prove_each<F, Fut>(proof_types: &[ProofType], prove: F) -> MultiResponse
+where
+    F: Fn(ProofType) -> Fut,
+    Fut: Future<Output = Result<String, String>>,
+{
+    let mut resp = MultiResponse::default();
+    for proof_type in proof_types.iter().cloned().collect::<BTreeSet<_>>() {
+        match prove(proof_type.clone()).await {
+            Ok(proof) => resp.proofs.insert(proof_type, proof),
+            Err(err) => resp.errors.insert(proof_type, err),
+        };
+    }
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use ethers_core::{k256::ecdsa::SigningKey, utils::secret_key_to_address};
+    use serde_json::json;
+    use zeth_lib::consts::ConsensusOverrides;
+    use zeth_primitives::{signature::TxSignature, taiko::ProofEnvelope, Address, B256, U256};
+
+    use super::*;
+    use crate::prover::{
+        request::MAX_METADATA_BYTES,
+        sink::{OutputSharding, SinkHandle},
+    };
+
+    /// The response of a guest signing `signed` into the proof of `public_inputs`.
+    fn sgx_response(public_inputs: B256, signed: B256) -> SgxResponse {
+        let key = SigningKey::from_slice(&[7u8; 32]).unwrap();
+        let instance = secret_key_to_address(&key);
+        let sign = |hash: B256| {
+            let (signature, recovery_id) = key.sign_prehash_recoverable(hash.as_slice()).unwrap();
+            let mut signature = signature.to_bytes().to_vec();
+            signature.push(recovery_id.to_byte() + 27);
+            signature
+        };
+        let mut proof = 1u32.to_be_bytes().to_vec();
+        proof.extend(instance.as_bytes());
+        proof.extend(sign(signed));
+        let timestamp = 1_700_000_000;
+        let signature = sign(ProofEnvelope::signing_hash(
+            &proof,
+            public_inputs,
+            timestamp,
+        ));
+        SgxResponse {
+            proof: format!("0x{}", hex::encode(&proof)),
+            envelope: Some(ProofEnvelope {
+                proof: proof.into(),
+                public_inputs,
+                timestamp,
+                signer: Address::from_slice(instance.as_bytes()),
+                signature: TxSignature {
+                    r: U256::from_be_slice(&signature[..32]),
+                    s: U256::from_be_slice(&signature[32..64]),
+                    v: signature[64] as u64,
+                },
+            }),
+            chain_spec_mismatch: None,
+            benchmark: false,
+            metadata: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn proof_failing_to_self_verify_is_proven_again() {
+        let public_inputs = B256::repeat_byte(0x11);
+        let valid = sgx_response(public_inputs, public_inputs);
+        let invalid = sgx_response(public_inputs, B256::repeat_byte(0x12));
+        assert!(verify_sgx_response(&valid).is_ok());
+        assert!(verify_sgx_response(&invalid).is_err());
+
+        // invalid once, then valid
+        let attempts = Cell::new(0);
+        let prove = || {
+            attempts.set(attempts.get() + 1);
+            let resp = match attempts.get() {
+                1 => invalid.clone(),
+                _ => valid.clone(),
+            };
+            async move { Ok(resp) }
+        };
+        let resp = prove_self_verified(2, prove, verify_sgx_response)
+            .await
+            .unwrap();
+        assert_eq!(resp.proof, valid.proof);
+        assert_eq!(attempts.get(), 2);
+
+        // never valid
+        let attempts = Cell::new(0);
+        let prove = || {
+            attempts.set(attempts.get() + 1);
+            let resp = invalid.clone();
+            async move { Ok(resp) }
+        };
+        let err = prove_self_verified(2, prove, verify_sgx_response)
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::SelfVerifyFailed(_)));
+        assert_eq!(attempts.get(), 3);
+    }
+
+    #[tokio::test]
+    async fn metadata_is_echoed_but_never_proved() {
+        let ctx = Context {
+            l2_chain: "testnet".to_owned(),
+            ..Default::default()
+        };
+        let metadata = json!({
+            "batchId": "b-17",
+            "deadline": 1_700_000_000,
+            "exporter": { "version": "1.2.0" },
+        });
+        let request = |metadata: serde_json::Value| {
+            serde_json::from_value::<ProofRequest>(json!({
+                "type": "Sgx",
+                "block": 1,
+                "l2Rpc": "http://127.0.0.1:1",
+                "l1Rpc": "http://127.0.0.1:1",
+                "prover": "0x0000000000000000000000000000000000000000",
+                "graffiti": "0x0000000000000000000000000000000000000000000000000000000000000000",
+                "metadata": metadata,
+            }))
+            .unwrap()
+        };
+        let req = request(metadata.clone());
+        let ProofRequest::Sgx(sgx_req) = &req else {
+            panic!("not an sgx proof request");
+        };
+        // the proof of the same request without metadata serves it
+        let without = request(json!({}));
+        let ProofRequest::Sgx(sgx_without) = &without else {
+            panic!("not an sgx proof request");
+        };
+        assert!(sgx_cache_key(&ctx, sgx_req) == sgx_cache_key(&ctx, sgx_without));
+        let cache = Cache::new(10);
+        cache.set(sgx_cache_key(&ctx, sgx_without), "0x01".to_owned());
+
+        let resp = execute(&cache, &ctx, &req).await.unwrap();
+        let resp = serde_json::to_value(resp).unwrap();
+        assert_eq!(resp["proof"], "0x01");
+        assert_eq!(resp["metadata"], metadata);
+        let resp = serde_json::to_value(execute(&cache, &ctx, &without).await.unwrap()).unwrap();
+        assert!(resp.get("metadata").is_none());
+
+        let oversized = request(json!({ "blob": "0".repeat(MAX_METADATA_BYTES) }));
+        let err = execute(&cache, &ctx, &oversized).await.err().unwrap();
+        assert!(err.to_string().contains("metadata"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn state_overrides_require_allow_consensus_overrides() {
+        let ctx = Context {
+            l2_chain: "testnet".to_owned(),
+            ..Default::default()
+        };
+        let req: ProofRequest = serde_json::from_value(json!({
+            "type": "Sgx",
+            "block": 1,
+            "l2Rpc": "http://127.0.0.1:1",
+            "l1Rpc": "http://127.0.0.1:1",
+            "prover": "0x0000000000000000000000000000000000000000",
+            "graffiti": "0x0000000000000000000000000000000000000000000000000000000000000000",
+            "stateOverrides": {
+                "0x0101010101010101010101010101010101010101": { "balance": "0x3e8" },
+            },
+        }))
+        .unwrap();
+        let ProofRequest::Sgx(sgx_req) = &req else {
+            panic!("not an sgx proof request");
+        };
+        let overrides = sgx_req.state_overrides.as_ref().unwrap();
+        assert_eq!(
+            overrides.accounts[&Address::repeat_byte(0x01)].balance,
+            Some(U256::from(1000))
+        );
+        // the cached proof of the block is not the one of the overridden state
+        let cache = Cache::new(10);
+        cache.set(sgx_cache_key(&ctx, sgx_req), "0x01".to_owned());
+        let err = execute(&cache, &ctx, &req).await.err().unwrap();
+        assert!(
+            err.to_string().contains("allow_consensus_overrides"),
+            "{}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn overridden_proofs_are_neither_cached_nor_delivered() {
+        let dir = tempfile::tempdir().unwrap();
+        let sink = format!("file:{}", dir.path().display());
+        let ctx = Context {
+            l2_chain: "testnet".to_owned(),
+            sinks: vec![SinkHandle::new(
+                &sink.parse().unwrap(),
+                OutputSharding::Flat,
+            )],
+            ..Default::default()
+        };
+        let req: ProofRequest = serde_json::from_value(json!({
+            "type": "Sgx",
+            "block": 1,
+            "l2Rpc": "http://127.0.0.1:1",
+            "l1Rpc": "http://127.0.0.1:1",
+            "prover": "0x0000000000000000000000000000000000000000",
+            "graffiti": "0x0000000000000000000000000000000000000000000000000000000000000000",
+        }))
+        .unwrap();
+        let ProofRequest::Sgx(req) = req else {
+            panic!("not an sgx proof request");
+        };
+        let delivered = dir.path().join("testnet").join("1.json");
+
+        // proven with the server-wide overrides
+        let overridden = Context {
+            consensus_overrides: ConsensusOverrides {
+                base_fee_per_gas: Some(U256::from(1)),
+                gas_limit: None,
+            },
+            ..ctx.clone()
+        };
+        let cache = Cache::new(10);
+        let key = sgx_cache_key(&overridden, &req);
+        assert!(keep_proof(&cache, &overridden, &req, Some(key.clone()), "0x01").is_none());
+        assert!(cache.get(&key).is_none());
+        assert!(!delivered.exists());
+        // or with the code of system contracts overridden
+        let overridden = Context {
+            system_overrides: Some("system-overrides.json".into()),
+            ..ctx.clone()
+        };
+        assert!(keep_proof(&cache, &overridden, &req, Some(key.clone()), "0x01").is_none());
+        assert!(cache.get(&key).is_none());
+        assert!(!delivered.exists());
+
+        let handle = keep_proof(&cache, &ctx, &req, Some(key.clone()), "0x01").unwrap();
+        assert!(handle.await.unwrap().is_empty());
+        assert_eq!(cache.get(&key).as_deref(), Some("0x01"));
+        assert!(delivered.exists());
+    }
+
+    #[tokio::test]
+    async fn multi_proof_keeps_the_successes_of_a_partial_failure() {
+        let req: ProofRequest = serde_json::from_value(json!({
+            "type": "Multi",
+            "proofTypes": ["sgx", "pse_zk", "sgx"],
+            "block": 1,
+            "l2Rpc": "http://l2",
+            "l1Rpc": "http://l1",
+            "prover": "0x0000000000000000000000000000000000000000",
+            "graffiti": "0x0000000000000000000000000000000000000000000000000000000000000000",
+        }))
+        .unwrap();
+        let ProofRequest::Multi(req) = req else {
+            panic!("not a multi proof request");
+        };
+        assert_eq!(req.block.block, 1);
+
+        let resp = prove_each(&req.proof_types, |proof_type| async move {
+            match proof_type {
+                ProofType::Sgx => Ok("0x01".to_owned()),
+                ProofType::PseZk => Err("not available".to_owned()),
+            }
+        })
+        .await;
+        assert_eq!(
+            serde_json::to_value(ProofResponse::Multi(resp)).unwrap(),
+            json!({
+                "type": "Multi",
+                "proofs": { "sgx": "0x01" },
+                "errors": { "pse_zk": "not available" },
+            })
+        );
+    }
+}
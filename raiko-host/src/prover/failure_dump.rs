@@ -0,0 +1,247 @@
+//! Bundles of the inputs of the failed proofs, for an engineer to reproduce a failure
+//! offline: the request as `record_requests` records it, the cached block data the proof
+//! was built from, the chain spec and the error. The bundle replays with `--replay`
+//! against a server whose `--cache` is the `cache` directory of the bundle.
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::Value;
+use tracing::{info, warn};
+use zeth_lib::consts::{get_taiko_chain_spec, ChainSpec};
+
+use super::{context::Context, record::RecordedRequest, utils::cache_file_path};
+use crate::config::{redact_message, redact_urls};
+
+/// The request of a bundle, a `record_requests` file of one line.
+pub const FAILURE_REQUEST_FILE: &str = "request.jsonl";
+/// What failed, and on which chain.
+pub const FAILURE_REPORT_FILE: &str = "failure.json";
+/// The cache directory of a bundle, laid out as the one of the server.
+pub const FAILURE_CACHE_DIR: &str = "cache";
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FailureReport {
+    /// unix time of the failure, in milliseconds
+    failed_at: u64,
+    chain: String,
+    chain_id: u64,
+    chain_spec: ChainSpec,
+    block: Option<u64>,
+    error: String,
+    /// the cached block data was left out of the bundle, over `max_bundle_bytes`
+    block_data_omitted: bool,
+}
+
+/// Where the bundles of the failed proofs are written, keeping the `max_count` most
+/// recent ones.
+#[derive(Debug, Clone)]
+pub struct FailureDumps {
+    dir: PathBuf,
+    max_count: usize,
+    /// most bytes of block data a bundle holds
+    max_bundle_bytes: u64,
+}
+
+impl FailureDumps {
+    pub fn new(dir: PathBuf, max_count: usize, max_bundle_bytes: u64) -> Self {
+        FailureDumps {
+            dir,
+            max_count,
+            max_bundle_bytes,
+        }
+    }
+
+    /// Writes the bundle of the failed request, logging the failures to write it: they
+    /// don't fail anything else.
+    pub fn dump_logged(
+        &self,
+        ctx: &Context,
+        block: Option<u64>,
+        method: &str,
+        params: &Value,
+        error: &str,
+    ) {
+        match self.dump(ctx, block, method, params, error) {
+            Ok(bundle) => info!("Wrote the inputs of the failed proof to {:?}", bundle),
+            Err(err) => warn!("Failed to write the inputs of a failed proof: {:#}", err),
+        }
+    }
+
+    /// Writes the bundle of the failed request, with the defaults applied to its params,
+    /// returning the directory of the bundle.
+    pub fn dump(
+        &self,
+        ctx: &Context,
+        block: Option<u64>,
+        method: &str,
+        params: &Value,
+        error: &str,
+    ) -> Result<PathBuf> {
+        let failed_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let name = match block {
+            Some(block) => format!("block-{}-{}", block, failed_at),
+            None => format!("request-{}", failed_at),
+        };
+        let bundle = self.dir.join(name);
+        fs::create_dir_all(&bundle)?;
+
+        let error = redact_message(error);
+        let mut params = params.clone();
+        redact_urls(&mut params);
+        let request = RecordedRequest {
+            recorded_at: failed_at / 1000,
+            method: method.to_owned(),
+            params: vec![params],
+            result: None,
+            error: Some(error.clone()),
+        };
+        let mut line = serde_json::to_vec(&request)?;
+        line.push(b'\n');
+        fs::write(bundle.join(FAILURE_REQUEST_FILE), line)?;
+
+        let block_data_omitted = match block {
+            Some(block) => !self.copy_block_data(ctx, block, &bundle.join(FAILURE_CACHE_DIR))?,
+            None => false,
+        };
+        let report = FailureReport {
+            failed_at,
+            chain: ctx.l2_chain.clone(),
+            chain_id: ctx.l2_chain_id,
+            chain_spec: get_taiko_chain_spec(&ctx.l2_chain),
+            block,
+            error,
+            block_data_omitted,
+        };
+        let mut file = fs::File::create(bundle.join(FAILURE_REPORT_FILE))?;
+        file.write_all(&serde_json::to_vec_pretty(&report)?)?;
+
+        self.prune()?;
+        Ok(bundle)
+    }
+
+    /// Copies the cache files of the block into `cache_dir`, unless they hold more than
+    /// `max_bundle_bytes`. Whether they were copied.
+    fn copy_block_data(&self, ctx: &Context, block: u64, cache_dir: &Path) -> io::Result<bool> {
+        let files: Vec<_> = [true, false]
+            .into_iter()
+            .map(|is_l1| {
+                (
+                    cache_file_path(&ctx.cache_path, ctx.l2_chain_id, block, is_l1),
+                    cache_file_path(cache_dir, ctx.l2_chain_id, block, is_l1),
+                )
+            })
+            .filter(|(from, _)| from.exists())
+            .collect();
+        let mut bytes = 0;
+        for (from, _) in &files {
+            bytes += fs::metadata(from)?.len();
+        }
+        if bytes > self.max_bundle_bytes {
+            return Ok(false);
+        }
+        for (from, to) in &files {
+            fs::create_dir_all(to.parent().unwrap())?;
+            fs::copy(from, to)?;
+        }
+        Ok(true)
+    }
+
+    /// Removes the oldest bundles beyond `max_count`.
+    fn prune(&self) -> io::Result<()> {
+        let mut bundles = vec![];
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            // only the bundles, whatever else the directory holds
+            if path.join(FAILURE_REPORT_FILE).exists() {
+                bundles.push((fs::metadata(&path)?.modified()?, path));
+            }
+        }
+        bundles.sort();
+        let excess = bundles.len().saturating_sub(self.max_count);
+        for (_, path) in bundles.into_iter().take(excess) {
+            fs::remove_dir_all(path)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::prover::{record::read_recorded, request::ProofRequest};
+
+    #[test]
+    fn failed_proofs_leave_a_replayable_bundle() {
+        let dir = tempfile::tempdir().unwrap();
+        let ctx = Context {
+            cache_path: dir.path().join("cache"),
+            l2_chain: "internal_devnet_a".to_owned(),
+            l2_chain_id: get_taiko_chain_spec("internal_devnet_a").chain_id(),
+            ..Default::default()
+        };
+        for is_l1 in [true, false] {
+            let path = cache_file_path(&ctx.cache_path, ctx.l2_chain_id, 7, is_l1);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"block data").unwrap();
+        }
+        let dumps = FailureDumps::new(dir.path().join("failures"), 2, 1 << 20);
+        let params = json!({
+            "type": "Sgx",
+            "l2Rpc": "https://l2.example/v2/secret-key",
+            "l1Rpc": "http://l1:8545",
+            "block": 7,
+            "prover": "0x0000000000000000000000000000000000000001",
+            "graffiti": "0x0000000000000000000000000000000000000000000000000000000000000000",
+        });
+        let error = "failed to fetch https://l2.example/v2/secret-key: 429";
+        let bundle = dumps.dump(&ctx, Some(7), "proof", &params, error).unwrap();
+
+        // the request replays, with the block data cached
+        let requests = read_recorded(&bundle.join(FAILURE_REQUEST_FILE)).unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "proof");
+        let req: ProofRequest = serde_json::from_value(requests[0].params[0].clone()).unwrap();
+        assert!(matches!(req, ProofRequest::Sgx(req) if req.block == 7));
+        for is_l1 in [true, false] {
+            let path = cache_file_path(&bundle.join(FAILURE_CACHE_DIR), ctx.l2_chain_id, 7, is_l1);
+            assert_eq!(fs::read(path).unwrap(), b"block data");
+        }
+        let report: Value =
+            serde_json::from_slice(&fs::read(bundle.join(FAILURE_REPORT_FILE)).unwrap()).unwrap();
+        assert_eq!(report["chainId"], ctx.l2_chain_id);
+        assert_eq!(report["blockDataOmitted"], false);
+        // redacted of the credentials of the URLs
+        for file in [FAILURE_REQUEST_FILE, FAILURE_REPORT_FILE] {
+            let content = fs::read_to_string(bundle.join(file)).unwrap();
+            assert!(!content.contains("secret-key"), "{}", file);
+        }
+
+        // the block data over the size of a bundle is left out
+        let small = FailureDumps::new(dir.path().join("failures"), 2, 10);
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        let bundle = small
+            .dump(&ctx, Some(7), "proof", &params, "failed")
+            .unwrap();
+        assert!(!bundle.join(FAILURE_CACHE_DIR).exists());
+        // the oldest bundles are removed beyond the count
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        small.dump(&ctx, None, "proof", &params, "failed").unwrap();
+        assert_eq!(
+            fs::read_dir(dir.path().join("failures")).unwrap().count(),
+            2
+        );
+        assert!(bundle.exists());
+    }
+}
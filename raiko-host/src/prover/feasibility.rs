@@ -0,0 +1,191 @@
+//! Checks that a block can be proven without proving it: its data is fetched and checked
+//! like for a proof, into the cache the proof of the block then reuses.
+use std::{collections::VecDeque, future::Future, ops::RangeInclusive};
+
+use hyper::Body;
+use serde::Serialize;
+use tracing::warn;
+use zeth_lib::{
+    host::provider::failover_provider::is_endpoint_failure,
+    taiko::block_builder::TaikoStrategyBundle,
+};
+use zeth_primitives::{Address, B256};
+
+use super::{
+    allowed_range::check_block_allowed,
+    context::Context,
+    error::{Error, Result},
+    prepare_input::prepare_input,
+    request::{ProofRequest, SgxRequest},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FeasibilityResponse {
+    pub provable: bool,
+    /// whether the block may become provable later, e.g. once its blob is available
+    pub retryable: bool,
+    /// why the block is not provable
+    pub reasons: Vec<String>,
+}
+
+impl FeasibilityResponse {
+    fn not_provable(retryable: bool, reason: String) -> Self {
+        FeasibilityResponse {
+            provable: false,
+            retryable,
+            reasons: vec![reason],
+        }
+    }
+}
+
+/// Fetches and checks the data of `block`, as for its proof.
+pub async fn check_feasibility(
+    ctx: &Context,
+    block: u64,
+    l1_rpc: String,
+    l2_rpc: String,
+) -> FeasibilityResponse {
+    if let Err(reason) = check_block_allowed(&ctx.prove_allowed_ranges, &ctx.l2_chain, block) {
+        return FeasibilityResponse::not_provable(false, reason);
+    }
+    let req = ProofRequest::Sgx(SgxRequest {
+        block,
+        l2_rpc,
+        l1_rpc,
+        // only used in the protocol instance, not in the cached data
+        prover: Address::ZERO,
+        graffiti: B256::ZERO,
+        timeout_secs: None,
+        data_source: None,
+        skip_cache: false,
+        state_overrides: None,
+        metadata: Default::default(),
+        deadline: None,
+    });
+    let _pin = ctx.cache_pins.pin(block);
+    let prepared = prepare_input::<TaikoStrategyBundle>(ctx, &req).await;
+    feasibility_of(prepared.map(|_| ()))
+}
+
+/// The feasibility of a block of a batch, a line of the response.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BlockFeasibility {
+    pub block: u64,
+    #[serde(flatten)]
+    pub feasibility: FeasibilityResponse,
+}
+
+/// The feasibility of each block of `blocks`, as a body streaming a json line per block
+/// in block order, `concurrency` blocks being checked at once.
+pub fn feasibility_batch<F, Fut>(blocks: RangeInclusive<u64>, concurrency: usize, check: F) -> Body
+where
+    F: Fn(u64) -> Fut + Send + 'static,
+    Fut: Future<Output = FeasibilityResponse> + Send + 'static,
+{
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        let mut blocks = blocks.into_iter();
+        let mut checks = VecDeque::new();
+        loop {
+            while checks.len() < concurrency.max(1) {
+                let Some(block) = blocks.next() else {
+                    break;
+                };
+                checks.push_back((block, tokio::spawn(check(block))));
+            }
+            let Some((block, check)) = checks.pop_front() else {
+                return;
+            };
+            let feasibility = match check.await {
+                Ok(feasibility) => feasibility,
+                Err(err) => FeasibilityResponse::not_provable(false, err.to_string()),
+            };
+            let mut line = serde_json::to_vec(&BlockFeasibility { block, feasibility }).unwrap();
+            line.push(b'\n');
+            if sender.send_data(line.into()).await.is_err() {
+                // the client is gone, the checks in flight still fill the cache
+                warn!("Feasibility batch abandoned at block {}", block);
+                return;
+            }
+        }
+    });
+    body
+}
+
+fn feasibility_of(prepared: Result<()>) -> FeasibilityResponse {
+    match prepared {
+        Ok(()) => FeasibilityResponse {
+            provable: true,
+            retryable: false,
+            reasons: vec![],
+        },
+        Err(Error::BlobFetch(err)) => FeasibilityResponse::not_provable(
+            true,
+            format!("the blob data is not available: {:#}", err),
+        ),
+        Err(Error::Anyhow(err)) if is_endpoint_failure(&err) => FeasibilityResponse::not_provable(
+            true,
+            format!("the RPC node is not available: {:#}", err),
+        ),
+        Err(err) => FeasibilityResponse::not_provable(false, err.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+    use zeth_lib::taiko::blob::BlobFetchError;
+
+    use super::*;
+
+    #[test]
+    fn block_missing_its_blob_is_not_yet_provable() {
+        let missing_blob = anyhow!(BlobFetchError::NotFound(B256::repeat_byte(0x01)))
+            .context("fetching the tx list of block 7");
+        let feasibility = feasibility_of(Err(missing_blob.into()));
+        assert!(!feasibility.provable);
+        assert!(feasibility.retryable);
+        assert!(feasibility.reasons[0].contains("no blob sidecar with versioned hash"));
+
+        let invalid = feasibility_of(Err(anyhow!("block hash mismatch").into()));
+        assert!(!invalid.provable && !invalid.retryable);
+
+        assert!(feasibility_of(Ok(())).provable);
+    }
+
+    #[tokio::test]
+    async fn batch_streams_a_line_per_block_in_order() {
+        let body = feasibility_batch(5..=8, 2, |block| async move {
+            if block == 7 {
+                let missing_blob = anyhow!(BlobFetchError::NotFound(B256::repeat_byte(0x07)));
+                return feasibility_of(Err(missing_blob.into()));
+            }
+            // the later blocks are checked before the earlier ones finish
+            let delay = if block == 5 { 50 } else { 0 };
+            tokio::time::sleep(std::time::Duration::from_millis(delay)).await;
+            feasibility_of(Ok(()))
+        });
+        let bytes = hyper::body::to_bytes(body).await.unwrap();
+        let lines: Vec<serde_json::Value> = std::str::from_utf8(&bytes)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let blocks: Vec<_> = lines.iter().map(|line| line["block"].as_u64()).collect();
+        assert_eq!(blocks, vec![Some(5), Some(6), Some(7), Some(8)]);
+        let provable: Vec<_> = lines
+            .iter()
+            .map(|line| line["provable"].as_bool())
+            .collect();
+        assert_eq!(
+            provable,
+            vec![Some(true), Some(true), Some(false), Some(true)]
+        );
+        assert_eq!(lines[2]["retryable"], true);
+        assert!(lines[2]["reasons"][0]
+            .as_str()
+            .unwrap()
+            .contains("the blob data is not available"));
+    }
+}
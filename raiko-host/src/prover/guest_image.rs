@@ -0,0 +1,161 @@
+//! Checks of the guest image at startup, so that a missing or corrupt guest fails the
+//! startup, or holds the readiness back until it shows up, rather than failing the first
+//! proof request.
+use std::{
+    fmt,
+    fs::{self, File},
+    io::Read,
+    path::Path,
+    str::FromStr,
+    time::Duration,
+};
+
+use serde_with::{DeserializeFromStr, SerializeDisplay};
+use tracing::debug;
+
+use super::{consts::SGX_PARENT_DIR, utils::guest_executable_path};
+
+/// How often a missing or invalid guest is checked again, with `--on-invalid-guest=wait`.
+pub const GUEST_RECHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+/// The HEADER field of a SIGSTRUCT, as written to the `.sig` file by gramine-sgx-sign.
+const SIGSTRUCT_HEADER: [u8; 16] = [6, 0, 0, 0, 0xe1, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0];
+const SIGSTRUCT_SIZE: usize = 1808;
+/// Offset of ENCLAVEHASH, the MRENCLAVE of the signed enclave, in a SIGSTRUCT.
+const MRENCLAVE_OFFSET: usize = 960;
+
+/// What to do at startup when the guest is missing or invalid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, DeserializeFromStr, SerializeDisplay)]
+pub enum OnInvalidGuest {
+    /// exit at once
+    Error,
+    /// hold the readiness and check it again until it is valid
+    Wait,
+}
+
+impl FromStr for OnInvalidGuest {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(OnInvalidGuest::Error),
+            "wait" => Ok(OnInvalidGuest::Wait),
+            _ => Err(format!(
+                "invalid on_invalid_guest {:?}, expected error or wait",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for OnInvalidGuest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OnInvalidGuest::Error => write!(f, "error"),
+            OnInvalidGuest::Wait => write!(f, "wait"),
+        }
+    }
+}
+
+/// A guest image gramine-sgx can run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GuestImage {
+    /// the measurement of the enclave, from its SIGSTRUCT
+    pub mrenclave: [u8; 32],
+}
+
+/// Checks that the sgx guest under `guest_path` is a readable ELF executable, signed into
+/// the SIGSTRUCT of its `.sig` file.
+pub fn check_guest(guest_path: &Path) -> Result<GuestImage, String> {
+    let executable = guest_executable_path(guest_path, SGX_PARENT_DIR);
+    let mut magic = [0; 4];
+    File::open(&executable)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .map_err(|err| format!("cannot read {:?}: {}", executable, err))?;
+    if magic != ELF_MAGIC {
+        return Err(format!("{:?} is not an ELF executable", executable));
+    }
+    let sig_path = executable.with_extension("sig");
+    let sig = fs::read(&sig_path).map_err(|err| format!("cannot read {:?}: {}", sig_path, err))?;
+    if sig.len() != SIGSTRUCT_SIZE || !sig.starts_with(&SIGSTRUCT_HEADER) {
+        return Err(format!("{:?} is not an SGX SIGSTRUCT", sig_path));
+    }
+    let mut mrenclave = [0; 32];
+    mrenclave.copy_from_slice(&sig[MRENCLAVE_OFFSET..MRENCLAVE_OFFSET + 32]);
+    Ok(GuestImage { mrenclave })
+}
+
+/// The guest at startup: `Some` when it is valid, `None` when it is not and `policy` is
+/// to wait for it, the error of the check when it is to fail.
+pub fn startup_check(
+    guest_path: &Path,
+    policy: OnInvalidGuest,
+) -> Result<Option<GuestImage>, String> {
+    match (check_guest(guest_path), policy) {
+        (Ok(image), _) => Ok(Some(image)),
+        (Err(_), OnInvalidGuest::Wait) => Ok(None),
+        (Err(err), OnInvalidGuest::Error) => Err(err),
+    }
+}
+
+/// Checks the guest every `interval` until it is valid.
+pub async fn wait_for_guest(guest_path: &Path, interval: Duration) -> GuestImage {
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        match check_guest(guest_path) {
+            Ok(image) => return image,
+            Err(err) => debug!("Still waiting for the guest: {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes a guest under `guest_path`, measured as `mrenclave`.
+    fn install_guest(guest_path: &Path, mrenclave: [u8; 32]) {
+        let executable = guest_executable_path(guest_path, SGX_PARENT_DIR);
+        fs::create_dir_all(executable.parent().unwrap()).unwrap();
+        fs::write(&executable, [&ELF_MAGIC[..], &[2, 1, 1]].concat()).unwrap();
+        let mut sig = vec![0; SIGSTRUCT_SIZE];
+        sig[..16].copy_from_slice(&SIGSTRUCT_HEADER);
+        sig[MRENCLAVE_OFFSET..MRENCLAVE_OFFSET + 32].copy_from_slice(&mrenclave);
+        fs::write(executable.with_extension("sig"), sig).unwrap();
+    }
+
+    #[tokio::test]
+    async fn missing_guest_fails_or_is_waited_for() {
+        let dir = tempfile::tempdir().unwrap();
+        let err = startup_check(dir.path(), OnInvalidGuest::Error).unwrap_err();
+        assert!(err.contains("cannot read"));
+        assert_eq!(startup_check(dir.path(), OnInvalidGuest::Wait), Ok(None));
+
+        let guest_path = dir.path().to_path_buf();
+        let waiter =
+            tokio::spawn(
+                async move { wait_for_guest(&guest_path, Duration::from_millis(10)).await },
+            );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiter.is_finished());
+
+        // the init container fills the volume
+        install_guest(dir.path(), [7; 32]);
+        let image = tokio::time::timeout(Duration::from_secs(5), waiter)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(image.mrenclave, [7; 32]);
+        assert_eq!(
+            startup_check(dir.path(), OnInvalidGuest::Error),
+            Ok(Some(image))
+        );
+
+        // a truncated signature is no guest
+        let sig_path = guest_executable_path(dir.path(), SGX_PARENT_DIR).with_extension("sig");
+        fs::write(&sig_path, &SIGSTRUCT_HEADER).unwrap();
+        assert!(check_guest(dir.path()).unwrap_err().contains("SIGSTRUCT"));
+    }
+}
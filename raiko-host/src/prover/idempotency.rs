@@ -0,0 +1,303 @@
+//! Dedupes the proof requests resubmitted with the same `Idempotency-Key` header.
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use serde_json::Value;
+use tokio::sync::OnceCell;
+use tracing::debug;
+
+use crate::metrics::inc_coalesced;
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// How long the results of the submissions are kept, by outcome, since their submission.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retention {
+    pub success: Duration,
+    /// zero not to keep the failures, their resubmissions proving again
+    pub error: Duration,
+}
+
+struct Submission {
+    request: Value,
+    submitted_at: Instant,
+    result: Arc<OnceCell<Value>>,
+    /// the error of the proof, kept for `Retention::error`
+    error: Option<String>,
+}
+
+impl Submission {
+    fn retained(&self, now: Instant, retention: Retention) -> bool {
+        let retention = match self.error {
+            Some(_) => retention.error,
+            None => retention.success,
+        };
+        // submissions still being proven are kept past their retention
+        now.duration_since(self.submitted_at) < retention || Arc::strong_count(&self.result) > 1
+    }
+}
+
+/// The results of the requests by idempotency key. A resubmission within the retention of
+/// the result waits for the proof of the first submission, or gets its result once done,
+/// instead of starting another one. The failures are kept for their own, shorter,
+/// retention, not at all by default. Beyond `max_keys`, the oldest submissions are
+/// dropped before their retention, but for those still being proven.
+///
+/// The keys live in memory only and don't outlive a restart: a resubmission after one is
+/// proven again, unless its proof is served from the proof cache.
+#[derive(Clone)]
+pub struct IdempotencyStore {
+    retention: Retention,
+    max_keys: usize,
+    submissions: Arc<Mutex<HashMap<String, Submission>>>,
+}
+
+impl IdempotencyStore {
+    pub fn new(retention: Retention, max_keys: usize) -> Self {
+        IdempotencyStore {
+            retention,
+            max_keys,
+            submissions: Default::default(),
+        }
+    }
+
+    /// Runs `prove` for `request`, unless it was already submitted with `key`.
+    pub async fn run<F, Fut>(&self, key: &str, request: &Value, prove: F) -> Result<Value, String>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Value, String>>,
+    {
+        let result = {
+            let mut submissions = self.submissions.lock().unwrap();
+            let now = Instant::now();
+            submissions.retain(|_, submission| submission.retained(now, self.retention));
+            if !submissions.contains_key(key) && submissions.len() >= self.max_keys {
+                evict_oldest(&mut submissions);
+            }
+            match submissions.entry(key.to_owned()) {
+                Entry::Occupied(entry) => {
+                    if entry.get().request != *request {
+                        return Err(format!(
+                            "Idempotency-Key {:?} was used for a different request",
+                            key
+                        ));
+                    }
+                    debug!(
+                        "Request coalesced onto the proof of Idempotency-Key {:?}",
+                        key
+                    );
+                    inc_coalesced();
+                    if let Some(error) = &entry.get().error {
+                        return Err(error.clone());
+                    }
+                    entry.get().result.clone()
+                }
+                Entry::Vacant(entry) => entry
+                    .insert(Submission {
+                        request: request.clone(),
+                        submitted_at: now,
+                        result: Default::default(),
+                        error: None,
+                    })
+                    .result
+                    .clone(),
+            }
+        };
+        let outcome = result.get_or_try_init(prove).await.cloned();
+        if let (Err(error), false) = (&outcome, self.retention.error.is_zero()) {
+            let mut submissions = self.submissions.lock().unwrap();
+            // unless proven by a resubmission meanwhile
+            if let Some(submission) = submissions
+                .get_mut(key)
+                .filter(|submission| Arc::ptr_eq(&submission.result, &result))
+                .filter(|submission| submission.result.get().is_none())
+            {
+                submission.error = Some(error.clone());
+            }
+        }
+        outcome
+    }
+}
+
+/// Drops the oldest submission not being proven, if any.
+fn evict_oldest(submissions: &mut HashMap<String, Submission>) {
+    let oldest = submissions
+        .iter()
+        .filter(|(_, submission)| Arc::strong_count(&submission.result) == 1)
+        .min_by_key(|(_, submission)| submission.submitted_at)
+        .map(|(key, _)| key.clone());
+    if let Some(key) = oldest {
+        submissions.remove(&key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::metrics::COALESCED_REQUESTS_COUNTER;
+
+    async fn prove(proofs: &AtomicUsize, result: Result<Value, String>) -> Result<Value, String> {
+        proofs.fetch_add(1, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        result
+    }
+
+    /// Keeps the successes for `success`, not the failures.
+    fn retention(success: Duration) -> Retention {
+        Retention {
+            success,
+            error: Duration::ZERO,
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_resubmissions_share_one_proof() {
+        let store = IdempotencyStore::new(retention(Duration::from_secs(60)), 10);
+        let proofs = Arc::new(AtomicUsize::new(0));
+        let request = json!({"block": 1});
+        let coalesced = COALESCED_REQUESTS_COUNTER.get();
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let (store, proofs, request) = (store.clone(), proofs.clone(), request.clone());
+                tokio::spawn(async move {
+                    store
+                        .run("key", &request, || {
+                            prove(&proofs, Ok(json!({ "proof": i })))
+                        })
+                        .await
+                })
+            })
+            .collect();
+        let mut results = vec![];
+        for handle in handles {
+            results.push(handle.await.unwrap().unwrap());
+        }
+        assert_eq!(proofs.load(Ordering::SeqCst), 1);
+        assert!(results.iter().all(|result| *result == results[0]));
+        // the other tests coalesce too
+        assert!(COALESCED_REQUESTS_COUNTER.get() >= coalesced + 3);
+    }
+
+    #[tokio::test]
+    async fn sequential_resubmissions_get_the_result() {
+        let store = IdempotencyStore::new(retention(Duration::from_millis(200)), 10);
+        let proofs = AtomicUsize::new(0);
+        let request = json!({"block": 1});
+
+        let failed = store
+            .run("key", &request, || prove(&proofs, Err("rpc down".into())))
+            .await;
+        assert!(failed.is_err());
+        let first = store
+            .run("key", &request, || prove(&proofs, Ok(json!("proof"))))
+            .await;
+        let second = store
+            .run("key", &request, || prove(&proofs, Ok(json!("other proof"))))
+            .await;
+        assert_eq!(first, Ok(json!("proof")));
+        assert_eq!(second, first);
+        assert_eq!(proofs.load(Ordering::SeqCst), 2);
+
+        // another request can't reuse the key
+        assert!(store
+            .run("key", &json!({"block": 2}), || prove(
+                &proofs,
+                Ok(json!("proof"))
+            ))
+            .await
+            .is_err());
+
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        let expired = store
+            .run("key", &json!({"block": 2}), || {
+                prove(&proofs, Ok(json!("new proof")))
+            })
+            .await;
+        assert_eq!(expired, Ok(json!("new proof")));
+        assert_eq!(proofs.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn failures_are_evicted_before_the_successes_of_the_same_age() {
+        let store = IdempotencyStore::new(
+            Retention {
+                success: Duration::from_millis(400),
+                error: Duration::from_millis(100),
+            },
+            10,
+        );
+        let proofs = AtomicUsize::new(0);
+        let (failing, succeeding) = (json!({"block": 1}), json!({"block": 2}));
+        let (failed, succeeded) = tokio::join!(
+            store.run("failing", &failing, || prove(
+                &proofs,
+                Err("rpc down".into())
+            )),
+            store.run("succeeding", &succeeding, || {
+                prove(&proofs, Ok(json!("proof")))
+            }),
+        );
+        assert_eq!(failed, Err("rpc down".to_owned()));
+        assert_eq!(succeeded, Ok(json!("proof")));
+
+        // within the retention of the failures, both are kept
+        let failed = store
+            .run("failing", &failing, || prove(&proofs, Ok(json!("proof"))))
+            .await;
+        assert_eq!(failed, Err("rpc down".to_owned()));
+        assert_eq!(proofs.load(Ordering::SeqCst), 2);
+
+        // past it, the failure is proven again, the success is still served
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        let (failed, succeeded) = tokio::join!(
+            store.run("failing", &failing, || prove(
+                &proofs,
+                Ok(json!("new proof"))
+            )),
+            store.run("succeeding", &succeeding, || {
+                prove(&proofs, Ok(json!("new proof")))
+            }),
+        );
+        assert_eq!(failed, Ok(json!("new proof")));
+        assert_eq!(succeeded, Ok(json!("proof")));
+        assert_eq!(proofs.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn the_oldest_keys_are_dropped_beyond_the_limit() {
+        let store = IdempotencyStore::new(retention(Duration::from_secs(60)), 2);
+        let proofs = AtomicUsize::new(0);
+        for block in 0..3 {
+            let request = json!({ "block": block });
+            let key = block.to_string();
+            assert!(store
+                .run(&key, &request, || prove(&proofs, Ok(json!(block))))
+                .await
+                .is_ok());
+        }
+        assert_eq!(store.submissions.lock().unwrap().len(), 2);
+
+        // the first key was dropped, the last ones are still served
+        let served = store
+            .run("2", &json!({ "block": 2 }), || {
+                prove(&proofs, Ok(json!("new proof")))
+            })
+            .await;
+        assert_eq!(served, Ok(json!(2)));
+        let proven = store
+            .run("0", &json!({ "block": 0 }), || {
+                prove(&proofs, Ok(json!("new proof")))
+            })
+            .await;
+        assert_eq!(proven, Ok(json!("new proof")));
+        assert_eq!(proofs.load(Ordering::SeqCst), 4);
+    }
+}
@@ -0,0 +1,303 @@
+//! The idle timeout of the accepted connections, `--http-idle-timeout-secs`: a connection
+//! is closed once it has no request in flight and neither read nor wrote for the timeout.
+//! A request is in flight until its response body is sent, so a long proof or a quiet
+//! `/logs/tail` stream keeps its connection however long it takes.
+use std::{
+    future::Future,
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use hyper::{
+    body::{HttpBody, SizeHint},
+    server::{
+        accept::Accept,
+        conn::{AddrIncoming, AddrStream},
+    },
+    HeaderMap, Response,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::{Instant, Sleep},
+};
+
+/// The connections accepted by `incoming`, closed once idle for `timeout`, if any.
+pub struct IdleIncoming {
+    incoming: AddrIncoming,
+    timeout: Option<Duration>,
+}
+
+impl IdleIncoming {
+    pub fn new(incoming: AddrIncoming, timeout: Option<Duration>) -> Self {
+        IdleIncoming { incoming, timeout }
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.incoming.local_addr()
+    }
+}
+
+impl Accept for IdleIncoming {
+    type Conn = IdleStream<AddrStream>;
+    type Error = io::Error;
+
+    fn poll_accept(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        let timeout = self.timeout;
+        Pin::new(&mut self.incoming)
+            .poll_accept(cx)
+            .map_ok(|stream| IdleStream::new(stream, timeout))
+    }
+}
+
+/// The requests in flight on a connection.
+#[derive(Debug, Clone, Default)]
+pub struct InFlight(Arc<AtomicUsize>);
+
+impl InFlight {
+    /// Holds the connection busy until the guard is dropped.
+    pub fn begin(&self) -> InFlightGuard {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        InFlightGuard(self.0.clone())
+    }
+
+    fn is_idle(&self) -> bool {
+        self.0.load(Ordering::SeqCst) == 0
+    }
+}
+
+#[derive(Debug)]
+pub struct InFlightGuard(Arc<AtomicUsize>);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// A connection which reads as closed once idle for its timeout, hyper then closing it.
+pub struct IdleStream<S> {
+    inner: S,
+    timeout: Option<(Duration, Pin<Box<Sleep>>)>,
+    in_flight: InFlight,
+}
+
+impl<S> IdleStream<S> {
+    pub fn new(inner: S, timeout: Option<Duration>) -> Self {
+        IdleStream {
+            inner,
+            timeout: timeout.map(|timeout| (timeout, Box::pin(tokio::time::sleep(timeout)))),
+            in_flight: InFlight::default(),
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// The requests in flight, `None` without a timeout.
+    pub fn in_flight(&self) -> Option<&InFlight> {
+        self.timeout.as_ref().map(|_| &self.in_flight)
+    }
+
+    /// Restarts the timer, on some activity.
+    fn touch(&mut self) {
+        if let Some((timeout, sleep)) = &mut self.timeout {
+            sleep.as_mut().reset(Instant::now() + *timeout);
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for IdleStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        let filled = buf.filled().len();
+        if let Poll::Ready(result) = Pin::new(&mut this.inner).poll_read(cx, buf) {
+            if buf.filled().len() > filled {
+                this.touch();
+            }
+            return Poll::Ready(result);
+        }
+        if !this.in_flight.is_idle() {
+            // the timer runs from the end of the last request
+            this.touch();
+            return Poll::Pending;
+        }
+        match &mut this.timeout {
+            // nothing read, the end of the stream
+            Some((_, sleep)) if sleep.as_mut().poll(cx).is_ready() => Poll::Ready(Ok(())),
+            _ => Poll::Pending,
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for IdleStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let written = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(1..)) = written {
+            self.touch();
+        }
+        written
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let written = Pin::new(&mut self.inner).poll_write_vectored(cx, bufs);
+        if let Poll::Ready(Ok(1..)) = written {
+            self.touch();
+        }
+        written
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// The response of a request in flight, holding its connection busy until it resolves.
+pub struct InFlightFuture<F> {
+    future: Pin<Box<F>>,
+    guard: Option<InFlightGuard>,
+}
+
+impl<F> InFlightFuture<F> {
+    pub fn new(future: F, guard: Option<InFlightGuard>) -> Self {
+        InFlightFuture {
+            future: Box::pin(future),
+            guard,
+        }
+    }
+}
+
+impl<F, B, E> Future for InFlightFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+{
+    type Output = Result<Response<InFlightBody<B>>, E>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let resp = ready!(self.future.as_mut().poll(cx));
+        // the body takes over the guard, until it is sent
+        let guard = self.guard.take();
+        Poll::Ready(resp.map(|resp| {
+            resp.map(|inner| InFlightBody {
+                inner,
+                _guard: guard,
+            })
+        }))
+    }
+}
+
+/// A response body holding its connection busy until it is sent or dropped.
+pub struct InFlightBody<B> {
+    inner: B,
+    _guard: Option<InFlightGuard>,
+}
+
+impl<B: HttpBody + Unpin> HttpBody for InFlightBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        Pin::new(&mut self.inner).poll_data(cx)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::Body;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    use super::*;
+    use crate::prover::mock_server::serve_on;
+
+    const TIMEOUT: Duration = Duration::from_millis(200);
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn connections_are_closed_once_idle() {
+        let addr = serve_on(
+            "127.0.0.1:0".parse().unwrap(),
+            Some(TIMEOUT),
+            |req| async move {
+                // a proof taking longer than the timeout
+                if req.uri().path() == "/slow" {
+                    tokio::time::sleep(TIMEOUT * 3).await;
+                }
+                Response::new(Body::from("ok"))
+            },
+        );
+
+        async fn get(stream: &mut TcpStream, path: &str) -> bool {
+            let request = format!("GET {} HTTP/1.1\r\nhost: raiko\r\n\r\n", path);
+            if stream.write_all(request.as_bytes()).await.is_err() {
+                return false;
+            }
+            let mut buf = [0u8; 1024];
+            match stream.read(&mut buf).await {
+                Ok(read) => buf[..read].starts_with(b"HTTP/1.1 200"),
+                Err(_) => false,
+            }
+        }
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        // the requests in flight keep their connection, the timer restarting after them
+        assert!(get(&mut stream, "/slow").await);
+        tokio::time::sleep(TIMEOUT / 2).await;
+        assert!(get(&mut stream, "/").await);
+
+        // closed once idle for the timeout
+        tokio::time::sleep(TIMEOUT * 2).await;
+        let mut buf = [0u8; 16];
+        assert!(matches!(stream.read(&mut buf).await, Ok(0) | Err(_)));
+        assert!(!get(&mut stream, "/").await);
+    }
+}
@@ -0,0 +1,103 @@
+//! The bound on the lifetime of a proof job, from its submission to its result, the wait
+//! for a slot of the concurrency limit included: the proofs coming out of a backed up
+//! queue would be useless by the time they are served.
+use std::time::{Duration, Instant};
+
+use hyper::{Body, Response, StatusCode};
+use tokio::sync::OwnedSemaphorePermit;
+
+use super::concurrency::ConcurrencyLimit;
+use crate::metrics::inc_expired_jobs;
+
+/// The reason the expired jobs fail with, opening their error.
+pub const JOB_EXPIRED: &str = "JobExpired";
+
+/// When a job expires, `max_job_lifetime_secs` after its submission.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JobDeadline(pub Instant);
+
+impl JobDeadline {
+    pub fn is_expired(&self) -> bool {
+        Instant::now() >= self.0
+    }
+
+    /// The timeout of a guest given `timeout`, cut to the lifetime left to the job.
+    pub fn guest_timeout(&self, timeout: Option<Duration>) -> Duration {
+        let left = self.0.saturating_duration_since(Instant::now());
+        timeout.map_or(left, |timeout| timeout.min(left))
+    }
+
+    /// The error of a job which failed, told expired once past the deadline.
+    pub fn job_error(&self, error: String) -> String {
+        match self.is_expired() {
+            true => {
+                inc_expired_jobs();
+                format!("{}: {}", JOB_EXPIRED, error)
+            }
+            false => error,
+        }
+    }
+}
+
+/// Waits for a slot of `limit`, giving up at the deadline of the job.
+pub async fn acquire_before(
+    limit: &ConcurrencyLimit,
+    deadline: Option<JobDeadline>,
+) -> Option<OwnedSemaphorePermit> {
+    match deadline {
+        Some(JobDeadline(deadline)) => tokio::time::timeout_at(deadline.into(), limit.acquire())
+            .await
+            .ok(),
+        None => Some(limit.acquire().await),
+    }
+}
+
+/// The 503 of a job which waited in the queue for all of its `lifetime`.
+pub fn expired_response(lifetime: Duration) -> Response<Body> {
+    inc_expired_jobs();
+    let mut resp = Response::new(Body::from(format!(
+        "{}: the job waited in the queue for max_job_lifetime_secs ({}s)",
+        JOB_EXPIRED,
+        lifetime.as_secs()
+    )));
+    *resp.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::EXPIRED_JOBS_COUNTER;
+
+    #[tokio::test]
+    async fn jobs_expire_in_a_full_queue() {
+        let limit = ConcurrencyLimit::new(1);
+        let running = limit.acquire().await;
+        let expired = EXPIRED_JOBS_COUNTER.get();
+
+        // the queue stays full for longer than the lifetime of the job
+        let lifetime = Duration::from_millis(100);
+        let deadline = JobDeadline(Instant::now() + lifetime);
+        let started = Instant::now();
+        assert!(acquire_before(&limit, Some(deadline)).await.is_none());
+        assert!(started.elapsed() >= lifetime);
+        assert!(deadline.is_expired());
+        assert_eq!(limit.waiting(), 0);
+        let resp = expired_response(lifetime);
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(body.starts_with(JOB_EXPIRED.as_bytes()));
+        assert!(EXPIRED_JOBS_COUNTER.get() > expired);
+
+        // a job getting a slot in time runs, its guest cut to the lifetime left
+        drop(running);
+        let deadline = JobDeadline(Instant::now() + Duration::from_secs(60));
+        assert!(acquire_before(&limit, Some(deadline)).await.is_some());
+        assert!(deadline.guest_timeout(None) <= Duration::from_secs(60));
+        assert_eq!(
+            deadline.guest_timeout(Some(Duration::from_secs(1))),
+            Duration::from_secs(1)
+        );
+        assert_eq!(deadline.job_error("failed".to_owned()), "failed");
+    }
+}
@@ -0,0 +1,97 @@
+//! The command the guest is launched through, e.g. `sudo gramine-sgx` or `ego run`, so
+//! that the SGX runtime can be switched without code changes.
+use std::{
+    env,
+    ffi::OsStr,
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use serde_with::{DeserializeFromStr, SerializeDisplay};
+use tokio::process::Command;
+
+/// The launcher of the default runtime.
+pub const DEFAULT_GUEST_LAUNCHER: &str = "sudo gramine-sgx";
+
+/// A program and its arguments, separated by whitespace, the guest executable and its
+/// arguments being appended to them.
+#[derive(Debug, Clone, PartialEq, Eq, DeserializeFromStr, SerializeDisplay)]
+pub struct GuestLauncher(Vec<String>);
+
+impl FromStr for GuestLauncher {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let words: Vec<String> = s.split_whitespace().map(ToOwned::to_owned).collect();
+        if words.is_empty() {
+            return Err("empty guest launcher".to_owned());
+        }
+        Ok(GuestLauncher(words))
+    }
+}
+
+impl fmt::Display for GuestLauncher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0.join(" "))
+    }
+}
+
+impl Default for GuestLauncher {
+    fn default() -> Self {
+        DEFAULT_GUEST_LAUNCHER.parse().unwrap()
+    }
+}
+
+impl GuestLauncher {
+    /// The command launching `bin`, to which the guest arguments are appended.
+    pub fn command(&self, bin: impl AsRef<OsStr>) -> Command {
+        let mut cmd = Command::new(&self.0[0]);
+        cmd.args(&self.0[1..]).arg(bin);
+        cmd
+    }
+
+    /// The path of the program of the launcher, looked up in `PATH` unless it has a
+    /// directory.
+    pub fn find_program(&self) -> Result<PathBuf, String> {
+        let program = Path::new(&self.0[0]);
+        if program.components().count() > 1 {
+            return match program.is_file() {
+                true => Ok(program.to_path_buf()),
+                false => Err(format!("{:?} does not exist", program)),
+            };
+        }
+        env::var_os("PATH")
+            .iter()
+            .flat_map(env::split_paths)
+            .map(|dir| dir.join(program))
+            .find(|path| path.is_file())
+            .ok_or_else(|| format!("{:?} is not in PATH", program))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn launchers_are_split_on_whitespace() {
+        let launcher: GuestLauncher = " ego  run ".parse().unwrap();
+        assert_eq!(launcher, GuestLauncher(vec!["ego".into(), "run".into()]));
+        assert_eq!(launcher.to_string(), "ego run");
+        assert!("  ".parse::<GuestLauncher>().is_err());
+
+        assert!("sh -c"
+            .parse::<GuestLauncher>()
+            .unwrap()
+            .find_program()
+            .is_ok());
+        let missing: GuestLauncher = "raiko-missing-launcher run".parse().unwrap();
+        assert!(missing.find_program().unwrap_err().contains("not in PATH"));
+        let missing: GuestLauncher = "/nonexistent/ego run".parse().unwrap();
+        assert!(missing
+            .find_program()
+            .unwrap_err()
+            .contains("does not exist"));
+    }
+}
@@ -0,0 +1,72 @@
+//! Local HTTP servers standing in for the RPC nodes, object stores and provers of the
+//! tests.
+use std::{convert::Infallible, future::Future, net::SocketAddr, time::Duration};
+
+use hyper::{
+    server::conn::{AddrIncoming, AddrStream},
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server,
+};
+use serde_json::Value;
+
+use super::idle_timeout::{IdleIncoming, IdleStream, InFlight, InFlightFuture};
+
+/// Serves `handle` on a free local port, returning the url of the server.
+pub fn serve<F, Fut>(handle: F) -> String
+where
+    F: Fn(Request<Body>) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = Response<Body>> + Send + 'static,
+{
+    let addr = serve_on(SocketAddr::from(([127, 0, 0, 1], 0)), None, handle);
+    format!("http://{}", addr)
+}
+
+/// Serves `handle` on `addr`, closing the connections once idle for `idle_timeout` as the
+/// server does. Returns the address served.
+pub fn serve_on<F, Fut>(addr: SocketAddr, idle_timeout: Option<Duration>, handle: F) -> SocketAddr
+where
+    F: Fn(Request<Body>) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = Response<Body>> + Send + 'static,
+{
+    let incoming = AddrIncoming::bind(&addr).unwrap();
+    let addr = incoming.local_addr();
+    let make_service = make_service_fn(move |conn: &IdleStream<AddrStream>| {
+        let (handle, in_flight) = (handle.clone(), conn.in_flight().cloned());
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let guard = in_flight.as_ref().map(InFlight::begin);
+                let response = handle(req);
+                InFlightFuture::new(async move { Ok::<_, Infallible>(response.await) }, guard)
+            }))
+        }
+    });
+    tokio::spawn(Server::builder(IdleIncoming::new(incoming, idle_timeout)).serve(make_service));
+    addr
+}
+
+/// Serves a JSON-RPC node answering each request with the result `answer` gives it.
+pub fn serve_json_rpc<F>(answer: F) -> String
+where
+    F: Fn(&Value) -> Value + Clone + Send + 'static,
+{
+    serve(move |req| {
+        let answer = answer.clone();
+        async move {
+            let request = read_json(req).await;
+            json_rpc_response(&request, serde_json::json!({ "result": answer(&request) }))
+        }
+    })
+}
+
+/// The JSON body of `req`.
+pub async fn read_json(req: Request<Body>) -> Value {
+    let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
+
+/// Answers the JSON-RPC `request` with `response`, its `result` or its `error`.
+pub fn json_rpc_response(request: &Value, mut response: Value) -> Response<Body> {
+    response["jsonrpc"] = "2.0".into();
+    response["id"] = request["id"].clone();
+    Response::new(Body::from(response.to_string()))
+}
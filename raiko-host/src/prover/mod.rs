@@ -1,10 +1,47 @@
+pub mod allowed_range;
+pub mod benchmark;
+pub mod cache_pins;
+pub mod client_connections;
+pub mod compaction;
+pub mod concurrency;
 pub mod consts;
 pub mod context;
+pub mod cpu_affinity;
+pub mod disk_budget;
+pub mod empty_block;
+pub mod enclave_memory;
 pub mod error;
+pub mod estimate;
 pub mod execution;
+pub mod failure_dump;
+pub mod feasibility;
+pub mod guest_image;
+pub mod idempotency;
+pub mod idle_timeout;
+pub mod job_lifetime;
 pub mod json_rpc;
+pub mod launcher;
+#[cfg(test)]
+mod mock_server;
 pub mod prepare_input;
 pub mod proof;
+pub mod proof_history;
+pub mod record;
 pub mod request;
+pub mod reverify;
+pub mod rpc_benchmark;
+#[cfg(feature = "s3")]
+pub mod s3_sink;
 pub mod server;
+pub mod shedding;
+pub mod sink;
+pub mod startup_probe;
+pub mod upload;
 pub mod utils;
+pub mod validate;
+#[cfg(feature = "verifier-simulate")]
+pub mod verifier_simulate;
+pub mod verify_cache;
+pub mod warm;
+pub mod warmup;
+pub mod witness_store;
@@ -1,20 +1,24 @@
 //! Prepare Input for guest
-use std::fmt::Debug;
+use std::{fmt::Debug, sync::Arc};
 
 use zeth_lib::{
     block_builder::NetworkStrategyBundle,
     consts::{get_taiko_chain_spec, ETH_MAINNET_CHAIN_SPEC},
-    host::Init,
-    taiko::host::TaikoExtra,
+    host::{
+        provider::{rpc_call_budget::RpcCallBudget, RpcOptions},
+        Init,
+    },
+    taiko::host::{read_system_overrides, TaikoExtra},
     EthereumTxEssence,
 };
 
 use super::{
     context::Context,
     error::Result,
-    request::{ProofRequest, PseZkRequest, SgxRequest},
+    request::{MultiRequest, ProofRequest, PseZkRequest, SgxRequest},
     utils::cache_file_path,
 };
+use crate::metrics::observe_rpc_calls;
 
 /// prepare input data for guests
 pub async fn prepare_input<N: NetworkStrategyBundle<TxEssence = EthereumTxEssence>>(
@@ -31,34 +35,75 @@ where
             l2_rpc,
             prover,
             graffiti,
+            data_source,
+            skip_cache,
+            state_overrides,
+            ..
+        })
+        | ProofRequest::Multi(MultiRequest {
+            block:
+                SgxRequest {
+                    block,
+                    l1_rpc,
+                    l2_rpc,
+                    prover,
+                    graffiti,
+                    data_source,
+                    skip_cache,
+                    state_overrides,
+                    ..
+                },
+            ..
         }) => {
             let l2_block = *block;
-            let l2_cache_path = cache_file_path(&ctx.cache_path, l2_block, false);
+            let l2_cache_path = cache_file_path(&ctx.cache_path, ctx.l2_chain_id, l2_block, false);
 
-            let l2_spec = get_taiko_chain_spec(&ctx.l2_chain);
-            let l2_rpc = l2_rpc.to_owned();
+            let mut l2_spec =
+                get_taiko_chain_spec(&ctx.l2_chain).with_mismatch_policy(ctx.on_chainspec_mismatch);
+            if let Some(chain_id) = ctx.chain_id_override {
+                l2_spec = l2_spec.with_chain_id_override(chain_id)?;
+            }
+            let mut l2_spec = l2_spec.with_consensus_overrides(ctx.consensus_overrides);
+            if let Some(path) = &ctx.system_overrides {
+                l2_spec = l2_spec.with_system_overrides(read_system_overrides(path)?);
+            }
+            if let Some(overrides) = state_overrides {
+                l2_spec = l2_spec.with_state_overrides(overrides.clone());
+            }
+            // the benchmarks only read the cache
+            let l2_rpc = (!ctx.benchmark_mode).then(|| l2_rpc.to_owned());
 
             let l1_spec = ETH_MAINNET_CHAIN_SPEC.clone();
-            let l1_cache_path = cache_file_path(&ctx.cache_path, l2_block, true);
-            let l1_rpc = l1_rpc.to_owned();
+            let l1_cache_path = cache_file_path(&ctx.cache_path, ctx.l2_chain_id, l2_block, true);
+            let l1_rpc = (!ctx.benchmark_mode).then(|| l1_rpc.to_owned());
             let prover = prover.to_owned();
             let graffiti = *graffiti;
+            let rpc_options = ctx.rpc_options_for(*data_source)?;
+            let rpc_call_budget = Arc::new(RpcCallBudget::new(rpc_options.max_rpc_calls_per_block));
+            let rpc_options = RpcOptions {
+                skip_cache_reads: *skip_cache,
+                rpc_call_budget: Some(rpc_call_budget.clone()),
+                ..rpc_options
+            };
             // run sync task in blocking mode
-            tokio::task::spawn_blocking(move || {
+            let input = tokio::task::spawn_blocking(move || {
                 zeth_lib::taiko::host::get_taiko_initial_data::<N>(
                     Some(l1_cache_path.into_os_string().into_string().unwrap()),
                     l1_spec,
-                    Some(l1_rpc),
+                    l1_rpc,
                     prover,
                     Some(l2_cache_path.into_os_string().into_string().unwrap()),
                     l2_spec,
-                    Some(l2_rpc),
+                    l2_rpc,
                     l2_block,
                     graffiti,
+                    rpc_options,
                 )
             })
-            .await?
-            .map_err(Into::into)
+            .await?;
+            // the calls of the blocks over the budget too
+            observe_rpc_calls(l2_block, rpc_call_budget.calls());
+            input.map_err(Into::into)
         }
         ProofRequest::PseZk(PseZkRequest { .. }) => todo!(),
     }
@@ -1,30 +1,76 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    collections::VecDeque,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
 
 use lru_time_cache::LruCache;
-use zeth_primitives::{Address, B256};
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+use zeth_lib::host::atomic_file::{temp_file_target, write_atomically};
+use zeth_primitives::{keccak256, Address, B256};
 
 use super::ProofType;
+
+/// Version of the persisted proof files. Files of another version are skipped on load.
+pub const PROOF_FORMAT_VERSION: u32 = 1;
+
+/// Extension of the temporary files of the proofs being persisted, named
+/// `{file name}.{random}.part`.
+pub const PROOF_TMP_EXTENSION: &str = "part";
+
 pub struct CachedProof {
     proof: String,
 }
 
-#[derive(PartialEq, Eq, PartialOrd, Ord, Clone)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct CacheKey {
     pub proof_type: ProofType,
+    pub chain_id: u64,
     pub block: u64,
     pub prover: Address,
     pub graffiti: B256,
 }
 
+impl CacheKey {
+    /// File name of the persisted proof, the hash of the key.
+    fn file_name(&self) -> String {
+        let mut data = vec![self.proof_type.clone() as u8];
+        data.extend(self.chain_id.to_be_bytes());
+        data.extend(self.block.to_be_bytes());
+        data.extend(self.prover.as_slice());
+        data.extend(self.graffiti.as_slice());
+        format!("{}.json", hex::encode(keccak256(data)))
+    }
+}
+
 impl AsRef<CacheKey> for CacheKey {
     fn as_ref(&self) -> &CacheKey {
         self
     }
 }
 
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PersistedProof {
+    version: u32,
+    key: CacheKey,
+    proof: String,
+}
+
 #[derive(Clone)]
 pub struct Cache {
     lru_cache: Arc<Mutex<LruCache<CacheKey, CachedProof>>>,
+    capacity: usize,
+    /// directory the proofs are persisted in, holding at most `capacity` proofs
+    dir: Option<PathBuf>,
+    /// names of the persisted proof files, from the oldest to the most recent, whose lock
+    /// is held by the persisting writes
+    persisted: Arc<Mutex<VecDeque<String>>>,
 }
 
 impl Cache {
@@ -32,7 +78,36 @@ impl Cache {
         let lru_cache = LruCache::with_capacity(capacity);
         Cache {
             lru_cache: Arc::new(Mutex::new(lru_cache)),
+            capacity,
+            dir: None,
+            persisted: Default::default(),
+        }
+    }
+
+    /// Creates a cache persisting the proofs in `dir`, loaded with the most recent proofs
+    /// persisted by previous runs.
+    pub fn with_dir(capacity: usize, dir: PathBuf) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        let mut cache = Cache::new(capacity);
+        let mut loaded = 0;
+        let mut persisted = VecDeque::new();
+        // oldest first, so that the most recent proofs end up the most recently used
+        for (_, path) in prune_dir(&dir, capacity)? {
+            if let Some(file_name) = path.file_name().and_then(|name| name.to_str()) {
+                persisted.push_back(file_name.to_owned());
+            }
+            match read_persisted_proof(&path) {
+                Some(proof) => {
+                    cache.set(proof.key, proof.proof);
+                    loaded += 1;
+                }
+                None => warn!("Skipping incompatible proof cache file {:?}", path),
+            }
         }
+        info!("Loaded {} proofs from {:?}", loaded, dir);
+        cache.persisted = Arc::new(Mutex::new(persisted));
+        cache.dir = Some(dir);
+        Ok(cache)
     }
 
     pub fn get<T: AsRef<CacheKey>>(&self, cache_key: T) -> Option<String> {
@@ -44,9 +119,204 @@ impl Cache {
         }
     }
 
+    /// Caches the proof, persisting it in the background when the cache has a directory,
+    /// which must then be within the tokio runtime.
     pub fn set(&self, cache_key: CacheKey, proof: String) {
+        self.set_and_persist(cache_key, proof);
+    }
+
+    /// Caches the proof, returning its persisting, if any.
+    fn set_and_persist(&self, cache_key: CacheKey, proof: String) -> Option<JoinHandle<()>> {
+        let persisting = self.dir.clone().map(|dir| {
+            let persisted = PersistedProof {
+                version: PROOF_FORMAT_VERSION,
+                key: cache_key.clone(),
+                proof: proof.clone(),
+            };
+            let files = self.persisted.clone();
+            let capacity = self.capacity;
+            tokio::task::spawn_blocking(move || {
+                let mut files = files.lock().unwrap();
+                if let Err(err) = persist_proof(&dir, &persisted, &mut files, capacity) {
+                    warn!("Failed to persist the proof in {:?}: {:#}", dir, err);
+                }
+            })
+        });
         let mut inner_cache = self.lru_cache.lock().unwrap();
         let entry = CachedProof { proof };
         inner_cache.insert(cache_key, entry);
+        persisting
+    }
+}
+
+fn read_persisted_proof(path: &Path) -> Option<PersistedProof> {
+    let persisted: PersistedProof = serde_json::from_slice(&fs::read(path).ok()?).ok()?;
+    (persisted.version == PROOF_FORMAT_VERSION).then_some(persisted)
+}
+
+/// Checks that the proof file at `path` is of the current version and is named after
+/// the hash of its key, which a truncated or altered file is not.
+pub fn check_proof_file(path: &Path) -> Result<(), String> {
+    let data = fs::read(path).map_err(|e| e.to_string())?;
+    let persisted: PersistedProof =
+        serde_json::from_slice(&data).map_err(|e| format!("invalid proof file: {}", e))?;
+    if persisted.version != PROOF_FORMAT_VERSION {
+        return Err(format!(
+            "proof file of version {}, expected {}",
+            persisted.version, PROOF_FORMAT_VERSION
+        ));
+    }
+    let expected = persisted.key.file_name();
+    if path.file_name().and_then(|name| name.to_str()) != Some(expected.as_str()) {
+        return Err(format!("the key of the proof file hashes to {}", expected));
+    }
+    Ok(())
+}
+
+/// Writes the proof file, then removes the oldest of `files` beyond `capacity`.
+fn persist_proof(
+    dir: &Path,
+    persisted: &PersistedProof,
+    files: &mut VecDeque<String>,
+    capacity: usize,
+) -> anyhow::Result<()> {
+    let file_name = persisted.key.file_name();
+    write_atomically(&dir.join(&file_name), PROOF_TMP_EXTENSION, |file| {
+        Ok(serde_json::to_writer(file, persisted)?)
+    })?;
+    files.retain(|name| *name != file_name);
+    files.push_back(file_name);
+    while files.len() > capacity {
+        let Some(oldest) = files.pop_front() else {
+            break;
+        };
+        match fs::remove_file(dir.join(oldest)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Whether `file_name` is the one of a proof file being persisted.
+pub fn is_proof_temp_file_name(file_name: &str) -> bool {
+    temp_file_target(file_name, PROOF_TMP_EXTENSION).is_some_and(|name| name.ends_with(".json"))
+}
+
+/// Removes the oldest proof files beyond `capacity` and the temporary files left by
+/// interrupted writes, returning the remaining proof files from the oldest to the most
+/// recent.
+fn prune_dir(dir: &Path, capacity: usize) -> io::Result<Vec<(SystemTime, PathBuf)>> {
+    let mut files = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            files.push((entry.metadata()?.modified()?, path));
+        } else if entry
+            .file_name()
+            .to_str()
+            .is_some_and(is_proof_temp_file_name)
+        {
+            fs::remove_file(path)?;
+        }
+    }
+    files.sort();
+    let excess = files.len().saturating_sub(capacity);
+    for (_, path) in files.drain(..excess) {
+        fs::remove_file(path)?;
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(chain_id: u64, block: u64) -> CacheKey {
+        CacheKey {
+            proof_type: ProofType::Sgx,
+            chain_id,
+            block,
+            prover: Address::ZERO,
+            graffiti: B256::ZERO,
+        }
+    }
+
+    async fn set_persisted(cache: &Cache, key: CacheKey, proof: &str) {
+        let persisting = cache.set_and_persist(key, proof.to_owned());
+        persisting.unwrap().await.unwrap();
+    }
+
+    #[test]
+    fn test_same_block_of_different_chains() {
+        let cache = Cache::new(10);
+        let key = |chain_id| key(chain_id, 100);
+        cache.set(key(167001), "proof a".to_owned());
+        cache.set(key(167002), "proof b".to_owned());
+        assert_eq!(cache.get(key(167001)).as_deref(), Some("proof a"));
+        assert_eq!(cache.get(key(167002)).as_deref(), Some("proof b"));
+        assert_eq!(cache.get(key(167008)), None);
+    }
+
+    #[tokio::test]
+    async fn test_proofs_survive_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::with_dir(10, dir.path().to_owned()).unwrap();
+        set_persisted(&cache, key(1, 100), "proof a").await;
+        set_persisted(&cache, key(1, 101), "proof b").await;
+        drop(cache);
+
+        let cache = Cache::with_dir(10, dir.path().to_owned()).unwrap();
+        assert_eq!(cache.get(key(1, 100)).as_deref(), Some("proof a"));
+        assert_eq!(cache.get(key(1, 101)).as_deref(), Some("proof b"));
+    }
+
+    #[tokio::test]
+    async fn test_incompatible_files_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::with_dir(10, dir.path().to_owned()).unwrap();
+        set_persisted(&cache, key(1, 100), "proof a").await;
+        let path = dir.path().join(key(1, 100).file_name());
+        let mut persisted: serde_json::Value =
+            serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        persisted["version"] = (PROOF_FORMAT_VERSION + 1).into();
+        fs::write(&path, persisted.to_string()).unwrap();
+        fs::write(dir.path().join("garbage.json"), b"{").unwrap();
+
+        let cache = Cache::with_dir(10, dir.path().to_owned()).unwrap();
+        assert_eq!(cache.get(key(1, 100)), None);
+    }
+
+    #[tokio::test]
+    async fn test_persisted_proofs_are_bounded() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::with_dir(2, dir.path().to_owned()).unwrap();
+        for block in 0..5 {
+            set_persisted(&cache, key(1, block), &format!("proof {}", block)).await;
+        }
+        // set again, so not pruned before the more recent proof
+        set_persisted(&cache, key(1, 3), "proof 3").await;
+        set_persisted(&cache, key(1, 5), "proof 5").await;
+        let mut names: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        names.sort();
+        let mut expected = [key(1, 3).file_name(), key(1, 5).file_name()];
+        expected.sort();
+        assert_eq!(names, expected);
+        // left by an interrupted write
+        fs::write(
+            dir.path()
+                .join(format!("{}.a1B2c3.part", key(1, 6).file_name())),
+            b"{",
+        )
+        .unwrap();
+
+        let cache = Cache::with_dir(1, dir.path().to_owned()).unwrap();
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+        let loaded = (0..5).filter(|&block| cache.get(key(1, block)).is_some());
+        assert_eq!(loaded.count(), 1);
     }
 }
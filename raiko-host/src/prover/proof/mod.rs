@@ -1,12 +1,14 @@
 //! Generate different proofs for the taiko protocol.
 
-#[allow(dead_code)]
 pub mod cache;
 pub mod pse_zk;
 pub mod sgx;
 
+use serde::{Deserialize, Serialize};
+
 #[allow(dead_code)]
-#[derive(PartialEq, Eq, PartialOrd, Ord, Clone)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ProofType {
     PseZk,
     Sgx,
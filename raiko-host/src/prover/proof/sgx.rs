@@ -1,39 +1,131 @@
-use std::str;
+use std::{
+    process::{Output, Stdio},
+    str,
+    time::Duration,
+};
 
 use tokio::{fs, process::Command};
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
+use zeth_lib::consts::{
+    get_taiko_chain_spec, ChainSpecMismatchPolicy, ReportDataField, ReportDataLayout,
+    REPORT_DATA_SIZE,
+};
+use zeth_primitives::{taiko::QUOTE_REPORT_DATA_OFFSET, ChainId, B256};
 
 use crate::{
     metrics::inc_sgx_error,
     prover::{
         consts::*,
         context::Context,
-        request::{SgxRequest, SgxResponse},
+        request::{QuoteResponse, SgxRequest, SgxResponse},
         utils::{cache_file_path, guest_executable_path},
+        witness_store::Witness,
     },
 };
 
-pub async fn execute_sgx(ctx: &Context, req: &SgxRequest) -> Result<SgxResponse, String> {
+/// The command running the guest in the enclave, without its arguments.
+fn guest_command(ctx: &Context) -> Result<Command, String> {
     let guest_path = guest_executable_path(&ctx.guest_path, SGX_PARENT_DIR);
     debug!("Guest path: {:?}", guest_path);
-    let mut cmd = {
-        let bin_directory = guest_path
-            .parent()
-            .ok_or(String::from("missing sgx executable directory"))?;
-        let bin = guest_path
-            .file_name()
-            .ok_or(String::from("missing sgx executable"))?;
-        let mut cmd = Command::new("sudo");
-        cmd.current_dir(bin_directory)
-            .arg("gramine-sgx")
-            .arg(bin)
-            .arg("one-shot");
-        cmd
-    };
-    let l1_cache_file = cache_file_path(&ctx.cache_path, req.block, true);
-    let l2_cache_file = cache_file_path(&ctx.cache_path, req.block, false);
-    let output = cmd
-        .arg("--blocks-data-file")
+    let bin_directory = guest_path
+        .parent()
+        .ok_or(String::from("missing sgx executable directory"))?;
+    let bin = guest_path
+        .file_name()
+        .ok_or(String::from("missing sgx executable"))?;
+    let mut cmd = ctx.guest_launcher.command(bin);
+    cmd.current_dir(bin_directory);
+    if let Some(cpus) = &ctx.sgx_context.cpu_affinity {
+        debug!("Guest CPU affinity: {}", cpus);
+        cpus.apply(&mut cmd);
+    }
+    Ok(cmd)
+}
+
+/// Starts the enclave once without proving anything, for its pages to be loaded before
+/// the first proof.
+pub async fn warmup_sgx(ctx: &Context) -> Result<(), String> {
+    let mut cmd = guest_command(ctx)?;
+    cmd.arg("--help");
+    let (timeout, _) = ctx.guest_timeout(None);
+    let output = run_guest(cmd, timeout).await?;
+    if !output.status.success() {
+        return Err(format!(
+            "the guest exited with {}: {:?}",
+            output.status,
+            str::from_utf8(&output.stderr)
+        ));
+    }
+    Ok(())
+}
+
+/// Has the guest generate a fresh quote over its active key, `None` outside of an enclave
+/// or with the mock guest.
+pub async fn fetch_quote(ctx: &Context) -> Result<Option<QuoteResponse>, String> {
+    if ctx.benchmark_mock_guest {
+        return Ok(None);
+    }
+    let mut cmd = guest_command(ctx)?;
+    cmd.arg("quote").arg("--l2-chain").arg(&ctx.l2_chain);
+    let (timeout, _) = ctx.guest_timeout(None);
+    let output = run_guest(cmd, timeout).await?;
+    if !output.status.success() {
+        inc_sgx_error(0);
+        return Err(format!(
+            "the guest exited with {}: {:?}",
+            output.status,
+            str::from_utf8(&output.stderr)
+        ));
+    }
+    let layout = get_taiko_chain_spec(&ctx.l2_chain).report_data().clone();
+    let resp = parse_quote(&output.stdout, &layout, ctx.l2_chain_id)?;
+    Ok(resp.quote.is_some().then_some(resp))
+}
+
+/// The quote printed by the guest, checked to attest its key: the report data must be the
+/// one composed with `layout` for `chain_id`.
+fn parse_quote(
+    stdout: &[u8],
+    layout: &ReportDataLayout,
+    chain_id: ChainId,
+) -> Result<QuoteResponse, String> {
+    let stdout = str::from_utf8(stdout).map_err(|e| e.to_string())?;
+    let line = stdout
+        .lines()
+        .rev()
+        .find(|line| line.trim_start().starts_with('{'))
+        .ok_or("the guest printed no quote")?;
+    let resp: QuoteResponse = serde_json::from_str(line).map_err(|e| e.to_string())?;
+    if let Some(quote) = &resp.quote {
+        let quote = hex::decode(quote).map_err(|e| e.to_string())?;
+        let public_key_hash = match layout.0.contains(&ReportDataField::PublicKeyHash) {
+            true => resp
+                .fingerprint
+                .parse::<B256>()
+                .map_err(|_| format!("invalid fingerprint {}", resp.fingerprint))?,
+            false => B256::ZERO,
+        };
+        let expected = layout
+            .compose(resp.instance_address, public_key_hash, chain_id)
+            .map_err(|e| e.to_string())?;
+        let report_data =
+            quote.get(QUOTE_REPORT_DATA_OFFSET..QUOTE_REPORT_DATA_OFFSET + REPORT_DATA_SIZE);
+        if report_data != Some(expected.as_slice()) {
+            return Err(format!(
+                "the quote does not attest the instance {}",
+                resp.instance_address
+            ));
+        }
+    }
+    Ok(resp)
+}
+
+pub async fn execute_sgx(ctx: &Context, req: &SgxRequest) -> Result<SgxResponse, String> {
+    let mut cmd = guest_command(ctx)?;
+    cmd.arg("one-shot");
+    let l1_cache_file = cache_file_path(&ctx.cache_path, ctx.l2_chain_id, req.block, true);
+    let l2_cache_file = cache_file_path(&ctx.cache_path, ctx.l2_chain_id, req.block, false);
+    cmd.arg("--blocks-data-file")
         .arg(&l2_cache_file)
         .arg("--l1-blocks-data-file")
         .arg(&l1_cache_file)
@@ -45,15 +137,74 @@ pub async fn execute_sgx(ctx: &Context, req: &SgxRequest) -> Result<SgxResponse,
         .arg(ctx.sgx_context.instance_id.to_string())
         .arg("--l2-chain")
         .arg(&ctx.l2_chain)
-        .output()
-        .await
-        .map_err(|e| e.to_string())?;
+        .args(
+            ctx.chain_id_override
+                .map(|chain_id| format!("--chain-id-override={}", chain_id)),
+        )
+        .args(
+            (ctx.on_chainspec_mismatch != ChainSpecMismatchPolicy::Error)
+                .then(|| format!("--on-chainspec-mismatch={}", ctx.on_chainspec_mismatch)),
+        )
+        .args(
+            ctx.allow_consensus_overrides
+                .then_some("--allow-consensus-overrides"),
+        )
+        .args(
+            ctx.consensus_overrides
+                .base_fee_per_gas
+                .map(|base_fee| format!("--base-fee-override={}", base_fee)),
+        )
+        .args(
+            ctx.consensus_overrides
+                .gas_limit
+                .map(|gas_limit| format!("--gas-limit-override={}", gas_limit)),
+        )
+        .args(
+            ctx.system_overrides
+                .as_ref()
+                .map(|path| format!("--system-overrides={}", path.display())),
+        )
+        .args(
+            req.state_overrides
+                .as_ref()
+                .map(|overrides| serde_json::to_string(overrides).map_err(|e| e.to_string()))
+                .transpose()?
+                .map(|json| format!("--state-overrides={}", json)),
+        )
+        .args(
+            ctx.rpc_options
+                .kzg_trusted_setup
+                .as_ref()
+                .map(|path| format!("--kzg-trusted-setup={}", path.display())),
+        )
+        .args(ctx.fixed_now.map(|now| format!("--fixed-now={}", now)));
+    let (mut timeout, _) = ctx.guest_timeout(req.timeout_secs);
+    if let Some(deadline) = req.deadline {
+        if deadline.is_expired() {
+            remove_cache_files(ctx, &l1_cache_file, &l2_cache_file).await?;
+            return Err("the job expired before the guest was launched".to_owned());
+        }
+        timeout = Some(deadline.guest_timeout(timeout));
+    }
+    let output = run_guest(cmd, timeout).await;
+    let output = match output {
+        Ok(output) => output,
+        Err(err) => {
+            remove_cache_files(ctx, &l1_cache_file, &l2_cache_file).await?;
+            inc_sgx_error(req.block);
+            return Err(err);
+        }
+    };
     info!("Sgx execution stderr: {:?}", str::from_utf8(&output.stderr));
     info!("Sgx execution stdout: {:?}", str::from_utf8(&output.stdout));
-    // clean cache file, avoid reorg error
-    for file in &[l1_cache_file, l2_cache_file] {
-        fs::remove_file(file).await.map_err(|e| e.to_string())?;
+    if let Some(witness_store) = &ctx.witness_store {
+        witness_store.insert(Witness {
+            block: req.block,
+            l1_blocks_data: fs::read(&l1_cache_file).await.map_err(|e| e.to_string())?,
+            l2_blocks_data: fs::read(&l2_cache_file).await.map_err(|e| e.to_string())?,
+        });
     }
+    remove_cache_files(ctx, &l1_cache_file, &l2_cache_file).await?;
     if !output.status.success() {
         inc_sgx_error(req.block);
         return Err(output.status.to_string());
@@ -61,14 +212,251 @@ pub async fn execute_sgx(ctx: &Context, req: &SgxRequest) -> Result<SgxResponse,
     parse_sgx_result(output.stdout)
 }
 
+/// Runs the guest, stopping it once `timeout` elapsed.
+async fn run_guest(mut cmd: Command, timeout: Option<Duration>) -> Result<Output, String> {
+    let child = cmd
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| e.to_string())?;
+    let pid = child.id();
+    let Some(timeout) = timeout else {
+        return child.wait_with_output().await.map_err(|e| e.to_string());
+    };
+    match tokio::time::timeout(timeout, child.wait_with_output()).await {
+        Ok(output) => output.map_err(|e| e.to_string()),
+        Err(_) => {
+            warn!("The guest ran for more than {:?}, stopping it", timeout);
+            if let Some(pid) = pid {
+                terminate(pid);
+            }
+            Err(format!("the guest did not finish within {:?}", timeout))
+        }
+    }
+}
+
+/// Asks the process to stop. `sudo` forwards the SIGTERM to the guest, which a SIGKILL
+/// would orphan instead. Does nothing on other systems than Linux.
+fn terminate(pid: u32) {
+    #[cfg(target_os = "linux")]
+    // SAFETY: kill has no memory safety requirements
+    unsafe {
+        libc::kill(pid as libc::pid_t, libc::SIGTERM);
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = pid;
+}
+
+/// Cleans the cache files of the block, avoiding the reorg errors. The benchmarks keep
+/// them, they are the only block data they read.
+async fn remove_cache_files(
+    ctx: &Context,
+    l1_cache_file: &std::path::Path,
+    l2_cache_file: &std::path::Path,
+) -> Result<(), String> {
+    if ctx.benchmark_mode {
+        return Ok(());
+    }
+    for file in [l1_cache_file, l2_cache_file] {
+        fs::remove_file(file).await.map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
 fn parse_sgx_result(output: Vec<u8>) -> Result<SgxResponse, String> {
     // parse result of sgx execution
     let output = String::from_utf8(output).map_err(|e| e.to_string())?;
     let mut proof = String::new();
+    let mut envelope = None;
+    let mut chain_spec_mismatch = None;
     for line in output.lines() {
         if let Some(_proof) = line.trim().strip_prefix(SGX_PROOF_PREFIX) {
             proof = _proof.trim().to_owned();
+        } else if let Some(_envelope) = line.trim().strip_prefix(SGX_ENVELOPE_PREFIX) {
+            envelope = Some(serde_json::from_str(_envelope.trim()).map_err(|e| e.to_string())?);
+        } else if let Some(mismatch) = line.trim().strip_prefix(SGX_CHAIN_SPEC_MISMATCH_PREFIX) {
+            chain_spec_mismatch =
+                Some(serde_json::from_str(mismatch.trim()).map_err(|e| e.to_string())?);
         }
     }
-    Ok(SgxResponse { proof })
+    Ok(SgxResponse {
+        proof,
+        envelope,
+        chain_spec_mismatch,
+        benchmark: false,
+        metadata: Default::default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, time::Instant};
+
+    use serde_json::json;
+    use zeth_primitives::Address;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn request_timeout_fires_before_the_global_one() {
+        let ctx = Context {
+            guest_timeout: Some(Duration::from_secs(60)),
+            max_guest_timeout: Some(Duration::from_secs(120)),
+            ..Default::default()
+        };
+        assert_eq!(
+            ctx.guest_timeout(None),
+            (Some(Duration::from_secs(60)), false)
+        );
+        assert_eq!(
+            ctx.guest_timeout(Some(600)),
+            (Some(Duration::from_secs(120)), true)
+        );
+
+        let (timeout, clamped) = ctx.guest_timeout(Some(1));
+        assert!(!clamped);
+        let mut guest = Command::new("sleep");
+        guest.arg("30");
+        let start = Instant::now();
+        let err = run_guest(guest, timeout).await.unwrap_err();
+        assert!(err.contains("did not finish within 1s"));
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn guest_is_spawned_through_the_launcher() {
+        let args = |cmd: &Command| {
+            let cmd = cmd.as_std();
+            let mut line = vec![cmd.get_program().to_string_lossy().into_owned()];
+            line.extend(cmd.get_args().map(|arg| arg.to_string_lossy().into_owned()));
+            line
+        };
+        let ctx = Context {
+            guest_path: "/opt/raiko/guest".into(),
+            ..Default::default()
+        };
+        let cmd = guest_command(&ctx).unwrap();
+        assert_eq!(args(&cmd), ["sudo", "gramine-sgx", "raiko-guest"]);
+
+        let ctx = Context {
+            guest_launcher: "ego run".parse().unwrap(),
+            ..ctx
+        };
+        let mut cmd = guest_command(&ctx).unwrap();
+        cmd.arg("quote");
+        assert_eq!(args(&cmd), ["ego", "run", "raiko-guest", "quote"]);
+        assert_eq!(
+            cmd.as_std().get_current_dir(),
+            Some(Path::new("/opt/raiko/guest/sgx"))
+        );
+    }
+
+    #[test]
+    fn quotes_must_attest_the_active_key() {
+        let instance = Address::repeat_byte(0x11);
+        let mut quote = vec![0; 432];
+        quote[QUOTE_REPORT_DATA_OFFSET..QUOTE_REPORT_DATA_OFFSET + 20]
+            .copy_from_slice(instance.as_slice());
+        let output = |quote: &[u8]| {
+            format!(
+                "Loading the key\n{}\n",
+                json!({
+                    "publicKey": "0x04",
+                    "fingerprint": "0x05",
+                    "instanceAddress": instance.to_string(),
+                    "mrEnclave": "06",
+                    "mrSigner": "07",
+                    "quote": hex::encode(quote),
+                })
+            )
+        };
+        let layout = ReportDataLayout::default();
+        let resp = parse_quote(output(&quote).as_bytes(), &layout, 167).unwrap();
+        assert_eq!(resp.instance_address, instance);
+        assert_eq!(resp.mr_enclave.as_deref(), Some("06"));
+        assert_eq!(resp.quote, Some(hex::encode(&quote)));
+
+        // a quote over another key
+        quote[QUOTE_REPORT_DATA_OFFSET] = 0x22;
+        let err = parse_quote(output(&quote).as_bytes(), &layout, 167).unwrap_err();
+        assert!(err.contains("does not attest"), "{}", err);
+        // trailing bytes past the address
+        quote[QUOTE_REPORT_DATA_OFFSET] = 0x11;
+        quote[QUOTE_REPORT_DATA_OFFSET + 20] = 0x01;
+        assert!(parse_quote(output(&quote).as_bytes(), &layout, 167).is_err());
+
+        // outside of an enclave
+        let output = json!({
+            "publicKey": "0x04",
+            "fingerprint": "0x05",
+            "instanceAddress": instance.to_string(),
+        });
+        let resp = parse_quote(output.to_string().as_bytes(), &layout, 167).unwrap();
+        assert!(resp.quote.is_none());
+    }
+
+    #[test]
+    fn quotes_must_match_the_report_data_layout() {
+        let instance = Address::repeat_byte(0x11);
+        let fingerprint = B256::repeat_byte(0x33);
+        let layout = ReportDataLayout(vec![
+            ReportDataField::PublicKeyHash,
+            ReportDataField::ChainId,
+        ]);
+        let output = |report_data: &[u8]| {
+            let mut quote = vec![0; 432];
+            quote[QUOTE_REPORT_DATA_OFFSET..QUOTE_REPORT_DATA_OFFSET + report_data.len()]
+                .copy_from_slice(report_data);
+            json!({
+                "publicKey": "0x04",
+                "fingerprint": fingerprint.to_string(),
+                "instanceAddress": instance.to_string(),
+                "quote": hex::encode(quote),
+            })
+            .to_string()
+        };
+        let report_data = [fingerprint.as_slice(), &167u64.to_be_bytes()].concat();
+        assert!(parse_quote(output(&report_data).as_bytes(), &layout, 167).is_ok());
+
+        // attested for another chain
+        let err = parse_quote(output(&report_data).as_bytes(), &layout, 168).unwrap_err();
+        assert!(err.contains("does not attest"), "{}", err);
+        // the report data of the default layout
+        let err = parse_quote(output(instance.as_slice()).as_bytes(), &layout, 167).unwrap_err();
+        assert!(err.contains("does not attest"), "{}", err);
+    }
+
+    #[test]
+    fn parse_sgx_result_with_envelope() {
+        let output = "Proof: 0x01\n\
+            Public key: 0x02\n\
+            Envelope: {\"proof\":\"0x01\",\"publicInputs\":\"0x1111111111111111111111111111111111111111111111111111111111111111\",\"timestamp\":1,\"signer\":\"0x0000000000000000000000000000000000000000\",\"signature\":{\"v\":0,\"r\":\"0x1\",\"s\":\"0x2\"}}\n";
+        let resp = parse_sgx_result(output.as_bytes().to_vec()).unwrap();
+        assert_eq!(resp.proof, "0x01");
+        let envelope = resp.envelope.unwrap();
+        assert_eq!(envelope.proof.to_vec(), vec![1]);
+        assert_eq!(envelope.timestamp, 1);
+
+        // guests of older versions print no envelope
+        let resp = parse_sgx_result(b"Proof: 0x01\n".to_vec()).unwrap();
+        assert!(resp.envelope.is_none());
+    }
+
+    #[test]
+    fn parse_sgx_result_with_chain_spec_mismatch() {
+        let output = "Proof: 0x01\n\
+            Chain spec mismatch: {\"policy\":\"warn\",\"configChainId\":167001,\"dataChainId\":167002,\"configFork\":\"SHANGHAI\",\"dataFork\":null}\n";
+        let mismatch = parse_sgx_result(output.as_bytes().to_vec())
+            .unwrap()
+            .chain_spec_mismatch
+            .unwrap();
+        assert_eq!(mismatch.policy, ChainSpecMismatchPolicy::Warn);
+        assert_eq!(mismatch.data_chain_id, Some(167002));
+        assert_eq!(mismatch.data_fork, None);
+
+        assert!(parse_sgx_result(b"Proof: 0x01\n".to_vec())
+            .unwrap()
+            .chain_spec_mismatch
+            .is_none());
+    }
 }
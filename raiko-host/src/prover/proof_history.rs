@@ -0,0 +1,169 @@
+//! The most recent proofs served, for `GET /proofs/recent`: a feed of the activity of
+//! the prover, next to the aggregates of the metrics.
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+
+use super::{proof::ProofType, request::ProofRequest};
+
+/// How a proof request ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProofOutcome {
+    Success,
+    Failure,
+}
+
+/// A proof request served. Neither the metadata nor the errors of the request are kept,
+/// they may hold URLs with credentials.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProofRecord {
+    /// increasing with the completion, the cursor of the pages
+    pub id: u64,
+    pub chain: String,
+    /// `None` for the requests without a block
+    pub block: Option<u64>,
+    pub outcome: ProofOutcome,
+    pub duration_ms: u64,
+    /// the proof types requested
+    pub backend: Vec<ProofType>,
+    /// seconds since the unix epoch
+    pub completed_at: u64,
+}
+
+/// A page of the history, most recent first.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProofHistoryPage {
+    pub proofs: Vec<ProofRecord>,
+    /// the `cursor` of the next page, `None` on the last one
+    pub next_cursor: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+struct History {
+    records: VecDeque<ProofRecord>,
+    next_id: u64,
+}
+
+/// The last `capacity` proof requests served, the oldest ones being dropped first.
+#[derive(Debug, Clone)]
+pub struct ProofHistory {
+    capacity: usize,
+    history: Arc<Mutex<History>>,
+}
+
+impl ProofHistory {
+    pub fn new(capacity: usize) -> Self {
+        ProofHistory {
+            capacity,
+            history: Default::default(),
+        }
+    }
+
+    /// Records a proof of `chain` for `req`, completed after `duration`.
+    pub fn record(&self, chain: &str, req: &ProofRequest, succeeded: bool, duration: Duration) {
+        let (block, backend) = match req {
+            ProofRequest::Sgx(req) => (Some(req.block), vec![ProofType::Sgx]),
+            ProofRequest::Multi(req) => (Some(req.block.block), req.proof_types.clone()),
+            ProofRequest::PseZk(_) => (None, vec![ProofType::PseZk]),
+        };
+        let completed_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut history = self.history.lock().unwrap();
+        let record = ProofRecord {
+            id: history.next_id,
+            chain: chain.to_owned(),
+            block,
+            outcome: match succeeded {
+                true => ProofOutcome::Success,
+                false => ProofOutcome::Failure,
+            },
+            duration_ms: duration.as_millis() as u64,
+            backend,
+            completed_at,
+        };
+        history.next_id += 1;
+        history.records.push_back(record);
+        while history.records.len() > self.capacity {
+            history.records.pop_front();
+        }
+    }
+
+    /// The `limit` most recent proofs completed before the one of `cursor`, or the most
+    /// recent ones without a cursor.
+    pub fn recent(&self, limit: usize, cursor: Option<u64>) -> ProofHistoryPage {
+        let history = self.history.lock().unwrap();
+        let mut older = history
+            .records
+            .iter()
+            .rev()
+            .filter(|record| cursor.map_or(true, |cursor| record.id < cursor));
+        let proofs: Vec<ProofRecord> = older.by_ref().take(limit).cloned().collect();
+        let next_cursor = match older.next() {
+            Some(_) => proofs.last().map(|record| record.id),
+            None => None,
+        };
+        ProofHistoryPage {
+            proofs,
+            next_cursor,
+        }
+    }
+}
+
+impl Default for ProofHistory {
+    fn default() -> Self {
+        ProofHistory::new(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn sgx_request(block: u64) -> ProofRequest {
+        serde_json::from_value(json!({
+            "type": "Sgx",
+            "block": block,
+            "l2Rpc": "http://l2",
+            "l1Rpc": "http://l1",
+            "prover": "0x0000000000000000000000000000000000000000",
+            "graffiti": "0x0000000000000000000000000000000000000000000000000000000000000000",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn recent_proofs_are_listed_most_recent_first() {
+        let history = ProofHistory::new(4);
+        for block in 1..=5 {
+            let succeeded = block != 3;
+            let duration = Duration::from_millis(block * 10);
+            history.record("testnet", &sgx_request(block), succeeded, duration);
+        }
+
+        // the first proof was dropped for the capacity
+        let page = history.recent(3, None);
+        let blocks: Vec<_> = page.proofs.iter().map(|record| record.block).collect();
+        assert_eq!(blocks, [Some(5), Some(4), Some(3)]);
+        assert_eq!(page.proofs[0].chain, "testnet");
+        assert_eq!(page.proofs[0].duration_ms, 50);
+        assert_eq!(page.proofs[0].backend, [ProofType::Sgx]);
+        assert_eq!(page.proofs[2].outcome, ProofOutcome::Failure);
+
+        let page = history.recent(3, page.next_cursor);
+        let blocks: Vec<_> = page.proofs.iter().map(|record| record.block).collect();
+        assert_eq!(blocks, [Some(2)]);
+        assert_eq!(page.next_cursor, None);
+        assert_eq!(page.proofs[0].outcome, ProofOutcome::Success);
+    }
+}
@@ -0,0 +1,307 @@
+//! Recording of the proof requests served, and their replay against another server to
+//! catch the regressions of a new build.
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context as _, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use crate::{config::redact_urls, prover::utils::REDACTED};
+
+/// A proof request as recorded, with the result it was served.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordedRequest {
+    /// unix time the request was served at, in seconds
+    pub recorded_at: u64,
+    pub method: String,
+    /// the params of the request, the credentials of their URLs redacted
+    pub params: Vec<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Appends the proof requests served to a file, one JSON line each, while enabled.
+#[derive(Debug, Clone)]
+pub struct RequestRecorder {
+    path: PathBuf,
+    enabled: Arc<AtomicBool>,
+    file: Arc<Mutex<()>>,
+}
+
+impl RequestRecorder {
+    pub fn new(path: PathBuf) -> Self {
+        RequestRecorder {
+            path,
+            enabled: Arc::new(AtomicBool::new(true)),
+            file: Default::default(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+        info!(
+            "{} recording the proof requests to {}",
+            if enabled { "Started" } else { "Stopped" },
+            self.path.display()
+        );
+    }
+
+    /// Records the request and its result, unless recording is disabled. Failures to
+    /// record are logged, they don't fail the request.
+    pub fn record(&self, method: &str, params: &[Value], result: &Result<Value, String>) {
+        if !self.is_enabled() {
+            return;
+        }
+        let mut params = params.to_vec();
+        params.iter_mut().for_each(redact_urls);
+        let recorded = RecordedRequest {
+            recorded_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            method: method.to_owned(),
+            params,
+            result: result.as_ref().ok().cloned(),
+            error: result.as_ref().err().cloned(),
+        };
+        if let Err(err) = self.append(&recorded) {
+            warn!(
+                "Failed to record a proof request to {}: {}",
+                self.path.display(),
+                err
+            );
+        }
+    }
+
+    fn append(&self, recorded: &RecordedRequest) -> Result<()> {
+        let mut line = serde_json::to_vec(recorded)?;
+        line.push(b'\n');
+        // one writer at a time, for the lines not to interleave
+        let _file = self.file.lock().unwrap();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        Ok(file.write_all(&line)?)
+    }
+}
+
+/// The recorded requests of the file written by a `RequestRecorder`.
+pub fn read_recorded(path: &Path) -> Result<Vec<RecordedRequest>> {
+    let file = File::open(path).with_context(|| format!("open {}", path.display()))?;
+    BufReader::new(file)
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.as_ref().is_ok_and(|line| line.trim().is_empty()))
+        .map(|(n, line)| {
+            let line = line?;
+            serde_json::from_str(&line)
+                .with_context(|| format!("invalid request on line {} of {}", n + 1, path.display()))
+        })
+        .collect()
+}
+
+/// The RPC URLs the replayed requests are sent with, in place of the redacted ones.
+#[derive(Debug, Default, Clone)]
+pub struct ReplayRpcs {
+    pub l1_rpc: Option<String>,
+    pub l2_rpc: Option<String>,
+}
+
+/// The outcome of a replayed request.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReplayOutcome {
+    Match,
+    /// the comparable parts of the recorded and of the replayed results
+    Mismatch {
+        recorded: Value,
+        replayed: Value,
+    },
+}
+
+/// Sends the recorded requests to `target` one after the other, comparing each result
+/// with the recorded one.
+pub async fn replay(
+    target: &str,
+    requests: &[RecordedRequest],
+    rpcs: &ReplayRpcs,
+) -> Result<Vec<ReplayOutcome>> {
+    let client = reqwest::Client::new();
+    let mut outcomes = vec![];
+    for (n, request) in requests.iter().enumerate() {
+        let mut params = request.params.clone();
+        for param in &mut params {
+            substitute_rpcs(param, rpcs).with_context(|| format!("replay request {}", n))?;
+        }
+        let resp: Value = client
+            .post(target)
+            .json(&json!({
+                "jsonrpc": "2.0",
+                "id": n,
+                "method": request.method,
+                "params": params,
+            }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let recorded = comparable(request.result.as_ref(), request.error.is_some());
+        let replayed = comparable(resp.get("result"), resp.get("error").is_some());
+        outcomes.push(match recorded == replayed {
+            true => ReplayOutcome::Match,
+            false => ReplayOutcome::Mismatch { recorded, replayed },
+        });
+    }
+    Ok(outcomes)
+}
+
+/// Replays the requests recorded in `path` against `target`, failing on any mismatch.
+pub async fn replay_file(path: &Path, target: &str, rpcs: &ReplayRpcs) -> Result<()> {
+    let requests = read_recorded(path)?;
+    let outcomes = replay(target, &requests, rpcs).await?;
+    let mut mismatches = 0;
+    for (n, (request, outcome)) in requests.iter().zip(&outcomes).enumerate() {
+        let block = request
+            .params
+            .first()
+            .and_then(|params| params.get("block"))
+            .cloned()
+            .unwrap_or_default();
+        match outcome {
+            ReplayOutcome::Match => println!("request {} (block {}): match", n, block),
+            ReplayOutcome::Mismatch { recorded, replayed } => {
+                mismatches += 1;
+                println!(
+                    "request {} (block {}): mismatch, recorded {} replayed {}",
+                    n, block, recorded, replayed
+                );
+            }
+        }
+    }
+    if mismatches > 0 {
+        bail!(
+            "{} of the {} requests replayed differently",
+            mismatches,
+            outcomes.len()
+        );
+    }
+    println!("All the {} requests replayed identically", outcomes.len());
+    Ok(())
+}
+
+fn substitute_rpcs(params: &mut Value, rpcs: &ReplayRpcs) -> Result<()> {
+    for (field, rpc, option) in [
+        ("l1Rpc", &rpcs.l1_rpc, "replay_l1_rpc"),
+        ("l2Rpc", &rpcs.l2_rpc, "replay_l2_rpc"),
+    ] {
+        let Some(url) = params.get_mut(field) else {
+            continue;
+        };
+        match rpc {
+            Some(rpc) => *url = rpc.clone().into(),
+            None if url.as_str().is_some_and(|url| url.contains(REDACTED)) => {
+                bail!("the {} of the request was redacted, set {}", field, option)
+            }
+            None => {}
+        }
+    }
+    Ok(())
+}
+
+/// The parts of a result a new build must reproduce. The envelopes are signed at the time
+/// of proving, their timestamps and signatures differ between runs.
+fn comparable(result: Option<&Value>, failed: bool) -> Value {
+    if failed {
+        return json!({ "failed": true });
+    }
+    let mut result = result.cloned().unwrap_or_default();
+    if let Some(envelope) = result.get_mut("envelope").and_then(Value::as_object_mut) {
+        envelope.remove("timestamp");
+        envelope.remove("signature");
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prover::mock_server::serve_json_rpc;
+
+    /// A server answering the proof of every block with `0x{block}`, but for
+    /// `wrong_block`.
+    fn mock_target(wrong_block: u64) -> String {
+        serve_json_rpc(move |req| {
+            let block = req["params"][0]["block"].as_u64().unwrap();
+            let proof = match block == wrong_block {
+                true => "0xbad".to_owned(),
+                false => format!("0x{}", block),
+            };
+            json!({
+                "proof": proof,
+                "envelope": { "publicInputs": block, "timestamp": 2 },
+            })
+        })
+    }
+
+    #[tokio::test]
+    async fn recorded_requests_replay_against_a_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("requests.jsonl");
+        let recorder = RequestRecorder::new(path.clone());
+        for block in [1, 2] {
+            let params = [json!({
+                "block": block,
+                "l1Rpc": "https://l1.example/v2/secret-key",
+                "l2Rpc": "http://l2:8545",
+            })];
+            let result = json!({
+                "proof": format!("0x{}", block),
+                "envelope": { "publicInputs": block, "timestamp": 1 },
+            });
+            recorder.record("proof", &params, &Ok(result));
+        }
+        recorder.set_enabled(false);
+        recorder.record("proof", &[json!({ "block": 3 })], &Err("failed".to_owned()));
+
+        let requests = read_recorded(&path).unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(!std::fs::read_to_string(&path)
+            .unwrap()
+            .contains("secret-key"));
+        assert_eq!(requests[0].params[0]["l2Rpc"], "http://l2:8545");
+
+        // the redacted RPC URL must be replaced
+        let err = replay(&mock_target(0), &requests, &ReplayRpcs::default())
+            .await
+            .unwrap_err();
+        assert!(format!("{:#}", err).contains("set replay_l1_rpc"));
+
+        let rpcs = ReplayRpcs {
+            l1_rpc: Some("http://l1:8545".to_owned()),
+            l2_rpc: None,
+        };
+        let outcomes = replay(&mock_target(0), &requests, &rpcs).await.unwrap();
+        assert_eq!(outcomes, vec![ReplayOutcome::Match, ReplayOutcome::Match]);
+        let outcomes = replay(&mock_target(2), &requests, &rpcs).await.unwrap();
+        assert_eq!(outcomes[0], ReplayOutcome::Match);
+        assert!(matches!(outcomes[1], ReplayOutcome::Mismatch { .. }));
+    }
+}
@@ -1,6 +1,18 @@
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, DisplayFromStr};
-use zeth_primitives::{Address, B256};
+use zeth_lib::{
+    consts::{ReportDataLayout, StateOverrides},
+    host::{provider::data_source::DataSourceKind, validate::FieldMismatch},
+    taiko::spec_mismatch::ChainSpecMismatch,
+};
+use zeth_primitives::{
+    taiko::{ProofEnvelope, PublicInputs, VerificationReport},
+    Address, Bytes, ChainId, B256,
+};
+
+use super::{job_lifetime::JobDeadline, proof::ProofType};
 
 #[derive(Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
@@ -8,6 +20,7 @@ use zeth_primitives::{Address, B256};
 pub enum ProofRequest {
     Sgx(SgxRequest),
     PseZk(PseZkRequest),
+    Multi(MultiRequest),
 }
 
 #[serde_as]
@@ -24,16 +37,127 @@ pub struct SgxRequest {
     #[serde_as(as = "DisplayFromStr")]
     pub prover: Address,
     pub graffiti: B256,
+    /// seconds the guest may run, instead of guest_timeout_secs, bounded by
+    /// max_guest_timeout_secs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+    /// what `l1_rpc` and `l2_rpc` name, one of the configured data_sources, data_source
+    /// by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_source: Option<DataSourceKind>,
+    /// fetch the block data and prove the block again, ignoring the cached block data and
+    /// proof, e.g. for the blocks of the volatile chain tip. The fresh ones are cached
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub skip_cache: bool,
+    /// testing only, accounts overridden in the pre-state of the block, like the state
+    /// overrides of `eth_call`. Requires allow_consensus_overrides, the proof will not
+    /// verify on the chain and is neither cached nor delivered to the sinks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state_overrides: Option<StateOverrides>,
+    /// bookkeeping of the client, echoed in the response but never proved, see
+    /// [MAX_METADATA_BYTES]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, serde_json::Value>,
+    /// when the job expires, with max_job_lifetime_secs, set by the server
+    #[serde(skip)]
+    pub deadline: Option<JobDeadline>,
+}
+
+/// Most bytes the `metadata` of a request may take, as json.
+pub const MAX_METADATA_BYTES: usize = 4096;
+
+impl SgxRequest {
+    pub fn check_metadata(&self) -> Result<(), String> {
+        let size = serde_json::to_vec(&self.metadata)
+            .map_err(|e| e.to_string())?
+            .len();
+        if size > MAX_METADATA_BYTES {
+            return Err(format!(
+                "the metadata takes {} bytes, more than the {} allowed",
+                size, MAX_METADATA_BYTES
+            ));
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct PseZkRequest {}
 
+/// The prover and the graffiti of the proof requests of the chain omitting them.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RequestDefaults {
+    pub prover: Option<Address>,
+    pub graffiti: Option<B256>,
+}
+
+impl RequestDefaults {
+    /// Sets the fields of the defaults missing from the JSON `request`, the ones of the
+    /// request taking precedence.
+    pub fn apply(&self, request: &mut serde_json::Value) {
+        let Some(request) = request.as_object_mut() else {
+            return;
+        };
+        if let Some(prover) = self.prover {
+            request
+                .entry("prover")
+                .or_insert_with(|| prover.to_string().into());
+        }
+        if let Some(graffiti) = self.graffiti {
+            request
+                .entry("graffiti")
+                .or_insert_with(|| graffiti.to_string().into());
+        }
+    }
+}
+
+/// A block proven with each of `proof_types`, from a single witness.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MultiRequest {
+    pub proof_types: Vec<ProofType>,
+    #[serde(flatten)]
+    pub block: SgxRequest,
+}
+
+/// The cached block data of a block to check against the canonical chain.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidateRequest {
+    /// the l2 block number
+    pub block: u64,
+    pub l2_rpc: String,
+    /// also checks the l1 block data when given
+    pub l1_rpc: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ValidateResponse {
+    /// fields of the cached l2 blocks which differ from the l2 node's
+    pub l2: Vec<FieldMismatch>,
+    /// same for the l1 blocks, when checked
+    pub l1: Option<Vec<FieldMismatch>>,
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum ProofResponse {
     Sgx(SgxResponse),
     PseZk(PseZkResponse),
+    Multi(MultiResponse),
+    Skipped(SkippedResponse),
+}
+
+/// A block left unproven, the empty ones with `on_empty_block=skip`.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SkippedResponse {
+    pub block: u64,
+    /// why the block was not proven, e.g. `empty block`
+    pub reason: String,
+    /// the metadata of the request
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, serde_json::Value>,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -41,7 +165,453 @@ pub enum ProofResponse {
 pub struct SgxResponse {
     /// proof format: 4b(id)+20b(pubkey)+65b(signature)
     pub proof: String,
+    /// the proof signed by the instance key, missing from the proofs served from the
+    /// proof cache
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub envelope: Option<ProofEnvelope>,
+    /// how the block data disagreed with the chain spec, when it did
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chain_spec_mismatch: Option<ChainSpecMismatch>,
+    /// served in benchmark mode, not a proof to submit
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub benchmark: bool,
+    /// the metadata of the request
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, serde_json::Value>,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct PseZkResponse {}
+
+/// A fresh quote over the active key of the guest, see `POST /attestation/quote`.
+#[serde_as]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuoteResponse {
+    pub public_key: String,
+    /// keccak256 of the compressed public key
+    pub fingerprint: String,
+    #[serde_as(as = "DisplayFromStr")]
+    pub instance_address: Address,
+    /// the measurements of the enclave, missing outside of one, like the quote
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mr_enclave: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mr_signer: Option<String>,
+    /// the quote, whose report data is composed with the layout of the chain spec, hex
+    /// encoded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quote: Option<String>,
+}
+
+/// Whether the proof requests are recorded, see `POST /debug/record-requests`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RecordRequestsToggle {
+    pub enabled: bool,
+}
+
+/// The new limit of the requests served at once, see `POST /admin/concurrency`.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConcurrencyUpdate {
+    pub concurrency_limit: usize,
+}
+
+/// The blocks `fromBlock` to `toBlock` included to check, see
+/// `POST /prove/feasibility/batch`, the chain being optional.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FeasibilityBatchRequest {
+    pub chain: Option<String>,
+    pub from_block: u64,
+    pub to_block: u64,
+    pub l1_rpc: String,
+    pub l2_rpc: String,
+}
+
+/// A proof envelope to verify, see `POST /verify-envelope`, with the quote of its
+/// instance and the measurement it must have, both optional.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyEnvelopeRequest {
+    #[serde(flatten)]
+    pub envelope: ProofEnvelope,
+    /// the quote of the instance, as served by `POST /attestation/quote`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quote: Option<Bytes>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected_mr_enclave: Option<B256>,
+}
+
+/// Whether a proof envelope is signed by its signer, see `POST /verify-envelope`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct VerifyEnvelopeResponse {
+    pub valid: bool,
+    /// why the envelope is not valid, the reason of the first failed check
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(flatten)]
+    pub report: VerificationReport,
+}
+
+impl VerifyEnvelopeResponse {
+    /// Verifies the envelope of `req`, its quote holding the report data composed with
+    /// `layout` for `chain_id`.
+    pub fn verify(
+        req: &VerifyEnvelopeRequest,
+        layout: &ReportDataLayout,
+        chain_id: ChainId,
+    ) -> Self {
+        let report_data = |instance, fingerprint| layout.compose(instance, fingerprint, chain_id);
+        let report = req.envelope.verification_report(
+            req.quote.as_deref(),
+            Some(&report_data),
+            req.expected_mr_enclave,
+        );
+        VerifyEnvelopeResponse {
+            valid: report.failure().is_none(),
+            error: report.failure().and_then(|check| check.reason.clone()),
+            report,
+        }
+    }
+}
+
+/// The proofs of a range of blocks to verify against the range they are claimed to cover,
+/// see `POST /verify-envelope/range`. The prover aggregates no proofs, so a range is
+/// attested by the proofs of its blocks, in order, all signed by the instance trusted to
+/// prove it.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyRangeRequest {
+    pub first_block_hash: B256,
+    pub last_block_hash: B256,
+    /// the address of the instance which must have signed every proof
+    pub expected_signer: Address,
+    pub proofs: Vec<RangeProof>,
+}
+
+/// The proof of a block of a range, its envelope with the public inputs it signs.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RangeProof {
+    pub envelope: ProofEnvelope,
+    pub public_inputs: PublicInputs,
+}
+
+/// The checks binding the proofs of a range to the range, see `POST
+/// /verify-envelope/range`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RangeCheck {
+    /// every envelope is valid and signs the hash of its public inputs
+    Components,
+    /// every envelope is signed by the expected instance
+    Signer,
+    /// the first proof is of the claimed first block
+    FirstHash,
+    /// the last proof is of the claimed last block
+    LastHash,
+    /// each block is the child of the block of the previous proof
+    Continuity,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RangeCheckResult {
+    pub check: RangeCheck,
+    pub passed: bool,
+    /// why the check failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// Whether the proofs of a range attest the claimed range, see
+/// `POST /verify-envelope/range`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct VerifyRangeResponse {
+    pub valid: bool,
+    /// the instance which signed every proof, if a single one did
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signer: Option<Address>,
+    /// the checks which failed
+    pub failed: Vec<RangeCheck>,
+    pub checks: Vec<RangeCheckResult>,
+}
+
+impl VerifyRangeResponse {
+    /// Runs every check of the range rather than stopping at the first failure.
+    pub fn verify(req: &VerifyRangeRequest) -> Self {
+        let proofs = &req.proofs;
+        let block_hash = |proof: Option<&RangeProof>| proof.map(|p| p.public_inputs.block_hash);
+        let components = match proofs.is_empty() {
+            true => Err("no proof given".to_owned()),
+            false => proofs.iter().enumerate().try_for_each(|(i, proof)| {
+                let report = proof.envelope.verification_report(None, None, None);
+                if let Some(failure) = report.failure() {
+                    return Err(format!(
+                        "proof {}: {}",
+                        i,
+                        failure.reason.as_deref().unwrap_or("invalid envelope")
+                    ));
+                }
+                let hash = proof.public_inputs.hash();
+                if proof.envelope.public_inputs != hash {
+                    return Err(format!(
+                        "proof {} signs {}, not the hash {} of its public inputs",
+                        i, proof.envelope.public_inputs, hash
+                    ));
+                }
+                Ok(())
+            }),
+        };
+        // an envelope only names the key it is signed with, anyone can sign one
+        let signer = proofs
+            .first()
+            .map(|proof| proof.envelope.signer)
+            .filter(|signer| proofs.iter().all(|proof| proof.envelope.signer == *signer));
+        let signed = match proofs.is_empty() {
+            true => Err("no proof given".to_owned()),
+            false => proofs.iter().enumerate().try_for_each(|(i, proof)| {
+                match proof.envelope.signer == req.expected_signer {
+                    true => Ok(()),
+                    false => Err(format!(
+                        "proof {} is signed by {}, not by the expected instance {}",
+                        i, proof.envelope.signer, req.expected_signer
+                    )),
+                }
+            }),
+        };
+        let endpoint = |which, claimed, proven: Option<B256>| match proven {
+            Some(proven) if proven == claimed => Ok(()),
+            Some(proven) => Err(format!(
+                "the {} proof is of block {}, not of {}",
+                which, proven, claimed
+            )),
+            None => Err("no proof given".to_owned()),
+        };
+        let first_hash = endpoint("first", req.first_block_hash, block_hash(proofs.first()));
+        let last_hash = endpoint("last", req.last_block_hash, block_hash(proofs.last()));
+        let continuity = proofs.windows(2).enumerate().try_for_each(|(i, pair)| {
+            let (parent, child) = (&pair[0].public_inputs, &pair[1].public_inputs);
+            match child.parent_hash == parent.block_hash {
+                true => Ok(()),
+                false => Err(format!(
+                    "proof {} is of a child of {}, not of block {} of proof {}",
+                    i + 1,
+                    child.parent_hash,
+                    parent.block_hash,
+                    i
+                )),
+            }
+        });
+
+        let checks: Vec<_> = [
+            (RangeCheck::Components, components),
+            (RangeCheck::Signer, signed),
+            (RangeCheck::FirstHash, first_hash),
+            (RangeCheck::LastHash, last_hash),
+            (RangeCheck::Continuity, continuity),
+        ]
+        .into_iter()
+        .map(|(check, result)| RangeCheckResult {
+            check,
+            passed: result.is_ok(),
+            reason: result.err(),
+        })
+        .collect();
+        let failed: Vec<_> = checks
+            .iter()
+            .filter(|result| !result.passed)
+            .map(|result| result.check)
+            .collect();
+        VerifyRangeResponse {
+            valid: failed.is_empty(),
+            signer,
+            failed,
+            checks,
+        }
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct MultiResponse {
+    /// proofs of the proof types which succeeded
+    pub proofs: BTreeMap<ProofType, String>,
+    /// errors of the proof types which failed
+    pub errors: BTreeMap<ProofType, String>,
+    /// the metadata of the request
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, serde_json::Value>,
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn request_omitting_prover_and_graffiti_inherits_the_defaults() {
+        let defaults = RequestDefaults {
+            prover: Some(Address::repeat_byte(0x11)),
+            graffiti: Some(B256::repeat_byte(0x22)),
+        };
+        let mut request = json!({
+            "type": "Sgx",
+            "block": 7,
+            "l2Rpc": "http://l2",
+            "l1Rpc": "http://l1",
+        });
+        defaults.apply(&mut request);
+        let ProofRequest::Sgx(req) = serde_json::from_value(request).unwrap() else {
+            panic!("not an sgx request");
+        };
+        assert_eq!(req.prover, Address::repeat_byte(0x11));
+        assert_eq!(req.graffiti, B256::repeat_byte(0x22));
+
+        let mut request = json!({
+            "type": "Multi",
+            "proofTypes": ["sgx"],
+            "block": 7,
+            "l2Rpc": "http://l2",
+            "l1Rpc": "http://l1",
+            "prover": Address::repeat_byte(0x33).to_string(),
+        });
+        defaults.apply(&mut request);
+        let ProofRequest::Multi(req) = serde_json::from_value(request).unwrap() else {
+            panic!("not a multi request");
+        };
+        assert_eq!(req.block.prover, Address::repeat_byte(0x33));
+        assert_eq!(req.block.graffiti, B256::repeat_byte(0x22));
+    }
+
+    /// The address of the test key `key`.
+    fn instance(key: u8) -> Address {
+        use ethers_core::{k256::ecdsa::SigningKey, utils::secret_key_to_address};
+
+        let key = SigningKey::from_slice(&[key; 32]).unwrap();
+        Address::from_slice(secret_key_to_address(&key).as_bytes())
+    }
+
+    /// The proof of the block `block_hash`, child of `parent_hash`, signed by the test
+    /// key `key`.
+    fn range_proof(key: u8, parent_hash: B256, block_hash: B256) -> RangeProof {
+        use ethers_core::k256::ecdsa::SigningKey;
+        use zeth_primitives::{signature::TxSignature, U256};
+
+        let instance = instance(key);
+        let key = SigningKey::from_slice(&[key; 32]).unwrap();
+        let sign = |hash: B256| {
+            let (signature, recovery_id) = key.sign_prehash_recoverable(hash.as_slice()).unwrap();
+            let mut signature = signature.to_bytes().to_vec();
+            signature.push(recovery_id.to_byte() + 27);
+            signature
+        };
+        let public_inputs = PublicInputs {
+            parent_hash,
+            block_hash,
+            signal_root: B256::ZERO,
+            graffiti: B256::ZERO,
+            new_instance: instance,
+            prover: Address::repeat_byte(0x01),
+            meta_hash: B256::repeat_byte(0x02),
+        };
+        let mut proof = 1u32.to_be_bytes().to_vec();
+        proof.extend(instance.as_slice());
+        proof.extend(sign(public_inputs.hash()));
+        let signature = sign(ProofEnvelope::signing_hash(
+            &proof,
+            public_inputs.hash(),
+            1_700_000_000,
+        ));
+        RangeProof {
+            envelope: ProofEnvelope {
+                proof: proof.into(),
+                public_inputs: public_inputs.hash(),
+                timestamp: 1_700_000_000,
+                signer: instance,
+                signature: TxSignature {
+                    r: U256::from_be_slice(&signature[..32]),
+                    s: U256::from_be_slice(&signature[32..64]),
+                    v: signature[64] as u64,
+                },
+            },
+            public_inputs,
+        }
+    }
+
+    #[test]
+    fn range_is_bound_to_its_endpoints_and_continuous() {
+        let hash = B256::with_last_byte;
+        let request = VerifyRangeRequest {
+            first_block_hash: hash(1),
+            last_block_hash: hash(3),
+            expected_signer: instance(7),
+            proofs: vec![
+                range_proof(7, hash(0), hash(1)),
+                range_proof(7, hash(1), hash(2)),
+                range_proof(7, hash(2), hash(3)),
+            ],
+        };
+        let valid = VerifyRangeResponse::verify(&request);
+        assert!(valid.valid);
+        assert!(valid.checks.iter().all(|check| check.passed));
+        assert_eq!(valid.signer, Some(instance(7)));
+
+        // the last block claimed is not the one proven
+        let tampered = VerifyRangeRequest {
+            last_block_hash: hash(4),
+            ..request.clone()
+        };
+        let rejected = VerifyRangeResponse::verify(&tampered);
+        assert!(!rejected.valid);
+        assert_eq!(rejected.failed, [RangeCheck::LastHash]);
+
+        // a gap in the range
+        let mut gap = request.clone();
+        gap.proofs.remove(1);
+        assert_eq!(
+            VerifyRangeResponse::verify(&gap).failed,
+            [RangeCheck::Continuity]
+        );
+
+        // the public inputs altered after signing
+        let mut altered = request.clone();
+        altered.proofs[2].public_inputs.block_hash = hash(4);
+        altered.last_block_hash = hash(4);
+        assert_eq!(
+            VerifyRangeResponse::verify(&altered).failed,
+            [RangeCheck::Components]
+        );
+    }
+
+    #[test]
+    fn range_is_signed_by_the_expected_instance() {
+        let hash = B256::with_last_byte;
+        let range = |keys: [u8; 2]| VerifyRangeRequest {
+            first_block_hash: hash(1),
+            last_block_hash: hash(2),
+            expected_signer: instance(7),
+            proofs: vec![
+                range_proof(keys[0], hash(0), hash(1)),
+                range_proof(keys[1], hash(1), hash(2)),
+            ],
+        };
+        assert!(VerifyRangeResponse::verify(&range([7, 7])).valid);
+
+        // a block proven by another instance
+        let mixed = VerifyRangeResponse::verify(&range([7, 8]));
+        assert_eq!(mixed.failed, [RangeCheck::Signer]);
+        assert_eq!(mixed.signer, None);
+        assert!(mixed.checks[1]
+            .reason
+            .as_deref()
+            .unwrap()
+            .starts_with("proof 1 is signed by"));
+
+        // forged envelopes, valid but signed with a key of their own
+        let forged = VerifyRangeResponse::verify(&range([8, 8]));
+        assert!(!forged.valid);
+        assert_eq!(forged.failed, [RangeCheck::Signer]);
+        assert_eq!(forged.signer, Some(instance(8)));
+    }
+}
@@ -0,0 +1,198 @@
+//! Re-verification of a sample of the proofs produced, after they were served and
+//! cached: nothing is held back. A sampled proof is checked with the verifier of
+//! `POST /verify-envelope`, i.e. the signature of its envelope and the sgx proof over its
+//! public inputs, and the proof served must be the one of its envelope. A failure raises
+//! `proof_reverify_failures_counter` and an error log, for the instance to be drained.
+//! This only catches the proofs corrupted between their signing and their delivery: an
+//! enclave computing a wrong result signs it validly, which no signature check detects.
+//! The sampled proofs are verified one at a time on a blocking thread, pausing between
+//! them to leave the capacity to the proofs.
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::sync::Notify;
+use tracing::error;
+use zeth_lib::consts::ReportDataLayout;
+use zeth_primitives::taiko::ProofEnvelope;
+
+use super::request::{VerifyEnvelopeRequest, VerifyEnvelopeResponse};
+use crate::metrics::{inc_reverified, inc_reverify_failures};
+
+/// Sampled proofs waiting for their re-verification, the oldest ones dropped beyond.
+const MAX_PENDING_REVERIFICATIONS: usize = 100;
+/// Pause after each re-verification.
+const REVERIFY_PAUSE: Duration = Duration::from_millis(100);
+
+/// A proof produced, to verify again.
+#[derive(Debug, Clone)]
+pub struct SampledProof {
+    pub block: u64,
+    pub proof: String,
+    pub envelope: ProofEnvelope,
+}
+
+#[derive(Debug, Default)]
+struct Sampling {
+    /// the fraction of a proof sampled so far
+    credit: f64,
+    pending: VecDeque<SampledProof>,
+}
+
+/// The proofs sampled for their re-verification, `sample_rate` of the ones produced.
+#[derive(Debug)]
+pub struct Reverifier {
+    sample_rate: f64,
+    sampling: Mutex<Sampling>,
+    sampled: Notify,
+}
+
+impl Reverifier {
+    pub fn new(sample_rate: f64) -> Self {
+        Reverifier {
+            sample_rate,
+            sampling: Default::default(),
+            sampled: Notify::new(),
+        }
+    }
+
+    /// Samples `proof` at the sample rate, e.g. one proof in ten at 0.1.
+    pub fn sample(&self, proof: SampledProof) {
+        let mut sampling = self.sampling.lock().unwrap();
+        sampling.credit += self.sample_rate;
+        if sampling.credit < 1.0 {
+            return;
+        }
+        sampling.credit -= 1.0;
+        if sampling.pending.len() == MAX_PENDING_REVERIFICATIONS {
+            sampling.pending.pop_front();
+        }
+        sampling.pending.push_back(proof);
+        drop(sampling);
+        self.sampled.notify_one();
+    }
+
+    /// Re-verifies the sampled proofs as they come.
+    pub async fn run(self: Arc<Self>) {
+        loop {
+            let next = self.sampling.lock().unwrap().pending.pop_front();
+            let Some(proof) = next else {
+                self.sampled.notified().await;
+                continue;
+            };
+            let _ = tokio::task::spawn_blocking(move || reverify(&proof)).await;
+            tokio::time::sleep(REVERIFY_PAUSE).await;
+        }
+    }
+}
+
+/// Verifies `proof` again, raising the alarm when it fails.
+pub fn reverify(proof: &SampledProof) -> bool {
+    // without a quote, the report data layout is not used
+    let result = VerifyEnvelopeResponse::verify(
+        &VerifyEnvelopeRequest {
+            envelope: proof.envelope.clone(),
+            quote: None,
+            expected_mr_enclave: None,
+        },
+        &ReportDataLayout::default(),
+        0,
+    );
+    let served = hex::decode(proof.proof.trim_start_matches("0x")).ok();
+    let failure = match (result.valid, served) {
+        (false, _) => Some(
+            result
+                .error
+                .unwrap_or_else(|| "invalid envelope".to_owned()),
+        ),
+        (true, Some(served)) if served[..] == proof.envelope.proof[..] => None,
+        (true, _) => Some("the proof served is not the one of its envelope".to_owned()),
+    };
+    inc_reverified();
+    let Some(reason) = failure else {
+        return true;
+    };
+    error!(
+        "ALARM: the proof of block {} failed its re-verification, the enclave may be faulty: {}",
+        proof.block, reason
+    );
+    inc_reverify_failures();
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers_core::{k256::ecdsa::SigningKey, utils::secret_key_to_address};
+    use zeth_primitives::{signature::TxSignature, Address, B256, U256};
+
+    use super::*;
+    use crate::metrics::PROOF_REVERIFY_FAILURES_COUNTER;
+
+    /// A proof of `block` signed by its instance.
+    fn sampled_proof(block: u64) -> SampledProof {
+        let key = SigningKey::from_slice(&[7u8; 32]).unwrap();
+        let instance = secret_key_to_address(&key);
+        let sign = |hash: B256| {
+            let (signature, recovery_id) = key.sign_prehash_recoverable(hash.as_slice()).unwrap();
+            let mut signature = signature.to_bytes().to_vec();
+            signature.push(recovery_id.to_byte() + 27);
+            signature
+        };
+        let public_inputs = B256::repeat_byte(block as u8);
+        let mut proof = 1u32.to_be_bytes().to_vec();
+        proof.extend(instance.as_bytes());
+        proof.extend(sign(public_inputs));
+        let timestamp = 1_700_000_000;
+        let signature = sign(ProofEnvelope::signing_hash(
+            &proof,
+            public_inputs,
+            timestamp,
+        ));
+        SampledProof {
+            block,
+            proof: format!("0x{}", hex::encode(&proof)),
+            envelope: ProofEnvelope {
+                proof: proof.into(),
+                public_inputs,
+                timestamp,
+                signer: Address::from_slice(instance.as_bytes()),
+                signature: TxSignature {
+                    r: U256::from_be_slice(&signature[..32]),
+                    s: U256::from_be_slice(&signature[32..64]),
+                    v: signature[64] as u64,
+                },
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn proofs_failing_their_reverification_raise_the_alarm() {
+        assert!(reverify(&sampled_proof(1)));
+
+        // one proof in two is sampled
+        let reverifier = Reverifier::new(0.5);
+        for block in 0..4 {
+            reverifier.sample(sampled_proof(block));
+        }
+        let pending = |reverifier: &Reverifier| reverifier.sampling.lock().unwrap().pending.len();
+        assert_eq!(pending(&reverifier), 2);
+
+        // a proof failing its re-verification, signed over another timestamp
+        let reverifier = Arc::new(Reverifier::new(1.0));
+        let failures = PROOF_REVERIFY_FAILURES_COUNTER.get();
+        tokio::spawn(reverifier.clone().run());
+        let mut faulty = sampled_proof(2);
+        faulty.envelope.timestamp += 1;
+        reverifier.sample(faulty);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while PROOF_REVERIFY_FAILURES_COUNTER.get() == failures {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("alarm raised");
+        assert_eq!(pending(&reverifier), 0);
+    }
+}
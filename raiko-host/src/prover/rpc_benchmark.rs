@@ -0,0 +1,242 @@
+//! Samples of the latency of an RPC node, for the operators to tune the fetch concurrency
+//! and the RPC timeouts from measurements: the calls the witnesses are built from are
+//! timed one after another, with the client of the proofs, nothing being proven.
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use zeth_lib::host::provider::{rpc_allowlist::AllowlistedHttp, RpcOptions};
+use zeth_primitives::Address;
+
+/// Calls of each method a benchmark makes by default.
+pub const RPC_BENCHMARK_SAMPLES: usize = 20;
+/// Most calls of each method a benchmark may make.
+pub const MAX_RPC_BENCHMARK_SAMPLES: usize = 100;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcBenchmarkRequest {
+    pub rpc_url: String,
+    /// calls of each method
+    pub samples: Option<usize>,
+    /// block the calls are made at, the head of the node by default
+    pub block: Option<u64>,
+    /// account whose proof is fetched, the L2 signal service by default
+    pub address: Option<Address>,
+}
+
+/// The latency of the calls of a method, of the ones which succeeded.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MethodLatency {
+    pub method: String,
+    pub calls: usize,
+    pub errors: usize,
+    pub error_rate: f64,
+    pub p50_ms: Option<f64>,
+    pub p95_ms: Option<f64>,
+    pub p99_ms: Option<f64>,
+}
+
+impl MethodLatency {
+    fn of(method: &str, mut latencies: Vec<Duration>, errors: usize) -> Self {
+        latencies.sort();
+        let calls = latencies.len() + errors;
+        let millis = |p| percentile(&latencies, p).map(|d| d.as_secs_f64() * 1000.0);
+        MethodLatency {
+            method: method.to_owned(),
+            calls,
+            errors,
+            error_rate: errors as f64 / calls.max(1) as f64,
+            p50_ms: millis(50.0),
+            p95_ms: millis(95.0),
+            p99_ms: millis(99.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcBenchmarkReport {
+    pub block: u64,
+    pub methods: Vec<MethodLatency>,
+}
+
+/// The `p`th percentile of `sorted`, by nearest rank.
+pub fn percentile(sorted: &[Duration], p: f64) -> Option<Duration> {
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted.get(rank.max(1) - 1).copied()
+}
+
+/// Lets a benchmark start once every `interval`, sparing the RPC node.
+#[derive(Debug)]
+pub struct RpcBenchmarkLimit {
+    interval: Duration,
+    last_start: Mutex<Option<Instant>>,
+}
+
+impl RpcBenchmarkLimit {
+    pub fn new(interval: Duration) -> Self {
+        RpcBenchmarkLimit {
+            interval,
+            last_start: Mutex::new(None),
+        }
+    }
+
+    /// Starts a benchmark, or returns how long until the next one may start.
+    pub fn try_start(&self) -> Result<(), Duration> {
+        let mut last_start = self.last_start.lock().unwrap();
+        if let Some(wait) = last_start
+            .map(|start| self.interval.saturating_sub(start.elapsed()))
+            .filter(|wait| !wait.is_zero())
+        {
+            return Err(wait);
+        }
+        *last_start = Some(Instant::now());
+        Ok(())
+    }
+}
+
+/// The transport of the providers, so that the benchmark calls the allowed methods only.
+struct RpcClient {
+    transport: AllowlistedHttp,
+    request_timeout: Option<Duration>,
+}
+
+impl RpcClient {
+    async fn call(&self, method: &str, params: Value) -> Result<Value, String> {
+        let call = self.transport.call(method, params);
+        let result = match self.request_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, call)
+                .await
+                .map_err(|_| format!("{} timed out after {:?}", method, timeout))?,
+            None => call.await,
+        };
+        result.map_err(|err| err.to_string())
+    }
+}
+
+/// Times `samples` calls of `eth_getBlockByNumber` then of `eth_getProof` of
+/// `req.address`, or of `default_address`, at `req.block`.
+pub async fn run_rpc_benchmark(
+    options: &RpcOptions,
+    req: &RpcBenchmarkRequest,
+    samples: usize,
+    default_address: Address,
+) -> Result<RpcBenchmarkReport, String> {
+    let rpc_url = reqwest::Url::parse(&req.rpc_url).map_err(|err| err.to_string())?;
+    let rpc = RpcClient {
+        transport: AllowlistedHttp::for_url(rpc_url, options).map_err(|err| err.to_string())?,
+        request_timeout: options.request_timeout,
+    };
+    let block = match req.block {
+        Some(block) => block,
+        None => {
+            let head = rpc.call("eth_blockNumber", json!([])).await?;
+            head.as_str()
+                .and_then(|head| u64::from_str_radix(head.trim_start_matches("0x"), 16).ok())
+                .ok_or_else(|| format!("invalid head block {}", head))?
+        }
+    };
+    let block_id = format!("{:#x}", block);
+    let address = req.address.unwrap_or(default_address);
+    let calls = [
+        ("eth_getBlockByNumber", json!([block_id, false])),
+        ("eth_getProof", json!([address, [], block_id])),
+    ];
+    let mut methods = vec![];
+    for (method, params) in calls {
+        let mut latencies = vec![];
+        let mut errors = 0;
+        for _ in 0..samples {
+            let start = Instant::now();
+            match rpc.call(method, params.clone()).await {
+                Ok(_) => latencies.push(start.elapsed()),
+                Err(_) => errors += 1,
+            }
+        }
+        methods.push(MethodLatency::of(method, latencies, errors));
+    }
+    Ok(RpcBenchmarkReport { block, methods })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use super::*;
+    use crate::prover::mock_server::{json_rpc_response, read_json, serve};
+
+    /// An RPC node taking 20ms per block and 60ms per proof, failing every 4th proof.
+    fn slow_rpc() -> String {
+        let proofs = Arc::new(AtomicUsize::new(0));
+        serve(move |req| {
+            let proofs = proofs.clone();
+            async move {
+                let request = read_json(req).await;
+                let response = match request["method"].as_str().unwrap() {
+                    "eth_blockNumber" => json!({ "result": "0x10" }),
+                    "eth_getBlockByNumber" => {
+                        tokio::time::sleep(Duration::from_millis(20)).await;
+                        json!({ "result": { "number": request["params"][0] } })
+                    }
+                    _ => {
+                        tokio::time::sleep(Duration::from_millis(60)).await;
+                        match proofs.fetch_add(1, Ordering::SeqCst) % 4 {
+                            3 => json!({ "error": { "code": -32000, "message": "busy" } }),
+                            _ => json!({ "result": {} }),
+                        }
+                    }
+                };
+                json_rpc_response(&request, response)
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn rpc_latencies_are_reported_by_percentile() {
+        let sorted: Vec<_> = (1..=100).map(Duration::from_millis).collect();
+        assert_eq!(percentile(&sorted, 50.0), Some(Duration::from_millis(50)));
+        assert_eq!(percentile(&sorted, 99.0), Some(Duration::from_millis(99)));
+        assert_eq!(percentile(&[], 50.0), None);
+
+        let req = RpcBenchmarkRequest {
+            rpc_url: slow_rpc(),
+            samples: None,
+            block: None,
+            address: None,
+        };
+        let report = run_rpc_benchmark(&RpcOptions::default(), &req, 8, Address::ZERO)
+            .await
+            .unwrap();
+        assert_eq!(report.block, 0x10);
+        let [blocks, proofs] = &report.methods[..] else {
+            panic!("{:?}", report);
+        };
+        assert_eq!((blocks.calls, blocks.errors), (8, 0));
+        assert_eq!((proofs.calls, proofs.errors), (8, 2));
+        assert_eq!(proofs.error_rate, 0.25);
+        for latency in [blocks, proofs] {
+            let (p50, p95, p99) = (
+                latency.p50_ms.unwrap(),
+                latency.p95_ms.unwrap(),
+                latency.p99_ms.unwrap(),
+            );
+            assert!(p50 <= p95 && p95 <= p99, "{:?}", latency);
+        }
+        assert!(blocks.p50_ms.unwrap() >= 20.0, "{:?}", blocks);
+        assert!(proofs.p50_ms.unwrap() >= 60.0, "{:?}", proofs);
+        assert!(proofs.p50_ms > blocks.p99_ms, "{:?}", report);
+
+        // another benchmark waits for the interval
+        let limit = RpcBenchmarkLimit::new(Duration::from_secs(60));
+        assert_eq!(limit.try_start(), Ok(()));
+        assert!(limit.try_start().unwrap_err() > Duration::from_secs(59));
+    }
+}
@@ -1,71 +1,469 @@
-use std::path::{Path, PathBuf};
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::Arc,
+    task::Poll,
+    time::{Duration, Instant},
+};
 
 use hyper::{
     body::{Buf, HttpBody},
-    header::HeaderValue,
+    header::{HeaderValue, WARNING},
+    server::{
+        conn::{AddrIncoming, AddrStream},
+        Builder,
+    },
     service::{make_service_fn, service_fn},
-    Body, Method, Request, Response, Server, StatusCode,
+    Body, Method, Request, Response, Server, StatusCode, Uri,
 };
 use prometheus::{Encoder, TextEncoder};
-use tower::ServiceBuilder;
-use tracing::info;
-
-use crate::prover::{
-    context::Context,
-    execution::execute,
-    json_rpc::{JsonRpcError, JsonRpcRequest, JsonRpcResponse, JsonRpcResponseError},
-    proof::cache::Cache,
-    request::*,
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_with::{serde_as, DisplayFromStr};
+use tower::{ServiceBuilder, ServiceExt};
+use tracing::{info, warn, Level};
+use zeth_lib::consts::get_taiko_chain_spec;
+use zeth_primitives::keccak256;
+
+use crate::{
+    log_tail::stream_log_lines,
+    metrics::{
+        gather, observe_cache_writes, observe_rpc_breakers, HttpMetricsLayer, SHED_REQUESTS_COUNTER,
+    },
+    prover::{
+        benchmark::execute_benchmark,
+        cache_pins::CachePins,
+        client_connections::{ClientConnection, ClientConnections},
+        compaction::{compact, ORPHAN_AGE},
+        concurrency::ConcurrencyLimit,
+        context::Context,
+        disk_budget::DiskBudget,
+        estimate::estimate_block,
+        execution::execute,
+        feasibility::{check_feasibility, feasibility_batch},
+        guest_image::{wait_for_guest, GUEST_RECHECK_INTERVAL},
+        idempotency::{IdempotencyStore, Retention, IDEMPOTENCY_KEY_HEADER},
+        idle_timeout::{IdleIncoming, IdleStream, InFlight, InFlightBody, InFlightFuture},
+        job_lifetime::{acquire_before, expired_response, JobDeadline},
+        json_rpc::{JsonRpcError, JsonRpcRequest, JsonRpcResponse, JsonRpcResponseError},
+        proof::{
+            cache::Cache,
+            sgx::{fetch_quote, warmup_sgx},
+        },
+        request::*,
+        rpc_benchmark::{
+            run_rpc_benchmark, RpcBenchmarkLimit, RpcBenchmarkRequest, MAX_RPC_BENCHMARK_SAMPLES,
+            RPC_BENCHMARK_SAMPLES,
+        },
+        shedding::LoadShedder,
+        startup_probe::{run_startup_probe, StartupProbe, STARTUP_PROBE_FIRST_BACKOFF},
+        upload::store_block_data,
+        utils::{
+            cache_file_path, chain_cache_dir, migrate_flat_cache, remove_stray_temp_files, Secret,
+        },
+        validate::validate,
+        warm::{warm_cache, warm_schedule},
+        warmup::{warm_up, Readiness},
+        witness_store::witness_body,
+    },
+    statsd::{push_statsd, StatsdExporter},
+    Opt,
 };
 
-/// Starts the proverd json-rpc server.
+/// Starts the proverd json-rpc server, unready until the guest is valid unless
+/// `guest_valid`.
 /// Note: the server may not immediately listening after returning the
 /// `JoinHandle`.
 pub fn serve(
-    addr: &str,
-    guest_path: &Path,
-    cache_path: &Path,
-    l2_chain: &str,
-    sgx_instance_id: u32,
-    proof_cache: usize,
-    concurrency_limit: usize,
-    max_caches: usize,
+    opt: Opt,
+    effective_config: serde_json::Value,
+    guest_valid: bool,
 ) -> tokio::task::JoinHandle<()> {
-    let addr = addr
-        .parse::<std::net::SocketAddr>()
+    let addr = opt
+        .bind
+        .parse::<SocketAddr>()
         .expect("valid socket address");
-    let guest_path = guest_path.to_owned();
-    let cache_path = cache_path.to_owned();
-    let l2_chain = l2_chain.to_owned();
     tokio::spawn(async move {
-        let handler = Handler::new(
-            guest_path.clone(),
-            cache_path.clone(),
-            l2_chain.clone(),
-            sgx_instance_id,
-            proof_cache,
-            max_caches,
-        );
+        let handler = Handler::new(&opt, effective_config, guest_valid);
+        let migrated = migrate_flat_cache(&handler.ctx.cache_path, handler.ctx.l2_chain_id)
+            .expect("prepare the cache directory");
+        if migrated > 0 {
+            info!(
+                "Moved {} cache files into {:?}",
+                migrated,
+                chain_cache_dir(&handler.ctx.cache_path, handler.ctx.l2_chain_id)
+            );
+        }
+        let chain_dir = chain_cache_dir(&handler.ctx.cache_path, handler.ctx.l2_chain_id);
+        let removed = remove_stray_temp_files(&chain_dir).expect("clean the cache directory");
+        if removed > 0 {
+            info!(
+                "Removed {} interrupted cache writes from {:?}",
+                removed, chain_dir
+            );
+        }
+        if let (Some(warm_blocks), Some(l1_rpc), Some(l2_rpc)) =
+            (&opt.warm_blocks, &opt.warm_l1_rpc, &opt.warm_l2_rpc)
+        {
+            let blocks = warm_schedule(warm_blocks, &opt.warm_order).expect("read the warm order");
+            info!(
+                "Warming {} blocks of {} in {} order",
+                blocks.len(),
+                warm_blocks,
+                opt.warm_order
+            );
+            tokio::spawn(warm_cache(
+                handler.ctx.clone(),
+                blocks,
+                l1_rpc.clone(),
+                l2_rpc.clone(),
+            ));
+        }
+        if !guest_valid {
+            let (ctx, readiness) = (handler.ctx.clone(), handler.readiness.clone());
+            let warmup_on_start = opt.warmup_on_start;
+            tokio::spawn(async move {
+                let image = wait_for_guest(&ctx.guest_path, GUEST_RECHECK_INTERVAL).await;
+                info!(
+                    "Found a valid guest, mrenclave: 0x{}",
+                    hex::encode(image.mrenclave)
+                );
+                match warmup_on_start {
+                    true => warm_up(readiness, warmup_sgx(&ctx)).await,
+                    false => readiness.set_ready(),
+                }
+            });
+        } else if opt.warmup_on_start {
+            info!("Warming the enclave up");
+            let (ctx, readiness) = (handler.ctx.clone(), handler.readiness.clone());
+            tokio::spawn(async move { warm_up(readiness, warmup_sgx(&ctx)).await });
+        }
+        if let Some(rpc_url) = &opt.startup_probe_rpc {
+            info!("Probing the RPC node of startup_probe_rpc before reporting the server ready");
+            let probe = StartupProbe {
+                rpc_url: rpc_url.clone(),
+                reference_rpc_url: opt.startup_probe_reference_rpc.clone(),
+                max_head_lag_blocks: opt.max_head_lag_blocks,
+                timeout: Duration::from_secs(opt.startup_probe_timeout_secs),
+            };
+            tokio::spawn(run_startup_probe(
+                probe,
+                handler.ctx.rpc_options.clone(),
+                handler.dependencies.clone(),
+                STARTUP_PROBE_FIRST_BACKOFF,
+            ));
+        }
+        if let Some(disk_budget) = handler.ctx.disk_budget.clone() {
+            tokio::spawn(enforce_disk_budget(disk_budget));
+        }
+        if let Some(reverifier) = handler.ctx.reverifier.clone() {
+            tokio::spawn(reverifier.run());
+        }
+        if let Some(interval) = opt.cache_compact_interval_secs {
+            tokio::spawn(compact_cache(
+                chain_dir,
+                handler.ctx.cache_pins.clone(),
+                Duration::from_secs(interval),
+            ));
+        }
+        if let Some(endpoint) = &opt.statsd_endpoint {
+            let mut tags = vec![
+                format!("sgx_instance_id:{}", opt.sgx_instance_id),
+                format!("l2_chain:{}", opt.l2_chain),
+            ];
+            tags.extend(opt.statsd_tag.iter().cloned());
+            tokio::spawn(push_statsd(
+                StatsdExporter::new(endpoint.clone(), tags, opt.metrics_prefix.clone()),
+                Duration::from_secs(opt.statsd_interval_secs),
+                handler.ctx.rpc_options.circuit_breaker.clone(),
+            ));
+        }
+        let limit = handler.concurrency.clone();
+        let max_job_lifetime = opt.max_job_lifetime_secs.map(Duration::from_secs);
+        let shedder = opt
+            .target_proof_ms
+            .map(|target| Arc::new(LoadShedder::new(Duration::from_millis(target))));
         let service = service_fn(move |req| {
             let handler = handler.clone();
             handler.handle_request(req)
         });
 
         let service = ServiceBuilder::new()
-            .concurrency_limit(concurrency_limit)
+            .layer(HttpMetricsLayer::new(route_template))
             .service(service);
 
-        let service = make_service_fn(|_| {
+        let base_path = opt.base_path.as_deref().unwrap_or_default();
+        let base_path: Arc<str> = base_path.trim_end_matches('/').into();
+        let prefix = base_path.clone();
+        let service = service_fn(move |req| {
             let service = service.clone();
-            async move { Ok::<_, hyper::Error>(service) }
+            let prefix = prefix.clone();
+            let limit = limit.clone();
+            let shedder = shedder.clone();
+            async move {
+                match strip_base_path(&prefix, req) {
+                    // reachable when the limit is saturated, to raise it
+                    Some(req) if req.uri().path() == ADMIN_CONCURRENCY_PATH => {
+                        service.oneshot(req).await
+                    }
+                    Some(mut req) => {
+                        // the json-rpc requests, cache hits included, are the proofs
+                        let is_proof = req.method() == Method::POST && req.uri().path() == "/";
+                        let shedder = shedder.filter(|_| is_proof);
+                        if let Some(shedder) = &shedder {
+                            if let Some(projected) = shedder.should_shed(&limit) {
+                                return Ok(shed_response(projected, shedder.target()));
+                            }
+                        }
+                        let lifetime = max_job_lifetime.filter(|_| is_proof);
+                        let deadline =
+                            lifetime.map(|lifetime| JobDeadline(Instant::now() + lifetime));
+                        let Some(_permit) = acquire_before(&limit, deadline).await else {
+                            return Ok(expired_response(lifetime.unwrap_or_default()));
+                        };
+                        if let Some(deadline) = deadline {
+                            req.extensions_mut().insert(deadline);
+                        }
+                        let start = Instant::now();
+                        let resp = service.oneshot(req).await;
+                        if let Some(shedder) = &shedder {
+                            shedder.record(start.elapsed());
+                        }
+                        resp
+                    }
+                    None => Ok(status_response(StatusCode::NOT_FOUND)),
+                }
+            }
+        });
+
+        let connections = opt.max_connections_per_client.map(ClientConnections::new);
+        let service = make_service_fn(|conn: &IdleStream<AddrStream>| {
+            let service = connection_service(
+                service.clone(),
+                conn.get_ref().remote_addr().ip(),
+                connections.as_ref(),
+                conn.in_flight().cloned(),
+            );
+            async move { service }
         });
 
-        let server = Server::bind(&addr).serve(service);
-        info!("Listening on http://{}", addr);
+        let incoming = AddrIncoming::bind(&addr).expect("bind the server address");
+        let server = server_builder(
+            incoming,
+            opt.http_keepalive_secs.map(Duration::from_secs),
+            opt.http_idle_timeout_secs.map(Duration::from_secs),
+            opt.http2,
+        )
+        .serve(service);
+        info!("Listening on http://{}{}/", addr, base_path);
         server.await.expect("server should be serving");
     })
 }
 
+/// The listener of the server, HTTP/1.1 only unless `--http2`. The HTTP/2 streams of a
+/// connection are served concurrently, the streamed bodies (`/logs/tail`) included, all
+/// closed with the connection, once idle for `idle_timeout`.
+fn server_builder(
+    mut incoming: AddrIncoming,
+    keepalive: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    http2: bool,
+) -> Builder<IdleIncoming> {
+    incoming.set_keepalive(keepalive);
+    // hyper tells HTTP/2 connections by their preface, serving the others as HTTP/1.1
+    Server::builder(IdleIncoming::new(incoming, idle_timeout)).http1_only(!http2)
+}
+
+/// The service of a connection accepted from `client`, which holds one of the connections
+/// of the client under `max_connections_per_client` until it closes. Over the limit the
+/// connection is refused: hyper closes it and goes on accepting the others. The requests
+/// served are counted `in_flight`, for the idle timeout of the connection.
+fn connection_service<S>(
+    service: S,
+    client: IpAddr,
+    connections: Option<&Arc<ClientConnections>>,
+    in_flight: Option<InFlight>,
+) -> Result<ConnectionService<S>, String> {
+    let connection = match connections {
+        Some(connections) => Some(connections.open(client).ok_or_else(|| {
+            warn!(
+                "Refused a connection of {}, over its open connections",
+                client
+            );
+            format!("too many connections from {}", client)
+        })?),
+        None => None,
+    };
+    Ok(ConnectionService {
+        service,
+        _connection: connection,
+        in_flight,
+    })
+}
+
+/// The service of a connection, counted with the connections of its client.
+struct ConnectionService<S> {
+    service: S,
+    _connection: Option<ClientConnection>,
+    in_flight: Option<InFlight>,
+}
+
+impl<S, R, B> tower::Service<R> for ConnectionService<S>
+where
+    S: tower::Service<R, Response = Response<B>>,
+{
+    type Response = Response<InFlightBody<B>>;
+    type Error = S::Error;
+    type Future = InFlightFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), S::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: R) -> Self::Future {
+        let guard = self.in_flight.as_ref().map(InFlight::begin);
+        InFlightFuture::new(self.service.call(req), guard)
+    }
+}
+
+/// The 503 of a proof request projected to take longer than `target_proof_ms`.
+fn shed_response(projected: Duration, target: Duration) -> Response<Body> {
+    SHED_REQUESTS_COUNTER.inc();
+    let mut resp = Response::new(Body::from(format!(
+        "the proof is projected to take {}ms, over the target of {}ms",
+        projected.as_millis(),
+        target.as_millis()
+    )));
+    *resp.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+    resp
+}
+
+/// How often the disk footprint is checked against `max_disk_bytes`, besides after each
+/// proof.
+const DISK_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+async fn enforce_disk_budget(disk_budget: Arc<DiskBudget>) {
+    let mut interval = tokio::time::interval(DISK_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let disk_budget = disk_budget.clone();
+        let usage = tokio::task::spawn_blocking(move || disk_budget.enforce()).await;
+        if let Err(err) = usage.map_err(std::io::Error::from).and_then(|usage| usage) {
+            warn!("Failed to check the disk usage: {}", err);
+        }
+    }
+}
+
+async fn compact_cache(chain_dir: PathBuf, pins: CachePins, period: Duration) {
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        let (chain_dir, pins) = (chain_dir.clone(), pins.clone());
+        let stats =
+            tokio::task::spawn_blocking(move || compact(&chain_dir, &pins, ORPHAN_AGE)).await;
+        match stats.map_err(std::io::Error::from).and_then(|stats| stats) {
+            Ok(stats) if stats.removed_files > 0 => info!(
+                "Compacted the cache: removed {} orphaned files ({} bytes), {} entries left",
+                stats.removed_files, stats.removed_bytes, stats.entries
+            ),
+            Ok(_) => {}
+            Err(err) => warn!("Failed to compact the cache: {}", err),
+        }
+    }
+}
+
+const DUMP_WITNESS_PATH: &str = "/debug/dump-witness/";
+const FEASIBILITY_PATH: &str = "/prove/feasibility";
+const FEASIBILITY_BATCH_PATH: &str = "/prove/feasibility/batch";
+const ESTIMATE_PATH: &str = "/prove/estimate";
+/// Most blocks a feasibility batch may check.
+const MAX_FEASIBILITY_BATCH_BLOCKS: u64 = 1000;
+const BLOCK_DATA_PATH: &str = "/block-data/";
+const RECORD_REQUESTS_PATH: &str = "/debug/record-requests";
+const ATTESTATION_QUOTE_PATH: &str = "/attestation/quote";
+const ADMIN_CONCURRENCY_PATH: &str = "/admin/concurrency";
+const ADMIN_RPC_BENCHMARK_PATH: &str = "/admin/rpc-benchmark";
+const RECENT_PROOFS_PATH: &str = "/proofs/recent";
+const LOGS_TAIL_PATH: &str = "/logs/tail";
+const VERIFY_RANGE_PATH: &str = "/verify-envelope/range";
+/// The readiness of the server, the same as `/health`.
+const HEALTH_READY_PATH: &str = "/health/ready";
+
+/// The query of a feasibility check or estimate, the chain being optional.
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FeasibilityQuery {
+    chain: Option<String>,
+    block: u64,
+    l1_rpc: String,
+    l2_rpc: String,
+}
+
+/// The query of a page of the recent proofs.
+#[derive(Debug, PartialEq, Deserialize)]
+struct RecentProofsQuery {
+    limit: Option<usize>,
+    cursor: Option<u64>,
+}
+
+/// The query of the log tail, streaming every level without one.
+#[serde_as]
+#[derive(Debug, PartialEq, Deserialize)]
+struct LogsTailQuery {
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    level: Option<Level>,
+}
+
+/// The query of `uri` as a `T`, `None` when a parameter is missing or does not parse.
+fn query<T: DeserializeOwned>(uri: &Uri) -> Option<T> {
+    serde_urlencoded::from_str(uri.query().unwrap_or_default()).ok()
+}
+
+/// Removes `base_path` from the path of `req`, for the routes to match. `None` for the
+/// requests outside of it.
+fn strip_base_path(base_path: &str, mut req: Request<Body>) -> Option<Request<Body>> {
+    if base_path.is_empty() {
+        return Some(req);
+    }
+    let path = match req.uri().path().strip_prefix(base_path)? {
+        "" => "/",
+        path if path.starts_with('/') => path,
+        // another path sharing the prefix, like `/raikox` for `/raiko`
+        _ => return None,
+    };
+    let path_and_query = match req.uri().query() {
+        Some(query) => format!("{}?{}", path, query),
+        None => path.to_owned(),
+    };
+    *req.uri_mut() = Uri::builder().path_and_query(path_and_query).build().ok()?;
+    Some(req)
+}
+
+/// Maps the request path to its route, with the ids replaced by placeholders.
+fn route_template(path: &str) -> &'static str {
+    match path {
+        "/" => "/",
+        "/health" => "/health",
+        HEALTH_READY_PATH => HEALTH_READY_PATH,
+        "/metrics" => "/metrics",
+        "/config" => "/config",
+        "/validate" => "/validate",
+        "/verify-envelope" => "/verify-envelope",
+        VERIFY_RANGE_PATH => VERIFY_RANGE_PATH,
+        FEASIBILITY_PATH => FEASIBILITY_PATH,
+        FEASIBILITY_BATCH_PATH => FEASIBILITY_BATCH_PATH,
+        ESTIMATE_PATH => ESTIMATE_PATH,
+        RECORD_REQUESTS_PATH => RECORD_REQUESTS_PATH,
+        ATTESTATION_QUOTE_PATH => ATTESTATION_QUOTE_PATH,
+        ADMIN_CONCURRENCY_PATH => ADMIN_CONCURRENCY_PATH,
+        ADMIN_RPC_BENCHMARK_PATH => ADMIN_RPC_BENCHMARK_PATH,
+        RECENT_PROOFS_PATH => RECENT_PROOFS_PATH,
+        LOGS_TAIL_PATH => LOGS_TAIL_PATH,
+        _ if path.starts_with(DUMP_WITNESS_PATH) => "/debug/dump-witness/{block}",
+        _ if path.starts_with(BLOCK_DATA_PATH) => "/block-data/{block}/{layer}",
+        _ => "unmatched",
+    }
+}
+
 /// sets default headers for CORS requests
 fn set_headers(headers: &mut hyper::HeaderMap, extended: bool) {
     headers.insert("content-type", HeaderValue::from_static("application/json"));
@@ -84,35 +482,65 @@ fn set_headers(headers: &mut hyper::HeaderMap, extended: bool) {
     }
 }
 
+fn status_response(status: StatusCode) -> Response<Body> {
+    let mut resp = Response::default();
+    *resp.status_mut() = status;
+    resp
+}
+
 #[derive(Clone)]
 struct Handler {
     ctx: Context,
     cache: Cache,
+    /// `None` when coalescing is disabled
+    idempotency: Option<IdempotencyStore>,
+    effective_config: Arc<serde_json::Value>,
+    /// not ready until the guest is valid and the enclave warmed up, with
+    /// `warmup_on_start`
+    readiness: Readiness,
+    /// not ready until the RPC node passes the startup probe, with `startup_probe_rpc`
+    dependencies: Readiness,
+    /// limit of the requests served at once, resized by `POST /admin/concurrency`
+    concurrency: Arc<ConcurrencyLimit>,
+    /// how often `POST /admin/rpc-benchmark` may run
+    rpc_benchmark_limit: Arc<RpcBenchmarkLimit>,
 }
 
 impl Handler {
-    fn new(
-        guest_path: PathBuf,
-        cache_path: PathBuf,
-        l2_chain: String,
-        sgx_instance_id: u32,
-        capacity: usize,
-        max_caches: usize,
-    ) -> Self {
+    fn new(opt: &Opt, effective_config: serde_json::Value, guest_valid: bool) -> Self {
         Self {
-            ctx: Context::new(
-                guest_path,
-                cache_path,
-                l2_chain,
-                sgx_instance_id,
-                max_caches,
-            ),
-            cache: Cache::new(capacity),
+            ctx: Context::new(opt),
+            cache: match &opt.proof_cache_dir {
+                Some(dir) => Cache::with_dir(opt.proof_cache, dir.clone())
+                    .expect("prepare the proof cache directory"),
+                None => Cache::new(opt.proof_cache),
+            },
+            idempotency: (!opt.disable_coalescing).then(|| {
+                IdempotencyStore::new(
+                    Retention {
+                        success: Duration::from_secs(
+                            opt.retain_success_secs.unwrap_or(opt.idempotency_ttl_secs),
+                        ),
+                        error: Duration::from_secs(opt.retain_error_secs),
+                    },
+                    opt.idempotency_max_keys,
+                )
+            }),
+            effective_config: Arc::new(effective_config),
+            readiness: Readiness::new(guest_valid && !opt.warmup_on_start),
+            dependencies: Readiness::new(opt.startup_probe_rpc.is_none()),
+            concurrency: ConcurrencyLimit::new(opt.concurrency_limit),
+            rpc_benchmark_limit: Arc::new(RpcBenchmarkLimit::new(Duration::from_secs(
+                opt.rpc_benchmark_interval_secs,
+            ))),
         }
     }
 
     async fn handle_request(self, req: Request<Body>) -> Result<Response<Body>, hyper::Error> {
-        {
+        // block data uploads are streamed and limited while decompressing
+        let is_upload =
+            req.method() == Method::POST && req.uri().path().starts_with(BLOCK_DATA_PATH);
+        if !is_upload {
             // limits the request size
             const MAX_BODY_SIZE: u64 = 1 << 20;
             let response_content_length = match req.body().size_hint().upper() {
@@ -128,15 +556,25 @@ impl Handler {
         }
 
         match (req.method(), req.uri().path()) {
-            (&Method::GET, "/health") => {
-                // nothing to report yet - healthy by default
-                let mut resp = Response::default();
+            (&Method::GET, "/health" | HEALTH_READY_PATH) => {
+                // healthy once ready, nothing else to report yet
+                let status = match self.dependencies.is_ready() {
+                    true => self.readiness.health_status(),
+                    false => self.dependencies.health_status(),
+                };
+                let mut resp = status_response(status);
                 set_headers(resp.headers_mut(), false);
                 Ok(resp)
             }
 
             // json-rpc
             (&Method::POST, "/") => {
+                let idempotency_key = req
+                    .headers()
+                    .get(IDEMPOTENCY_KEY_HEADER)
+                    .and_then(|value| value.to_str().ok())
+                    .map(ToOwned::to_owned);
+                let deadline = req.extensions().get::<JobDeadline>().copied();
                 let body_bytes = hyper::body::aggregate(req.into_body())
                     .await
                     .unwrap()
@@ -161,8 +599,14 @@ impl Handler {
                 }
 
                 let json_req = json_req.unwrap();
+                let clamped_timeout = self.clamped_timeout(&json_req.method, &json_req.params);
                 let result: Result<serde_json::Value, String> = self
-                    .handle_method(json_req.method.as_str(), &json_req.params)
+                    .handle_method(
+                        json_req.method.as_str(),
+                        &json_req.params,
+                        idempotency_key.as_deref(),
+                        deadline,
+                    )
                     .await;
                 let payload = match result {
                     Err(err) => {
@@ -184,6 +628,11 @@ impl Handler {
                 };
                 let mut resp = Response::new(Body::from(payload.unwrap()));
                 set_headers(resp.headers_mut(), false);
+                if let Some(timeout) = clamped_timeout {
+                    let warning = format!("299 raiko \"timeoutSecs clamped to {:?}\"", timeout);
+                    resp.headers_mut()
+                        .insert(WARNING, HeaderValue::from_str(&warning).unwrap());
+                }
                 Ok(resp)
             }
 
@@ -196,9 +645,15 @@ impl Handler {
 
             // serve metrics
             (&Method::GET, "/metrics") => {
+                if let Some(breaker) = &self.ctx.rpc_options.circuit_breaker {
+                    observe_rpc_breakers(breaker);
+                }
+                if let Some(limit) = &self.ctx.rpc_options.cache_write_limit {
+                    observe_cache_writes(limit);
+                }
                 let encoder = TextEncoder::new();
                 let mut buffer = vec![];
-                let mf = prometheus::gather();
+                let mf = gather(&self.ctx.metrics_prefix);
                 encoder.encode(&mf, &mut buffer).unwrap();
                 let resp = Response::builder()
                     .header(hyper::header::CONTENT_TYPE, encoder.format_type())
@@ -207,6 +662,405 @@ impl Handler {
                 Ok(resp)
             }
 
+            // serve the effective config, with the secrets redacted
+            (&Method::GET, "/config") => {
+                if !self.is_debug_authorized(req.headers()) {
+                    return Ok(status_response(StatusCode::UNAUTHORIZED));
+                }
+                let mut resp = Response::new(Body::from(
+                    serde_json::to_vec(&*self.effective_config).unwrap(),
+                ));
+                set_headers(resp.headers_mut(), false);
+                Ok(resp)
+            }
+
+            // check the cached block data against the canonical chain, without proving
+            (&Method::POST, "/validate") => {
+                let body_bytes = hyper::body::aggregate(req.into_body())
+                    .await
+                    .unwrap()
+                    .reader();
+                let Ok(validate_req) = serde_json::from_reader::<_, ValidateRequest>(body_bytes)
+                else {
+                    return Ok(status_response(StatusCode::BAD_REQUEST));
+                };
+                match validate(&self.ctx, &validate_req).await {
+                    Ok(Some(report)) => {
+                        let mut resp =
+                            Response::new(Body::from(serde_json::to_vec(&report).unwrap()));
+                        set_headers(resp.headers_mut(), false);
+                        Ok(resp)
+                    }
+                    Ok(None) => Ok(status_response(StatusCode::NOT_FOUND)),
+                    Err(err) => {
+                        let mut resp = Response::new(Body::from(err.to_string()));
+                        *resp.status_mut() = StatusCode::BAD_GATEWAY;
+                        Ok(resp)
+                    }
+                }
+            }
+
+            // fetch and check the data of a block, without proving it
+            (&Method::GET, FEASIBILITY_PATH) => {
+                let Some(FeasibilityQuery {
+                    chain,
+                    block,
+                    l1_rpc,
+                    l2_rpc,
+                }) = query(req.uri())
+                else {
+                    return Ok(status_response(StatusCode::BAD_REQUEST));
+                };
+                if chain.is_some_and(|chain| chain != self.ctx.l2_chain) {
+                    return Ok(status_response(StatusCode::NOT_FOUND));
+                }
+                let feasibility = check_feasibility(&self.ctx, block, l1_rpc, l2_rpc).await;
+                let mut resp = Response::new(Body::from(serde_json::to_vec(&feasibility).unwrap()));
+                set_headers(resp.headers_mut(), false);
+                Ok(resp)
+            }
+
+            // estimate the time of the proof of a block, fetching its data
+            (&Method::GET, ESTIMATE_PATH) => {
+                let Some(FeasibilityQuery {
+                    chain,
+                    block,
+                    l1_rpc,
+                    l2_rpc,
+                }) = query(req.uri())
+                else {
+                    return Ok(status_response(StatusCode::BAD_REQUEST));
+                };
+                if chain.is_some_and(|chain| chain != self.ctx.l2_chain) {
+                    return Ok(status_response(StatusCode::NOT_FOUND));
+                }
+                match estimate_block(&self.ctx, block, l1_rpc, l2_rpc).await {
+                    Ok(estimate) => {
+                        let mut resp =
+                            Response::new(Body::from(serde_json::to_vec(&estimate).unwrap()));
+                        set_headers(resp.headers_mut(), false);
+                        Ok(resp)
+                    }
+                    Err(err) => {
+                        let mut resp = Response::new(Body::from(err.to_string()));
+                        *resp.status_mut() = StatusCode::BAD_GATEWAY;
+                        Ok(resp)
+                    }
+                }
+            }
+
+            // check the blocks of a range, streaming a json line per block
+            (&Method::POST, FEASIBILITY_BATCH_PATH) => {
+                let body_bytes = hyper::body::aggregate(req.into_body())
+                    .await
+                    .unwrap()
+                    .reader();
+                let Ok(batch) = serde_json::from_reader::<_, FeasibilityBatchRequest>(body_bytes)
+                else {
+                    return Ok(status_response(StatusCode::BAD_REQUEST));
+                };
+                if batch.chain.is_some_and(|chain| chain != self.ctx.l2_chain) {
+                    return Ok(status_response(StatusCode::NOT_FOUND));
+                }
+                if batch.from_block > batch.to_block
+                    || batch.to_block - batch.from_block >= MAX_FEASIBILITY_BATCH_BLOCKS
+                {
+                    let mut resp = Response::new(Body::from(format!(
+                        "fromBlock must not be after toBlock, and a batch checks {} blocks at most",
+                        MAX_FEASIBILITY_BATCH_BLOCKS
+                    )));
+                    *resp.status_mut() = StatusCode::BAD_REQUEST;
+                    return Ok(resp);
+                }
+                let (ctx, l1_rpc, l2_rpc) = (self.ctx.clone(), batch.l1_rpc, batch.l2_rpc);
+                let body = feasibility_batch(
+                    batch.from_block..=batch.to_block,
+                    self.ctx.feasibility_batch_concurrency,
+                    move |block| {
+                        let (ctx, l1_rpc, l2_rpc) = (ctx.clone(), l1_rpc.clone(), l2_rpc.clone());
+                        async move { check_feasibility(&ctx, block, l1_rpc, l2_rpc).await }
+                    },
+                );
+                let mut resp = Response::new(body);
+                set_headers(resp.headers_mut(), false);
+                resp.headers_mut().insert(
+                    "content-type",
+                    HeaderValue::from_static("application/x-ndjson"),
+                );
+                Ok(resp)
+            }
+
+            // check the signature of a proof envelope
+            (&Method::POST, "/verify-envelope") => {
+                let body_bytes = hyper::body::aggregate(req.into_body())
+                    .await
+                    .unwrap()
+                    .reader();
+                let Ok(verify_req) =
+                    serde_json::from_reader::<_, VerifyEnvelopeRequest>(body_bytes)
+                else {
+                    return Ok(status_response(StatusCode::BAD_REQUEST));
+                };
+                let result = VerifyEnvelopeResponse::verify(
+                    &verify_req,
+                    get_taiko_chain_spec(&self.ctx.l2_chain).report_data(),
+                    self.ctx.l2_chain_id,
+                );
+                let mut resp = Response::new(Body::from(serde_json::to_vec(&result).unwrap()));
+                set_headers(resp.headers_mut(), false);
+                Ok(resp)
+            }
+
+            // check that the proofs of a range of blocks attest the claimed range
+            (&Method::POST, VERIFY_RANGE_PATH) => {
+                let body_bytes = hyper::body::aggregate(req.into_body())
+                    .await
+                    .unwrap()
+                    .reader();
+                let Ok(verify_req) = serde_json::from_reader::<_, VerifyRangeRequest>(body_bytes)
+                else {
+                    return Ok(status_response(StatusCode::BAD_REQUEST));
+                };
+                let result = VerifyRangeResponse::verify(&verify_req);
+                let mut resp = Response::new(Body::from(serde_json::to_vec(&result).unwrap()));
+                set_headers(resp.headers_mut(), false);
+                Ok(resp)
+            }
+
+            // serve the witness of a recent job, keyed by block number
+            (&Method::GET, path) if path.starts_with(DUMP_WITNESS_PATH) => {
+                let block = &path[DUMP_WITNESS_PATH.len()..];
+                let Some(witness_store) = &self.ctx.witness_store else {
+                    return Ok(status_response(StatusCode::NOT_FOUND));
+                };
+                if !self.is_debug_authorized(req.headers()) {
+                    return Ok(status_response(StatusCode::UNAUTHORIZED));
+                }
+                let Ok(block) = block.parse::<u64>() else {
+                    return Ok(status_response(StatusCode::BAD_REQUEST));
+                };
+                let Some(witness) = witness_store.get(block) else {
+                    return Ok(status_response(StatusCode::NOT_FOUND));
+                };
+                let mut resp = Response::new(witness_body(witness));
+                set_headers(resp.headers_mut(), false);
+                Ok(resp)
+            }
+
+            // pause or resume the recording of the proof requests
+            (&Method::POST, RECORD_REQUESTS_PATH) => {
+                let Some(recorder) = &self.ctx.request_recorder else {
+                    return Ok(status_response(StatusCode::NOT_FOUND));
+                };
+                if !self.is_debug_authorized(req.headers()) {
+                    return Ok(status_response(StatusCode::UNAUTHORIZED));
+                }
+                let body_bytes = hyper::body::aggregate(req.into_body())
+                    .await
+                    .unwrap()
+                    .reader();
+                let Ok(toggle) = serde_json::from_reader::<_, RecordRequestsToggle>(body_bytes)
+                else {
+                    return Ok(status_response(StatusCode::BAD_REQUEST));
+                };
+                recorder.set_enabled(toggle.enabled);
+                let mut resp = Response::new(Body::from(serde_json::to_vec(&toggle).unwrap()));
+                set_headers(resp.headers_mut(), false);
+                Ok(resp)
+            }
+
+            // attest the active key of the guest with a fresh quote
+            (&Method::POST, ATTESTATION_QUOTE_PATH) => {
+                if !self.is_debug_authorized(req.headers()) {
+                    return Ok(status_response(StatusCode::UNAUTHORIZED));
+                }
+                match fetch_quote(&self.ctx).await {
+                    Ok(Some(quote)) => {
+                        let mut resp =
+                            Response::new(Body::from(serde_json::to_vec(&quote).unwrap()));
+                        set_headers(resp.headers_mut(), false);
+                        Ok(resp)
+                    }
+                    Ok(None) => Ok(status_response(StatusCode::NOT_IMPLEMENTED)),
+                    Err(err) => {
+                        warn!("Failed to generate a quote: {}", err);
+                        let mut resp = Response::new(Body::from(err));
+                        *resp.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                        Ok(resp)
+                    }
+                }
+            }
+
+            // the concurrency limit, and its target while it shrinks
+            (&Method::GET, ADMIN_CONCURRENCY_PATH) => {
+                if !self.is_debug_authorized(req.headers()) {
+                    return Ok(status_response(StatusCode::UNAUTHORIZED));
+                }
+                let status = self.concurrency.status();
+                let mut resp = Response::new(Body::from(serde_json::to_vec(&status).unwrap()));
+                set_headers(resp.headers_mut(), false);
+                Ok(resp)
+            }
+
+            // the most recent proof requests served, a page at a time
+            (&Method::GET, RECENT_PROOFS_PATH) => {
+                if !self.is_debug_authorized(req.headers()) {
+                    return Ok(status_response(StatusCode::UNAUTHORIZED));
+                }
+                let Some(RecentProofsQuery { limit, cursor }) = query(req.uri()) else {
+                    return Ok(status_response(StatusCode::BAD_REQUEST));
+                };
+                let max_limit = self.ctx.max_proof_history_limit;
+                let limit = limit.unwrap_or(max_limit).min(max_limit);
+                let page = self.ctx.proof_history.recent(limit, cursor);
+                let mut resp = Response::new(Body::from(serde_json::to_vec(&page).unwrap()));
+                set_headers(resp.headers_mut(), false);
+                Ok(resp)
+            }
+
+            // stream the log lines written from now on as server-sent events
+            (&Method::GET, LOGS_TAIL_PATH) => {
+                let Some(log_tail) = &self.ctx.log_tail else {
+                    return Ok(status_response(StatusCode::NOT_FOUND));
+                };
+                if !self.is_debug_authorized(req.headers()) {
+                    return Ok(status_response(StatusCode::UNAUTHORIZED));
+                }
+                let Some(LogsTailQuery { level }) = query(req.uri()) else {
+                    return Ok(status_response(StatusCode::BAD_REQUEST));
+                };
+                let (sender, body) = Body::channel();
+                tokio::spawn(stream_log_lines(log_tail.subscribe(), level, sender));
+                let resp = Response::builder()
+                    .header(hyper::header::CONTENT_TYPE, "text/event-stream")
+                    .header(hyper::header::CACHE_CONTROL, "no-cache")
+                    .body(body)
+                    .unwrap();
+                Ok(resp)
+            }
+
+            // resize the concurrency limit without a restart
+            (&Method::POST, ADMIN_CONCURRENCY_PATH) => {
+                if !self.is_debug_authorized(req.headers()) {
+                    return Ok(status_response(StatusCode::UNAUTHORIZED));
+                }
+                let body_bytes = hyper::body::aggregate(req.into_body())
+                    .await
+                    .unwrap()
+                    .reader();
+                let Ok(update) = serde_json::from_reader::<_, ConcurrencyUpdate>(body_bytes) else {
+                    return Ok(status_response(StatusCode::BAD_REQUEST));
+                };
+                let limit = update.concurrency_limit;
+                if limit == 0 || limit >= self.ctx.max_caches {
+                    let mut resp = Response::new(Body::from(format!(
+                        "concurrencyLimit must be greater than 0 and less than max_caches ({})",
+                        self.ctx.max_caches
+                    )));
+                    *resp.status_mut() = StatusCode::BAD_REQUEST;
+                    return Ok(resp);
+                }
+                let status = self.concurrency.resize(limit);
+                let mut resp = Response::new(Body::from(serde_json::to_vec(&status).unwrap()));
+                set_headers(resp.headers_mut(), false);
+                Ok(resp)
+            }
+
+            // time sample RPC calls to a node, without proving anything
+            (&Method::POST, ADMIN_RPC_BENCHMARK_PATH) => {
+                if !self.is_debug_authorized(req.headers()) {
+                    return Ok(status_response(StatusCode::UNAUTHORIZED));
+                }
+                let body_bytes = hyper::body::aggregate(req.into_body())
+                    .await
+                    .unwrap()
+                    .reader();
+                let Ok(benchmark) = serde_json::from_reader::<_, RpcBenchmarkRequest>(body_bytes)
+                else {
+                    return Ok(status_response(StatusCode::BAD_REQUEST));
+                };
+                let samples = benchmark.samples.unwrap_or(RPC_BENCHMARK_SAMPLES);
+                if samples == 0 || samples > MAX_RPC_BENCHMARK_SAMPLES {
+                    let mut resp = Response::new(Body::from(format!(
+                        "samples must be greater than 0 and at most {}",
+                        MAX_RPC_BENCHMARK_SAMPLES
+                    )));
+                    *resp.status_mut() = StatusCode::BAD_REQUEST;
+                    return Ok(resp);
+                }
+                if let Err(wait) = self.rpc_benchmark_limit.try_start() {
+                    let mut resp = Response::new(Body::from(format!(
+                        "a benchmark ran less than rpc_benchmark_interval_secs ago, retry in {}s",
+                        wait.as_secs() + 1
+                    )));
+                    *resp.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+                    resp.headers_mut().insert(
+                        hyper::header::RETRY_AFTER,
+                        HeaderValue::from(wait.as_secs() + 1),
+                    );
+                    return Ok(resp);
+                }
+                let default_address = get_taiko_chain_spec(&self.ctx.l2_chain)
+                    .l2_signal_service
+                    .unwrap_or_default();
+                match run_rpc_benchmark(&self.ctx.rpc_options, &benchmark, samples, default_address)
+                    .await
+                {
+                    Ok(report) => {
+                        let mut resp =
+                            Response::new(Body::from(serde_json::to_vec(&report).unwrap()));
+                        set_headers(resp.headers_mut(), false);
+                        // a measurement of the moment
+                        resp.headers_mut().insert(
+                            hyper::header::CACHE_CONTROL,
+                            HeaderValue::from_static("no-store"),
+                        );
+                        Ok(resp)
+                    }
+                    Err(err) => {
+                        let mut resp = Response::new(Body::from(err));
+                        *resp.status_mut() = StatusCode::BAD_GATEWAY;
+                        Ok(resp)
+                    }
+                }
+            }
+
+            // upload the block data of `/block-data/{block}/{l1|l2}` ahead of the proof request,
+            // in the gzipped json format of the cache files
+            (&Method::POST, path) if is_upload => {
+                // the proofs are built from the cache files
+                if !self.is_debug_authorized(req.headers()) {
+                    return Ok(status_response(StatusCode::UNAUTHORIZED));
+                }
+                let Some((Ok(block), layer)) = path[BLOCK_DATA_PATH.len()..]
+                    .split_once('/')
+                    .map(|(block, layer)| (block.parse::<u64>(), layer))
+                else {
+                    return Ok(status_response(StatusCode::BAD_REQUEST));
+                };
+                let is_l1 = match layer {
+                    "l1" => true,
+                    "l2" => false,
+                    _ => return Ok(status_response(StatusCode::BAD_REQUEST)),
+                };
+                let path =
+                    cache_file_path(&self.ctx.cache_path, self.ctx.l2_chain_id, block, is_l1);
+                match store_block_data(req.into_body(), &path, self.ctx.max_block_size_bytes).await
+                {
+                    Ok(size) => {
+                        info!("Stored {} bytes of block data in {:?}", size, path);
+                        let mut resp = Response::default();
+                        set_headers(resp.headers_mut(), false);
+                        Ok(resp)
+                    }
+                    Err(err) => {
+                        let mut resp = Response::new(Body::from(err.to_string()));
+                        *resp.status_mut() = StatusCode::BAD_REQUEST;
+                        Ok(resp)
+                    }
+                }
+            }
+
             // everything else
             _ => {
                 let mut not_found = Response::default();
@@ -216,23 +1070,452 @@ impl Handler {
         }
     }
 
+    fn is_debug_authorized(&self, headers: &hyper::HeaderMap) -> bool {
+        let Some(Secret(token)) = &self.ctx.debug_auth_token else {
+            return false;
+        };
+        headers
+            .get(hyper::header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            // compared by digest, in a time independent of the bytes of the token matched
+            .map(|value| keccak256(value) == keccak256(token))
+            .unwrap_or(false)
+    }
+
+    /// The ceiling the `timeoutSecs` of a proof request was clamped to, if it was.
+    fn clamped_timeout(&self, method: &str, params: &[serde_json::Value]) -> Option<Duration> {
+        if method != "proof" {
+            return None;
+        }
+        let req: ProofRequest = serde_json::from_value(params.first()?.clone()).ok()?;
+        let timeout_secs = match &req {
+            ProofRequest::Sgx(req) => req.timeout_secs,
+            ProofRequest::Multi(req) => req.block.timeout_secs,
+            ProofRequest::PseZk(_) => None,
+        };
+        match self.ctx.guest_timeout(timeout_secs) {
+            (Some(timeout), true) => {
+                warn!(
+                    "Clamped the timeoutSecs ({:?}) of a proof request to {:?}",
+                    timeout_secs, timeout
+                );
+                Some(timeout)
+            }
+            _ => None,
+        }
+    }
+
     async fn handle_method(
         &self,
         method: &str,
         params: &[serde_json::Value],
+        idempotency_key: Option<&str>,
+        deadline: Option<JobDeadline>,
     ) -> Result<serde_json::Value, String> {
         match method {
             // enqueues a task for computating proof for any given block
             "proof" => {
-                let options = params.first().ok_or("expected struct ProofRequest")?;
-                let req: ProofRequest =
-                    serde_json::from_value(options.to_owned()).map_err(|e| e.to_string())?;
-                execute(&self.cache, &self.ctx, &req)
-                    .await
-                    .and_then(|result| serde_json::to_value(result).map_err(Into::into))
-                    .map_err(|e| e.to_string())
+                let mut options = params
+                    .first()
+                    .ok_or("expected struct ProofRequest")?
+                    .to_owned();
+                self.ctx.request_defaults.apply(&mut options);
+                let mut req: ProofRequest =
+                    serde_json::from_value(options.clone()).map_err(|e| e.to_string())?;
+                match &mut req {
+                    ProofRequest::Sgx(req) => req.deadline = deadline,
+                    ProofRequest::Multi(req) => req.block.deadline = deadline,
+                    ProofRequest::PseZk(_) => {}
+                }
+                let prove = || async {
+                    let result = match self.ctx.benchmark_mode {
+                        true => execute_benchmark(&self.ctx, &req).await,
+                        false => execute(&self.cache, &self.ctx, &req).await,
+                    };
+                    result
+                        .and_then(|result| serde_json::to_value(result).map_err(Into::into))
+                        .map_err(|e| e.to_string())
+                };
+                let start = Instant::now();
+                let result = match (idempotency_key, &self.idempotency) {
+                    (Some(key), Some(idempotency)) => idempotency.run(key, &options, prove).await,
+                    _ => prove().await,
+                };
+                let result = match deadline {
+                    Some(deadline) => result.map_err(|err| deadline.job_error(err)),
+                    None => result,
+                };
+                self.ctx.proof_history.record(
+                    &self.ctx.l2_chain,
+                    &req,
+                    result.is_ok(),
+                    start.elapsed(),
+                );
+                if let (Err(error), Some(dumps)) = (&result, self.ctx.failure_dumps.clone()) {
+                    let block = match &req {
+                        ProofRequest::Sgx(req) => Some(req.block),
+                        ProofRequest::Multi(req) => Some(req.block.block),
+                        ProofRequest::PseZk(_) => None,
+                    };
+                    let (ctx, method, options, error) = (
+                        self.ctx.clone(),
+                        method.to_owned(),
+                        options.clone(),
+                        error.clone(),
+                    );
+                    tokio::task::spawn_blocking(move || {
+                        dumps.dump_logged(&ctx, block, &method, &options, &error)
+                    });
+                }
+                if let Some(recorder) = self.ctx.request_recorder.clone() {
+                    // with the defaults applied, for the replays not to depend on the
+                    // ones of the target
+                    let (method, result) = (method.to_owned(), result.clone());
+                    tokio::task::spawn_blocking(move || {
+                        recorder.record(&method, &[options], &result)
+                    });
+                }
+                result
             }
             _ => todo!(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prover::witness_store::Witness;
+
+    /// A handler of a server started with `args`.
+    fn handler(args: &[&str]) -> Handler {
+        use structopt::StructOpt;
+
+        let opt = Opt::from_clap(&Opt::clap().get_matches_from(args));
+        Handler::new(&opt, serde_json::Value::Null, true)
+    }
+
+    /// The status of the response to `method path`, with `token` as the bearer token.
+    async fn status(
+        handler: &Handler,
+        method: Method,
+        path: &str,
+        token: Option<&str>,
+        body: Body,
+    ) -> StatusCode {
+        let mut req = Request::builder().method(method).uri(path);
+        if let Some(token) = token {
+            req = req.header(hyper::header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        let resp = handler
+            .clone()
+            .handle_request(req.body(body).unwrap())
+            .await
+            .unwrap();
+        resp.status()
+    }
+
+    async fn get(handler: &Handler, path: &str, token: Option<&str>) -> StatusCode {
+        status(handler, Method::GET, path, token, Body::empty()).await
+    }
+
+    #[tokio::test]
+    async fn debug_endpoints_require_the_bearer_token() {
+        let path = "/debug/dump-witness/7";
+
+        // not served without --enable-debug-endpoints
+        let disabled = handler(&["raiko-host", "--debug-auth-token=secret"]);
+        assert_eq!(
+            get(&disabled, path, Some("secret")).await,
+            StatusCode::NOT_FOUND
+        );
+
+        let enabled = handler(&[
+            "raiko-host",
+            "--enable-debug-endpoints",
+            "--debug-auth-token=secret",
+        ]);
+        enabled.ctx.witness_store.as_ref().unwrap().insert(Witness {
+            block: 7,
+            l1_blocks_data: vec![1],
+            l2_blocks_data: vec![2],
+        });
+        assert_eq!(get(&enabled, path, None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            get(&enabled, path, Some("secre")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            get(&enabled, path, Some("secret!")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(get(&enabled, path, Some("secret")).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn block_data_uploads_require_the_bearer_token() {
+        let handler = handler(&["raiko-host", "--debug-auth-token=secret"]);
+        let path = "/block-data/7/l2";
+        let upload = |token| status(&handler, Method::POST, path, token, Body::from("data"));
+        assert_eq!(upload(None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(upload(Some("wrong")).await, StatusCode::UNAUTHORIZED);
+
+        // only the uploads bypass the request size limit
+        let large = Body::from(vec![0u8; 2 << 20]);
+        let resp = status(&handler, Method::GET, path, Some("secret"), large).await;
+        assert_eq!(resp, StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn route_template_hides_ids() {
+        assert_eq!(route_template("/"), "/");
+        assert_eq!(route_template("/metrics"), "/metrics");
+        assert_eq!(route_template("/health/ready"), "/health/ready");
+        assert_eq!(route_template("/config"), "/config");
+        assert_eq!(route_template("/validate"), "/validate");
+        assert_eq!(route_template("/verify-envelope"), "/verify-envelope");
+        assert_eq!(
+            route_template("/verify-envelope/range"),
+            "/verify-envelope/range"
+        );
+        assert_eq!(route_template("/prove/feasibility"), "/prove/feasibility");
+        assert_eq!(
+            route_template("/prove/feasibility/batch"),
+            "/prove/feasibility/batch"
+        );
+        assert_eq!(route_template("/prove/estimate"), "/prove/estimate");
+        assert_eq!(
+            route_template("/debug/record-requests"),
+            "/debug/record-requests"
+        );
+        assert_eq!(route_template("/attestation/quote"), "/attestation/quote");
+        assert_eq!(route_template("/admin/concurrency"), "/admin/concurrency");
+        assert_eq!(
+            route_template("/admin/rpc-benchmark"),
+            "/admin/rpc-benchmark"
+        );
+        assert_eq!(route_template("/proofs/recent"), "/proofs/recent");
+        assert_eq!(route_template("/logs/tail"), "/logs/tail");
+        assert_eq!(
+            route_template("/debug/dump-witness/123"),
+            "/debug/dump-witness/{block}"
+        );
+        assert_eq!(
+            route_template("/block-data/123/l1"),
+            route_template("/block-data/456/l2")
+        );
+        assert_eq!(route_template("/scan/123"), "unmatched");
+    }
+
+    #[test]
+    fn queries_decode_their_parameters() {
+        let uri = |uri: &'static str| Uri::from_static(uri);
+        assert_eq!(
+            query(&uri(
+                "/prove/feasibility?block=7&l1Rpc=http%3A%2F%2Fl1%3A8545&l2Rpc=http://l2"
+            )),
+            Some(FeasibilityQuery {
+                chain: None,
+                block: 7,
+                l1_rpc: "http://l1:8545".to_owned(),
+                l2_rpc: "http://l2".to_owned(),
+            })
+        );
+        assert_eq!(
+            query::<FeasibilityQuery>(&uri(
+                "/prove/feasibility?chain=testnet&block=x&l1Rpc=a&l2Rpc=b"
+            )),
+            None
+        );
+
+        assert_eq!(
+            query(&uri("/proofs/recent")),
+            Some(RecentProofsQuery {
+                limit: None,
+                cursor: None
+            })
+        );
+        assert_eq!(
+            query(&uri("/proofs/recent?limit=5&cursor=12")),
+            Some(RecentProofsQuery {
+                limit: Some(5),
+                cursor: Some(12)
+            })
+        );
+        assert_eq!(
+            query::<RecentProofsQuery>(&uri("/proofs/recent?limit=-1")),
+            None
+        );
+
+        assert_eq!(
+            query(&uri("/logs/tail")),
+            Some(LogsTailQuery { level: None })
+        );
+        assert_eq!(
+            query(&uri("/logs/tail?level=warn")),
+            Some(LogsTailQuery {
+                level: Some(Level::WARN)
+            })
+        );
+        assert_eq!(query::<LogsTailQuery>(&uri("/logs/tail?level=loud")), None);
+    }
+
+    #[test]
+    fn routes_are_served_under_the_base_path() {
+        let request = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+        let strip = |base_path: &str, uri: &str| {
+            strip_base_path(base_path, request(uri)).map(|req| req.uri().to_string())
+        };
+
+        assert_eq!(strip("/raiko", "/raiko/health").as_deref(), Some("/health"));
+        assert_eq!(strip("/raiko", "/raiko").as_deref(), Some("/"));
+        assert_eq!(strip("/raiko", "/raiko/").as_deref(), Some("/"));
+        assert_eq!(
+            strip("/raiko", "/raiko/debug/dump-witness/12?pretty").as_deref(),
+            Some("/debug/dump-witness/12?pretty")
+        );
+        // the metrics are labelled by the routes, whatever the base path
+        let req = strip_base_path("/raiko", request("/raiko/block-data/2/l1")).unwrap();
+        assert_eq!(
+            route_template(req.uri().path()),
+            "/block-data/{block}/{layer}"
+        );
+
+        assert_eq!(strip("/raiko", "/health"), None);
+        assert_eq!(strip("/raiko", "/raikox/health"), None);
+        assert_eq!(strip("", "/health").as_deref(), Some("/health"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn http2_streams_share_one_connection() {
+        use std::{
+            convert::Infallible,
+            sync::atomic::{AtomicUsize, Ordering},
+        };
+
+        use hyper::{Client, Version};
+
+        let serve = |http2: bool| {
+            let connections = Arc::new(AtomicUsize::new(0));
+            let counted = connections.clone();
+            let make_service = make_service_fn(move |_| {
+                counted.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    Ok::<_, Infallible>(service_fn(|_req: Request<Body>| async move {
+                        // streamed as the events of /logs/tail
+                        let (mut sender, body) = Body::channel();
+                        tokio::spawn(async move {
+                            for event in ["data: one\n\n", "data: two\n\n"] {
+                                tokio::time::sleep(Duration::from_millis(100)).await;
+                                sender.send_data(event.into()).await.unwrap();
+                            }
+                        });
+                        Ok::<_, Infallible>(Response::new(body))
+                    }))
+                }
+            });
+            let incoming = AddrIncoming::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+            let uri: Uri = format!("http://{}/logs/tail", incoming.local_addr())
+                .parse()
+                .unwrap();
+            let server = server_builder(incoming, None, None, http2).serve(make_service);
+            tokio::spawn(server);
+            (uri, connections)
+        };
+        let client = Client::builder().http2_only(true).build_http::<Body>();
+
+        let (uri, connections) = serve(true);
+        client.get(uri.clone()).await.unwrap();
+        let started = Instant::now();
+        let requests: Vec<_> = (0..8)
+            .map(|_| {
+                let (client, uri) = (client.clone(), uri.clone());
+                tokio::spawn(async move {
+                    let resp = client.get(uri).await.unwrap();
+                    assert_eq!(resp.version(), Version::HTTP_2);
+                    hyper::body::to_bytes(resp.into_body()).await.unwrap()
+                })
+            })
+            .collect();
+        for request in requests {
+            assert_eq!(&request.await.unwrap()[..], b"data: one\n\ndata: two\n\n");
+        }
+        // the streams are served at once, over the connection of the first request
+        assert!(started.elapsed() < Duration::from_millis(8 * 200));
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+
+        // HTTP/1.1 only by default
+        let (uri, _) = serve(false);
+        assert!(client.get(uri).await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn connections_over_the_limit_of_a_client_are_refused() {
+        use std::convert::Infallible;
+
+        use tokio::{
+            io::{AsyncReadExt, AsyncWriteExt},
+            net::{TcpSocket, TcpStream},
+        };
+
+        let connections = ClientConnections::new(2);
+        let counted = connections.clone();
+        let make_service = make_service_fn(move |conn: &AddrStream| {
+            let service = service_fn(|_req: Request<Body>| async move {
+                Ok::<_, Infallible>(Response::new(Body::from("ok")))
+            });
+            let service =
+                connection_service(service, conn.remote_addr().ip(), Some(&counted), None);
+            async move { service }
+        });
+        let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make_service);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let connect = |client: &str| {
+            let client = client.parse().unwrap();
+            async move {
+                let socket = TcpSocket::new_v4().unwrap();
+                socket.bind(SocketAddr::new(client, 0)).unwrap();
+                socket.connect(addr).await.unwrap()
+            }
+        };
+        // whether the connection is still served
+        async fn get(stream: &mut TcpStream) -> bool {
+            let mut buf = [0u8; 1024];
+            if stream
+                .write_all(b"GET / HTTP/1.1\r\nhost: raiko\r\n\r\n")
+                .await
+                .is_err()
+            {
+                return false;
+            }
+            match stream.read(&mut buf).await {
+                Ok(read) => buf[..read].starts_with(b"HTTP/1.1 200"),
+                Err(_) => false,
+            }
+        }
+
+        let mut first = connect("127.0.0.1").await;
+        let mut second = connect("127.0.0.1").await;
+        assert!(get(&mut first).await);
+        assert!(get(&mut second).await);
+        assert!(!get(&mut connect("127.0.0.1").await).await);
+        // the open connections are still served, the other clients too
+        assert!(get(&mut first).await);
+        assert!(get(&mut connect("127.0.0.2").await).await);
+        let client = "127.0.0.1".parse().unwrap();
+        assert_eq!(connections.count(&client), 2);
+
+        // a connection closed frees its slot
+        drop(first);
+        let started = Instant::now();
+        while connections.count(&client) > 1 {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(get(&mut connect("127.0.0.1").await).await);
+        assert!(get(&mut second).await);
+    }
+}
@@ -0,0 +1,110 @@
+//! Rejects the proof requests projected to take longer than `target_proof_ms`, waiting
+//! for the concurrency limit included, so that the accepted ones stay within the target
+//! during an overload and the rejected ones are retried on another server.
+use std::{sync::Mutex, time::Duration};
+
+use super::concurrency::ConcurrencyLimit;
+
+/// Weight of the latest proof in the estimated proof time.
+const PROOF_TIME_WEIGHT: f64 = 0.2;
+
+/// Projects the time a new proof request would take from the requests ahead of it and
+/// from the moving average of the recent proof times.
+#[derive(Debug)]
+pub struct LoadShedder {
+    target: Duration,
+    /// `None` until a proof is served, no request is shed before
+    proof_time: Mutex<Option<Duration>>,
+}
+
+impl LoadShedder {
+    pub fn new(target: Duration) -> Self {
+        LoadShedder {
+            target,
+            proof_time: Mutex::new(None),
+        }
+    }
+
+    pub fn target(&self) -> Duration {
+        self.target
+    }
+
+    /// Accounts the time a proof request held its permit.
+    pub fn record(&self, elapsed: Duration) {
+        let mut proof_time = self.proof_time.lock().unwrap();
+        *proof_time = Some(match *proof_time {
+            Some(average) => {
+                average.mul_f64(1.0 - PROOF_TIME_WEIGHT) + elapsed.mul_f64(PROOF_TIME_WEIGHT)
+            }
+            None => elapsed,
+        });
+    }
+
+    /// The time a new proof request would take: none waiting with a free permit,
+    /// otherwise until the requests ahead of it and one of the requests in flight
+    /// finish, `status.current` at a time, then its own proof.
+    pub fn projected(&self, limit: &ConcurrencyLimit) -> Option<Duration> {
+        let proof_time = (*self.proof_time.lock().unwrap())?;
+        let (waiting, available) = (limit.waiting(), limit.available());
+        if available > waiting {
+            return Some(proof_time);
+        }
+        let slots = limit.status().current.max(1) as u32;
+        let ahead = (waiting - available + 1) as u32;
+        Some(proof_time * ahead / slots + proof_time)
+    }
+
+    /// The projected time of a new proof request, when it exceeds the target.
+    pub fn should_shed(&self, limit: &ConcurrencyLimit) -> Option<Duration> {
+        self.projected(limit)
+            .filter(|projected| *projected > self.target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn saturated_server_sheds_once_over_the_target() {
+        let limit = ConcurrencyLimit::new(2);
+        let shedder = LoadShedder::new(Duration::from_millis(200));
+        // nothing to project from before the first proof
+        let first = limit.acquire().await;
+        let second = limit.acquire().await;
+        assert_eq!(shedder.should_shed(&limit), None);
+
+        shedder.record(Duration::from_millis(100));
+        drop(second);
+        assert_eq!(shedder.projected(&limit), Some(Duration::from_millis(100)));
+        assert_eq!(shedder.should_shed(&limit), None);
+
+        // saturated: waits for one of the two in flight, 150ms in all
+        let second = limit.acquire().await;
+        assert_eq!(shedder.projected(&limit), Some(Duration::from_millis(150)));
+        assert_eq!(shedder.should_shed(&limit), None);
+
+        // with two waiting ahead, 250ms, and more once the proofs slow down
+        let waiters: Vec<_> = (0..2)
+            .map(|_| {
+                let limit = limit.clone();
+                tokio::spawn(async move { drop(limit.acquire().await) })
+            })
+            .collect();
+        while limit.waiting() < 2 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(
+            shedder.should_shed(&limit),
+            Some(Duration::from_millis(250))
+        );
+        shedder.record(Duration::from_millis(200));
+        assert!(shedder.should_shed(&limit) > Some(Duration::from_millis(250)));
+
+        drop((first, second));
+        for waiter in waiters {
+            waiter.await.unwrap();
+        }
+        assert_eq!(shedder.should_shed(&limit), None);
+    }
+}
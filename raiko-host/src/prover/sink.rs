@@ -0,0 +1,492 @@
+//! Sinks every completed proof is delivered to, e.g. an archive directory and the
+//! submitter service.
+use std::{
+    collections::BTreeMap, fmt, fs, path::PathBuf, str::FromStr, sync::Arc, thread, time::Duration,
+};
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_with::{DeserializeFromStr, SerializeDisplay};
+use tracing::warn;
+use zeth_primitives::{Address, B256};
+
+/// A completed proof, as delivered to the sinks.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProofResult {
+    pub chain: String,
+    pub chain_id: u64,
+    pub block: u64,
+    pub prover: Address,
+    pub graffiti: B256,
+    pub proof: String,
+    /// the metadata of the request
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, serde_json::Value>,
+}
+
+pub trait Sink: fmt::Debug + Send + Sync {
+    fn deliver(&self, result: &ProofResult) -> Result<()>;
+}
+
+/// Prints each proof as a JSON line.
+#[derive(Debug)]
+pub struct StdoutSink;
+
+impl Sink for StdoutSink {
+    fn deliver(&self, result: &ProofResult) -> Result<()> {
+        println!("{}", serde_json::to_string(result)?);
+        Ok(())
+    }
+}
+
+/// How the file sinks spread the proofs over subdirectories by block number, given as
+/// `flat`, `by-1000` or `by-10000`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, DeserializeFromStr, SerializeDisplay)]
+pub enum OutputSharding {
+    #[default]
+    Flat,
+    By1000,
+    By10000,
+}
+
+impl OutputSharding {
+    /// The subdirectory of the proof of `block`, e.g. `12000-12999` for block 12345 by
+    /// 1000, or none when flat.
+    pub fn shard(&self, block: u64) -> Option<String> {
+        let size = match self {
+            OutputSharding::Flat => return None,
+            OutputSharding::By1000 => 1000,
+            OutputSharding::By10000 => 10_000,
+        };
+        let start = block / size * size;
+        Some(format!("{}-{}", start, start + size - 1))
+    }
+}
+
+impl FromStr for OutputSharding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "flat" => Ok(OutputSharding::Flat),
+            "by-1000" => Ok(OutputSharding::By1000),
+            "by-10000" => Ok(OutputSharding::By10000),
+            _ => Err(format!(
+                "invalid output sharding {:?}, expected flat, by-1000 or by-10000",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for OutputSharding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputSharding::Flat => write!(f, "flat"),
+            OutputSharding::By1000 => write!(f, "by-1000"),
+            OutputSharding::By10000 => write!(f, "by-10000"),
+        }
+    }
+}
+
+/// Writes each proof to `{dir}/{chain}/{block}.json`, or to
+/// `{dir}/{chain}/{shard}/{block}.json` when sharded.
+#[derive(Debug)]
+pub struct FileSink {
+    dir: PathBuf,
+    sharding: OutputSharding,
+}
+
+impl FileSink {
+    fn path(&self, result: &ProofResult) -> PathBuf {
+        let mut dir = self.dir.join(&result.chain);
+        if let Some(shard) = self.sharding.shard(result.block) {
+            dir.push(shard);
+        }
+        dir.join(format!("{}.json", result.block))
+    }
+}
+
+impl Sink for FileSink {
+    fn deliver(&self, result: &ProofResult) -> Result<()> {
+        let path = self.path(result);
+        fs::create_dir_all(path.parent().unwrap())?;
+        let tmp_path = path.with_extension("json.part");
+        fs::write(&tmp_path, serde_json::to_vec(result)?)?;
+        fs::rename(&tmp_path, &path)?;
+        Ok(())
+    }
+}
+
+/// Posts each proof as JSON to `url`.
+pub struct WebhookSink {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl fmt::Debug for WebhookSink {
+    // the url may embed credentials
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("WebhookSink")
+    }
+}
+
+impl Sink for WebhookSink {
+    fn deliver(&self, result: &ProofResult) -> Result<()> {
+        // sinks run on the blocking threads of the runtime
+        tokio::runtime::Handle::current().block_on(async {
+            let resp = self.client.post(&self.url).json(result).send().await?;
+            if !resp.status().is_success() {
+                bail!("webhook responded with {}", resp.status());
+            }
+            Ok(())
+        })
+    }
+}
+
+/// A `[[sink]]` of the config file, or a `--sink` given as `stdout`, `file:<dir>`,
+/// `webhook:<url>` or `s3://<bucket>/<prefix>`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SinkConfig {
+    #[serde(flatten)]
+    pub kind: SinkKind,
+    /// Number of retries of a failed delivery
+    #[serde(default = "default_sink_retries")]
+    pub retries: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SinkKind {
+    Stdout,
+    File {
+        dir: PathBuf,
+    },
+    Webhook {
+        url: String,
+    },
+    /// `url` as `s3://bucket/prefix`, on the AWS endpoint of `region` unless `endpoint`
+    #[cfg(feature = "s3")]
+    S3 {
+        url: String,
+        endpoint: Option<String>,
+        #[serde(default = "default_s3_region")]
+        region: String,
+        #[serde(default = "default_s3_key_template")]
+        key_template: String,
+    },
+}
+
+#[cfg(feature = "s3")]
+fn default_s3_region() -> String {
+    std::env::var("AWS_REGION").unwrap_or_else(|_| "us-east-1".to_owned())
+}
+
+#[cfg(feature = "s3")]
+fn default_s3_key_template() -> String {
+    super::s3_sink::DEFAULT_KEY_TEMPLATE.to_owned()
+}
+
+fn default_sink_retries() -> u32 {
+    3
+}
+
+impl FromStr for SinkConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let kind = match s.split_once(':') {
+            None if s == "stdout" => SinkKind::Stdout,
+            Some(("file", dir)) if !dir.is_empty() => SinkKind::File { dir: dir.into() },
+            Some(("webhook", url)) if !url.is_empty() => SinkKind::Webhook {
+                url: url.to_owned(),
+            },
+            #[cfg(feature = "s3")]
+            Some(("s3", _)) => {
+                super::s3_sink::parse_s3_url(s)?;
+                SinkKind::S3 {
+                    url: s.to_owned(),
+                    endpoint: None,
+                    region: default_s3_region(),
+                    key_template: default_s3_key_template(),
+                }
+            }
+            _ => {
+                return Err(format!(
+                    "invalid sink {:?}, expected stdout, file:<dir> or webhook:<url>",
+                    s
+                ))
+            }
+        };
+        Ok(SinkConfig {
+            kind,
+            retries: default_sink_retries(),
+        })
+    }
+}
+
+impl SinkConfig {
+    /// Checks the fields the parsing of the config file does not.
+    pub fn validate(&self) -> Result<(), String> {
+        #[cfg(feature = "s3")]
+        if let SinkKind::S3 {
+            url,
+            endpoint,
+            region,
+            key_template,
+        } = &self.kind
+        {
+            super::s3_sink::S3Sink::new(url, endpoint.as_deref(), region, key_template)
+                .map_err(|err| format!("invalid s3 sink {}: {}", url, err))?;
+        }
+        Ok(())
+    }
+}
+
+/// A configured sink with its retry policy.
+#[derive(Debug, Clone)]
+pub struct SinkHandle {
+    sink: Arc<dyn Sink>,
+    retries: u32,
+    retry_delay: Duration,
+}
+
+impl SinkHandle {
+    pub fn new(config: &SinkConfig, sharding: OutputSharding) -> Self {
+        let sink: Arc<dyn Sink> = match &config.kind {
+            SinkKind::Stdout => Arc::new(StdoutSink),
+            SinkKind::File { dir } => Arc::new(FileSink {
+                dir: dir.clone(),
+                sharding,
+            }),
+            SinkKind::Webhook { url } => Arc::new(WebhookSink {
+                url: url.clone(),
+                client: reqwest::Client::new(),
+            }),
+            #[cfg(feature = "s3")]
+            SinkKind::S3 {
+                url,
+                endpoint,
+                region,
+                key_template,
+            } => Arc::new(
+                super::s3_sink::S3Sink::new(url, endpoint.as_deref(), region, key_template)
+                    .expect("checked by SinkConfig::validate"),
+            ),
+        };
+        SinkHandle {
+            sink,
+            retries: config.retries,
+            retry_delay: Duration::from_secs(1),
+        }
+    }
+
+    fn deliver_with_retries(&self, result: &ProofResult) -> Result<()> {
+        let mut attempt = 0;
+        loop {
+            match self.sink.deliver(result) {
+                Ok(()) => return Ok(()),
+                Err(err) if attempt < self.retries => {
+                    warn!(
+                        "Delivering the proof of block {} to {:?} failed, retrying: {}",
+                        result.block, self.sink, err
+                    );
+                    attempt += 1;
+                    thread::sleep(self.retry_delay * attempt);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+/// Delivers the proof to all sinks, each on its own blocking thread so that a failing or
+/// slow sink doesn't hold up the others. Returns the errors of the sinks which failed
+/// after all retries, in the order of the sinks.
+pub async fn fan_out(sinks: Vec<SinkHandle>, result: ProofResult) -> Vec<anyhow::Error> {
+    let result = Arc::new(result);
+    let deliveries: Vec<_> = sinks
+        .iter()
+        .cloned()
+        .map(|sink| {
+            let result = result.clone();
+            tokio::task::spawn_blocking(move || sink.deliver_with_retries(&result))
+        })
+        .collect();
+    let mut errors = vec![];
+    for (sink, delivery) in sinks.iter().zip(deliveries) {
+        let delivered = match delivery.await {
+            Ok(delivered) => delivered,
+            Err(err) => Err(err.into()),
+        };
+        if let Err(err) = delivered {
+            warn!(
+                "Dropping the proof of block {} for {:?}: {}",
+                result.block, sink.sink, err
+            );
+            errors.push(err);
+        }
+    }
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicU32, Ordering},
+        Mutex,
+    };
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct FailingSink {
+        attempts: AtomicU32,
+    }
+
+    impl Sink for FailingSink {
+        fn deliver(&self, _result: &ProofResult) -> Result<()> {
+            self.attempts.fetch_add(1, Ordering::SeqCst);
+            bail!("submitter is down")
+        }
+    }
+
+    #[derive(Debug, Default)]
+    struct RecordingSink {
+        delivered: Mutex<Vec<u64>>,
+    }
+
+    impl Sink for RecordingSink {
+        fn deliver(&self, result: &ProofResult) -> Result<()> {
+            self.delivered.lock().unwrap().push(result.block);
+            Ok(())
+        }
+    }
+
+    fn handle(sink: Arc<dyn Sink>, retries: u32) -> SinkHandle {
+        SinkHandle {
+            sink,
+            retries,
+            retry_delay: Duration::ZERO,
+        }
+    }
+
+    #[test]
+    fn test_parse_sink() {
+        assert_eq!(
+            "stdout".parse::<SinkConfig>().unwrap().kind,
+            SinkKind::Stdout
+        );
+        assert_eq!(
+            "file:/var/proofs".parse::<SinkConfig>().unwrap().kind,
+            SinkKind::File {
+                dir: "/var/proofs".into()
+            }
+        );
+        assert_eq!(
+            "webhook:http://localhost:9000/proofs"
+                .parse::<SinkConfig>()
+                .unwrap()
+                .kind,
+            SinkKind::Webhook {
+                url: "http://localhost:9000/proofs".into()
+            }
+        );
+        assert!("file:".parse::<SinkConfig>().is_err());
+        assert!("kafka:proofs".parse::<SinkConfig>().is_err());
+    }
+
+    #[test]
+    fn test_sink_tables() {
+        #[derive(Deserialize)]
+        struct Config {
+            sink: Vec<SinkConfig>,
+        }
+        let config: Config = toml::from_str(
+            r#"
+            [[sink]]
+            type = "file"
+            dir = "/var/proofs"
+
+            [[sink]]
+            type = "webhook"
+            url = "http://localhost:9000/proofs"
+            retries = 5
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.sink,
+            vec![
+                SinkConfig {
+                    kind: SinkKind::File {
+                        dir: "/var/proofs".into()
+                    },
+                    retries: 3,
+                },
+                SinkConfig {
+                    kind: SinkKind::Webhook {
+                        url: "http://localhost:9000/proofs".into()
+                    },
+                    retries: 5,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_output_sharding() {
+        let result = ProofResult {
+            chain: "testnet".to_owned(),
+            chain_id: 167001,
+            block: 12345,
+            prover: Address::ZERO,
+            graffiti: B256::ZERO,
+            proof: "0x01".to_owned(),
+            metadata: Default::default(),
+        };
+        let path = |sharding: &str| {
+            let sink = FileSink {
+                dir: "/var/proofs".into(),
+                sharding: sharding.parse().unwrap(),
+            };
+            sink.path(&result)
+        };
+        assert_eq!(
+            path("flat"),
+            PathBuf::from("/var/proofs/testnet/12345.json")
+        );
+        assert_eq!(
+            path("by-1000"),
+            PathBuf::from("/var/proofs/testnet/12000-12999/12345.json")
+        );
+        assert_eq!(
+            path("by-10000"),
+            PathBuf::from("/var/proofs/testnet/10000-19999/12345.json")
+        );
+        assert_eq!(OutputSharding::By1000.shard(999).unwrap(), "0-999");
+        assert!("by-100".parse::<OutputSharding>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_failing_sink_does_not_block_the_others() {
+        let failing = Arc::new(FailingSink::default());
+        let recording = Arc::new(RecordingSink::default());
+        let sinks = [handle(failing.clone(), 2), handle(recording.clone(), 2)];
+        let result = ProofResult {
+            chain: "testnet".to_owned(),
+            chain_id: 167001,
+            block: 100,
+            prover: Address::ZERO,
+            graffiti: B256::ZERO,
+            proof: "0x01".to_owned(),
+            metadata: Default::default(),
+        };
+
+        let errors = fan_out(sinks.to_vec(), result).await;
+        assert_eq!(errors.len(), 1);
+        assert_eq!(failing.attempts.load(Ordering::SeqCst), 3);
+        assert_eq!(*recording.delivered.lock().unwrap(), vec![100]);
+    }
+}
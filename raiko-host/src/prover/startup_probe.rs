@@ -0,0 +1,183 @@
+//! Probe of the RPC node the proofs are fetched from, run at startup: the server reports
+//! itself ready only once the node answers and, given a reference node, has synced up to
+//! it, for no proof to be routed to a prover whose node is not ready.
+use std::time::{Duration, Instant};
+
+use tracing::{error, info, warn};
+use zeth_lib::host::provider::{rpc_provider::RpcProvider, RpcOptions};
+
+use super::warmup::Readiness;
+
+/// The delay before the second attempt, doubled after each failed one.
+pub const STARTUP_PROBE_FIRST_BACKOFF: Duration = Duration::from_secs(1);
+/// The longest delay between two attempts.
+pub const STARTUP_PROBE_MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// The node probed and how far behind it may be.
+#[derive(Debug, Clone)]
+pub struct StartupProbe {
+    pub rpc_url: String,
+    /// the node whose head the head of the probed one is compared to
+    pub reference_rpc_url: Option<String>,
+    /// most blocks the probed node may lag behind the reference
+    pub max_head_lag_blocks: Option<u64>,
+    /// how long the node may take to become ready
+    pub timeout: Duration,
+}
+
+impl StartupProbe {
+    /// Probes the node once, returning its head.
+    fn check(&self, options: &RpcOptions) -> Result<u64, String> {
+        let head = head_block_number(&self.rpc_url, options)?;
+        if let (Some(reference_rpc_url), Some(max_lag)) =
+            (&self.reference_rpc_url, self.max_head_lag_blocks)
+        {
+            let reference = head_block_number(reference_rpc_url, options)
+                .map_err(|err| format!("the reference node failed: {}", err))?;
+            let lag = reference.saturating_sub(head);
+            if lag > max_lag {
+                return Err(format!(
+                    "the head {} lags {} blocks behind the reference, more than {}",
+                    head, lag, max_lag
+                ));
+            }
+        }
+        Ok(head)
+    }
+}
+
+fn head_block_number(rpc_url: &str, options: &RpcOptions) -> Result<u64, String> {
+    let mut rpc = RpcProvider::new(rpc_url.to_owned(), options).map_err(|e| e.to_string())?;
+    let head = rpc.get_head_block().map_err(|e| format!("{:#}", e))?;
+    head.number
+        .map(|number| number.as_u64())
+        .ok_or_else(|| "the head block has no number".to_owned())
+}
+
+/// Probes the node until it passes, `first_backoff` after the first failed attempt and
+/// twice as long after each one, then reports `readiness` ready. Past the timeout of the
+/// probe the server stays unready, still serving `/health` for the orchestrator to see.
+pub async fn run_startup_probe(
+    probe: StartupProbe,
+    options: RpcOptions,
+    readiness: Readiness,
+    first_backoff: Duration,
+) {
+    let started = Instant::now();
+    let mut backoff = first_backoff;
+    loop {
+        let remaining = probe.timeout.saturating_sub(started.elapsed());
+        let attempt = {
+            let (probe, options) = (probe.clone(), options.clone());
+            tokio::time::timeout(
+                remaining,
+                tokio::task::spawn_blocking(move || probe.check(&options)),
+            )
+            .await
+        };
+        let err = match attempt {
+            Ok(Ok(Ok(head))) => {
+                info!(
+                    "The RPC node is ready at block {}, after {:?}",
+                    head,
+                    started.elapsed()
+                );
+                readiness.set_ready();
+                return;
+            }
+            Ok(Ok(Err(err))) => err,
+            Ok(Err(err)) => err.to_string(),
+            Err(_) => "the probe timed out".to_owned(),
+        };
+        if started.elapsed() + backoff >= probe.timeout {
+            error!(
+                "The RPC node is still not ready after {:?}, staying unready: {}",
+                started.elapsed(),
+                err
+            );
+            return;
+        }
+        warn!(
+            "The RPC node is not ready, probing again in {:?}: {}",
+            backoff, err
+        );
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(STARTUP_PROBE_MAX_BACKOFF);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use ethers_core::types::{Block, H256};
+    use hyper::StatusCode;
+    use serde_json::json;
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::prover::mock_server::{json_rpc_response, read_json, serve_on};
+
+    /// Serves `head` as the latest block on `addr`.
+    fn serve_head(addr: SocketAddr, head: u64) {
+        serve_on(addr, None, move |req| async move {
+            let req = read_json(req).await;
+            let block = Block::<H256> {
+                number: Some(head.into()),
+                ..Default::default()
+            };
+            json_rpc_response(&req, json!({ "result": block }))
+        });
+    }
+
+    async fn free_addr() -> SocketAddr {
+        TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn readiness_waits_for_an_initially_unreachable_node() {
+        let (node, reference) = (free_addr().await, free_addr().await);
+        serve_head(reference, 110);
+        let probe = StartupProbe {
+            rpc_url: format!("http://{}", node),
+            reference_rpc_url: Some(format!("http://{}", reference)),
+            max_head_lag_blocks: Some(16),
+            timeout: Duration::from_secs(10),
+        };
+        let readiness = Readiness::new(false);
+        let probing = tokio::spawn(run_startup_probe(
+            probe.clone(),
+            RpcOptions::default(),
+            readiness.clone(),
+            Duration::from_millis(50),
+        ));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(readiness.health_status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        // the node comes up synced
+        serve_head(node, 100);
+        probing.await.unwrap();
+        assert!(readiness.is_ready());
+
+        // a node lagging too far behind never becomes ready
+        let lagging = free_addr().await;
+        serve_head(lagging, 10);
+        let readiness = Readiness::new(false);
+        run_startup_probe(
+            StartupProbe {
+                rpc_url: format!("http://{}", lagging),
+                timeout: Duration::from_millis(300),
+                ..probe
+            },
+            RpcOptions::default(),
+            readiness.clone(),
+            Duration::from_millis(50),
+        )
+        .await;
+        assert!(!readiness.is_ready());
+    }
+}
@@ -0,0 +1,185 @@
+//! Upload of block data files, streamed into the cache directory so that large blocks
+//! are never buffered in memory.
+use std::{
+    io::{self, Write},
+    path::Path,
+};
+
+use anyhow::bail;
+use hyper::{
+    body::{Bytes, HttpBody},
+    Body,
+};
+use tokio::sync::mpsc;
+use zeth_lib::host::atomic_file::write_atomically;
+
+use super::error::{Error, Result};
+
+/// Extension of the temporary files of the uploads, named `{file name}.{random}.part`.
+pub const UPLOAD_TMP_EXTENSION: &str = "part";
+
+/// Chunks of the body received ahead of the writes.
+const CHUNKS_IN_FLIGHT: usize = 16;
+
+/// Counts the decompressed bytes and fails once they exceed the limit, which guards
+/// against decompression bombs.
+struct SizeLimit {
+    written: u64,
+    limit: u64,
+}
+
+impl Write for SizeLimit {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.written += buf.len() as u64;
+        if self.written > self.limit {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("block data exceeds {} bytes", self.limit),
+            ));
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Streams the gzipped block data of `body` to `path`, decompressing it on the fly to
+/// enforce `max_block_size` on the decompressed size. The file only appears at `path`
+/// once the whole body was received and verified, concurrent uploads of a block each
+/// writing a temporary file of their own. Returns the decompressed size.
+pub async fn store_block_data(mut body: Body, path: &Path, max_block_size: u64) -> Result<u64> {
+    // `None` ends the body, the channel closing before it interrupts the upload
+    let (sender, receiver) = mpsc::channel::<Option<Bytes>>(CHUNKS_IN_FLIGHT);
+    let path = path.to_owned();
+    // the writes and the decompression block, so they run off the runtime threads
+    let writer =
+        tokio::task::spawn_blocking(move || write_block_data(receiver, &path, max_block_size));
+    let received: Result<()> = async {
+        while let Some(chunk) = body.data().await {
+            let chunk = chunk.map_err(|e| Error::String(e.to_string()))?;
+            if sender.send(Some(chunk)).await.is_err() {
+                // the writer failed, its error is returned below
+                return Ok(());
+            }
+        }
+        let _ = sender.send(None).await;
+        Ok(())
+    }
+    .await;
+    drop(sender);
+    let written = writer.await?;
+    received?;
+    Ok(written?)
+}
+
+fn write_block_data(
+    mut receiver: mpsc::Receiver<Option<Bytes>>,
+    path: &Path,
+    max_block_size: u64,
+) -> anyhow::Result<u64> {
+    let mut decoder = flate2::write::GzDecoder::new(SizeLimit {
+        written: 0,
+        limit: max_block_size,
+    });
+    write_atomically(path, UPLOAD_TMP_EXTENSION, |file| {
+        loop {
+            match receiver.blocking_recv() {
+                Some(Some(chunk)) => {
+                    decoder.write_all(&chunk)?;
+                    file.write_all(&chunk)?;
+                }
+                Some(None) => break,
+                None => bail!("the upload was interrupted"),
+            }
+        }
+        decoder.try_finish()?;
+        Ok(())
+    })?;
+    Ok(decoder.get_ref().written)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Read};
+
+    use super::*;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::best());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn chunked_body(data: &[u8], chunk_size: usize) -> Body {
+        let (mut sender, body) = Body::channel();
+        let chunks: Vec<Bytes> = data
+            .chunks(chunk_size)
+            .map(Bytes::copy_from_slice)
+            .collect();
+        tokio::spawn(async move {
+            for chunk in chunks {
+                sender.send_data(chunk).await.unwrap();
+            }
+        });
+        body
+    }
+
+    #[tokio::test]
+    async fn test_streamed_upload_matches_buffered() {
+        let data = br#"{"full_blocks":[],"partial_blocks":[]}"#.repeat(1000);
+        let compressed = gzip(&data);
+        let dir = tempfile::tempdir().unwrap();
+
+        let buffered = dir.path().join("1.l2.json.gz");
+        let size = store_block_data(Body::from(compressed.clone()), &buffered, 1 << 20)
+            .await
+            .unwrap();
+        assert_eq!(size, data.len() as u64);
+
+        let streamed = dir.path().join("2.l2.json.gz");
+        let size = store_block_data(chunked_body(&compressed, 64), &streamed, 1 << 20)
+            .await
+            .unwrap();
+        assert_eq!(size, data.len() as u64);
+
+        let streamed = fs::read(&streamed).unwrap();
+        assert_eq!(streamed, fs::read(&buffered).unwrap());
+        let mut decompressed = vec![];
+        flate2::read::GzDecoder::new(&streamed[..])
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, data);
+    }
+
+    #[tokio::test]
+    async fn test_upload_over_limit_is_rejected() {
+        let compressed = gzip(&[0u8; 1 << 16]);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("1.l2.json.gz");
+        assert!(
+            store_block_data(chunked_body(&compressed, 16), &path, 1 << 10)
+                .await
+                .is_err()
+        );
+        assert!(!path.exists());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_concurrent_uploads_of_a_block_do_not_interleave() {
+        let data = br#"{"full_blocks":[],"partial_blocks":[]}"#.repeat(1000);
+        let compressed = gzip(&data);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("1.l2.json.gz");
+
+        let upload = || store_block_data(chunked_body(&compressed, 64), &path, 1 << 20);
+        let sizes = tokio::join!(upload(), upload(), upload(), upload());
+        for size in [sizes.0, sizes.1, sizes.2, sizes.3] {
+            assert_eq!(size.unwrap(), data.len() as u64);
+        }
+        assert_eq!(fs::read(&path).unwrap(), compressed);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
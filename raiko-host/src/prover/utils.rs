@@ -1,11 +1,109 @@
-use std::path::{Path, PathBuf};
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
-use super::consts::RAIKO_GUEST_EXECUTABLE;
+use serde::{Deserialize, Serialize, Serializer};
+use zeth_lib::host::{atomic_file::temp_file_target, provider::file_provider::TMP_EXTENSION};
 
-pub fn cache_file_path(cache_path: &Path, block_no: u64, is_l1: bool) -> PathBuf {
+use super::{consts::RAIKO_GUEST_EXECUTABLE, upload::UPLOAD_TMP_EXTENSION};
+
+/// Printed in place of the redacted values.
+pub const REDACTED: &str = "***";
+
+/// A string option, like a token, which is redacted when printed or serialized.
+#[derive(Clone, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(pub String);
+
+impl FromStr for Secret {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Secret(s.to_owned()))
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Secret({})", REDACTED)
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(REDACTED)
+    }
+}
+
+/// The cache directory of a chain, entries of different chains never share a directory.
+pub fn chain_cache_dir(cache_path: &Path, chain_id: u64) -> PathBuf {
+    cache_path.join(chain_id.to_string())
+}
+
+pub fn cache_file_path(cache_path: &Path, chain_id: u64, block_no: u64, is_l1: bool) -> PathBuf {
     let prefix = if is_l1 { "l1" } else { "l2" };
     let file_name = format!("{}.{}.json.gz", block_no, prefix);
-    cache_path.join(file_name)
+    chain_cache_dir(cache_path, chain_id).join(file_name)
+}
+
+pub fn is_cache_file_name(file_name: &str) -> bool {
+    [".l1.json.gz", ".l2.json.gz"].iter().any(|suffix| {
+        file_name
+            .strip_suffix(suffix)
+            .map(|block_no| block_no.parse::<u64>().is_ok())
+            .unwrap_or(false)
+    })
+}
+
+/// Whether `file_name` is the one of a cache file being written, saved by the rpc cache
+/// or uploaded.
+pub fn is_temp_file_name(file_name: &str) -> bool {
+    [TMP_EXTENSION, UPLOAD_TMP_EXTENSION]
+        .iter()
+        .any(|extension| temp_file_target(file_name, extension).map_or(false, is_cache_file_name))
+}
+
+/// Creates the chain's cache directory and moves the cache files of the former flat
+/// layout into it. Returns the number of migrated files.
+pub fn migrate_flat_cache(cache_path: &Path, chain_id: u64) -> io::Result<usize> {
+    let chain_dir = chain_cache_dir(cache_path, chain_id);
+    fs::create_dir_all(&chain_dir)?;
+    let mut migrated = 0;
+    for entry in fs::read_dir(cache_path)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        let file_name = entry.file_name();
+        let Some(name) = file_name.to_str() else {
+            continue;
+        };
+        if is_cache_file_name(name) {
+            fs::rename(entry.path(), chain_dir.join(name))?;
+            migrated += 1;
+        }
+    }
+    Ok(migrated)
+}
+
+/// Removes the temporary files of cache writes interrupted by a crash, returning their
+/// number.
+pub fn remove_stray_temp_files(chain_dir: &Path) -> io::Result<usize> {
+    let mut removed = 0;
+    for entry in fs::read_dir(chain_dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let Some(name) = file_name.to_str() else {
+            continue;
+        };
+        if is_temp_file_name(name) && entry.file_type()?.is_file() {
+            fs::remove_file(entry.path())?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
 }
 
 pub fn guest_executable_path(guest_path: &Path, proof_type: &str) -> PathBuf {
@@ -14,6 +112,55 @@ pub fn guest_executable_path(guest_path: &Path, proof_type: &str) -> PathBuf {
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_file_path_per_chain() {
+        let cache_path = Path::new("/tmp/sgx");
+        let a = cache_file_path(cache_path, 167001, 100, false);
+        let b = cache_file_path(cache_path, 167002, 100, false);
+        assert_ne!(a, b);
+        assert_eq!(a, Path::new("/tmp/sgx/167001/100.l2.json.gz"));
+        // the guest derives the block number from the file prefix
+        assert_eq!(b.file_prefix().unwrap(), "100");
+    }
+
+    #[test]
+    fn test_migrate_flat_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("100.l1.json.gz"), b"l1").unwrap();
+        fs::write(dir.path().join("100.l2.json.gz"), b"l2").unwrap();
+        fs::write(dir.path().join("raiko.log"), b"log").unwrap();
+
+        assert_eq!(migrate_flat_cache(dir.path(), 167001).unwrap(), 2);
+        assert_eq!(
+            fs::read(cache_file_path(dir.path(), 167001, 100, true)).unwrap(),
+            b"l1"
+        );
+        assert!(!dir.path().join("100.l2.json.gz").exists());
+        assert!(dir.path().join("raiko.log").exists());
+        // nothing left to migrate
+        assert_eq!(migrate_flat_cache(dir.path(), 167001).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_remove_stray_temp_files() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in [
+            "100.l1.json.gz",
+            "100.l1.json.gz.a1B2c3.tmp",
+            "100.l2.json.gz.d4E5f6.part",
+            "notes.tmp",
+        ] {
+            fs::write(dir.path().join(name), b"data").unwrap();
+        }
+        assert_eq!(remove_stray_temp_files(dir.path()).unwrap(), 2);
+        assert!(dir.path().join("100.l1.json.gz").exists());
+        assert!(!dir.path().join("100.l1.json.gz.a1B2c3.tmp").exists());
+        assert!(!dir.path().join("100.l2.json.gz.d4E5f6.part").exists());
+        assert!(dir.path().join("notes.tmp").exists());
+    }
+
     #[test]
     fn test_file_prefix() {
         let path = std::path::Path::new("/tmp/ethereum/1234.l1.json.gz");
@@ -0,0 +1,56 @@
+//! Checks the cached block data against the canonical chain, without proving.
+use std::path::PathBuf;
+
+use zeth_lib::host::{
+    provider::RpcOptions,
+    validate::{validate_against_rpc, FieldMismatch},
+};
+
+use super::{
+    context::Context,
+    error::Result,
+    request::{ValidateRequest, ValidateResponse},
+    utils::cache_file_path,
+};
+
+/// Compares the cached block data of the block with the RPC nodes of `req`, `None` when
+/// there is no cached l2 block data.
+pub async fn validate(ctx: &Context, req: &ValidateRequest) -> Result<Option<ValidateResponse>> {
+    let l2_cache_path = cache_file_path(&ctx.cache_path, ctx.l2_chain_id, req.block, false);
+    if !l2_cache_path.is_file() {
+        return Ok(None);
+    }
+    // the shared cache is no canonical chain
+    let rpc_options = RpcOptions {
+        shared_cache_url: None,
+        ..ctx.rpc_options.clone()
+    };
+    let l2 = validate_file(l2_cache_path, req.l2_rpc.clone(), &rpc_options).await?;
+
+    let l1_cache_path = cache_file_path(&ctx.cache_path, ctx.l2_chain_id, req.block, true);
+    let l1 = match &req.l1_rpc {
+        Some(l1_rpc) if l1_cache_path.is_file() => {
+            Some(validate_file(l1_cache_path, l1_rpc.clone(), &rpc_options).await?)
+        }
+        _ => None,
+    };
+    Ok(Some(ValidateResponse { l2, l1 }))
+}
+
+async fn validate_file(
+    path: PathBuf,
+    rpc_url: String,
+    rpc_options: &RpcOptions,
+) -> Result<Vec<FieldMismatch>> {
+    let rpc_options = rpc_options.clone();
+    // run sync task in blocking mode
+    let mismatches = tokio::task::spawn_blocking(move || {
+        validate_against_rpc(
+            path.into_os_string().into_string().unwrap(),
+            rpc_url,
+            &rpc_options,
+        )
+    })
+    .await??;
+    Ok(mismatches)
+}
@@ -0,0 +1,277 @@
+//! Runs the call submitting a proof against the bytecode of the on-chain verifier on a
+//! local EVM, so that a guest whose proofs the deployed verifier rejects is caught before
+//! its proofs are submitted and the gas is spent.
+use std::{collections::BTreeMap, fs, path::Path};
+
+use anyhow::{bail, Context as _, Result};
+use revm::{
+    primitives::{AccountInfo, Bytecode, ExecutionResult, SpecId, TransactTo},
+    InMemoryDB, EVM,
+};
+use serde::Deserialize;
+use zeth_primitives::{Address, Bytes, U256};
+
+/// Where the verifier is deployed when the call has no `to`.
+const DEFAULT_VERIFIER_ADDRESS: Address = Address::repeat_byte(0x0f);
+/// Gas of the simulated call, the block gas limit of the L1.
+const SIMULATION_GAS_LIMIT: u64 = 30_000_000;
+/// Selector of `Error(string)`, the revert reason of `require` and `revert`.
+const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+/// Selector of `Panic(uint256)`, the failed assertions and arithmetic errors.
+const PANIC_SELECTOR: [u8; 4] = [0x4e, 0x48, 0x7b, 0x71];
+
+/// The call submitting a proof, as written by the `--emit-calldata` of the guest.
+#[derive(Debug, Deserialize)]
+pub struct EmittedCalldata {
+    pub to: Option<Address>,
+    pub calldata: Bytes,
+}
+
+/// What the verifier made of the call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SimulationOutcome {
+    Success {
+        gas_used: u64,
+    },
+    /// `reason` decoded from the revert data, or the data itself as hex
+    Revert {
+        reason: String,
+        gas_used: u64,
+    },
+    /// out of gas, an invalid opcode...
+    Halt {
+        reason: String,
+        gas_used: u64,
+    },
+}
+
+/// Calls `code`, the runtime bytecode of the verifier deployed at `to` with `storage`,
+/// with `calldata`.
+pub fn simulate_verifier(
+    code: Bytes,
+    storage: &BTreeMap<U256, U256>,
+    to: Address,
+    calldata: Bytes,
+) -> Result<SimulationOutcome> {
+    let mut db = InMemoryDB::default();
+    let bytecode = Bytecode::new_raw(code);
+    db.insert_account_info(
+        to,
+        AccountInfo {
+            code_hash: bytecode.hash_slow(),
+            code: Some(bytecode),
+            ..Default::default()
+        },
+    );
+    for (slot, value) in storage {
+        db.insert_account_storage(to, *slot, *value)
+            .map_err(|err| anyhow::anyhow!("{:?}", err))?;
+    }
+    let mut evm = EVM::new();
+    evm.env.cfg.spec_id = SpecId::LATEST;
+    evm.env.tx.caller = Address::repeat_byte(0xca);
+    evm.env.tx.transact_to = TransactTo::Call(to);
+    evm.env.tx.data = calldata;
+    evm.env.tx.value = U256::ZERO;
+    evm.env.tx.gas_limit = SIMULATION_GAS_LIMIT;
+    evm.database(db);
+    let result = evm
+        .transact()
+        .map_err(|err| anyhow::anyhow!("{:?}", err))
+        .context("simulate the verifier call")?
+        .result;
+    Ok(match result {
+        ExecutionResult::Success { gas_used, .. } => SimulationOutcome::Success { gas_used },
+        ExecutionResult::Revert { gas_used, output } => SimulationOutcome::Revert {
+            reason: revert_reason(&output),
+            gas_used,
+        },
+        ExecutionResult::Halt { reason, gas_used } => SimulationOutcome::Halt {
+            reason: format!("{:?}", reason),
+            gas_used,
+        },
+    })
+}
+
+/// The message of an `Error(string)`, the code of a `Panic(uint256)`, or else the revert
+/// data as hex, e.g. a custom error to look up in the ABI of the verifier.
+fn revert_reason(output: &[u8]) -> String {
+    let word = |offset: usize| -> Option<usize> {
+        let word = output.get(4 + offset..4 + offset + 32)?;
+        // the offsets and lengths fit in the low 8 bytes
+        word[..24].iter().all(|byte| *byte == 0).then(|| {
+            let mut low = [0; 8];
+            low.copy_from_slice(&word[24..]);
+            u64::from_be_bytes(low) as usize
+        })
+    };
+    match output.get(..4) {
+        Some(selector) if selector == ERROR_SELECTOR => {
+            let message = word(0).and_then(|offset| {
+                let len = word(offset)?;
+                let start = 4 + offset + 32;
+                let bytes = output.get(start..start.checked_add(len)?)?;
+                Some(String::from_utf8_lossy(bytes).into_owned())
+            });
+            if let Some(message) = message {
+                return message;
+            }
+        }
+        Some(selector) if selector == PANIC_SELECTOR && output.len() == 36 => {
+            if let Some(code) = word(0) {
+                return format!("panic 0x{:02x}", code);
+            }
+        }
+        _ => {}
+    }
+    if output.is_empty() {
+        "reverted without a reason".to_owned()
+    } else {
+        format!("0x{}", hex::encode(output))
+    }
+}
+
+/// The runtime bytecode in `path`, as hex or as the JSON build artifact of the verifier
+/// with a `deployedBytecode`, the string of Hardhat or the `object` of Foundry.
+fn read_bytecode(path: &Path) -> Result<Bytes> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("read the verifier bytecode {}", path.display()))?;
+    let hex_code = match serde_json::from_str::<serde_json::Value>(&text) {
+        Ok(artifact) => {
+            let deployed = &artifact["deployedBytecode"];
+            deployed
+                .as_str()
+                .or_else(|| deployed["object"].as_str())
+                .context("no deployedBytecode in the verifier artifact")?
+                .to_owned()
+        }
+        Err(_) => text.trim().to_owned(),
+    };
+    let code = hex::decode(hex_code.trim_start_matches("0x")).context("invalid bytecode hex")?;
+    if code.is_empty() {
+        bail!("the verifier bytecode is empty");
+    }
+    Ok(code.into())
+}
+
+/// The storage slots of the verifier in `path`, a JSON object of the values by slot.
+fn read_storage(path: &Path) -> Result<BTreeMap<U256, U256>> {
+    let data =
+        fs::read(path).with_context(|| format!("read the verifier storage {}", path.display()))?;
+    serde_json::from_slice(&data).context("invalid verifier storage")
+}
+
+/// Simulates the call of the `--emit-calldata` file `calldata_path` against the verifier
+/// of `bytecode_path`, with the storage of `storage_path`, and prints the outcome,
+/// failing unless the call succeeds.
+pub fn simulate_verifier_file(
+    calldata_path: &Path,
+    bytecode_path: &Path,
+    storage_path: Option<&Path>,
+) -> Result<()> {
+    let emitted: EmittedCalldata = serde_json::from_slice(
+        &fs::read(calldata_path)
+            .with_context(|| format!("read the calldata {}", calldata_path.display()))?,
+    )
+    .context("invalid calldata file")?;
+    let code = read_bytecode(bytecode_path)?;
+    let storage = storage_path
+        .map(read_storage)
+        .transpose()?
+        .unwrap_or_default();
+    let to = emitted.to.unwrap_or(DEFAULT_VERIFIER_ADDRESS);
+    match simulate_verifier(code, &storage, to, emitted.calldata)? {
+        SimulationOutcome::Success { gas_used } => {
+            println!("accepted by the verifier, {} gas", gas_used);
+            Ok(())
+        }
+        SimulationOutcome::Revert { reason, gas_used } => {
+            bail!("the verifier reverted: {} ({} gas)", reason, gas_used)
+        }
+        SimulationOutcome::Halt { reason, gas_used } => {
+            bail!("the verifier halted: {} ({} gas)", reason, gas_used)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use k256::ecdsa::SigningKey;
+    use zeth_primitives::{
+        keccak::keccak,
+        taiko::{BlockMetadata, ProtocolInstance, Transition},
+        B256,
+    };
+
+    use super::*;
+
+    /// A verifier of `verifyProof(bytes32 publicInputsHash, bytes proof)` for the 89
+    /// bytes sgx proofs: it recovers the signer of `publicInputsHash` from the
+    /// signature of the proof with `ecrecover`, and reverts with `Error("invalid
+    /// proof")` unless it is the instance of the proof.
+    const TEST_VERIFIER: &str = "600435600052609d3560ff16602052607c35604052609c35606052602060806080600060015afa5060805160683560601c146063576308c379a060e01b6000526020600452600d6024526c696e76616c69642070726f6f6660981b60445260646000fd5b00";
+
+    /// The calldata of `verifyProof` with the sgx proof of a block, as the guest emits
+    /// it.
+    fn signed_calldata() -> Bytes {
+        let key = SigningKey::from_slice(&[7u8; 32]).unwrap();
+        let public_key = key.verifying_key().to_encoded_point(false);
+        let instance = Address::from_slice(&keccak(&public_key.as_bytes()[1..])[12..]);
+        let pi = ProtocolInstance {
+            transition: Transition {
+                parentHash: B256::repeat_byte(1),
+                blockHash: B256::repeat_byte(2),
+                signalRoot: B256::repeat_byte(3),
+                graffiti: B256::repeat_byte(4),
+            },
+            block_metadata: BlockMetadata {
+                id: 12345,
+                ..Default::default()
+            },
+            prover: Address::repeat_byte(6),
+        };
+        let pi_hash = pi.public_inputs(instance).hash();
+        let (signature, recovery_id) = key.sign_prehash_recoverable(pi_hash.as_slice()).unwrap();
+        let mut proof = 1u32.to_be_bytes().to_vec();
+        proof.extend(instance.as_slice());
+        proof.extend(signature.to_bytes().as_slice());
+        proof.push(recovery_id.is_y_odd() as u8 + 27);
+        pi.verify_proof_calldata(instance, proof).into()
+    }
+
+    fn simulate(calldata: Bytes) -> SimulationOutcome {
+        let code = hex::decode(TEST_VERIFIER).unwrap();
+        simulate_verifier(
+            code.into(),
+            &BTreeMap::new(),
+            DEFAULT_VERIFIER_ADDRESS,
+            calldata,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn valid_proof_is_accepted_and_tampered_one_reverts() {
+        let calldata = signed_calldata();
+        assert!(matches!(
+            simulate(calldata.clone()),
+            SimulationOutcome::Success { .. }
+        ));
+
+        // a byte of the signature
+        let mut tampered = calldata.to_vec();
+        tampered[4 + 32 * 3 + 60] ^= 1;
+        let SimulationOutcome::Revert { reason, .. } = simulate(tampered.into()) else {
+            panic!("the tampered proof was not rejected");
+        };
+        assert_eq!(reason, "invalid proof");
+
+        // the hash of another block
+        let mut other_block = calldata.to_vec();
+        other_block[4] ^= 1;
+        assert!(matches!(
+            simulate(other_block.into()),
+            SimulationOutcome::Revert { .. }
+        ));
+    }
+}
@@ -0,0 +1,201 @@
+//! Offline check of the cache directory of the chain and of the persisted proofs, e.g.
+//! after copying them between machines, so that a corrupt entry is caught before it
+//! produces a wrong witness.
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context as _, Result};
+use zeth_lib::host::provider::file_provider::FileProvider;
+
+use super::{
+    proof::cache::{check_proof_file, is_proof_temp_file_name},
+    utils::{is_cache_file_name, is_temp_file_name},
+};
+
+/// The entries of the cache checked by [verify_cache].
+#[derive(Debug, Default)]
+pub struct CacheReport {
+    pub valid: u64,
+    /// the corrupt entries, with what is wrong with them
+    pub corrupt: Vec<(PathBuf, String)>,
+    /// the temporary files of interrupted writes, and the l1 block data of the blocks
+    /// without l2 block data
+    pub orphans: Vec<PathBuf>,
+    /// the corrupt entries removed
+    pub pruned: u64,
+}
+
+/// Checks every cache file of `chain_dir` and every proof file of `proof_dir`, removing
+/// the corrupt ones when `prune`.
+pub fn verify_cache(
+    chain_dir: &Path,
+    proof_dir: Option<&Path>,
+    prune: bool,
+) -> io::Result<CacheReport> {
+    let mut report = CacheReport::default();
+    for path in files(chain_dir)? {
+        let name = file_name(&path);
+        if is_temp_file_name(name) {
+            report.orphans.push(path);
+        } else if is_cache_file_name(name) {
+            let l2_file = name.replace(".l1.", ".l2.");
+            if name.contains(".l1.") && !chain_dir.join(l2_file).is_file() {
+                report.orphans.push(path.clone());
+            }
+            report.check(path, check_block_data, prune)?;
+        }
+    }
+    if let Some(proof_dir) = proof_dir {
+        for path in files(proof_dir)? {
+            let name = file_name(&path);
+            if is_proof_temp_file_name(name) {
+                report.orphans.push(path);
+            } else if name.ends_with(".json") {
+                report.check(path, check_proof_file, prune)?;
+            }
+        }
+    }
+    Ok(report)
+}
+
+impl CacheReport {
+    fn check(
+        &mut self,
+        path: PathBuf,
+        check: impl Fn(&Path) -> Result<(), String>,
+        prune: bool,
+    ) -> io::Result<()> {
+        match check(&path) {
+            Ok(()) => self.valid += 1,
+            Err(err) => {
+                if prune {
+                    fs::remove_file(&path)?;
+                    self.pruned += 1;
+                }
+                self.corrupt.push((path, err));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The files of `dir`, sorted, none if it is missing.
+fn files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(err),
+    };
+    let mut files = vec![];
+    for entry in entries {
+        let entry = entry?;
+        if entry.metadata()?.is_file() {
+            files.push(entry.path());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn file_name(path: &Path) -> &str {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default()
+}
+
+/// Checks that the block data file decodes, that its blocks are the ones they are
+/// stored as, and that an l2 file holds the block it is named after.
+fn check_block_data(path: &Path) -> Result<(), String> {
+    let provider = FileProvider::read_from_file(path.to_string_lossy().into_owned())
+        .map_err(|e| format!("invalid block data: {:#}", e))?;
+    let blocks = provider.full_blocks();
+    for (query, block) in &blocks {
+        let number = block.number.map(|number| number.as_u64());
+        if number != Some(query.block_no) {
+            return Err(format!(
+                "block {:?} stored as block {}",
+                number, query.block_no
+            ));
+        }
+    }
+    let name = file_name(path);
+    if let Some(block_no) = name.strip_suffix(".l2.json.gz") {
+        let block_no: u64 = block_no.parse().map_err(|_| "invalid file name")?;
+        if !blocks.iter().any(|(query, _)| query.block_no == block_no) {
+            return Err(format!("no block {} in its block data", block_no));
+        }
+    }
+    Ok(())
+}
+
+/// Checks the cache and prints a summary, failing if corrupt entries are left.
+pub fn verify_cache_dir(chain_dir: &Path, proof_dir: Option<&Path>, prune: bool) -> Result<()> {
+    let report = verify_cache(chain_dir, proof_dir, prune)
+        .with_context(|| format!("verify the cache in {}", chain_dir.display()))?;
+    for (path, err) in &report.corrupt {
+        println!("corrupt: {}: {}", path.display(), err);
+    }
+    for path in &report.orphans {
+        println!("orphan: {}", path.display());
+    }
+    println!(
+        "{} valid, {} corrupt ({} pruned), {} orphan entries",
+        report.valid,
+        report.corrupt.len(),
+        report.pruned,
+        report.orphans.len()
+    );
+    let left = report.corrupt.len() as u64 - report.pruned;
+    if left > 0 {
+        bail!("{} corrupt cache entries, see verify_cache_prune", left);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use ethers_core::types::{Block, Transaction};
+    use flate2::{write::GzEncoder, Compression};
+    use zeth_lib::host::provider::{BlockQuery, MutProvider};
+
+    use super::*;
+
+    #[test]
+    fn corrupt_entries_are_reported_and_pruned() {
+        let dir = tempfile::tempdir().unwrap();
+        let good = dir.path().join("7.l2.json.gz");
+        let mut provider = FileProvider::empty(good.to_string_lossy().into_owned());
+        let block = Block::<Transaction> {
+            number: Some(7.into()),
+            ..Default::default()
+        };
+        provider.insert_full_block(BlockQuery { block_no: 7 }, block);
+        provider
+            .save_to_file(&good.to_string_lossy().into_owned())
+            .unwrap();
+        // truncated by a bad transfer
+        let corrupt = dir.path().join("8.l2.json.gz");
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(b"{\"full_blocks\":[").unwrap();
+        fs::write(&corrupt, &encoder.finish().unwrap()[..20]).unwrap();
+        let orphan = dir.path().join("9.l2.json.gz.a1B2c3.tmp");
+        fs::write(&orphan, b"").unwrap();
+
+        let report = verify_cache(dir.path(), None, false).unwrap();
+        assert_eq!(report.valid, 1);
+        assert_eq!(report.corrupt.len(), 1);
+        assert_eq!(report.corrupt[0].0, corrupt);
+        assert_eq!(report.orphans, vec![orphan]);
+        assert!(verify_cache_dir(dir.path(), None, false).is_err());
+
+        assert!(verify_cache_dir(dir.path(), None, true).is_ok());
+        assert!(!corrupt.exists());
+        assert!(good.exists());
+        let report = verify_cache(dir.path(), None, false).unwrap();
+        assert_eq!((report.valid, report.corrupt.len()), (1, 0));
+    }
+}
@@ -0,0 +1,171 @@
+//! Fetches the block data of upcoming proof requests into the cache ahead of time.
+use std::{fmt, fs, io, path::PathBuf, str::FromStr, time::Instant};
+
+use serde_with::{DeserializeFromStr, SerializeDisplay};
+use tracing::{info, warn};
+use zeth_lib::taiko::block_builder::TaikoStrategyBundle;
+use zeth_primitives::{Address, B256};
+
+use super::{
+    allowed_range::AllowedRange,
+    context::Context,
+    prepare_input::prepare_input,
+    request::{ProofRequest, SgxRequest},
+    utils::cache_file_path,
+};
+
+/// Order in which the blocks are warmed, given as `ascending`, `descending` or
+/// `file:<path>` with one block number per line.
+#[derive(Debug, Clone, PartialEq, Eq, DeserializeFromStr, SerializeDisplay)]
+pub enum WarmOrder {
+    Ascending,
+    Descending,
+    File(PathBuf),
+}
+
+impl FromStr for WarmOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ascending" => Ok(WarmOrder::Ascending),
+            "descending" => Ok(WarmOrder::Descending),
+            _ => match s.strip_prefix("file:") {
+                Some(path) if !path.is_empty() => Ok(WarmOrder::File(path.into())),
+                _ => Err(format!(
+                    "invalid warm order {:?}, expected ascending, descending or file:<path>",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+impl fmt::Display for WarmOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WarmOrder::Ascending => write!(f, "ascending"),
+            WarmOrder::Descending => write!(f, "descending"),
+            WarmOrder::File(path) => write!(f, "file:{}", path.display()),
+        }
+    }
+}
+
+/// Lists the blocks of `range` in the order they are warmed. An order file may list the
+/// blocks of the range in any order, blocks outside the range and duplicates are skipped,
+/// and blocks missing from the file are not warmed.
+pub fn warm_schedule(range: &AllowedRange, order: &WarmOrder) -> io::Result<Vec<u64>> {
+    let blocks = match order {
+        WarmOrder::Ascending => (range.start..=range.end).collect(),
+        WarmOrder::Descending => (range.start..=range.end).rev().collect(),
+        WarmOrder::File(path) => {
+            let mut blocks: Vec<u64> = Vec::new();
+            for line in fs::read_to_string(path)?.lines() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let block = line.parse::<u64>().map_err(|e| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("invalid block number {:?} in {:?}: {}", line, path, e),
+                    )
+                })?;
+                if range.start <= block && block <= range.end && !blocks.contains(&block) {
+                    blocks.push(block);
+                }
+            }
+            blocks
+        }
+    };
+    Ok(blocks)
+}
+
+/// Fetches the block data of `blocks` in the given order, skipping the blocks which are
+/// cached already.
+pub async fn warm_cache(ctx: Context, blocks: Vec<u64>, l1_rpc: String, l2_rpc: String) {
+    let start = Instant::now();
+    let total = blocks.len();
+    for (done, block) in blocks.into_iter().enumerate() {
+        let cached = [true, false].iter().all(|&is_l1| {
+            cache_file_path(&ctx.cache_path, ctx.l2_chain_id, block, is_l1).is_file()
+        });
+        if !cached {
+            let req = ProofRequest::Sgx(SgxRequest {
+                block,
+                l2_rpc: l2_rpc.clone(),
+                l1_rpc: l1_rpc.clone(),
+                // only used in the protocol instance, not in the cached data
+                prover: Address::ZERO,
+                graffiti: B256::ZERO,
+                timeout_secs: None,
+                data_source: None,
+                skip_cache: false,
+                state_overrides: None,
+                metadata: Default::default(),
+                deadline: None,
+            });
+            if let Err(err) = prepare_input::<TaikoStrategyBundle>(&ctx, &req).await {
+                warn!("Warming block {} failed: {}", block, err);
+            }
+        }
+        let done = done + 1;
+        let eta = start.elapsed().mul_f64((total - done) as f64 / done as f64);
+        info!(
+            "Warmed block {} ({}/{}, {:.1}%), ETA {}s",
+            block,
+            done,
+            total,
+            done as f64 * 100.0 / total as f64,
+            eta.as_secs()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: u64, end: u64) -> AllowedRange {
+        AllowedRange {
+            chain: None,
+            start,
+            end,
+        }
+    }
+
+    #[test]
+    fn test_parse_warm_order() {
+        assert_eq!("ascending".parse(), Ok(WarmOrder::Ascending));
+        assert_eq!("descending".parse(), Ok(WarmOrder::Descending));
+        assert_eq!(
+            "file:/tmp/order".parse(),
+            Ok(WarmOrder::File("/tmp/order".into()))
+        );
+        assert!("file:".parse::<WarmOrder>().is_err());
+        assert!("random".parse::<WarmOrder>().is_err());
+    }
+
+    #[test]
+    fn test_warm_schedule_follows_order() {
+        let range = range(10, 13);
+        assert_eq!(
+            warm_schedule(&range, &WarmOrder::Ascending).unwrap(),
+            vec![10, 11, 12, 13]
+        );
+        assert_eq!(
+            warm_schedule(&range, &WarmOrder::Descending).unwrap(),
+            vec![13, 12, 11, 10]
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("order");
+        fs::write(&path, "12\n# next\n10\n99\n12\n\n11\n").unwrap();
+        assert_eq!(
+            warm_schedule(&range, &WarmOrder::File(path.clone())).unwrap(),
+            vec![12, 10, 11]
+        );
+        fs::write(&path, "12\nlatest\n").unwrap();
+        assert!(warm_schedule(&range, &WarmOrder::File(path)).is_err());
+    }
+}
@@ -0,0 +1,85 @@
+//! Warm-up of the enclave at startup, for the first proof not to pay for its
+//! initialization. The server reports itself ready on `/health` once it is done.
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use hyper::StatusCode;
+use tracing::{info, warn};
+
+/// Whether the server is ready to serve proof requests.
+#[derive(Debug, Clone)]
+pub struct Readiness(Arc<AtomicBool>);
+
+impl Readiness {
+    pub fn new(ready: bool) -> Self {
+        Readiness(Arc::new(AtomicBool::new(ready)))
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+
+    pub fn set_ready(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+
+    /// The status of `/health`: unavailable until ready, for the load balancers to hold
+    /// the traffic back.
+    pub fn health_status(&self) -> StatusCode {
+        match self.is_ready() {
+            true => StatusCode::OK,
+            false => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+}
+
+/// Runs `warmup`, then reports the server ready. A failed warm-up is logged, the server
+/// becomes ready all the same and the first proof initializes the enclave instead.
+pub async fn warm_up<F>(readiness: Readiness, warmup: F)
+where
+    F: Future<Output = Result<(), String>>,
+{
+    let started = Instant::now();
+    match warmup.await {
+        Ok(()) => info!("Warmed the enclave up in {:?}", started.elapsed()),
+        Err(err) => warn!(
+            "Failed to warm the enclave up, after {:?}: {}",
+            started.elapsed(),
+            err
+        ),
+    }
+    readiness.set_ready();
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::oneshot;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn readiness_waits_for_the_warmup() {
+        let readiness = Readiness::new(false);
+        let (done, warmed) = oneshot::channel::<()>();
+        let warming = tokio::spawn(warm_up(readiness.clone(), async move {
+            warmed.await.map_err(|err| err.to_string())
+        }));
+        tokio::task::yield_now().await;
+        assert_eq!(readiness.health_status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        done.send(()).unwrap();
+        warming.await.unwrap();
+        assert_eq!(readiness.health_status(), StatusCode::OK);
+
+        // failing to warm up does not keep the server unavailable
+        let readiness = Readiness::new(false);
+        warm_up(readiness.clone(), async { Err("no enclave".to_owned()) }).await;
+        assert!(readiness.is_ready());
+    }
+}
@@ -0,0 +1,162 @@
+//! Bounded store of the witnesses handed to the guest, kept for post-mortem debugging.
+use std::{
+    fmt,
+    io::{self, BufWriter, Write},
+    str,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use hyper::{body::Sender, Body};
+use lru_time_cache::LruCache;
+use serde::Serialize;
+use tokio::runtime::Handle;
+use tracing::warn;
+
+/// Size of the chunks the witnesses are streamed in, and of the hex encoded at a time.
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+/// The block data files the guest proved, as the gzipped json it read.
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Witness {
+    pub block: u64,
+    #[serde(serialize_with = "hex_bytes")]
+    pub l1_blocks_data: Vec<u8>,
+    #[serde(serialize_with = "hex_bytes")]
+    pub l2_blocks_data: Vec<u8>,
+}
+
+fn hex_bytes<S: serde::Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    // written to the serializer piece by piece, never as a whole string
+    serializer.collect_str(&Hex(bytes))
+}
+
+/// Formats bytes as 0x-prefixed hex, encoding a chunk at a time.
+struct Hex<'a>(&'a [u8]);
+
+impl fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("0x")?;
+        let mut buf = vec![0; STREAM_CHUNK_SIZE];
+        for chunk in self.0.chunks(STREAM_CHUNK_SIZE / 2) {
+            let hex = &mut buf[..chunk.len() * 2];
+            hex::encode_to_slice(chunk, hex).map_err(|_| fmt::Error)?;
+            f.write_str(str::from_utf8(hex).map_err(|_| fmt::Error)?)?;
+        }
+        Ok(())
+    }
+}
+
+/// The json of the witness as a body streamed in chunks, so that serving a witness takes
+/// no more memory than the witness itself.
+pub fn witness_body(witness: Witness) -> Body {
+    let (sender, body) = Body::channel();
+    let handle = Handle::current();
+    tokio::task::spawn_blocking(move || {
+        let mut writer = BufWriter::with_capacity(STREAM_CHUNK_SIZE, BodyWriter { sender, handle });
+        let result = serde_json::to_writer(&mut writer, &witness)
+            .map_err(io::Error::from)
+            .and_then(|()| writer.flush());
+        if let Err(err) = result {
+            warn!(
+                "Failed to stream the witness of block {}: {}",
+                witness.block, err
+            );
+            // the client sees the body fail rather than a truncated json
+            writer.into_parts().0.sender.abort();
+        }
+    });
+    body
+}
+
+/// Sends what is written as the chunks of a body.
+struct BodyWriter {
+    sender: Sender,
+    handle: Handle,
+}
+
+impl Write for BodyWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let chunk = hyper::body::Bytes::copy_from_slice(buf);
+        self.handle
+            .block_on(self.sender.send_data(chunk))
+            .map_err(|err| io::Error::new(io::ErrorKind::BrokenPipe, err))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Keeps the witnesses of the most recent jobs, keyed by the l2 block number. Entries are
+/// dropped when the store is full or when they expire.
+#[derive(Clone)]
+pub struct WitnessStore {
+    witnesses: Arc<Mutex<LruCache<u64, Witness>>>,
+}
+
+impl WitnessStore {
+    pub fn new(capacity: usize, retention: Duration) -> Self {
+        let witnesses = LruCache::with_expiry_duration_and_capacity(retention, capacity);
+        WitnessStore {
+            witnesses: Arc::new(Mutex::new(witnesses)),
+        }
+    }
+
+    pub fn insert(&self, witness: Witness) {
+        let mut witnesses = self.witnesses.lock().unwrap();
+        witnesses.insert(witness.block, witness);
+    }
+
+    pub fn get(&self, block: u64) -> Option<Witness> {
+        let mut witnesses = self.witnesses.lock().unwrap();
+        witnesses.get(&block).cloned()
+    }
+}
+
+impl fmt::Debug for WitnessStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let witnesses = self.witnesses.lock().unwrap();
+        f.debug_struct("WitnessStore")
+            .field("len", &witnesses.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::body::HttpBody;
+    use serde_json::json;
+
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn streamed_witness_matches_the_buffered_json() {
+        for size in [0, 1000, 5 * STREAM_CHUNK_SIZE + 7] {
+            let witness = Witness {
+                block: 7,
+                l1_blocks_data: (0..size).map(|i| i as u8).collect(),
+                l2_blocks_data: vec![0xab; size / 2],
+            };
+            // as the witnesses were serialized in memory
+            let buffered = serde_json::to_vec(&json!({
+                "block": 7,
+                "l1BlocksData": format!("0x{}", hex::encode(&witness.l1_blocks_data)),
+                "l2BlocksData": format!("0x{}", hex::encode(&witness.l2_blocks_data)),
+            }))
+            .unwrap();
+
+            let mut body = witness_body(witness);
+            let mut streamed = vec![];
+            while let Some(chunk) = body.data().await {
+                let chunk = chunk.unwrap();
+                // bounded by the chunk size, whatever the size of the witness
+                assert!(chunk.len() <= STREAM_CHUNK_SIZE, "{}", chunk.len());
+                streamed.extend_from_slice(&chunk);
+            }
+            assert_eq!(streamed, buffered);
+        }
+    }
+}
@@ -0,0 +1,279 @@
+//! Pushes the metrics served on `/metrics` to a StatsD server, in the DogStatsD format,
+//! for the monitoring stacks without a Prometheus scraper.
+use std::{collections::HashMap, io, sync::Arc, time::Duration};
+
+use prometheus::proto::{LabelPair, MetricFamily, MetricType};
+use tokio::net::{lookup_host, UdpSocket};
+use tracing::{info, warn};
+use zeth_lib::host::provider::circuit_breaker::CircuitBreaker;
+
+use crate::metrics::{gather, observe_rpc_breakers};
+
+/// Size of the datagrams at most, below the usual MTU once the headers are added.
+const MAX_DATAGRAM_BYTES: usize = 1432;
+
+/// Turns the metrics of the default registry into StatsD lines: the gauges as gauges,
+/// the counters as their increments since the last push, and the histograms as the
+/// increments of their `.count` and `.sum`. The labels become tags, after the
+/// configured ones.
+#[derive(Debug)]
+pub struct StatsdExporter {
+    endpoint: String,
+    tags: Vec<String>,
+    /// prefix of the metric names, as on `/metrics`
+    prefix: String,
+    /// the last pushed value of each counter, by name and labels
+    counters: HashMap<String, f64>,
+}
+
+impl StatsdExporter {
+    /// Pushes to `endpoint`, given as `host:port`, with the `key:value` tags `tags` and
+    /// the metric names prefixed with `prefix`.
+    pub fn new(endpoint: String, tags: Vec<String>, prefix: String) -> Self {
+        StatsdExporter {
+            endpoint,
+            tags,
+            prefix,
+            counters: Default::default(),
+        }
+    }
+
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    /// Sends the current metrics. The counters only advance once every datagram is sent,
+    /// the increments of a failed push being sent with the next one.
+    pub async fn push(&mut self) -> io::Result<()> {
+        let addr = lookup_host(&self.endpoint).await?.next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "the endpoint resolves to nothing")
+        })?;
+        let socket = UdpSocket::bind(if addr.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        })
+        .await?;
+        let mut counters = self.counters.clone();
+        let lines = self.lines(&gather(&self.prefix), &mut counters);
+        for datagram in datagrams(&lines) {
+            socket.send_to(datagram.as_bytes(), addr).await?;
+        }
+        self.counters = counters;
+        Ok(())
+    }
+
+    fn lines(&self, families: &[MetricFamily], counters: &mut HashMap<String, f64>) -> Vec<String> {
+        let mut lines = vec![];
+        for family in families {
+            let name = family.get_name();
+            for metric in family.get_metric() {
+                let tags = self.tags(metric.get_label());
+                match family.get_field_type() {
+                    MetricType::COUNTER => {
+                        let value = metric.get_counter().get_value();
+                        lines.extend(counter(counters, name, &tags, value));
+                    }
+                    MetricType::GAUGE => {
+                        let value = metric.get_gauge().get_value();
+                        lines.push(line(name, value, "g", &tags));
+                    }
+                    MetricType::HISTOGRAM => {
+                        let histogram = metric.get_histogram();
+                        let count = histogram.get_sample_count() as f64;
+                        lines.extend(counter(counters, &format!("{}.count", name), &tags, count));
+                        let sum = histogram.get_sample_sum();
+                        lines.extend(counter(counters, &format!("{}.sum", name), &tags, sum));
+                    }
+                    MetricType::SUMMARY => {
+                        let summary = metric.get_summary();
+                        let count = summary.get_sample_count() as f64;
+                        lines.extend(counter(counters, &format!("{}.count", name), &tags, count));
+                        let sum = summary.get_sample_sum();
+                        lines.extend(counter(counters, &format!("{}.sum", name), &tags, sum));
+                    }
+                    MetricType::UNTYPED => {
+                        let value = metric.get_untyped().get_value();
+                        lines.push(line(name, value, "g", &tags));
+                    }
+                }
+            }
+        }
+        lines
+    }
+
+    fn tags(&self, labels: &[LabelPair]) -> Vec<String> {
+        let labels = labels.iter().map(|label| {
+            // `,` and `|` delimit the tags and the fields of a line
+            let value = label.get_value().replace([',', '|'], "_");
+            format!("{}:{}", label.get_name(), value)
+        });
+        self.tags.iter().cloned().chain(labels).collect()
+    }
+}
+
+/// The line of the increment of a counter since `counters`, none if it did not move. A
+/// counter below its last value was reset, all of it is new.
+fn counter(
+    counters: &mut HashMap<String, f64>,
+    name: &str,
+    tags: &[String],
+    value: f64,
+) -> Option<String> {
+    let key = format!("{}|{}", name, tags.join(","));
+    let last = counters.insert(key, value).unwrap_or_default();
+    let increment = if value < last { value } else { value - last };
+    (increment > 0.0).then(|| line(name, increment, "c", tags))
+}
+
+fn line(name: &str, value: f64, kind: &str, tags: &[String]) -> String {
+    if tags.is_empty() {
+        format!("{}:{}|{}", name, value, kind)
+    } else {
+        format!("{}:{}|{}|#{}", name, value, kind, tags.join(","))
+    }
+}
+
+/// The lines joined by newlines into datagrams of [MAX_DATAGRAM_BYTES] at most, but for
+/// the lines longer than that, sent alone.
+fn datagrams(lines: &[String]) -> Vec<String> {
+    let mut datagrams: Vec<String> = vec![];
+    for line in lines {
+        match datagrams.last_mut() {
+            Some(datagram) if datagram.len() + 1 + line.len() <= MAX_DATAGRAM_BYTES => {
+                datagram.push('\n');
+                datagram.push_str(line);
+            }
+            _ => datagrams.push(line.clone()),
+        }
+    }
+    datagrams
+}
+
+/// Pushes the metrics every `interval`, logging when the endpoint becomes unavailable
+/// and when it is back rather than at every failed push.
+pub async fn push_statsd(
+    mut exporter: StatsdExporter,
+    interval: Duration,
+    circuit_breaker: Option<Arc<CircuitBreaker>>,
+) {
+    let mut interval = tokio::time::interval(interval);
+    let mut failing = false;
+    loop {
+        interval.tick().await;
+        if let Some(breaker) = &circuit_breaker {
+            observe_rpc_breakers(breaker);
+        }
+        match exporter.push().await {
+            Ok(()) if failing => {
+                info!("Pushing the metrics to {} again", exporter.endpoint());
+                failing = false;
+            }
+            Ok(()) => {}
+            Err(err) if !failing => {
+                warn!(
+                    "Failed to push the metrics to {}: {}, retrying every {:?}",
+                    exporter.endpoint(),
+                    err,
+                    interval.period()
+                );
+                failing = true;
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::{inc_sgx_success, observe_sgx_gen, HTTP_REQUEST_DURATION};
+
+    /// The lines received until the endpoint is quiet, of the metrics of block 4242 and
+    /// of the route of this test, the other tests sharing the registry.
+    async fn received(socket: &UdpSocket) -> Vec<String> {
+        let mut lines = vec![];
+        let mut buf = [0; MAX_DATAGRAM_BYTES];
+        while let Ok(Ok(len)) =
+            tokio::time::timeout(Duration::from_millis(200), socket.recv(&mut buf)).await
+        {
+            assert!(len <= MAX_DATAGRAM_BYTES);
+            let datagram = std::str::from_utf8(&buf[..len]).unwrap();
+            lines.extend(
+                datagram
+                    .lines()
+                    .filter(|line| line.contains("blockid:4242") || line.contains("/statsd"))
+                    .map(str::to_owned),
+            );
+        }
+        lines.sort();
+        lines
+    }
+
+    #[tokio::test]
+    async fn proof_metrics_are_pushed_as_dogstatsd_lines() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let tags = vec![
+            "sgx_instance_id:3".to_owned(),
+            "l2_chain:testnet".to_owned(),
+        ];
+        let mut exporter = StatsdExporter::new(
+            socket.local_addr().unwrap().to_string(),
+            tags,
+            String::new(),
+        );
+        let tags = "#sgx_instance_id:3,l2_chain:testnet";
+
+        // a proof request served
+        observe_sgx_gen(4242, 1500);
+        inc_sgx_success(4242);
+        HTTP_REQUEST_DURATION
+            .with_label_values(&["POST", "/statsd"])
+            .observe(1.5);
+        exporter.push().await.unwrap();
+        let route = "method:POST,route:/statsd";
+        assert_eq!(
+            received(&socket).await,
+            vec![
+                format!("http_request_duration_seconds.count:1|c|{},{}", tags, route),
+                format!("http_request_duration_seconds.sum:1.5|c|{},{}", tags, route),
+                format!("sgx_proof_success_counter:1|c|{},blockid:4242", tags),
+                format!("sgx_proof_time_gauge:1500|g|{},blockid:4242", tags),
+            ]
+        );
+
+        // the counters send their increments only, the gauges their value
+        exporter.push().await.unwrap();
+        assert_eq!(
+            received(&socket).await,
+            vec![format!("sgx_proof_time_gauge:1500|g|{},blockid:4242", tags)]
+        );
+        inc_sgx_success(4242);
+        exporter.push().await.unwrap();
+        assert_eq!(
+            received(&socket).await,
+            vec![
+                format!("sgx_proof_success_counter:1|c|{},blockid:4242", tags),
+                format!("sgx_proof_time_gauge:1500|g|{},blockid:4242", tags),
+            ]
+        );
+
+        // an unresolvable endpoint fails the push, the increments are kept for the next
+        let mut unavailable =
+            StatsdExporter::new("statsd.invalid:8125".to_owned(), vec![], String::new());
+        inc_sgx_success(4242);
+        assert!(unavailable.push().await.is_err());
+        assert!(unavailable.counters.is_empty());
+    }
+
+    #[test]
+    fn long_pushes_are_split_into_datagrams() {
+        let lines: Vec<String> = (0..100)
+            .map(|i| format!("metric_{}:1|c|#l2_chain:testnet", i))
+            .collect();
+        let datagrams = datagrams(&lines);
+        assert!(datagrams.len() > 1);
+        assert!(datagrams.iter().all(|d| d.len() <= MAX_DATAGRAM_BYTES));
+        assert_eq!(datagrams.join("\n"), lines.join("\n"));
+    }
+}